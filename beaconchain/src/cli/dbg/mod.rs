@@ -1,5 +1,7 @@
 //! Debug commands.
 
 mod rpc;
+mod store;
 
 pub use self::rpc::DebugRpcCommand;
+pub use self::store::DebugStoreCommand;
@@ -0,0 +1,99 @@
+use apibara_dna_common::{
+    cli::ObjectStoreArgs,
+    dbg::{run_debug_store_diff, DebugInspectSegmentCommand, DebugLintFilterCommand},
+    fragment::{FragmentId, HEADER_FRAGMENT_ID},
+};
+use apibara_dna_protocol::beaconchain;
+use clap::Subcommand;
+use error_stack::{Result, ResultExt};
+use prost::Message;
+
+use crate::{
+    cli::{rpc::RpcArgs, start::BeaconChainArgs},
+    error::BeaconChainError,
+    filter::BeaconChainFilterFactory,
+    fragment::{
+        ATTESTER_SLASHING_FRAGMENT_ID, BLOB_FRAGMENT_ID, CONSOLIDATION_REQUEST_FRAGMENT_ID,
+        DEPOSIT_FRAGMENT_ID, PROPOSER_SLASHING_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
+        VALIDATOR_FRAGMENT_ID, VOLUNTARY_EXIT_FRAGMENT_ID, WITHDRAWAL_REQUEST_FRAGMENT_ID,
+    },
+    BeaconChainChainSupport,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum DebugStoreCommand {
+    /// Compare a stored block with the same block fetched from the RPC.
+    Diff {
+        #[clap(flatten)]
+        beaconchain: BeaconChainArgs,
+        #[clap(flatten)]
+        rpc: RpcArgs,
+        #[clap(flatten)]
+        object_store: ObjectStoreArgs,
+        /// The block number.
+        #[arg(long)]
+        block: u64,
+    },
+    /// Print a summary of the segment starting at the given block.
+    InspectSegment(DebugInspectSegmentCommand),
+    /// Validate filters and estimate their selectivity on a stored segment.
+    LintFilter(DebugLintFilterCommand),
+}
+
+impl DebugStoreCommand {
+    pub async fn run(self) -> Result<(), BeaconChainError> {
+        match self {
+            DebugStoreCommand::Diff {
+                beaconchain,
+                rpc,
+                object_store,
+                block,
+            } => {
+                let provider = rpc.to_beacon_api_provider()?;
+                let options = beaconchain.to_beacon_chain_options();
+                let beaconchain_chain = BeaconChainChainSupport::new(provider, options);
+
+                run_debug_store_diff(beaconchain_chain, format_fragment, block, object_store)
+                    .await
+                    .change_context(BeaconChainError)
+            }
+            DebugStoreCommand::InspectSegment(command) => {
+                command.run().await.change_context(BeaconChainError)
+            }
+            DebugStoreCommand::LintFilter(command) => command
+                .run(BeaconChainFilterFactory)
+                .await
+                .change_context(BeaconChainError),
+        }
+    }
+}
+
+fn format_fragment(fragment_id: FragmentId, data: &[u8]) -> String {
+    let formatted = match fragment_id {
+        HEADER_FRAGMENT_ID => beaconchain::BlockHeader::decode(data).map(|m| format!("{m:#?}")),
+        TRANSACTION_FRAGMENT_ID => {
+            beaconchain::Transaction::decode(data).map(|m| format!("{m:#?}"))
+        }
+        VALIDATOR_FRAGMENT_ID => beaconchain::Validator::decode(data).map(|m| format!("{m:#?}")),
+        BLOB_FRAGMENT_ID => beaconchain::Blob::decode(data).map(|m| format!("{m:#?}")),
+        DEPOSIT_FRAGMENT_ID => beaconchain::Deposit::decode(data).map(|m| format!("{m:#?}")),
+        VOLUNTARY_EXIT_FRAGMENT_ID => {
+            beaconchain::VoluntaryExit::decode(data).map(|m| format!("{m:#?}"))
+        }
+        PROPOSER_SLASHING_FRAGMENT_ID => {
+            beaconchain::ProposerSlashing::decode(data).map(|m| format!("{m:#?}"))
+        }
+        ATTESTER_SLASHING_FRAGMENT_ID => {
+            beaconchain::AttesterSlashing::decode(data).map(|m| format!("{m:#?}"))
+        }
+        WITHDRAWAL_REQUEST_FRAGMENT_ID => {
+            beaconchain::WithdrawalRequest::decode(data).map(|m| format!("{m:#?}"))
+        }
+        CONSOLIDATION_REQUEST_FRAGMENT_ID => {
+            beaconchain::ConsolidationRequest::decode(data).map(|m| format!("{m:#?}"))
+        }
+        _ => return hex::encode(data),
+    };
+
+    formatted.unwrap_or_else(|err| format!("invalid message: {err}"))
+}
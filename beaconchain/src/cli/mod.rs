@@ -2,18 +2,29 @@ mod dbg;
 mod rpc;
 mod start;
 
+use std::path::PathBuf;
+
+use apibara_dna_common::{
+    export::ExportCommand, migrate::MigrateCommand, resegment::ResegmentCommand,
+    snapshot::SnapshotCommand,
+};
 use clap::{Parser, Subcommand};
-use error_stack::Result;
+use error_stack::{Result, ResultExt};
 use start::StartCommand;
 use tokio_util::sync::CancellationToken;
 
-use crate::error::BeaconChainError;
+use crate::{error::BeaconChainError, filter::BeaconChainFilterFactory, fragment};
 
-use self::dbg::DebugRpcCommand;
+use self::dbg::{DebugRpcCommand, DebugStoreCommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Path to a TOML or YAML configuration file.
+    ///
+    /// Options set on the command line or in the environment take precedence.
+    #[arg(long = "config", env = "DNA_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
@@ -28,6 +39,23 @@ pub enum Command {
         #[clap(subcommand)]
         command: DebugRpcCommand,
     },
+    /// Debug the blocks in the object store.
+    #[command(name = "dbg-store")]
+    DebugStore {
+        #[clap(subcommand)]
+        command: DebugStoreCommand,
+    },
+    /// Upgrade stored segments to the current format.
+    Migrate(Box<MigrateCommand>),
+    /// Rewrite stored segments with a new segment size or compression.
+    Resegment(Box<ResegmentCommand>),
+    /// Export the data matching a filter to Parquet files.
+    Export(Box<ExportCommand>),
+    /// Export or import a snapshot of the data.
+    Snapshot {
+        #[clap(subcommand)]
+        command: Box<SnapshotCommand>,
+    },
 }
 
 impl Cli {
@@ -35,6 +63,14 @@ impl Cli {
         match self.command {
             Command::Start(command) => command.run(ct).await,
             Command::DebugRpc { command } => command.run().await,
+            Command::DebugStore { command } => command.run().await,
+            Command::Migrate(command) => command.run().await.change_context(BeaconChainError),
+            Command::Resegment(command) => command.run().await.change_context(BeaconChainError),
+            Command::Export(command) => command
+                .run(BeaconChainFilterFactory, fragment::fragment_info(), ct)
+                .await
+                .change_context(BeaconChainError),
+            Command::Snapshot { command } => command.run().await.change_context(BeaconChainError),
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use apibara_dna_common::rpc_pool::{weighted_urls, RpcEndpoint, RpcPoolOptions};
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use reqwest::{
@@ -15,12 +16,71 @@ use crate::{
 #[derive(Args, Clone, Debug)]
 pub struct RpcArgs {
     /// Beacon RPC URL.
+    ///
+    /// Pass multiple URLs, separated by commas, to fail over between them.
     #[arg(
         long = "rpc.url",
         env = "BEACON_RPC_URL",
+        value_delimiter = ',',
         default_value = "http://localhost:3500"
     )]
-    pub rpc_url: String,
+    pub rpc_url: Vec<String>,
+
+    /// Weight of each RPC URL when load balancing, in the same order as the URLs.
+    ///
+    /// URLs without a weight default to 1.
+    #[arg(
+        long = "rpc.weights",
+        env = "BEACON_RPC_WEIGHTS",
+        value_delimiter = ','
+    )]
+    pub rpc_weights: Vec<u32>,
+
+    /// Distribute requests across all healthy RPC URLs.
+    #[arg(
+        long = "rpc.load-balance",
+        env = "BEACON_RPC_LOAD_BALANCE",
+        default_value = "false"
+    )]
+    pub rpc_load_balance: bool,
+
+    /// How long to skip an RPC URL after a failed request.
+    #[arg(
+        long = "rpc.failover-cooldown-sec",
+        env = "BEACON_RPC_FAILOVER_COOLDOWN_SEC",
+        default_value = "30"
+    )]
+    pub rpc_failover_cooldown_sec: u64,
+
+    /// How often to check the RPC URLs' health in the background, in seconds.
+    ///
+    /// Set to 0 to disable the health checks.
+    #[arg(
+        long = "rpc.health-check-interval-sec",
+        env = "BEACON_RPC_HEALTH_CHECK_INTERVAL_SEC",
+        default_value = "10"
+    )]
+    pub rpc_health_check_interval_sec: u64,
+
+    /// Send requests to all RPC URLs and check that they agree on the blocks.
+    ///
+    /// Protects ingestion from lagging or forked beacon nodes, at the cost of
+    /// sending more requests. Only used with more than one URL.
+    #[arg(
+        long = "rpc.consistency-check",
+        env = "BEACON_RPC_CONSISTENCY_CHECK",
+        default_value = "false"
+    )]
+    pub rpc_consistency_check: bool,
+
+    /// With consistency checks, ignore nodes whose head is more than this many slots
+    /// behind the other nodes.
+    #[arg(
+        long = "rpc.max-head-lag",
+        env = "BEACON_RPC_MAX_HEAD_LAG",
+        default_value = "4"
+    )]
+    pub rpc_max_head_lag: u64,
 
     /// Timeout for normal requests.
     #[arg(
@@ -45,12 +105,25 @@ pub struct RpcArgs {
 
 impl RpcArgs {
     pub fn to_beacon_api_provider(&self) -> Result<BeaconApiProvider, BeaconChainError> {
-        let url = self
-            .rpc_url
-            .parse::<Url>()
+        let urls = weighted_urls(&self.rpc_url, &self.rpc_weights)
             .change_context(BeaconChainError)
-            .attach_printable("failed to parse RPC URL")
-            .attach_printable_lazy(|| format!("url: {}", self.rpc_url))?;
+            .attach_printable("invalid RPC weights")?
+            .into_iter()
+            .map(
+                |(url, weight)| -> Result<RpcEndpoint<Url>, BeaconChainError> {
+                    let url = url
+                        .parse::<Url>()
+                        .change_context(BeaconChainError)
+                        .attach_printable("failed to parse RPC URL")
+                        .attach_printable_lazy(|| format!("url: {}", url))?;
+                    Ok(RpcEndpoint {
+                        name: url.host_str().unwrap_or_default().to_string(),
+                        client: url,
+                        weight,
+                    })
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
 
         let headers = {
             let mut headers = HeaderMap::default();
@@ -82,8 +155,16 @@ impl RpcArgs {
             timeout: Duration::from_secs(self.rpc_timeout_sec),
             validators_timeout: Duration::from_secs(self.rpc_validators_timeout_sec),
             headers,
+            pool: RpcPoolOptions {
+                load_balance: self.rpc_load_balance,
+                unhealthy_cooldown: Duration::from_secs(self.rpc_failover_cooldown_sec),
+                health_check_interval: (self.rpc_health_check_interval_sec > 0)
+                    .then(|| Duration::from_secs(self.rpc_health_check_interval_sec)),
+            },
+            consistency_check: self.rpc_consistency_check,
+            max_head_lag: self.rpc_max_head_lag,
         };
 
-        Ok(BeaconApiProvider::new(url, options))
+        BeaconApiProvider::new(urls, options).change_context(BeaconChainError)
     }
 }
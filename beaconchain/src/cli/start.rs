@@ -36,6 +36,7 @@ impl StartCommand {
     pub async fn run(self, ct: CancellationToken) -> Result<(), BeaconChainError> {
         info!("Starting Beaconchain DNA server");
         let provider = self.rpc.to_beacon_api_provider()?;
+        tokio::spawn(provider.clone().health_check_loop(ct.clone()));
         let options = self.beaconchain.to_beacon_chain_options();
         let beaconchain_chain = BeaconChainChainSupport::new(provider, options);
 
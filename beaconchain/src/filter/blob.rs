@@ -1,12 +1,34 @@
-use apibara_dna_common::query::Filter;
-use apibara_dna_protocol::beaconchain;
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
 
-use crate::fragment::{BLOB_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID};
+use crate::fragment::{
+    BLOB_FRAGMENT_ID, INDEX_BLOB_BY_BLOB_HASH, INDEX_BLOB_BY_KZG_COMMITMENT,
+    TRANSACTION_FRAGMENT_ID,
+};
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for beaconchain::BlobFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(kzg_commitment) = self.kzg_commitment.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_BLOB_BY_KZG_COMMITMENT,
+                key: ScalarValue::B384(kzg_commitment.to_bytes()).into(),
+            });
+        }
+
+        if let Some(blob_hash) = self.blob_hash.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_BLOB_BY_BLOB_HASH,
+                key: ScalarValue::B256(blob_hash.to_bytes()).into(),
+            });
+        }
+
         let mut joins = Vec::new();
 
         if let Some(true) = self.include_transaction {
@@ -16,8 +38,9 @@ impl FragmentFilterExt for beaconchain::BlobFilter {
         Ok(Filter {
             filter_id: self.id,
             fragment_id: BLOB_FRAGMENT_ID,
-            conditions: Vec::default(),
+            conditions,
             joins,
+            fields: compile_field_mask("beaconchain.v2.Blob", self.fields.as_ref())?,
         })
     }
 }
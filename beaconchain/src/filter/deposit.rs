@@ -0,0 +1,39 @@
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
+
+use crate::fragment::{
+    DEPOSIT_FRAGMENT_ID, INDEX_DEPOSIT_BY_PUBKEY, INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS,
+};
+
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+impl FragmentFilterExt for beaconchain::DepositFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(pubkey) = self.pubkey.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_DEPOSIT_BY_PUBKEY,
+                key: ScalarValue::B384(pubkey.to_bytes()).into(),
+            });
+        }
+
+        if let Some(withdrawal_credentials) = self.withdrawal_credentials.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS,
+                key: ScalarValue::B256(withdrawal_credentials.to_bytes()).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: DEPOSIT_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.Deposit", self.fields.as_ref())?,
+        })
+    }
+}
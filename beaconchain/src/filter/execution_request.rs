@@ -0,0 +1,80 @@
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
+
+use crate::fragment::{
+    CONSOLIDATION_REQUEST_FRAGMENT_ID, INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+    INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_PUBKEY, INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+    INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS, INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY,
+    WITHDRAWAL_REQUEST_FRAGMENT_ID,
+};
+
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+impl FragmentFilterExt for beaconchain::WithdrawalRequestFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(source_address) = self.source_address.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS,
+                key: ScalarValue::B160(source_address.to_bytes()).into(),
+            });
+        }
+
+        if let Some(validator_pubkey) = self.validator_pubkey.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY,
+                key: ScalarValue::B384(validator_pubkey.to_bytes()).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: WITHDRAWAL_REQUEST_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.WithdrawalRequest", self.fields.as_ref())?,
+        })
+    }
+}
+
+impl FragmentFilterExt for beaconchain::ConsolidationRequestFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(source_address) = self.source_address.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+                key: ScalarValue::B160(source_address.to_bytes()).into(),
+            });
+        }
+
+        if let Some(source_pubkey) = self.source_pubkey.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_PUBKEY,
+                key: ScalarValue::B384(source_pubkey.to_bytes()).into(),
+            });
+        }
+
+        if let Some(target_pubkey) = self.target_pubkey.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+                key: ScalarValue::B384(target_pubkey.to_bytes()).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: CONSOLIDATION_REQUEST_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask(
+                "beaconchain.v2.ConsolidationRequest",
+                self.fields.as_ref(),
+            )?,
+        })
+    }
+}
@@ -1,16 +1,28 @@
 mod blob;
+mod deposit;
+mod execution_request;
 mod helpers;
+mod slashing;
 mod transaction;
 mod validator;
+mod voluntary_exit;
 
 use apibara_dna_common::{
     data_stream::BlockFilterFactory,
-    query::{BlockFilter, HeaderFilter},
+    fragment::HEADER_FRAGMENT_ID,
+    index::ScalarValue,
+    query::{BlockFilter, Condition, FieldProjection, Filter, HeaderFilter},
+};
+use apibara_dna_protocol::{
+    beaconchain,
+    error_details::{BadRequest, FieldViolation},
+    field_mask::resolve_field_mask,
 };
-use apibara_dna_protocol::beaconchain;
 use prost::Message;
 
-use self::helpers::{BlockFilterExt, FragmentFilterExt};
+use crate::fragment::INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER;
+
+use self::helpers::{compile_fragment_filters, BlockFilterExt};
 
 pub struct BeaconChainFilterFactory;
 
@@ -19,40 +31,53 @@ impl BlockFilterFactory for BeaconChainFilterFactory {
         &self,
         filters: &[Vec<u8>],
     ) -> tonic::Result<Vec<BlockFilter>, tonic::Status> {
-        let proto_filters = filters
-            .iter()
-            .map(|bytes| beaconchain::Filter::decode(bytes.as_slice()))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| tonic::Status::invalid_argument("failed to decode filter"))?;
-
-        if proto_filters.is_empty() {
-            return Err(tonic::Status::invalid_argument("no filters provided"));
+        let mut block_filters = Vec::with_capacity(filters.len());
+        let mut violations = Vec::new();
+
+        for (index, bytes) in filters.iter().enumerate() {
+            let parent = format!("filter[{index}]");
+
+            let Ok(filter) = beaconchain::Filter::decode(bytes.as_slice()) else {
+                violations
+                    .push(FieldViolation::new("", "failed to decode filter").with_parent(parent));
+                continue;
+            };
+
+            match filter.compile_to_block_filter() {
+                Ok(block_filter) => block_filters.push(block_filter),
+                Err(errors) => violations.extend(
+                    errors
+                        .into_iter()
+                        .map(|violation| violation.with_parent(&parent)),
+                ),
+            }
         }
 
-        if proto_filters.len() > 5 {
-            return Err(tonic::Status::invalid_argument(format!(
-                "too many filters ({} > 5)",
-                proto_filters.len(),
-            )));
+        if !violations.is_empty() {
+            return Err(BadRequest::new(violations).into_status());
         }
 
-        let filters = proto_filters
-            .iter()
-            .map(BlockFilterExt::compile_to_block_filter)
-            .collect::<tonic::Result<Vec<_>>>()?;
+        if block_filters.is_empty() {
+            return Err(filter_violation("no filters provided"));
+        }
 
-        if filters.iter().any(|f| f.can_produce_data()) {
-            Ok(filters)
+        if block_filters.iter().any(|f| f.can_produce_data()) {
+            Ok(block_filters)
         } else {
-            Err(tonic::Status::invalid_argument(
-                "at least one filter must be non-empty",
-            ))
+            Err(filter_violation("at least one filter must be non-empty"))
         }
     }
+
+    fn filter_descriptor(&self) -> (&'static [u8], &'static str) {
+        (
+            beaconchain::BEACONCHAIN_DESCRIPTOR_SET,
+            "beaconchain.v2.Filter",
+        )
+    }
 }
 
 impl BlockFilterExt for beaconchain::Filter {
-    fn compile_to_block_filter(&self) -> tonic::Result<BlockFilter, tonic::Status> {
+    fn compile_to_block_filter(&self) -> Result<BlockFilter, Vec<FieldViolation>> {
         let mut block_filter = BlockFilter::default();
 
         let header_filter = match beaconchain::HeaderFilter::try_from(self.header) {
@@ -67,21 +92,97 @@ impl BlockFilterExt for beaconchain::Filter {
 
         block_filter.set_header_filter(header_filter);
 
-        for filter in self.transactions.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+        let mut violations = Vec::new();
+        if let Some(header_fields) = self.header_fields.as_ref() {
+            match resolve_field_mask(
+                beaconchain::BEACONCHAIN_DESCRIPTOR_SET,
+                "beaconchain.v2.BlockHeader",
+                header_fields,
+            ) {
+                Ok(fields) => block_filter.set_header_fields(FieldProjection::new(fields)),
+                Err(violation) => violations.push(violation.with_parent("header_fields")),
+            }
         }
 
-        for filter in self.blobs.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
+        if let Some(execution_block_number) = self.execution_block_number {
+            if block_filter.always_include_header() {
+                violations.push(FieldViolation::new(
+                    "execution_block_number",
+                    "has no effect when the header filter is `always`",
+                ));
+            }
 
-        for filter in self.validators.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+            block_filter.add_filter(Filter {
+                filter_id: 0,
+                fragment_id: HEADER_FRAGMENT_ID,
+                conditions: vec![Condition {
+                    index_id: INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+                    key: ScalarValue::Uint64(execution_block_number).into(),
+                }],
+                joins: Vec::default(),
+                fields: None,
+            });
         }
 
-        Ok(block_filter)
+        compile_fragment_filters(
+            &mut block_filter,
+            "transactions",
+            &self.transactions,
+            &mut violations,
+        );
+        compile_fragment_filters(&mut block_filter, "blobs", &self.blobs, &mut violations);
+        compile_fragment_filters(
+            &mut block_filter,
+            "validators",
+            &self.validators,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "deposits",
+            &self.deposits,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "voluntary_exits",
+            &self.voluntary_exits,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "proposer_slashings",
+            &self.proposer_slashings,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "attester_slashings",
+            &self.attester_slashings,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "withdrawal_requests",
+            &self.withdrawal_requests,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "consolidation_requests",
+            &self.consolidation_requests,
+            &mut violations,
+        );
+
+        if violations.is_empty() {
+            Ok(block_filter)
+        } else {
+            Err(violations)
+        }
     }
 }
+
+/// Returns an error about the list of filters as a whole.
+fn filter_violation(description: &str) -> tonic::Status {
+    BadRequest::new(vec![FieldViolation::new("filter", description)]).into_status()
+}
@@ -0,0 +1,54 @@
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
+
+use crate::fragment::{
+    ATTESTER_SLASHING_FRAGMENT_ID, INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+    INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX, PROPOSER_SLASHING_FRAGMENT_ID,
+};
+
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+impl FragmentFilterExt for beaconchain::ProposerSlashingFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(proposer_index) = self.proposer_index {
+            conditions.push(Condition {
+                index_id: INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX,
+                key: ScalarValue::Uint32(proposer_index).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: PROPOSER_SLASHING_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.ProposerSlashing", self.fields.as_ref())?,
+        })
+    }
+}
+
+impl FragmentFilterExt for beaconchain::AttesterSlashingFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(validator_index) = self.validator_index {
+            conditions.push(Condition {
+                index_id: INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+                key: ScalarValue::Uint32(validator_index).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: ATTESTER_SLASHING_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.AttesterSlashing", self.fields.as_ref())?,
+        })
+    }
+}
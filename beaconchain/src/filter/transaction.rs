@@ -2,37 +2,37 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::beaconchain;
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
 
 use crate::fragment::{
     BLOB_FRAGMENT_ID, INDEX_TRANSACTION_BY_CREATE, INDEX_TRANSACTION_BY_FROM_ADDRESS,
     INDEX_TRANSACTION_BY_TO_ADDRESS, TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for beaconchain::TransactionFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(from) = self.from.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_FROM_ADDRESS,
-                key: ScalarValue::B160(from.to_bytes()),
+                key: ScalarValue::B160(from.to_bytes()).into(),
             });
         }
 
         if let Some(to) = self.to.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_TO_ADDRESS,
-                key: ScalarValue::B160(to.to_bytes()),
+                key: ScalarValue::B160(to.to_bytes()).into(),
             });
         }
 
         if let Some(true) = self.create {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_CREATE,
-                key: ScalarValue::Bool(true),
+                key: ScalarValue::Bool(true).into(),
             });
         }
 
@@ -47,6 +47,7 @@ impl FragmentFilterExt for beaconchain::TransactionFilter {
             fragment_id: TRANSACTION_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("beaconchain.v2.Transaction", self.fields.as_ref())?,
         })
     }
 }
@@ -2,27 +2,27 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::beaconchain;
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
 
 use crate::fragment::{INDEX_VALIDATOR_BY_INDEX, INDEX_VALIDATOR_BY_STATUS, VALIDATOR_FRAGMENT_ID};
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for beaconchain::ValidatorFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(index) = self.validator_index {
             conditions.push(Condition {
                 index_id: INDEX_VALIDATOR_BY_INDEX,
-                key: ScalarValue::Uint32(index),
+                key: ScalarValue::Uint32(index).into(),
             });
         }
 
         if let Some(status) = self.status {
             conditions.push(Condition {
                 index_id: INDEX_VALIDATOR_BY_STATUS,
-                key: ScalarValue::Int32(status),
+                key: ScalarValue::Int32(status).into(),
             });
         }
 
@@ -31,6 +31,7 @@ impl FragmentFilterExt for beaconchain::ValidatorFilter {
             fragment_id: VALIDATOR_FRAGMENT_ID,
             conditions,
             joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.Validator", self.fields.as_ref())?,
         })
     }
 }
@@ -0,0 +1,30 @@
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{beaconchain, error_details::FieldViolation};
+
+use crate::fragment::{INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX, VOLUNTARY_EXIT_FRAGMENT_ID};
+
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+impl FragmentFilterExt for beaconchain::VoluntaryExitFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(validator_index) = self.validator_index {
+            conditions.push(Condition {
+                index_id: INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX,
+                key: ScalarValue::Uint32(validator_index).into(),
+            });
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: VOLUNTARY_EXIT_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("beaconchain.v2.VoluntaryExit", self.fields.as_ref())?,
+        })
+    }
+}
@@ -1,5 +1,7 @@
 //! Fragment constants.
 
+use apibara_dna_common::fragment::FragmentInfo;
+
 // Make sure the fragment IDs match the field tags in the protobuf Block message.
 
 pub const TRANSACTION_FRAGMENT_ID: u8 = 2;
@@ -11,9 +13,91 @@ pub const VALIDATOR_FRAGMENT_NAME: &str = "validator";
 pub const BLOB_FRAGMENT_ID: u8 = 4;
 pub const BLOB_FRAGMENT_NAME: &str = "blob";
 
+pub const DEPOSIT_FRAGMENT_ID: u8 = 5;
+pub const DEPOSIT_FRAGMENT_NAME: &str = "deposit";
+
+pub const VOLUNTARY_EXIT_FRAGMENT_ID: u8 = 6;
+pub const VOLUNTARY_EXIT_FRAGMENT_NAME: &str = "voluntary_exit";
+
+pub const PROPOSER_SLASHING_FRAGMENT_ID: u8 = 7;
+pub const PROPOSER_SLASHING_FRAGMENT_NAME: &str = "proposer_slashing";
+
+pub const ATTESTER_SLASHING_FRAGMENT_ID: u8 = 8;
+pub const ATTESTER_SLASHING_FRAGMENT_NAME: &str = "attester_slashing";
+
+pub const WITHDRAWAL_REQUEST_FRAGMENT_ID: u8 = 9;
+pub const WITHDRAWAL_REQUEST_FRAGMENT_NAME: &str = "withdrawal_request";
+
+pub const CONSOLIDATION_REQUEST_FRAGMENT_ID: u8 = 10;
+pub const CONSOLIDATION_REQUEST_FRAGMENT_NAME: &str = "consolidation_request";
+
+/// The header's index has a single row, the block's header.
+pub const INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER: u8 = 0;
+
 pub const INDEX_TRANSACTION_BY_FROM_ADDRESS: u8 = 0;
 pub const INDEX_TRANSACTION_BY_TO_ADDRESS: u8 = 1;
 pub const INDEX_TRANSACTION_BY_CREATE: u8 = 2;
 
 pub const INDEX_VALIDATOR_BY_INDEX: u8 = 0;
 pub const INDEX_VALIDATOR_BY_STATUS: u8 = 1;
+
+pub const INDEX_BLOB_BY_KZG_COMMITMENT: u8 = 0;
+pub const INDEX_BLOB_BY_BLOB_HASH: u8 = 1;
+
+pub const INDEX_DEPOSIT_BY_PUBKEY: u8 = 0;
+pub const INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS: u8 = 1;
+
+pub const INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX: u8 = 0;
+
+pub const INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX: u8 = 0;
+
+pub const INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX: u8 = 0;
+
+pub const INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS: u8 = 0;
+pub const INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY: u8 = 1;
+
+pub const INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS: u8 = 0;
+pub const INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_PUBKEY: u8 = 1;
+pub const INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY: u8 = 2;
+
+/// Returns the fragments generated by the chain.
+pub fn fragment_info() -> Vec<FragmentInfo> {
+    vec![
+        FragmentInfo {
+            fragment_id: TRANSACTION_FRAGMENT_ID,
+            name: TRANSACTION_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: VALIDATOR_FRAGMENT_ID,
+            name: VALIDATOR_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: BLOB_FRAGMENT_ID,
+            name: BLOB_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: DEPOSIT_FRAGMENT_ID,
+            name: DEPOSIT_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: VOLUNTARY_EXIT_FRAGMENT_ID,
+            name: VOLUNTARY_EXIT_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: PROPOSER_SLASHING_FRAGMENT_ID,
+            name: PROPOSER_SLASHING_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: ATTESTER_SLASHING_FRAGMENT_ID,
+            name: ATTESTER_SLASHING_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: WITHDRAWAL_REQUEST_FRAGMENT_ID,
+            name: WITHDRAWAL_REQUEST_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: CONSOLIDATION_REQUEST_FRAGMENT_ID,
+            name: CONSOLIDATION_REQUEST_FRAGMENT_NAME.to_string(),
+        },
+    ]
+}
@@ -5,23 +5,36 @@ use apibara_dna_common::{
     chain::BlockInfo,
     fragment::{
         Block, BodyFragment, HeaderFragment, Index, IndexFragment, IndexGroupFragment, Join,
-        JoinFragment, JoinGroupFragment,
+        JoinFragment, JoinGroupFragment, HEADER_FRAGMENT_ID,
     },
     index::{BitmapIndexBuilder, ScalarValue},
-    ingestion::{BlockIngestion, IngestionError},
+    ingestion::{BlockIngestion, HeadSubscription, IngestionError},
     join::{JoinToManyIndex, JoinToManyIndexBuilder, JoinToOneIndex, JoinToOneIndexBuilder},
     Cursor, Hash,
 };
 use error_stack::{FutureExt, Result, ResultExt};
+use futures::StreamExt;
 use prost::Message;
 use tracing::Instrument;
 
 use crate::{
     fragment::{
-        BLOB_FRAGMENT_ID, BLOB_FRAGMENT_NAME, INDEX_TRANSACTION_BY_CREATE,
-        INDEX_TRANSACTION_BY_FROM_ADDRESS, INDEX_TRANSACTION_BY_TO_ADDRESS,
-        INDEX_VALIDATOR_BY_INDEX, INDEX_VALIDATOR_BY_STATUS, TRANSACTION_FRAGMENT_ID,
-        TRANSACTION_FRAGMENT_NAME, VALIDATOR_FRAGMENT_ID, VALIDATOR_FRAGMENT_NAME,
+        ATTESTER_SLASHING_FRAGMENT_ID, ATTESTER_SLASHING_FRAGMENT_NAME, BLOB_FRAGMENT_ID,
+        BLOB_FRAGMENT_NAME, CONSOLIDATION_REQUEST_FRAGMENT_ID, CONSOLIDATION_REQUEST_FRAGMENT_NAME,
+        DEPOSIT_FRAGMENT_ID, DEPOSIT_FRAGMENT_NAME, INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+        INDEX_BLOB_BY_BLOB_HASH, INDEX_BLOB_BY_KZG_COMMITMENT,
+        INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+        INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_PUBKEY, INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+        INDEX_DEPOSIT_BY_PUBKEY, INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS,
+        INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER, INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX,
+        INDEX_TRANSACTION_BY_CREATE, INDEX_TRANSACTION_BY_FROM_ADDRESS,
+        INDEX_TRANSACTION_BY_TO_ADDRESS, INDEX_VALIDATOR_BY_INDEX, INDEX_VALIDATOR_BY_STATUS,
+        INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX, INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS,
+        INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY, PROPOSER_SLASHING_FRAGMENT_ID,
+        PROPOSER_SLASHING_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME,
+        VALIDATOR_FRAGMENT_ID, VALIDATOR_FRAGMENT_NAME, VOLUNTARY_EXIT_FRAGMENT_ID,
+        VOLUNTARY_EXIT_FRAGMENT_NAME, WITHDRAWAL_REQUEST_FRAGMENT_ID,
+        WITHDRAWAL_REQUEST_FRAGMENT_NAME,
     },
     proto::{FallibleModelExt, ModelExt},
     provider::{
@@ -48,10 +61,13 @@ impl BeaconChainBlockIngestion {
     ) -> Result<Option<(BlockInfo, Block)>, IngestionError> {
         let block_id = BlockId::Slot(block_number);
 
+        // Fetch all data from the same node, so that it's from the same block.
+        let provider = self.provider.pinned();
+
         // Fetch all data using the block root to avoid issues with reorgs.
         // Except for the validators since it won't work with the block root.
         let original_block_id = block_id.clone();
-        let block_root = match self.provider.get_block_root(block_id).await {
+        let block_root = match provider.get_block_root(block_id).await {
             Ok(header) => header,
             Err(err) if err.is_not_found() => return Ok(None),
             Err(err) => {
@@ -62,7 +78,7 @@ impl BeaconChainBlockIngestion {
         let block_id = BlockId::BlockRoot(block_root.data.root);
 
         let block = tokio::spawn({
-            let provider = self.provider.clone();
+            let provider = provider.clone();
             let block_id = block_id.clone();
             async move {
                 provider
@@ -76,7 +92,7 @@ impl BeaconChainBlockIngestion {
         .instrument(tracing::debug_span!("beaconchain_get_block"));
 
         let blob_sidecar = tokio::spawn({
-            let provider = self.provider.clone();
+            let provider = provider.clone();
             let block_id = block_id.clone();
             async move {
                 provider
@@ -90,7 +106,7 @@ impl BeaconChainBlockIngestion {
         .instrument(tracing::debug_span!("beaconchain_get_blob_sidecar"));
 
         let validators = tokio::spawn({
-            let provider = self.provider.clone();
+            let provider = provider.clone();
             let block_id = original_block_id.clone();
             let should_ingest = self.options.sample_validators > 0
                 && block_number % self.options.sample_validators == 0;
@@ -145,7 +161,18 @@ impl BeaconChainBlockIngestion {
             }
         };
 
-        let (body, index, join) = collect_block_body_and_index(&transactions, &validators, &blobs)?;
+        let (mut body, mut index, mut join) =
+            collect_block_body_and_index(&transactions, &validators, &blobs)?;
+
+        let (operations_body, operations_index, operations_join) =
+            collect_operations_body_and_index(Some(&block.body))?;
+        body.extend(operations_body);
+        index.indexes.extend(operations_index);
+        join.joins.extend(operations_join);
+
+        index
+            .indexes
+            .push(collect_header_index(block.body.execution_payload.as_ref())?);
 
         let block = Block {
             header: header_fragment,
@@ -216,6 +243,41 @@ impl BlockIngestion for BeaconChainBlockIngestion {
         Ok(cursor)
     }
 
+    async fn subscribe_heads(&self) -> Result<Option<HeadSubscription>, IngestionError> {
+        let stream = self
+            .provider
+            .subscribe_heads()
+            .await
+            .change_context(IngestionError::RpcRequest)
+            .attach_printable("failed to subscribe to head events")?;
+
+        Ok(Some(
+            stream
+                .map(|event| event.change_context(IngestionError::RpcRequest))
+                .boxed(),
+        ))
+    }
+
+    /// Returns the genesis validators root.
+    ///
+    /// Checkpoint-synced nodes don't have the genesis block, but they all know the genesis.
+    #[tracing::instrument(
+        "beaconchain_get_genesis_hash",
+        skip(self),
+        err(Debug),
+        level = "debug"
+    )]
+    async fn get_genesis_hash(&self) -> Result<Hash, IngestionError> {
+        let genesis = self
+            .provider
+            .get_genesis()
+            .await
+            .change_context(IngestionError::RpcRequest)
+            .attach_printable("failed to get genesis")?;
+
+        Ok(Hash(genesis.data.genesis_validators_root.0.to_vec()))
+    }
+
     #[tracing::instrument(
         "beaconchain_get_block_info_by_number",
         skip(self),
@@ -280,7 +342,7 @@ impl BlockIngestion for BeaconChainBlockIngestion {
         // Missed slots have no data and no indices.
         let header_fragment = HeaderFragment { data: Vec::new() };
 
-        let index_fragment = IndexGroupFragment {
+        let mut index_fragment = IndexGroupFragment {
             indexes: vec![
                 IndexFragment {
                     fragment_id: TRANSACTION_FRAGMENT_ID,
@@ -320,12 +382,21 @@ impl BlockIngestion for BeaconChainBlockIngestion {
                     fragment_id: BLOB_FRAGMENT_ID,
                     range_start: 0,
                     range_len: 0,
-                    indexes: Vec::default(),
+                    indexes: vec![
+                        Index {
+                            index_id: INDEX_BLOB_BY_KZG_COMMITMENT,
+                            index: apibara_dna_common::index::Index::Empty,
+                        },
+                        Index {
+                            index_id: INDEX_BLOB_BY_BLOB_HASH,
+                            index: apibara_dna_common::index::Index::Empty,
+                        },
+                    ],
                 },
             ],
         };
 
-        let join_fragment = JoinGroupFragment {
+        let mut join_fragment = JoinGroupFragment {
             joins: vec![
                 JoinFragment {
                     fragment_id: TRANSACTION_FRAGMENT_ID,
@@ -348,7 +419,7 @@ impl BlockIngestion for BeaconChainBlockIngestion {
             ],
         };
 
-        let body_fragments = vec![
+        let mut body_fragments = vec![
             BodyFragment {
                 fragment_id: TRANSACTION_FRAGMENT_ID,
                 name: TRANSACTION_FRAGMENT_NAME.to_string(),
@@ -366,6 +437,22 @@ impl BlockIngestion for BeaconChainBlockIngestion {
             },
         ];
 
+        let (operations_body, operations_index, operations_join) =
+            collect_operations_body_and_index(None)?;
+        body_fragments.extend(operations_body);
+        index_fragment.indexes.extend(operations_index);
+        join_fragment.joins.extend(operations_join);
+
+        index_fragment.indexes.push(IndexFragment {
+            fragment_id: HEADER_FRAGMENT_ID,
+            range_start: 0,
+            range_len: 0,
+            indexes: vec![Index {
+                index_id: INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+                index: apibara_dna_common::index::Index::Empty,
+            }],
+        });
+
         let block = Block {
             header: header_fragment,
             index: index_fragment,
@@ -377,6 +464,35 @@ impl BlockIngestion for BeaconChainBlockIngestion {
     }
 }
 
+/// Index the block's header, so that filters can select blocks by their execution payload.
+///
+/// The index has a single row, the header.
+pub fn collect_header_index(
+    execution_payload: Option<&models::ExecutionPayload>,
+) -> Result<IndexFragment, IngestionError> {
+    let mut index_header_by_execution_block_number = BitmapIndexBuilder::default();
+
+    if let Some(execution_payload) = execution_payload {
+        index_header_by_execution_block_number
+            .insert(ScalarValue::Uint64(execution_payload.block_number), 0);
+    }
+
+    let index_header_by_execution_block_number = Index {
+        index_id: INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+        index: index_header_by_execution_block_number
+            .build()
+            .change_context(IngestionError::Indexing)?
+            .into(),
+    };
+
+    Ok(IndexFragment {
+        fragment_id: HEADER_FRAGMENT_ID,
+        range_start: 0,
+        range_len: 1,
+        indexes: vec![index_header_by_execution_block_number],
+    })
+}
+
 pub fn collect_block_body_and_index(
     transactions: &[models::Bytes],
     validators: &[models::Validator],
@@ -399,6 +515,8 @@ pub fn collect_block_body_and_index(
     let mut index_validator_by_index = BitmapIndexBuilder::default();
     let mut index_validator_by_status = BitmapIndexBuilder::default();
 
+    let mut index_blob_by_kzg_commitment = BitmapIndexBuilder::default();
+    let mut index_blob_by_blob_hash = BitmapIndexBuilder::default();
     let mut join_blob_to_transaction = JoinToOneIndexBuilder::default();
 
     for (transaction_index, transaction) in transactions.into_iter().enumerate() {
@@ -457,6 +575,18 @@ pub fn collect_block_body_and_index(
         blob.transaction_index = tx.transaction_index;
         blob.transaction_hash = tx.transaction_hash;
 
+        if let Some(kzg_commitment) = blob.kzg_commitment.as_ref() {
+            index_blob_by_kzg_commitment.insert(
+                ScalarValue::B384(kzg_commitment.to_bytes()),
+                blob.blob_index,
+            );
+        }
+
+        if let Some(blob_hash) = blob.blob_hash.as_ref() {
+            index_blob_by_blob_hash
+                .insert(ScalarValue::B256(blob_hash.to_bytes()), blob.blob_index);
+        }
+
         join_blob_to_transaction.insert(blob.blob_index, tx.transaction_index);
         join_transaction_to_blobs.insert(tx.transaction_index, blob.blob_index);
 
@@ -563,11 +693,29 @@ pub fn collect_block_body_and_index(
             .collect(),
     };
 
-    let blob_index = IndexFragment {
-        fragment_id: BLOB_FRAGMENT_ID,
-        range_start: 0,
-        range_len: block_blobs.len() as u32,
-        indexes: Vec::new(),
+    let blob_index = {
+        let index_blob_by_kzg_commitment = Index {
+            index_id: INDEX_BLOB_BY_KZG_COMMITMENT,
+            index: index_blob_by_kzg_commitment
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
+
+        let index_blob_by_blob_hash = Index {
+            index_id: INDEX_BLOB_BY_BLOB_HASH,
+            index: index_blob_by_blob_hash
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
+
+        IndexFragment {
+            fragment_id: BLOB_FRAGMENT_ID,
+            range_start: 0,
+            range_len: block_blobs.len() as u32,
+            indexes: vec![index_blob_by_kzg_commitment, index_blob_by_blob_hash],
+        }
     };
 
     let blob_join = {
@@ -603,8 +751,778 @@ pub fn collect_block_body_and_index(
     ))
 }
 
+/// The body, index and join fragments of the operations in a block.
+pub type OperationFragments = (Vec<BodyFragment>, Vec<IndexFragment>, Vec<JoinFragment>);
+
+/// Collects the deposits, exits, slashings and execution layer requests in the block body.
+///
+/// Missed slots have no body and produce empty fragments.
+pub fn collect_operations_body_and_index(
+    body: Option<&models::BeaconBlockBody>,
+) -> Result<OperationFragments, IngestionError> {
+    let mut block_deposits = Vec::new();
+    let mut block_voluntary_exits = Vec::new();
+    let mut block_proposer_slashings = Vec::new();
+    let mut block_attester_slashings = Vec::new();
+    let mut block_withdrawal_requests = Vec::new();
+    let mut block_consolidation_requests = Vec::new();
+
+    let mut index_deposit_by_pubkey = BitmapIndexBuilder::default();
+    let mut index_deposit_by_withdrawal_credentials = BitmapIndexBuilder::default();
+
+    let mut index_voluntary_exit_by_validator_index = BitmapIndexBuilder::default();
+
+    let mut index_proposer_slashing_by_proposer_index = BitmapIndexBuilder::default();
+
+    let mut index_attester_slashing_by_validator_index = BitmapIndexBuilder::default();
+
+    let mut index_withdrawal_request_by_source_address = BitmapIndexBuilder::default();
+    let mut index_withdrawal_request_by_validator_pubkey = BitmapIndexBuilder::default();
+
+    let mut index_consolidation_request_by_source_address = BitmapIndexBuilder::default();
+    let mut index_consolidation_request_by_source_pubkey = BitmapIndexBuilder::default();
+    let mut index_consolidation_request_by_target_pubkey = BitmapIndexBuilder::default();
+
+    if let Some(body) = body {
+        for (deposit_index, deposit) in body.deposits.iter().enumerate() {
+            let deposit_index = deposit_index as u32;
+
+            let mut deposit = deposit.to_proto();
+            deposit.deposit_index = deposit_index;
+
+            if let Some(pubkey) = deposit.pubkey.as_ref() {
+                index_deposit_by_pubkey.insert(ScalarValue::B384(pubkey.to_bytes()), deposit_index);
+            }
+
+            if let Some(withdrawal_credentials) = deposit.withdrawal_credentials.as_ref() {
+                index_deposit_by_withdrawal_credentials.insert(
+                    ScalarValue::B256(withdrawal_credentials.to_bytes()),
+                    deposit_index,
+                );
+            }
+
+            block_deposits.push(deposit);
+        }
+
+        for (voluntary_exit_index, voluntary_exit) in body.voluntary_exits.iter().enumerate() {
+            let voluntary_exit_index = voluntary_exit_index as u32;
+
+            let mut voluntary_exit = voluntary_exit.to_proto();
+            voluntary_exit.voluntary_exit_index = voluntary_exit_index;
+
+            index_voluntary_exit_by_validator_index.insert(
+                ScalarValue::Uint32(voluntary_exit.validator_index),
+                voluntary_exit_index,
+            );
+
+            block_voluntary_exits.push(voluntary_exit);
+        }
+
+        for (proposer_slashing_index, proposer_slashing) in
+            body.proposer_slashings.iter().enumerate()
+        {
+            let proposer_slashing_index = proposer_slashing_index as u32;
+
+            let mut proposer_slashing = proposer_slashing.to_proto();
+            proposer_slashing.proposer_slashing_index = proposer_slashing_index;
+
+            if let Some(header) = proposer_slashing.signed_header_1.as_ref() {
+                index_proposer_slashing_by_proposer_index.insert(
+                    ScalarValue::Uint32(header.proposer_index),
+                    proposer_slashing_index,
+                );
+            }
+
+            block_proposer_slashings.push(proposer_slashing);
+        }
+
+        for (attester_slashing_index, attester_slashing) in
+            body.attester_slashings.iter().enumerate()
+        {
+            let attester_slashing_index = attester_slashing_index as u32;
+
+            let mut attester_slashing = attester_slashing.to_proto();
+            attester_slashing.attester_slashing_index = attester_slashing_index;
+
+            for validator_index in attester_slashing.slashed_indices.iter() {
+                index_attester_slashing_by_validator_index.insert(
+                    ScalarValue::Uint32(*validator_index),
+                    attester_slashing_index,
+                );
+            }
+
+            block_attester_slashings.push(attester_slashing);
+        }
+
+        if let Some(execution_requests) = body.execution_requests.as_ref() {
+            for (withdrawal_request_index, withdrawal_request) in
+                execution_requests.withdrawals.iter().enumerate()
+            {
+                let withdrawal_request_index = withdrawal_request_index as u32;
+
+                let mut withdrawal_request = withdrawal_request.to_proto();
+                withdrawal_request.withdrawal_request_index = withdrawal_request_index;
+
+                if let Some(source_address) = withdrawal_request.source_address.as_ref() {
+                    index_withdrawal_request_by_source_address.insert(
+                        ScalarValue::B160(source_address.to_bytes()),
+                        withdrawal_request_index,
+                    );
+                }
+
+                if let Some(validator_pubkey) = withdrawal_request.validator_pubkey.as_ref() {
+                    index_withdrawal_request_by_validator_pubkey.insert(
+                        ScalarValue::B384(validator_pubkey.to_bytes()),
+                        withdrawal_request_index,
+                    );
+                }
+
+                block_withdrawal_requests.push(withdrawal_request);
+            }
+
+            for (consolidation_request_index, consolidation_request) in
+                execution_requests.consolidations.iter().enumerate()
+            {
+                let consolidation_request_index = consolidation_request_index as u32;
+
+                let mut consolidation_request = consolidation_request.to_proto();
+                consolidation_request.consolidation_request_index = consolidation_request_index;
+
+                if let Some(source_address) = consolidation_request.source_address.as_ref() {
+                    index_consolidation_request_by_source_address.insert(
+                        ScalarValue::B160(source_address.to_bytes()),
+                        consolidation_request_index,
+                    );
+                }
+
+                if let Some(source_pubkey) = consolidation_request.source_pubkey.as_ref() {
+                    index_consolidation_request_by_source_pubkey.insert(
+                        ScalarValue::B384(source_pubkey.to_bytes()),
+                        consolidation_request_index,
+                    );
+                }
+
+                if let Some(target_pubkey) = consolidation_request.target_pubkey.as_ref() {
+                    index_consolidation_request_by_target_pubkey.insert(
+                        ScalarValue::B384(target_pubkey.to_bytes()),
+                        consolidation_request_index,
+                    );
+                }
+
+                block_consolidation_requests.push(consolidation_request);
+            }
+        }
+    }
+
+    let deposit_index = IndexFragment {
+        fragment_id: DEPOSIT_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_deposits.len() as u32,
+        indexes: vec![
+            Index {
+                index_id: INDEX_DEPOSIT_BY_PUBKEY,
+                index: index_deposit_by_pubkey
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+            Index {
+                index_id: INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS,
+                index: index_deposit_by_withdrawal_credentials
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+        ],
+    };
+
+    let voluntary_exit_index = IndexFragment {
+        fragment_id: VOLUNTARY_EXIT_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_voluntary_exits.len() as u32,
+        indexes: vec![Index {
+            index_id: INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX,
+            index: index_voluntary_exit_by_validator_index
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        }],
+    };
+
+    let proposer_slashing_index = IndexFragment {
+        fragment_id: PROPOSER_SLASHING_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_proposer_slashings.len() as u32,
+        indexes: vec![Index {
+            index_id: INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX,
+            index: index_proposer_slashing_by_proposer_index
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        }],
+    };
+
+    let attester_slashing_index = IndexFragment {
+        fragment_id: ATTESTER_SLASHING_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_attester_slashings.len() as u32,
+        indexes: vec![Index {
+            index_id: INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+            index: index_attester_slashing_by_validator_index
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        }],
+    };
+
+    let withdrawal_request_index = IndexFragment {
+        fragment_id: WITHDRAWAL_REQUEST_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_withdrawal_requests.len() as u32,
+        indexes: vec![
+            Index {
+                index_id: INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS,
+                index: index_withdrawal_request_by_source_address
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+            Index {
+                index_id: INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY,
+                index: index_withdrawal_request_by_validator_pubkey
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+        ],
+    };
+
+    let consolidation_request_index = IndexFragment {
+        fragment_id: CONSOLIDATION_REQUEST_FRAGMENT_ID,
+        range_start: 0,
+        range_len: block_consolidation_requests.len() as u32,
+        indexes: vec![
+            Index {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+                index: index_consolidation_request_by_source_address
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+            Index {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_PUBKEY,
+                index: index_consolidation_request_by_source_pubkey
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+            Index {
+                index_id: INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+                index: index_consolidation_request_by_target_pubkey
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            },
+        ],
+    };
+
+    let body_fragments = vec![
+        BodyFragment {
+            fragment_id: DEPOSIT_FRAGMENT_ID,
+            name: DEPOSIT_FRAGMENT_NAME.to_string(),
+            data: block_deposits.iter().map(Message::encode_to_vec).collect(),
+        },
+        BodyFragment {
+            fragment_id: VOLUNTARY_EXIT_FRAGMENT_ID,
+            name: VOLUNTARY_EXIT_FRAGMENT_NAME.to_string(),
+            data: block_voluntary_exits
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        },
+        BodyFragment {
+            fragment_id: PROPOSER_SLASHING_FRAGMENT_ID,
+            name: PROPOSER_SLASHING_FRAGMENT_NAME.to_string(),
+            data: block_proposer_slashings
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        },
+        BodyFragment {
+            fragment_id: ATTESTER_SLASHING_FRAGMENT_ID,
+            name: ATTESTER_SLASHING_FRAGMENT_NAME.to_string(),
+            data: block_attester_slashings
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        },
+        BodyFragment {
+            fragment_id: WITHDRAWAL_REQUEST_FRAGMENT_ID,
+            name: WITHDRAWAL_REQUEST_FRAGMENT_NAME.to_string(),
+            data: block_withdrawal_requests
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        },
+        BodyFragment {
+            fragment_id: CONSOLIDATION_REQUEST_FRAGMENT_ID,
+            name: CONSOLIDATION_REQUEST_FRAGMENT_NAME.to_string(),
+            data: block_consolidation_requests
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        },
+    ];
+
+    let index_fragments = vec![
+        deposit_index,
+        voluntary_exit_index,
+        proposer_slashing_index,
+        attester_slashing_index,
+        withdrawal_request_index,
+        consolidation_request_index,
+    ];
+
+    // Operations don't join to other fragments.
+    let join_fragments = [
+        DEPOSIT_FRAGMENT_ID,
+        VOLUNTARY_EXIT_FRAGMENT_ID,
+        PROPOSER_SLASHING_FRAGMENT_ID,
+        ATTESTER_SLASHING_FRAGMENT_ID,
+        WITHDRAWAL_REQUEST_FRAGMENT_ID,
+        CONSOLIDATION_REQUEST_FRAGMENT_ID,
+    ]
+    .into_iter()
+    .map(|fragment_id| JoinFragment {
+        fragment_id,
+        joins: Vec::default(),
+    })
+    .collect();
+
+    Ok((body_fragments, index_fragments, join_fragments))
+}
+
 pub fn decode_transaction(mut bytes: &[u8]) -> Result<models::TxEnvelope, IngestionError> {
     models::TxEnvelope::network_decode(&mut bytes)
         .change_context(IngestionError::Model)
         .attach_printable("failed to decode EIP 2718 transaction")
 }
+
+#[cfg(test)]
+mod tests {
+    use alloy_consensus::SignableTransaction;
+    use alloy_eips::eip2718::Encodable2718;
+    use alloy_primitives::{uint, Parity};
+    use apibara_dna_common::{
+        fragment::{ArchivedIndexFragment, ArchivedIndexGroupFragment, HEADER_FRAGMENT_ID},
+        index::ScalarValue,
+        query::{Condition, Filter},
+    };
+
+    use crate::{
+        fragment::{
+            ATTESTER_SLASHING_FRAGMENT_ID, BLOB_FRAGMENT_ID, CONSOLIDATION_REQUEST_FRAGMENT_ID,
+            DEPOSIT_FRAGMENT_ID, INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+            INDEX_BLOB_BY_BLOB_HASH, INDEX_BLOB_BY_KZG_COMMITMENT,
+            INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+            INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY, INDEX_DEPOSIT_BY_PUBKEY,
+            INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS, INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+            INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX, INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX,
+            INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS,
+            INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY, PROPOSER_SLASHING_FRAGMENT_ID,
+            VOLUNTARY_EXIT_FRAGMENT_ID, WITHDRAWAL_REQUEST_FRAGMENT_ID,
+        },
+        provider::{models, utils::kzg_commitment_to_versioned_hash},
+    };
+
+    use super::{
+        collect_block_body_and_index, collect_header_index, collect_operations_body_and_index,
+    };
+
+    const COMMITMENTS: [&str; 2] = [
+        "8a2461b2ad767d96d11fe783fc63023fcde21d8dd03064056fa522ebbfae185ec1f82025b627977603b014ec64c5ee19",
+        "9007ff0d9ca54b8fe0b25ae5bdb8fa2ee30249f88c4da33a6a8d8ab09828c1100353a0f6dd0f97dfc493ac942462e2e0",
+    ];
+
+    /// Returns a signed blob transaction posting the blobs with the given hashes.
+    ///
+    /// The signature is any valid point, so the sender is arbitrary.
+    fn blob_transaction(blob_versioned_hashes: Vec<models::B256>) -> models::Bytes {
+        let tx = models::TxEip4844 {
+            to: models::Address::repeat_byte(1),
+            blob_versioned_hashes,
+            ..Default::default()
+        };
+
+        let generator_x =
+            uint!(0x79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798_U256);
+        let signature =
+            models::Signature::new(generator_x, models::U256::from(1), Parity::Parity(false));
+
+        let tx = models::TxEnvelope::from(tx.into_signed(signature));
+        tx.encoded_2718().into()
+    }
+
+    fn pubkey(value: u64) -> models::B384 {
+        models::B384::from_limbs([value, 0, 0, 0, 0, 0])
+    }
+
+    fn blob_sidecar(index: u32, kzg_commitment: models::B384) -> models::BlobSidecar {
+        models::BlobSidecar {
+            index,
+            blob: models::Bytes::default(),
+            kzg_commitment,
+            kzg_proof: models::B384::ZERO,
+            kzg_commitment_inclusion_proof: Vec::default(),
+        }
+    }
+
+    fn filter_rows(
+        indexes: &ArchivedIndexFragment,
+        fragment_id: u8,
+        index_id: u8,
+        key: ScalarValue,
+    ) -> Vec<u32> {
+        let filter = Filter {
+            filter_id: 0,
+            fragment_id,
+            conditions: vec![Condition {
+                index_id,
+                key: key.into(),
+            }],
+            joins: Vec::default(),
+            fields: None,
+        };
+
+        filter.filter(indexes).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_blob_indexes() {
+        let commitments =
+            COMMITMENTS.map(|commitment| models::B384::from_str_radix(commitment, 16).unwrap());
+        let hashes = commitments.map(|commitment| kzg_commitment_to_versioned_hash(&commitment));
+
+        let transactions = [blob_transaction(hashes.to_vec())];
+        let blobs = [
+            blob_sidecar(0, commitments[0]),
+            blob_sidecar(1, commitments[1]),
+        ];
+
+        let (_, index, _) = collect_block_body_and_index(&transactions, &[], &blobs).unwrap();
+
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let index =
+            rkyv::access::<ArchivedIndexGroupFragment, rkyv::rancor::Error>(&bytes).unwrap();
+        let indexes = index
+            .indexes
+            .iter()
+            .find(|index| index.fragment_id == BLOB_FRAGMENT_ID)
+            .unwrap();
+
+        for (row, commitment) in commitments.iter().enumerate() {
+            let key = ScalarValue::B384(commitment.to_be_bytes::<48>());
+            assert_eq!(
+                filter_rows(indexes, BLOB_FRAGMENT_ID, INDEX_BLOB_BY_KZG_COMMITMENT, key),
+                vec![row as u32]
+            );
+        }
+
+        for (row, hash) in hashes.iter().enumerate() {
+            let key = ScalarValue::B256(hash.0);
+            assert_eq!(
+                filter_rows(indexes, BLOB_FRAGMENT_ID, INDEX_BLOB_BY_BLOB_HASH, key),
+                vec![row as u32]
+            );
+        }
+
+        let key = ScalarValue::B256([0; 32]);
+        assert!(filter_rows(indexes, BLOB_FRAGMENT_ID, INDEX_BLOB_BY_BLOB_HASH, key).is_empty());
+    }
+
+    fn execution_payload(block_number: u64) -> models::ExecutionPayload {
+        models::ExecutionPayload {
+            parent_hash: models::B256::ZERO,
+            fee_recipient: models::Address::ZERO,
+            state_root: models::B256::ZERO,
+            receipts_root: models::B256::ZERO,
+            logs_bloom: models::Bytes::default(),
+            prev_randao: models::B256::ZERO,
+            block_number,
+            block_hash: models::B256::ZERO,
+            timestamp: 0,
+            transactions: Vec::default(),
+            withdrawals: Vec::default(),
+        }
+    }
+
+    #[test]
+    fn test_header_index() {
+        let index = collect_header_index(Some(&execution_payload(1_000))).unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let indexes = rkyv::access::<ArchivedIndexFragment, rkyv::rancor::Error>(&bytes).unwrap();
+
+        let key = ScalarValue::Uint64(1_000);
+        assert_eq!(
+            filter_rows(
+                indexes,
+                HEADER_FRAGMENT_ID,
+                INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+                key
+            ),
+            vec![0]
+        );
+
+        let key = ScalarValue::Uint64(1_001);
+        assert!(filter_rows(
+            indexes,
+            HEADER_FRAGMENT_ID,
+            INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+            key
+        )
+        .is_empty());
+
+        // Blocks before the merge have no execution payload.
+        let index = collect_header_index(None).unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let indexes = rkyv::access::<ArchivedIndexFragment, rkyv::rancor::Error>(&bytes).unwrap();
+
+        let key = ScalarValue::Uint64(0);
+        assert!(filter_rows(
+            indexes,
+            HEADER_FRAGMENT_ID,
+            INDEX_HEADER_BY_EXECUTION_BLOCK_NUMBER,
+            key
+        )
+        .is_empty());
+    }
+
+    fn signed_header(
+        proposer_index: u32,
+        body_root: models::B256,
+    ) -> models::SignedBeaconBlockHeader {
+        models::SignedBeaconBlockHeader {
+            message: models::BeaconBlockHeader {
+                slot: 100,
+                proposer_index,
+                parent_root: models::B256::ZERO,
+                state_root: models::B256::ZERO,
+                body_root,
+            },
+            signature: models::Bytes::default(),
+        }
+    }
+
+    fn operations_body() -> models::BeaconBlockBody {
+        models::BeaconBlockBody {
+            randao_reveal: models::Bytes::default(),
+            eth1_data: models::Eth1Data {
+                deposit_count: 0,
+                deposit_root: models::B256::ZERO,
+                block_hash: models::B256::ZERO,
+            },
+            graffiti: models::B256::ZERO,
+            execution_payload: None,
+            blob_kzg_commitments: Vec::default(),
+            proposer_slashings: vec![models::ProposerSlashing {
+                signed_header_1: signed_header(7, models::B256::ZERO),
+                signed_header_2: signed_header(7, models::B256::repeat_byte(1)),
+            }],
+            attester_slashings: vec![models::AttesterSlashing {
+                attestation_1: models::IndexedAttestation {
+                    attesting_indices: vec![1, 2, 3, 5],
+                },
+                attestation_2: models::IndexedAttestation {
+                    attesting_indices: vec![2, 4, 5],
+                },
+            }],
+            deposits: vec![
+                models::Deposit {
+                    data: models::DepositData {
+                        pubkey: pubkey(10),
+                        withdrawal_credentials: models::B256::repeat_byte(10),
+                        amount: 32_000_000_000,
+                        signature: models::Bytes::default(),
+                    },
+                },
+                models::Deposit {
+                    data: models::DepositData {
+                        pubkey: pubkey(11),
+                        withdrawal_credentials: models::B256::repeat_byte(10),
+                        amount: 32_000_000_000,
+                        signature: models::Bytes::default(),
+                    },
+                },
+            ],
+            voluntary_exits: vec![models::SignedVoluntaryExit {
+                message: models::VoluntaryExit {
+                    epoch: 10,
+                    validator_index: 42,
+                },
+                signature: models::Bytes::default(),
+            }],
+            execution_requests: Some(models::ExecutionRequests {
+                withdrawals: vec![models::WithdrawalRequest {
+                    source_address: models::Address::repeat_byte(20),
+                    validator_pubkey: pubkey(20),
+                    amount: 0,
+                }],
+                consolidations: vec![models::ConsolidationRequest {
+                    source_address: models::Address::repeat_byte(30),
+                    source_pubkey: pubkey(30),
+                    target_pubkey: pubkey(31),
+                }],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_operations_indexes() {
+        let body = operations_body();
+        let (fragments, indexes, _) = collect_operations_body_and_index(Some(&body)).unwrap();
+
+        let rows = |fragment_id: u8| {
+            fragments
+                .iter()
+                .find(|fragment| fragment.fragment_id == fragment_id)
+                .unwrap()
+                .data
+                .len()
+        };
+        assert_eq!(rows(DEPOSIT_FRAGMENT_ID), 2);
+        assert_eq!(rows(VOLUNTARY_EXIT_FRAGMENT_ID), 1);
+        assert_eq!(rows(PROPOSER_SLASHING_FRAGMENT_ID), 1);
+        assert_eq!(rows(ATTESTER_SLASHING_FRAGMENT_ID), 1);
+        assert_eq!(rows(WITHDRAWAL_REQUEST_FRAGMENT_ID), 1);
+        assert_eq!(rows(CONSOLIDATION_REQUEST_FRAGMENT_ID), 1);
+
+        let bytes = indexes
+            .iter()
+            .map(|index| rkyv::to_bytes::<rkyv::rancor::Error>(index).unwrap())
+            .collect::<Vec<_>>();
+        let rows_matching = |fragment_id: u8, index_id: u8, key: ScalarValue| {
+            let indexes = bytes
+                .iter()
+                .map(|bytes| {
+                    rkyv::access::<ArchivedIndexFragment, rkyv::rancor::Error>(bytes).unwrap()
+                })
+                .find(|index| index.fragment_id == fragment_id)
+                .unwrap();
+            filter_rows(indexes, fragment_id, index_id, key)
+        };
+
+        let key = ScalarValue::B384(pubkey(11).to_be_bytes::<48>());
+        assert_eq!(
+            rows_matching(DEPOSIT_FRAGMENT_ID, INDEX_DEPOSIT_BY_PUBKEY, key),
+            vec![1]
+        );
+        let key = ScalarValue::B256(models::B256::repeat_byte(10).0);
+        assert_eq!(
+            rows_matching(
+                DEPOSIT_FRAGMENT_ID,
+                INDEX_DEPOSIT_BY_WITHDRAWAL_CREDENTIALS,
+                key
+            ),
+            vec![0, 1]
+        );
+
+        let key = ScalarValue::Uint32(42);
+        assert_eq!(
+            rows_matching(
+                VOLUNTARY_EXIT_FRAGMENT_ID,
+                INDEX_VOLUNTARY_EXIT_BY_VALIDATOR_INDEX,
+                key
+            ),
+            vec![0]
+        );
+
+        let key = ScalarValue::Uint32(7);
+        assert_eq!(
+            rows_matching(
+                PROPOSER_SLASHING_FRAGMENT_ID,
+                INDEX_PROPOSER_SLASHING_BY_PROPOSER_INDEX,
+                key
+            ),
+            vec![0]
+        );
+
+        // Only validators that attested both attestations are slashed.
+        for (validator_index, slashed) in [(1, false), (2, true), (4, false), (5, true)] {
+            let key = ScalarValue::Uint32(validator_index);
+            let rows = rows_matching(
+                ATTESTER_SLASHING_FRAGMENT_ID,
+                INDEX_ATTESTER_SLASHING_BY_VALIDATOR_INDEX,
+                key,
+            );
+            assert_eq!(!rows.is_empty(), slashed, "validator {validator_index}");
+        }
+
+        let key = ScalarValue::B160(models::Address::repeat_byte(20).into_array());
+        assert_eq!(
+            rows_matching(
+                WITHDRAWAL_REQUEST_FRAGMENT_ID,
+                INDEX_WITHDRAWAL_REQUEST_BY_SOURCE_ADDRESS,
+                key
+            ),
+            vec![0]
+        );
+        let key = ScalarValue::B384(pubkey(20).to_be_bytes::<48>());
+        assert_eq!(
+            rows_matching(
+                WITHDRAWAL_REQUEST_FRAGMENT_ID,
+                INDEX_WITHDRAWAL_REQUEST_BY_VALIDATOR_PUBKEY,
+                key
+            ),
+            vec![0]
+        );
+
+        let key = ScalarValue::B160(models::Address::repeat_byte(30).into_array());
+        assert_eq!(
+            rows_matching(
+                CONSOLIDATION_REQUEST_FRAGMENT_ID,
+                INDEX_CONSOLIDATION_REQUEST_BY_SOURCE_ADDRESS,
+                key
+            ),
+            vec![0]
+        );
+        let key = ScalarValue::B384(pubkey(31).to_be_bytes::<48>());
+        assert_eq!(
+            rows_matching(
+                CONSOLIDATION_REQUEST_FRAGMENT_ID,
+                INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+                key
+            ),
+            vec![0]
+        );
+        let key = ScalarValue::B384(pubkey(30).to_be_bytes::<48>());
+        assert!(rows_matching(
+            CONSOLIDATION_REQUEST_FRAGMENT_ID,
+            INDEX_CONSOLIDATION_REQUEST_BY_TARGET_PUBKEY,
+            key
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_operations_without_body() {
+        // Missed slots still produce every fragment, empty.
+        let (fragments, indexes, _) = collect_operations_body_and_index(None).unwrap();
+        assert_eq!(fragments.len(), 6);
+        assert!(fragments.iter().all(|fragment| fragment.data.is_empty()));
+        assert!(indexes.iter().all(|index| index.range_len == 0));
+    }
+
+    #[test]
+    fn test_attester_slashing_slashed_indices() {
+        let slashing = models::AttesterSlashing {
+            attestation_1: models::IndexedAttestation {
+                attesting_indices: vec![9, 1, 5, 3],
+            },
+            attestation_2: models::IndexedAttestation {
+                attesting_indices: vec![3, 4, 9],
+            },
+        };
+
+        assert_eq!(slashing.slashed_indices(), vec![9, 3]);
+    }
+}
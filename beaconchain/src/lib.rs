@@ -1,9 +1,5 @@
-use apibara_dna_common::{fragment::FragmentInfo, ChainSupport};
+use apibara_dna_common::{fragment::FragmentInfo, ingestion::RpcRateLimiter, ChainSupport};
 use filter::BeaconChainFilterFactory;
-use fragment::{
-    BLOB_FRAGMENT_ID, BLOB_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME,
-    VALIDATOR_FRAGMENT_ID, VALIDATOR_FRAGMENT_NAME,
-};
 use ingestion::BeaconChainBlockIngestion;
 use provider::http::BeaconApiProvider;
 
@@ -36,27 +32,15 @@ impl ChainSupport for BeaconChainChainSupport {
     type BlockFilterFactory = BeaconChainFilterFactory;
 
     fn fragment_info(&self) -> Vec<FragmentInfo> {
-        vec![
-            FragmentInfo {
-                fragment_id: TRANSACTION_FRAGMENT_ID,
-                name: TRANSACTION_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: VALIDATOR_FRAGMENT_ID,
-                name: VALIDATOR_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: BLOB_FRAGMENT_ID,
-                name: BLOB_FRAGMENT_NAME.to_string(),
-            },
-        ]
+        fragment::fragment_info()
     }
 
     fn block_filter_factory(&self) -> Self::BlockFilterFactory {
         BeaconChainFilterFactory
     }
 
-    fn block_ingestion(&self) -> Self::BlockIngestion {
-        BeaconChainBlockIngestion::new(self.provider.clone(), self.options.clone())
+    fn block_ingestion(&self, rate_limiter: RpcRateLimiter) -> Self::BlockIngestion {
+        let provider = self.provider.clone().with_rate_limiter(rate_limiter);
+        BeaconChainBlockIngestion::new(provider, self.options.clone())
     }
 }
@@ -63,6 +63,7 @@ impl ModelExt for models::ExecutionPayload {
             prev_randao: self.prev_randao.to_proto().into(),
             block_number: self.block_number,
             timestamp: timestamp.into(),
+            block_hash: self.block_hash.to_proto().into(),
         }
     }
 }
@@ -282,6 +283,105 @@ impl ModelExt for models::BlobSidecar {
     }
 }
 
+impl ModelExt for models::Deposit {
+    type Proto = beaconchain::Deposit;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::Deposit {
+            filter_ids: Vec::default(),
+            deposit_index: u32::MAX,
+            pubkey: self.data.pubkey.to_proto().into(),
+            withdrawal_credentials: self.data.withdrawal_credentials.to_proto().into(),
+            amount: self.data.amount,
+            signature: self.data.signature.to_vec(),
+        }
+    }
+}
+
+impl ModelExt for models::SignedVoluntaryExit {
+    type Proto = beaconchain::VoluntaryExit;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::VoluntaryExit {
+            filter_ids: Vec::default(),
+            voluntary_exit_index: u32::MAX,
+            epoch: self.message.epoch,
+            validator_index: self.message.validator_index,
+            signature: self.signature.to_vec(),
+        }
+    }
+}
+
+impl ModelExt for models::ProposerSlashing {
+    type Proto = beaconchain::ProposerSlashing;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::ProposerSlashing {
+            filter_ids: Vec::default(),
+            proposer_slashing_index: u32::MAX,
+            signed_header_1: self.signed_header_1.to_proto().into(),
+            signed_header_2: self.signed_header_2.to_proto().into(),
+        }
+    }
+}
+
+impl ModelExt for models::SignedBeaconBlockHeader {
+    type Proto = beaconchain::SignedBeaconBlockHeader;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::SignedBeaconBlockHeader {
+            slot: self.message.slot,
+            proposer_index: self.message.proposer_index,
+            parent_root: self.message.parent_root.to_proto().into(),
+            state_root: self.message.state_root.to_proto().into(),
+            body_root: self.message.body_root.to_proto().into(),
+            signature: self.signature.to_vec(),
+        }
+    }
+}
+
+impl ModelExt for models::AttesterSlashing {
+    type Proto = beaconchain::AttesterSlashing;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::AttesterSlashing {
+            filter_ids: Vec::default(),
+            attester_slashing_index: u32::MAX,
+            attestation_1_indices: self.attestation_1.attesting_indices.clone(),
+            attestation_2_indices: self.attestation_2.attesting_indices.clone(),
+            slashed_indices: self.slashed_indices(),
+        }
+    }
+}
+
+impl ModelExt for models::WithdrawalRequest {
+    type Proto = beaconchain::WithdrawalRequest;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::WithdrawalRequest {
+            filter_ids: Vec::default(),
+            withdrawal_request_index: u32::MAX,
+            source_address: self.source_address.to_proto().into(),
+            validator_pubkey: self.validator_pubkey.to_proto().into(),
+            amount: self.amount,
+        }
+    }
+}
+
+impl ModelExt for models::ConsolidationRequest {
+    type Proto = beaconchain::ConsolidationRequest;
+
+    fn to_proto(&self) -> Self::Proto {
+        beaconchain::ConsolidationRequest {
+            filter_ids: Vec::default(),
+            consolidation_request_index: u32::MAX,
+            source_address: self.source_address.to_proto().into(),
+            source_pubkey: self.source_pubkey.to_proto().into(),
+            target_pubkey: self.target_pubkey.to_proto().into(),
+        }
+    }
+}
+
 impl ModelExt for models::ValidatorStatus {
     type Proto = beaconchain::ValidatorStatus;
 
@@ -1,12 +1,22 @@
 use std::{fmt::Debug, time::Duration};
 
-use error_stack::{Report, Result, ResultExt};
+use apibara_dna_common::{
+    ingestion::RpcRateLimiter,
+    rpc_pool::{Failover, RpcEndpoint, RpcPool, RpcPoolOptions},
+};
+use error_stack::{report, Report, Result, ResultExt};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
-    Client,
+    Client, Response,
 };
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
-use crate::provider::models;
+use crate::provider::{models, utils::take_sse_events};
 
 #[derive(Debug)]
 pub enum BeaconApiError {
@@ -17,6 +27,9 @@ pub enum BeaconApiError {
     Timeout,
     Unauthorized,
     ServerError,
+    Configuration,
+    /// The beacon nodes returned different blocks.
+    Inconsistent,
 }
 
 /// Block identifier.
@@ -35,7 +48,7 @@ pub enum BlockId {
 #[derive(Clone)]
 pub struct BeaconApiProvider {
     client: Client,
-    url: String,
+    pool: RpcPool<String>,
     options: BeaconApiProviderOptions,
 }
 
@@ -51,22 +64,84 @@ pub struct BeaconApiProviderOptions {
     pub validators_timeout: Duration,
     /// Headers to send with the requests.
     pub headers: HeaderMap<HeaderValue>,
+    /// Failover and load balancing options.
+    pub pool: RpcPoolOptions,
+    /// Send requests to all nodes and check that they agree on the block roots.
+    ///
+    /// Only used with more than one node.
+    pub consistency_check: bool,
+    /// Ignore the head of nodes that are more than this many slots behind the others.
+    pub max_head_lag: u64,
 }
 
 impl BeaconApiProvider {
-    pub fn new(url: impl Into<String>, options: BeaconApiProviderOptions) -> Self {
-        let url = url.into().trim_end_matches('/').to_string();
-        Self {
+    pub fn new(
+        urls: Vec<RpcEndpoint<impl Into<String>>>,
+        options: BeaconApiProviderOptions,
+    ) -> Result<Self, BeaconApiError> {
+        let endpoints = urls
+            .into_iter()
+            .map(|endpoint| RpcEndpoint {
+                name: endpoint.name,
+                client: endpoint.client.into().trim_end_matches('/').to_string(),
+                weight: endpoint.weight,
+            })
+            .collect();
+
+        let pool = RpcPool::new(endpoints, options.pool.clone())
+            .change_context(BeaconApiError::Configuration)?;
+
+        Ok(Self {
             client: Client::new(),
-            url,
+            pool,
             options,
+        })
+    }
+
+    /// Limit the requests sent to the beacon nodes.
+    pub fn with_rate_limiter(self, rate_limiter: RpcRateLimiter) -> Self {
+        Self {
+            pool: self.pool.with_rate_limiter(rate_limiter),
+            ..self
         }
     }
 
+    /// Returns a provider that sends all requests to the same beacon node.
+    ///
+    /// Use it to fetch the different parts of a block from the same node.
+    pub fn pinned(&self) -> Self {
+        Self {
+            pool: self.pool.pinned(),
+            ..self.clone()
+        }
+    }
+
+    /// Check the beacon nodes' health in the background, until cancelled.
+    pub async fn health_check_loop(self, ct: CancellationToken) {
+        let path = HeaderRequest::new(BlockId::Head).path();
+        self.pool
+            .clone()
+            .health_check_loop(
+                |base_url| {
+                    self.send_request_to::<models::HeaderResponse>(
+                        base_url,
+                        &path,
+                        self.options.timeout,
+                    )
+                },
+                ct,
+            )
+            .await
+    }
+
     pub async fn get_header(
         &self,
         block_id: BlockId,
     ) -> Result<models::HeaderResponse, BeaconApiError> {
+        if self.checks_consistency() {
+            return self.get_consistent_header(block_id).await;
+        }
+
         let request = HeaderRequest::new(block_id);
         self.send_request(request, self.options.timeout).await
     }
@@ -102,15 +177,141 @@ impl BeaconApiProvider {
             .await
     }
 
+    /// Returns the chain's genesis, which is available even on checkpoint-synced nodes.
+    pub async fn get_genesis(&self) -> Result<models::GenesisResponse, BeaconApiError> {
+        self.send_request(GenesisRequest, self.options.timeout)
+            .await
+    }
+
     pub async fn get_block_root(
         &self,
         block_id: BlockId,
     ) -> Result<models::BlockRootResponse, BeaconApiError> {
         let request = BlockRootRequest::new(block_id);
+
+        if self.checks_consistency() {
+            let responses = self
+                .send_request_to_all(request, self.options.timeout)
+                .await;
+            return agreed_response(responses, |response| response.data.root);
+        }
+
         self.send_request(request, self.options.timeout).await
     }
 
-    /// Send a request to the beacon node.
+    /// Subscribe to the node's head events.
+    ///
+    /// The stream yields an item each time the node notifies a new head.
+    pub async fn subscribe_heads(
+        &self,
+    ) -> Result<BoxStream<'static, Result<(), BeaconApiError>>, BeaconApiError> {
+        let response = self
+            .pool
+            .call_with_failover(
+                "subscribe_heads",
+                |base_url| self.open_event_stream(base_url, "/eth/v1/events?topics=head"),
+                failover,
+            )
+            .await?;
+
+        let stream = stream::unfold(
+            (response, String::new()),
+            |(mut response, mut buffer)| async move {
+                loop {
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => {
+                            buffer.push_str(&String::from_utf8_lossy(&chunk));
+                            if take_sse_events(&mut buffer) {
+                                return Some((Ok(()), (response, buffer)));
+                            }
+                        }
+                        Ok(None) => return None,
+                        Err(err) => {
+                            let err = Err(err).change_context(BeaconApiError::Request);
+                            return Some((err, (response, buffer)));
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(stream.boxed())
+    }
+
+    fn checks_consistency(&self) -> bool {
+        self.options.consistency_check && self.pool.len() > 1
+    }
+
+    /// Get a header, checking that all nodes agree on it.
+    ///
+    /// Nodes report different heads while they sync, so the head (or finalized)
+    /// header is the lowest one among the nodes that are not lagging behind. This way
+    /// a node that is a few slots behind doesn't make the head jump back and forth.
+    async fn get_consistent_header(
+        &self,
+        block_id: BlockId,
+    ) -> Result<models::HeaderResponse, BeaconApiError> {
+        let request = HeaderRequest::new(block_id.clone());
+        let responses = self
+            .send_request_to_all(request, self.options.timeout)
+            .await;
+
+        if !matches!(block_id, BlockId::Head | BlockId::Finalized) {
+            return agreed_response(responses, |response| response.data.root);
+        }
+
+        let mut headers = Vec::with_capacity(responses.len());
+        let mut last_error = None;
+        for (endpoint, response) in responses {
+            match response {
+                Ok(header) => headers.push((endpoint, header)),
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        let Some(highest_slot) = headers.iter().map(|(_, header)| header_slot(header)).max() else {
+            return Err(last_error.expect("beacon provider has at least one node"));
+        };
+
+        let mut lowest = None;
+        for (endpoint, header) in headers {
+            let slot = header_slot(&header);
+            if slot + self.options.max_head_lag < highest_slot {
+                warn!(
+                    endpoint = %endpoint,
+                    %block_id,
+                    slot,
+                    highest_slot,
+                    "beacon node is lagging behind"
+                );
+                continue;
+            }
+
+            if lowest
+                .as_ref()
+                .map(|lowest| slot < header_slot(lowest))
+                .unwrap_or(true)
+            {
+                lowest = Some(header);
+            }
+        }
+
+        let header = lowest.expect("the highest header is never lagging");
+
+        // Check that the other nodes have the same block at that slot.
+        let slot = header_slot(&header);
+        let block_root = self.get_block_root(BlockId::Slot(slot)).await?;
+        if block_root.data.root != header.data.root {
+            return Err(report!(BeaconApiError::Inconsistent))
+                .attach_printable("beacon nodes disagree on the block root")
+                .attach_printable_lazy(|| format!("block id: {block_id}"))
+                .attach_printable_lazy(|| format!("slot: {slot}"));
+        }
+
+        Ok(header)
+    }
+
+    /// Send a request to the beacon node, failing over to the next node on error.
     ///
     /// TODO: this function can be turned into a `Transport` trait if we ever need it.
     #[tracing::instrument(level = "debug", skip(self))]
@@ -122,7 +323,68 @@ impl BeaconApiProvider {
     where
         Req: BeaconApiRequest + Debug,
     {
-        let url = format!("{}{}", self.url, request.path());
+        let path = request.path();
+        self.pool
+            .call_with_failover(
+                request.method(),
+                |base_url| self.send_request_to::<Req::Response>(base_url, &path, timeout),
+                failover,
+            )
+            .await
+    }
+
+    /// Send a request to all beacon nodes.
+    async fn send_request_to_all<Req>(
+        &self,
+        request: Req,
+        timeout: Duration,
+    ) -> Vec<(String, Result<Req::Response, BeaconApiError>)>
+    where
+        Req: BeaconApiRequest + Debug,
+    {
+        let path = request.path();
+        self.pool
+            .call_all(request.method(), |base_url| {
+                self.send_request_to::<Req::Response>(base_url, &path, timeout)
+            })
+            .await
+    }
+
+    /// Open a server-sent events stream. The request has no timeout since the stream is long lived.
+    async fn open_event_stream(
+        &self,
+        base_url: String,
+        path: &str,
+    ) -> Result<Response, BeaconApiError> {
+        let url = format!("{}{}", base_url, path);
+        let response = self
+            .client
+            .get(&url)
+            .header("Accept", "text/event-stream")
+            .headers(self.options.headers.clone())
+            .send()
+            .await
+            .change_context(BeaconApiError::Request)?;
+
+        match response.status().as_u16() {
+            200 => Ok(response),
+            400 => Err(BeaconApiError::BadRequest.into()),
+            401 => Err(BeaconApiError::Unauthorized.into()),
+            404 => Err(BeaconApiError::NotFound.into()),
+            _ => Err(BeaconApiError::ServerError.into()),
+        }
+    }
+
+    async fn send_request_to<Res>(
+        &self,
+        base_url: String,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<Res, BeaconApiError>
+    where
+        Res: serde::de::DeserializeOwned,
+    {
+        let url = format!("{}{}", base_url, path);
         let response = match self
             .client
             .get(&url)
@@ -169,9 +431,79 @@ impl BeaconApiProvider {
     }
 }
 
+/// Returns the response of the nodes if they all agree on its `key`.
+///
+/// Nodes that don't have the block are ignored, since they may be behind the others.
+/// Returns an error if no node returned the block.
+fn agreed_response<T, K>(
+    responses: Vec<(String, Result<T, BeaconApiError>)>,
+    key: impl Fn(&T) -> K,
+) -> Result<T, BeaconApiError>
+where
+    K: PartialEq + Debug,
+{
+    let mut agreed: Option<(String, T)> = None;
+    let mut not_found = None;
+    let mut last_error = None;
+
+    for (endpoint, response) in responses {
+        match response {
+            Ok(response) => {
+                if let Some((agreed_endpoint, agreed_response)) = agreed.as_ref() {
+                    let expected = key(agreed_response);
+                    let actual = key(&response);
+                    if expected != actual {
+                        return Err(report!(BeaconApiError::Inconsistent))
+                            .attach_printable("beacon nodes returned different blocks")
+                            .attach_printable_lazy(|| format!("{agreed_endpoint}: {expected:?}"))
+                            .attach_printable_lazy(|| format!("{endpoint}: {actual:?}"));
+                    }
+                } else {
+                    agreed = Some((endpoint, response));
+                }
+            }
+            Err(err) if BeaconApiErrorExt::is_not_found(&err) => not_found = Some(err),
+            Err(err) => {
+                warn!(endpoint = %endpoint, error = ?err, "beacon node request failed");
+                last_error = Some(err);
+            }
+        }
+    }
+
+    if let Some((_, response)) = agreed {
+        return Ok(response);
+    }
+
+    // Only report a missing block if no node failed, since the failed nodes may have it.
+    Err(last_error
+        .or(not_found)
+        .expect("beacon provider has at least one node"))
+}
+
+fn header_slot(header: &models::HeaderResponse) -> u64 {
+    header.data.header.message.slot
+}
+
+/// Mark nodes unhealthy only on errors caused by the node, not by the request.
+///
+/// Nodes that don't have the block may be lagging behind, so the other nodes are
+/// tried before reporting the block as missing.
+fn failover(err: &Report<BeaconApiError>) -> Failover {
+    if BeaconApiErrorExt::is_not_found(err) {
+        Failover::TryNext
+    } else if err.is_bad_request() {
+        Failover::Return
+    } else {
+        Failover::MarkUnhealthy
+    }
+}
+
 pub trait BeaconApiRequest {
     type Response: serde::de::DeserializeOwned;
 
+    /// The method name used in metrics.
+    fn method(&self) -> &'static str;
+
     fn path(&self) -> String;
 }
 
@@ -200,6 +532,9 @@ pub struct BlockRootRequest {
     block_id: BlockId,
 }
 
+#[derive(Debug)]
+pub struct GenesisRequest;
+
 impl std::fmt::Display for BlockId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -246,6 +581,8 @@ impl std::fmt::Display for BeaconApiError {
             BeaconApiError::Unauthorized => write!(f, "unauthorized"),
             BeaconApiError::BadRequest => write!(f, "bad request"),
             BeaconApiError::ServerError => write!(f, "server error"),
+            BeaconApiError::Configuration => write!(f, "configuration error"),
+            BeaconApiError::Inconsistent => write!(f, "beacon nodes are inconsistent"),
         }
     }
 }
@@ -261,6 +598,10 @@ impl HeaderRequest {
 impl BeaconApiRequest for HeaderRequest {
     type Response = models::HeaderResponse;
 
+    fn method(&self) -> &'static str {
+        "get_header"
+    }
+
     fn path(&self) -> String {
         format!("/eth/v1/beacon/headers/{}", self.block_id)
     }
@@ -275,6 +616,10 @@ impl BlockRequest {
 impl BeaconApiRequest for BlockRequest {
     type Response = models::BeaconBlockResponse;
 
+    fn method(&self) -> &'static str {
+        "get_block"
+    }
+
     fn path(&self) -> String {
         format!("/eth/v2/beacon/blocks/{}", self.block_id)
     }
@@ -289,6 +634,10 @@ impl BlobSidecarRequest {
 impl BeaconApiRequest for BlobSidecarRequest {
     type Response = models::BlobSidecarResponse;
 
+    fn method(&self) -> &'static str {
+        "get_blob_sidecar"
+    }
+
     fn path(&self) -> String {
         format!("/eth/v1/beacon/blob_sidecars/{}", self.block_id)
     }
@@ -303,6 +652,10 @@ impl ValidatorsRequest {
 impl BeaconApiRequest for ValidatorsRequest {
     type Response = models::ValidatorsResponse;
 
+    fn method(&self) -> &'static str {
+        "get_validators"
+    }
+
     fn path(&self) -> String {
         format!("/eth/v1/beacon/states/{}/validators", self.block_id)
     }
@@ -317,17 +670,36 @@ impl BlockRootRequest {
 impl BeaconApiRequest for BlockRootRequest {
     type Response = models::BlockRootResponse;
 
+    fn method(&self) -> &'static str {
+        "get_block_root"
+    }
+
     fn path(&self) -> String {
         format!("/eth/v1/beacon/blocks/{}/root", self.block_id)
     }
 }
 
+impl BeaconApiRequest for GenesisRequest {
+    type Response = models::GenesisResponse;
+
+    fn method(&self) -> &'static str {
+        "get_genesis"
+    }
+
+    fn path(&self) -> String {
+        "/eth/v1/beacon/genesis".to_string()
+    }
+}
+
 impl Default for BeaconApiProviderOptions {
     fn default() -> Self {
         Self {
             timeout: Duration::from_secs(5),
             validators_timeout: Duration::from_secs(60),
             headers: HeaderMap::default(),
+            pool: RpcPoolOptions::default(),
+            consistency_check: false,
+            max_head_lag: 4,
         }
     }
 }
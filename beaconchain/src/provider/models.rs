@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use apibara_dna_common::{Cursor, GetCursor, Hash};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DefaultOnNull, DisplayFromStr};
@@ -21,6 +23,19 @@ pub struct BlockRoot {
     pub root: B256,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisResponse {
+    pub data: Genesis,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    #[serde_as(as = "DisplayFromStr")]
+    pub genesis_time: u64,
+    pub genesis_validators_root: B256,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BeaconBlockResponse {
     pub finalized: bool,
@@ -55,6 +70,108 @@ pub struct BeaconBlockBody {
     pub execution_payload: Option<ExecutionPayload>,
     #[serde(default)]
     pub blob_kzg_commitments: Vec<B384>,
+    #[serde(default)]
+    pub proposer_slashings: Vec<ProposerSlashing>,
+    #[serde(default)]
+    pub attester_slashings: Vec<AttesterSlashing>,
+    #[serde(default)]
+    pub deposits: Vec<Deposit>,
+    #[serde(default)]
+    pub voluntary_exits: Vec<SignedVoluntaryExit>,
+    /// Execution layer requests, added in Electra.
+    #[serde(default)]
+    pub execution_requests: Option<ExecutionRequests>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposerSlashing {
+    pub signed_header_1: SignedBeaconBlockHeader,
+    pub signed_header_2: SignedBeaconBlockHeader,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedBeaconBlockHeader {
+    pub message: BeaconBlockHeader,
+    pub signature: Bytes,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BeaconBlockHeader {
+    #[serde_as(as = "DisplayFromStr")]
+    pub slot: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub proposer_index: u32,
+    pub parent_root: B256,
+    pub state_root: B256,
+    pub body_root: B256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttesterSlashing {
+    pub attestation_1: IndexedAttestation,
+    pub attestation_2: IndexedAttestation,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedAttestation {
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub attesting_indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub data: DepositData,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositData {
+    pub pubkey: B384,
+    pub withdrawal_credentials: B256,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: u64,
+    pub signature: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedVoluntaryExit {
+    pub message: VoluntaryExit,
+    pub signature: Bytes,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoluntaryExit {
+    #[serde_as(as = "DisplayFromStr")]
+    pub epoch: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub validator_index: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ExecutionRequests {
+    #[serde(default)]
+    pub withdrawals: Vec<WithdrawalRequest>,
+    #[serde(default)]
+    pub consolidations: Vec<ConsolidationRequest>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WithdrawalRequest {
+    pub source_address: Address,
+    pub validator_pubkey: B384,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsolidationRequest {
+    pub source_address: Address,
+    pub source_pubkey: B384,
+    pub target_pubkey: B384,
 }
 
 #[serde_as]
@@ -77,6 +194,7 @@ pub struct ExecutionPayload {
     pub prev_randao: B256,
     #[serde_as(as = "DisplayFromStr")]
     pub block_number: u64,
+    pub block_hash: B256,
     #[serde_as(as = "DisplayFromStr")]
     pub timestamp: u64,
     #[serde_as(deserialize_as = "DefaultOnNull")]
@@ -169,6 +287,24 @@ impl BlobSidecar {
     }
 }
 
+impl AttesterSlashing {
+    /// Returns the validators that attested both conflicting attestations, in order.
+    pub fn slashed_indices(&self) -> Vec<u32> {
+        let attestation_2 = self
+            .attestation_2
+            .attesting_indices
+            .iter()
+            .collect::<HashSet<_>>();
+
+        self.attestation_1
+            .attesting_indices
+            .iter()
+            .filter(|index| attestation_2.contains(index))
+            .copied()
+            .collect()
+    }
+}
+
 impl GetCursor for BeaconBlock {
     fn cursor(&self) -> Option<Cursor> {
         let hash = Hash(self.state_root.0.to_vec());
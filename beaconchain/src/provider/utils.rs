@@ -4,6 +4,25 @@ pub fn kzg_commitment_to_versioned_hash(commitment: &B384) -> B256 {
     alloy_eips::eip4844::kzg_to_versioned_hash(&commitment.to_be_bytes::<48>())
 }
 
+/// Removes the complete server-sent events from `buffer`.
+///
+/// Returns `true` if any of them carried data. Comments (used as keep-alives) and
+/// incomplete events are not counted, the latter stay in the buffer.
+pub fn take_sse_events(buffer: &mut String) -> bool {
+    if buffer.contains('\r') {
+        *buffer = buffer.replace("\r\n", "\n");
+    }
+
+    let Some(end) = buffer.rfind("\n\n") else {
+        return false;
+    };
+
+    let has_data = buffer[..end].lines().any(|line| line.starts_with("data:"));
+    buffer.drain(..end + 2);
+
+    has_data
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -25,4 +44,21 @@ mod tests {
             assert_eq!(hash, expected);
         }
     }
+
+    #[test]
+    pub fn test_take_sse_events() {
+        let mut buffer = String::from(": keep-alive\n\n");
+        assert!(!super::take_sse_events(&mut buffer));
+        assert!(buffer.is_empty());
+
+        buffer.push_str("event: head\ndata: {\"slot\":\"10\"}\n\nevent: head\ndata: {\"sl");
+        assert!(super::take_sse_events(&mut buffer));
+        assert_eq!(buffer, "event: head\ndata: {\"sl");
+
+        assert!(!super::take_sse_events(&mut buffer));
+
+        buffer.push_str("ot\":\"11\"}\r\n\r\n");
+        assert!(super::take_sse_events(&mut buffer));
+        assert!(buffer.is_empty());
+    }
 }
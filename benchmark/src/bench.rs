@@ -0,0 +1,241 @@
+//! Measure the stream throughput for a filter over a block range.
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use apibara_dna_protocol::dna::stream::{
+    dna_stream_client::DnaStreamClient, stream_data_response::Message as ProtoMessage, Cursor,
+    StreamDataRequest,
+};
+use byte_unit::Byte;
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use prost::Message;
+use tokio_util::sync::CancellationToken;
+use tonic::IntoRequest;
+use tracing::{info, warn};
+
+use crate::{
+    authorize_request, ensure_data_checksum, tail::read_filter_file, BenchmarkError, Chain,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct BenchArgs {
+    /// The chain streamed by the server.
+    #[clap(long, value_enum)]
+    pub chain: Chain,
+    /// Path to the JSON file with the filter.
+    #[clap(long)]
+    pub filter: PathBuf,
+    /// Stream URL.
+    #[clap(long, default_value = "http://localhost:7007")]
+    pub stream_url: String,
+    /// Bearer token used for authentication.
+    #[clap(long)]
+    pub bearer_token: Option<String>,
+    /// Start streaming from this block.
+    #[clap(long)]
+    pub starting_block: u64,
+    /// Stop streaming at this block.
+    #[clap(long)]
+    pub ending_block: u64,
+}
+
+#[derive(Debug, Default)]
+struct BenchStats {
+    /// Blocks scanned by the server, from the stream's cursors.
+    blocks_scanned: u64,
+    /// Data messages received.
+    messages: u64,
+    /// Data messages with at least one non-empty block.
+    blocks_with_data: u64,
+    /// Size of the data received.
+    bytes: u64,
+    heartbeats: u64,
+    invalidates: u64,
+    /// Time between data messages.
+    latencies: Vec<Duration>,
+    time_to_first_message: Option<Duration>,
+}
+
+/// Stream the block range and print the throughput and latency of the stream.
+pub async fn run_bench(args: BenchArgs, ct: CancellationToken) -> Result<(), BenchmarkError> {
+    if args.ending_block <= args.starting_block {
+        return Err(BenchmarkError)
+            .attach_printable("ending block must be greater than starting block");
+    }
+
+    let filter = read_filter_file(args.chain, &args.filter)?;
+
+    let mut client = DnaStreamClient::connect(args.stream_url.clone())
+        .await
+        .change_context(BenchmarkError)?;
+
+    let mut request = StreamDataRequest {
+        filter: vec![filter.encode_to_vec()],
+        starting_cursor: Some(Cursor {
+            order_key: args.starting_block,
+            unique_key: Vec::new(),
+        }),
+        ending_cursor: Some(Cursor {
+            order_key: args.ending_block,
+            unique_key: Vec::new(),
+        }),
+        ..Default::default()
+    }
+    .into_request();
+
+    authorize_request(&mut request, args.bearer_token)?;
+
+    let start = Instant::now();
+
+    let stream = client
+        .stream_data(request)
+        .await
+        .change_context(BenchmarkError)?
+        .into_inner()
+        .take_until(async move { ct.cancelled().await });
+
+    tokio::pin!(stream);
+
+    let mut stats = BenchStats::default();
+    let mut last_message = start;
+
+    while let Some(message) = stream.try_next().await.change_context(BenchmarkError)? {
+        match message.message {
+            Some(ProtoMessage::Data(data_message)) => {
+                ensure_data_checksum(&data_message)?;
+
+                let now = Instant::now();
+                if stats.time_to_first_message.is_none() {
+                    stats.time_to_first_message = Some(now - start);
+                } else {
+                    stats.latencies.push(now - last_message);
+                }
+                last_message = now;
+
+                let block_number = data_message
+                    .end_cursor
+                    .as_ref()
+                    .map(|c| c.order_key)
+                    .unwrap_or_default();
+
+                stats.messages += 1;
+                stats.blocks_scanned = block_number.saturating_sub(args.starting_block) + 1;
+                stats.bytes += data_message.encoded_len() as u64;
+                if data_message.data.iter().any(|data| !data.is_empty()) {
+                    stats.blocks_with_data += 1;
+                }
+
+                if block_number >= args.ending_block {
+                    info!(block_number, "reached ending block");
+                    break;
+                }
+            }
+            Some(ProtoMessage::Heartbeat(_)) => {
+                stats.heartbeats += 1;
+            }
+            Some(ProtoMessage::RangeComplete(range_complete)) => {
+                let block_number = range_complete
+                    .cursor
+                    .as_ref()
+                    .map(|c| c.order_key)
+                    .unwrap_or(args.ending_block);
+                stats.blocks_scanned = block_number.saturating_sub(args.starting_block) + 1;
+                info!(block_number, "range complete");
+                break;
+            }
+            Some(ProtoMessage::Invalidate(_)) => {
+                stats.invalidates += 1;
+            }
+            Some(ProtoMessage::SystemMessage(system_message)) => {
+                use apibara_dna_protocol::dna::stream::system_message::Output;
+
+                match system_message.output {
+                    Some(Output::Stdout(stdout)) => info!("{}", stdout),
+                    Some(Output::Stderr(stderr)) => warn!("{}", stderr),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stats.print_summary(start.elapsed());
+
+    Ok(())
+}
+
+impl BenchStats {
+    fn print_summary(&mut self, elapsed: Duration) {
+        let elapsed_sec = elapsed.as_secs_f64();
+
+        let block_rate = self.blocks_scanned as f64 / elapsed_sec;
+        let message_rate = self.messages as f64 / elapsed_sec;
+        let byte_rate = Byte::from_f64(self.bytes as f64 / elapsed_sec).unwrap_or_default();
+
+        info!(
+            blocks = %block_rate,
+            messages = %message_rate,
+            bytes = format!("{:#.6}/s", byte_rate),
+            elapsed = ?elapsed,
+            "throughput"
+        );
+
+        self.latencies.sort_unstable();
+
+        info!(
+            first = ?self.time_to_first_message,
+            p50 = ?percentile(&self.latencies, 0.5),
+            p90 = ?percentile(&self.latencies, 0.9),
+            p99 = ?percentile(&self.latencies, 0.99),
+            max = ?self.latencies.last(),
+            "latency between messages"
+        );
+
+        let hit_ratio = if self.blocks_scanned == 0 {
+            0.0
+        } else {
+            self.blocks_with_data as f64 / self.blocks_scanned as f64
+        };
+
+        info!(
+            blocks_scanned = self.blocks_scanned,
+            blocks_with_data = self.blocks_with_data,
+            hit_ratio = format!("{hit_ratio:.4}"),
+            bytes = format!("{:#.6}", Byte::from_u64(self.bytes)),
+            heartbeats = self.heartbeats,
+            invalidates = self.invalidates,
+            "scan stats"
+        );
+    }
+}
+
+/// Returns the value at the given percentile of the sorted values.
+fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted.get(index).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::percentile;
+
+    #[test]
+    fn test_percentile() {
+        let values = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&values, 0.5), Some(Duration::from_millis(51)));
+        assert_eq!(percentile(&values, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&values, 1.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+}
@@ -4,7 +4,7 @@ use std::{
 };
 
 use apibara_dna_protocol::{
-    dna::stream::{dna_stream_client::DnaStreamClient, Cursor, StreamDataRequest},
+    dna::stream::{dna_stream_client::DnaStreamClient, Cursor, Data, StreamDataRequest},
     evm, starknet,
 };
 use byte_unit::Byte;
@@ -17,6 +17,12 @@ use tokio_util::sync::CancellationToken;
 use tonic::{metadata::AsciiMetadataValue, IntoRequest};
 use tracing::{info, warn};
 
+mod bench;
+mod tail;
+
+pub use self::bench::BenchArgs;
+pub use self::tail::{Chain, TailArgs};
+
 #[derive(Debug)]
 pub struct BenchmarkError;
 
@@ -33,6 +39,10 @@ pub enum Command {
     Evm(CommonArgs),
     /// Benchmark the Starknet DNA stream.
     Starknet(CommonArgs),
+    /// Print the streamed data as JSON lines.
+    Tail(TailArgs),
+    /// Measure the stream throughput and latency over a block range.
+    Bench(BenchArgs),
 }
 
 #[derive(Args, Debug, Clone)]
@@ -63,6 +73,8 @@ impl Cli {
             Command::Starknet(args) => {
                 run_benchmark::<starknet::Filter, StarknetStats>(args, ct).await
             }
+            Command::Tail(args) => tail::run_tail(args, ct).await,
+            Command::Bench(args) => bench::run_bench(args, ct).await,
         }
     }
 }
@@ -123,15 +135,7 @@ where
     }
     .into_request();
 
-    if let Some(bearer_token) = args.bearer_token {
-        let authorization_value = format!("Bearer {bearer_token}");
-        let authorization_value = AsciiMetadataValue::from_str(&authorization_value)
-            .change_context(BenchmarkError)
-            .attach_printable("failed to parse authorization value")?;
-        request
-            .metadata_mut()
-            .insert("authorization", authorization_value);
-    }
+    authorize_request(&mut request, args.bearer_token)?;
 
     let stream = client
         .stream_data(request)
@@ -151,6 +155,8 @@ where
         use apibara_dna_protocol::dna::stream::stream_data_response::Message as ProtoMessage;
         match message.message {
             Some(ProtoMessage::Data(data_message)) => {
+                ensure_data_checksum(&data_message)?;
+
                 let block_number = data_message
                     .end_cursor
                     .as_ref()
@@ -194,6 +200,35 @@ where
     Ok(())
 }
 
+fn authorize_request<T>(
+    request: &mut tonic::Request<T>,
+    bearer_token: Option<String>,
+) -> Result<(), BenchmarkError> {
+    if let Some(bearer_token) = bearer_token {
+        let authorization_value = format!("Bearer {bearer_token}");
+        let authorization_value = AsciiMetadataValue::from_str(&authorization_value)
+            .change_context(BenchmarkError)
+            .attach_printable("failed to parse authorization value")?;
+        request
+            .metadata_mut()
+            .insert("authorization", authorization_value);
+    }
+
+    Ok(())
+}
+
+/// Fail if the data doesn't match the checksum sent by the server.
+fn ensure_data_checksum(data: &Data) -> Result<(), BenchmarkError> {
+    if data.verify_checksum() {
+        return Ok(());
+    }
+
+    Err(BenchmarkError)
+        .attach_printable("data checksum mismatch")
+        .attach_printable_lazy(|| format!("end cursor: {:?}", data.end_cursor))
+        .attach_printable_lazy(|| format!("server id: {:?}", data.server_id))
+}
+
 trait Stats {
     type Block: Message + Default;
     fn new(index: usize) -> Self;
@@ -0,0 +1,208 @@
+//! Print the data streamed by a DNA server as JSON lines.
+use std::path::{Path, PathBuf};
+
+use apibara_dna_protocol::{
+    beaconchain,
+    dna::stream::{
+        dna_stream_client::DnaStreamClient, stream_data_response::Message as ProtoMessage, Cursor,
+        DataFinality, StreamDataRequest,
+    },
+    evm,
+    json::{message_descriptor, message_from_json, message_to_json},
+    starknet,
+};
+use clap::{Args, ValueEnum};
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use prost::Message;
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+use tonic::IntoRequest;
+use tracing::{info, warn};
+
+use crate::{authorize_request, ensure_data_checksum, BenchmarkError};
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Chain {
+    Evm,
+    Starknet,
+    Beaconchain,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TailArgs {
+    /// The chain streamed by the server.
+    #[clap(long, value_enum)]
+    pub chain: Chain,
+    /// Path to the JSON file with the filter.
+    #[clap(long)]
+    pub filter: PathBuf,
+    /// Stream URL.
+    #[clap(long, default_value = "http://localhost:7007")]
+    pub stream_url: String,
+    /// Bearer token used for authentication.
+    #[clap(long)]
+    pub bearer_token: Option<String>,
+    /// Start streaming from this block.
+    ///
+    /// If not set, start streaming from the current head.
+    #[clap(long)]
+    pub starting_block: Option<u64>,
+    /// Stop streaming at this block.
+    #[clap(long)]
+    pub ending_block: Option<u64>,
+}
+
+impl Chain {
+    fn descriptor_set(&self) -> &'static [u8] {
+        match self {
+            Chain::Evm => evm::EVM_DESCRIPTOR_SET,
+            Chain::Starknet => starknet::STARKNET_DESCRIPTOR_SET,
+            Chain::Beaconchain => beaconchain::BEACONCHAIN_DESCRIPTOR_SET,
+        }
+    }
+
+    fn package(&self) -> &'static str {
+        match self {
+            Chain::Evm => "evm.v2",
+            Chain::Starknet => "starknet.v2",
+            Chain::Beaconchain => "beaconchain.v2",
+        }
+    }
+
+    fn message_descriptor(&self, name: &str) -> Result<MessageDescriptor, BenchmarkError> {
+        let full_name = format!("{}.{}", self.package(), name);
+        message_descriptor(self.descriptor_set(), &full_name).change_context(BenchmarkError)
+    }
+}
+
+/// Read a JSON filter file and convert it to the chain's filter message.
+pub(crate) fn read_filter_file(
+    chain: Chain,
+    path: &Path,
+) -> Result<DynamicMessage, BenchmarkError> {
+    let filter_descriptor = chain.message_descriptor("Filter")?;
+
+    let filter = std::fs::read_to_string(path)
+        .change_context(BenchmarkError)
+        .attach_printable("failed to read filter file")
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+    let filter = serde_json::from_str::<serde_json::Value>(&filter)
+        .change_context(BenchmarkError)
+        .attach_printable("failed to parse filter file")?;
+
+    message_from_json(&filter_descriptor, &filter).change_context(BenchmarkError)
+}
+
+/// Stream data matching the filter and print each block as a JSON line.
+pub async fn run_tail(args: TailArgs, ct: CancellationToken) -> Result<(), BenchmarkError> {
+    let block_descriptor = args.chain.message_descriptor("Block")?;
+    let filter = read_filter_file(args.chain, &args.filter)?;
+
+    let mut client = DnaStreamClient::connect(args.stream_url.clone())
+        .await
+        .change_context(BenchmarkError)?;
+
+    let starting_cursor = args.starting_block.map(|block| Cursor {
+        order_key: block,
+        unique_key: Vec::new(),
+    });
+
+    let mut request = StreamDataRequest {
+        filter: vec![filter.encode_to_vec()],
+        start_at_head: Some(starting_cursor.is_none()),
+        starting_cursor,
+        ..Default::default()
+    }
+    .into_request();
+
+    authorize_request(&mut request, args.bearer_token)?;
+
+    let stream = client
+        .stream_data(request)
+        .await
+        .change_context(BenchmarkError)?
+        .into_inner()
+        .take_until(async move { ct.cancelled().await });
+
+    tokio::pin!(stream);
+
+    while let Some(message) = stream.try_next().await.change_context(BenchmarkError)? {
+        match message.message {
+            Some(ProtoMessage::Data(data_message)) => {
+                ensure_data_checksum(&data_message)?;
+
+                let finality = DataFinality::try_from(data_message.finality).unwrap_or_default();
+
+                for block_data in data_message.data.iter() {
+                    if block_data.is_empty() {
+                        continue;
+                    }
+
+                    let block =
+                        DynamicMessage::decode(block_descriptor.clone(), block_data.as_ref())
+                            .change_context(BenchmarkError)
+                            .attach_printable("failed to decode block")?;
+
+                    let line = json!({
+                        "cursor": cursor_to_json(data_message.cursor.as_ref()),
+                        "endCursor": cursor_to_json(data_message.end_cursor.as_ref()),
+                        "finality": finality.as_str_name(),
+                        "block": message_to_json(&block),
+                    });
+
+                    println!("{line}");
+                }
+
+                let block_number = data_message
+                    .end_cursor
+                    .as_ref()
+                    .map(|c| c.order_key)
+                    .unwrap_or_default();
+
+                if let Some(end_block) = args.ending_block {
+                    if block_number >= end_block {
+                        info!(block_number, "reached ending block");
+                        break;
+                    }
+                }
+            }
+            Some(ProtoMessage::Invalidate(invalidate)) => {
+                println!(
+                    "{}",
+                    json!({ "invalidate": cursor_to_json(invalidate.cursor.as_ref()) })
+                );
+            }
+            Some(ProtoMessage::Finalize(finalize)) => {
+                println!(
+                    "{}",
+                    json!({ "finalize": cursor_to_json(finalize.cursor.as_ref()) })
+                );
+            }
+            Some(ProtoMessage::SystemMessage(system_message)) => {
+                use apibara_dna_protocol::dna::stream::system_message::Output;
+
+                match system_message.output {
+                    Some(Output::Stdout(stdout)) => info!("{}", stdout),
+                    Some(Output::Stderr(stderr)) => warn!("{}", stderr),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn cursor_to_json(cursor: Option<&Cursor>) -> serde_json::Value {
+    match cursor {
+        None => serde_json::Value::Null,
+        Some(cursor) => json!({
+            "orderKey": cursor.order_key,
+            "uniqueKey": format!("0x{}", hex::encode(&cursor.unique_key)),
+        }),
+    }
+}
@@ -1,3 +1,5 @@
+use std::future::Future;
+
 use anyhow::anyhow;
 use apibara_observability::{Counter, KeyValue};
 use bytes::Bytes;
@@ -8,14 +10,23 @@ use crate::{
     chain::PendingBlockInfo,
     file_cache::{FileCache, FileFetch},
     fragment,
-    object_store::{GetOptions, ObjectETag, ObjectStore, PutOptions},
-    segment::{SegmentGroup, SerializedSegment},
+    migrate::upgrade_format,
+    object_store::{
+        DeleteOptions, GetOptions, ObjectETag, ObjectStore, PutMultipartOptions, PutOptions,
+    },
+    segment::{
+        decode_segment_header, encode_segment_header, SegmentGroup, SerializedSegment,
+        SEGMENT_FORMAT_VERSION,
+    },
+    shared_cache::SharedCache,
     Cursor,
 };
 
 static BLOCK_PREFIX: &str = "block";
+static QUARANTINE_PREFIX: &str = "quarantine";
 static SEGMENT_PREFIX: &str = "segment";
 static GROUP_PREFIX: &str = "group";
+pub static GENERATION_PREFIX: &str = "generation";
 
 #[derive(Debug)]
 pub struct BlockStoreError;
@@ -24,19 +35,26 @@ pub struct BlockStoreError;
 pub struct BlockStoreMetrics {
     pub block_count: Counter<u64>,
     pub block_cache_hit: Counter<u64>,
+    pub block_prefetch: Counter<u64>,
     pub pending_block_count: Counter<u64>,
     pub pending_block_cache_hit: Counter<u64>,
     pub segment_count: Counter<u64>,
     pub segment_cache_hit: Counter<u64>,
+    pub segment_coalesced: Counter<u64>,
     pub group_count: Counter<u64>,
     pub group_cache_hit: Counter<u64>,
 }
 
 /// Download blocks from the object store with a local cache.
+///
+/// Concurrent requests for the same file share a single download: only the first
+/// request reaches the object store, the others wait for its result.
 #[derive(Clone)]
 pub struct BlockStoreReader {
     client: ObjectStore,
+    segment_generation: u64,
     file_cache: FileCache,
+    shared_cache: Option<SharedCache>,
     metrics: BlockStoreMetrics,
 }
 
@@ -44,12 +62,14 @@ pub struct BlockStoreReader {
 #[derive(Clone)]
 pub struct UncachedBlockStoreReader {
     client: ObjectStore,
+    segment_generation: u64,
 }
 
 /// Upload blocks to the object store.
 #[derive(Clone)]
 pub struct BlockStoreWriter {
     client: ObjectStore,
+    segment_generation: u64,
 }
 
 impl BlockStoreReader {
@@ -57,11 +77,25 @@ impl BlockStoreReader {
         let metrics = BlockStoreMetrics::default();
         Self {
             client,
+            segment_generation: 0,
             file_cache,
+            shared_cache: None,
             metrics,
         }
     }
 
+    /// Read the segments and groups of the given generation.
+    pub fn with_segment_generation(mut self, segment_generation: u64) -> Self {
+        self.segment_generation = segment_generation;
+        self
+    }
+
+    /// Check the shared cache before downloading blocks, segments and groups.
+    pub fn with_shared_cache(mut self, shared_cache: SharedCache) -> Self {
+        self.shared_cache = Some(shared_cache);
+        self
+    }
+
     #[tracing::instrument(
         name = "block_store_get_block",
         skip_all,
@@ -76,15 +110,7 @@ impl BlockStoreReader {
 
         let fetch_block = {
             let key = key.clone();
-            move || {
-                let client = self.client.clone();
-                async move {
-                    match client.get(&key, GetOptions::default()).await {
-                        Ok(response) => Ok(response.body),
-                        Err(err) => Err(anyhow!(err)),
-                    }
-                }
-            }
+            move || self.fetch_immutable(key, false)
         };
         let entry = self.file_cache.general.fetch(key, fetch_block);
 
@@ -99,6 +125,25 @@ impl BlockStoreReader {
         entry
     }
 
+    /// Start downloading a block in the background.
+    ///
+    /// Prefetches are not counted as block requests, so they don't change the cache hit rate.
+    pub fn prefetch_block(&self, cursor: &Cursor) {
+        let key = format_block_key(cursor);
+
+        let fetch_block = {
+            let key = key.clone();
+            move || self.fetch_immutable(key, false)
+        };
+
+        // The cache keeps downloading the block after the fetch handle is dropped.
+        let entry = self.file_cache.general.fetch(key, fetch_block);
+
+        if let FetchState::Miss = entry.state() {
+            self.metrics.block_prefetch.add(1, &[]);
+        }
+    }
+
     #[tracing::instrument(
         name = "block_store_get_pending_block",
         skip_all,
@@ -149,7 +194,7 @@ impl BlockStoreReader {
     pub fn get_segment(&self, first_cursor: &Cursor, name: impl Into<String>) -> FileFetch {
         let current_span = tracing::Span::current();
         let name = name.into();
-        let key = format_segment_key(first_cursor, &name);
+        let key = format_segment_key(self.segment_generation, first_cursor, &name);
 
         current_span.record("name", &name);
         self.metrics
@@ -158,22 +203,21 @@ impl BlockStoreReader {
 
         let fetch_segment = {
             let key = key.clone();
-            move || {
-                let client = self.client.clone();
-                async move {
-                    match client.get(&key, GetOptions::default()).await {
-                        Ok(response) => Ok(response.body),
-                        Err(err) => Err(anyhow!(err)),
-                    }
-                }
-            }
+            move || self.fetch_immutable(key, true)
         };
 
         let entry = self.file_cache.general.fetch(key, fetch_segment);
 
         match entry.state() {
             FetchState::Miss => current_span.record("cache_hit", 0),
-            _ => {
+            FetchState::Wait => {
+                // Another stream is already downloading this segment.
+                self.metrics
+                    .segment_coalesced
+                    .add(1, &[KeyValue::new("name", name)]);
+                current_span.record("cache_hit", 1)
+            }
+            FetchState::Hit => {
                 self.metrics
                     .segment_cache_hit
                     .add(1, &[KeyValue::new("name", name)]);
@@ -192,21 +236,13 @@ impl BlockStoreReader {
     )]
     pub fn get_group(&self, cursor: &Cursor) -> FileFetch {
         let current_span = tracing::Span::current();
-        let key = format_group_key(cursor);
+        let key = format_group_key(self.segment_generation, cursor);
 
         self.metrics.group_count.add(1, &[]);
 
         let fetch_group = {
             let key = key.clone();
-            move || {
-                let client = self.client.clone();
-                async move {
-                    match client.get(&key, GetOptions::default()).await {
-                        Ok(response) => Ok(response.body),
-                        Err(err) => Err(anyhow!(err)),
-                    }
-                }
-            }
+            move || self.fetch_immutable(key, true)
         };
         let entry = self.file_cache.index.fetch(key, fetch_group);
 
@@ -220,11 +256,60 @@ impl BlockStoreReader {
 
         entry
     }
+
+    /// Download an object that never changes once written.
+    ///
+    /// Checks the shared cache first, and stores the objects downloaded from the
+    /// object store in the shared cache in the background.
+    /// Segments and groups are returned without their header.
+    fn fetch_immutable(
+        &self,
+        key: String,
+        versioned: bool,
+    ) -> impl Future<Output = anyhow::Result<Bytes>> + Send + 'static {
+        let client = self.client.clone();
+        let shared_cache = self.shared_cache.clone();
+
+        async move {
+            if let Some(shared_cache) = shared_cache.as_ref() {
+                if let Some(body) = shared_cache.get(&key).await {
+                    return Ok(body);
+                }
+            }
+
+            let response = client
+                .get(&key, GetOptions::default())
+                .await
+                .map_err(|err| anyhow!(err))?;
+
+            let body = if versioned {
+                decode_versioned_object(&key, response.body).map_err(|err| anyhow!(err))?
+            } else {
+                response.body
+            };
+
+            if let Some(shared_cache) = shared_cache {
+                let body = body.clone();
+                tokio::spawn(async move { shared_cache.put(&key, body).await });
+            }
+
+            Ok(body)
+        }
+    }
 }
 
 impl UncachedBlockStoreReader {
     pub fn new(client: ObjectStore) -> Self {
-        Self { client }
+        Self {
+            client,
+            segment_generation: 0,
+        }
+    }
+
+    /// Read the segments and groups of the given generation.
+    pub fn with_segment_generation(mut self, segment_generation: u64) -> Self {
+        self.segment_generation = segment_generation;
+        self
     }
 
     #[tracing::instrument(name = "uncached_block_store_get_block", skip_all, level = "debug")]
@@ -270,20 +355,32 @@ impl UncachedBlockStoreReader {
         Ok((first_cursor, segment))
     }
 
+    pub async fn get_segment(
+        &self,
+        first_cursor: &Cursor,
+        name: impl Into<String>,
+    ) -> Result<Bytes, BlockStoreError> {
+        let (body, _) = self
+            .get_segment_with_stored_size(first_cursor, name)
+            .await?;
+        Ok(body)
+    }
+
+    /// Returns the segment's data together with its size in the object store.
     #[tracing::instrument(
         name = "uncached_block_store_get_segment",
         skip_all,
         fields(name),
         level = "debug"
     )]
-    pub async fn get_segment(
+    pub async fn get_segment_with_stored_size(
         &self,
         first_cursor: &Cursor,
         name: impl Into<String>,
-    ) -> Result<Bytes, BlockStoreError> {
+    ) -> Result<(Bytes, usize), BlockStoreError> {
         let current_span = tracing::Span::current();
         let name = name.into();
-        let key = format_segment_key(first_cursor, &name);
+        let key = format_segment_key(self.segment_generation, first_cursor, &name);
 
         current_span.record("name", &name);
 
@@ -296,12 +393,37 @@ impl UncachedBlockStoreReader {
             .attach_printable_lazy(|| format!("cursor: {}", first_cursor))
             .attach_printable_lazy(|| format!("name: {}", name))?;
 
-        Ok(response.body)
+        let body = decode_versioned_object(&key, response.body)?;
+
+        Ok((body, response.stored_size))
+    }
+
+    /// Returns the names of the fragments stored in the segment starting at `first_cursor`.
+    pub async fn list_segment_names(
+        &self,
+        first_cursor: &Cursor,
+    ) -> Result<Vec<String>, BlockStoreError> {
+        let prefix = format_segment_key(self.segment_generation, first_cursor, "");
+        let keys = self
+            .client
+            .list(&prefix)
+            .await
+            .change_context(BlockStoreError)
+            .attach_printable("failed to list segment fragments")
+            .attach_printable_lazy(|| format!("cursor: {}", first_cursor))?;
+
+        let names = keys
+            .iter()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(String::from)
+            .collect();
+
+        Ok(names)
     }
 
     #[tracing::instrument(name = "uncached_block_store_get_group", skip_all, level = "debug")]
     pub async fn get_group(&self, cursor: &Cursor) -> Result<Bytes, BlockStoreError> {
-        let key = format_group_key(cursor);
+        let key = format_group_key(self.segment_generation, cursor);
         let response = self
             .client
             .get(&key, GetOptions::default())
@@ -310,13 +432,22 @@ impl UncachedBlockStoreReader {
             .attach_printable("failed to get group")
             .attach_printable_lazy(|| format!("cursor: {}", cursor))?;
 
-        Ok(response.body)
+        decode_versioned_object(&key, response.body)
     }
 }
 
 impl BlockStoreWriter {
     pub fn new(client: ObjectStore) -> Self {
-        Self { client }
+        Self {
+            client,
+            segment_generation: 0,
+        }
+    }
+
+    /// Write the segments and groups of the given generation.
+    pub fn with_segment_generation(mut self, segment_generation: u64) -> Self {
+        self.segment_generation = segment_generation;
+        self
     }
 
     pub async fn put_block(
@@ -342,6 +473,35 @@ impl BlockStoreWriter {
         Ok((size, response.etag))
     }
 
+    /// Upload a block that failed validation, so that it can be inspected later.
+    ///
+    /// Quarantined blocks are never read by the DNA server.
+    pub async fn put_quarantined_block(
+        &self,
+        cursor: &Cursor,
+        block: &fragment::Block,
+    ) -> Result<ObjectETag, BlockStoreError> {
+        let serialized = rkyv::to_bytes::<rkyv::rancor::Error>(block)
+            .change_context(BlockStoreError)
+            .attach_printable("failed to serialize quarantined block")?;
+
+        let bytes = Bytes::copy_from_slice(serialized.as_slice());
+
+        let response = self
+            .client
+            .put(
+                &format_quarantined_block_key(cursor),
+                bytes,
+                PutOptions::default(),
+            )
+            .await
+            .change_context(BlockStoreError)
+            .attach_printable("failed to put quarantined block")
+            .attach_printable_lazy(|| format!("cursor: {}", cursor))?;
+
+        Ok(response.etag)
+    }
+
     pub async fn put_pending_block(
         &self,
         block_info: &PendingBlockInfo,
@@ -369,27 +529,55 @@ impl BlockStoreWriter {
         Ok((size, response.etag))
     }
 
+    /// Upload a segment.
+    ///
+    /// Segments can be large, so they're uploaded with a multipart upload.
     pub async fn put_segment(
         &self,
-        first_cursor: &Cursor,
         segment: SerializedSegment,
     ) -> Result<ObjectETag, BlockStoreError> {
+        let key = format_segment_key(self.segment_generation, &segment.first_block, &segment.name);
         let response = self
             .client
-            .put(
-                &format_segment_key(first_cursor, &segment.name),
-                segment.data,
-                PutOptions::default(),
+            .put_multipart(
+                &key,
+                encode_segment_header(&segment.data),
+                PutMultipartOptions::default(),
             )
             .await
             .change_context(BlockStoreError)
             .attach_printable("failed to put segment")
-            .attach_printable_lazy(|| format!("cursor: {}", first_cursor))
+            .attach_printable_lazy(|| format!("cursor: {}", segment.first_block))
             .attach_printable_lazy(|| format!("segment name: {}", segment.name))?;
 
         Ok(response.etag)
     }
 
+    /// Delete all block objects at the given height, including pending and reorged blocks.
+    ///
+    /// Returns the number of deleted objects.
+    pub async fn delete_blocks(&self, block_number: u64) -> Result<usize, BlockStoreError> {
+        let prefix = format_block_prefix(block_number);
+        let keys = self
+            .client
+            .list(&prefix)
+            .await
+            .change_context(BlockStoreError)
+            .attach_printable("failed to list blocks")
+            .attach_printable_lazy(|| format!("block number: {}", block_number))?;
+
+        for key in keys.iter() {
+            self.client
+                .delete(key, DeleteOptions::default())
+                .await
+                .change_context(BlockStoreError)
+                .attach_printable("failed to delete block")
+                .attach_printable_lazy(|| format!("key: {}", key))?;
+        }
+
+        Ok(keys.len())
+    }
+
     pub async fn put_group(
         &self,
         first_cursor: &Cursor,
@@ -399,13 +587,13 @@ impl BlockStoreWriter {
             .change_context(BlockStoreError)
             .attach_printable("failed to serialize segment group")?;
 
-        let bytes = Bytes::copy_from_slice(serialized.as_slice());
+        let bytes = encode_segment_header(serialized.as_slice());
         let size = bytes.len();
 
         let response = self
             .client
             .put(
-                &format_group_key(first_cursor),
+                &format_group_key(self.segment_generation, first_cursor),
                 bytes,
                 PutOptions::default(),
             )
@@ -418,6 +606,31 @@ impl BlockStoreWriter {
     }
 }
 
+/// Returns the content of a segment or group, checking its format version.
+///
+/// Objects in an older format are upgraded on read, until the migrate command
+/// rewrites them. Objects in a newer format were written by a newer version.
+fn decode_versioned_object(key: &str, body: Bytes) -> Result<Bytes, BlockStoreError> {
+    let (version, content) = decode_segment_header(body);
+
+    if version > SEGMENT_FORMAT_VERSION {
+        return Err(BlockStoreError)
+            .attach_printable("format version is newer than the supported version")
+            .attach_printable_lazy(|| format!("key: {}", key))
+            .attach_printable_lazy(|| format!("version: {}", version))
+            .attach_printable_lazy(|| format!("supported version: {}", SEGMENT_FORMAT_VERSION));
+    }
+
+    if version < SEGMENT_FORMAT_VERSION {
+        return upgrade_format(version, content)
+            .change_context(BlockStoreError)
+            .attach_printable("unsupported format version. run the migrate command to upgrade")
+            .attach_printable_lazy(|| format!("key: {}", key));
+    }
+
+    Ok(content)
+}
+
 fn format_pending_block_key(number: u64, generation: u64) -> String {
     format!(
         "{}/{:0>10}/pending-{:0>4}",
@@ -425,16 +638,49 @@ fn format_pending_block_key(number: u64, generation: u64) -> String {
     )
 }
 
+fn format_block_prefix(number: u64) -> String {
+    format!("{}/{:0>10}/", BLOCK_PREFIX, number)
+}
+
 fn format_block_key(cursor: &Cursor) -> String {
     format!("{}/{:0>10}/{}", BLOCK_PREFIX, cursor.number, cursor.hash)
 }
 
-fn format_segment_key(first_block: &Cursor, name: &str) -> String {
-    format!("{}/{:0>10}/{}", SEGMENT_PREFIX, first_block.number, name)
+fn format_quarantined_block_key(cursor: &Cursor) -> String {
+    format!(
+        "{}/{:0>10}/{}",
+        QUARANTINE_PREFIX, cursor.number, cursor.hash
+    )
+}
+
+/// Returns the prefix of the segments and groups of the given generation.
+///
+/// Generation 0 is the original layout, stored at the root of the object store.
+pub fn segment_generation_prefix(generation: u64) -> String {
+    if generation == 0 {
+        String::new()
+    } else {
+        format!("{}/{:0>4}/", GENERATION_PREFIX, generation)
+    }
 }
 
-fn format_group_key(first_block: &Cursor) -> String {
-    format!("{}/{:0>10}/index", GROUP_PREFIX, first_block.number)
+fn format_segment_key(generation: u64, first_block: &Cursor, name: &str) -> String {
+    format!(
+        "{}{}/{:0>10}/{}",
+        segment_generation_prefix(generation),
+        SEGMENT_PREFIX,
+        first_block.number,
+        name
+    )
+}
+
+fn format_group_key(generation: u64, first_block: &Cursor) -> String {
+    format!(
+        "{}{}/{:0>10}/index",
+        segment_generation_prefix(generation),
+        GROUP_PREFIX,
+        first_block.number
+    )
 }
 
 impl error_stack::Context for BlockStoreError {}
@@ -454,6 +700,10 @@ impl Default for BlockStoreMetrics {
             block_cache_hit: meter
                 .u64_counter("dna.block_store.get_block_cache_hit")
                 .build(),
+            block_prefetch: meter
+                .u64_counter("dna.block_store.prefetch_block")
+                .with_description("blocks downloaded before they were requested")
+                .build(),
             pending_block_count: meter
                 .u64_counter("dna.block_store.get_pending_block")
                 .build(),
@@ -464,6 +714,10 @@ impl Default for BlockStoreMetrics {
             segment_cache_hit: meter
                 .u64_counter("dna.block_store.get_segment_cache_hit")
                 .build(),
+            segment_coalesced: meter
+                .u64_counter("dna.block_store.get_segment_coalesced")
+                .with_description("segment requests that waited for an in-flight download")
+                .build(),
             group_count: meter.u64_counter("dna.block_store.get_group").build(),
             group_cache_hit: meter
                 .u64_counter("dna.block_store.get_group_cache_hit")
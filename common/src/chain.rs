@@ -148,6 +148,16 @@ impl CanonicalChainBuilder {
         }
     }
 
+    /// Returns the segment before the current segment, if any.
+    pub fn previous_segment(&self) -> Option<&CanonicalChainSegmentInfo> {
+        match self {
+            CanonicalChainBuilder::Empty => None,
+            CanonicalChainBuilder::Building {
+                previous_segment, ..
+            } => previous_segment.as_ref(),
+        }
+    }
+
     /// Returns the number of blocks in the segment.
     pub fn segment_size(&self) -> usize {
         match self {
@@ -276,6 +286,55 @@ impl CanonicalChainBuilder {
         Ok(removed)
     }
 
+    /// Add the previous segment back to the start of the current segment.
+    ///
+    /// This is used to recover from reorgs deeper than the current segment, and undoes
+    /// `take_segment`.
+    pub fn prepend_segment(
+        &mut self,
+        segment: CanonicalChainSegment,
+    ) -> Result<(), CanonicalChainError> {
+        let CanonicalChainBuilder::Building {
+            canonical,
+            info,
+            reorgs,
+            previous_segment,
+        } = self
+        else {
+            return Err(CanonicalChainError::Builder)
+                .attach_printable("tried to prepend a segment to an empty segment");
+        };
+
+        if previous_segment.as_ref() != Some(&segment.info)
+            || segment.info.last_block.number + 1 != info.first_block.number
+        {
+            return Err(CanonicalChainError::Builder)
+                .attach_printable("segment is not the previous segment")
+                .attach_printable_lazy(|| format!("segment: {:?}", segment.info))
+                .attach_printable_lazy(|| format!("previous segment: {:?}", previous_segment));
+        }
+
+        let mut new_canonical = Vec::with_capacity(segment.canonical.len() + canonical.len());
+        for (offset, canonical_block) in segment.canonical.into_iter().enumerate() {
+            let block_number = segment.info.first_block.number + offset as u64;
+
+            new_canonical.push(canonical_block.hash);
+            if !canonical_block.reorgs.is_empty() {
+                reorgs
+                    .entry(block_number)
+                    .or_default()
+                    .extend(canonical_block.reorgs);
+            }
+        }
+        new_canonical.append(canonical);
+
+        *canonical = new_canonical;
+        info.first_block = segment.info.first_block;
+        *previous_segment = segment.previous_segment;
+
+        Ok(())
+    }
+
     // Returns the current builder's state ready for serialization.
     pub fn current_segment(&self) -> Result<CanonicalChainSegment, CanonicalChainError> {
         let CanonicalChainBuilder::Building {
@@ -493,6 +552,20 @@ impl CanonicalChainSegment {
 
         Ok(ReconnectAction::OfflineReorg(reorg_target))
     }
+
+    /// Remove the blocks after `last_block_number` from the segment.
+    ///
+    /// Unlike shrinking the chain builder, the removed blocks are not recorded as reorged.
+    pub fn truncate(&mut self, last_block_number: u64) -> Result<(), CanonicalChainError> {
+        let last_block = self.canonical(last_block_number)?;
+        let len = last_block_number - self.info.first_block.number + 1;
+
+        self.canonical.truncate(len as usize);
+        self.extra_reorgs.clear();
+        self.info.last_block = last_block;
+
+        Ok(())
+    }
 }
 
 impl Default for CanonicalChainBuilder {
@@ -818,4 +891,78 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_prepend_segment() {
+        let mut builder = CanonicalChainBuilder::new();
+
+        let mut block = genesis_block(0);
+        builder.grow(block.clone()).unwrap();
+
+        for _ in 0..19 {
+            block = next_block(&block, 0);
+            builder.grow(block.clone()).unwrap();
+        }
+
+        let segment = builder.take_segment(10).unwrap();
+        assert_eq!(builder.segment_size(), 10);
+        assert_eq!(builder.previous_segment(), Some(&segment.info));
+
+        // Can't shrink to a block in the previous segment.
+        let checkpoint = new_test_cursor(1_005, 0);
+        assert!(builder.shrink(checkpoint.clone()).is_err());
+
+        // Only the previous segment can be prepended.
+        let mut other = segment.clone();
+        other.info.last_block = new_test_cursor(1_008, 0);
+        assert!(builder.prepend_segment(other).is_err());
+
+        builder.prepend_segment(segment).unwrap();
+        assert_eq!(builder.segment_size(), 20);
+        assert!(builder.previous_segment().is_none());
+
+        builder.shrink(checkpoint.clone()).unwrap();
+
+        let mut block = BlockInfo {
+            number: checkpoint.number,
+            hash: checkpoint.hash.clone(),
+            parent: Hash::default(),
+        };
+        for _ in 0..20 {
+            block = next_block(&block, 1);
+            builder.grow(block.clone()).unwrap();
+        }
+
+        let segment = builder.take_segment(10).unwrap();
+        assert_eq!(segment.info.first_block, new_test_cursor(1_000, 0));
+        assert_eq!(segment.info.last_block, new_test_cursor(1_009, 1));
+
+        let action = segment.reconnect(&new_test_cursor(1_007, 0)).unwrap();
+        assert_eq!(action, ReconnectAction::OfflineReorg(checkpoint));
+    }
+
+    #[test]
+    fn test_truncate_segment() {
+        let mut builder = CanonicalChainBuilder::new();
+
+        let mut block = genesis_block(0);
+        builder.grow(block.clone()).unwrap();
+
+        for _ in 0..9 {
+            block = next_block(&block, 0);
+            builder.grow(block.clone()).unwrap();
+        }
+
+        let mut segment = builder.current_segment().unwrap();
+
+        assert!(segment.truncate(999).is_err());
+        assert!(segment.truncate(1_010).is_err());
+
+        segment.truncate(1_004).unwrap();
+        assert_eq!(segment.info.first_block, new_test_cursor(1_000, 0));
+        assert_eq!(segment.info.last_block, new_test_cursor(1_004, 0));
+        assert_eq!(segment.canonical.len(), 5);
+        assert!(segment.canonical(1_005).is_err());
+        assert_eq!(segment.canonical(1_004).unwrap(), new_test_cursor(1_004, 0));
+    }
 }
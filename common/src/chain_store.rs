@@ -37,7 +37,16 @@ impl ChainStore {
         segment: &CanonicalChainSegment,
     ) -> Result<ObjectETag, ChainStoreError> {
         let filename = self.segment_filename(segment.info.first_block.number);
-        self.put_impl(&filename, segment).await
+        let etag = self.put_impl(&filename, segment).await?;
+        // Segments are uploaded again after deep reorgs.
+        self.cache.general.remove(&self.format_key(&filename));
+        Ok(etag)
+    }
+
+    /// Remove the segment starting at the given block from the cache.
+    pub fn invalidate(&self, first_block_number: u64) {
+        let filename = self.segment_filename(first_block_number);
+        self.cache.general.remove(&self.format_key(&filename));
     }
 
     pub async fn put_recent(
@@ -61,6 +61,29 @@ impl FullCanonicalChain {
         })
     }
 
+    /// Create the canonical chain from the recent segment saved in a snapshot.
+    pub fn from_recent(
+        store: ChainStore,
+        starting_block: u64,
+        chain_segment_size: usize,
+        recent: CanonicalChainSegment,
+    ) -> Self {
+        Self {
+            store,
+            starting_block,
+            chain_segment_size,
+            recent,
+        }
+    }
+
+    pub(crate) fn chain_segment_size(&self) -> usize {
+        self.chain_segment_size
+    }
+
+    pub(crate) fn recent(&self) -> &CanonicalChainSegment {
+        &self.recent
+    }
+
     pub async fn get_next_cursor(
         &self,
         cursor: &Option<Cursor>,
@@ -145,6 +168,14 @@ impl FullCanonicalChain {
             return Ok(());
         };
 
+        // After a reorg deeper than the recent segment, the segments it covers again
+        // will be uploaded with the new canonical chain.
+        let mut segment_start = recent.info.first_block.number;
+        while segment_start < self.recent.info.first_block.number {
+            self.store.invalidate(segment_start);
+            segment_start += self.chain_segment_size as u64;
+        }
+
         self.recent = recent;
 
         Ok(())
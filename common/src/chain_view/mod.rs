@@ -1,10 +1,11 @@
 mod error;
 mod full;
 mod metrics;
+mod snapshot;
 mod sync;
 mod view;
 
 pub use self::error::ChainViewError;
 pub use self::full::{CanonicalCursor, NextCursor, ValidatedCursor};
 pub use self::sync::{chain_view_sync_loop, ChainViewSyncService};
-pub use self::view::ChainView;
+pub use self::view::{ChainView, SegmentLayout};
@@ -0,0 +1,90 @@
+//! Save the chain view to disk so that restarted servers are ready immediately.
+use std::path::Path;
+
+use error_stack::{Result, ResultExt};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::chain::CanonicalChainSegment;
+
+use super::error::ChainViewError;
+
+/// Snapshots with a different version are ignored.
+pub(crate) const SNAPSHOT_VERSION: u32 = 2;
+
+/// The state of the chain view, including the tail of the canonical chain.
+#[derive(Archive, Serialize, Deserialize)]
+pub struct ChainViewSnapshot {
+    pub version: u32,
+    pub starting_block: u64,
+    pub finalized: u64,
+    pub segmented: Option<u64>,
+    pub grouped: Option<u64>,
+    pub chain_segment_size: u64,
+    pub segment_generation: u64,
+    pub segment_size: u64,
+    pub group_size: u64,
+    pub fragment_segment_size: Vec<(String, u64)>,
+    pub recent: CanonicalChainSegment,
+}
+
+impl ChainViewSnapshot {
+    /// Returns the snapshot stored at `path`, if any.
+    ///
+    /// Snapshots written by a different version of the server are ignored.
+    pub async fn load(path: &Path) -> Result<Option<Self>, ChainViewError> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .change_context(ChainViewError)
+                    .attach_printable("failed to read chain view snapshot")
+                    .attach_printable_lazy(|| format!("path: {}", path.display()));
+            }
+        };
+
+        let snapshot = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&bytes)
+            .change_context(ChainViewError)
+            .attach_printable("failed to deserialize chain view snapshot")
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Ok(None);
+        }
+
+        Ok(Some(snapshot))
+    }
+
+    /// Store the snapshot at `path`, replacing the previous snapshot.
+    ///
+    /// The snapshot is written to a temporary file first, so that a crash while
+    /// writing never leaves a corrupted snapshot.
+    pub async fn save(&self, path: &Path) -> Result<(), ChainViewError> {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(self)
+            .change_context(ChainViewError)
+            .attach_printable("failed to serialize chain view snapshot")?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .change_context(ChainViewError)
+                .attach_printable("failed to create chain view snapshot directory")
+                .attach_printable_lazy(|| format!("path: {}", parent.display()))?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, bytes.as_slice())
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to write chain view snapshot")
+            .attach_printable_lazy(|| format!("path: {}", temp_path.display()))?;
+
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to replace chain view snapshot")
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
 use apibara_etcd::EtcdClient;
 use error_stack::{Result, ResultExt};
@@ -14,12 +14,26 @@ use crate::{
     options_store::OptionsStore,
 };
 
-use super::{error::ChainViewError, full::FullCanonicalChain, view::ChainView};
+use super::{
+    error::ChainViewError,
+    full::FullCanonicalChain,
+    snapshot::ChainViewSnapshot,
+    view::{ChainView, SegmentLayout},
+};
 
 pub struct ChainViewSyncService {
     tx: tokio::sync::watch::Sender<Option<ChainView>>,
-    etcd_client: EtcdClient,
+    ingestion_state_client: IngestionStateClient,
+    options_store: OptionsStore,
     chain_store: ChainStore,
+    snapshot_path: Option<PathBuf>,
+}
+
+/// The segment options used by the chain view to find segments and groups.
+#[derive(Debug, PartialEq, Eq)]
+struct StoredLayout {
+    chain_segment_size: usize,
+    segment: SegmentLayout,
 }
 
 impl ChainViewSyncService {
@@ -32,14 +46,25 @@ impl ChainViewSyncService {
         let chain_store = ChainStore::new(object_store, chain_file_cache);
         Self {
             tx,
-            etcd_client,
+            ingestion_state_client: IngestionStateClient::new(&etcd_client),
+            options_store: OptionsStore::new(&etcd_client),
             chain_store,
+            snapshot_path: None,
         }
     }
 
+    /// Save the chain view to this file, and restore it from the file on startup.
+    ///
+    /// A restored chain view is available as soon as it catches up with ingestion,
+    /// without waiting for the recent canonical chain segment to download.
+    pub fn with_snapshot_path(mut self, path: PathBuf) -> Self {
+        self.snapshot_path = Some(path);
+        self
+    }
+
     pub async fn start(self, ct: CancellationToken) -> Result<(), ChainViewError> {
         info!("starting chain view sync service");
-        let mut ingestion_state_client = IngestionStateClient::new(&self.etcd_client);
+        let mut ingestion_state_client = self.ingestion_state_client.clone();
 
         let starting_block = loop {
             if ct.is_cancelled() {
@@ -59,103 +84,30 @@ impl ChainViewSyncService {
             tokio::time::sleep(Duration::from_secs(10)).await;
         };
 
-        let finalized = loop {
-            if ct.is_cancelled() {
+        let chain_view = if let Some(chain_view) = self
+            .restore_snapshot(starting_block, &mut ingestion_state_client)
+            .await?
+        {
+            info!("restored chain view from snapshot");
+            chain_view
+        } else {
+            let Some(chain_view) = self
+                .initialize(starting_block, &mut ingestion_state_client, &ct)
+                .await?
+            else {
                 return Ok(());
-            }
-
-            let finalized = ingestion_state_client
-                .get_finalized()
-                .await
-                .change_context(ChainViewError)?;
-
-            if let Some(finalized) = finalized {
-                break finalized;
-            }
-
-            info!(step = "finalized_block", "waiting for ingestion to start");
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            };
+            chain_view
         };
 
-        let segmented = ingestion_state_client
-            .get_segmented()
-            .await
-            .change_context(ChainViewError)?;
-
-        let grouped = ingestion_state_client
-            .get_grouped()
-            .await
-            .change_context(ChainViewError)?;
-
-        loop {
-            if ct.is_cancelled() {
-                return Ok(());
-            }
-
-            let recent = ingestion_state_client
-                .get_ingested()
-                .await
-                .change_context(ChainViewError)?;
-
-            if recent.is_some() {
-                break;
-            }
-
-            info!(step = "recent", "waiting for ingestion to start");
-            tokio::time::sleep(Duration::from_secs(10)).await;
-        }
-
-        if ct.is_cancelled() {
-            return Ok(());
-        }
-
-        let mut options_store = OptionsStore::new(&self.etcd_client);
-        let chain_segment_size = options_store
-            .get_chain_segment_size()
-            .await
-            .change_context(ChainViewError)
-            .attach_printable("failed to get chain segment size options")?
-            .ok_or(ChainViewError)
-            .attach_printable("chain segment size option not found")?;
-
-        let canonical_chain = FullCanonicalChain::initialize(
-            self.chain_store.clone(),
-            starting_block,
-            chain_segment_size,
-        )
-        .await?;
-
-        let segment_size = options_store
-            .get_segment_size()
-            .await
-            .change_context(ChainViewError)
-            .attach_printable("failed to get segment size options")?
-            .ok_or(ChainViewError)
-            .attach_printable("segment size option not found")?;
-
-        let group_size = options_store
-            .get_group_size()
-            .await
-            .change_context(ChainViewError)
-            .attach_printable("failed to get group size options")?
-            .ok_or(ChainViewError)
-            .attach_printable("group size option not found")?;
-
-        let chain_view = ChainView::new(
-            finalized,
-            segmented,
-            grouped,
-            segment_size as u64,
-            group_size as u64,
-            canonical_chain,
-        );
-
         chain_view.record_starting_metrics().await?;
 
         self.tx
             .send(Some(chain_view.clone()))
             .change_context(ChainViewError)?;
 
+        self.save_snapshot(&chain_view).await;
+
         info!("finished initializing chain view");
 
         if ct.is_cancelled() {
@@ -180,6 +132,8 @@ impl ChainViewSyncService {
                 debug!(update = ?update, "chain view sync update");
             }
 
+            let is_finalized_update = matches!(update, IngestionStateUpdate::Finalized(_));
+
             match update {
                 IngestionStateUpdate::StartingBlock(block) => {
                     // The starting block should never be updated.
@@ -192,6 +146,9 @@ impl ChainViewSyncService {
                     chain_view.set_segmented_block(block).await;
                 }
                 IngestionStateUpdate::Grouped(block) => {
+                    // Rewriting the segments updates the grouped block after switching
+                    // to the new layout, so this is the time to reload it.
+                    self.reload_segment_layout(&chain_view).await?;
                     chain_view.set_grouped_block(block).await;
                 }
                 IngestionStateUpdate::Pending(generation) => {
@@ -205,14 +162,286 @@ impl ChainViewSyncService {
             self.tx
                 .send(Some(chain_view.clone()))
                 .change_context(ChainViewError)?;
+
+            // The finalized block changes rarely enough to save the snapshot each time.
+            if is_finalized_update {
+                self.save_snapshot(&chain_view).await;
+            }
         }
 
         if ct.is_cancelled() {
+            self.save_snapshot(&chain_view).await;
             return Ok(());
         }
 
         Err(ChainViewError).attach_printable("etcd sync stream ended unexpectedly")
     }
+
+    /// Returns the chain view stored in the snapshot, updated with the current ingestion state.
+    ///
+    /// Returns `None` if there's no snapshot or if it doesn't match the current
+    /// deployment and segment layout.
+    async fn restore_snapshot(
+        &self,
+        starting_block: u64,
+        ingestion_state_client: &mut IngestionStateClient,
+    ) -> Result<Option<ChainView>, ChainViewError> {
+        let Some(path) = self.snapshot_path.as_ref() else {
+            return Ok(None);
+        };
+
+        let snapshot = match ChainViewSnapshot::load(path).await {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                warn!(error = ?err, "failed to load chain view snapshot");
+                return Ok(None);
+            }
+        };
+
+        if snapshot.starting_block != starting_block {
+            warn!(
+                snapshot_starting_block = snapshot.starting_block,
+                starting_block, "chain view snapshot is for a different deployment"
+            );
+            return Ok(None);
+        }
+
+        // The segments may have been rewritten with a new layout since the snapshot was saved.
+        let layout = match self.get_segment_layout().await {
+            Ok(layout) => layout,
+            Err(err) => {
+                warn!(error = ?err, "failed to get segment layout to validate snapshot");
+                return Ok(None);
+            }
+        };
+
+        let snapshot_layout = StoredLayout {
+            chain_segment_size: snapshot.chain_segment_size as usize,
+            segment: SegmentLayout {
+                generation: snapshot.segment_generation,
+                segment_size: snapshot.segment_size,
+                group_size: snapshot.group_size,
+                fragment_segment_size: snapshot.fragment_segment_size.iter().cloned().collect(),
+            },
+        };
+
+        if snapshot_layout != layout {
+            warn!(
+                snapshot_layout = ?snapshot_layout,
+                layout = ?layout,
+                "chain view snapshot has a different segment layout"
+            );
+            return Ok(None);
+        }
+
+        let chain_view = ChainView::from_snapshot(snapshot, self.chain_store.clone());
+        self.catch_up(&chain_view, ingestion_state_client).await?;
+
+        Ok(Some(chain_view))
+    }
+
+    /// Switch the chain view to the current segment layout, if the segments were rewritten.
+    async fn reload_segment_layout(&self, chain_view: &ChainView) -> Result<(), ChainViewError> {
+        let layout = self.get_segment_layout().await?.segment;
+        let current = chain_view.get_segment_layout().await;
+
+        if layout == current {
+            return Ok(());
+        }
+
+        info!(
+            generation = layout.generation,
+            previous_generation = current.generation,
+            segment_size = layout.segment_size,
+            group_size = layout.group_size,
+            "switching to new segment layout"
+        );
+
+        chain_view.set_segment_layout(layout).await;
+        self.save_snapshot(chain_view).await;
+
+        Ok(())
+    }
+
+    /// Returns the segment layout from the options store.
+    async fn get_segment_layout(&self) -> Result<StoredLayout, ChainViewError> {
+        let mut options_store = self.options_store.clone();
+
+        let chain_segment_size = options_store
+            .get_chain_segment_size()
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to get chain segment size options")?
+            .ok_or(ChainViewError)
+            .attach_printable("chain segment size option not found")?;
+
+        let segment_size = options_store
+            .get_segment_size()
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to get segment size options")?
+            .ok_or(ChainViewError)
+            .attach_printable("segment size option not found")?;
+
+        let group_size = options_store
+            .get_group_size()
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to get group size options")?
+            .ok_or(ChainViewError)
+            .attach_printable("group size option not found")?;
+
+        let fragment_segment_size = options_store
+            .get_fragment_segment_sizes()
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to get fragment segment size options")?
+            .into_iter()
+            .map(|(name, size)| (name, size as u64))
+            .collect();
+
+        let generation = options_store
+            .get_segment_generation()
+            .await
+            .change_context(ChainViewError)
+            .attach_printable("failed to get segment generation")?;
+
+        Ok(StoredLayout {
+            chain_segment_size,
+            segment: SegmentLayout {
+                generation,
+                segment_size: segment_size as u64,
+                group_size: group_size as u64,
+                fragment_segment_size,
+            },
+        })
+    }
+
+    /// Update a chain view restored from a snapshot with the current ingestion state.
+    async fn catch_up(
+        &self,
+        chain_view: &ChainView,
+        ingestion_state_client: &mut IngestionStateClient,
+    ) -> Result<(), ChainViewError> {
+        if let Some(finalized) = ingestion_state_client
+            .get_finalized()
+            .await
+            .change_context(ChainViewError)?
+        {
+            chain_view.set_finalized_block(finalized).await;
+        }
+
+        if let Some(segmented) = ingestion_state_client
+            .get_segmented()
+            .await
+            .change_context(ChainViewError)?
+        {
+            chain_view.set_segmented_block(segmented).await;
+        }
+
+        if let Some(grouped) = ingestion_state_client
+            .get_grouped()
+            .await
+            .change_context(ChainViewError)?
+        {
+            chain_view.set_grouped_block(grouped).await;
+        }
+
+        chain_view.refresh_recent().await
+    }
+
+    async fn save_snapshot(&self, chain_view: &ChainView) {
+        let Some(path) = self.snapshot_path.as_ref() else {
+            return;
+        };
+
+        let snapshot = chain_view.snapshot().await;
+        if let Err(err) = snapshot.save(path).await {
+            warn!(error = ?err, "failed to save chain view snapshot");
+        } else {
+            debug!(path = %path.display(), "saved chain view snapshot");
+        }
+    }
+
+    /// Initialize the chain view from the ingestion state and the chain store.
+    ///
+    /// Returns `None` if cancelled while waiting for ingestion.
+    async fn initialize(
+        &self,
+        starting_block: u64,
+        ingestion_state_client: &mut IngestionStateClient,
+        ct: &CancellationToken,
+    ) -> Result<Option<ChainView>, ChainViewError> {
+        let finalized = loop {
+            if ct.is_cancelled() {
+                return Ok(None);
+            }
+
+            let finalized = ingestion_state_client
+                .get_finalized()
+                .await
+                .change_context(ChainViewError)?;
+
+            if let Some(finalized) = finalized {
+                break finalized;
+            }
+
+            info!(step = "finalized_block", "waiting for ingestion to start");
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        };
+
+        let segmented = ingestion_state_client
+            .get_segmented()
+            .await
+            .change_context(ChainViewError)?;
+
+        let grouped = ingestion_state_client
+            .get_grouped()
+            .await
+            .change_context(ChainViewError)?;
+
+        loop {
+            if ct.is_cancelled() {
+                return Ok(None);
+            }
+
+            let recent = ingestion_state_client
+                .get_ingested()
+                .await
+                .change_context(ChainViewError)?;
+
+            if recent.is_some() {
+                break;
+            }
+
+            info!(step = "recent", "waiting for ingestion to start");
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+
+        if ct.is_cancelled() {
+            return Ok(None);
+        }
+
+        let layout = self.get_segment_layout().await?;
+
+        let canonical_chain = FullCanonicalChain::initialize(
+            self.chain_store.clone(),
+            starting_block,
+            layout.chain_segment_size,
+        )
+        .await?;
+
+        let chain_view = ChainView::new(
+            finalized,
+            segmented,
+            grouped,
+            layout.segment,
+            canonical_chain,
+        );
+
+        Ok(Some(chain_view))
+    }
 }
 
 pub async fn chain_view_sync_loop(
@@ -232,3 +461,184 @@ pub async fn chain_view_sync_loop(
 
     Ok((rx, sync_service))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path, time::Duration};
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        chain::{BlockInfo, CanonicalChainBuilder},
+        chain_store::ChainStore,
+        chain_view::snapshot::{ChainViewSnapshot, SNAPSHOT_VERSION},
+        file_cache::testing::temp_file_cache,
+        ingestion::IngestionStateClient,
+        new_test_cursor,
+        object_store::ObjectStore,
+        options_store::OptionsStore,
+        Hash,
+    };
+
+    use super::ChainViewSyncService;
+
+    async fn save_snapshot(path: &Path) {
+        let mut builder = CanonicalChainBuilder::new();
+        let mut parent = Hash::default();
+        for number in 1_000..1_010 {
+            let cursor = new_test_cursor(number, 0);
+            builder
+                .grow(BlockInfo {
+                    number,
+                    hash: cursor.hash.clone(),
+                    parent,
+                })
+                .unwrap();
+            parent = cursor.hash;
+        }
+
+        ChainViewSnapshot {
+            version: SNAPSHOT_VERSION,
+            starting_block: 1_000,
+            finalized: 1_002,
+            segmented: None,
+            grouped: None,
+            chain_segment_size: 100,
+            segment_generation: 0,
+            segment_size: 10,
+            group_size: 5,
+            fragment_segment_size: Vec::default(),
+            recent: builder.current_segment().unwrap(),
+        }
+        .save(path)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_snapshot() {
+        let (_cache_dir, file_cache) = temp_file_cache().await.unwrap();
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let snapshot_path = snapshot_dir.path().join("chain_view");
+
+        let mut ingestion_state_client = IngestionStateClient::new_in_memory();
+        let mut options_store = OptionsStore::new_in_memory();
+        options_store.set_chain_segment_size(100).await.unwrap();
+        options_store.set_segment_size(10).await.unwrap();
+        options_store.set_group_size(5).await.unwrap();
+
+        let (tx, _rx) = tokio::sync::watch::channel(None);
+        let service = ChainViewSyncService {
+            tx,
+            ingestion_state_client: ingestion_state_client.clone(),
+            options_store: options_store.clone(),
+            chain_store: ChainStore::new(
+                ObjectStore::new_in_memory(Default::default()),
+                file_cache,
+            ),
+            snapshot_path: Some(snapshot_path.clone()),
+        };
+
+        save_snapshot(&snapshot_path).await;
+
+        ingestion_state_client.put_finalized(1_005).await.unwrap();
+
+        // Snapshots of another deployment are ignored.
+        assert!(service
+            .restore_snapshot(900, &mut ingestion_state_client)
+            .await
+            .unwrap()
+            .is_none());
+
+        // The restored chain view is up to date with ingestion.
+        let chain_view = service
+            .restore_snapshot(1_000, &mut ingestion_state_client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(chain_view.get_segment_size().await, 10);
+        assert_eq!(chain_view.snapshot().await.finalized, 1_005);
+
+        // Snapshots with a different layout than the segments are ignored.
+        options_store.set_segment_size(20).await.unwrap();
+        assert!(service
+            .restore_snapshot(1_000, &mut ingestion_state_client)
+            .await
+            .unwrap()
+            .is_none());
+
+        options_store.set_segment_size(10).await.unwrap();
+        options_store
+            .set_fragment_segment_size("log", 1)
+            .await
+            .unwrap();
+        assert!(service
+            .restore_snapshot(1_000, &mut ingestion_state_client)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reload_segment_layout() {
+        let (_cache_dir, file_cache) = temp_file_cache().await.unwrap();
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let snapshot_path = snapshot_dir.path().join("chain_view");
+
+        let mut ingestion_state_client = IngestionStateClient::new_in_memory();
+        let mut options_store = OptionsStore::new_in_memory();
+        options_store.set_chain_segment_size(100).await.unwrap();
+        options_store.set_segment_size(10).await.unwrap();
+        options_store.set_group_size(5).await.unwrap();
+        ingestion_state_client
+            .put_starting_block(1_000)
+            .await
+            .unwrap();
+
+        save_snapshot(&snapshot_path).await;
+
+        let (tx, mut rx) = tokio::sync::watch::channel(None);
+        let service = ChainViewSyncService {
+            tx,
+            ingestion_state_client: ingestion_state_client.clone(),
+            options_store: options_store.clone(),
+            chain_store: ChainStore::new(
+                ObjectStore::new_in_memory(Default::default()),
+                file_cache,
+            ),
+            snapshot_path: Some(snapshot_path.clone()),
+        };
+
+        let ct = CancellationToken::new();
+        let service_handle = tokio::spawn(service.start(ct.clone()));
+
+        let chain_view = rx.wait_for(Option::is_some).await.unwrap().clone().unwrap();
+        let layout = chain_view.get_segment_layout().await;
+        assert_eq!(layout.generation, 0);
+        assert_eq!(layout.segment_size, 10);
+
+        // The segments are rewritten while the service is running.
+        options_store
+            .swap_segment_layout(1, 20, 5, &HashMap::from([("log".to_string(), 5)]))
+            .await
+            .unwrap();
+
+        // The service may not be watching the ingestion state yet, so keep updating it.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while chain_view.get_segment_layout().await.generation != 1 {
+                ingestion_state_client.put_grouped(1_099).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let layout = chain_view.get_segment_layout().await;
+        assert_eq!(layout.segment_size, 20);
+        assert_eq!(layout.group_size, 5);
+        assert_eq!(layout.fragment_segment_size.get("log"), Some(&5));
+
+        ct.cancel();
+        service_handle.await.unwrap().unwrap();
+    }
+}
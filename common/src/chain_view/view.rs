@@ -1,14 +1,15 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use error_stack::Result;
 use tokio::sync::{Notify, RwLock};
 
-use crate::Cursor;
+use crate::{chain_store::ChainStore, Cursor};
 
 use super::{
     error::ChainViewError,
     full::{FullCanonicalChain, NextCursor, ValidatedCursor},
     metrics::ChainViewMetrics,
+    snapshot::{ChainViewSnapshot, SNAPSHOT_VERSION},
     CanonicalCursor,
 };
 
@@ -16,14 +17,27 @@ use super::{
 #[derive(Clone)]
 pub struct ChainView(Arc<RwLock<ChainViewInner>>);
 
+/// How segments and groups are laid out in the object store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentLayout {
+    /// Segments and groups are read from this generation.
+    pub generation: u64,
+    pub segment_size: u64,
+    pub group_size: u64,
+    /// The segment size of the fragments that don't use the default segment size.
+    pub fragment_segment_size: HashMap<String, u64>,
+}
+
 pub(crate) struct ChainViewInner {
     finalized: u64,
     pending_generation: Option<u64>,
     segmented: Option<u64>,
     grouped: Option<u64>,
     canonical: FullCanonicalChain,
+    segment_generation: u64,
     segment_size: u64,
     group_size: u64,
+    fragment_segment_size: HashMap<String, u64>,
     pending_notify: Arc<Notify>,
     head_notify: Arc<Notify>,
     finalized_notify: Arc<Notify>,
@@ -36,8 +50,7 @@ impl ChainView {
         finalized: u64,
         segmented: Option<u64>,
         grouped: Option<u64>,
-        segment_size: u64,
-        group_size: u64,
+        layout: SegmentLayout,
         canonical: FullCanonicalChain,
     ) -> Self {
         let inner = ChainViewInner {
@@ -46,8 +59,10 @@ impl ChainView {
             pending_generation: None,
             grouped,
             canonical,
-            segment_size,
-            group_size,
+            segment_generation: layout.generation,
+            segment_size: layout.segment_size,
+            group_size: layout.group_size,
+            fragment_segment_size: layout.fragment_segment_size,
             pending_notify: Arc::new(Notify::new()),
             head_notify: Arc::new(Notify::new()),
             finalized_notify: Arc::new(Notify::new()),
@@ -58,6 +73,104 @@ impl ChainView {
         Self(Arc::new(RwLock::new(inner)))
     }
 
+    /// Restore the chain view from a snapshot.
+    pub(crate) fn from_snapshot(snapshot: ChainViewSnapshot, store: ChainStore) -> Self {
+        let canonical = FullCanonicalChain::from_recent(
+            store,
+            snapshot.starting_block,
+            snapshot.chain_segment_size as usize,
+            snapshot.recent,
+        );
+
+        Self::new(
+            snapshot.finalized,
+            snapshot.segmented,
+            snapshot.grouped,
+            SegmentLayout {
+                generation: snapshot.segment_generation,
+                segment_size: snapshot.segment_size,
+                group_size: snapshot.group_size,
+                fragment_segment_size: snapshot.fragment_segment_size.into_iter().collect(),
+            },
+            canonical,
+        )
+    }
+
+    /// Returns a chain view of the blocks from `starting_block` to `finalized`.
+    #[cfg(test)]
+    pub(crate) fn new_for_testing(
+        store: ChainStore,
+        starting_block: u64,
+        finalized: u64,
+        segmented: Option<u64>,
+        grouped: Option<u64>,
+        layout: SegmentLayout,
+    ) -> Self {
+        use crate::{
+            chain::{BlockInfo, CanonicalChainBuilder},
+            new_test_cursor, Hash,
+        };
+
+        let mut builder = CanonicalChainBuilder::new();
+        let mut parent = Hash::default();
+        for number in starting_block..=finalized {
+            let cursor = new_test_cursor(number, 0);
+            builder
+                .grow(BlockInfo {
+                    number,
+                    hash: cursor.hash.clone(),
+                    parent,
+                })
+                .expect("failed to grow test chain");
+            parent = cursor.hash;
+        }
+
+        let recent = builder
+            .current_segment()
+            .expect("failed to build test chain segment");
+        let canonical = FullCanonicalChain::from_recent(store, starting_block, 100, recent);
+
+        Self::new(finalized, segmented, grouped, layout, canonical)
+    }
+
+    /// Returns a snapshot of the chain view, used to restore it after a restart.
+    pub(crate) async fn snapshot(&self) -> ChainViewSnapshot {
+        let inner = self.0.read().await;
+        ChainViewSnapshot {
+            version: SNAPSHOT_VERSION,
+            starting_block: inner.canonical.starting_block,
+            finalized: inner.finalized,
+            segmented: inner.segmented,
+            grouped: inner.grouped,
+            chain_segment_size: inner.canonical.chain_segment_size() as u64,
+            segment_generation: inner.segment_generation,
+            segment_size: inner.segment_size,
+            group_size: inner.group_size,
+            fragment_segment_size: inner
+                .fragment_segment_size
+                .iter()
+                .map(|(name, size)| (name.clone(), *size))
+                .collect(),
+            recent: inner.canonical.recent().clone(),
+        }
+    }
+
+    /// Returns the segment layout, read at once so that its values are consistent
+    /// while the layout changes.
+    pub async fn get_segment_layout(&self) -> SegmentLayout {
+        let inner = self.0.read().await;
+        SegmentLayout {
+            generation: inner.segment_generation,
+            segment_size: inner.segment_size,
+            group_size: inner.group_size,
+            fragment_segment_size: inner.fragment_segment_size.clone(),
+        }
+    }
+
+    pub async fn get_segment_generation(&self) -> u64 {
+        self.0.read().await.segment_generation
+    }
+
     pub async fn get_segment_size(&self) -> u64 {
         self.0.read().await.segment_size
     }
@@ -66,6 +179,11 @@ impl ChainView {
         self.0.read().await.group_size
     }
 
+    /// Returns the segment size of the fragments that don't use the default segment size.
+    pub async fn get_fragment_segment_size(&self) -> HashMap<String, u64> {
+        self.0.read().await.fragment_segment_size.clone()
+    }
+
     pub async fn get_segment_start_block(&self, block: u64) -> u64 {
         let inner = self.0.read().await;
         inner.get_segment_start_block(block)
@@ -234,6 +352,15 @@ impl ChainView {
         inner.grouped = Some(block);
     }
 
+    /// Switch to the segments and groups rewritten with a new layout.
+    pub(crate) async fn set_segment_layout(&self, layout: SegmentLayout) {
+        let mut inner = self.0.write().await;
+        inner.segment_generation = layout.generation;
+        inner.segment_size = layout.segment_size;
+        inner.group_size = layout.group_size;
+        inner.fragment_segment_size = layout.fragment_segment_size;
+    }
+
     pub(crate) async fn refresh_recent(&self) -> Result<(), ChainViewError> {
         let mut inner = self.0.write().await;
 
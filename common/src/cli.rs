@@ -1,15 +1,18 @@
-use std::time::Duration;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use apibara_etcd::{AuthOptions, EtcdClient, EtcdClientError, EtcdClientOptions};
+use apibara_etcd::{AuthOptions, EtcdClient, EtcdClientError, EtcdClientOptions, TlsOptions};
 use aws_config::{meta::region::RegionProviderChain, Region};
 use clap::Args;
-use error_stack::Result;
+use error_stack::{Result, ResultExt};
 
 use crate::{
     compaction::CompactionArgs,
     file_cache::FileCacheArgs,
     ingestion::IngestionArgs,
-    object_store::{ObjectStore, ObjectStoreOptions},
+    object_store::{CompressionCodec, CompressionOptions, ObjectStore, ObjectStoreOptions},
     server::ServerArgs,
 };
 
@@ -27,6 +30,17 @@ pub struct StartArgs {
     pub server: ServerArgs,
     #[clap(flatten)]
     pub cache: FileCacheArgs,
+    #[clap(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct LogArgs {
+    /// Filter for the logs printed to stdout, with the same syntax as `RUST_LOG`.
+    ///
+    /// Reloaded on SIGHUP.
+    #[arg(long = "log.filter", env = "RUST_LOG", default_value = "info")]
+    pub log_filter: String,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -43,6 +57,25 @@ pub struct ObjectStoreArgs {
     /// The S3 region.
     #[arg(long = "s3.region", env = "DNA_S3_REGION")]
     pub s3_region: Option<String>,
+    /// The compression codec used for new blocks and segments.
+    ///
+    /// Existing data is always readable, regardless of this value.
+    #[arg(
+        long = "s3.compression",
+        env = "DNA_S3_COMPRESSION",
+        value_enum,
+        default_value = "zstd"
+    )]
+    pub s3_compression: CompressionCodec,
+    /// The compression level.
+    ///
+    /// Higher levels trade CPU time for smaller objects. Use 0 for the codec's default level.
+    #[arg(
+        long = "s3.compression-level",
+        env = "DNA_S3_COMPRESSION_LEVEL",
+        default_value = "0"
+    )]
+    pub s3_compression_level: i32,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -71,6 +104,26 @@ pub struct EtcdArgs {
         default_value = "300"
     )]
     pub etcd_auth_token_ttl: u64,
+    /// Path to the CA certificate used to verify the etcd server, in PEM format.
+    #[arg(long = "etcd.tls-ca-cert", env = "DNA_ETCD_TLS_CA_CERT")]
+    pub etcd_tls_ca_cert: Option<PathBuf>,
+    /// Path to the client certificate, in PEM format.
+    #[arg(
+        long = "etcd.tls-cert",
+        env = "DNA_ETCD_TLS_CERT",
+        requires = "etcd_tls_key"
+    )]
+    pub etcd_tls_cert: Option<PathBuf>,
+    /// Path to the client certificate key, in PEM format.
+    #[arg(
+        long = "etcd.tls-key",
+        env = "DNA_ETCD_TLS_KEY",
+        requires = "etcd_tls_cert"
+    )]
+    pub etcd_tls_key: Option<PathBuf>,
+    /// Domain name used to verify the etcd server certificate.
+    #[arg(long = "etcd.tls-domain", env = "DNA_ETCD_TLS_DOMAIN")]
+    pub etcd_tls_domain: Option<String>,
 }
 
 impl ObjectStoreArgs {
@@ -97,6 +150,10 @@ impl ObjectStoreArgs {
         let options = ObjectStoreOptions {
             bucket: self.s3_bucket,
             prefix: self.s3_prefix,
+            compression: CompressionOptions {
+                codec: self.s3_compression,
+                level: self.s3_compression_level,
+            },
         };
 
         ObjectStore::new_from_config(s3_config, options)
@@ -105,6 +162,8 @@ impl ObjectStoreArgs {
 
 impl EtcdArgs {
     pub async fn into_etcd_client(self) -> Result<EtcdClient, EtcdClientError> {
+        let tls = self.tls_options()?;
+
         let auth = if let (Some(user), Some(password)) = (self.etcd_user, self.etcd_password) {
             let token_ttl = Duration::from_secs(self.etcd_auth_token_ttl);
             Some(AuthOptions {
@@ -119,8 +178,107 @@ impl EtcdArgs {
         let options = EtcdClientOptions {
             prefix: self.etcd_prefix,
             auth,
+            tls,
         };
 
         EtcdClient::connect(self.etcd_endpoints, options).await
     }
+
+    /// Returns the TLS options, reading the certificates from disk.
+    fn tls_options(&self) -> Result<Option<TlsOptions>, EtcdClientError> {
+        if self.etcd_tls_ca_cert.is_none()
+            && self.etcd_tls_cert.is_none()
+            && self.etcd_tls_domain.is_none()
+        {
+            return Ok(None);
+        }
+
+        let ca_certificate = self
+            .etcd_tls_ca_cert
+            .as_ref()
+            .map(|path| read_pem_file(path))
+            .transpose()?;
+
+        let identity = match (self.etcd_tls_cert.as_ref(), self.etcd_tls_key.as_ref()) {
+            (Some(cert), Some(key)) => Some((read_pem_file(cert)?, read_pem_file(key)?)),
+            _ => None,
+        };
+
+        Ok(Some(TlsOptions {
+            ca_certificate,
+            identity,
+            domain_name: self.etcd_tls_domain.clone(),
+        }))
+    }
+}
+
+fn read_pem_file(path: &Path) -> Result<Vec<u8>, EtcdClientError> {
+    std::fs::read(path)
+        .change_context(EtcdClientError)
+        .attach_printable("failed to read TLS certificate")
+        .attach_printable_lazy(|| format!("path: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EtcdArgs;
+
+    fn etcd_args() -> EtcdArgs {
+        EtcdArgs {
+            etcd_endpoints: vec!["https://localhost:2379".to_string()],
+            etcd_prefix: None,
+            etcd_user: None,
+            etcd_password: None,
+            etcd_auth_token_ttl: 300,
+            etcd_tls_ca_cert: None,
+            etcd_tls_cert: None,
+            etcd_tls_key: None,
+            etcd_tls_domain: None,
+        }
+    }
+
+    #[test]
+    fn test_tls_options_disabled() {
+        assert!(etcd_args().tls_options().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_tls_options_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        let cert_path = dir.path().join("client.pem");
+        let key_path = dir.path().join("client-key.pem");
+        std::fs::write(&ca_path, b"ca").unwrap();
+        std::fs::write(&cert_path, b"cert").unwrap();
+        std::fs::write(&key_path, b"key").unwrap();
+
+        let args = EtcdArgs {
+            etcd_tls_ca_cert: Some(ca_path),
+            etcd_tls_cert: Some(cert_path),
+            etcd_tls_key: Some(key_path),
+            etcd_tls_domain: Some("etcd.local".to_string()),
+            ..etcd_args()
+        };
+
+        let tls = args.tls_options().unwrap().unwrap();
+        assert_eq!(tls.ca_certificate.as_deref(), Some(b"ca".as_slice()));
+        assert_eq!(tls.identity, Some((b"cert".to_vec(), b"key".to_vec())));
+        assert_eq!(tls.domain_name.as_deref(), Some("etcd.local"));
+    }
+
+    #[test]
+    fn test_tls_options_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.pem");
+
+        let args = EtcdArgs {
+            etcd_tls_ca_cert: Some(missing.clone()),
+            ..etcd_args()
+        };
+
+        let err = args.tls_options().unwrap_err();
+        let message = format!("{err:?}");
+        assert!(message.contains("failed to read TLS certificate"));
+        assert!(message.contains(&missing.display().to_string()));
+    }
 }
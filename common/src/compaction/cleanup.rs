@@ -0,0 +1,147 @@
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::{
+    block_store::BlockStoreWriter, chain_view::ChainView, ingestion::IngestionStateClient,
+};
+
+use super::{metrics::CompactionMetrics, CompactionError};
+
+const MAX_BLOCKS_PER_CLEANUP: u64 = 100;
+const MAX_CONCURRENT_BLOCK_DELETES: usize = 16;
+
+/// Delete block objects once they're included in a segment.
+///
+/// The most recent deleted block is stored in the ingestion state so that
+/// cleanup resumes where it left off after a restart.
+pub struct BlockCleanupService {
+    block_retention: u64,
+    chain_view: ChainView,
+    block_store_writer: BlockStoreWriter,
+    state_client: IngestionStateClient,
+    metrics: CompactionMetrics,
+}
+
+impl BlockCleanupService {
+    pub fn new(
+        block_retention: u64,
+        chain_view: ChainView,
+        block_store_writer: BlockStoreWriter,
+        state_client: IngestionStateClient,
+        metrics: CompactionMetrics,
+    ) -> Self {
+        Self {
+            block_retention,
+            chain_view,
+            block_store_writer,
+            state_client,
+            metrics,
+        }
+    }
+
+    pub async fn start(mut self, ct: CancellationToken) -> Result<(), CompactionError> {
+        loop {
+            if ct.is_cancelled() {
+                return Ok(());
+            }
+
+            let first_block = if let Some(pruned) = self
+                .state_client
+                .get_pruned()
+                .await
+                .change_context(CompactionError)
+                .attach_printable("failed to get pruned block")?
+            {
+                pruned + 1
+            } else {
+                self.chain_view
+                    .get_starting_cursor()
+                    .await
+                    .change_context(CompactionError)?
+                    .number
+            };
+
+            let segmented = self
+                .chain_view
+                .get_segmented_cursor()
+                .await
+                .change_context(CompactionError)?;
+            let finalized = self
+                .chain_view
+                .get_finalized_cursor()
+                .await
+                .change_context(CompactionError)?;
+
+            let last_prunable = segmented.and_then(|segmented| {
+                last_prunable_block(segmented.number, finalized.number, self.block_retention)
+            });
+
+            match last_prunable {
+                Some(last_prunable) if first_block <= last_prunable => {
+                    let last_block =
+                        u64::min(last_prunable, first_block + MAX_BLOCKS_PER_CLEANUP - 1);
+                    self.delete_blocks(first_block, last_block).await?;
+                }
+                _ => {
+                    info!("compaction cleanup waiting for segmented change");
+                    tokio::select! {
+                        _ = ct.cancelled() => return Ok(()),
+                        _ = self.chain_view.segmented_changed() => {}
+                        _ = self.chain_view.finalized_changed() => {}
+                    }
+                }
+            }
+        }
+    }
+
+    async fn delete_blocks(
+        &mut self,
+        first_block: u64,
+        last_block: u64,
+    ) -> Result<(), CompactionError> {
+        info!(first_block, last_block, "deleting segmented blocks");
+
+        let deleted = futures::stream::iter(first_block..=last_block)
+            .map(|block_number| self.block_store_writer.delete_blocks(block_number))
+            .buffer_unordered(MAX_CONCURRENT_BLOCK_DELETES)
+            .try_collect::<Vec<_>>()
+            .await
+            .change_context(CompactionError)
+            .attach_printable("failed to delete blocks")?;
+
+        debug!(
+            objects = deleted.iter().sum::<usize>(),
+            "compaction: deleted block objects"
+        );
+
+        self.state_client
+            .put_pruned(last_block)
+            .await
+            .change_context(CompactionError)
+            .attach_printable("failed to put pruned block")?;
+
+        self.metrics.pruned.record(last_block, &[]);
+
+        Ok(())
+    }
+}
+
+/// Returns the most recent block whose block objects can be deleted.
+fn last_prunable_block(segmented: u64, finalized: u64, block_retention: u64) -> Option<u64> {
+    let retained = finalized.checked_sub(block_retention)?;
+    Some(u64::min(segmented, retained))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::last_prunable_block;
+
+    #[test]
+    fn test_last_prunable_block() {
+        assert_eq!(last_prunable_block(999, 5_000, 100), Some(999));
+        assert_eq!(last_prunable_block(999, 1_050, 100), Some(950));
+        assert_eq!(last_prunable_block(999, 50, 100), None);
+    }
+}
@@ -19,6 +19,26 @@ pub struct CompactionArgs {
         default_value = "100"
     )]
     pub compaction_group_size: usize,
+    /// Use a different segment size for some fragments, as `name=size` pairs.
+    ///
+    /// The size must divide the segment size. Use smaller segments for large
+    /// fragments so that streams only download the blocks they need.
+    #[clap(
+        long = "compaction.fragment-segment-size",
+        env = "DNA_COMPACTION_FRAGMENT_SEGMENT_SIZE",
+        value_delimiter = ',',
+        value_parser = parse_fragment_segment_size
+    )]
+    pub compaction_fragment_segment_size: Vec<(String, usize)>,
+    /// Delete block objects once they're segmented and this many blocks behind
+    /// the finalized block.
+    ///
+    /// Block objects are never deleted if not set.
+    #[clap(
+        long = "compaction.block-retention",
+        env = "DNA_COMPACTION_BLOCK_RETENTION"
+    )]
+    pub compaction_block_retention: Option<u64>,
 }
 
 impl CompactionArgs {
@@ -26,6 +46,24 @@ impl CompactionArgs {
         super::CompactionServiceOptions {
             segment_size: self.compaction_segment_size,
             group_size: self.compaction_group_size,
+            fragment_segment_size: self
+                .compaction_fragment_segment_size
+                .iter()
+                .cloned()
+                .collect(),
+            block_retention: self.compaction_block_retention,
         }
     }
 }
+
+pub(crate) fn parse_fragment_segment_size(value: &str) -> Result<(String, usize), String> {
+    let Some((name, size)) = value.split_once('=') else {
+        return Err(format!("expected name=size, got {value}"));
+    };
+
+    let size = size
+        .parse::<usize>()
+        .map_err(|err| format!("invalid segment size for {name}: {err}"))?;
+
+    Ok((name.to_string(), size))
+}
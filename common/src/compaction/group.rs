@@ -9,6 +9,7 @@ use crate::{
     block_store::{BlockStoreWriter, UncachedBlockStoreReader},
     chain_view::{ChainView, NextCursor},
     compaction::group_builder::SegmentGroupBuilder,
+    dataset_manifest::DatasetManifestStore,
     fragment::IndexGroupFragment,
     ingestion::IngestionStateClient,
     segment::Segment,
@@ -26,10 +27,12 @@ pub struct SegmentGroupService {
     block_store_reader: UncachedBlockStoreReader,
     block_store_writer: BlockStoreWriter,
     state_client: IngestionStateClient,
+    manifest_store: DatasetManifestStore,
     metrics: CompactionMetrics,
 }
 
 impl SegmentGroupService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         segment_size: usize,
         group_size: usize,
@@ -37,6 +40,7 @@ impl SegmentGroupService {
         block_store_reader: UncachedBlockStoreReader,
         block_store_writer: BlockStoreWriter,
         state_client: IngestionStateClient,
+        manifest_store: DatasetManifestStore,
         metrics: CompactionMetrics,
     ) -> Self {
         Self {
@@ -46,6 +50,7 @@ impl SegmentGroupService {
             block_store_reader,
             block_store_writer,
             state_client,
+            manifest_store,
             metrics,
         }
     }
@@ -245,6 +250,12 @@ impl SegmentGroupService {
             .await
             .change_context(CompactionError)?;
 
+        self.manifest_store
+            .update(|manifest| manifest.grouped = Some(last_block_in_group))
+            .await
+            .change_context(CompactionError)
+            .attach_printable("failed to update dataset manifest")?;
+
         Ok(())
     }
 }
@@ -11,12 +11,20 @@ use crate::{
 
 use super::CompactionError;
 
+/// Maximum number of keys of an index in a segment group.
+///
+/// Indexes on values that are almost unique, like transaction values, would make the
+/// group as large as the data. They are stored as empty indexes instead, so that their
+/// conditions match all blocks in the group and only the block indexes filter the data.
+const MAX_GROUP_INDEX_KEYS: usize = 100_000;
+
 #[derive(Debug)]
 pub struct SegmentGroupBuilder {
     segment_size: u64,
     pub segment_count: usize,
     pub block_range: Option<(Cursor, u64)>,
-    block_indexes: BTreeMap<FragmentId, BTreeMap<IndexId, index::BitmapIndexBuilder>>,
+    /// The group indexes. `None` if the index has too many keys.
+    block_indexes: BTreeMap<FragmentId, BTreeMap<IndexId, Option<index::BitmapIndexBuilder>>>,
 }
 
 impl SegmentGroupBuilder {
@@ -52,7 +60,13 @@ impl SegmentGroupBuilder {
                     .or_default();
 
                 for index in index_fragment.indexes.iter() {
-                    let block_index = block_index_fragment.entry(index.index_id).or_default();
+                    let entry = block_index_fragment
+                        .entry(index.index_id)
+                        .or_insert_with(|| Some(Default::default()));
+                    let Some(block_index) = entry.as_mut() else {
+                        continue;
+                    };
+
                     match &index.index {
                         index::Index::Bitmap(bitmap_index) => {
                             for key in bitmap_index.keys() {
@@ -61,6 +75,10 @@ impl SegmentGroupBuilder {
                         }
                         index::Index::Empty => {}
                     }
+
+                    if block_index.len() > MAX_GROUP_INDEX_KEYS {
+                        *entry = None;
+                    }
                 }
             }
         }
@@ -84,11 +102,14 @@ impl SegmentGroupBuilder {
             let fragment_indexes = fragment_indexes
                 .into_iter()
                 .map(|(index_id, index_builder)| {
-                    let index = index_builder.build().change_context(CompactionError)?;
-                    Ok(fragment::Index {
-                        index_id,
-                        index: index.into(),
-                    })
+                    let index = match index_builder {
+                        Some(index_builder) => index_builder
+                            .build()
+                            .change_context(CompactionError)?
+                            .into(),
+                        None => index::Index::Empty,
+                    };
+                    Ok(fragment::Index { index_id, index })
                 })
                 .collect::<Result<Vec<_>, _>>()?;
 
@@ -107,3 +128,157 @@ impl SegmentGroupBuilder {
         Ok(SegmentGroup { first_block, index })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use roaring::RoaringBitmap;
+
+    use crate::{
+        fragment::{ArchivedIndexFragment, Index, IndexFragment, IndexGroupFragment},
+        index::{self, BitmapIndexBuilder, ScalarValue},
+        query::{Condition, ConditionKey, Filter},
+        segment::{FragmentData, Segment},
+        Cursor,
+    };
+
+    use super::{SegmentGroupBuilder, MAX_GROUP_INDEX_KEYS};
+
+    fn block_index(block_number: u64, keys: &[u8]) -> FragmentData<IndexGroupFragment> {
+        block_index_with_keys(
+            block_number,
+            keys.iter().map(|key| ScalarValue::Uint8(*key)),
+        )
+    }
+
+    fn block_index_with_keys(
+        block_number: u64,
+        keys: impl IntoIterator<Item = ScalarValue>,
+    ) -> FragmentData<IndexGroupFragment> {
+        let mut builder = BitmapIndexBuilder::default();
+        for key in keys {
+            builder.insert(key, 0);
+        }
+
+        FragmentData {
+            cursor: Cursor::new_finalized(block_number),
+            data: IndexGroupFragment {
+                indexes: vec![IndexFragment {
+                    fragment_id: 2,
+                    range_start: 0,
+                    range_len: 1,
+                    indexes: vec![Index {
+                        index_id: 0,
+                        index: builder.build().unwrap().into(),
+                    }],
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_group_index_aggregates_block_indexes() {
+        let mut builder = SegmentGroupBuilder::new(2);
+
+        builder
+            .add_segment(&Segment {
+                first_block: Cursor::new_finalized(0),
+                data: vec![block_index(0, &[1]), block_index(1, &[])],
+            })
+            .unwrap();
+        builder
+            .add_segment(&Segment {
+                first_block: Cursor::new_finalized(2),
+                data: vec![block_index(2, &[]), block_index(3, &[1, 2])],
+            })
+            .unwrap();
+
+        let group = builder.build().unwrap();
+        assert_eq!(group.first_block, Cursor::new_finalized(0));
+        assert_eq!(group.index.indexes.len(), 1);
+
+        let fragment_index = &group.index.indexes[0];
+        assert_eq!(fragment_index.fragment_id, 2);
+        assert_eq!(fragment_index.range_start, 0);
+        assert_eq!(fragment_index.range_len, 4);
+
+        let index::Index::Bitmap(bitmap_index) = &fragment_index.indexes[0].index else {
+            panic!("expected bitmap index");
+        };
+
+        let blocks = bitmap_index
+            .iter()
+            .map(|(key, value)| {
+                let bitmap = RoaringBitmap::deserialize_from(value.as_slice()).unwrap();
+                (key.clone(), bitmap.iter().collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            blocks,
+            vec![
+                (ScalarValue::Uint8(1), vec![0, 3]),
+                (ScalarValue::Uint8(2), vec![3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_index_with_too_many_keys() {
+        let mut builder = SegmentGroupBuilder::new(2);
+
+        // Every block has unique keys, like transaction values.
+        let keys_per_block = MAX_GROUP_INDEX_KEYS as u32 / 2 + 1;
+        builder
+            .add_segment(&Segment {
+                first_block: Cursor::new_finalized(0),
+                data: vec![
+                    block_index_with_keys(0, (0..keys_per_block).map(ScalarValue::Uint32)),
+                    block_index_with_keys(
+                        1,
+                        (keys_per_block..2 * keys_per_block).map(ScalarValue::Uint32),
+                    ),
+                ],
+            })
+            .unwrap();
+        builder
+            .add_segment(&Segment {
+                first_block: Cursor::new_finalized(2),
+                data: vec![block_index(2, &[1]), block_index(3, &[])],
+            })
+            .unwrap();
+
+        let group = builder.build().unwrap();
+        let fragment_index = &group.index.indexes[0];
+        assert_eq!(fragment_index.indexes.len(), 1);
+        assert!(matches!(
+            fragment_index.indexes[0].index,
+            index::Index::Empty
+        ));
+
+        // Conditions on the index don't exclude any block of the group.
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(fragment_index).unwrap();
+        let fragment_index =
+            rkyv::access::<ArchivedIndexFragment, rkyv::rancor::Error>(&bytes).unwrap();
+        let filter = Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![Condition {
+                index_id: 0,
+                key: ConditionKey::Range {
+                    start: ScalarValue::Uint32(10),
+                    end: ScalarValue::Uint32(20),
+                },
+            }],
+            joins: Vec::default(),
+            fields: None,
+        };
+        assert_eq!(
+            filter
+                .filter(fragment_index)
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+mod cleanup;
 mod cli;
 mod error;
 mod group;
@@ -7,13 +8,25 @@ mod segment;
 mod segment_builder;
 mod service;
 
+use std::sync::Arc;
+
 use apibara_etcd::{EtcdClient, LockOptions};
 use error_stack::{Result, ResultExt};
 use metrics::CompactionMetrics;
+use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
-use crate::{chain_view::ChainView, object_store::ObjectStore, options_store::OptionsStore};
+use crate::{
+    chain_view::ChainView,
+    fragment::{INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_NAME},
+    object_store::ObjectStore,
+    options_store::OptionsStore,
+};
+
+pub(crate) use self::cli::parse_fragment_segment_size;
+pub(crate) use self::group_builder::SegmentGroupBuilder;
+pub(crate) use self::segment_builder::SegmentBuilder;
 
 pub use self::cli::CompactionArgs;
 pub use self::error::CompactionError;
@@ -24,10 +37,13 @@ pub async fn compaction_service_loop(
     object_store: ObjectStore,
     chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
     options: CompactionServiceOptions,
+    trigger: Arc<Notify>,
     ct: CancellationToken,
 ) -> Result<(), CompactionError> {
     use apibara_observability::KeyValue;
 
+    validate_fragment_segment_size(&options)?;
+
     let mut lock_client = etcd_client.lock_client(LockOptions::default());
 
     let metrics = CompactionMetrics::default();
@@ -52,12 +68,13 @@ pub async fn compaction_service_loop(
         // Load options from etcd and check if they match the current options.
         let mut options_store = OptionsStore::new(&etcd_client);
 
-        if let Some(segment_size) = options_store
+        let stored_segment_size = options_store
             .get_segment_size()
             .await
             .change_context(CompactionError)
-            .attach_printable("failed to get segment size options")?
-        {
+            .attach_printable("failed to get segment size options")?;
+
+        if let Some(segment_size) = stored_segment_size {
             if segment_size != options.segment_size {
                 return Err(CompactionError)
                     .attach_printable("segment size changed")
@@ -94,13 +111,53 @@ pub async fn compaction_service_loop(
                 .attach_printable("failed to set group size options")?;
         }
 
+        // Existing segments use the stored fragment segment sizes, so they can
+        // only be set when the dataset is new.
+        if stored_segment_size.is_some() {
+            let fragment_segment_size = options_store
+                .get_fragment_segment_sizes()
+                .await
+                .change_context(CompactionError)
+                .attach_printable("failed to get fragment segment size options")?;
+
+            if fragment_segment_size != options.fragment_segment_size {
+                return Err(CompactionError)
+                    .attach_printable("fragment segment size changed")
+                    .attach_printable_lazy(|| {
+                        format!("stored fragment segment size: {:?}", fragment_segment_size)
+                    })
+                    .attach_printable_lazy(|| {
+                        format!(
+                            "new fragment segment size: {:?}",
+                            options.fragment_segment_size
+                        )
+                    });
+            }
+        } else {
+            for (name, size) in options.fragment_segment_size.iter() {
+                options_store
+                    .set_fragment_segment_size(name, *size)
+                    .await
+                    .change_context(CompactionError)
+                    .attach_printable("failed to set fragment segment size options")?;
+            }
+        }
+
+        // New segments are added to the generation written by the last resegment.
+        let segment_generation = options_store
+            .get_segment_generation()
+            .await
+            .change_context(CompactionError)?;
+
         let compaction_service = CompactionService::new(
             etcd_client.clone(),
             object_store.clone(),
             chain_view.clone(),
             options.clone(),
+            trigger.clone(),
             metrics.clone(),
-        );
+        )
+        .with_segment_generation(segment_generation);
 
         match compaction_service.start(&mut lock, ct.clone()).await {
             Ok(_) => {
@@ -126,3 +183,27 @@ pub async fn compaction_service_loop(
 
     Ok(())
 }
+
+pub(crate) fn validate_fragment_segment_size(
+    options: &CompactionServiceOptions,
+) -> Result<(), CompactionError> {
+    for (name, size) in options.fragment_segment_size.iter() {
+        // The group builder and the data stream expect the index and join
+        // segments to cover the whole segment.
+        if name == INDEX_FRAGMENT_NAME || name == JOIN_FRAGMENT_NAME {
+            return Err(CompactionError)
+                .attach_printable("fragment segment size not supported")
+                .attach_printable_lazy(|| format!("fragment: {name}"));
+        }
+
+        if *size == 0 || options.segment_size % size != 0 {
+            return Err(CompactionError)
+                .attach_printable("fragment segment size must divide the segment size")
+                .attach_printable_lazy(|| format!("fragment: {name}"))
+                .attach_printable_lazy(|| format!("fragment segment size: {size}"))
+                .attach_printable_lazy(|| format!("segment size: {}", options.segment_size));
+        }
+    }
+
+    Ok(())
+}
@@ -1,50 +1,71 @@
-use apibara_observability::RecordRequest;
+use std::{collections::HashMap, sync::Arc};
+
+use apibara_observability::{KeyValue, RecordRequest};
+use bytes::Bytes;
 use error_stack::{Result, ResultExt};
-use futures::{FutureExt, StreamExt};
+use futures::{FutureExt, StreamExt, TryStreamExt};
 use futures_buffered::FuturesOrderedBounded;
+use tokio::sync::{mpsc, Notify};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
 use crate::{
-    block_store::{BlockStoreWriter, UncachedBlockStoreReader},
+    block_store::{BlockStoreError, BlockStoreWriter, UncachedBlockStoreReader},
     chain_view::{ChainView, NextCursor},
+    dataset_manifest::DatasetManifestStore,
     ingestion::IngestionStateClient,
+    segment::SerializedSegment,
     Cursor,
 };
 
 use super::{metrics::CompactionMetrics, segment_builder::SegmentBuilder, CompactionError};
 
 const MAX_BUFFERED_BLOCKS: usize = 128;
+const MAX_CONCURRENT_SEGMENT_UPLOADS: usize = 4;
 
 pub struct SegmentService {
     segment_size: usize,
+    fragment_segment_size: HashMap<String, usize>,
     chain_view: ChainView,
     block_store_reader: UncachedBlockStoreReader,
     block_store_writer: BlockStoreWriter,
     state_client: IngestionStateClient,
+    manifest_store: DatasetManifestStore,
     metrics: CompactionMetrics,
 }
 
 impl SegmentService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         segment_size: usize,
+        fragment_segment_size: HashMap<String, usize>,
         chain_view: ChainView,
         block_store_reader: UncachedBlockStoreReader,
         block_store_writer: BlockStoreWriter,
         state_client: IngestionStateClient,
+        manifest_store: DatasetManifestStore,
         metrics: CompactionMetrics,
     ) -> Self {
         Self {
             segment_size,
+            fragment_segment_size,
             chain_view,
             block_store_reader,
             block_store_writer,
             state_client,
+            manifest_store,
             metrics,
         }
     }
 
-    pub async fn start(mut self, ct: CancellationToken) -> Result<(), CompactionError> {
+    /// Create segments as blocks are finalized.
+    ///
+    /// `trigger` wakes up the service to check for new segments immediately.
+    pub async fn start(
+        mut self,
+        trigger: Arc<Notify>,
+        ct: CancellationToken,
+    ) -> Result<(), CompactionError> {
         loop {
             if ct.is_cancelled() {
                 return Ok(());
@@ -111,9 +132,13 @@ impl SegmentService {
 
                 tokio::pin!(state_change);
 
-                let Some(_) = ct.run_until_cancelled(state_change).await else {
-                    return Ok(());
-                };
+                tokio::select! {
+                    _ = ct.cancelled() => return Ok(()),
+                    _ = state_change => {}
+                    _ = trigger.notified() => {
+                        info!("compaction triggered");
+                    }
+                }
             }
         }
     }
@@ -122,19 +147,112 @@ impl SegmentService {
         &mut self,
         first_block_in_segment: Cursor,
     ) -> Result<(), CompactionError> {
-        let mut builder = SegmentBuilder::default();
-        let chain_view = &self.chain_view;
-
         info!(
             starting_cursor = %first_block_in_segment,
             "creating new segment"
         );
 
-        builder
-            .start_new_segment(first_block_in_segment.clone())
-            .change_context(CompactionError)?;
-
         let buffered_queue_size = usize::min(self.segment_size, MAX_BUFFERED_BLOCKS);
+
+        // Blocks are added to the segment on a blocking thread so that decoding them
+        // doesn't stall the block downloads.
+        let (block_tx, mut block_rx) = mpsc::channel::<(Cursor, Bytes)>(buffered_queue_size);
+
+        let builder_task = tokio::task::spawn_blocking({
+            let first_block_in_segment = first_block_in_segment.clone();
+            let segment_size = self.segment_size;
+            let fragment_segment_size = self.fragment_segment_size.clone();
+            move || {
+                let mut builder = SegmentBuilder::default();
+
+                builder
+                    .start_new_segment(first_block_in_segment)
+                    .change_context(CompactionError)?;
+
+                while let Some((block_cursor, block_data)) = block_rx.blocking_recv() {
+                    builder
+                        .add_block(&block_cursor, &block_data)
+                        .change_context(CompactionError)
+                        .attach_printable("failed to add block to segment")
+                        .attach_printable_lazy(|| format!("cursor: {block_cursor}"))?;
+
+                    debug!(cursor = %block_cursor, "compaction: added block to segment");
+                }
+
+                // Sanity checks
+                if builder.block_count() != segment_size {
+                    return Err(CompactionError)
+                        .attach_printable("builder block count does not match segment size")
+                        .attach_printable_lazy(|| {
+                            format!(
+                                "builder: {:}, segment: {:}",
+                                builder.block_count(),
+                                segment_size
+                            )
+                        });
+                }
+
+                builder
+                    .segment_data(&fragment_segment_size)
+                    .change_context(CompactionError)
+            }
+        });
+
+        let last_block_in_segment = self
+            .download_segment_blocks(&first_block_in_segment, buffered_queue_size, block_tx)
+            .await?;
+
+        let segment_data = builder_task
+            .await
+            .change_context(CompactionError)
+            .attach_printable("segment builder task failed")??;
+
+        let Some(last_block_in_segment) = last_block_in_segment else {
+            return Err(CompactionError).attach_printable("segment builder stopped early");
+        };
+
+        info!(
+            first_block = %first_block_in_segment,
+            last_block = %last_block_in_segment,
+             "uploading segment to object store"
+        );
+
+        futures::stream::iter(segment_data)
+            .map(|segment| self.upload_segment(segment))
+            .buffer_unordered(MAX_CONCURRENT_SEGMENT_UPLOADS)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        self.state_client
+            .put_segmented(last_block_in_segment.number)
+            .await
+            .change_context(CompactionError)
+            .attach_printable("failed to put segmented block")?;
+
+        self.manifest_store
+            .update(|manifest| manifest.segmented = Some(last_block_in_segment.number))
+            .await
+            .change_context(CompactionError)
+            .attach_printable("failed to update dataset manifest")?;
+
+        self.metrics
+            .segmented
+            .record(last_block_in_segment.number, &[]);
+
+        Ok(())
+    }
+
+    /// Download the segment's blocks and send them to the segment builder.
+    ///
+    /// Returns the last block in the segment, or `None` if the builder stopped
+    /// before receiving all blocks.
+    async fn download_segment_blocks(
+        &self,
+        first_block_in_segment: &Cursor,
+        buffered_queue_size: usize,
+        block_tx: mpsc::Sender<(Cursor, Bytes)>,
+    ) -> Result<Option<Cursor>, CompactionError> {
+        let chain_view = &self.chain_view;
         let mut block_queue = FuturesOrderedBounded::new(buffered_queue_size);
 
         let mut current = first_block_in_segment.clone();
@@ -146,7 +264,38 @@ impl SegmentService {
             "starting segment compaction"
         );
 
-        for _ in 0..buffered_queue_size {
+        for block_index in 0..self.segment_size + buffered_queue_size {
+            if block_index >= buffered_queue_size {
+                let block: Result<(Cursor, Bytes), BlockStoreError> = block_queue
+                    .next()
+                    .await
+                    .ok_or(CompactionError)
+                    .attach_printable("compaction segment buffer is empty")?;
+                let (block_cursor, block_data) = block
+                    .change_context(CompactionError)
+                    .attach_printable("failed to get block")?;
+
+                if block_cursor != *first_block_in_segment
+                    && block_cursor.number != last_block_in_segment.number + 1
+                {
+                    return Err(CompactionError)
+                        .attach_printable("block cursor number does not match expected number")
+                        .attach_printable_lazy(|| {
+                            format!("cursor: {block_cursor}, last_block: {last_block_in_segment}")
+                        });
+                }
+
+                last_block_in_segment = block_cursor.clone();
+
+                if block_tx.send((block_cursor, block_data)).await.is_err() {
+                    return Ok(None);
+                }
+            }
+
+            if block_index >= self.segment_size {
+                continue;
+            }
+
             let block_cursor = current.clone();
             let block_download_metrics = self.metrics.block_download.clone();
             block_queue.push_back(
@@ -173,138 +322,28 @@ impl SegmentService {
             current = next_cursor;
         }
 
-        if self.segment_size > buffered_queue_size {
-            debug!("compaction: queue full, waiting for blocks");
-
-            for _ in buffered_queue_size..self.segment_size {
-                let (block_cursor, block_data) = block_queue
-                    .next()
-                    .await
-                    .ok_or(CompactionError)
-                    .attach_printable("compaction segment buffer is empty")?
-                    .change_context(CompactionError)
-                    .attach_printable("failed to get block")?;
-
-                builder
-                    .add_block(&block_cursor, &block_data)
-                    .change_context(CompactionError)
-                    .attach_printable("failed to add block to segment")
-                    .attach_printable_lazy(|| format!("cursor: {current}"))?;
-
-                debug!(cursor = %block_cursor, "compaction: added block to segment");
-
-                last_block_in_segment = block_cursor;
-
-                let block_cursor = current.clone();
-                let block_download_metrics = self.metrics.block_download.clone();
-                block_queue.push_back(
-                    self.block_store_reader
-                        .get_block_and_cursor(block_cursor)
-                        .record_request(block_download_metrics),
-                );
-
-                let NextCursor::Continue {
-                    cursor: next_cursor,
-                    ..
-                } = chain_view
-                    .get_next_cursor(&Some(current.clone()))
-                    .await
-                    .change_context(CompactionError)?
-                else {
-                    return Err(CompactionError)
-                        .attach_printable("chain view returned invalid next cursor")
-                        .attach_printable_lazy(|| format!("cursor: {current}"));
-                };
-
-                debug!(current = %current, "compaction: pushed block to queue");
-
-                current = next_cursor;
-            }
-        }
-
-        debug!("compaction: pushed all blocks to queue");
-
-        for _ in 0..buffered_queue_size {
-            let (block_cursor, block_data) = block_queue
-                .next()
-                .await
-                .ok_or(CompactionError)
-                .attach_printable("compaction segment buffer is empty")?
-                .change_context(CompactionError)
-                .attach_printable("failed to get block")?;
-
-            builder
-                .add_block(&block_cursor, &block_data)
-                .change_context(CompactionError)
-                .attach_printable("failed to add block to segment")
-                .attach_printable_lazy(|| format!("cursor: {current}"))?;
+        debug!("compaction: downloaded all blocks");
 
-            debug!(cursor = %block_cursor, "compaction: added block to segment");
-
-            if block_cursor != first_block_in_segment
-                && block_cursor.number != last_block_in_segment.number + 1
-            {
-                return Err(CompactionError)
-                    .attach_printable("block cursor number does not match expected number")
-                    .attach_printable_lazy(|| {
-                        format!("cursor: {block_cursor}, last_block: {last_block_in_segment}")
-                    });
-            }
-
-            last_block_in_segment = block_cursor;
-        }
-
-        // Sanity checks
-        if builder.block_count() != self.segment_size {
-            return Err(CompactionError)
-                .attach_printable("builder block count does not match segment size")
-                .attach_printable_lazy(|| {
-                    format!(
-                        "builder: {:}, segment: {:}",
-                        builder.block_count(),
-                        self.segment_size
-                    )
-                });
-        }
+        Ok(Some(last_block_in_segment))
+    }
 
-        let segment_data = builder.segment_data().change_context(CompactionError)?;
+    async fn upload_segment(&self, segment: SerializedSegment) -> Result<(), CompactionError> {
+        let segment_name = segment.name.clone();
 
-        info!(
-            first_block = %first_block_in_segment,
-            last_block = %last_block_in_segment,
-             "uploading segment to object store"
+        self.metrics.segment_size.record(
+            segment.data.len() as u64,
+            &[KeyValue::new("name", segment_name.clone())],
         );
 
-        for segment in segment_data {
-            use apibara_observability::KeyValue;
-
-            let segment_name = segment.name.clone();
-
-            self.metrics.segment_size.record(
-                segment.data.len() as u64,
-                &[KeyValue::new("name", segment_name.clone())],
-            );
-
-            self.block_store_writer
-                .put_segment(&first_block_in_segment, segment)
-                .record_request_with_attributes(
-                    self.metrics.segment_upload.clone(),
-                    &[KeyValue::new("name", segment_name)],
-                )
-                .await
-                .change_context(CompactionError)
-                .attach_printable("failed to put segment")?;
-        }
-
-        self.state_client
-            .put_segmented(last_block_in_segment.number)
+        self.block_store_writer
+            .put_segment(segment)
+            .record_request_with_attributes(
+                self.metrics.segment_upload.clone(),
+                &[KeyValue::new("name", segment_name)],
+            )
             .await
             .change_context(CompactionError)
-            .attach_printable("failed to put segmented block")?;
-
-        self.metrics
-            .segmented
-            .record(last_block_in_segment.number, &[]);
+            .attach_printable("failed to put segment")?;
 
         Ok(())
     }
@@ -2,12 +2,14 @@ use std::collections::HashMap;
 
 use bytes::Bytes;
 use error_stack::{Result, ResultExt};
+use rayon::prelude::*;
 
 use crate::{
     fragment::{
-        Block, BodyFragment, HeaderFragment, IndexGroupFragment, JoinGroupFragment,
+        Block, BodyFragment, FragmentId, HeaderFragment, IndexGroupFragment, JoinGroupFragment,
         HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_NAME,
     },
+    rkyv::Serializable,
     segment::{FragmentData, Segment, SerializedSegment},
     Cursor,
 };
@@ -16,7 +18,6 @@ use super::CompactionError;
 
 #[derive(Debug, Default)]
 pub struct SegmentBuilder {
-    expected_fragment_count: Option<usize>,
     first_block: Option<Cursor>,
     headers: Vec<FragmentData<HeaderFragment>>,
     indexes: Vec<FragmentData<IndexGroupFragment>>,
@@ -45,18 +46,15 @@ impl SegmentBuilder {
             .change_context(CompactionError)
             .attach_printable("failed to access block")?;
 
-        if let Some(expected_count) = self.expected_fragment_count {
-            if block.body.len() != expected_count {
-                return Err(CompactionError)
-                    .attach_printable("block does not have the expected number of fragments")
-                    .attach_printable("this is a bug in the network-specific ingestion code")
-                    .attach_printable_lazy(|| format!("expected: {}", expected_count))
-                    .attach_printable_lazy(|| format!("actual: {}", block.body.len()));
-            }
-        } else {
-            self.expected_fragment_count = Some(block.body.len());
-        }
+        self.add_decoded_block(cursor, block)
+    }
 
+    /// Like `add_block`, but with a block that has already been deserialized.
+    pub fn add_decoded_block(
+        &mut self,
+        cursor: &Cursor,
+        block: Block,
+    ) -> Result<(), CompactionError> {
         {
             let index = block.index;
 
@@ -102,14 +100,37 @@ impl SegmentBuilder {
             if let Some(existing) = self.body.get_mut(&fragment_id) {
                 existing.1.push(data);
             } else {
-                self.body.insert(fragment_id, (name, vec![data]));
+                // The previous blocks in the segment don't have the fragment.
+                let mut fragments = self.headers[..self.headers.len() - 1]
+                    .iter()
+                    .map(|header| empty_body_fragment(&header.cursor, fragment_id, &name))
+                    .collect::<Vec<_>>();
+                fragments.push(data);
+                self.body.insert(fragment_id, (name, fragments));
+            }
+        }
+
+        // Blocks ingested before a fragment was introduced, or with its ingestion
+        // disabled, don't have it. Store an empty fragment for them, their index
+        // doesn't reference it so it's never read.
+        let block_count = self.headers.len();
+        for (fragment_id, (name, fragments)) in self.body.iter_mut() {
+            if fragments.len() < block_count {
+                fragments.push(empty_body_fragment(cursor, *fragment_id, name));
             }
         }
 
         Ok(())
     }
 
-    pub fn segment_data(&mut self) -> Result<Vec<SerializedSegment>, CompactionError> {
+    /// Serialize the segment, one segment for each fragment.
+    ///
+    /// Fragments in `fragment_segment_size` are split into smaller segments with
+    /// the given number of blocks.
+    pub fn segment_data(
+        &mut self,
+        fragment_segment_size: &HashMap<String, usize>,
+    ) -> Result<Vec<SerializedSegment>, CompactionError> {
         let Some(first_block) = self.first_block.take() else {
             return Err(CompactionError).attach_printable("no segment started");
         };
@@ -120,8 +141,6 @@ impl SegmentBuilder {
         let segments = std::mem::take(&mut self.body);
         let expected_fragment_count = headers.len();
 
-        let mut serialized = Vec::with_capacity(segments.len());
-
         if indexes.len() != headers.len() {
             return Err(CompactionError)
                 .attach_printable("index, header, and body fragments do not match")
@@ -136,81 +155,225 @@ impl SegmentBuilder {
                 .attach_printable_lazy(|| format!("headers len: {}", headers.len()));
         }
 
-        {
-            let segment = Segment {
-                first_block: first_block.clone(),
-                data: indexes,
-            };
-
-            let data = rkyv::to_bytes::<rkyv::rancor::Error>(&segment)
-                .change_context(CompactionError)
-                .attach_printable("failed to serialize index segment")?;
-            let data = Bytes::copy_from_slice(data.as_slice());
-
-            serialized.push(SerializedSegment {
-                name: INDEX_FRAGMENT_NAME.to_string(),
-                data,
-            });
+        for (_, data) in segments.values() {
+            if data.len() != expected_fragment_count {
+                return Err(CompactionError)
+                    .attach_printable("body fragments do not match")
+                    .attach_printable_lazy(|| format!("expected: {}", expected_fragment_count))
+                    .attach_printable_lazy(|| format!("actual: {}", data.len()));
+            }
         }
 
-        {
-            let segment = Segment {
-                first_block: first_block.clone(),
-                data: joins,
-            };
-
-            let data = rkyv::to_bytes::<rkyv::rancor::Error>(&segment)
-                .change_context(CompactionError)
-                .attach_printable("failed to serialize join segment")?;
-            let data = Bytes::copy_from_slice(data.as_slice());
+        let header_segment_size = fragment_segment_size.get(HEADER_FRAGMENT_NAME).copied();
+
+        // Serializing large segments is expensive, so serialize each fragment
+        // on its own thread.
+        let first_block = &first_block;
+        let ((index, join), (header, body)) = rayon::join(
+            || {
+                rayon::join(
+                    move || {
+                        let segment = Segment {
+                            first_block: first_block.clone(),
+                            data: indexes,
+                        };
+                        serialize_segment(INDEX_FRAGMENT_NAME, segment)
+                    },
+                    move || {
+                        let segment = Segment {
+                            first_block: first_block.clone(),
+                            data: joins,
+                        };
+                        serialize_segment(JOIN_FRAGMENT_NAME, segment)
+                    },
+                )
+            },
+            || {
+                rayon::join(
+                    move || {
+                        split_segment(first_block, headers, header_segment_size)
+                            .into_par_iter()
+                            .map(|segment| serialize_segment(HEADER_FRAGMENT_NAME, segment))
+                            .collect::<Result<Vec<_>, CompactionError>>()
+                    },
+                    move || {
+                        segments
+                            .into_par_iter()
+                            .flat_map(|(_, (name, data))| {
+                                let segment_size = fragment_segment_size.get(&name).copied();
+                                split_segment(first_block, data, segment_size)
+                                    .into_par_iter()
+                                    .map(move |segment| serialize_segment(&name, segment))
+                            })
+                            .collect::<Result<Vec<_>, CompactionError>>()
+                    },
+                )
+            },
+        );
+
+        let mut serialized = Vec::new();
+        serialized.push(index?);
+        serialized.push(join?);
+        serialized.extend(header?);
+        serialized.extend(body?);
 
-            serialized.push(SerializedSegment {
-                name: JOIN_FRAGMENT_NAME.to_string(),
-                data,
-            });
-        }
+        Ok(serialized)
+    }
+}
 
-        {
-            let segment = Segment {
-                first_block: first_block.clone(),
-                data: headers,
-            };
+fn empty_body_fragment(
+    cursor: &Cursor,
+    fragment_id: FragmentId,
+    name: &str,
+) -> FragmentData<BodyFragment> {
+    FragmentData {
+        cursor: cursor.clone(),
+        data: BodyFragment {
+            fragment_id,
+            name: name.to_string(),
+            data: Vec::new(),
+        },
+    }
+}
 
-            let data = rkyv::to_bytes::<rkyv::rancor::Error>(&segment)
-                .change_context(CompactionError)
-                .attach_printable("failed to serialize header segment")?;
-            let data = Bytes::copy_from_slice(data.as_slice());
+/// Split the fragment data into segments of `segment_size` blocks.
+///
+/// If `segment_size` is `None`, all data goes into a single segment.
+fn split_segment<T>(
+    first_block: &Cursor,
+    data: Vec<FragmentData<T>>,
+    segment_size: Option<usize>,
+) -> Vec<Segment<T>> {
+    let Some(segment_size) = segment_size else {
+        return vec![Segment {
+            first_block: first_block.clone(),
+            data,
+        }];
+    };
+
+    let mut segments = Vec::new();
+    let mut data = data.into_iter().peekable();
+
+    while let Some(first) = data.peek() {
+        let first_block = first.cursor.clone();
+        let data = data.by_ref().take(segment_size).collect::<Vec<_>>();
+        segments.push(Segment { first_block, data });
+    }
 
-            serialized.push(SerializedSegment {
-                name: HEADER_FRAGMENT_NAME.to_string(),
-                data,
-            });
-        }
+    segments
+}
 
-        for (name, data) in segments.into_values() {
-            if data.len() != expected_fragment_count {
-                return Err(CompactionError)
-                    .attach_printable("body fragments do not match")
-                    .attach_printable_lazy(|| format!("expected: {}", expected_fragment_count))
-                    .attach_printable_lazy(|| format!("actual: {}", data.len()));
-            }
+/// Serialize the segment of the fragment with the given name.
+fn serialize_segment<T>(
+    name: &str,
+    segment: Segment<T>,
+) -> Result<SerializedSegment, CompactionError>
+where
+    Segment<T>: for<'a> Serializable<'a>,
+{
+    let data = rkyv::to_bytes::<rkyv::rancor::Error>(&segment)
+        .change_context(CompactionError)
+        .attach_printable("failed to serialize segment")
+        .attach_printable_lazy(|| format!("name: {name}"))?;
+
+    Ok(SerializedSegment {
+        name: name.to_string(),
+        first_block: segment.first_block,
+        data: Bytes::copy_from_slice(data.as_slice()),
+    })
+}
 
-            let segment = Segment {
-                first_block: first_block.clone(),
-                data,
-            };
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        fragment::{Block, BodyFragment, HeaderFragment, IndexGroupFragment, JoinGroupFragment},
+        segment::{FragmentData, Segment},
+        Cursor,
+    };
+
+    use super::{split_segment, SegmentBuilder};
+
+    fn fragment_data(first_block: u64, count: u64) -> Vec<FragmentData<u64>> {
+        (first_block..first_block + count)
+            .map(|block_number| FragmentData {
+                cursor: Cursor::new_finalized(block_number),
+                data: block_number,
+            })
+            .collect()
+    }
 
-            let data = rkyv::to_bytes::<rkyv::rancor::Error>(&segment)
-                .change_context(CompactionError)
-                .attach_printable("failed to serialize segment")?;
-            let data = Bytes::copy_from_slice(data.as_slice());
+    #[test]
+    fn test_split_segment() {
+        let first_block = Cursor::new_finalized(1_000);
+
+        let segments = split_segment(&first_block, fragment_data(1_000, 100), None);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].first_block.number, 1_000);
+        assert_eq!(segments[0].data.len(), 100);
+
+        let segments = split_segment(&first_block, fragment_data(1_000, 100), Some(25));
+        assert_eq!(segments.len(), 4);
+        for (index, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.first_block.number, 1_000 + 25 * index as u64);
+            assert_eq!(segment.data.len(), 25);
+            assert_eq!(segment.data[0].data, segment.first_block.number);
+        }
+    }
 
-            serialized.push(SerializedSegment { name, data });
+    fn block(fragments: &[(u8, &str)]) -> Block {
+        Block {
+            header: HeaderFragment { data: Vec::new() },
+            index: IndexGroupFragment {
+                indexes: Vec::new(),
+            },
+            join: JoinGroupFragment { joins: Vec::new() },
+            body: fragments
+                .iter()
+                .map(|(fragment_id, name)| BodyFragment {
+                    fragment_id: *fragment_id,
+                    name: name.to_string(),
+                    data: vec![vec![*fragment_id]],
+                })
+                .collect(),
         }
+    }
 
-        // NOTE: we leave the expected_fragment_count field as is because we want the data to be consistent
-        // across all segments.
+    #[test]
+    fn test_blocks_with_missing_fragments() {
+        let mut builder = SegmentBuilder::default();
+        builder
+            .start_new_segment(Cursor::new_finalized(100))
+            .unwrap();
+
+        // A fragment introduced mid-segment, then disabled again.
+        let blocks = [
+            block(&[(2, "transaction")]),
+            block(&[(2, "transaction"), (3, "contract_class")]),
+            block(&[(2, "transaction")]),
+        ];
+
+        for (offset, block) in blocks.into_iter().enumerate() {
+            let cursor = Cursor::new_finalized(100 + offset as u64);
+            builder.add_decoded_block(&cursor, block).unwrap();
+        }
 
-        Ok(serialized)
+        let segments = builder.segment_data(&HashMap::new()).unwrap();
+
+        let contract_class = segments
+            .iter()
+            .find(|segment| segment.name == "contract_class")
+            .unwrap();
+        let segment =
+            rkyv::from_bytes::<Segment<BodyFragment>, rkyv::rancor::Error>(&contract_class.data)
+                .unwrap();
+
+        let data = segment
+            .data
+            .iter()
+            .map(|fragment| (fragment.cursor.number, fragment.data.data.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(data, vec![(100, 0), (101, 1), (102, 0)]);
     }
 }
@@ -1,5 +1,8 @@
+use std::{collections::HashMap, sync::Arc};
+
 use apibara_etcd::{EtcdClient, Lock};
 use error_stack::{Result, ResultExt};
+use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
@@ -7,11 +10,15 @@ use crate::{
     block_store::{BlockStoreWriter, UncachedBlockStoreReader},
     chain_view::ChainView,
     compaction::group::SegmentGroupService,
+    dataset_manifest::DatasetManifestStore,
     ingestion::IngestionStateClient,
     object_store::ObjectStore,
 };
 
-use super::{error::CompactionError, metrics::CompactionMetrics, segment::SegmentService};
+use super::{
+    cleanup::BlockCleanupService, error::CompactionError, metrics::CompactionMetrics,
+    segment::SegmentService,
+};
 
 #[derive(Debug, Clone)]
 pub struct CompactionServiceOptions {
@@ -19,6 +26,15 @@ pub struct CompactionServiceOptions {
     pub segment_size: usize,
     /// How many segments in a single segment group.
     pub group_size: usize,
+    /// Segment size of the fragments that don't use `segment_size`.
+    ///
+    /// Each size must divide `segment_size`.
+    pub fragment_segment_size: HashMap<String, usize>,
+    /// Delete the block objects that are segmented and at least this many blocks
+    /// behind the finalized block.
+    ///
+    /// If `None`, block objects are never deleted.
+    pub block_retention: Option<u64>,
 }
 
 pub struct CompactionService {
@@ -26,7 +42,9 @@ pub struct CompactionService {
     block_store_reader: UncachedBlockStoreReader,
     block_store_writer: BlockStoreWriter,
     state_client: IngestionStateClient,
+    manifest_store: DatasetManifestStore,
     chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
+    trigger: Arc<Notify>,
     metrics: CompactionMetrics,
 }
 
@@ -36,9 +54,11 @@ impl CompactionService {
         object_store: ObjectStore,
         chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
         options: CompactionServiceOptions,
+        trigger: Arc<Notify>,
         metrics: CompactionMetrics,
     ) -> Self {
         let block_store_reader = UncachedBlockStoreReader::new(object_store.clone());
+        let manifest_store = DatasetManifestStore::new(object_store.clone());
         let block_store_writer = BlockStoreWriter::new(object_store);
         let state_client = IngestionStateClient::new(&etcd_client);
 
@@ -48,10 +68,23 @@ impl CompactionService {
             block_store_writer,
             chain_view,
             state_client,
+            manifest_store,
+            trigger,
             metrics,
         }
     }
 
+    /// Read and write the segments and groups of the given generation.
+    pub fn with_segment_generation(mut self, segment_generation: u64) -> Self {
+        self.block_store_reader = self
+            .block_store_reader
+            .with_segment_generation(segment_generation);
+        self.block_store_writer = self
+            .block_store_writer
+            .with_segment_generation(segment_generation);
+        self
+    }
+
     pub async fn start(
         self,
         lock: &mut Lock,
@@ -74,10 +107,12 @@ impl CompactionService {
 
         let segment_service = SegmentService::new(
             self.options.segment_size,
+            self.options.fragment_segment_size.clone(),
             chain_view.clone(),
             self.block_store_reader.clone(),
             self.block_store_writer.clone(),
             self.state_client.clone(),
+            self.manifest_store.clone(),
             self.metrics.clone(),
         );
 
@@ -88,12 +123,28 @@ impl CompactionService {
             self.block_store_reader.clone(),
             self.block_store_writer.clone(),
             self.state_client.clone(),
+            self.manifest_store.clone(),
             self.metrics.clone(),
         );
 
-        let segment_service_handle = tokio::spawn(segment_service.start(ct.clone()));
+        let segment_service_handle =
+            tokio::spawn(segment_service.start(self.trigger.clone(), ct.clone()));
         let group_service_handle = tokio::spawn(group_service.start(ct.clone()));
 
+        let cleanup_service_handle = if let Some(block_retention) = self.options.block_retention {
+            let cleanup_service = BlockCleanupService::new(
+                block_retention,
+                chain_view.clone(),
+                self.block_store_writer.clone(),
+                self.state_client.clone(),
+                self.metrics.clone(),
+            );
+
+            tokio::spawn(cleanup_service.start(ct.clone()))
+        } else {
+            tokio::spawn(futures::future::pending())
+        };
+
         let lock_handle = lock_keep_alive_loop(lock, ct.clone());
 
         tokio::select! {
@@ -112,6 +163,10 @@ impl CompactionService {
                 info!("compaction group service loop terminated");
                 group_service.change_context(CompactionError)?.change_context(CompactionError)
             }
+            cleanup_service = cleanup_service_handle => {
+                info!("compaction block cleanup loop terminated");
+                cleanup_service.change_context(CompactionError)?.change_context(CompactionError)
+            }
         }
     }
 }
@@ -140,6 +195,8 @@ impl Default for CompactionServiceOptions {
         Self {
             segment_size: 1_000,
             group_size: 100,
+            fragment_segment_size: HashMap::default(),
+            block_retention: None,
         }
     }
 }
@@ -0,0 +1,438 @@
+//! Load command line options from a TOML or YAML configuration file.
+//!
+//! Keys in the file match the options' long names, with sections for the part
+//! before the dot. For example, `--s3.bucket` is set by:
+//!
+//! ```toml
+//! [s3]
+//! bucket = "my-bucket"
+//! ```
+//!
+//! Values become the options' defaults, so they're overridden by both the
+//! environment and the command line. The process environment is never modified.
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, FromArgMatches, Parser};
+use error_stack::{Result, ResultExt};
+use serde_json::Value;
+
+/// Command line flag with the path to the configuration file.
+pub const CONFIG_FILE_FLAG: &str = "--config";
+
+/// Environment variable with the path to the configuration file.
+pub const CONFIG_FILE_ENV: &str = "DNA_CONFIG";
+
+#[derive(Debug)]
+pub struct ConfigFileError;
+
+/// The values read from a configuration file, by option long name.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    values: BTreeMap<String, Vec<String>>,
+}
+
+impl ConfigFile {
+    /// Read the configuration file given with `--config` or `DNA_CONFIG`, if any.
+    ///
+    /// The file is read again on every call, so this also reloads it.
+    pub fn load() -> Result<Option<Self>, ConfigFileError> {
+        let Some(path) = config_file_path(std::env::args_os()) else {
+            return Ok(None);
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .change_context(ConfigFileError)
+            .attach_printable("failed to read configuration file")
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+        let values = parse_config_file(&path, &content)?;
+
+        Ok(Some(Self { values }))
+    }
+
+    /// Use the file's values as the defaults of the command's options.
+    ///
+    /// Adds the names of the options that were set to `used`.
+    fn apply_defaults(
+        &self,
+        mut command: clap::Command,
+        used: &mut HashSet<String>,
+    ) -> Result<clap::Command, ConfigFileError> {
+        let options = command
+            .get_arguments()
+            .filter_map(|arg| {
+                let long = arg.get_long()?;
+                let values = self.values.get(long)?;
+                let accepts_list = arg.get_value_delimiter().is_some()
+                    || matches!(arg.get_action(), clap::ArgAction::Append);
+                Some((
+                    arg.get_id().clone(),
+                    long.to_string(),
+                    values.clone(),
+                    accepts_list,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        for (id, long, values, accepts_list) in options {
+            if values.len() > 1 && !accepts_list {
+                return Err(ConfigFileError)
+                    .attach_printable("option does not accept a list of values")
+                    .attach_printable_lazy(|| format!("option: {long}"));
+            }
+
+            command = command.mut_arg(id, |arg| arg.default_values(values));
+            used.insert(long);
+        }
+
+        let subcommands = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect::<Vec<_>>();
+
+        for name in subcommands {
+            let mut result = Ok(());
+            command = command.mut_subcommand(&name, |subcommand| {
+                match self.apply_defaults(subcommand.clone(), used) {
+                    Ok(subcommand) => subcommand,
+                    Err(err) => {
+                        result = Err(err);
+                        subcommand
+                    }
+                }
+            });
+            result?;
+        }
+
+        Ok(command)
+    }
+}
+
+/// Parse the command line, using the configuration file for the options that
+/// are not set on the command line or in the environment.
+///
+/// Exits the process on invalid arguments, like [Parser::parse].
+pub fn parse_with_config_file<C: Parser>() -> Result<C, ConfigFileError> {
+    let mut command = C::command();
+
+    if let Some(config_file) = ConfigFile::load()? {
+        let mut used = HashSet::new();
+        command = config_file.apply_defaults(command, &mut used)?;
+
+        if let Some(key) = config_file.values.keys().find(|key| !used.contains(*key)) {
+            return Err(ConfigFileError)
+                .attach_printable("unknown option in configuration file")
+                .attach_printable_lazy(|| format!("option: {key}"));
+        }
+    }
+
+    let matches = command.clone().get_matches();
+
+    Ok(C::from_arg_matches(&matches).unwrap_or_else(|err| err.format(&mut command).exit()))
+}
+
+/// Parse the options in `A` again, from the command line, the environment, and
+/// the given configuration file.
+///
+/// Use this with a freshly loaded [ConfigFile] to read the new values of the options.
+pub fn reparse_args<A>(config_file: Option<&ConfigFile>) -> Result<A, ConfigFileError>
+where
+    A: Args + FromArgMatches,
+{
+    let mut command = A::augment_args(clap::Command::new("reparse").no_binary_name(true));
+    if let Some(config_file) = config_file {
+        command = config_file.apply_defaults(command, &mut HashSet::new())?;
+    }
+    command.build();
+
+    let args = command_line_args(&command, std::env::args_os().skip(1));
+
+    let matches = command
+        .try_get_matches_from(args)
+        .change_context(ConfigFileError)
+        .attach_printable("failed to parse options")?;
+
+    A::from_arg_matches(&matches)
+        .change_context(ConfigFileError)
+        .attach_printable("failed to parse options")
+}
+
+fn config_file_path(mut args: impl Iterator<Item = OsString>) -> Option<PathBuf> {
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.to_str() else {
+            continue;
+        };
+
+        if arg == CONFIG_FILE_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg
+            .strip_prefix(CONFIG_FILE_FLAG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from)
+}
+
+/// Returns the command line arguments that belong to the command's options.
+fn command_line_args(
+    command: &clap::Command,
+    mut args: impl Iterator<Item = OsString>,
+) -> Vec<OsString> {
+    let mut out = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.to_str().and_then(|arg| arg.strip_prefix("--")) else {
+            continue;
+        };
+
+        let (name, has_value) = match name.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (name, false),
+        };
+
+        let Some(option) = command
+            .get_arguments()
+            .find(|option| option.get_long() == Some(name))
+        else {
+            continue;
+        };
+
+        let takes_value = !has_value && option.get_action().takes_values();
+
+        out.push(arg);
+
+        if takes_value {
+            if let Some(value) = args.next() {
+                out.push(value);
+            }
+        }
+    }
+
+    out
+}
+
+/// Parse the configuration file into a map from option name to values.
+fn parse_config_file(
+    path: &Path,
+    content: &str,
+) -> Result<BTreeMap<String, Vec<String>>, ConfigFileError> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    let value = match extension {
+        "toml" => toml::from_str::<Value>(content)
+            .change_context(ConfigFileError)
+            .attach_printable("failed to parse TOML configuration file")?,
+        "yaml" | "yml" => serde_yaml::from_str::<Value>(content)
+            .change_context(ConfigFileError)
+            .attach_printable("failed to parse YAML configuration file")?,
+        _ => {
+            return Err(ConfigFileError)
+                .attach_printable("configuration file must be a .toml, .yaml, or .yml file")
+                .attach_printable_lazy(|| format!("path: {}", path.display()));
+        }
+    };
+
+    let mut values = BTreeMap::new();
+    flatten_value(None, value, &mut values)?;
+    Ok(values)
+}
+
+fn flatten_value(
+    prefix: Option<&str>,
+    value: Value,
+    out: &mut BTreeMap<String, Vec<String>>,
+) -> Result<(), ConfigFileError> {
+    let value = match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let key = match prefix {
+                    Some(prefix) => format!("{prefix}.{key}"),
+                    None => key,
+                };
+                flatten_value(Some(&key), value, out)?;
+            }
+            return Ok(());
+        }
+        Value::Null => return Ok(()),
+        Value::String(value) => vec![value],
+        Value::Bool(value) => vec![value.to_string()],
+        Value::Number(value) => vec![value.to_string()],
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::String(value) => Ok(value),
+                Value::Bool(_) | Value::Number(_) => Ok(item.to_string()),
+                _ => Err(ConfigFileError)
+                    .attach_printable("lists can only contain strings, numbers, or booleans"),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    let key = prefix
+        .ok_or(ConfigFileError)
+        .attach_printable("configuration file must contain a table")?;
+
+    out.insert(key.to_string(), value);
+
+    Ok(())
+}
+
+impl error_stack::Context for ConfigFileError {}
+
+impl std::fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "configuration file error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use clap::{Args, Command, FromArgMatches};
+
+    use super::{command_line_args, config_file_path, parse_config_file, ConfigFile};
+
+    #[test]
+    fn test_parse_config_file() {
+        let toml = r#"
+        [s3]
+        bucket = "dna"
+        compression-level = 3
+
+        [server]
+        enabled = true
+
+        [rpc]
+        weights = [2, 1]
+        "#;
+
+        let yaml = r#"
+        s3:
+          bucket: dna
+          compression-level: 3
+        server:
+          enabled: true
+        rpc:
+          weights:
+            - 2
+            - 1
+        "#;
+
+        let from_toml = parse_config_file(Path::new("dna.toml"), toml).unwrap();
+        let from_yaml = parse_config_file(Path::new("dna.yaml"), yaml).unwrap();
+
+        assert_eq!(from_toml, from_yaml);
+        assert_eq!(from_toml["s3.bucket"], vec!["dna"]);
+        assert_eq!(from_toml["s3.compression-level"], vec!["3"]);
+        assert_eq!(from_toml["server.enabled"], vec!["true"]);
+        assert_eq!(from_toml["rpc.weights"], vec!["2", "1"]);
+
+        assert!(parse_config_file(Path::new("dna.json"), "{}").is_err());
+    }
+
+    #[test]
+    fn test_config_file_path() {
+        let args = ["dna", "start", "--config", "dna.toml"].map(Into::into);
+        assert_eq!(config_file_path(args.into_iter()), Some("dna.toml".into()));
+
+        let args = ["dna", "start", "--config=dna.yaml"].map(Into::into);
+        assert_eq!(config_file_path(args.into_iter()), Some("dna.yaml".into()));
+    }
+
+    #[derive(Args, Debug)]
+    struct TestArgs {
+        #[arg(long = "server.max-concurrent-streams", default_value = "10")]
+        max_concurrent_streams: usize,
+        #[arg(long = "server.enabled")]
+        enabled: bool,
+    }
+
+    #[test]
+    fn test_command_line_args() {
+        let mut command = TestArgs::augment_args(Command::new("test").no_binary_name(true));
+        command.build();
+
+        let args = [
+            "start",
+            "--rpc.url",
+            "http://localhost:8545",
+            "--server.enabled",
+            "--server.max-concurrent-streams",
+            "20",
+            "--s3.bucket=dna",
+        ]
+        .map(Into::into);
+
+        let args = command_line_args(&command, args.into_iter());
+        assert_eq!(
+            args,
+            ["--server.enabled", "--server.max-concurrent-streams", "20"]
+                .map(std::ffi::OsString::from)
+        );
+
+        let matches = command.try_get_matches_from(args).unwrap();
+        let args = TestArgs::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.max_concurrent_streams, 20);
+        assert!(args.enabled);
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let content = r#"
+        [server]
+        max-concurrent-streams = 30
+        enabled = true
+        "#;
+
+        let config_file = ConfigFile {
+            values: parse_config_file(Path::new("dna.toml"), content).unwrap(),
+        };
+
+        let command = TestArgs::augment_args(Command::new("test").no_binary_name(true));
+        let mut used = Default::default();
+        let command = config_file.apply_defaults(command, &mut used).unwrap();
+        assert_eq!(used.len(), 2);
+
+        let matches = command
+            .clone()
+            .try_get_matches_from(Vec::<String>::new())
+            .unwrap();
+        let args = TestArgs::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.max_concurrent_streams, 30);
+        assert!(args.enabled);
+
+        // The command line takes precedence over the file.
+        let matches = command
+            .try_get_matches_from(["--server.max-concurrent-streams", "40"])
+            .unwrap();
+        let args = TestArgs::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.max_concurrent_streams, 40);
+
+        let content = r#"
+        [server]
+        max-concurrent-streams = [1, 2]
+        "#;
+
+        let config_file = ConfigFile {
+            values: parse_config_file(Path::new("dna.toml"), content).unwrap(),
+        };
+
+        let command = TestArgs::augment_args(Command::new("test").no_binary_name(true));
+        assert!(config_file
+            .apply_defaults(command, &mut Default::default())
+            .is_err());
+    }
+}
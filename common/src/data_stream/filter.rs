@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::BTreeMap;
 
 use roaring::RoaringBitmap;
 
@@ -9,10 +9,18 @@ pub trait BlockFilterFactory {
         &self,
         filters: &[Vec<u8>],
     ) -> tonic::Result<Vec<BlockFilter>, tonic::Status>;
+
+    /// Returns the descriptor set with the chain's filter message, and the message's
+    /// fully qualified name. Used to read filters from JSON files.
+    fn filter_descriptor(&self) -> (&'static [u8], &'static str);
 }
 
-#[derive(Debug, Default)]
-pub struct FilterMatch(BTreeMap<u32, HashSet<FilterId>>);
+/// Rows matched by each filter.
+///
+/// Rows are stored as one bitmap per filter, so matching many rows doesn't allocate
+/// for each row.
+#[derive(Debug, Default, Clone)]
+pub struct FilterMatch(BTreeMap<FilterId, RoaringBitmap>);
 
 #[derive(Debug)]
 pub struct Match {
@@ -29,24 +37,79 @@ impl FilterMatch {
         self.0.is_empty()
     }
 
+    /// Returns an estimate of the memory used by the matched rows.
+    pub fn size_bytes(&self) -> usize {
+        self.0
+            .values()
+            .map(|rows| std::mem::size_of::<FilterId>() + rows.serialized_size())
+            .sum()
+    }
+
+    /// Returns the number of rows matched by any filter.
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.rows().len() as usize
     }
 
     pub fn add_match(&mut self, filter_id: FilterId, bitmap: &RoaringBitmap) {
-        for index in bitmap.iter() {
-            self.0.entry(index).or_default().insert(filter_id);
+        if bitmap.is_empty() {
+            return;
         }
+
+        *self.0.entry(filter_id).or_default() |= bitmap;
     }
 
     pub fn add_single_match(&mut self, filter_id: FilterId, index: u32) {
-        self.0.entry(index).or_default().insert(filter_id);
+        self.0.entry(filter_id).or_default().insert(index);
     }
 
+    /// Iterates over the matched rows, in order, with the (sorted) ids of the filters
+    /// that matched them.
     pub fn iter(&self) -> impl Iterator<Item = Match> + '_ {
-        self.0.iter().map(|(index, filter_ids)| Match {
-            index: *index,
-            filter_ids: filter_ids.iter().copied().collect(),
+        self.rows().into_iter().map(move |index| Match {
+            index,
+            filter_ids: self
+                .0
+                .iter()
+                .filter(|(_, rows)| rows.contains(index))
+                .map(|(filter_id, _)| *filter_id)
+                .collect(),
         })
     }
+
+    fn rows(&self) -> RoaringBitmap {
+        self.0
+            .values()
+            .fold(RoaringBitmap::new(), |acc, rows| acc | rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roaring::RoaringBitmap;
+
+    use super::FilterMatch;
+
+    #[test]
+    fn test_filter_match() {
+        let mut filter_match = FilterMatch::default();
+        assert!(filter_match.is_empty());
+
+        filter_match.add_match(2, &RoaringBitmap::new());
+        assert!(filter_match.is_empty());
+
+        filter_match.add_match(2, &RoaringBitmap::from_iter([1, 3, 5]));
+        filter_match.add_match(1, &RoaringBitmap::from_iter([3, 4]));
+        filter_match.add_single_match(1, 5);
+
+        assert_eq!(filter_match.len(), 4);
+
+        let matches = filter_match
+            .iter()
+            .map(|m| (m.index, m.filter_ids))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matches,
+            vec![(1, vec![2]), (3, vec![1, 2]), (4, vec![1]), (5, vec![1, 2])]
+        );
+    }
 }
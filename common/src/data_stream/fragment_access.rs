@@ -17,20 +17,25 @@ pub enum FragmentAccess<'a> {
 }
 
 impl<'a> FragmentAccess<'a> {
+    /// Returns the fragment's indexes, or `None` if the block doesn't have the fragment.
+    ///
+    /// Blocks stored before a fragment was introduced, or with its ingestion disabled,
+    /// don't have it.
     pub fn get_index_fragment(
         &'a self,
         fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<IndexFragment>, FragmentAccessError> {
+    ) -> Result<Option<&'a rkyv::Archived<IndexFragment>>, FragmentAccessError> {
         match self {
             Self::Segment(access) => access.get_index_fragment(fragment_id),
             Self::Block(access) => access.get_index_fragment(fragment_id),
         }
     }
 
+    /// Returns the fragment's joins, or `None` if the block doesn't have the fragment.
     pub fn get_join_fragment(
         &'a self,
         fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<JoinFragment>, FragmentAccessError> {
+    ) -> Result<Option<&'a rkyv::Archived<JoinFragment>>, FragmentAccessError> {
         match self {
             Self::Segment(access) => access.get_join_fragment(fragment_id),
             Self::Block(access) => access.get_join_fragment(fragment_id),
@@ -58,58 +63,48 @@ impl<'a> FragmentAccess<'a> {
 }
 
 impl BlockAccess {
+    /// Returns the archived block, without deserializing it.
+    fn block(&self) -> &rkyv::Archived<Block> {
+        unsafe { rkyv::access_unchecked::<rkyv::Archived<Block>>(self.0.value()) }
+    }
+
     pub fn get_index_fragment<'a>(
         &'a self,
         fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<IndexFragment>, FragmentAccessError> {
-        let block = unsafe { rkyv::access_unchecked::<rkyv::Archived<Block>>(self.0.value()) };
+    ) -> Result<Option<&'a rkyv::Archived<IndexFragment>>, FragmentAccessError> {
+        let block = self.block();
 
-        let Some(pos) = block
+        Ok(block
             .index
             .indexes
             .iter()
-            .position(|f| f.fragment_id == *fragment_id)
-        else {
-            return Err(FragmentAccessError)
-                .attach_printable("index for fragment not found")
-                .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-        };
-
-        Ok(&block.index.indexes[pos])
+            .find(|f| f.fragment_id == *fragment_id))
     }
 
     pub fn get_join_fragment<'a>(
         &'a self,
         fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<JoinFragment>, FragmentAccessError> {
-        let block = unsafe { rkyv::access_unchecked::<rkyv::Archived<Block>>(self.0.value()) };
+    ) -> Result<Option<&'a rkyv::Archived<JoinFragment>>, FragmentAccessError> {
+        let block = self.block();
 
-        let Some(pos) = block
+        Ok(block
             .join
             .joins
             .iter()
-            .position(|f| f.fragment_id == *fragment_id)
-        else {
-            return Err(FragmentAccessError)
-                .attach_printable("joins for fragment not found")
-                .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-        };
-
-        Ok(&block.join.joins[pos])
+            .find(|f| f.fragment_id == *fragment_id))
     }
 
     pub fn get_header_fragment(
         &self,
     ) -> Result<&rkyv::Archived<HeaderFragment>, FragmentAccessError> {
-        let block = unsafe { rkyv::access_unchecked::<rkyv::Archived<Block>>(self.0.value()) };
-        Ok(&block.header)
+        Ok(&self.block().header)
     }
 
     pub fn get_body_fragment<'a>(
         &'a self,
         fragment_id: &FragmentId,
     ) -> Result<&'a rkyv::Archived<BodyFragment>, FragmentAccessError> {
-        let block = unsafe { rkyv::access_unchecked::<rkyv::Archived<Block>>(self.0.value()) };
+        let block = self.block();
 
         let Some(pos) = block
             .body
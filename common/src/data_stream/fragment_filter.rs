@@ -0,0 +1,543 @@
+//! Evaluate the stream's filters against the blocks' fragments.
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use apibara_observability::{KeyValue, RecordRequest};
+use bytes::{BufMut, Bytes, BytesMut};
+use error_stack::{Result, ResultExt};
+use tokio::sync::oneshot;
+
+use crate::{
+    data_stream::{
+        fragment_access::BlockAccess, result_cache::SegmentMatches, FilterMatch, FragmentAccess,
+        SegmentAccess,
+    },
+    fragment::{FragmentId, HEADER_FRAGMENT_ID},
+    join::ArchivedJoinTo,
+    query::{BlockFilter, HeaderFilter},
+    Cursor,
+};
+
+use super::{DataStreamError, DataStreamMetrics};
+
+/// Tag of the `filter_ids` field in all fragment messages.
+const FILTER_IDS_TAG: u32 = 1;
+
+/// The stream's filters, shared with the threads that evaluate them.
+///
+/// Matching filters and copying the fragments' data is CPU bound, so it runs on
+/// the rayon thread pool instead of the async runtime. This way a stream with
+/// heavy filters doesn't starve the I/O of the other streams on the same server.
+/// Each stream waits for its evaluation to complete, so the pool's queue is
+/// bounded by the number of streams.
+#[derive(Clone)]
+pub struct FragmentFilter {
+    block_filter: Arc<Vec<BlockFilter>>,
+    fragment_id_to_name: Arc<HashMap<FragmentId, String>>,
+    metrics: DataStreamMetrics,
+}
+
+/// The rows matched in each fragment, one map for each block filter.
+pub type BlockMatches = Vec<BTreeMap<FragmentId, FilterMatch>>;
+
+/// The data of a block that matched the filters.
+#[derive(Clone)]
+pub struct BlockData {
+    pub cursor: Cursor,
+    pub data: Vec<Bytes>,
+}
+
+impl BlockData {
+    /// Returns the size of the block's data.
+    pub fn size_bytes(&self) -> usize {
+        self.data.iter().map(|data| data.len()).sum()
+    }
+}
+
+impl FragmentFilter {
+    pub fn new(
+        block_filter: Vec<BlockFilter>,
+        fragment_id_to_name: HashMap<FragmentId, String>,
+        metrics: DataStreamMetrics,
+    ) -> Self {
+        Self {
+            block_filter: Arc::new(block_filter),
+            fragment_id_to_name: Arc::new(fragment_id_to_name),
+            metrics,
+        }
+    }
+
+    pub fn block_filter(&self) -> &[BlockFilter] {
+        &self.block_filter
+    }
+
+    pub fn fragment_id_to_name(&self) -> &HashMap<FragmentId, String> {
+        &self.fragment_id_to_name
+    }
+
+    /// Filter the segment's blocks, starting from `starting_block`.
+    ///
+    /// Returns the blocks that have data, in order.
+    pub async fn filter_segment(
+        &self,
+        segment_access: SegmentAccess,
+        starting_block: u64,
+    ) -> Result<Vec<BlockData>, DataStreamError> {
+        let mut stream = self.filter_segment_stream(segment_access, starting_block, None, false);
+
+        let mut blocks = Vec::new();
+        while let Some(block) = stream.next().await? {
+            blocks.push(block);
+        }
+
+        Ok(blocks)
+    }
+
+    /// Filter the segment's blocks one at a time, starting from `starting_block`.
+    ///
+    /// If `cached` contains the rows matched by a previous evaluation of the same
+    /// filter, only the data of the matched rows is copied. Otherwise, if
+    /// `collect_matches` is set, the stream collects the rows matched in the whole
+    /// segment so that they can be cached.
+    pub fn filter_segment_stream(
+        &self,
+        segment_access: SegmentAccess,
+        starting_block: u64,
+        cached: Option<Arc<SegmentMatches>>,
+        collect_matches: bool,
+    ) -> SegmentFilterStream {
+        let blocks = segment_access
+            .blocks
+            .iter()
+            .filter(|block_number| *block_number as u64 >= starting_block)
+            .collect::<Vec<_>>();
+
+        // Matches are only useful to other streams if they cover the whole segment.
+        let matches = if collect_matches
+            && cached.is_none()
+            && starting_block <= segment_access.first_block
+        {
+            Some(SegmentMatches::default())
+        } else {
+            None
+        };
+
+        SegmentFilterStream {
+            filter: self.clone(),
+            state: Some(SegmentFilterState {
+                segment_access,
+                blocks: blocks.into_iter(),
+                cached,
+                matches,
+            }),
+        }
+    }
+
+    /// Filter a single block.
+    ///
+    /// Returns `None` if the block has no data.
+    pub async fn filter_block(
+        &self,
+        block_access: BlockAccess,
+        is_live: bool,
+    ) -> Result<Option<Vec<Bytes>>, DataStreamError> {
+        let filter = self.clone();
+        self.spawn_filter(move || {
+            let fragment_access = FragmentAccess::Block(block_access);
+            let mut data = Vec::new();
+            if filter.filter_fragment(fragment_access, is_live, &mut data)? {
+                Ok(Some(data))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+    }
+
+    async fn spawn_filter<T, F>(&self, f: F) -> Result<T, DataStreamError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, DataStreamError> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        // Keep the stream's span as the parent of the spans created by the filter.
+        let span = tracing::Span::current();
+        rayon::spawn(move || {
+            let _span = span.enter();
+            let _ = tx.send(f());
+        });
+
+        rx.record_request(self.metrics.filter_evaluation.clone())
+            .await
+            .change_context(DataStreamError)
+            .attach_printable("filter evaluation task stopped")?
+    }
+
+    #[tracing::instrument(
+        name = "send_data",
+        skip_all,
+        fields(blocks_count, blocks_size_bytes, fragments_count, fragments_size_bytes)
+    )]
+    pub fn filter_fragment(
+        &self,
+        fragment_access: FragmentAccess<'_>,
+        is_live: bool,
+        output: &mut Vec<Bytes>,
+    ) -> Result<bool, DataStreamError> {
+        let block_matches = self.match_fragment(&fragment_access)?;
+        self.encode_fragment(&fragment_access, &block_matches, is_live, output)
+    }
+
+    /// Filter the segment's blocks until one has data.
+    fn next_segment_block(
+        &self,
+        state: &mut SegmentFilterState,
+    ) -> Result<Option<BlockData>, DataStreamError> {
+        let no_matches: BlockMatches = vec![BTreeMap::default(); self.block_filter.len()];
+
+        for block_number in state.blocks.by_ref() {
+            let Some(block_access) = state.segment_access.block(block_number as u64) else {
+                continue;
+            };
+
+            let cursor = block_access.cursor();
+            let fragment_access = FragmentAccess::Segment(block_access);
+
+            let mut data = Vec::new();
+            let has_data = match (state.cached.as_ref(), state.matches.as_mut()) {
+                (Some(cached), _) => {
+                    let block_matches = cached.get(cursor.number).unwrap_or(&no_matches);
+                    self.encode_fragment(&fragment_access, block_matches, false, &mut data)?
+                }
+                (None, Some(matches)) => {
+                    let block_matches = self
+                        .match_fragment(&fragment_access)
+                        .attach_printable_lazy(|| format!("cursor: {}", cursor))?;
+                    let has_data =
+                        self.encode_fragment(&fragment_access, &block_matches, false, &mut data)?;
+                    matches.insert(cursor.number, block_matches);
+                    has_data
+                }
+                (None, None) => self
+                    .filter_fragment(fragment_access, false, &mut data)
+                    .attach_printable_lazy(|| format!("cursor: {}", cursor))?,
+            };
+
+            if has_data {
+                return Ok(Some(BlockData { cursor, data }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the rows matched by each block filter.
+    fn match_fragment(
+        &self,
+        fragment_access: &FragmentAccess<'_>,
+    ) -> Result<BlockMatches, DataStreamError> {
+        let mut block_matches = Vec::with_capacity(self.block_filter.len());
+
+        for block_filter in self.block_filter.iter() {
+            let mut fragment_matches = BTreeMap::default();
+
+            let mut joins = BTreeMap::<(FragmentId, FragmentId), FilterMatch>::default();
+
+            for (fragment_id, filters) in block_filter.iter() {
+                let mut filter_match = FilterMatch::default();
+
+                let Some(indexes) = fragment_access
+                    .get_index_fragment(fragment_id)
+                    .change_context(DataStreamError)
+                    .attach_printable("failed to get fragment indexes")?
+                else {
+                    // Blocks stored before the fragment was introduced don't have it.
+                    continue;
+                };
+
+                for filter in filters {
+                    let rows = filter.filter(indexes).change_context(DataStreamError)?;
+                    filter_match.add_match(filter.filter_id, &rows);
+
+                    for join_with_fragment_id in filter.joins.iter() {
+                        joins
+                            .entry((*fragment_id, *join_with_fragment_id))
+                            .or_default()
+                            .add_match(filter.filter_id, &rows);
+                    }
+                }
+
+                if filter_match.is_empty() {
+                    continue;
+                }
+
+                fragment_matches.insert(*fragment_id, filter_match);
+            }
+
+            for ((source_fragment_id, target_fragment_id), filter_match) in joins.into_iter() {
+                // Data is cached so it's fine to read it multiple times.
+                // We could group by `source_fragment_id` to cleanup the code.
+                let Some(join_fragment) = fragment_access
+                    .get_join_fragment(&source_fragment_id)
+                    .change_context(DataStreamError)
+                    .attach_printable("failed to get join fragment")?
+                else {
+                    continue;
+                };
+
+                // Blocks stored before the target fragment was introduced don't join to it.
+                let Some(join) = join_fragment
+                    .joins
+                    .iter()
+                    .find(|f| f.to_fragment_id == target_fragment_id)
+                else {
+                    continue;
+                };
+
+                let target_fragment_matches =
+                    fragment_matches.entry(target_fragment_id).or_default();
+
+                match &join.index {
+                    ArchivedJoinTo::One(inner) => {
+                        for match_ in filter_match.iter() {
+                            if let Some(index) = inner.get(&match_.index) {
+                                for filter_id in match_.filter_ids.iter() {
+                                    target_fragment_matches.add_single_match(*filter_id, index);
+                                }
+                            }
+                        }
+                    }
+                    ArchivedJoinTo::Many(inner) => {
+                        for match_ in filter_match.iter() {
+                            if let Some(bitmap) = inner.get(&match_.index) {
+                                for filter_id in match_.filter_ids.iter() {
+                                    target_fragment_matches.add_match(*filter_id, &bitmap);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            block_matches.push(fragment_matches);
+        }
+
+        Ok(block_matches)
+    }
+
+    /// Copy the matched rows of each block filter to `output`.
+    fn encode_fragment(
+        &self,
+        fragment_access: &FragmentAccess<'_>,
+        block_matches: &BlockMatches,
+        is_live: bool,
+        output: &mut Vec<Bytes>,
+    ) -> Result<bool, DataStreamError> {
+        let mut has_data = false;
+
+        let mut total_fragments_size_bytes = Vec::with_capacity(self.block_filter.len());
+        let mut total_blocks_size_bytes = Vec::with_capacity(self.block_filter.len());
+
+        for (block_filter, fragment_matches) in self.block_filter.iter().zip(block_matches) {
+            let mut local_fragments_size_bytes = HashMap::<String, usize>::new();
+
+            let should_send_header = match block_filter.header_filter {
+                HeaderFilter::Always => true,
+                HeaderFilter::OnData => !fragment_matches.is_empty(),
+                HeaderFilter::OnDataOrOnNewBlock => !fragment_matches.is_empty() || is_live,
+            };
+
+            let header = if should_send_header {
+                let header = fragment_access
+                    .get_header_fragment()
+                    .change_context(DataStreamError)
+                    .attach_printable("failed to get header fragment")?;
+                let header = match &block_filter.header_fields {
+                    Some(fields) => Cow::Owned(fields.project(header.data.as_slice())),
+                    None => Cow::Borrowed(header.data.as_slice()),
+                };
+                Some(header)
+            } else {
+                None
+            };
+
+            // Collect the matched messages first to allocate the block's buffer once,
+            // with its exact size.
+            let mut block_size = header
+                .as_ref()
+                .map(|header| field_encoded_len(HEADER_FRAGMENT_ID, header.len()))
+                .unwrap_or_default();
+
+            let mut fragments = Vec::with_capacity(fragment_matches.len());
+            for (fragment_id, filter_match) in fragment_matches.iter() {
+                // Header matches only select the block, the header is sent above.
+                if *fragment_id == HEADER_FRAGMENT_ID {
+                    continue;
+                }
+
+                let Some(fragment_name) = self.fragment_id_to_name.get(fragment_id).cloned() else {
+                    return Err(DataStreamError)
+                        .attach_printable("unknown fragment id")
+                        .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
+                };
+
+                let body = fragment_access
+                    .get_body_fragment(fragment_id)
+                    .change_context(DataStreamError)
+                    .attach_printable("failed to get body fragment")?;
+
+                let mut fragment_size = 0;
+                let mut messages = Vec::new();
+                for match_ in filter_match.iter() {
+                    let message_bytes = body.data[match_.index as usize].as_slice();
+                    let message_bytes =
+                        match block_filter.field_projection(*fragment_id, &match_.filter_ids) {
+                            Some(fields) => Cow::Owned(fields.project(message_bytes)),
+                            None => Cow::Borrowed(message_bytes),
+                        };
+                    let filter_ids_len = prost::encoding::uint32::encoded_len_packed(
+                        FILTER_IDS_TAG,
+                        &match_.filter_ids,
+                    );
+
+                    fragment_size +=
+                        field_encoded_len(*fragment_id, filter_ids_len + message_bytes.len());
+                    messages.push((match_.filter_ids, filter_ids_len, message_bytes));
+                }
+
+                block_size += fragment_size;
+                *local_fragments_size_bytes.entry(fragment_name).or_default() += fragment_size;
+                fragments.push((*fragment_id, messages));
+            }
+
+            let data = if block_size == 0 {
+                Bytes::new()
+            } else {
+                let mut data_buffer = BytesMut::with_capacity(block_size);
+
+                if let Some(header) = header {
+                    prost::encoding::encode_key(
+                        HEADER_FRAGMENT_ID as u32,
+                        prost::encoding::WireType::LengthDelimited,
+                        &mut data_buffer,
+                    );
+                    prost::encoding::encode_varint(header.len() as u64, &mut data_buffer);
+                    data_buffer.put(header.as_ref());
+                }
+
+                for (fragment_id, messages) in fragments {
+                    for (filter_ids, filter_ids_len, message_bytes) in messages {
+                        prost::encoding::encode_key(
+                            fragment_id as u32,
+                            prost::encoding::WireType::LengthDelimited,
+                            &mut data_buffer,
+                        );
+
+                        prost::encoding::encode_varint(
+                            (filter_ids_len + message_bytes.len()) as u64,
+                            &mut data_buffer,
+                        );
+
+                        prost::encoding::uint32::encode_packed(
+                            FILTER_IDS_TAG,
+                            &filter_ids,
+                            &mut data_buffer,
+                        );
+                        data_buffer.put(message_bytes.as_ref());
+                    }
+                }
+
+                debug_assert_eq!(data_buffer.len(), block_size);
+                data_buffer.freeze()
+            };
+
+            if !data.is_empty() {
+                has_data = true;
+            }
+
+            total_blocks_size_bytes.push(data.len());
+            total_fragments_size_bytes.push(local_fragments_size_bytes);
+
+            output.push(data);
+        }
+
+        if has_data {
+            for block_size in total_blocks_size_bytes {
+                self.metrics.block_size.record(block_size as u64, &[]);
+            }
+
+            for block_fragment_size_bytes in total_fragments_size_bytes {
+                for (fragment_name, fragment_size_bytes) in block_fragment_size_bytes {
+                    self.metrics.fragment_size.record(
+                        fragment_size_bytes as u64,
+                        &[KeyValue::new("name", fragment_name)],
+                    );
+                }
+            }
+        }
+
+        Ok(has_data)
+    }
+}
+
+/// The blocks of a segment that match the filters, evaluated one at a time.
+///
+/// Each call to `next` evaluates the segment's blocks on the rayon thread pool
+/// until it finds one with data. The next block is evaluated only after the
+/// stream asks for it, so a stream that waits for its client doesn't filter
+/// and buffer the rest of the segment.
+pub struct SegmentFilterStream {
+    filter: FragmentFilter,
+    /// Moved to the thread pool while evaluating, `None` after an error.
+    state: Option<SegmentFilterState>,
+}
+
+struct SegmentFilterState {
+    segment_access: SegmentAccess,
+    /// The blocks left to evaluate.
+    blocks: std::vec::IntoIter<u32>,
+    cached: Option<Arc<SegmentMatches>>,
+    matches: Option<SegmentMatches>,
+}
+
+impl SegmentFilterStream {
+    /// Returns the next block with data, or `None` once the segment is done.
+    pub async fn next(&mut self) -> Result<Option<BlockData>, DataStreamError> {
+        let Some(mut state) = self.state.take() else {
+            return Ok(None);
+        };
+
+        let filter = self.filter.clone();
+        let (state, block) = self
+            .filter
+            .spawn_filter(move || {
+                let block = filter.next_segment_block(&mut state)?;
+                Ok((state, block))
+            })
+            .await?;
+
+        self.state = Some(state);
+
+        Ok(block)
+    }
+
+    /// Returns the rows matched in the whole segment, once all blocks are filtered.
+    pub fn take_matches(&mut self) -> Option<SegmentMatches> {
+        let state = self.state.as_mut()?;
+        if !state.blocks.as_slice().is_empty() {
+            return None;
+        }
+        state.matches.take()
+    }
+}
+
+/// Returns the size of a length-delimited field with the given tag and data length.
+fn field_encoded_len(fragment_id: FragmentId, len: usize) -> usize {
+    prost::encoding::key_len(fragment_id as u32)
+        + prost::encoding::encoded_len_varint(len as u64)
+        + len
+}
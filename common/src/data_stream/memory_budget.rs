@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use apibara_dna_protocol::dna::stream::StreamDataResponse;
+use apibara_observability::UpDownCounter;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits how many bytes of messages a stream buffers for its client.
+///
+/// Messages hold their share of the budget until they're handed to the gRPC
+/// server, so a client that reads slowly stops the stream from producing new
+/// data instead of growing the server's memory.
+#[derive(Clone)]
+pub struct StreamMemoryBudget {
+    semaphore: Option<Arc<Semaphore>>,
+    max_bytes: usize,
+    buffered_bytes: UpDownCounter<i64>,
+}
+
+/// Bytes reserved by a message, released when the message is dropped.
+pub struct MemoryReservation {
+    permit: Option<OwnedSemaphorePermit>,
+    size: usize,
+    buffered_bytes: UpDownCounter<i64>,
+}
+
+/// A message for the client, together with its memory reservation.
+pub struct DataStreamMessage {
+    response: tonic::Result<StreamDataResponse, tonic::Status>,
+    _reservation: Option<MemoryReservation>,
+}
+
+impl StreamMemoryBudget {
+    /// Creates a new memory budget.
+    ///
+    /// If `max_bytes` is 0, the budget is unlimited.
+    pub fn new(max_bytes: usize, buffered_bytes: UpDownCounter<i64>) -> Self {
+        // The semaphore tracks bytes with `u32` permits.
+        let max_bytes = max_bytes.min(u32::MAX as usize);
+        let semaphore = if max_bytes > 0 {
+            Some(Arc::new(Semaphore::new(max_bytes)))
+        } else {
+            None
+        };
+
+        Self {
+            semaphore,
+            max_bytes,
+            buffered_bytes,
+        }
+    }
+
+    /// Wait until `size` bytes are available in the budget.
+    ///
+    /// Messages larger than the budget wait until all other messages are sent.
+    pub async fn reserve(&self, size: usize) -> MemoryReservation {
+        let permit = if let Some(semaphore) = &self.semaphore {
+            let permits = size.clamp(1, self.max_bytes) as u32;
+            let permit = semaphore
+                .clone()
+                .acquire_many_owned(permits)
+                .await
+                .expect("memory budget semaphore is never closed");
+            Some(permit)
+        } else {
+            None
+        };
+
+        self.buffered_bytes.add(size as i64, &[]);
+
+        MemoryReservation {
+            permit,
+            size,
+            buffered_bytes: self.buffered_bytes.clone(),
+        }
+    }
+}
+
+impl MemoryReservation {
+    /// Move up to `size` bytes of this reservation to a new reservation.
+    pub fn split(&mut self, size: usize) -> MemoryReservation {
+        let size = size.min(self.size);
+        self.size -= size;
+
+        // Reservations larger than the budget hold fewer permits than bytes.
+        let permit = self.permit.as_mut().and_then(|permit| {
+            let permits = size.min(permit.num_permits());
+            permit.split(permits)
+        });
+
+        MemoryReservation {
+            permit,
+            size,
+            buffered_bytes: self.buffered_bytes.clone(),
+        }
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.buffered_bytes.add(-(self.size as i64), &[]);
+    }
+}
+
+impl DataStreamMessage {
+    pub fn new(
+        response: tonic::Result<StreamDataResponse, tonic::Status>,
+        reservation: Option<MemoryReservation>,
+    ) -> Self {
+        Self {
+            response,
+            _reservation: reservation,
+        }
+    }
+
+    /// Returns the response, releasing the message's memory reservation.
+    pub fn into_response(self) -> tonic::Result<StreamDataResponse, tonic::Status> {
+        self.response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::StreamMemoryBudget;
+
+    #[tokio::test]
+    async fn test_memory_budget_backpressure() {
+        let buffered_bytes = apibara_observability::meter("test")
+            .i64_up_down_counter("test.buffered_bytes")
+            .build();
+        let budget = StreamMemoryBudget::new(100, buffered_bytes);
+
+        let first = budget.reserve(60).await;
+
+        let second = tokio::time::timeout(Duration::from_millis(50), budget.reserve(60)).await;
+        assert!(second.is_err());
+
+        drop(first);
+
+        // Messages larger than the budget are still sent.
+        let large = tokio::time::timeout(Duration::from_millis(50), budget.reserve(1_000)).await;
+        assert!(large.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_memory_reservation_split() {
+        let buffered_bytes = apibara_observability::meter("test")
+            .i64_up_down_counter("test.buffered_bytes")
+            .build();
+        let budget = StreamMemoryBudget::new(100, buffered_bytes);
+
+        let mut segment = budget.reserve(80).await;
+        let block = segment.split(50);
+        drop(segment);
+
+        // The bytes split from the segment are still reserved.
+        let second = tokio::time::timeout(Duration::from_millis(50), budget.reserve(60)).await;
+        assert!(second.is_err());
+
+        drop(block);
+
+        let second = tokio::time::timeout(Duration::from_millis(50), budget.reserve(60)).await;
+        assert!(second.is_ok());
+    }
+}
@@ -12,6 +12,12 @@ pub struct DataStreamMetrics {
     pub group_download: RequestMetrics,
     pub group_wait: RequestMetrics,
     pub group_cache_hit: Counter<u64>,
+    pub filter_evaluation: RequestMetrics,
+    pub shared_filter_hit: Counter<u64>,
+    pub filter_result_hit: Counter<u64>,
+    pub buffered_bytes: UpDownCounter<i64>,
+    pub slow_consumer: Counter<u64>,
+    pub slow_consumer_disconnect: Counter<u64>,
 }
 
 impl Default for DataStreamMetrics {
@@ -111,6 +117,37 @@ impl Default for DataStreamMetrics {
                 .u64_counter("dna.data_stream.group_cache_hit")
                 .with_description("number of group cache hits")
                 .build(),
+            filter_evaluation: RequestMetrics::new_with_boundaries(
+                "dna_data_stream",
+                "dna.data_stream.filter_evaluation",
+                vec![
+                    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.0075, 0.01, 0.025, 0.05,
+                    0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0,
+                ],
+            ),
+            shared_filter_hit: meter
+                .u64_counter("dna.data_stream.shared_filter_hit")
+                .with_description("number of segments filtered once for multiple streams")
+                .build(),
+            filter_result_hit: meter
+                .u64_counter("dna.data_stream.filter_result_hit")
+                .with_description("number of segments filtered with cached filter results")
+                .build(),
+            buffered_bytes: meter
+                .i64_up_down_counter("dna.data_stream.buffered_bytes")
+                .with_description("size (in bytes) of messages waiting to be sent to clients")
+                .with_unit("By")
+                .build(),
+            slow_consumer: meter
+                .u64_counter("dna.data_stream.slow_consumer")
+                .with_description("number of times a client stopped reading its stream")
+                .build(),
+            slow_consumer_disconnect: meter
+                .u64_counter("dna.data_stream.slow_consumer_disconnect")
+                .with_description(
+                    "number of streams disconnected because the client stopped reading",
+                )
+                .build(),
         }
     }
 }
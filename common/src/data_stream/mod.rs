@@ -1,14 +1,24 @@
 mod filter;
 mod fragment_access;
+mod fragment_filter;
+mod memory_budget;
 mod metrics;
+mod result_cache;
 mod segment_access;
 mod segment_stream;
+mod shared_filter;
 mod stream;
 mod stream_group;
 
 pub use self::filter::{BlockFilterFactory, FilterMatch};
 pub use self::fragment_access::FragmentAccess;
+pub use self::fragment_filter::{BlockData, BlockMatches, FragmentFilter, SegmentFilterStream};
+pub use self::memory_budget::{DataStreamMessage, MemoryReservation, StreamMemoryBudget};
 pub use self::metrics::DataStreamMetrics;
+pub use self::result_cache::{FilterResultCache, SegmentMatches};
 pub use self::segment_access::{SegmentAccess, SegmentAccessFetch};
 pub use self::segment_stream::SegmentStream;
-pub use self::stream::{DataStream, DataStreamError};
+pub use self::shared_filter::{
+    filter_key, FilterKey, SegmentBlocks, SharedFilter, SharedFilterCache,
+};
+pub use self::stream::{DataStream, DataStreamError, SlowConsumerPolicy};
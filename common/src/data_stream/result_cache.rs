@@ -0,0 +1,193 @@
+//! Cache the rows matched by filters in each segment.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::fragment::FragmentId;
+
+use super::{fragment_filter::BlockMatches, shared_filter::SegmentKey};
+
+/// The rows matched by a filter in the blocks of a segment.
+///
+/// Blocks without matches are not stored.
+#[derive(Debug, Default)]
+pub struct SegmentMatches {
+    blocks: BTreeMap<u64, BlockMatches>,
+    size_bytes: usize,
+}
+
+/// Cache the rows matched by popular filters, keyed by filter and segment.
+///
+/// Matched rows are much smaller than the data they point to, so the cache can
+/// keep the results of many more segments than the shared filter cache. Streams
+/// that backfill a filter that was already evaluated skip evaluating its
+/// conditions and only copy the data of the matched rows.
+///
+/// The cache is disabled if its size is zero.
+#[derive(Clone)]
+pub struct FilterResultCache {
+    inner: Arc<Mutex<FilterResultCacheInner>>,
+}
+
+struct FilterResultCacheInner {
+    max_size_bytes: usize,
+    size_bytes: usize,
+    /// Incremented on each access, used to evict the least recently used results.
+    clock: u64,
+    entries: HashMap<SegmentKey, CachedMatches>,
+}
+
+struct CachedMatches {
+    matches: Arc<SegmentMatches>,
+    last_used: u64,
+}
+
+impl SegmentMatches {
+    pub fn insert(&mut self, block_number: u64, block_matches: BlockMatches) {
+        if block_matches.iter().all(BTreeMap::is_empty) {
+            return;
+        }
+
+        self.size_bytes += block_matches_size_bytes(&block_matches);
+        self.blocks.insert(block_number, block_matches);
+    }
+
+    pub fn get(&self, block_number: u64) -> Option<&BlockMatches> {
+        self.blocks.get(&block_number)
+    }
+
+    /// Returns an estimate of the memory used, including segments without matches.
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.size_bytes
+    }
+}
+
+impl FilterResultCache {
+    pub fn new(max_size_bytes: usize) -> Self {
+        let inner = FilterResultCacheInner {
+            max_size_bytes,
+            size_bytes: 0,
+            clock: 0,
+            entries: HashMap::default(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let inner = self.inner.lock().expect("filter result cache lock");
+        inner.max_size_bytes > 0
+    }
+
+    pub fn get(&self, segment_key: SegmentKey) -> Option<Arc<SegmentMatches>> {
+        let mut inner = self.inner.lock().expect("filter result cache lock");
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let entry = inner.entries.get_mut(&segment_key)?;
+        entry.last_used = clock;
+
+        Some(entry.matches.clone())
+    }
+
+    pub fn insert(&self, segment_key: SegmentKey, matches: Arc<SegmentMatches>) {
+        let mut inner = self.inner.lock().expect("filter result cache lock");
+
+        let size_bytes = matches.size_bytes();
+        if size_bytes > inner.max_size_bytes {
+            return;
+        }
+
+        // Evict the least recently used results to make space.
+        while inner.size_bytes + size_bytes > inner.max_size_bytes {
+            let Some(oldest) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(segment_key, _)| *segment_key)
+            else {
+                break;
+            };
+
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.size_bytes -= evicted.matches.size_bytes();
+            }
+        }
+
+        inner.clock += 1;
+        let entry = CachedMatches {
+            matches,
+            last_used: inner.clock,
+        };
+
+        if let Some(replaced) = inner.entries.insert(segment_key, entry) {
+            inner.size_bytes -= replaced.matches.size_bytes();
+        }
+        inner.size_bytes += size_bytes;
+    }
+}
+
+fn block_matches_size_bytes(block_matches: &BlockMatches) -> usize {
+    block_matches
+        .iter()
+        .flat_map(|fragment_matches| fragment_matches.values())
+        .map(|filter_match| std::mem::size_of::<FragmentId>() + filter_match.size_bytes())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use roaring::RoaringBitmap;
+
+    use crate::data_stream::FilterMatch;
+
+    use super::{FilterResultCache, SegmentMatches};
+
+    fn segment_matches(rows: u32) -> SegmentMatches {
+        let mut filter_match = FilterMatch::default();
+        filter_match.add_match(1, &RoaringBitmap::from_iter(0..rows));
+
+        let mut matches = SegmentMatches::default();
+        matches.insert(100, vec![BTreeMap::from([(2, filter_match)])]);
+        matches.insert(101, vec![BTreeMap::default()]);
+        matches
+    }
+
+    #[test]
+    fn test_segment_matches() {
+        let matches = segment_matches(10);
+        assert!(matches.get(100).is_some());
+        // Blocks without matches are not stored.
+        assert!(matches.get(101).is_none());
+        assert!(matches.size_bytes() > 0);
+    }
+
+    #[test]
+    fn test_filter_result_cache() {
+        let size = segment_matches(10).size_bytes();
+        let cache = FilterResultCache::new(size * 2);
+
+        cache.insert(([1; 32], 0, 1000), Arc::new(segment_matches(10)));
+        cache.insert(([1; 32], 1000, 1000), Arc::new(segment_matches(10)));
+        assert!(cache.get(([1; 32], 0, 1000)).is_some());
+        assert!(cache.get(([2; 32], 0, 1000)).is_none());
+        // Segments of another layout starting at the same block.
+        assert!(cache.get(([1; 32], 0, 500)).is_none());
+
+        // The least recently used result is evicted.
+        cache.insert(([2; 32], 0, 1000), Arc::new(segment_matches(10)));
+        assert!(cache.get(([1; 32], 0, 1000)).is_some());
+        assert!(cache.get(([1; 32], 1000, 1000)).is_none());
+        assert!(cache.get(([2; 32], 0, 1000)).is_some());
+
+        let disabled = FilterResultCache::new(0);
+        assert!(!disabled.is_enabled());
+        disabled.insert(([1; 32], 0, 1000), Arc::new(segment_matches(10)));
+        assert!(disabled.get(([1; 32], 0, 1000)).is_none());
+    }
+}
@@ -22,15 +22,22 @@ pub type FileEntry = CacheEntry<String, Bytes>;
 
 pub struct SegmentAccessFetch {
     first_block: u64,
+    segment_size: u64,
     blocks: RoaringBitmap,
-    fragments: HashMap<FragmentId, RecordedRequest<FileFetch>>,
+    /// Fragment files, keyed by fragment id and the file's first block.
+    fragments: HashMap<(FragmentId, u64), RecordedRequest<FileFetch>>,
+    fragment_segment_size: HashMap<FragmentId, u64>,
     created_at: Instant,
 }
 
 pub struct SegmentAccess {
     pub first_block: u64,
+    /// Segment size of the layout the segment was read with.
+    pub segment_size: u64,
     pub blocks: RoaringBitmap,
-    fragments: HashMap<FragmentId, FileEntry>,
+    fragments: HashMap<(FragmentId, u64), FileEntry>,
+    /// Segment size of the fragments split into smaller segments.
+    fragment_segment_size: HashMap<FragmentId, u64>,
 }
 
 pub struct SegmentAccessIter<'a> {
@@ -44,17 +51,44 @@ pub struct SegmentBlockAccess<'a> {
 }
 
 impl SegmentAccessFetch {
-    pub fn new(first_block: u64, blocks: RoaringBitmap) -> Self {
+    pub fn new(first_block: u64, segment_size: u64, blocks: RoaringBitmap) -> Self {
         Self {
             first_block,
+            segment_size,
             blocks,
             fragments: HashMap::new(),
+            fragment_segment_size: HashMap::new(),
             created_at: Instant::now(),
         }
     }
 
     pub fn insert_fragment(&mut self, fragment_id: FragmentId, fetch: RecordedRequest<FileFetch>) {
-        self.fragments.insert(fragment_id, fetch);
+        self.fragments
+            .insert((fragment_id, self.first_block), fetch);
+    }
+
+    /// Returns true if none of the segment's blocks must be accessed.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns true if any of the blocks in the range must be accessed.
+    pub fn has_blocks_in_range(&self, range: std::ops::Range<u64>) -> bool {
+        let mut range_blocks = RoaringBitmap::new();
+        range_blocks.insert_range(range.start as u32..range.end as u32);
+        !range_blocks.is_disjoint(&self.blocks)
+    }
+
+    /// Insert one of the smaller segments of a fragment with a custom segment size.
+    pub fn insert_fragment_segment(
+        &mut self,
+        fragment_id: FragmentId,
+        segment_size: u64,
+        first_block: u64,
+        fetch: RecordedRequest<FileFetch>,
+    ) {
+        self.fragment_segment_size.insert(fragment_id, segment_size);
+        self.fragments.insert((fragment_id, first_block), fetch);
     }
 
     pub async fn wait(
@@ -65,16 +99,18 @@ impl SegmentAccessFetch {
 
         let mut access = SegmentAccess {
             first_block: self.first_block,
+            segment_size: self.segment_size,
             blocks: self.blocks,
             fragments: Default::default(),
+            fragment_segment_size: self.fragment_segment_size,
         };
 
-        for (fragment_id, fetch) in self.fragments.into_iter() {
+        for (key, fetch) in self.fragments.into_iter() {
             let file = fetch
                 .await
                 .map_err(FileCacheError::Foyer)
                 .change_context(FragmentAccessError)?;
-            access.fragments.insert(fragment_id, file);
+            access.fragments.insert(key, file);
         }
 
         metrics.time_in_queue.record(elapsed.as_secs_f64(), &[]);
@@ -94,6 +130,18 @@ impl SegmentAccess {
             inner: self.blocks.iter(),
         }
     }
+
+    /// Returns the access to the block, if it's one of the blocks to access.
+    pub fn block(&self, block_number: u64) -> Option<SegmentBlockAccess<'_>> {
+        if block_number < self.first_block || !self.blocks.contains(block_number as u32) {
+            return None;
+        }
+
+        Some(SegmentBlockAccess {
+            segment: self,
+            offset: (block_number - self.first_block) as usize,
+        })
+    }
 }
 
 impl<'a> SegmentBlockAccess<'a> {
@@ -105,14 +153,28 @@ impl<'a> SegmentBlockAccess<'a> {
         self.segment.first_block + self.offset as u64
     }
 
-    pub fn get_index_fragment(
-        &self,
-        fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<IndexFragment>, FragmentAccessError> {
+    /// Returns the file with the fragment's data and the block offset in the file.
+    fn fragment_entry(&self, fragment_id: &FragmentId) -> Option<(&'a FileEntry, usize)> {
+        let offset = self.offset as u64;
+        let segment_offset = match self.segment.fragment_segment_size.get(fragment_id) {
+            Some(segment_size) => offset / segment_size * segment_size,
+            None => 0,
+        };
+
         let entry = self
             .segment
             .fragments
-            .get(&INDEX_FRAGMENT_ID)
+            .get(&(*fragment_id, self.segment.first_block + segment_offset))?;
+
+        Some((entry, (offset - segment_offset) as usize))
+    }
+
+    pub fn get_index_fragment(
+        &self,
+        fragment_id: &FragmentId,
+    ) -> Result<Option<&'a rkyv::Archived<IndexFragment>>, FragmentAccessError> {
+        let (entry, offset) = self
+            .fragment_entry(&INDEX_FRAGMENT_ID)
             .ok_or(FragmentAccessError)
             .attach_printable("index fragment not found")?;
 
@@ -120,30 +182,21 @@ impl<'a> SegmentBlockAccess<'a> {
             rkyv::access_unchecked::<rkyv::Archived<Segment<IndexGroupFragment>>>(entry.value())
         };
 
-        let block_index = &segment.data[self.offset];
+        let block_index = &segment.data[offset];
 
-        let Some(pos) = block_index
+        Ok(block_index
             .data
             .indexes
             .iter()
-            .position(|f| f.fragment_id == *fragment_id)
-        else {
-            return Err(FragmentAccessError)
-                .attach_printable("index for fragment not found")
-                .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-        };
-
-        Ok(&block_index.data.indexes[pos])
+            .find(|f| f.fragment_id == *fragment_id))
     }
 
     pub fn get_join_fragment(
         &self,
         fragment_id: &FragmentId,
-    ) -> Result<&'a rkyv::Archived<JoinFragment>, FragmentAccessError> {
-        let entry = self
-            .segment
-            .fragments
-            .get(&JOIN_FRAGMENT_ID)
+    ) -> Result<Option<&'a rkyv::Archived<JoinFragment>>, FragmentAccessError> {
+        let (entry, offset) = self
+            .fragment_entry(&JOIN_FRAGMENT_ID)
             .ok_or(FragmentAccessError)
             .attach_printable("join fragment not found")?;
 
@@ -151,29 +204,20 @@ impl<'a> SegmentBlockAccess<'a> {
             rkyv::access_unchecked::<rkyv::Archived<Segment<JoinGroupFragment>>>(entry.value())
         };
 
-        let block_index = &segment.data[self.offset];
+        let block_index = &segment.data[offset];
 
-        let Some(pos) = block_index
+        Ok(block_index
             .data
             .joins
             .iter()
-            .position(|f| f.fragment_id == *fragment_id)
-        else {
-            return Err(FragmentAccessError)
-                .attach_printable("join for fragment not found")
-                .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-        };
-
-        Ok(&block_index.data.joins[pos])
+            .find(|f| f.fragment_id == *fragment_id))
     }
 
     pub fn get_header_fragment(
         &self,
     ) -> Result<&'a rkyv::Archived<HeaderFragment>, FragmentAccessError> {
-        let entry = self
-            .segment
-            .fragments
-            .get(&HEADER_FRAGMENT_ID)
+        let (entry, offset) = self
+            .fragment_entry(&HEADER_FRAGMENT_ID)
             .ok_or(FragmentAccessError)
             .attach_printable("header fragment not found")?;
 
@@ -181,17 +225,15 @@ impl<'a> SegmentBlockAccess<'a> {
             rkyv::access_unchecked::<rkyv::Archived<Segment<HeaderFragment>>>(entry.value())
         };
 
-        Ok(&segment.data[self.offset].data)
+        Ok(&segment.data[offset].data)
     }
 
     pub fn get_body_fragment(
         &self,
         fragment_id: &FragmentId,
     ) -> Result<&'a rkyv::Archived<BodyFragment>, FragmentAccessError> {
-        let entry = self
-            .segment
-            .fragments
-            .get(fragment_id)
+        let (entry, offset) = self
+            .fragment_entry(fragment_id)
             .ok_or(FragmentAccessError)
             .attach_printable("body fragment not found")
             .attach_printable_lazy(|| format!("fragment id: {}", fragment_id))
@@ -201,7 +243,7 @@ impl<'a> SegmentBlockAccess<'a> {
             rkyv::access_unchecked::<rkyv::Archived<Segment<BodyFragment>>>(entry.value())
         };
 
-        Ok(&segment.data[self.offset].data)
+        Ok(&segment.data[offset].data)
     }
 }
 
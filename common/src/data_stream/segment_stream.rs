@@ -15,9 +15,12 @@ use crate::{
     chain_view::ChainView,
     data_stream::SegmentAccessFetch,
     file_cache::FileCacheError,
-    fragment::{FragmentId, HEADER_FRAGMENT_ID, INDEX_FRAGMENT_ID, JOIN_FRAGMENT_ID},
+    fragment::{
+        ArchivedIndexGroupFragment, FragmentId, IndexGroupFragment, HEADER_FRAGMENT_ID,
+        INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_ID,
+    },
     query::BlockFilter,
-    segment::SegmentGroup,
+    segment::{Segment, SegmentGroup},
     Cursor,
 };
 
@@ -52,14 +55,22 @@ impl SegmentStream {
         }
     }
 
+    /// Send the segments starting from the segment that contains the starting cursor.
+    ///
+    /// The stream reads the segments of the layout current when it starts. It ends
+    /// early if the segments are rewritten with a new layout, so that the consumer
+    /// can restart it with the new layout.
     pub async fn start(
-        self,
+        mut self,
         starting_cursor: Cursor,
         tx: mpsc::Sender<SegmentAccessFetch>,
         ct: CancellationToken,
     ) -> Result<(), DataStreamError> {
-        let group_size = self.chain_view.get_group_size().await;
-        let segment_size = self.chain_view.get_segment_size().await;
+        let layout = self.chain_view.get_segment_layout().await;
+        let group_size = layout.group_size;
+        let segment_size = layout.segment_size;
+        let fragment_segment_size = &layout.fragment_segment_size;
+        self.store = self.store.with_segment_generation(layout.generation);
 
         debug!(starting_cursor = %starting_cursor, "segment_stream: starting");
 
@@ -83,7 +94,7 @@ impl SegmentStream {
             let mut next_group_to_fetch = current_block_number;
 
             while current_block_number <= grouped.number {
-                if ct.is_cancelled() {
+                if ct.is_cancelled() || self.layout_changed(layout.generation).await {
                     return Ok(());
                 }
 
@@ -138,8 +149,6 @@ impl SegmentStream {
                     rkyv::access_unchecked::<rkyv::Archived<SegmentGroup>>(group_entry.value())
                 };
 
-                let mut blocks_with_data = RoaringBitmap::default();
-
                 // Some users stream block headers to benchmark the service. Avoid fetching useless index and join fragments.
                 let mut fragment_ids_needed = if self.block_filter.is_empty() {
                     HashSet::from([HEADER_FRAGMENT_ID])
@@ -148,45 +157,22 @@ impl SegmentStream {
                 };
 
                 // Use the group indices to compute which blocks have data for the client-provided filters.
-                for block_filter in self.block_filter.iter() {
-                    // If the client requested all headers include all blocks in the group.
-                    if block_filter.always_include_header() {
-                        let group_start = current_block_number as u32;
-                        let group_end_non_inclusive =
-                            (current_block_number + group_size * segment_size) as u32;
-                        let group_block_range =
-                            RoaringBitmap::from_sorted_iter(group_start..group_end_non_inclusive)
-                                .expect("failed to create bitmap from sorted iter");
-                        blocks_with_data |= group_block_range;
-                    }
-
-                    for (fragment_id, filters) in block_filter.iter() {
-                        let Some(pos) = group
-                            .index
-                            .indexes
-                            .iter()
-                            .position(|f| f.fragment_id == *fragment_id)
-                        else {
-                            return Err(DataStreamError)
-                                .attach_printable("missing index")
-                                .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-                        };
-
-                        let indexes = &group.index.indexes[pos];
-
-                        for filter in filters {
-                            let rows = filter.filter(indexes).change_context(DataStreamError)?;
-                            if rows.is_empty() {
-                                continue;
-                            }
-
-                            blocks_with_data |= &rows;
-                            fragment_ids_needed.insert(*fragment_id);
-                            for join_with_fragment_id in filter.joins.iter() {
-                                fragment_ids_needed.insert(*join_with_fragment_id);
-                            }
-                        }
-                    }
+                let mut blocks_with_data = self
+                    .filter_index(&group.index, &mut fragment_ids_needed)
+                    .attach_printable_lazy(|| {
+                        format!(
+                            "blocks: {}-{}",
+                            current_block_number,
+                            current_block_number + group_size * segment_size - 1
+                        )
+                    })?;
+
+                // If the client requested all headers include all blocks in the group.
+                if self.always_include_header() {
+                    let group_start = current_block_number as u32;
+                    let group_end_non_inclusive =
+                        (current_block_number + group_size * segment_size) as u32;
+                    blocks_with_data.insert_range(group_start..group_end_non_inclusive);
                 }
 
                 // Now start fetching data for all segments/fragments that are needed.
@@ -199,34 +185,28 @@ impl SegmentStream {
                     .expect("failed to create bitmap from sorted iter");
                     segment_block_range &= &blocks_with_data;
 
-                    if segment_block_range.is_empty() {
-                        continue;
-                    }
-
                     debug!(segment_start, "segment_stream: fetching segment");
 
                     let mut segment_fetch =
-                        SegmentAccessFetch::new(segment_start, segment_block_range);
-
-                    let segment_cursor = Cursor::new_finalized(segment_start);
-
-                    for fragment_id in fragment_ids_needed.iter() {
-                        let segment_name = self
-                            .fragment_id_to_name
-                            .get(fragment_id)
-                            .ok_or(DataStreamError)
-                            .attach_printable("expected fragment id to have a name")
-                            .attach_printable_lazy(|| format!("fragment_id: {fragment_id}"))?;
-                        let fragment_fetch = self
-                            .store
-                            .get_segment(&segment_cursor, segment_name)
-                            .record_request_with_attributes(
-                                self.metrics.segment_download.clone(),
-                                &[KeyValue::new("name", segment_name.clone())],
-                            );
-                        segment_fetch.insert_fragment(*fragment_id, fragment_fetch);
+                        SegmentAccessFetch::new(segment_start, segment_size, segment_block_range);
+
+                    // Send segments without data anyway so that the consumer can
+                    // move its cursor past them.
+                    if segment_fetch.is_empty() {
+                        let Ok(_) = tx.send(segment_fetch).await else {
+                            return Ok(());
+                        };
+                        continue;
                     }
 
+                    self.insert_fragment_fetches(
+                        &mut segment_fetch,
+                        segment_start,
+                        segment_size,
+                        &fragment_ids_needed,
+                        fragment_segment_size,
+                    )?;
+
                     let Ok(_) = tx.send(segment_fetch).await else {
                         return Ok(());
                     };
@@ -256,8 +236,15 @@ impl SegmentStream {
             .await
             .change_context(DataStreamError)?
         {
+            // The cursor is not at the start of a segment if the stream restarted
+            // after the segments were rewritten with a different size.
+            current_block_number = self
+                .chain_view
+                .get_segment_start_block(current_block_number)
+                .await;
+
             while current_block_number <= segmented.number {
-                if ct.is_cancelled() {
+                if ct.is_cancelled() || self.layout_changed(layout.generation).await {
                     return Ok(());
                 }
 
@@ -266,39 +253,35 @@ impl SegmentStream {
                     "segment_stream: fetching segment"
                 );
 
-                let segment_start = current_block_number as u32;
-                let segment_end_non_inclusive = (current_block_number + segment_size) as u32;
-                let segment_block_range =
-                    RoaringBitmap::from_sorted_iter(segment_start..segment_end_non_inclusive)
-                        .expect("failed to create bitmap from sorted iter");
-
                 let mut fragment_ids_needed =
                     HashSet::from([INDEX_FRAGMENT_ID, JOIN_FRAGMENT_ID, HEADER_FRAGMENT_ID]);
 
-                // In this case we just fetch all fragments that may be used by the filter.
-                for block_filter in self.block_filter.iter() {
-                    fragment_ids_needed.extend(block_filter.all_fragment_ids());
+                // The segment is not grouped yet, use the blocks' indices to compute
+                // which blocks have data.
+                let mut segment_block_range = self
+                    .segment_blocks_with_data(current_block_number, &mut fragment_ids_needed)
+                    .await?;
+
+                if self.always_include_header() {
+                    let segment_start = current_block_number as u32;
+                    let segment_end_non_inclusive = (current_block_number + segment_size) as u32;
+                    segment_block_range.insert_range(segment_start..segment_end_non_inclusive);
                 }
 
-                let mut segment_fetch =
-                    SegmentAccessFetch::new(current_block_number, segment_block_range);
-
-                let segment_cursor = Cursor::new_finalized(current_block_number);
-                for fragment_id in fragment_ids_needed.iter() {
-                    let segment_name = self
-                        .fragment_id_to_name
-                        .get(fragment_id)
-                        .ok_or(DataStreamError)
-                        .attach_printable("expected fragment id to have a name")
-                        .attach_printable_lazy(|| format!("fragment_id: {fragment_id}"))?;
-                    let fragment_fetch = self
-                        .store
-                        .get_segment(&segment_cursor, segment_name)
-                        .record_request_with_attributes(
-                            self.metrics.segment_download.clone(),
-                            &[KeyValue::new("name", segment_name.clone())],
-                        );
-                    segment_fetch.insert_fragment(*fragment_id, fragment_fetch);
+                let mut segment_fetch = SegmentAccessFetch::new(
+                    current_block_number,
+                    segment_size,
+                    segment_block_range,
+                );
+
+                if !segment_fetch.is_empty() {
+                    self.insert_fragment_fetches(
+                        &mut segment_fetch,
+                        current_block_number,
+                        segment_size,
+                        &fragment_ids_needed,
+                        fragment_segment_size,
+                    )?;
                 }
 
                 let Ok(_) = tx.send(segment_fetch).await else {
@@ -313,4 +296,340 @@ impl SegmentStream {
 
         Ok(())
     }
+
+    /// Returns true if the segments were rewritten since the stream started.
+    async fn layout_changed(&self, generation: u64) -> bool {
+        self.chain_view.get_segment_generation().await != generation
+    }
+
+    fn always_include_header(&self) -> bool {
+        self.block_filter
+            .iter()
+            .any(|block_filter| block_filter.always_include_header())
+    }
+
+    /// Returns the rows in the index that match the client-provided filters.
+    ///
+    /// The fragments needed to produce the matching data are added to `fragment_ids_needed`.
+    fn filter_index(
+        &self,
+        index: &ArchivedIndexGroupFragment,
+        fragment_ids_needed: &mut HashSet<FragmentId>,
+    ) -> Result<RoaringBitmap, DataStreamError> {
+        let mut rows_with_data = RoaringBitmap::default();
+
+        for block_filter in self.block_filter.iter() {
+            for (fragment_id, filters) in block_filter.iter() {
+                // Data stored before the fragment was introduced doesn't have it.
+                let Some(indexes) = index.indexes.iter().find(|f| f.fragment_id == *fragment_id)
+                else {
+                    continue;
+                };
+
+                for filter in filters {
+                    let rows = filter.filter(indexes).change_context(DataStreamError)?;
+                    if rows.is_empty() {
+                        continue;
+                    }
+
+                    rows_with_data |= &rows;
+                    fragment_ids_needed.insert(*fragment_id);
+                    for join_with_fragment_id in filter.joins.iter() {
+                        fragment_ids_needed.insert(*join_with_fragment_id);
+                    }
+                }
+            }
+        }
+
+        Ok(rows_with_data)
+    }
+
+    /// Returns the blocks in the segment that have data for the client-provided filters.
+    ///
+    /// The index segment is downloaded only if the client provided any filter.
+    async fn segment_blocks_with_data(
+        &self,
+        segment_start: u64,
+        fragment_ids_needed: &mut HashSet<FragmentId>,
+    ) -> Result<RoaringBitmap, DataStreamError> {
+        let mut blocks_with_data = RoaringBitmap::default();
+
+        if self
+            .block_filter
+            .iter()
+            .all(|block_filter| block_filter.is_empty())
+        {
+            return Ok(blocks_with_data);
+        }
+
+        let segment_cursor = Cursor::new_finalized(segment_start);
+        let index_entry = self
+            .store
+            .get_index_segment(&segment_cursor)
+            .record_request_with_attributes(
+                self.metrics.segment_download.clone(),
+                &[KeyValue::new("name", INDEX_FRAGMENT_NAME)],
+            )
+            .await
+            .map_err(FileCacheError::Foyer)
+            .change_context(DataStreamError)
+            .attach_printable("failed to get index segment")
+            .attach_printable_lazy(|| format!("cursor: {segment_cursor}"))?;
+
+        let segment = unsafe {
+            rkyv::access_unchecked::<rkyv::Archived<Segment<IndexGroupFragment>>>(
+                index_entry.value(),
+            )
+        };
+
+        for block_index in segment.data.iter() {
+            let rows = self
+                .filter_index(&block_index.data, fragment_ids_needed)
+                .attach_printable_lazy(|| {
+                    format!("block: {}", block_index.cursor.number.to_native())
+                })?;
+            if !rows.is_empty() {
+                // Segments don't have an entry for missed blocks, use the cursor
+                // to find the block number.
+                blocks_with_data.insert(block_index.cursor.number.to_native() as u32);
+            }
+        }
+
+        Ok(blocks_with_data)
+    }
+
+    /// Start fetching the fragments needed by the segment.
+    ///
+    /// Fragments with a custom segment size are split into smaller segments, only
+    /// the ones that contain blocks in the segment fetch are downloaded.
+    fn insert_fragment_fetches(
+        &self,
+        segment_fetch: &mut SegmentAccessFetch,
+        segment_start: u64,
+        segment_size: u64,
+        fragment_ids_needed: &HashSet<FragmentId>,
+        fragment_segment_size: &HashMap<String, u64>,
+    ) -> Result<(), DataStreamError> {
+        for fragment_id in fragment_ids_needed.iter() {
+            let segment_name = self
+                .fragment_id_to_name
+                .get(fragment_id)
+                .ok_or(DataStreamError)
+                .attach_printable("expected fragment id to have a name")
+                .attach_printable_lazy(|| format!("fragment_id: {fragment_id}"))?;
+
+            let Some(fragment_size) = fragment_segment_size.get(segment_name) else {
+                let segment_cursor = Cursor::new_finalized(segment_start);
+                let fragment_fetch = self
+                    .store
+                    .get_segment(&segment_cursor, segment_name)
+                    .record_request_with_attributes(
+                        self.metrics.segment_download.clone(),
+                        &[KeyValue::new("name", segment_name.clone())],
+                    );
+                segment_fetch.insert_fragment(*fragment_id, fragment_fetch);
+                continue;
+            };
+
+            let segment_end = segment_start + segment_size;
+            for fragment_segment_start in
+                (segment_start..segment_end).step_by(*fragment_size as usize)
+            {
+                let fragment_segment_end = fragment_segment_start + fragment_size;
+                if !segment_fetch.has_blocks_in_range(fragment_segment_start..fragment_segment_end)
+                {
+                    continue;
+                }
+
+                let segment_cursor = Cursor::new_finalized(fragment_segment_start);
+                let fragment_fetch = self
+                    .store
+                    .get_segment(&segment_cursor, segment_name)
+                    .record_request_with_attributes(
+                        self.metrics.segment_download.clone(),
+                        &[KeyValue::new("name", segment_name.clone())],
+                    );
+                segment_fetch.insert_fragment_segment(
+                    *fragment_id,
+                    *fragment_size,
+                    fragment_segment_start,
+                    fragment_fetch,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        block_store::{BlockStoreReader, BlockStoreWriter},
+        chain_store::ChainStore,
+        chain_view::{ChainView, SegmentLayout},
+        compaction::SegmentBuilder,
+        data_stream::{DataStreamMetrics, SegmentAccess},
+        file_cache::testing::temp_file_cache,
+        fragment::{
+            Block, BodyFragment, HeaderFragment, Index, IndexFragment, IndexGroupFragment,
+            JoinGroupFragment, HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_ID,
+            INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME,
+        },
+        index::{BitmapIndexBuilder, ScalarValue},
+        object_store::ObjectStore,
+        query::{BlockFilter, Condition, Filter},
+        Cursor,
+    };
+
+    use super::SegmentStream;
+
+    /// A block with one log, indexed by the block number.
+    fn new_block(number: u64) -> Block {
+        let mut index = BitmapIndexBuilder::default();
+        index.insert(ScalarValue::Uint32(number as u32), 0);
+
+        Block {
+            header: HeaderFragment {
+                data: vec![number as u8],
+            },
+            index: IndexGroupFragment {
+                indexes: vec![IndexFragment {
+                    fragment_id: 2,
+                    range_start: 0,
+                    range_len: 1,
+                    indexes: vec![Index {
+                        index_id: 0,
+                        index: index.build().unwrap().into(),
+                    }],
+                }],
+            },
+            join: JoinGroupFragment {
+                joins: Vec::default(),
+            },
+            body: vec![BodyFragment {
+                fragment_id: 2,
+                name: "log".to_string(),
+                data: vec![vec![number as u8]],
+            }],
+        }
+    }
+
+    fn log_filter(value: u32) -> BlockFilter {
+        let mut block_filter = BlockFilter::default();
+        block_filter.add_filter(Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![Condition {
+                index_id: 0,
+                key: ScalarValue::Uint32(value).into(),
+            }],
+            joins: Vec::default(),
+            fields: None,
+        });
+        block_filter
+    }
+
+    /// Stream the segments of blocks 0 to 7, in segments of 4 blocks. Block 1 is missing.
+    async fn stream_segments(block_filter: BlockFilter) -> Vec<SegmentAccess> {
+        let (_cache_dir, file_cache) = temp_file_cache().await.unwrap();
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let writer = BlockStoreWriter::new(object_store.clone());
+
+        for segment_start in [0, 4] {
+            let mut builder = SegmentBuilder::default();
+            builder
+                .start_new_segment(Cursor::new_finalized(segment_start))
+                .unwrap();
+            for block_number in (segment_start..segment_start + 4).filter(|n| *n != 1) {
+                builder
+                    .add_decoded_block(
+                        &Cursor::new_finalized(block_number),
+                        new_block(block_number),
+                    )
+                    .unwrap();
+            }
+
+            for segment in builder.segment_data(&HashMap::default()).unwrap() {
+                writer.put_segment(segment).await.unwrap();
+            }
+        }
+
+        let chain_view = ChainView::new_for_testing(
+            ChainStore::new(object_store.clone(), file_cache.clone()),
+            0,
+            7,
+            Some(7),
+            None,
+            SegmentLayout {
+                generation: 0,
+                segment_size: 4,
+                group_size: 2,
+                fragment_segment_size: HashMap::default(),
+            },
+        );
+
+        let fragment_id_to_name = HashMap::from([
+            (INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME.to_string()),
+            (JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME.to_string()),
+            (HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME.to_string()),
+            (2, "log".to_string()),
+        ]);
+
+        let stream = SegmentStream::new(
+            vec![block_filter],
+            fragment_id_to_name,
+            BlockStoreReader::new(object_store, file_cache),
+            chain_view,
+            DataStreamMetrics::default(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(16);
+        stream
+            .start(Cursor::new_finalized(0), tx, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let metrics = DataStreamMetrics::default();
+        let mut segments = Vec::new();
+        while let Some(fetch) = rx.recv().await {
+            segments.push(fetch.wait(&metrics).await.unwrap());
+        }
+
+        segments
+    }
+
+    #[tokio::test]
+    async fn test_filter_ungrouped_segments() {
+        let segments = stream_segments(log_filter(2)).await;
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].first_block, 0);
+        assert_eq!(segments[0].blocks.iter().collect::<Vec<_>>(), vec![2]);
+        assert!(segments[0].fragment_len() > 0);
+        assert_eq!(segments[1].first_block, 4);
+        assert!(segments[1].blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_empty_segments() {
+        let segments = stream_segments(log_filter(100)).await;
+
+        assert_eq!(
+            segments
+                .iter()
+                .map(|segment| segment.first_block)
+                .collect::<Vec<_>>(),
+            vec![0, 4]
+        );
+        for segment in segments {
+            assert!(segment.blocks.is_empty());
+            assert_eq!(segment.fragment_len(), 0);
+        }
+    }
 }
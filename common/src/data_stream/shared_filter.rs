@@ -0,0 +1,435 @@
+//! Share the result of filtering segments between streams with the same filter.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use error_stack::{Report, Result};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+
+use super::{
+    fragment_filter::{BlockData, SegmentFilterStream},
+    DataStreamError, DataStreamMetrics, FilterResultCache, FragmentFilter, SegmentAccess,
+};
+
+/// Identifies a stream's filter, computed from the filter sent by the client.
+pub type FilterKey = [u8; 32];
+
+/// Identifies the results of a filter in a segment: the filter, the segment's first
+/// block and its size.
+///
+/// Segments rewritten with a different size can start at the same block.
+pub(crate) type SegmentKey = (FilterKey, u64, u64);
+
+type SharedResult = std::result::Result<Arc<Vec<BlockData>>, Arc<Report<DataStreamError>>>;
+
+type SharedEvaluation = Shared<BoxFuture<'static, SharedResult>>;
+
+/// Evaluate each segment once for all streams with the same filter.
+///
+/// Popular filters (for example, the events of a popular contract) are often
+/// streamed by many clients at the same time. Streams that filter a segment that
+/// is already being filtered wait for the result instead of evaluating it again.
+/// The most recent results are kept in memory, up to `max_size_bytes`, so that
+/// streams that are slightly behind can use them too.
+///
+/// Segments are only evaluated for sharing while more than one stream has the
+/// same filter. Otherwise, the stream filters the segment one block at a time.
+#[derive(Clone)]
+pub struct SharedFilterCache {
+    inner: Arc<Mutex<SharedFilterCacheInner>>,
+    results: FilterResultCache,
+    metrics: DataStreamMetrics,
+}
+
+struct SharedFilterCacheInner {
+    max_size_bytes: usize,
+    size_bytes: usize,
+    in_flight: HashMap<SegmentKey, SharedEvaluation>,
+    completed: HashMap<SegmentKey, Arc<Vec<BlockData>>>,
+    completed_order: VecDeque<SegmentKey>,
+    /// Number of streams with each filter.
+    streams: HashMap<FilterKey, usize>,
+}
+
+/// A stream's handle to the shared filter cache, it unregisters the stream on drop.
+pub struct SharedFilter {
+    cache: SharedFilterCache,
+    filter_key: FilterKey,
+}
+
+/// The blocks of a segment that match a stream's filter.
+pub struct SegmentBlocks {
+    inner: SegmentBlocksInner,
+}
+
+enum SegmentBlocksInner {
+    /// Evaluated once for all streams with the same filter.
+    Shared {
+        blocks: Arc<Vec<BlockData>>,
+        position: usize,
+    },
+    /// Evaluated one block at a time, as the stream reads them.
+    Streamed {
+        stream: SegmentFilterStream,
+        results: FilterResultCache,
+        segment_key: SegmentKey,
+    },
+}
+
+/// How a stream evaluates its filter on a segment.
+enum SegmentEvaluation {
+    /// Wait for the evaluation shared with other streams.
+    Shared(SharedEvaluation),
+    /// Filter the segment as the stream reads it.
+    Streamed(SegmentAccess),
+}
+
+impl SharedFilterCache {
+    pub fn new(
+        max_size_bytes: usize,
+        results: FilterResultCache,
+        metrics: DataStreamMetrics,
+    ) -> Self {
+        let inner = SharedFilterCacheInner {
+            max_size_bytes,
+            size_bytes: 0,
+            in_flight: HashMap::default(),
+            completed: HashMap::default(),
+            completed_order: VecDeque::default(),
+            streams: HashMap::default(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            results,
+            metrics,
+        }
+    }
+
+    /// Register a stream with the given filter.
+    pub fn register(&self, filter_key: FilterKey) -> SharedFilter {
+        let mut inner = self.inner.lock().expect("shared filter cache lock");
+        *inner.streams.entry(filter_key).or_default() += 1;
+
+        SharedFilter {
+            cache: self.clone(),
+            filter_key,
+        }
+    }
+
+    /// Returns the segment's blocks that match the filter, starting from `starting_block`.
+    async fn filter_segment(
+        &self,
+        filter: &FragmentFilter,
+        filter_key: FilterKey,
+        segment_access: SegmentAccess,
+        starting_block: u64,
+    ) -> Result<SegmentBlocks, DataStreamError> {
+        let segment_key = (
+            filter_key,
+            segment_access.first_block,
+            segment_access.segment_size,
+        );
+
+        let evaluation = {
+            let mut inner = self.inner.lock().expect("shared filter cache lock");
+
+            if let Some(blocks) = inner.completed.get(&segment_key) {
+                self.metrics.shared_filter_hit.add(1, &[]);
+                return Ok(SegmentBlocks::shared(blocks.clone(), starting_block));
+            }
+
+            if let Some(evaluation) = inner.in_flight.get(&segment_key) {
+                self.metrics.shared_filter_hit.add(1, &[]);
+                SegmentEvaluation::Shared(evaluation.clone())
+            } else if inner.streams.get(&filter_key).copied().unwrap_or_default() > 1 {
+                let evaluation = self.spawn_evaluation(filter.clone(), segment_key, segment_access);
+                inner.in_flight.insert(segment_key, evaluation.clone());
+                SegmentEvaluation::Shared(evaluation)
+            } else {
+                SegmentEvaluation::Streamed(segment_access)
+            }
+        };
+
+        match evaluation {
+            SegmentEvaluation::Shared(evaluation) => {
+                Self::wait_evaluation(evaluation, starting_block).await
+            }
+            SegmentEvaluation::Streamed(segment_access) => {
+                Ok(self.stream_segment(filter, segment_key, segment_access, starting_block))
+            }
+        }
+    }
+
+    async fn wait_evaluation(
+        evaluation: SharedEvaluation,
+        starting_block: u64,
+    ) -> Result<SegmentBlocks, DataStreamError> {
+        let blocks = evaluation.await.map_err(|err| {
+            Report::new(DataStreamError)
+                .attach_printable("shared filter evaluation failed")
+                .attach_printable(format!("{err:?}"))
+        })?;
+
+        Ok(SegmentBlocks::shared(blocks, starting_block))
+    }
+
+    /// Filter the segment one block at a time, using the rows matched by a previous
+    /// evaluation if cached.
+    fn stream_segment(
+        &self,
+        filter: &FragmentFilter,
+        segment_key: SegmentKey,
+        segment_access: SegmentAccess,
+        starting_block: u64,
+    ) -> SegmentBlocks {
+        let is_enabled = self.results.is_enabled();
+        let cached = if is_enabled {
+            self.results.get(segment_key)
+        } else {
+            None
+        };
+
+        if cached.is_some() {
+            self.metrics.filter_result_hit.add(1, &[]);
+        }
+
+        let stream =
+            filter.filter_segment_stream(segment_access, starting_block, cached, is_enabled);
+
+        SegmentBlocks {
+            inner: SegmentBlocksInner::Streamed {
+                stream,
+                results: self.results.clone(),
+                segment_key,
+            },
+        }
+    }
+
+    /// Filter the segment in a separate task, so that the evaluation completes even
+    /// if the stream that started it is dropped.
+    fn spawn_evaluation(
+        &self,
+        filter: FragmentFilter,
+        segment_key: SegmentKey,
+        segment_access: SegmentAccess,
+    ) -> SharedEvaluation {
+        let cache = self.clone();
+
+        let task = tokio::spawn(async move {
+            let result = cache
+                .evaluate(&filter, segment_key, segment_access)
+                .await
+                .map(Arc::new)
+                .map_err(Arc::new);
+
+            cache.complete(segment_key, &result);
+
+            result
+        });
+
+        task.map(|result| match result {
+            Ok(result) => result,
+            Err(err) => Err(Arc::new(
+                Report::new(DataStreamError)
+                    .attach_printable("shared filter evaluation task failed")
+                    .attach_printable(format!("{err:?}")),
+            )),
+        })
+        .boxed()
+        .shared()
+    }
+
+    /// Filter the whole segment.
+    async fn evaluate(
+        &self,
+        filter: &FragmentFilter,
+        segment_key: SegmentKey,
+        segment_access: SegmentAccess,
+    ) -> Result<Vec<BlockData>, DataStreamError> {
+        let first_block = segment_key.1;
+        let mut segment_blocks =
+            self.stream_segment(filter, segment_key, segment_access, first_block);
+
+        let mut blocks = Vec::new();
+        while let Some(block) = segment_blocks.next().await? {
+            blocks.push(block);
+        }
+
+        Ok(blocks)
+    }
+
+    fn unregister(&self, filter_key: FilterKey) {
+        let mut inner = self.inner.lock().expect("shared filter cache lock");
+
+        if let Some(count) = inner.streams.get_mut(&filter_key) {
+            *count -= 1;
+            if *count == 0 {
+                inner.streams.remove(&filter_key);
+            }
+        }
+    }
+
+    fn complete(&self, segment_key: SegmentKey, result: &SharedResult) {
+        let mut inner = self.inner.lock().expect("shared filter cache lock");
+
+        inner.in_flight.remove(&segment_key);
+
+        if let Ok(blocks) = result {
+            inner.insert_completed(segment_key, blocks.clone());
+        }
+    }
+}
+
+impl SharedFilter {
+    /// Returns the segment's blocks that match the filter, starting from `starting_block`.
+    pub async fn filter_segment(
+        &self,
+        filter: &FragmentFilter,
+        segment_access: SegmentAccess,
+        starting_block: u64,
+    ) -> Result<SegmentBlocks, DataStreamError> {
+        self.cache
+            .filter_segment(filter, self.filter_key, segment_access, starting_block)
+            .await
+    }
+}
+
+impl Drop for SharedFilter {
+    fn drop(&mut self) {
+        self.cache.unregister(self.filter_key);
+    }
+}
+
+impl SegmentBlocks {
+    fn shared(blocks: Arc<Vec<BlockData>>, starting_block: u64) -> Self {
+        let position = blocks.partition_point(|block| block.cursor.number < starting_block);
+        Self {
+            inner: SegmentBlocksInner::Shared { blocks, position },
+        }
+    }
+
+    /// Returns the size of the blocks already filtered but not returned yet.
+    ///
+    /// Streamed blocks are filtered when requested, so they're not buffered.
+    pub fn buffered_size_bytes(&self) -> usize {
+        match &self.inner {
+            SegmentBlocksInner::Shared { blocks, position } => {
+                blocks_size_bytes(blocks.get(*position..).unwrap_or_default())
+            }
+            SegmentBlocksInner::Streamed { .. } => 0,
+        }
+    }
+
+    /// Returns the next block with data, or `None` once the segment is done.
+    pub async fn next(&mut self) -> Result<Option<BlockData>, DataStreamError> {
+        match &mut self.inner {
+            SegmentBlocksInner::Shared { blocks, position } => {
+                let block = blocks.get(*position).cloned();
+                *position += 1;
+                Ok(block)
+            }
+            SegmentBlocksInner::Streamed {
+                stream,
+                results,
+                segment_key,
+            } => {
+                let block = stream.next().await?;
+
+                if block.is_none() {
+                    if let Some(matches) = stream.take_matches() {
+                        results.insert(*segment_key, Arc::new(matches));
+                    }
+                }
+
+                Ok(block)
+            }
+        }
+    }
+}
+
+impl SharedFilterCacheInner {
+    fn insert_completed(&mut self, segment_key: SegmentKey, blocks: Arc<Vec<BlockData>>) {
+        let size_bytes = blocks_size_bytes(&blocks);
+
+        if size_bytes > self.max_size_bytes {
+            return;
+        }
+
+        // Evict the oldest results to make space.
+        while self.size_bytes + size_bytes > self.max_size_bytes {
+            let Some(oldest) = self.completed_order.pop_front() else {
+                break;
+            };
+
+            if let Some(evicted) = self.completed.remove(&oldest) {
+                self.size_bytes -= blocks_size_bytes(&evicted);
+            }
+        }
+
+        if self.completed.insert(segment_key, blocks).is_none() {
+            self.completed_order.push_back(segment_key);
+            self.size_bytes += size_bytes;
+        }
+    }
+}
+
+fn blocks_size_bytes(blocks: &[BlockData]) -> usize {
+    blocks.iter().map(BlockData::size_bytes).sum()
+}
+
+/// Returns the key used to share the evaluation of the filters sent by the client.
+pub fn filter_key(filters: &[Vec<u8>]) -> FilterKey {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    for filter in filters {
+        hasher.update((filter.len() as u64).to_le_bytes());
+        hasher.update(filter);
+    }
+
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_stream::{DataStreamMetrics, FilterResultCache};
+
+    use super::{filter_key, SharedFilterCache};
+
+    #[test]
+    fn test_filter_key() {
+        let a = vec![vec![1, 2], vec![3]];
+        let b = vec![vec![1], vec![2, 3]];
+
+        assert_eq!(filter_key(&a), filter_key(&a.clone()));
+        assert_ne!(filter_key(&a), filter_key(&b));
+    }
+
+    #[test]
+    fn test_register_streams() {
+        let cache = SharedFilterCache::new(
+            1024,
+            FilterResultCache::new(0),
+            DataStreamMetrics::default(),
+        );
+        let stream_count = |cache: &SharedFilterCache| {
+            let inner = cache.inner.lock().unwrap();
+            inner.streams.get(&[1; 32]).copied()
+        };
+
+        let first = cache.register([1; 32]);
+        let second = cache.register([1; 32]);
+        assert_eq!(stream_count(&cache), Some(2));
+
+        drop(first);
+        assert_eq!(stream_count(&cache), Some(1));
+
+        drop(second);
+        assert_eq!(stream_count(&cache), None);
+    }
+}
@@ -1,26 +1,27 @@
-use std::collections::{BTreeMap, HashMap};
+use std::{collections::HashMap, future::Future, time::Duration};
 
 use apibara_dna_protocol::dna::stream::{
-    stream_data_response::Message, Data, DataFinality, DataProduction, Finalize, Invalidate,
-    StreamDataResponse,
+    data_checksum, stream_data_response::Message, Data, DataFinality, DataProduction, Finalize,
+    Invalidate, RangeComplete, StreamDataResponse,
 };
-use apibara_observability::{KeyValue, RecordRequest};
-use bytes::{BufMut, Bytes, BytesMut};
+use apibara_observability::RecordRequest;
 use error_stack::{Result, ResultExt};
 use futures::FutureExt;
 use tokio::sync::mpsc;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tokio_util::sync::CancellationToken;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
     block_store::BlockStoreReader,
-    chain_view::{ChainView, NextCursor},
-    data_stream::{fragment_access::BlockAccess, FilterMatch, FragmentAccess, SegmentStream},
+    chain_view::{CanonicalCursor, ChainView, NextCursor},
+    data_stream::{
+        fragment_access::BlockAccess, DataStreamMessage, FilterKey, FragmentFilter,
+        MemoryReservation, SegmentStream, SharedFilter, SharedFilterCache, StreamMemoryBudget,
+    },
     file_cache::FileCacheError,
-    fragment::{FragmentId, HEADER_FRAGMENT_ID},
-    join::ArchivedJoinTo,
-    query::{BlockFilter, HeaderFilter},
+    fragment::FragmentId,
+    query::BlockFilter,
     Cursor,
 };
 
@@ -29,45 +30,78 @@ use super::DataStreamMetrics;
 #[derive(Debug)]
 pub struct DataStreamError;
 
+/// How to handle clients that stop reading their stream.
+#[derive(Debug, Clone)]
+pub struct SlowConsumerPolicy {
+    /// Report the stream after waiting this long for the client to read data.
+    pub report_after: Duration,
+    /// Disconnect the stream after waiting this long for the client to read data.
+    pub disconnect_after: Option<Duration>,
+}
+
 pub struct DataStream {
-    block_filter: Vec<BlockFilter>,
+    filter: FragmentFilter,
+    shared_filter: SharedFilter,
+    memory_budget: StreamMemoryBudget,
     current: Option<Cursor>,
+    /// The stream completes after sending this block.
+    ending: Option<Cursor>,
     finalized: Cursor,
     finality: DataFinality,
     chain_view: ChainView,
     store: BlockStoreReader,
-    fragment_id_to_name: HashMap<FragmentId, String>,
     prefetch_segment_count: usize,
+    prefetch_block_count: usize,
+    /// The last block that was prefetched by the single block stream.
+    prefetched_block: Option<u64>,
+    /// Sent with each block to identify this server.
+    server_id: Option<String>,
+    slow_consumer: SlowConsumerPolicy,
+    /// Cancelled when the stream is disconnected because the client is too slow.
+    disconnected: CancellationToken,
     metrics: DataStreamMetrics,
     _permit: tokio::sync::OwnedSemaphorePermit,
 }
 
-type DataStreamMessage = tonic::Result<StreamDataResponse, tonic::Status>;
-
-const DEFAULT_BLOCKS_BUFFER_SIZE: usize = 1024 * 1024;
-
 impl DataStream {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         block_filter: Vec<BlockFilter>,
+        filter_key: FilterKey,
+        shared_filter: SharedFilterCache,
+        memory_budget: StreamMemoryBudget,
         starting: Option<Cursor>,
+        ending: Option<Cursor>,
         finalized: Cursor,
         finality: DataFinality,
         chain_view: ChainView,
         fragment_id_to_name: HashMap<FragmentId, String>,
         store: BlockStoreReader,
         prefetch_segment_count: usize,
+        prefetch_block_count: usize,
+        server_id: Option<String>,
+        slow_consumer: SlowConsumerPolicy,
+        disconnected: CancellationToken,
         permit: tokio::sync::OwnedSemaphorePermit,
         metrics: DataStreamMetrics,
     ) -> Self {
+        let filter = FragmentFilter::new(block_filter, fragment_id_to_name, metrics.clone());
+
         Self {
-            block_filter,
+            filter,
+            shared_filter: shared_filter.register(filter_key),
+            memory_budget,
             current: starting,
+            ending,
             finalized,
             finality,
             chain_view,
-            fragment_id_to_name,
             prefetch_segment_count,
+            prefetch_block_count,
+            prefetched_block: None,
+            server_id,
+            slow_consumer,
+            disconnected,
             store,
             metrics,
             _permit: permit,
@@ -81,7 +115,12 @@ impl DataStream {
     ) -> Result<(), DataStreamError> {
         self.metrics.active.add(1, &[]);
 
-        while !ct.is_cancelled() && !tx.is_closed() {
+        while !ct.is_cancelled() && !self.disconnected.is_cancelled() && !tx.is_closed() {
+            if self.reached_ending_cursor() {
+                self.send_range_complete_message(&tx, &ct).await;
+                break;
+            }
+
             tokio::select! {
                 biased;
 
@@ -118,15 +157,13 @@ impl DataStream {
                     ..Default::default()
                 });
 
-                let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
+                if !self.send_message(invalidate, tx, ct).await {
                     return Ok(());
-                };
-
-                permit.send(Ok(StreamDataResponse {
-                    message: Some(invalidate),
-                }));
+                }
 
                 self.current = Some(cursor);
+                // Blocks after the new head may have been prefetched from the old chain.
+                self.prefetched_block = None;
 
                 return Ok(());
             }
@@ -164,23 +201,143 @@ impl DataStream {
         self.tick_single(next_cursor, is_head, tx, ct).await
     }
 
+    /// Send a message to the client, waiting for space in the stream's memory budget.
+    ///
+    /// Returns false if the stream was cancelled or the client disconnected.
+    async fn send_message(
+        &self,
+        message: Message,
+        tx: &mpsc::Sender<DataStreamMessage>,
+        ct: &CancellationToken,
+    ) -> bool {
+        self.send_message_with_reservation(message, None, tx, ct)
+            .await
+    }
+
+    /// Send a message to the client, using `reservation` instead of reserving new
+    /// space in the memory budget if present.
+    async fn send_message_with_reservation(
+        &self,
+        mut message: Message,
+        reservation: Option<MemoryReservation>,
+        tx: &mpsc::Sender<DataStreamMessage>,
+        ct: &CancellationToken,
+    ) -> bool {
+        // Let clients detect data corrupted between the server and them.
+        if let Message::Data(data) = &mut message {
+            data.checksum = Some(data_checksum(&data.data));
+            data.server_id.clone_from(&self.server_id);
+        }
+
+        let response = StreamDataResponse {
+            message: Some(message),
+        };
+
+        self.wait_for_client(self.wait_and_send(response, reservation, tx, ct))
+            .await
+            .is_some()
+    }
+
+    /// Wait for `f`, which waits for the client to read the stream.
+    ///
+    /// Returns `None` if the stream was cancelled or the client was disconnected
+    /// because it's too slow.
+    async fn wait_for_client<T>(&self, f: impl Future<Output = Option<T>>) -> Option<T> {
+        tokio::pin!(f);
+
+        let report_after = self.slow_consumer.report_after;
+        if let Ok(result) = tokio::time::timeout(report_after, &mut f).await {
+            return result;
+        }
+
+        warn!(waited = ?report_after, "slow consumer: client is not reading the stream");
+        self.metrics.slow_consumer.add(1, &[]);
+
+        let result = match self.slow_consumer.disconnect_after {
+            None => f.await,
+            Some(disconnect_after) => {
+                let remaining = disconnect_after.saturating_sub(report_after);
+                let Ok(result) = tokio::time::timeout(remaining, &mut f).await else {
+                    warn!(waited = ?disconnect_after, "slow consumer: disconnecting stream");
+                    self.metrics.slow_consumer_disconnect.add(1, &[]);
+                    self.disconnected.cancel();
+                    return None;
+                };
+                result
+            }
+        };
+
+        if result.is_some() {
+            info!("slow consumer: client resumed reading the stream");
+        }
+
+        result
+    }
+
+    /// Wait for space in the memory budget and the channel, then send the response.
+    async fn wait_and_send(
+        &self,
+        response: StreamDataResponse,
+        reservation: Option<MemoryReservation>,
+        tx: &mpsc::Sender<DataStreamMessage>,
+        ct: &CancellationToken,
+    ) -> Option<()> {
+        let reservation = match reservation {
+            Some(reservation) => reservation,
+            None => {
+                let size = prost::Message::encoded_len(&response);
+                ct.run_until_cancelled(self.memory_budget.reserve(size))
+                    .await?
+            }
+        };
+
+        let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
+            return None;
+        };
+
+        permit.send(DataStreamMessage::new(Ok(response), Some(reservation)));
+
+        Some(())
+    }
+
+    /// Returns true if the stream sent all blocks up to the ending cursor.
+    fn reached_ending_cursor(&self) -> bool {
+        match (&self.current, &self.ending) {
+            (Some(current), Some(ending)) => current.number >= ending.number,
+            _ => false,
+        }
+    }
+
+    fn is_after_ending_cursor(&self, block_number: u64) -> bool {
+        self.ending
+            .as_ref()
+            .is_some_and(|ending| block_number > ending.number)
+    }
+
+    async fn send_range_complete_message(
+        &self,
+        tx: &mpsc::Sender<DataStreamMessage>,
+        ct: &CancellationToken,
+    ) {
+        debug!(current = ?self.current, "tick: send range complete message");
+        let range_complete = Message::RangeComplete(RangeComplete {
+            cursor: self.current.clone().map(Into::into),
+        });
+
+        self.send_message(range_complete, tx, ct).await;
+    }
+
     async fn send_finalize_message(
         &mut self,
         tx: &mpsc::Sender<DataStreamMessage>,
         ct: &CancellationToken,
     ) -> Result<(), DataStreamError> {
         debug!("tick: send finalize message");
-        let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
-            return Ok(());
-        };
-
         let finalize = Message::Finalize(Finalize {
             cursor: Some(self.finalized.clone().into()),
         });
 
-        permit.send(Ok(StreamDataResponse {
-            message: Some(finalize),
-        }));
+        self.send_message(finalize, tx, ct).await;
 
         Ok(())
     }
@@ -194,8 +351,8 @@ impl DataStream {
         debug!(cursor = %cursor, "tick: segment stream");
 
         let segment_stream = SegmentStream::new(
-            self.block_filter.clone(),
-            self.fragment_id_to_name.clone(),
+            self.filter.block_filter().to_vec(),
+            self.filter.fragment_id_to_name().clone(),
             self.store.clone(),
             self.chain_view.clone(),
             self.metrics.clone(),
@@ -229,45 +386,85 @@ impl DataStream {
                             .attach_printable("Failed to wait for segment fetch")?;
 
                     let finality = DataFinality::Finalized;
+                    let segment_end = segment_access.first_block + segment_access.segment_size - 1;
+
+                    let mut blocks = self
+                        .shared_filter
+                        .filter_segment(&self.filter, segment_access, cursor.number)
+                        .await?;
+
+                    // Results shared with other streams stay in memory until the stream sends
+                    // them, so they count towards the stream's budget.
+                    let mut segment_reservation = match blocks.buffered_size_bytes() {
+                        0 => None,
+                        size => {
+                            let reserve = async {
+                                ct.run_until_cancelled(self.memory_budget.reserve(size)).await
+                            };
+                            let Some(reservation) = self.wait_for_client(reserve).await else {
+                                return Ok(());
+                            };
+                            Some(reservation)
+                        }
+                    };
 
-                    for block_access in segment_access.iter() {
-                        let block_end_cursor = block_access.cursor();
+                    while let Some(block) = blocks.next().await? {
+                        let block_end_cursor = block.cursor.clone();
                         if block_end_cursor.number < cursor.number {
                             continue;
                         }
 
+                        if self.is_after_ending_cursor(block_end_cursor.number) {
+                            break;
+                        }
+
                         let proto_cursor = if block_end_cursor.number == 0 {
                             None
                         } else {
                             Some(Cursor::new_finalized(block_end_cursor.number - 1).into())
                         };
                         let proto_end_cursor: Option<ProtoCursor> = Some(block_end_cursor.clone().into());
+                        let block_size = block.size_bytes();
+
+                        let data = Message::Data(Data {
+                            cursor: proto_cursor,
+                            end_cursor: proto_end_cursor,
+                            data: block.data,
+                            finality: finality as i32,
+                            production: DataProduction::Backfill.into(),
+                            ..Default::default()
+                        });
 
-                        let fragment_access = FragmentAccess::Segment(block_access);
-                        let mut blocks = Vec::new();
-                        if self
-                            .filter_fragment(fragment_access, &finality, false, &mut blocks)
-                            .await?
-                        {
-                            let data = Message::Data(Data {
-                                cursor: proto_cursor,
-                                end_cursor: proto_end_cursor,
-                                data: blocks,
-                                finality: finality as i32,
-                                production: DataProduction::Backfill.into(),
-                            });
-
-                            let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
-                                return Ok(());
-                            };
-
-                            permit.send(Ok(StreamDataResponse {
-                                message: Some(data),
-                            }));
+                        let reservation = segment_reservation
+                            .as_mut()
+                            .map(|reservation| reservation.split(block_size));
+                        if !self.send_message_with_reservation(data, reservation, tx, ct).await {
+                            return Ok(());
                         }
 
                         self.current = block_end_cursor.into();
                     }
+
+                    // Blocks without data are not sent, so move the cursor to the end
+                    // of the segment to avoid scanning it again.
+                    if segment_end >= cursor.number {
+                        let last_block = match &self.ending {
+                            Some(ending) => segment_end.min(ending.number),
+                            None => segment_end,
+                        };
+                        // Keep the cursor of the last block sent, since it has its hash.
+                        let is_behind = match &self.current {
+                            Some(current) => current.number < last_block,
+                            None => true,
+                        };
+                        if is_behind {
+                            self.current = Cursor::new_finalized(last_block).into();
+                        }
+                    }
+
+                    if self.reached_ending_cursor() {
+                        return Ok(());
+                    }
                 }
             }
         }
@@ -299,6 +496,10 @@ impl DataStream {
             DataFinality::Finalized
         };
 
+        if !is_head {
+            self.prefetch_blocks(&cursor).await?;
+        }
+
         let block_entry: BlockAccess = self
             .store
             .get_block(&cursor)
@@ -310,13 +511,11 @@ impl DataStream {
             .attach_printable_lazy(|| format!("cursor: {}", cursor))?
             .into();
 
-        let fragment_access = FragmentAccess::Block(block_entry);
-
-        let mut blocks = Vec::new();
-
-        if self
-            .filter_fragment(fragment_access, &finality, is_head, &mut blocks)
-            .await?
+        if let Some(blocks) = self
+            .filter
+            .filter_block(block_entry, is_head)
+            .await
+            .attach_printable_lazy(|| format!("cursor: {}", cursor))?
         {
             let data = Message::Data(Data {
                 cursor: proto_cursor.clone(),
@@ -328,15 +527,12 @@ impl DataStream {
                 } else {
                     DataProduction::Backfill.into()
                 },
+                ..Default::default()
             });
 
-            let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
+            if !self.send_message(data, tx, ct).await {
                 return Ok(());
-            };
-
-            permit.send(Ok(StreamDataResponse {
-                message: Some(data),
-            }));
+            }
         }
 
         self.current = Some(cursor);
@@ -344,6 +540,34 @@ impl DataStream {
         Ok(())
     }
 
+    /// Start downloading the blocks after `cursor` so that they're already in the
+    /// cache when the stream reaches them.
+    async fn prefetch_blocks(&mut self, cursor: &Cursor) -> Result<(), DataStreamError> {
+        let last_block = cursor.number + self.prefetch_block_count as u64;
+        let mut block_number = match self.prefetched_block {
+            Some(prefetched) if prefetched > cursor.number => prefetched + 1,
+            _ => cursor.number + 1,
+        };
+
+        while block_number <= last_block {
+            let CanonicalCursor::Canonical(block_cursor) = self
+                .chain_view
+                .get_canonical(block_number)
+                .await
+                .change_context(DataStreamError)?
+            else {
+                break;
+            };
+
+            self.store.prefetch_block(&block_cursor);
+
+            self.prefetched_block = Some(block_number);
+            block_number += 1;
+        }
+
+        Ok(())
+    }
+
     async fn tick_at_head(
         &mut self,
         tx: &mpsc::Sender<DataStreamMessage>,
@@ -432,13 +656,7 @@ impl DataStream {
             .attach_printable_lazy(|| format!("generation: {}", generation))?
             .into();
 
-        let fragment_access = FragmentAccess::Block(block_entry);
-
-        let mut blocks = Vec::new();
-        if self
-            .filter_fragment(fragment_access, &finality, true, &mut blocks)
-            .await?
-        {
+        if let Some(blocks) = self.filter.filter_block(block_entry, true).await? {
             use sha2::Digest;
 
             let mut hasher = sha2::Sha256::new();
@@ -458,217 +676,18 @@ impl DataStream {
                 data: blocks,
                 finality: finality.into(),
                 production: DataProduction::Live.into(),
+                ..Default::default()
             });
 
-            let Some(Ok(permit)) = ct.run_until_cancelled(tx.reserve()).await else {
+            if !self.send_message(data, tx, ct).await {
                 return Ok(());
-            };
-
-            permit.send(Ok(StreamDataResponse {
-                message: Some(data),
-            }));
+            }
 
             *content_hash = new_content_hash;
         }
 
         Ok(())
     }
-
-    #[tracing::instrument(
-        name = "send_data",
-        skip_all,
-        fields(blocks_count, blocks_size_bytes, fragments_count, fragments_size_bytes)
-    )]
-    async fn filter_fragment<'a>(
-        &self,
-        fragment_access: FragmentAccess<'a>,
-        _finality: &DataFinality,
-        is_live: bool,
-        output: &mut Vec<Bytes>,
-    ) -> Result<bool, DataStreamError> {
-        let mut has_data = false;
-
-        let mut total_fragments_size_bytes = Vec::with_capacity(self.block_filter.len());
-        let mut total_blocks_size_bytes = Vec::with_capacity(self.block_filter.len());
-
-        for block_filter in self.block_filter.iter() {
-            let mut local_fragments_size_bytes = HashMap::<String, usize>::new();
-
-            let mut data_buffer = BytesMut::with_capacity(DEFAULT_BLOCKS_BUFFER_SIZE);
-            let mut fragment_matches = BTreeMap::default();
-
-            let mut joins = BTreeMap::<(FragmentId, FragmentId), FilterMatch>::default();
-
-            for (fragment_id, filters) in block_filter.iter() {
-                let mut filter_match = FilterMatch::default();
-
-                let indexes = fragment_access
-                    .get_index_fragment(fragment_id)
-                    .change_context(DataStreamError)
-                    .attach_printable("failed to get fragment indexes")?;
-
-                for filter in filters {
-                    let rows = filter.filter(indexes).change_context(DataStreamError)?;
-                    filter_match.add_match(filter.filter_id, &rows);
-
-                    for join_with_fragment_id in filter.joins.iter() {
-                        joins
-                            .entry((*fragment_id, *join_with_fragment_id))
-                            .or_default()
-                            .add_match(filter.filter_id, &rows);
-                    }
-                }
-
-                if filter_match.is_empty() {
-                    continue;
-                }
-
-                fragment_matches.insert(*fragment_id, filter_match);
-            }
-
-            for ((source_fragment_id, target_fragment_id), filter_match) in joins.into_iter() {
-                // Data is cached so it's fine to read it multiple times.
-                // We could group by `source_fragment_id` to cleanup the code.
-                let join_fragment = fragment_access
-                    .get_join_fragment(&source_fragment_id)
-                    .change_context(DataStreamError)
-                    .attach_printable("failed to get join fragment")?;
-
-                let Some(target_pos) = join_fragment
-                    .joins
-                    .iter()
-                    .position(|f| f.to_fragment_id == target_fragment_id)
-                else {
-                    return Err(DataStreamError)
-                        .attach_printable("join fragment not found")
-                        .attach_printable_lazy(|| {
-                            format!("source fragment id: {}", source_fragment_id)
-                        })
-                        .attach_printable_lazy(|| {
-                            format!("target fragment id: {}", target_fragment_id)
-                        });
-                };
-                let join = &join_fragment.joins[target_pos];
-
-                let target_fragment_matches =
-                    fragment_matches.entry(target_fragment_id).or_default();
-
-                match &join.index {
-                    ArchivedJoinTo::One(inner) => {
-                        for match_ in filter_match.iter() {
-                            if let Some(index) = inner.get(&match_.index) {
-                                for filter_id in match_.filter_ids.iter() {
-                                    target_fragment_matches.add_single_match(*filter_id, index);
-                                }
-                            }
-                        }
-                    }
-                    ArchivedJoinTo::Many(inner) => {
-                        for match_ in filter_match.iter() {
-                            if let Some(bitmap) = inner.get(&match_.index) {
-                                for filter_id in match_.filter_ids.iter() {
-                                    target_fragment_matches.add_match(*filter_id, &bitmap);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-
-            let should_send_header = match block_filter.header_filter {
-                HeaderFilter::Always => true,
-                HeaderFilter::OnData => !fragment_matches.is_empty(),
-                HeaderFilter::OnDataOrOnNewBlock => !fragment_matches.is_empty() || is_live,
-            };
-
-            if should_send_header {
-                let header = fragment_access
-                    .get_header_fragment()
-                    .change_context(DataStreamError)
-                    .attach_printable("failed to get header fragment")?;
-
-                prost::encoding::encode_key(
-                    HEADER_FRAGMENT_ID as u32,
-                    prost::encoding::WireType::LengthDelimited,
-                    &mut data_buffer,
-                );
-                prost::encoding::encode_varint(header.data.len() as u64, &mut data_buffer);
-                data_buffer.put(header.data.as_slice());
-            }
-
-            for (fragment_id, filter_match) in fragment_matches.into_iter() {
-                let Some(fragment_name) = self.fragment_id_to_name.get(&fragment_id).cloned()
-                else {
-                    return Err(DataStreamError)
-                        .attach_printable("unknown fragment id")
-                        .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
-                };
-
-                let body = fragment_access
-                    .get_body_fragment(&fragment_id)
-                    .change_context(DataStreamError)
-                    .attach_printable("failed to get body fragment")?;
-
-                let starting_size = data_buffer.len();
-                for match_ in filter_match.iter() {
-                    const FILTER_IDS_TAG: u32 = 1;
-
-                    let message_bytes = &body.data[match_.index as usize];
-                    let filter_ids_len = prost::encoding::uint32::encoded_len_packed(
-                        FILTER_IDS_TAG,
-                        &match_.filter_ids,
-                    );
-
-                    prost::encoding::encode_key(
-                        fragment_id as u32,
-                        prost::encoding::WireType::LengthDelimited,
-                        &mut data_buffer,
-                    );
-
-                    prost::encoding::encode_varint(
-                        (filter_ids_len + message_bytes.len()) as u64,
-                        &mut data_buffer,
-                    );
-
-                    prost::encoding::uint32::encode_packed(
-                        FILTER_IDS_TAG,
-                        &match_.filter_ids,
-                        &mut data_buffer,
-                    );
-                    data_buffer.put(message_bytes.as_slice());
-                }
-
-                let fragment_size = data_buffer.len() - starting_size;
-                *local_fragments_size_bytes.entry(fragment_name).or_default() += fragment_size;
-            }
-
-            if !data_buffer.is_empty() {
-                has_data = true;
-            }
-
-            total_blocks_size_bytes.push(data_buffer.len());
-            total_fragments_size_bytes.push(local_fragments_size_bytes);
-
-            output.push(data_buffer.freeze());
-        }
-
-        if has_data {
-            for block_size in total_blocks_size_bytes {
-                self.metrics.block_size.record(block_size as u64, &[]);
-            }
-
-            for block_fragment_size_bytes in total_fragments_size_bytes {
-                for (fragment_name, fragment_size_bytes) in block_fragment_size_bytes {
-                    self.metrics.fragment_size.record(
-                        fragment_size_bytes as u64,
-                        &[KeyValue::new("name", fragment_name)],
-                    );
-                }
-            }
-        }
-
-        Ok(has_data)
-    }
 }
 
 impl error_stack::Context for DataStreamError {}
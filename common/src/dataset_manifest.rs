@@ -0,0 +1,325 @@
+//! A small object describing the data served by a deployment.
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use error_stack::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::{
+    migrate::VERSIONED_PREFIXES,
+    object_store::{
+        GetOptions, ObjectETag, ObjectStore, ObjectStoreResultExt, PutMode, PutOptions,
+    },
+    segment::SEGMENT_FORMAT_VERSION,
+};
+
+static DATASET_MANIFEST_KEY: &str = "manifest.json";
+
+/// Maximum number of attempts when updating the manifest concurrently.
+const MAX_UPDATE_ATTEMPTS: usize = 5;
+
+/// How long a cached manifest is served before fetching it again.
+const MANIFEST_CACHE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct DatasetManifestError;
+
+/// Describes the block ranges available in the object store.
+///
+/// The manifest is updated by the ingestion and compaction services, so that
+/// clients can discover the data available without access to etcd.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetManifest {
+    /// The chain identifier, if the chain has one.
+    pub chain_id: Option<String>,
+    /// Hash of the genesis block, as hex string.
+    pub genesis_hash: Option<String>,
+    /// The oldest format version of the stored segments and groups.
+    ///
+    /// Objects written before the version was tracked may still be stored, so it
+    /// only moves to the current version once the migrate command upgraded them.
+    pub format_version: u32,
+    /// The first block available.
+    pub starting_block: Option<u64>,
+    /// The last block ingested.
+    ///
+    /// This value is refreshed together with the finalized block, so it may lag
+    /// behind the chain head.
+    pub ingested: Option<u64>,
+    /// The last finalized block.
+    pub finalized: Option<u64>,
+    /// The last block included in a segment.
+    pub segmented: Option<u64>,
+    /// The last block included in a group.
+    pub grouped: Option<u64>,
+    /// Whether ingestion reached its stop block and the dataset won't grow.
+    #[serde(default)]
+    pub complete: bool,
+}
+
+#[derive(Clone)]
+pub struct DatasetManifestStore {
+    client: ObjectStore,
+    cached: Arc<Mutex<Option<CachedManifest>>>,
+}
+
+struct CachedManifest {
+    fetched_at: Instant,
+    manifest: Option<DatasetManifest>,
+}
+
+impl DatasetManifestStore {
+    pub fn new(client: ObjectStore) -> Self {
+        Self {
+            client,
+            cached: Default::default(),
+        }
+    }
+
+    pub async fn get(&self) -> Result<Option<DatasetManifest>, DatasetManifestError> {
+        let manifest = self.get_impl().await?.map(|(manifest, _)| manifest);
+        Ok(manifest)
+    }
+
+    /// Returns the manifest, fetching it from the object store at most once every
+    /// `MANIFEST_CACHE_TTL`.
+    ///
+    /// Updates made through this store (or its clones) refresh the cached manifest.
+    pub async fn get_cached(&self) -> Result<Option<DatasetManifest>, DatasetManifestError> {
+        // Hold the lock while fetching so that concurrent callers share one request.
+        let mut cached = self.cached.lock().await;
+
+        if let Some(cached) = cached.as_ref() {
+            if cached.fetched_at.elapsed() < MANIFEST_CACHE_TTL {
+                return Ok(cached.manifest.clone());
+            }
+        }
+
+        let manifest = self.get().await?;
+
+        *cached = Some(CachedManifest {
+            fetched_at: Instant::now(),
+            manifest: manifest.clone(),
+        });
+
+        Ok(manifest)
+    }
+
+    /// Update the manifest with the given function.
+    ///
+    /// The update is conditional on the manifest not changing in the meantime and
+    /// it's retried if another service updated the manifest concurrently.
+    pub async fn update<F>(&self, f: F) -> Result<DatasetManifest, DatasetManifestError>
+    where
+        F: Fn(&mut DatasetManifest),
+    {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (mut manifest, mode) = match self.get_impl().await? {
+                Some((manifest, etag)) => (manifest, PutMode::Update(etag)),
+                None => {
+                    let manifest = DatasetManifest {
+                        format_version: self.initial_format_version().await?,
+                        ..Default::default()
+                    };
+                    (manifest, PutMode::Create)
+                }
+            };
+
+            f(&mut manifest);
+
+            let body = serde_json::to_vec(&manifest)
+                .change_context(DatasetManifestError)
+                .attach_printable("failed to serialize dataset manifest")?;
+
+            let options = PutOptions {
+                mode,
+                ..Default::default()
+            };
+
+            match self
+                .client
+                .put(DATASET_MANIFEST_KEY, Bytes::from(body), options)
+                .await
+            {
+                Ok(_) => {
+                    *self.cached.lock().await = Some(CachedManifest {
+                        fetched_at: Instant::now(),
+                        manifest: Some(manifest.clone()),
+                    });
+
+                    return Ok(manifest);
+                }
+                Err(err) if err.is_precondition() => {
+                    debug!("dataset manifest changed concurrently. retrying");
+                }
+                Err(err) => {
+                    return Err(err)
+                        .change_context(DatasetManifestError)
+                        .attach_printable("failed to put dataset manifest");
+                }
+            }
+        }
+
+        Err(DatasetManifestError)
+            .attach_printable("failed to update dataset manifest")
+            .attach_printable_lazy(|| format!("attempts: {MAX_UPDATE_ATTEMPTS}"))
+    }
+
+    /// Returns the format version of a new manifest.
+    ///
+    /// Deployments that stored segments before the manifest existed may have
+    /// objects without a version header.
+    async fn initial_format_version(&self) -> Result<u32, DatasetManifestError> {
+        for prefix in VERSIONED_PREFIXES {
+            let has_objects = self
+                .client
+                .has_objects(prefix)
+                .await
+                .change_context(DatasetManifestError)
+                .attach_printable("failed to check for stored segments")
+                .attach_printable_lazy(|| format!("prefix: {prefix}"))?;
+
+            if has_objects {
+                return Ok(1);
+            }
+        }
+
+        Ok(SEGMENT_FORMAT_VERSION)
+    }
+
+    async fn get_impl(
+        &self,
+    ) -> Result<Option<(DatasetManifest, ObjectETag)>, DatasetManifestError> {
+        let response = match self
+            .client
+            .get(DATASET_MANIFEST_KEY, GetOptions::default())
+            .await
+        {
+            Ok(response) => response,
+            Err(err) if err.is_not_found() => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .change_context(DatasetManifestError)
+                    .attach_printable("failed to get dataset manifest")
+            }
+        };
+
+        let manifest = serde_json::from_slice(&response.body)
+            .change_context(DatasetManifestError)
+            .attach_printable("failed to parse dataset manifest")?;
+
+        Ok(Some((manifest, response.etag)))
+    }
+}
+
+impl From<DatasetManifest> for apibara_dna_protocol::dna::stream::DatasetManifest {
+    fn from(value: DatasetManifest) -> Self {
+        Self {
+            chain_id: value.chain_id,
+            format_version: value.format_version,
+            starting_block: value.starting_block,
+            ingested: value.ingested,
+            finalized: value.finalized,
+            segmented: value.segmented,
+            grouped: value.grouped,
+            complete: value.complete,
+            genesis_hash: value.genesis_hash,
+        }
+    }
+}
+
+impl error_stack::Context for DatasetManifestError {}
+
+impl std::fmt::Display for DatasetManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dataset manifest error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        migrate::migrate_object_store,
+        object_store::{ObjectStore, PutOptions},
+        segment::SEGMENT_FORMAT_VERSION,
+    };
+
+    use super::DatasetManifestStore;
+
+    #[tokio::test]
+    async fn test_cached_manifest() {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let store = DatasetManifestStore::new(object_store.clone());
+        let other_store = DatasetManifestStore::new(object_store);
+
+        assert!(store.get_cached().await.unwrap().is_none());
+
+        // Updates from other stores are not visible until the cache expires.
+        other_store
+            .update(|manifest| manifest.finalized = Some(1_000))
+            .await
+            .unwrap();
+        assert!(store.get_cached().await.unwrap().is_none());
+        assert_eq!(store.get().await.unwrap().unwrap().finalized, Some(1_000));
+
+        // Updates from the same store refresh the cache.
+        store
+            .update(|manifest| manifest.finalized = Some(2_000))
+            .await
+            .unwrap();
+        let manifest = store.get_cached().await.unwrap().unwrap();
+        assert_eq!(manifest.finalized, Some(2_000));
+    }
+
+    #[tokio::test]
+    async fn test_format_version() {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let store = DatasetManifestStore::new(object_store.clone());
+
+        // New deployments only store objects in the current format.
+        let manifest = store.update(|_| {}).await.unwrap();
+        assert_eq!(manifest.format_version, SEGMENT_FORMAT_VERSION);
+
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let store = DatasetManifestStore::new(object_store.clone());
+        object_store
+            .put(
+                "segment/0000001000/header",
+                Bytes::from_static(b"legacy segment"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        // Updates keep the version until the objects are migrated.
+        let manifest = store.update(|_| {}).await.unwrap();
+        assert_eq!(manifest.format_version, 1);
+        let manifest = store
+            .update(|manifest| manifest.finalized = Some(1_000))
+            .await
+            .unwrap();
+        assert_eq!(manifest.format_version, 1);
+
+        // A dry run doesn't migrate the objects.
+        migrate_object_store(object_store.clone(), true, 4)
+            .await
+            .unwrap();
+        assert_eq!(store.get().await.unwrap().unwrap().format_version, 1);
+
+        migrate_object_store(object_store.clone(), false, 4)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get().await.unwrap().unwrap().format_version,
+            SEGMENT_FORMAT_VERSION
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Validate filters and estimate how much data they match.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use apibara_dna_protocol::json::{message_descriptor, message_from_json};
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::{
+    block_store::UncachedBlockStoreReader,
+    cli::ObjectStoreArgs,
+    data_stream::BlockFilterFactory,
+    fragment::{FragmentId, IndexGroupFragment},
+    query::{BlockFilter, ConditionKey, Filter},
+    segment::{ArchivedFragmentData, Segment},
+    Cursor,
+};
+
+use super::error::DebugCommandError;
+
+#[derive(Args, Debug)]
+pub struct DebugLintFilterCommand {
+    #[clap(flatten)]
+    object_store: ObjectStoreArgs,
+    /// Path to a JSON file with the filter, or an array of filters.
+    #[arg(long)]
+    filter: PathBuf,
+    /// Estimate the filters' selectivity on the segment starting at this block.
+    #[arg(long)]
+    block: Option<u64>,
+    /// The segment generation to read, as stored in the options.
+    #[arg(long, default_value = "0")]
+    segment_generation: u64,
+}
+
+/// Items matched by a single filter in a segment.
+#[derive(Debug, Default)]
+struct Selectivity {
+    /// Blocks with at least one match.
+    blocks: usize,
+    /// Items matched by the filter.
+    matched: u64,
+    /// Items in the filter's fragment.
+    total: u64,
+}
+
+impl DebugLintFilterCommand {
+    /// Compile the filters with the chain's filter factory and report any issue.
+    pub async fn run<BFF>(self, filter_factory: BFF) -> Result<(), DebugCommandError>
+    where
+        BFF: BlockFilterFactory,
+    {
+        let filters = read_filter_file(&self.filter, &filter_factory)?;
+
+        let mut block_filters = Vec::new();
+        let mut issues = 0;
+
+        // Compile filters one by one to report errors on the filter that caused them.
+        for (index, filter) in filters.iter().enumerate() {
+            match filter_factory.create_block_filter(std::slice::from_ref(filter)) {
+                Ok(filter) => block_filters.extend(filter.into_iter().map(|f| (index, f))),
+                Err(status) => {
+                    issues += 1;
+                    warn!(filter = index, error = status.message(), "invalid filter");
+                }
+            }
+        }
+
+        if issues == 0 {
+            if let Err(status) = filter_factory.create_block_filter(&filters) {
+                issues += 1;
+                warn!(error = status.message(), "invalid filters");
+            }
+        }
+
+        for (index, block_filter) in block_filters.iter() {
+            issues += lint_block_filter(*index, block_filter);
+        }
+
+        if let Some(block) = self.block {
+            let object_store = self.object_store.into_object_store_client().await;
+            let block_store = UncachedBlockStoreReader::new(object_store)
+                .with_segment_generation(self.segment_generation);
+            let first_cursor = Cursor::new_finalized(block);
+
+            let bytes = block_store
+                .get_index_segment(&first_cursor)
+                .await
+                .change_context(DebugCommandError)
+                .attach_printable("failed to get index segment")
+                .attach_printable_lazy(|| format!("first block: {block}"))?;
+
+            issues += estimate_selectivity(&block_filters, &bytes)?;
+        }
+
+        if issues == 0 {
+            info!(filters = filters.len(), "filters are valid");
+        } else {
+            info!(filters = filters.len(), issues, "filters have issues");
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the hex-encoded filters in the file, one per line.
+pub fn read_filter_file<BFF>(
+    path: &Path,
+    filter_factory: &BFF,
+) -> Result<Vec<Vec<u8>>, DebugCommandError>
+where
+    BFF: BlockFilterFactory,
+{
+    let content = std::fs::read_to_string(path)
+        .change_context(DebugCommandError)
+        .attach_printable("failed to read filter file")
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+    let (descriptor_set, message_name) = filter_factory.filter_descriptor();
+    decode_json_filters(&content, descriptor_set, message_name)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))
+}
+
+/// Decode a JSON filter, or an array of filters, to the chain's filter messages.
+fn decode_json_filters(
+    content: &str,
+    descriptor_set: &[u8],
+    message_name: &str,
+) -> Result<Vec<Vec<u8>>, DebugCommandError> {
+    let descriptor =
+        message_descriptor(descriptor_set, message_name).change_context(DebugCommandError)?;
+
+    let json = serde_json::from_str::<serde_json::Value>(content)
+        .change_context(DebugCommandError)
+        .attach_printable("failed to parse filter file")?;
+
+    let filters = match json {
+        serde_json::Value::Array(filters) => filters,
+        filter => vec![filter],
+    };
+
+    filters
+        .iter()
+        .enumerate()
+        .map(|(index, filter)| {
+            message_from_json(&descriptor, filter)
+                .map(|message| message.encode_to_vec())
+                .change_context(DebugCommandError)
+                .attach_printable_lazy(|| format!("invalid filter at index {index}"))
+        })
+        .collect()
+}
+
+/// Returns the number of issues found in the filter.
+fn lint_block_filter(index: usize, block_filter: &BlockFilter) -> usize {
+    let mut issues = 0;
+
+    info!(
+        filter = index,
+        header = ?block_filter.header_filter,
+        fragments = block_filter.len(),
+        "compiled filter"
+    );
+
+    if !block_filter.can_produce_data() {
+        issues += 1;
+        warn!(filter = index, "filter can never produce data");
+    }
+
+    for (fragment_id, filters) in block_filter.iter() {
+        for filter in filters {
+            info!(
+                filter = index,
+                fragment_id,
+                filter_id = filter.filter_id,
+                conditions = ?filter.conditions,
+                joins = ?filter.joins,
+                "fragment filter"
+            );
+
+            if filter.conditions.is_empty() {
+                warn!(
+                    filter = index,
+                    fragment_id,
+                    filter_id = filter.filter_id,
+                    "fragment filter matches all items"
+                );
+            }
+
+            if has_conflicting_conditions(filter) {
+                issues += 1;
+                warn!(
+                    filter = index,
+                    fragment_id,
+                    filter_id = filter.filter_id,
+                    "fragment filter requires different values for the same index and never matches"
+                );
+            }
+        }
+    }
+
+    issues
+}
+
+/// Conditions are ANDed together, so two different values for the same index
+/// can never match.
+fn has_conflicting_conditions(filter: &Filter) -> bool {
+    let mut keys = BTreeMap::new();
+    for condition in filter.conditions.iter() {
+        let ConditionKey::Eq(value) = &condition.key else {
+            continue;
+        };
+
+        if let Some(key) = keys.insert(condition.index_id, value) {
+            if key != value {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Run the filters against the index segment and print how many items they match.
+///
+/// Returns the number of filters that failed to run.
+fn estimate_selectivity(
+    block_filters: &[(usize, BlockFilter)],
+    bytes: &[u8],
+) -> Result<usize, DebugCommandError> {
+    // The archived segment must be aligned.
+    let mut aligned = rkyv::util::AlignedVec::<16>::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+
+    let segment =
+        rkyv::access::<rkyv::Archived<Segment<IndexGroupFragment>>, rkyv::rancor::Error>(&aligned)
+            .change_context(DebugCommandError)
+            .attach_printable("failed to deserialize index segment")?;
+
+    let mut issues = 0;
+
+    for (index, block_filter) in block_filters.iter() {
+        for (fragment_id, filters) in block_filter.iter() {
+            for filter in filters {
+                let Some(selectivity) = filter_selectivity(filter, *fragment_id, &segment.data)
+                else {
+                    issues += 1;
+                    warn!(
+                        filter = index,
+                        fragment_id,
+                        filter_id = filter.filter_id,
+                        "fragment filter references a missing index"
+                    );
+                    continue;
+                };
+
+                let ratio = if selectivity.total == 0 {
+                    0.0
+                } else {
+                    selectivity.matched as f64 / selectivity.total as f64
+                };
+
+                info!(
+                    filter = index,
+                    fragment_id,
+                    filter_id = filter.filter_id,
+                    blocks = selectivity.blocks,
+                    segment_blocks = segment.data.len(),
+                    matched = selectivity.matched,
+                    total = selectivity.total,
+                    selectivity = format!("{ratio:.6}"),
+                    "fragment filter selectivity"
+                );
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+fn filter_selectivity(
+    filter: &Filter,
+    fragment_id: FragmentId,
+    data: &[ArchivedFragmentData<IndexGroupFragment>],
+) -> Option<Selectivity> {
+    let mut selectivity = Selectivity::default();
+
+    for fragment in data.iter() {
+        let Some(indexes) = fragment
+            .data
+            .indexes
+            .iter()
+            .find(|index| index.fragment_id == fragment_id)
+        else {
+            continue;
+        };
+
+        let matched = filter.filter(indexes).ok()?.len();
+
+        if matched > 0 {
+            selectivity.blocks += 1;
+        }
+        selectivity.matched += matched;
+        selectivity.total += indexes.range_len.to_native() as u64;
+    }
+
+    Some(selectivity)
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_dna_protocol::evm;
+    use prost::Message;
+
+    use crate::{
+        index::ScalarValue,
+        query::{Condition, Filter},
+    };
+
+    use super::{decode_json_filters, has_conflicting_conditions};
+
+    #[test]
+    fn test_decode_json_filters() {
+        let content = r#"[
+            { "logs": [{ "address": "0x27504265a9bc4330e3fe82061a60cd8b6369b4dc" }] },
+            { "header": "HEADER_FILTER_ALWAYS" }
+        ]"#;
+
+        let filters =
+            decode_json_filters(content, evm::EVM_DESCRIPTOR_SET, "evm.v2.Filter").unwrap();
+        assert_eq!(filters.len(), 2);
+
+        let logs = evm::Filter::decode(filters[0].as_slice()).unwrap();
+        assert_eq!(
+            logs.logs[0].address.as_ref().unwrap().to_hex(),
+            "0x27504265a9bc4330e3fe82061a60cd8b6369b4dc"
+        );
+
+        let header = evm::Filter::decode(filters[1].as_slice()).unwrap();
+        assert_eq!(header.header(), evm::HeaderFilter::Always);
+
+        // A single filter doesn't need to be wrapped in an array.
+        let filters = decode_json_filters(
+            r#"{ "withdrawals": [{}] }"#,
+            evm::EVM_DESCRIPTOR_SET,
+            "evm.v2.Filter",
+        )
+        .unwrap();
+        assert_eq!(filters.len(), 1);
+
+        assert!(decode_json_filters(
+            r#"{ "blocks": [] }"#,
+            evm::EVM_DESCRIPTOR_SET,
+            "evm.v2.Filter"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_conflicting_conditions() {
+        let mut filter = Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![
+                Condition {
+                    index_id: 0,
+                    key: ScalarValue::Uint32(1).into(),
+                },
+                Condition {
+                    index_id: 1,
+                    key: ScalarValue::Uint32(2).into(),
+                },
+                Condition {
+                    index_id: 0,
+                    key: ScalarValue::Uint32(1).into(),
+                },
+            ],
+            joins: Vec::new(),
+            fields: None,
+        };
+
+        assert!(!has_conflicting_conditions(&filter));
+
+        filter.conditions.push(Condition {
+            index_id: 1,
+            key: ScalarValue::Uint32(3).into(),
+        });
+
+        assert!(has_conflicting_conditions(&filter));
+    }
+}
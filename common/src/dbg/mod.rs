@@ -1,7 +1,13 @@
 mod error;
+mod filter;
 mod index;
 mod prefetch;
+mod segment;
+mod store;
 
 pub use self::error::DebugCommandError;
+pub use self::filter::{read_filter_file, DebugLintFilterCommand};
 pub use self::index::DebugIndexCommand;
 pub use self::prefetch::run_debug_prefetch_stream;
+pub use self::segment::DebugInspectSegmentCommand;
+pub use self::store::run_debug_store_diff;
@@ -13,6 +13,7 @@ use crate::{
     data_stream::{DataStreamMetrics, SegmentAccessFetch, SegmentStream},
     file_cache::FileCacheArgs,
     fragment::FragmentId,
+    options_store::OptionsStore,
     query::BlockFilter,
 };
 
@@ -37,7 +38,13 @@ pub async fn run_debug_prefetch_stream(
         .await
         .change_context(DebugCommandError)?;
 
-    let block_store = BlockStoreReader::new(object_store.clone(), file_cache.clone());
+    let segment_generation = OptionsStore::new(&etcd_client)
+        .get_segment_generation()
+        .await
+        .change_context(DebugCommandError)?;
+
+    let block_store = BlockStoreReader::new(object_store.clone(), file_cache.clone())
+        .with_segment_generation(segment_generation);
     let (chain_view, chain_view_sync) = chain_view_sync_loop(file_cache, etcd_client, object_store)
         .await
         .change_context(DebugCommandError)?;
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use byte_unit::Byte;
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use tracing::info;
+
+use crate::{
+    block_store::UncachedBlockStoreReader,
+    cli::ObjectStoreArgs,
+    fragment::{
+        BodyFragment, FragmentId, HeaderFragment, IndexGroupFragment, IndexId, JoinGroupFragment,
+        HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_NAME,
+    },
+    index::Index,
+    segment::Segment,
+    Cursor,
+};
+
+use super::error::DebugCommandError;
+
+#[derive(Args, Debug)]
+pub struct DebugInspectSegmentCommand {
+    #[clap(flatten)]
+    object_store: ObjectStoreArgs,
+    /// The first block in the segment.
+    #[arg(long)]
+    block: u64,
+    /// The segment generation to read, as stored in the options.
+    #[arg(long, default_value = "0")]
+    segment_generation: u64,
+}
+
+/// Summary of a fragment's segment.
+#[derive(Debug)]
+struct SegmentSummary {
+    first_block: u64,
+    last_block: Option<u64>,
+    blocks: usize,
+    /// Number of items in the segment, for body fragments.
+    items: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct IndexSummary {
+    /// Keys in the index, summed over all blocks.
+    keys: usize,
+    /// Largest number of keys in a single block.
+    max_keys: usize,
+    /// Size of the serialized bitmaps, summed over all blocks.
+    bitmap_size: u64,
+}
+
+impl DebugInspectSegmentCommand {
+    pub async fn run(self) -> Result<(), DebugCommandError> {
+        let object_store = self.object_store.into_object_store_client().await;
+        let block_store = UncachedBlockStoreReader::new(object_store)
+            .with_segment_generation(self.segment_generation);
+
+        let first_cursor = Cursor::new_finalized(self.block);
+
+        let names = block_store
+            .list_segment_names(&first_cursor)
+            .await
+            .change_context(DebugCommandError)?;
+
+        if names.is_empty() {
+            return Err(DebugCommandError)
+                .attach_printable("segment not found")
+                .attach_printable_lazy(|| format!("first block: {}", self.block));
+        }
+
+        for name in names {
+            let (bytes, stored_size) = block_store
+                .get_segment_with_stored_size(&first_cursor, &name)
+                .await
+                .change_context(DebugCommandError)?;
+
+            let summary = match name.as_str() {
+                INDEX_FRAGMENT_NAME => {
+                    let segment = rkyv::from_bytes::<
+                        Segment<IndexGroupFragment>,
+                        rkyv::rancor::Error,
+                    >(&bytes)
+                    .change_context(DebugCommandError)
+                    .attach_printable("failed to deserialize index segment")?;
+                    inspect_index_segment(&segment);
+                    summarize(&segment, |_| None)
+                }
+                JOIN_FRAGMENT_NAME => {
+                    let segment =
+                        rkyv::from_bytes::<Segment<JoinGroupFragment>, rkyv::rancor::Error>(&bytes)
+                            .change_context(DebugCommandError)
+                            .attach_printable("failed to deserialize join segment")?;
+                    summarize(&segment, |_| None)
+                }
+                HEADER_FRAGMENT_NAME => {
+                    let segment =
+                        rkyv::from_bytes::<Segment<HeaderFragment>, rkyv::rancor::Error>(&bytes)
+                            .change_context(DebugCommandError)
+                            .attach_printable("failed to deserialize header segment")?;
+                    summarize(&segment, |_| None)
+                }
+                _ => {
+                    let segment =
+                        rkyv::from_bytes::<Segment<BodyFragment>, rkyv::rancor::Error>(&bytes)
+                            .change_context(DebugCommandError)
+                            .attach_printable("failed to deserialize body segment")
+                            .attach_printable_lazy(|| format!("name: {name}"))?;
+                    summarize(&segment, |fragment| Some(fragment.data.len()))
+                }
+            };
+
+            let compression_ratio = if stored_size == 0 {
+                0.0
+            } else {
+                bytes.len() as f64 / stored_size as f64
+            };
+
+            info!(
+                name = %name,
+                first_block = summary.first_block,
+                last_block = ?summary.last_block,
+                blocks = summary.blocks,
+                items = ?summary.items,
+                size = format!("{:#}", Byte::from_u64(bytes.len() as u64)),
+                stored_size = format!("{:#}", Byte::from_u64(stored_size as u64)),
+                compression_ratio = format!("{compression_ratio:.2}"),
+                "fragment segment"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn summarize<T>(segment: &Segment<T>, items: impl Fn(&T) -> Option<usize>) -> SegmentSummary {
+    let items = segment
+        .data
+        .iter()
+        .map(|fragment| items(&fragment.data))
+        .sum::<Option<usize>>();
+
+    SegmentSummary {
+        first_block: segment.first_block.number,
+        last_block: segment.data.last().map(|fragment| fragment.cursor.number),
+        blocks: segment.data.len(),
+        items,
+    }
+}
+
+fn inspect_index_segment(segment: &Segment<IndexGroupFragment>) {
+    let mut summaries = BTreeMap::<(FragmentId, IndexId), IndexSummary>::new();
+
+    for fragment in segment.data.iter() {
+        for index_fragment in fragment.data.indexes.iter() {
+            for index in index_fragment.indexes.iter() {
+                let summary = summaries
+                    .entry((index_fragment.fragment_id, index.index_id))
+                    .or_default();
+
+                if let Index::Bitmap(bitmap) = &index.index {
+                    let keys = bitmap.keys().count();
+                    summary.keys += keys;
+                    summary.max_keys = summary.max_keys.max(keys);
+                    summary.bitmap_size += bitmap
+                        .iter()
+                        .map(|(_, value)| value.len() as u64)
+                        .sum::<u64>();
+                }
+            }
+        }
+    }
+
+    for ((fragment_id, index_id), summary) in summaries {
+        info!(
+            fragment_id,
+            index_id,
+            keys = summary.keys,
+            max_keys = summary.max_keys,
+            bitmap_size = format!("{:#}", Byte::from_u64(summary.bitmap_size)),
+            "index cardinality"
+        );
+    }
+}
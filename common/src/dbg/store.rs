@@ -0,0 +1,330 @@
+//! Compare the blocks in the object store with the blocks returned by the RPC.
+use std::collections::{BTreeSet, HashMap};
+
+use error_stack::{Result, ResultExt};
+use tracing::info;
+
+use crate::{
+    block_store::UncachedBlockStoreReader,
+    cli::ObjectStoreArgs,
+    fragment::{
+        Block, BodyFragment, FragmentId, HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME,
+        INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME,
+    },
+    ingestion::{BlockIngestion, RpcRateLimiter},
+    ChainSupport,
+};
+
+use super::DebugCommandError;
+
+/// Inputs larger than this are not diffed line by line.
+const MAX_LINE_DIFF_SIZE: usize = 4_000_000;
+
+/// Download a block from the object store, fetch it again from the RPC, and print
+/// the differences between the two.
+///
+/// The `format_fragment` function returns a human-readable representation of the
+/// header and the body fragments' items, given their fragment ID.
+pub async fn run_debug_store_diff<CS>(
+    chain_support: CS,
+    format_fragment: impl Fn(FragmentId, &[u8]) -> String,
+    block_number: u64,
+    object_store: ObjectStoreArgs,
+) -> Result<(), DebugCommandError>
+where
+    CS: ChainSupport,
+{
+    let mut fragment_names = HashMap::from([
+        (INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME.to_string()),
+        (JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME.to_string()),
+        (HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME.to_string()),
+    ]);
+    for fragment in chain_support.fragment_info() {
+        fragment_names.insert(fragment.fragment_id, fragment.name);
+    }
+
+    info!(block_number, "fetching block from RPC");
+    let (block_info, rpc_block) = chain_support
+        .block_ingestion(RpcRateLimiter::default())
+        .ingest_block_by_number(block_number)
+        .await
+        .change_context(DebugCommandError)
+        .attach_printable("failed to ingest block from RPC")
+        .attach_printable_lazy(|| format!("block number: {block_number}"))?;
+
+    let cursor = block_info.cursor();
+
+    info!(cursor = %cursor, "fetching block from object store");
+    let object_store = object_store.into_object_store_client().await;
+    let block_store = UncachedBlockStoreReader::new(object_store);
+    let bytes = block_store
+        .get_block(&cursor)
+        .await
+        .change_context(DebugCommandError)
+        .attach_printable("failed to get stored block")
+        .attach_printable(
+            "the stored block has a different hash if the RPC returned another fork",
+        )?;
+
+    let stored_block = rkyv::from_bytes::<Block, rkyv::rancor::Error>(&bytes)
+        .change_context(DebugCommandError)
+        .attach_printable("failed to deserialize stored block")?;
+
+    let mut differences = 0;
+
+    if stored_block.header.data != rpc_block.header.data {
+        differences += 1;
+        print_diff(
+            HEADER_FRAGMENT_NAME,
+            &format_fragment(HEADER_FRAGMENT_ID, &stored_block.header.data),
+            &format_fragment(HEADER_FRAGMENT_ID, &rpc_block.header.data),
+        );
+    }
+
+    let fragment_name = |fragment_id: FragmentId| {
+        fragment_names
+            .get(&fragment_id)
+            .cloned()
+            .unwrap_or_else(|| format!("fragment-{fragment_id}"))
+    };
+
+    differences += diff_body(
+        &stored_block.body,
+        &rpc_block.body,
+        &fragment_name,
+        &format_fragment,
+    );
+
+    differences += diff_by_fragment(
+        INDEX_FRAGMENT_NAME,
+        &fragment_name,
+        stored_block
+            .index
+            .indexes
+            .iter()
+            .map(|index| (index.fragment_id, format!("{index:#?}"))),
+        rpc_block
+            .index
+            .indexes
+            .iter()
+            .map(|index| (index.fragment_id, format!("{index:#?}"))),
+    );
+
+    differences += diff_by_fragment(
+        JOIN_FRAGMENT_NAME,
+        &fragment_name,
+        stored_block
+            .join
+            .joins
+            .iter()
+            .map(|join| (join.fragment_id, format!("{join:#?}"))),
+        rpc_block
+            .join
+            .joins
+            .iter()
+            .map(|join| (join.fragment_id, format!("{join:#?}"))),
+    );
+
+    if differences == 0 {
+        info!(cursor = %cursor, "stored block matches RPC block");
+    } else {
+        info!(cursor = %cursor, differences, "stored block differs from RPC block");
+    }
+
+    Ok(())
+}
+
+/// Print the differences between the items of the body fragments.
+///
+/// Returns the number of items that differ.
+fn diff_body(
+    stored: &[BodyFragment],
+    rpc: &[BodyFragment],
+    fragment_name: &impl Fn(FragmentId) -> String,
+    format_fragment: &impl Fn(FragmentId, &[u8]) -> String,
+) -> usize {
+    let fragment_ids = stored
+        .iter()
+        .chain(rpc.iter())
+        .map(|fragment| fragment.fragment_id)
+        .collect::<BTreeSet<_>>();
+
+    let mut differences = 0;
+
+    for fragment_id in fragment_ids {
+        let name = fragment_name(fragment_id);
+
+        let stored_items = fragment_items(stored, fragment_id);
+        let rpc_items = fragment_items(rpc, fragment_id);
+
+        if stored_items.len() != rpc_items.len() {
+            println!(
+                "{name}: stored has {} items, RPC has {} items",
+                stored_items.len(),
+                rpc_items.len()
+            );
+        }
+
+        for index in 0..stored_items.len().max(rpc_items.len()) {
+            let stored_item = stored_items.get(index).map(Vec::as_slice);
+            let rpc_item = rpc_items.get(index).map(Vec::as_slice);
+
+            if stored_item == rpc_item {
+                continue;
+            }
+
+            differences += 1;
+
+            let stored_item = stored_item
+                .map(|data| format_fragment(fragment_id, data))
+                .unwrap_or_default();
+            let rpc_item = rpc_item
+                .map(|data| format_fragment(fragment_id, data))
+                .unwrap_or_default();
+
+            print_diff(&format!("{name}[{index}]"), &stored_item, &rpc_item);
+        }
+    }
+
+    differences
+}
+
+/// Print the differences between fragments identified by their fragment ID.
+///
+/// Returns the number of fragments that differ.
+fn diff_by_fragment(
+    group_name: &str,
+    fragment_name: &impl Fn(FragmentId) -> String,
+    stored: impl Iterator<Item = (FragmentId, String)>,
+    rpc: impl Iterator<Item = (FragmentId, String)>,
+) -> usize {
+    let stored = stored.collect::<HashMap<_, _>>();
+    let rpc = rpc.collect::<HashMap<_, _>>();
+
+    let fragment_ids = stored.keys().chain(rpc.keys()).collect::<BTreeSet<_>>();
+
+    let mut differences = 0;
+
+    for fragment_id in fragment_ids {
+        let stored = stored.get(fragment_id).map(String::as_str).unwrap_or("");
+        let rpc = rpc.get(fragment_id).map(String::as_str).unwrap_or("");
+
+        if stored == rpc {
+            continue;
+        }
+
+        differences += 1;
+        print_diff(
+            &format!("{group_name}/{}", fragment_name(*fragment_id)),
+            stored,
+            rpc,
+        );
+    }
+
+    differences
+}
+
+fn fragment_items(fragments: &[BodyFragment], fragment_id: FragmentId) -> &[Vec<u8>] {
+    fragments
+        .iter()
+        .find(|fragment| fragment.fragment_id == fragment_id)
+        .map(|fragment| fragment.data.as_slice())
+        .unwrap_or_default()
+}
+
+fn print_diff(name: &str, stored: &str, rpc: &str) {
+    println!("--- stored {name}");
+    println!("+++ rpc {name}");
+    for line in diff_lines(stored, rpc) {
+        println!("{line}");
+    }
+}
+
+/// Returns the lines removed (prefixed by `-`) and added (prefixed by `+`) to go
+/// from `old` to `new`.
+fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // Skip the common prefix and suffix to keep the table small.
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    let removed = |line: &&str| format!("-{line}");
+    let added = |line: &&str| format!("+{line}");
+
+    if old.len() * new.len() > MAX_LINE_DIFF_SIZE {
+        return old
+            .iter()
+            .map(removed)
+            .chain(new.iter().map(added))
+            .collect();
+    }
+
+    // Longest common subsequence of the remaining lines.
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(removed(&old[i]));
+            i += 1;
+        } else {
+            diff.push(added(&new[j]));
+            j += 1;
+        }
+    }
+
+    diff.extend(old[i..].iter().map(removed));
+    diff.extend(new[j..].iter().map(added));
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_lines;
+
+    #[test]
+    fn test_diff_lines() {
+        let old = "Log {\n    address: 0x1,\n    data: 0xaa,\n    index: 1,\n}";
+        let new = "Log {\n    address: 0x1,\n    data: 0xbb,\n    index: 1,\n    removed: true,\n}";
+
+        assert_eq!(
+            diff_lines(old, new),
+            vec![
+                "-    data: 0xaa,",
+                "+    data: 0xbb,",
+                "+    removed: true,"
+            ]
+        );
+
+        assert!(diff_lines(old, old).is_empty());
+        assert_eq!(diff_lines("", "a"), vec!["+a"]);
+    }
+}
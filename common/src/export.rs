@@ -0,0 +1,519 @@
+//! Export the data matching a filter to Parquet files.
+//!
+//! The command reads the segments directly from the object store and evaluates
+//! the filters like the DNA stream does, without going through the gRPC server.
+//! Only blocks that are already segmented are exported.
+//!
+//! Each segment with data is written to its own file, named after the segment's
+//! first block. Each row is one field of the chain's `Block` message:
+//!
+//!  - `block_number`, `block_hash`: the block.
+//!  - `filter_index`: the position of the filter in the filter file.
+//!  - `fragment`: the name of the field, for example `header` or `log`.
+//!  - `data`: the field's protobuf-encoded message.
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use arrow_array::{
+    builder::{BinaryBuilder, StringBuilder, UInt32Builder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use bytes::Bytes;
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use prost::encoding::WireType;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    block_store::BlockStoreReader,
+    chain_view::chain_view_sync_loop,
+    cli::{EtcdArgs, ObjectStoreArgs},
+    data_stream::{
+        BlockData, BlockFilterFactory, DataStreamMetrics, FragmentFilter, SegmentAccessFetch,
+        SegmentStream,
+    },
+    dbg::read_filter_file,
+    file_cache::FileCacheArgs,
+    fragment::{
+        FragmentId, FragmentInfo, HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_ID,
+        INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME,
+    },
+    object_store::{ObjectStore, PutOptions},
+    options_store::OptionsStore,
+    Cursor,
+};
+
+/// Number of segments fetched ahead of the one being exported.
+const SEGMENT_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug)]
+pub struct ExportError;
+
+#[derive(Args, Debug)]
+pub struct ExportCommand {
+    #[clap(flatten)]
+    object_store: ObjectStoreArgs,
+    #[clap(flatten)]
+    etcd: EtcdArgs,
+    #[clap(flatten)]
+    cache: FileCacheArgs,
+    /// Path to a JSON file with the filter, or an array of filters.
+    ///
+    /// The file uses the same format as the `tail` and `bench` commands.
+    #[arg(long)]
+    filter: PathBuf,
+    /// First block to export. Defaults to the first block available.
+    #[arg(long)]
+    from_block: Option<u64>,
+    /// Last block to export, inclusive. Defaults to the last segmented block.
+    #[arg(long)]
+    to_block: Option<u64>,
+    /// Write the Parquet files to this local directory.
+    #[arg(long, required_unless_present = "output_prefix")]
+    output: Option<PathBuf>,
+    /// Write the Parquet files to the object store, under this prefix.
+    #[arg(long, conflicts_with = "output")]
+    output_prefix: Option<String>,
+}
+
+/// Where the Parquet files are written.
+enum ExportOutput {
+    Local(PathBuf),
+    ObjectStore(ObjectStore),
+}
+
+/// Accumulates the rows of a Parquet file.
+struct RowsBuilder {
+    block_number: UInt64Builder,
+    block_hash: BinaryBuilder,
+    filter_index: UInt32Builder,
+    fragment: StringBuilder,
+    data: BinaryBuilder,
+    len: usize,
+}
+
+impl ExportCommand {
+    pub async fn run<BFF>(
+        self,
+        filter_factory: BFF,
+        fragment_info: Vec<FragmentInfo>,
+        ct: CancellationToken,
+    ) -> Result<(), ExportError>
+    where
+        BFF: BlockFilterFactory,
+    {
+        let filters =
+            read_filter_file(&self.filter, &filter_factory).change_context(ExportError)?;
+        let block_filter = match filter_factory.create_block_filter(&filters) {
+            Ok(block_filter) => block_filter,
+            Err(status) => {
+                return Err(ExportError)
+                    .attach_printable("invalid filter")
+                    .attach_printable(status.message().to_string());
+            }
+        };
+
+        let fragment_id_to_name = {
+            let mut fragment_id_to_name = HashMap::from([
+                (HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME.to_string()),
+                (INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME.to_string()),
+                (JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME.to_string()),
+            ]);
+            fragment_id_to_name.extend(
+                fragment_info
+                    .into_iter()
+                    .map(|fragment| (fragment.fragment_id, fragment.name)),
+            );
+            fragment_id_to_name
+        };
+
+        let object_store = self.object_store.into_object_store_client().await;
+
+        let output = if let Some(output) = self.output {
+            tokio::fs::create_dir_all(&output)
+                .await
+                .change_context(ExportError)
+                .attach_printable("failed to create output directory")
+                .attach_printable_lazy(|| format!("path: {}", output.display()))?;
+            ExportOutput::Local(output)
+        } else {
+            let prefix = self.output_prefix.unwrap_or_default();
+            ExportOutput::ObjectStore(object_store.with_prefix(&prefix))
+        };
+
+        let file_cache = self
+            .cache
+            .to_file_cache()
+            .await
+            .change_context(ExportError)?;
+        let etcd_client = self
+            .etcd
+            .into_etcd_client()
+            .await
+            .change_context(ExportError)?;
+
+        let segment_generation = OptionsStore::new(&etcd_client)
+            .get_segment_generation()
+            .await
+            .change_context(ExportError)?;
+
+        let block_store = BlockStoreReader::new(object_store.clone(), file_cache.clone())
+            .with_segment_generation(segment_generation);
+        let (chain_view, chain_view_sync) =
+            chain_view_sync_loop(file_cache, etcd_client, object_store)
+                .await
+                .change_context(ExportError)?;
+
+        let sync_ct = ct.child_token();
+        let mut sync_handle = tokio::spawn(chain_view_sync.start(sync_ct.clone()));
+
+        let chain_view = loop {
+            if let Some(chain_view) = chain_view.borrow().clone() {
+                break chain_view;
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {},
+                _ = ct.cancelled() => {
+                    return Ok(())
+                }
+                sync = &mut sync_handle => {
+                    sync.change_context(ExportError)?.change_context(ExportError)?;
+                    return Err(ExportError).attach_printable("chain view sync loop terminated");
+                }
+            };
+        };
+
+        let starting_cursor = match self.from_block {
+            Some(from_block) => Cursor::new_finalized(from_block),
+            None => chain_view
+                .get_starting_cursor()
+                .await
+                .change_context(ExportError)?,
+        };
+
+        info!(
+            filters = filters.len(),
+            from_block = starting_cursor.number,
+            to_block = ?self.to_block,
+            "exporting data"
+        );
+
+        let metrics = DataStreamMetrics::default();
+
+        let segment_stream = SegmentStream::new(
+            block_filter.clone(),
+            fragment_id_to_name.clone(),
+            block_store,
+            chain_view,
+            metrics.clone(),
+        );
+        let fragment_filter =
+            FragmentFilter::new(block_filter, fragment_id_to_name, metrics.clone());
+
+        let stream_ct = ct.child_token();
+        let (tx, rx) = mpsc::channel(SEGMENT_QUEUE_SIZE);
+        let stream_handle =
+            tokio::spawn(segment_stream.start(starting_cursor.clone(), tx, stream_ct.clone()));
+
+        let result = export_segments(
+            rx,
+            fragment_filter,
+            metrics,
+            starting_cursor.number,
+            self.to_block,
+            output,
+            &ct,
+        )
+        .await;
+
+        // Stop fetching segments after the last block.
+        stream_ct.cancel();
+        sync_ct.cancel();
+
+        let stream_result = stream_handle.await;
+
+        result?;
+
+        stream_result
+            .change_context(ExportError)?
+            .change_context(ExportError)
+            .attach_printable("failed to stream segments")
+    }
+}
+
+async fn export_segments(
+    mut rx: mpsc::Receiver<SegmentAccessFetch>,
+    fragment_filter: FragmentFilter,
+    metrics: DataStreamMetrics,
+    from_block: u64,
+    to_block: Option<u64>,
+    output: ExportOutput,
+    ct: &CancellationToken,
+) -> Result<(), ExportError> {
+    let schema = parquet_schema();
+
+    let mut files = 0;
+    let mut rows = 0;
+    let mut bytes = 0;
+
+    while let Some(Some(segment_fetch)) = ct.run_until_cancelled(rx.recv()).await {
+        let segment_access = segment_fetch
+            .wait(&metrics)
+            .await
+            .change_context(ExportError)
+            .attach_printable("failed to fetch segment")?;
+
+        let first_block = segment_access.first_block;
+        if to_block.is_some_and(|to_block| first_block > to_block) {
+            break;
+        }
+
+        let blocks = fragment_filter
+            .filter_segment(segment_access, from_block)
+            .await
+            .change_context(ExportError)
+            .attach_printable("failed to filter segment")?;
+
+        let mut builder = RowsBuilder::default();
+        for block in blocks.iter() {
+            if to_block.is_some_and(|to_block| block.cursor.number > to_block) {
+                break;
+            }
+
+            builder.append_block(block, fragment_filter.fragment_id_to_name())?;
+        }
+
+        if builder.is_empty() {
+            continue;
+        }
+
+        let segment_rows = builder.len();
+        let body = tokio::task::spawn_blocking({
+            let schema = schema.clone();
+            move || builder.finish(schema)
+        })
+        .await
+        .change_context(ExportError)??;
+
+        let file_name = format!("{first_block:0>10}.parquet");
+        let file_size = body.len();
+        output.write(&file_name, body).await?;
+
+        files += 1;
+        rows += segment_rows;
+        bytes += file_size;
+
+        info!(
+            file = %file_name,
+            rows = segment_rows,
+            size = file_size,
+            "exported segment"
+        );
+    }
+
+    info!(files, rows, bytes, "export completed");
+
+    Ok(())
+}
+
+impl ExportOutput {
+    async fn write(&self, file_name: &str, body: Bytes) -> Result<(), ExportError> {
+        match self {
+            ExportOutput::Local(dir) => {
+                let path = dir.join(file_name);
+                tokio::fs::write(&path, body)
+                    .await
+                    .change_context(ExportError)
+                    .attach_printable("failed to write file")
+                    .attach_printable_lazy(|| format!("path: {}", path.display()))
+            }
+            ExportOutput::ObjectStore(object_store) => {
+                object_store
+                    .put_raw(file_name, body, PutOptions::default())
+                    .await
+                    .change_context(ExportError)
+                    .attach_printable("failed to upload file")
+                    .attach_printable_lazy(|| format!("file: {file_name}"))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parquet_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("block_hash", DataType::Binary, false),
+        Field::new("filter_index", DataType::UInt32, false),
+        Field::new("fragment", DataType::Utf8, false),
+        Field::new("data", DataType::Binary, false),
+    ]))
+}
+
+impl RowsBuilder {
+    /// Add one row for each field of the block's data.
+    fn append_block(
+        &mut self,
+        block: &BlockData,
+        fragment_id_to_name: &HashMap<FragmentId, String>,
+    ) -> Result<(), ExportError> {
+        for (filter_index, data) in block.data.iter().enumerate() {
+            for (tag, field) in block_fields(data)? {
+                let Some(fragment_name) = u8::try_from(tag)
+                    .ok()
+                    .and_then(|fragment_id| fragment_id_to_name.get(&fragment_id))
+                else {
+                    return Err(ExportError)
+                        .attach_printable("unknown fragment")
+                        .attach_printable_lazy(|| format!("field tag: {tag}"));
+                };
+
+                self.block_number.append_value(block.cursor.number);
+                self.block_hash.append_value(block.cursor.hash.as_slice());
+                self.filter_index.append_value(filter_index as u32);
+                self.fragment.append_value(fragment_name);
+                self.data.append_value(field);
+                self.len += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Encode the rows as a Parquet file.
+    fn finish(mut self, schema: SchemaRef) -> Result<Bytes, ExportError> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.block_number.finish()),
+            Arc::new(self.block_hash.finish()),
+            Arc::new(self.filter_index.finish()),
+            Arc::new(self.fragment.finish()),
+            Arc::new(self.data.finish()),
+        ];
+
+        let batch = RecordBatch::try_new(schema.clone(), columns)
+            .change_context(ExportError)
+            .attach_printable("failed to create record batch")?;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+
+        let mut writer = ArrowWriter::try_new(Vec::new(), schema, Some(properties))
+            .change_context(ExportError)
+            .attach_printable("failed to create parquet writer")?;
+
+        writer
+            .write(&batch)
+            .change_context(ExportError)
+            .attach_printable("failed to write record batch")?;
+
+        let buffer = writer
+            .into_inner()
+            .change_context(ExportError)
+            .attach_printable("failed to finish parquet file")?;
+
+        Ok(buffer.into())
+    }
+}
+
+impl Default for RowsBuilder {
+    fn default() -> Self {
+        Self {
+            block_number: UInt64Builder::new(),
+            block_hash: BinaryBuilder::new(),
+            filter_index: UInt32Builder::new(),
+            fragment: StringBuilder::new(),
+            data: BinaryBuilder::new(),
+            len: 0,
+        }
+    }
+}
+
+/// Split an encoded `Block` message into its fields, as `(tag, encoded message)` pairs.
+fn block_fields(mut data: &[u8]) -> Result<Vec<(u32, &[u8])>, ExportError> {
+    let mut fields = Vec::new();
+
+    while !data.is_empty() {
+        let (tag, wire_type) = prost::encoding::decode_key(&mut data)
+            .change_context(ExportError)
+            .attach_printable("failed to decode field key")?;
+
+        if wire_type != WireType::LengthDelimited {
+            return Err(ExportError)
+                .attach_printable("unexpected wire type")
+                .attach_printable_lazy(|| format!("field tag: {tag}"));
+        }
+
+        let len = prost::encoding::decode_varint(&mut data)
+            .change_context(ExportError)
+            .attach_printable("failed to decode field length")? as usize;
+
+        if len > data.len() {
+            return Err(ExportError)
+                .attach_printable("field is truncated")
+                .attach_printable_lazy(|| format!("field tag: {tag}"));
+        }
+
+        let (field, rest) = data.split_at(len);
+        fields.push((tag, field));
+        data = rest;
+    }
+
+    Ok(fields)
+}
+
+impl error_stack::Context for ExportError {}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "export error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+    use prost::encoding::{encode_key, encode_varint, WireType};
+
+    use super::block_fields;
+
+    fn encode_field(tag: u32, data: &[u8], buffer: &mut Vec<u8>) {
+        encode_key(tag, WireType::LengthDelimited, buffer);
+        encode_varint(data.len() as u64, buffer);
+        buffer.put_slice(data);
+    }
+
+    #[test]
+    fn test_block_fields() {
+        let mut buffer = Vec::new();
+        encode_field(1, &[1, 2, 3], &mut buffer);
+        encode_field(5, &[], &mut buffer);
+        encode_field(5, &[4; 200], &mut buffer);
+
+        let fields = block_fields(&buffer).unwrap();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0], (1, &[1, 2, 3][..]));
+        assert_eq!(fields[1].0, 5);
+        assert!(fields[1].1.is_empty());
+        assert_eq!(fields[2], (5, &[4; 200][..]));
+
+        assert!(block_fields(&buffer[..buffer.len() - 1]).is_err());
+        assert!(block_fields(&[]).unwrap().is_empty());
+    }
+}
@@ -1,13 +1,14 @@
 use std::{path::PathBuf, str::FromStr, sync::Arc};
 
-use apibara_observability::mixtrics_registry;
+use apibara_observability::{mixtrics_registry, Counter, KeyValue, Meter};
 use bytes::Bytes;
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use foyer::{
-    AdmissionPicker, AdmitAllPicker, CacheEntry, Compression, DirectFsDeviceOptions, Engine,
-    HybridCache, HybridCacheBuilder, HybridFetch, LargeEngineOptions, RateLimitPicker, RecoverMode,
-    RuntimeOptions, S3FifoConfig, TokioRuntimeOptions,
+    AdmissionPicker, AdmitAllPicker, CacheEntry, Compression, DirectFsDeviceOptions, Engine, Event,
+    EventListener, EvictionConfig, HybridCache, HybridCacheBuilder, HybridFetch,
+    LargeEngineOptions, LruConfig, RateLimitPicker, RecoverMode, RuntimeOptions, S3FifoConfig,
+    TokioRuntimeOptions,
 };
 
 #[derive(Debug)]
@@ -29,6 +30,26 @@ pub type FileFetch = HybridFetch<String, Bytes>;
 
 pub type CachedFile = CacheEntry<String, Bytes>;
 
+/// In-memory eviction policy of the file cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CacheEvictionPolicy {
+    /// S3-FIFO, resistant to scans over data read once.
+    S3fifo,
+    /// Least recently used.
+    Lru,
+}
+
+/// Track entries leaving the in-memory cache.
+///
+/// Entries are reference counted, so evicting an entry that is still used by a
+/// stream only removes it from the cache. Its memory is released once the stream
+/// drops it.
+struct FileCacheEventListener {
+    attributes: [KeyValue; 1],
+    evicted: Counter<u64>,
+    evicted_bytes: Counter<u64>,
+}
+
 #[derive(Args, Debug)]
 pub struct FileCacheArgs {
     /// Where to store cached data.
@@ -128,6 +149,14 @@ pub struct FileCacheArgs {
         default_value = "1Gi"
     )]
     pub cache_flush_buffer_pool_size: String,
+    /// Set the in-memory eviction policy.
+    #[clap(
+        long = "cache.eviction",
+        env = "DNA_CACHE_EVICTION",
+        value_enum,
+        default_value = "s3fifo"
+    )]
+    pub cache_eviction: CacheEvictionPolicy,
 }
 
 impl FileCacheArgs {
@@ -154,6 +183,9 @@ impl FileCacheArgs {
                 .attach_printable_lazy(|| format!("compression: {}", self.cache_compression))?,
         };
 
+        let eviction_config = EvictionConfig::from(self.cache_eviction);
+        let meter = apibara_observability::meter("dna_file_cache");
+
         let flush_buffer_pool_size = byte_unit::Byte::from_str(&self.cache_flush_buffer_pool_size)
             .change_context(FileCacheError::Config)
             .attach_printable("failed to parse flush buffer pool size")
@@ -203,8 +235,9 @@ impl FileCacheArgs {
             HybridCacheBuilder::new()
                 .with_name("general")
                 .with_metrics_registry(mixtrics_registry("file_cache"))
+                .with_event_listener(Arc::new(FileCacheEventListener::new(&meter, "general")))
                 .memory(max_size_memory_bytes as usize)
-                .with_eviction_config(S3FifoConfig::default())
+                .with_eviction_config(eviction_config.clone())
                 .with_weighter(|_: &String, bytes: &Bytes| bytes.len())
                 .storage(Engine::Large)
                 .with_compression(compression)
@@ -276,8 +309,9 @@ impl FileCacheArgs {
             HybridCacheBuilder::new()
                 .with_name("index")
                 .with_metrics_registry(mixtrics_registry("file_cache"))
+                .with_event_listener(Arc::new(FileCacheEventListener::new(&meter, "index")))
                 .memory(max_size_memory_bytes as usize)
-                .with_eviction_config(S3FifoConfig::default())
+                .with_eviction_config(eviction_config.clone())
                 .with_weighter(|_: &String, bytes: &Bytes| bytes.len())
                 .storage(Engine::Large)
                 .with_compression(compression)
@@ -313,6 +347,46 @@ impl FileCacheArgs {
     }
 }
 
+impl From<CacheEvictionPolicy> for EvictionConfig {
+    fn from(policy: CacheEvictionPolicy) -> Self {
+        match policy {
+            CacheEvictionPolicy::S3fifo => S3FifoConfig::default().into(),
+            CacheEvictionPolicy::Lru => LruConfig::default().into(),
+        }
+    }
+}
+
+impl FileCacheEventListener {
+    fn new(meter: &Meter, cache: &'static str) -> Self {
+        Self {
+            attributes: [KeyValue::new("cache", cache)],
+            evicted: meter
+                .u64_counter("dna.file_cache.evicted")
+                .with_description("number of entries evicted from the memory cache")
+                .build(),
+            evicted_bytes: meter
+                .u64_counter("dna.file_cache.evicted_bytes")
+                .with_description("size of the entries evicted from the memory cache")
+                .with_unit("By")
+                .build(),
+        }
+    }
+}
+
+impl EventListener for FileCacheEventListener {
+    type Key = String;
+    type Value = Bytes;
+
+    fn on_leave(&self, reason: Event, _key: &Self::Key, value: &Self::Value) {
+        if !matches!(reason, Event::Evict) {
+            return;
+        }
+
+        self.evicted.add(1, &self.attributes);
+        self.evicted_bytes.add(value.len() as u64, &self.attributes);
+    }
+}
+
 impl error_stack::Context for FileCacheError {}
 
 impl std::fmt::Display for FileCacheError {
@@ -323,3 +397,145 @@ impl std::fmt::Display for FileCacheError {
         }
     }
 }
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use bytes::Bytes;
+    use error_stack::{Result, ResultExt};
+    use foyer::{DirectFsDeviceOptions, Engine, HybridCache, HybridCacheBuilder};
+    use tempfile::TempDir;
+
+    use super::{FileCache, FileCacheError};
+
+    const MEMORY_SIZE: usize = 16 * 1024 * 1024;
+    const DISK_SIZE: usize = 64 * 1024 * 1024;
+    const FILE_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Returns a small file cache stored in a temporary directory.
+    ///
+    /// The directory is removed when the returned `TempDir` is dropped.
+    pub async fn temp_file_cache() -> Result<(TempDir, FileCache), FileCacheError> {
+        let dir = tempfile::tempdir()
+            .change_context(FileCacheError::Config)
+            .attach_printable("failed to create temporary cache dir")?;
+
+        let general = temp_hybrid_cache(&dir, "general").await?;
+        let index = temp_hybrid_cache(&dir, "index").await?;
+
+        Ok((dir, FileCache { general, index }))
+    }
+
+    async fn temp_hybrid_cache(
+        dir: &TempDir,
+        name: &'static str,
+    ) -> Result<HybridCache<String, Bytes>, FileCacheError> {
+        let cache = HybridCacheBuilder::new()
+            .with_name(name)
+            .memory(MEMORY_SIZE)
+            .with_weighter(|_: &String, bytes: &Bytes| bytes.len())
+            .storage(Engine::Large)
+            .with_device_options(
+                DirectFsDeviceOptions::new(dir.path().join(name))
+                    .with_capacity(DISK_SIZE)
+                    .with_file_size(FILE_SIZE),
+            )
+            .build()
+            .await
+            .map_err(FileCacheError::Foyer)?;
+
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Weak};
+
+    use bytes::Bytes;
+    use foyer::CacheBuilder;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::{
+        metrics::{
+            data::{ResourceMetrics, Sum},
+            reader::MetricReader,
+            InstrumentKind, ManualReader, MetricResult, Pipeline, SdkMeterProvider, Temporality,
+        },
+        Resource,
+    };
+
+    use super::{CacheEvictionPolicy, FileCacheEventListener};
+
+    /// Shares a manual reader between the meter provider and the test.
+    #[derive(Clone, Debug)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> MetricResult<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> MetricResult<()> {
+            self.0.shutdown()
+        }
+
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    fn counter_value(reader: &SharedReader, name: &str) -> u64 {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::default(),
+        };
+        reader.collect(&mut metrics).unwrap();
+
+        metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+            .filter(|metric| metric.name == name)
+            .filter_map(|metric| metric.data.as_any().downcast_ref::<Sum<u64>>())
+            .flat_map(|sum| sum.data_points.iter())
+            .map(|point| point.value)
+            .sum()
+    }
+
+    #[test]
+    fn test_eviction_counter() {
+        for policy in [CacheEvictionPolicy::S3fifo, CacheEvictionPolicy::Lru] {
+            let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+            let provider = SdkMeterProvider::builder()
+                .with_reader(reader.clone())
+                .build();
+            let meter = provider.meter("test");
+
+            // The cache holds two entries, so inserting four evicts at least two.
+            let cache = CacheBuilder::new(16)
+                .with_shards(1)
+                .with_eviction_config(policy)
+                .with_weighter(|_: &String, bytes: &Bytes| bytes.len())
+                .with_event_listener(Arc::new(FileCacheEventListener::new(&meter, "test")))
+                .build();
+
+            for i in 0..4 {
+                cache.insert(format!("file-{i}"), Bytes::from(vec![0; 8]));
+            }
+
+            let evicted = counter_value(&reader, "dna.file_cache.evicted");
+            assert!(evicted >= 2, "{policy:?} evicted {evicted} entries");
+            assert_eq!(
+                counter_value(&reader, "dna.file_cache.evicted_bytes"),
+                evicted * 8
+            );
+        }
+    }
+}
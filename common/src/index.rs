@@ -91,10 +91,51 @@ impl ArchivedBitmapIndex {
             .binary_search_by(|entry| cmp_scalar_value(entry, key))
             .ok()?;
 
+        Some(self.bitmap_at(pos))
+    }
+
+    /// Returns the union of the bitmaps of the given keys.
+    ///
+    /// Returns `None` if none of the keys is in the index.
+    pub fn get_any(&self, keys: &[ScalarValue]) -> Option<RoaringBitmap> {
+        // With large key sets (e.g. thousands of addresses), it's faster to look up the
+        // index keys in the key set than the other way around.
+        if keys.len() > self.keys.len() && keys.is_sorted() {
+            return (0..self.keys.len())
+                .filter(|pos| {
+                    let entry = &self.keys[*pos];
+                    keys.binary_search_by(|key| cmp_scalar_value(entry, key).reverse())
+                        .is_ok()
+                })
+                .map(|pos| self.bitmap_at(pos))
+                .reduce(|acc, bitmap| acc | bitmap);
+        }
+
+        keys.iter()
+            .filter_map(|key| self.get(key))
+            .reduce(|acc, bitmap| acc | bitmap)
+    }
+
+    /// Returns the union of the bitmaps of the keys between `start` and `end`, inclusive.
+    ///
+    /// Returns `None` if no key is in the range.
+    pub fn get_range(&self, start: &ScalarValue, end: &ScalarValue) -> Option<RoaringBitmap> {
+        let first = self
+            .keys
+            .partition_point(|entry| cmp_scalar_value(entry, start).is_lt());
+        let last = self
+            .keys
+            .partition_point(|entry| cmp_scalar_value(entry, end).is_le());
+
+        (first..last)
+            .map(|pos| self.bitmap_at(pos))
+            .reduce(|acc, bitmap| acc | bitmap)
+    }
+
+    fn bitmap_at(&self, pos: usize) -> RoaringBitmap {
         let value = &self.values[pos];
         RoaringBitmap::deserialize_unchecked_from(value.as_slice())
             .expect("failed to deserialize bitmap")
-            .into()
     }
 }
 
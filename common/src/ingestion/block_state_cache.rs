@@ -0,0 +1,254 @@
+//! Cache the state of contracts observed at a block, for example the class of a contract.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use roaring::RoaringBitmap;
+
+/// Caches a value of each key, like the class of a contract, as it is at the end of a block.
+///
+/// Blocks are ingested concurrently and out of order, so a value observed at one block
+/// is only used for another block if the cache has seen the changes of all blocks in
+/// between, and none of them changed the value.
+///
+/// The least recently used values are evicted when the cache is full.
+#[derive(Clone)]
+pub struct BlockStateCache<K, V> {
+    inner: Arc<Mutex<BlockStateCacheInner<K, V>>>,
+}
+
+struct BlockStateCacheInner<K, V> {
+    max_entries: usize,
+    /// Incremented on each access, used to evict the least recently used values.
+    clock: u64,
+    entries: HashMap<K, CachedState<V>>,
+    /// Keys ordered by last use, oldest first.
+    by_last_used: BTreeMap<u64, K>,
+    /// The blocks whose changes were applied.
+    applied: RoaringBitmap,
+    /// The blocks in which the value of each key changed.
+    changed_at: HashMap<K, BTreeSet<u64>>,
+}
+
+struct CachedState<V> {
+    value: V,
+    observed_at: u64,
+    last_used: u64,
+}
+
+impl<K, V> BlockStateCache<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    pub fn new(max_entries: usize) -> Self {
+        let inner = BlockStateCacheInner {
+            max_entries,
+            clock: 0,
+            entries: HashMap::default(),
+            by_last_used: BTreeMap::default(),
+            applied: RoaringBitmap::new(),
+            changed_at: HashMap::default(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Returns the value at the end of the given block, if known.
+    pub fn get(&self, key: &K, block_number: u64) -> Option<V> {
+        let mut inner = self.inner.lock().expect("block state cache lock");
+
+        let observed_at = inner.entries.get(key)?.observed_at;
+        if !inner.is_unchanged_between(key, observed_at, block_number) {
+            return None;
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let entry = inner.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        let value = entry.value.clone();
+
+        inner.by_last_used.remove(&previous);
+        inner.by_last_used.insert(clock, key.clone());
+
+        Some(value)
+    }
+
+    /// Store the value at the end of the given block, as returned by the node.
+    pub fn insert(&self, key: K, value: V, block_number: u64) {
+        let mut inner = self.inner.lock().expect("block state cache lock");
+        inner.insert(key, value, block_number);
+    }
+
+    /// Record the values changed by the block.
+    ///
+    /// Must be called for every ingested block, including blocks without changes.
+    pub fn apply_block(&self, block_number: u64, changes: impl IntoIterator<Item = (K, V)>) {
+        let mut inner = self.inner.lock().expect("block state cache lock");
+
+        let Ok(block) = u32::try_from(block_number) else {
+            return;
+        };
+
+        // The block was ingested again after a reorg, so the cached values may come
+        // from blocks that are not canonical anymore.
+        if inner.applied.contains(block) {
+            inner.clear();
+        }
+
+        inner.applied.insert(block);
+
+        for (key, value) in changes {
+            inner
+                .changed_at
+                .entry(key.clone())
+                .or_default()
+                .insert(block_number);
+            inner.insert(key, value, block_number);
+        }
+    }
+}
+
+impl<K, V> BlockStateCacheInner<K, V>
+where
+    K: Clone + Eq + Hash,
+{
+    /// Returns true if the value at the end of both blocks is the same.
+    fn is_unchanged_between(&self, key: &K, a: u64, b: u64) -> bool {
+        let (start, end) = if a <= b { (a, b) } else { (b, a) };
+        if start == end {
+            return true;
+        }
+
+        // Check the changes of the blocks after `start`, up to `end` included.
+        let (Ok(start32), Ok(end32)) = (u32::try_from(start), u32::try_from(end)) else {
+            return false;
+        };
+
+        let applied_count = self.applied.rank(end32) - self.applied.rank(start32);
+        if applied_count != end - start {
+            return false;
+        }
+
+        self.changed_at
+            .get(key)
+            .is_none_or(|changed_at| changed_at.range(start + 1..=end).next().is_none())
+    }
+
+    fn insert(&mut self, key: K, value: V, observed_at: u64) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        let entry = CachedState {
+            value,
+            observed_at,
+            last_used: clock,
+        };
+
+        if let Some(previous) = self.entries.get(&key) {
+            // Keep the most recent value.
+            if previous.observed_at > observed_at {
+                return;
+            }
+            self.by_last_used.remove(&previous.last_used);
+        }
+
+        self.by_last_used.insert(clock, key.clone());
+        self.entries.insert(key, entry);
+
+        while self.entries.len() > self.max_entries {
+            let Some((_, oldest)) = self.by_last_used.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_last_used.clear();
+        self.applied.clear();
+        self.changed_at.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockStateCache;
+
+    #[test]
+    fn test_values_across_blocks() {
+        let cache = BlockStateCache::<u8, u8>::new(100);
+
+        cache.insert(1, 10, 100);
+        assert_eq!(cache.get(&1, 100), Some(10));
+        // The blocks in between are not known yet.
+        assert_eq!(cache.get(&1, 99), None);
+        assert_eq!(cache.get(&1, 102), None);
+
+        for block in 99..=102 {
+            cache.apply_block(block, []);
+        }
+        assert_eq!(cache.get(&1, 97), None);
+        assert_eq!(cache.get(&1, 98), Some(10));
+        assert_eq!(cache.get(&1, 102), Some(10));
+        assert_eq!(cache.get(&2, 100), None);
+    }
+
+    #[test]
+    fn test_changes_out_of_order() {
+        let cache = BlockStateCache::<u8, u8>::new(100);
+
+        // The value is looked up at a later block, before the block that changed it.
+        cache.insert(1, 20, 105);
+        for block in 100..=105 {
+            if block != 103 {
+                cache.apply_block(block, []);
+            }
+        }
+        assert_eq!(cache.get(&1, 101), None);
+
+        cache.apply_block(103, [(1, 20)]);
+        assert_eq!(cache.get(&1, 103), Some(20));
+        assert_eq!(cache.get(&1, 104), Some(20));
+        assert_eq!(cache.get(&1, 102), None);
+
+        // Older values don't replace newer ones.
+        cache.insert(1, 10, 101);
+        assert_eq!(cache.get(&1, 104), Some(20));
+        assert_eq!(cache.get(&1, 102), None);
+    }
+
+    #[test]
+    fn test_reorg_clears_cache() {
+        let cache = BlockStateCache::<u8, u8>::new(100);
+
+        cache.apply_block(100, [(1, 10)]);
+        cache.apply_block(101, []);
+        assert_eq!(cache.get(&1, 101), Some(10));
+
+        cache.apply_block(101, []);
+        assert_eq!(cache.get(&1, 101), None);
+        assert_eq!(cache.get(&1, 100), None);
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = BlockStateCache::<u8, u8>::new(2);
+
+        cache.insert(1, 10, 100);
+        cache.insert(2, 20, 100);
+        assert_eq!(cache.get(&1, 100), Some(10));
+
+        cache.insert(3, 30, 100);
+        assert_eq!(cache.get(&1, 100), Some(10));
+        assert_eq!(cache.get(&2, 100), None);
+        assert_eq!(cache.get(&3, 100), Some(30));
+    }
+}
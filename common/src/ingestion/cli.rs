@@ -49,6 +49,56 @@ pub struct IngestionArgs {
         default_value = "30s"
     )]
     pub ingestion_finalized_refresh_interval: String,
+    /// Ingest finalized blocks in chunks of this many blocks, each by an independent worker.
+    #[clap(
+        long = "ingestion.backfill-chunk-size",
+        env = "DNA_INGESTION_BACKFILL_CHUNK_SIZE"
+    )]
+    pub ingestion_backfill_chunk_size: Option<u64>,
+    /// Maximum number of block uploads in flight for each backfill chunk.
+    #[clap(
+        long = "ingestion.max-concurrent-uploads",
+        env = "DNA_INGESTION_MAX_CONCURRENT_UPLOADS",
+        default_value = "8"
+    )]
+    pub ingestion_max_concurrent_uploads: usize,
+    /// Time-to-live of the ingestion lock, for example "10s".
+    ///
+    /// Standby instances take over at most this long after the active instance dies.
+    #[clap(
+        long = "ingestion.lock-ttl",
+        env = "DNA_INGESTION_LOCK_TTL",
+        default_value = "60s"
+    )]
+    pub ingestion_lock_ttl: String,
+    /// How often standby instances refresh their cache, for example "10s".
+    #[clap(
+        long = "ingestion.standby-warm-interval",
+        env = "DNA_INGESTION_STANDBY_WARM_INTERVAL",
+        default_value = "10s"
+    )]
+    pub ingestion_standby_warm_interval: String,
+    /// Consider blocks this many blocks below the head finalized.
+    ///
+    /// Use this on chains where the RPC's finalized block is missing or unreliable.
+    #[clap(
+        long = "ingestion.finality-depth",
+        env = "DNA_INGESTION_FINALITY_DEPTH"
+    )]
+    pub ingestion_finality_depth: Option<u64>,
+    /// Stop ingestion after this block is ingested and finalized.
+    ///
+    /// The dataset is then marked as complete and the server keeps serving it.
+    #[clap(long = "ingestion.stop-block", env = "DNA_INGESTION_STOP_BLOCK")]
+    pub ingestion_stop_block: Option<u64>,
+    /// Maximum number of RPC requests per second.
+    ///
+    /// Reloaded on SIGHUP.
+    #[clap(long = "rpc.rate-limit", env = "DNA_RPC_RATE_LIMIT")]
+    pub rpc_rate_limit: Option<u32>,
+    /// Maximum number of concurrent RPC requests.
+    #[clap(long = "rpc.concurrency", env = "DNA_RPC_CONCURRENCY")]
+    pub rpc_concurrency: Option<usize>,
 }
 
 impl IngestionArgs {
@@ -74,6 +124,22 @@ impl IngestionArgs {
                     .attach_printable(format!("error: {}", err))
             })?;
 
+        let lock_ttl = duration_str::parse_std(&self.ingestion_lock_ttl).or_else(|err| {
+            Err(IngestionError::Options)
+                .attach_printable("failed to parse lock ttl")
+                .attach_printable(format!("error: {}", err))
+        })?;
+        if lock_ttl.as_secs() < 2 {
+            return Err(IngestionError::Options)
+                .attach_printable("lock ttl must be at least 2 seconds");
+        }
+        let standby_warm_interval = duration_str::parse_std(&self.ingestion_standby_warm_interval)
+            .or_else(|err| {
+                Err(IngestionError::Options)
+                    .attach_printable("failed to parse standby warm interval")
+                    .attach_printable(format!("error: {}", err))
+            })?;
+
         Ok(super::IngestionServiceOptions {
             max_concurrent_tasks: self.ingestion_max_concurrent_tasks,
             chain_segment_size: self.ingestion_chain_segment_size,
@@ -82,6 +148,14 @@ impl IngestionArgs {
             pending_refresh_interval,
             head_refresh_interval,
             finalized_refresh_interval,
+            rpc_rate_limit: self.rpc_rate_limit,
+            rpc_concurrency: self.rpc_concurrency,
+            backfill_chunk_size: self.ingestion_backfill_chunk_size,
+            max_concurrent_uploads: self.ingestion_max_concurrent_uploads,
+            lock_ttl,
+            standby_warm_interval,
+            finality_depth: self.ingestion_finality_depth,
+            stop_block: self.ingestion_stop_block,
         })
     }
 }
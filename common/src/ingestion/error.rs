@@ -12,16 +12,24 @@ pub enum IngestionError {
     BadHash,
     Model,
     Indexing,
+    DatasetManifest,
+    InvalidBlock,
+    ChainMismatch,
 }
 
 pub trait IngestionErrorExt {
     fn is_block_not_found(&self) -> bool;
+    fn is_invalid_block(&self) -> bool;
 }
 
 impl IngestionErrorExt for Report<IngestionError> {
     fn is_block_not_found(&self) -> bool {
         matches!(self.current_context(), IngestionError::BlockNotFound)
     }
+
+    fn is_invalid_block(&self) -> bool {
+        matches!(self.current_context(), IngestionError::InvalidBlock)
+    }
 }
 
 impl error_stack::Context for IngestionError {}
@@ -51,6 +59,15 @@ impl std::fmt::Display for IngestionError {
             IngestionError::Indexing => {
                 write!(f, "ingestion error: indexing error")
             }
+            IngestionError::DatasetManifest => {
+                write!(f, "ingestion error: dataset manifest error")
+            }
+            IngestionError::InvalidBlock => {
+                write!(f, "ingestion error: invalid block")
+            }
+            IngestionError::ChainMismatch => {
+                write!(f, "ingestion error: rpc chain does not match stored data")
+            }
         }
     }
 }
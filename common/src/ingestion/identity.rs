@@ -0,0 +1,114 @@
+//! Check that the RPC serves the same chain as the data in the object store.
+use error_stack::{Result, ResultExt};
+use tracing::info;
+
+use crate::dataset_manifest::DatasetManifestStore;
+
+use super::{BlockIngestion, IngestionError};
+
+/// Compare the chain id and genesis hash of the RPC with the ones recorded in the
+/// dataset manifest.
+///
+/// Values missing from the manifest are recorded, so that the next check can compare them.
+pub async fn validate_chain_identity<I>(
+    ingestion: &I,
+    manifest_store: &DatasetManifestStore,
+) -> Result<(), IngestionError>
+where
+    I: BlockIngestion + Sync,
+{
+    let chain_id = ingestion.get_chain_id().await?;
+    let genesis_hash = ingestion
+        .get_genesis_hash()
+        .await
+        .attach_printable("failed to get genesis hash")?
+        .to_string();
+
+    let manifest = manifest_store
+        .get()
+        .await
+        .change_context(IngestionError::DatasetManifest)?
+        .unwrap_or_default();
+
+    if let (Some(stored), Some(current)) = (&manifest.chain_id, &chain_id) {
+        if stored != current {
+            return Err(IngestionError::ChainMismatch)
+                .attach_printable("chain id does not match the dataset manifest")
+                .attach_printable_lazy(|| format!("stored chain id: {stored}"))
+                .attach_printable_lazy(|| format!("rpc chain id: {current}"));
+        }
+    }
+
+    if let Some(stored) = &manifest.genesis_hash {
+        if stored != &genesis_hash {
+            return Err(IngestionError::ChainMismatch)
+                .attach_printable("genesis hash does not match the dataset manifest")
+                .attach_printable_lazy(|| format!("stored genesis hash: {stored}"))
+                .attach_printable_lazy(|| format!("rpc genesis hash: {genesis_hash}"));
+        }
+    }
+
+    if manifest.genesis_hash.is_none() || (manifest.chain_id.is_none() && chain_id.is_some()) {
+        info!(?chain_id, genesis_hash, "recording chain identity");
+
+        manifest_store
+            .update(|manifest| {
+                if manifest.chain_id.is_none() {
+                    manifest.chain_id = chain_id.clone();
+                }
+                if manifest.genesis_hash.is_none() {
+                    manifest.genesis_hash = Some(genesis_hash.clone());
+                }
+            })
+            .await
+            .change_context(IngestionError::DatasetManifest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dataset_manifest::DatasetManifestStore,
+        ingestion::{simulated::SimulatedChain, IngestionError},
+        object_store::ObjectStore,
+    };
+
+    use super::validate_chain_identity;
+
+    #[tokio::test]
+    async fn test_validate_chain_identity() {
+        let manifest_store =
+            DatasetManifestStore::new(ObjectStore::new_in_memory(Default::default()));
+        let chain = SimulatedChain::new();
+
+        validate_chain_identity(&chain, &manifest_store)
+            .await
+            .unwrap();
+
+        let manifest = manifest_store.get().await.unwrap().unwrap();
+        assert_eq!(
+            manifest.genesis_hash,
+            Some(chain.block(0).unwrap().hash.to_string())
+        );
+
+        // The same chain is accepted again.
+        validate_chain_identity(&chain, &manifest_store)
+            .await
+            .unwrap();
+
+        manifest_store
+            .update(|manifest| manifest.genesis_hash = Some("0x01".to_string()))
+            .await
+            .unwrap();
+
+        let err = validate_chain_identity(&chain, &manifest_store)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.current_context(),
+            IngestionError::ChainMismatch
+        ));
+    }
+}
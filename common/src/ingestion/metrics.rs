@@ -1,4 +1,4 @@
-use apibara_observability::{Gauge, Histogram, RequestMetrics};
+use apibara_observability::{Counter, Gauge, Histogram, RequestMetrics};
 
 #[derive(Debug, Clone)]
 pub struct IngestionMetrics {
@@ -10,6 +10,7 @@ pub struct IngestionMetrics {
     pub block_size: Histogram<u64>,
     pub rpc: RequestMetrics,
     pub block_upload: RequestMetrics,
+    pub invalid_block: Counter<u64>,
 }
 
 impl Default for IngestionMetrics {
@@ -63,6 +64,10 @@ impl Default for IngestionMetrics {
                 .build(),
             rpc: RequestMetrics::new("dna_ingestion", "dna.ingestion.rpc"),
             block_upload: RequestMetrics::new("dna_ingestion", "dna.ingestion.block_upload"),
+            invalid_block: meter
+                .u64_counter("dna.ingestion.invalid_block")
+                .with_description("blocks that failed validation before upload")
+                .build(),
         }
     }
 }
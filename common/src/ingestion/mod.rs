@@ -1,33 +1,54 @@
+mod block_state_cache;
 mod cli;
 mod error;
+mod identity;
 mod metrics;
+mod rate_limit;
 mod service;
+#[cfg(any(test, feature = "testing"))]
+pub mod simulated;
+mod standby;
 pub mod state_client;
+mod validate;
 
 use apibara_etcd::{EtcdClient, LockOptions};
 use error_stack::{Result, ResultExt};
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
+use crate::block_store::BlockStoreReader;
+use crate::chain_store::ChainStore;
 use crate::file_cache::FileCache;
 use crate::object_store::ObjectStore;
 use crate::options_store::OptionsStore;
+use crate::reload::RuntimeSettings;
 
+pub use self::block_state_cache::BlockStateCache;
 pub use self::cli::IngestionArgs;
 pub use self::error::{IngestionError, IngestionErrorExt};
+pub use self::identity::validate_chain_identity;
 pub use self::metrics::IngestionMetrics;
-pub use self::service::{BlockIngestion, IngestionService, IngestionServiceOptions};
+pub use self::rate_limit::{RpcPermit, RpcRateLimiter};
+pub use self::service::{
+    BlockIngestion, HeadSubscription, IngestionService, IngestionServiceOptions,
+};
 pub use self::state_client::{
     IngestionStateClient, IngestionStateClientError, IngestionStateUpdate, FINALIZED_KEY,
     INGESTED_KEY, INGESTION_PREFIX_KEY, STARTING_BLOCK_KEY,
 };
+pub use self::validate::{validate_block, validate_fragments};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn ingestion_service_loop<I>(
     ingestion: I,
+    rate_limiter: RpcRateLimiter,
     etcd_client: EtcdClient,
     object_store: ObjectStore,
     file_cache: FileCache,
     options: IngestionServiceOptions,
+    settings: watch::Receiver<RuntimeSettings>,
+    paused: watch::Receiver<bool>,
     ct: CancellationToken,
 ) -> Result<(), IngestionError>
 where
@@ -37,23 +58,40 @@ where
 
     let metrics = IngestionMetrics::default();
 
-    let mut lock_client = etcd_client.lock_client(LockOptions::default());
+    let mut lock_client = etcd_client.lock_client(LockOptions {
+        ttl: options.lock_ttl.as_secs() as i64,
+    });
 
     while !ct.is_cancelled() {
         info!("acquiring ingestion lock");
 
         metrics.up.record(1, &[KeyValue::new("active", false)]);
+        metrics.up.record(0, &[KeyValue::new("active", true)]);
+
+        // Keep the cache warm while in standby.
+        let standby_ct = ct.child_token();
+        let standby_handle = tokio::spawn(standby::standby_warm_cache_loop(
+            ChainStore::new(object_store.clone(), file_cache.clone()),
+            BlockStoreReader::new(object_store.clone(), file_cache.clone()),
+            options.standby_warm_interval,
+            standby_ct.clone(),
+        ));
 
-        let Some(mut lock) = lock_client
+        let lock = lock_client
             .lock("ingestion/lock", ct.clone())
             .await
-            .change_context(IngestionError::LockKeepAlive)?
-        else {
+            .change_context(IngestionError::LockKeepAlive);
+
+        standby_ct.cancel();
+        let _ = standby_handle.await;
+
+        let Some(mut lock) = lock? else {
             warn!("failed to acquire ingestion lock");
             break;
         };
 
         info!("ingestion lock acquired");
+        metrics.up.record(0, &[KeyValue::new("active", false)]);
         metrics.up.record(1, &[KeyValue::new("active", true)]);
 
         // Compare the current options with the stored options.
@@ -92,7 +130,21 @@ where
             metrics.clone(),
         );
 
-        match ingestion_service.start(&mut lock, ct.clone()).await {
+        let settings_ct = ct.child_token();
+        let settings_handle = tokio::spawn(rate_limit_settings_loop(
+            rate_limiter.clone(),
+            settings.clone(),
+            settings_ct.clone(),
+        ));
+
+        let result = ingestion_service
+            .start(&mut lock, paused.clone(), ct.clone())
+            .await;
+
+        settings_ct.cancel();
+        let _ = settings_handle.await;
+
+        match result {
             Ok(_) => {
                 lock_client
                     .unlock(lock)
@@ -115,5 +167,36 @@ where
         }
     }
 
+    // Ingestion is no longer running, either because it's shutting down or because
+    // it reached the stop block.
+    metrics.up.record(0, &[KeyValue::new("active", false)]);
+    metrics.up.record(0, &[KeyValue::new("active", true)]);
+
+    // Ingestion stops early if it reaches the stop block.
+    // Keep running so that the other services don't shut down.
+    ct.cancelled().await;
+
     Ok(())
 }
+
+/// Update the RPC rate limit when the runtime settings change.
+async fn rate_limit_settings_loop(
+    rate_limiter: RpcRateLimiter,
+    mut settings: watch::Receiver<RuntimeSettings>,
+    ct: CancellationToken,
+) {
+    loop {
+        let rate_limit = settings.borrow_and_update().rpc_rate_limit;
+        rate_limiter.set_rate_limit(rate_limit);
+
+        tokio::select! {
+            _ = ct.cancelled() => break,
+            changed = settings.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                info!(rate_limit = ?settings.borrow().rpc_rate_limit, "updating RPC rate limit");
+            }
+        }
+    }
+}
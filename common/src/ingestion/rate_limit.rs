@@ -0,0 +1,185 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use apibara_observability::{Counter, KeyValue};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the rate and concurrency of requests to the chain's RPC.
+///
+/// The chain's RPC pool acquires a permit for every request it sends, including
+/// requests that fail over to another endpoint.
+#[derive(Clone)]
+pub struct RpcRateLimiter {
+    bucket: Arc<Mutex<Option<TokenBucket>>>,
+    semaphore: Option<Arc<Semaphore>>,
+    throttled: Counter<u64>,
+}
+
+/// Held while a request is in flight.
+pub struct RpcPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl RpcRateLimiter {
+    /// Creates a new rate limiter.
+    ///
+    /// `rate_limit` is the number of requests per second, `concurrency` the maximum
+    /// number of requests in flight.
+    pub fn new(rate_limit: Option<u32>, concurrency: Option<usize>) -> Self {
+        let bucket = rate_limit
+            .filter(|rate_limit| *rate_limit > 0)
+            .map(|rate_limit| TokenBucket::new(rate_limit as f64));
+        let semaphore = concurrency
+            .filter(|concurrency| *concurrency > 0)
+            .map(|concurrency| Arc::new(Semaphore::new(concurrency)));
+
+        let throttled = apibara_observability::meter("dna_ingestion")
+            .u64_counter("dna.ingestion.rpc_throttled")
+            .with_description("rpc requests delayed by the rate or concurrency limit")
+            .build();
+
+        Self {
+            bucket: Arc::new(Mutex::new(bucket)),
+            semaphore,
+            throttled,
+        }
+    }
+
+    /// Wait until a request can be sent.
+    pub async fn acquire(&self, method: &'static str) -> RpcPermit {
+        let permit = if let Some(semaphore) = &self.semaphore {
+            let permit = match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    self.record_throttled(method);
+                    semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("rpc semaphore is never closed")
+                }
+            };
+            Some(permit)
+        } else {
+            None
+        };
+
+        let mut throttled = false;
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .expect("rpc token bucket lock")
+                .as_mut()
+                .and_then(|bucket| bucket.try_take(Instant::now()));
+
+            let Some(wait) = wait else {
+                break;
+            };
+
+            if !throttled {
+                self.record_throttled(method);
+                throttled = true;
+            }
+
+            tokio::time::sleep(wait).await;
+        }
+
+        RpcPermit { _permit: permit }
+    }
+
+    /// Change the number of requests per second, shared by all clones of the limiter.
+    ///
+    /// `None` or 0 removes the limit.
+    pub fn set_rate_limit(&self, rate_limit: Option<u32>) {
+        let mut bucket = self.bucket.lock().expect("rpc token bucket lock");
+
+        *bucket = match (
+            rate_limit.filter(|rate_limit| *rate_limit > 0),
+            bucket.take(),
+        ) {
+            (None, _) => None,
+            (Some(rate_limit), None) => Some(TokenBucket::new(rate_limit as f64)),
+            (Some(rate_limit), Some(mut existing)) => {
+                existing.set_rate_limit(rate_limit as f64);
+                Some(existing)
+            }
+        };
+    }
+
+    fn record_throttled(&self, method: &'static str) {
+        self.throttled.add(1, &[KeyValue::new("method", method)]);
+    }
+}
+
+impl Default for RpcRateLimiter {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl TokenBucket {
+    fn new(rate_limit: f64) -> Self {
+        Self {
+            capacity: rate_limit,
+            tokens: rate_limit,
+            refill_per_sec: rate_limit,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Change the rate, keeping the tokens already in the bucket.
+    fn set_rate_limit(&mut self, rate_limit: f64) {
+        self.capacity = rate_limit;
+        self.refill_per_sec = rate_limit;
+        self.tokens = self.tokens.min(rate_limit);
+    }
+
+    /// Take a token, returning how long to wait if the bucket is empty.
+    fn try_take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        let missing = 1.0 - self.tokens;
+        Some(Duration::from_secs_f64(missing / self.refill_per_sec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::TokenBucket;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(2.0);
+        let now = Instant::now();
+
+        assert!(bucket.try_take(now).is_none());
+        assert!(bucket.try_take(now).is_none());
+
+        let wait = bucket.try_take(now).unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        let later = now + Duration::from_millis(500);
+        assert!(bucket.try_take(later).is_none());
+        assert!(bucket.try_take(later).is_some());
+    }
+}
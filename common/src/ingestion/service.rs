@@ -1,11 +1,19 @@
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use apibara_etcd::{EtcdClient, Lock};
 use apibara_observability::{KeyValue, RecordRequest};
 use error_stack::{Result, ResultExt};
-use futures::{stream::FuturesOrdered, StreamExt};
+use futures::{
+    stream::{BoxStream, FuturesOrdered},
+    StreamExt,
+};
 use tokio::{
-    task::{JoinError, JoinHandle},
+    sync::watch,
+    task::{JoinError, JoinHandle, JoinSet},
     time::Interval,
 };
 use tokio_util::sync::CancellationToken;
@@ -15,14 +23,29 @@ use crate::{
     block_store::BlockStoreWriter,
     chain::{BlockInfo, CanonicalChainBuilder, CanonicalChainSegment, PendingBlockInfo},
     chain_store::ChainStore,
+    dataset_manifest::DatasetManifestStore,
     file_cache::FileCache,
     fragment::Block,
     ingestion::IngestionErrorExt,
     object_store::ObjectStore,
-    Cursor,
+    Cursor, Hash,
 };
 
-use super::{error::IngestionError, metrics::IngestionMetrics, state_client::IngestionStateClient};
+use super::{
+    error::IngestionError,
+    metrics::IngestionMetrics,
+    state_client::IngestionStateClient,
+    validate::{validate_block, validate_fragments},
+};
+
+/// How many times a block that fails validation is ingested again before giving up.
+const MAX_INVALID_BLOCK_RETRIES: usize = 3;
+
+/// Resubscribe to head changes if the subscription is silent for this long.
+const HEAD_SUBSCRIPTION_STALE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the lock is kept alive while ingestion is paused.
+const PAUSED_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
 
 pub trait BlockIngestion: Clone {
     fn supports_pending(&self) -> bool {
@@ -33,6 +56,33 @@ pub trait BlockIngestion: Clone {
 
     fn get_finalized_cursor(&self) -> impl Future<Output = Result<Cursor, IngestionError>> + Send;
 
+    /// Subscribe to notifications of new heads.
+    ///
+    /// When a subscription is available, the head is refreshed on each notification and
+    /// polling only runs if the subscription goes quiet.
+    /// Returns `None` if the chain doesn't support subscriptions.
+    fn subscribe_heads(
+        &self,
+    ) -> impl Future<Output = Result<Option<HeadSubscription>, IngestionError>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Returns the chain identifier, if the chain has one.
+    fn get_chain_id(&self) -> impl Future<Output = Result<Option<String>, IngestionError>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Returns the hash that identifies the chain's genesis.
+    ///
+    /// Defaults to the hash of block zero. Chains whose nodes may not have block
+    /// zero, like checkpoint-synced beacon nodes, should override it.
+    fn get_genesis_hash(&self) -> impl Future<Output = Result<Hash, IngestionError>> + Send
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.get_block_info_by_number(0).await?.hash) }
+    }
+
     fn get_block_info_by_number(
         &self,
         block_number: u64,
@@ -55,11 +105,16 @@ pub trait BlockIngestion: Clone {
 
 pub enum IngestionTask {
     Main(BlockInfo),
+    /// A range of finalized blocks, in order.
+    Backfill(Vec<BlockInfo>),
     Pending(Option<PendingBlockInfo>),
 }
 
 type IngestionTaskHandle = JoinHandle<Result<IngestionTask, IngestionError>>;
 
+/// A stream that emits an item every time the chain head changes.
+pub type HeadSubscription = BoxStream<'static, Result<(), IngestionError>>;
+
 #[derive(Clone, Debug)]
 pub struct IngestionServiceOptions {
     /// Maximum number of concurrent ingestion tasks.
@@ -76,6 +131,31 @@ pub struct IngestionServiceOptions {
     pub head_refresh_interval: Duration,
     /// How often to refresh the finalized block.
     pub finalized_refresh_interval: Duration,
+    /// Maximum number of RPC requests per second.
+    pub rpc_rate_limit: Option<u32>,
+    /// Maximum number of concurrent RPC requests.
+    pub rpc_concurrency: Option<usize>,
+    /// Ingest finalized blocks in chunks of this many blocks.
+    ///
+    /// Each chunk is ingested by an independent worker and takes a single task slot.
+    pub backfill_chunk_size: Option<u64>,
+    /// Maximum number of block uploads in flight for each backfill chunk.
+    ///
+    /// The chunk's next blocks are ingested while the previous ones are uploaded.
+    pub max_concurrent_uploads: usize,
+    /// Time-to-live of the ingestion lock.
+    ///
+    /// A standby instance takes over at most this long after the active instance dies.
+    pub lock_ttl: Duration,
+    /// How often a standby instance refreshes its cache.
+    pub standby_warm_interval: Duration,
+    /// Consider blocks this deep below the head finalized, ignoring the chain's finalized block.
+    ///
+    /// Compaction and the stream read the finalized block written by ingestion,
+    /// so they follow the same rule.
+    pub finality_depth: Option<u64>,
+    /// Stop ingestion after this block is ingested and finalized.
+    pub stop_block: Option<u64>,
 }
 
 pub struct IngestionService<I>
@@ -86,6 +166,11 @@ where
     ingestion: IngestionInner<I>,
     state_client: IngestionStateClient,
     chain_store: ChainStore,
+    manifest_store: DatasetManifestStore,
+    chain_id: Option<String>,
+    head_subscription: Option<HeadSubscription>,
+    /// When the head subscription last notified a head change.
+    last_head_change: Instant,
     chain_builder: CanonicalChainBuilder,
     task_queue: FuturesOrdered<IngestionTaskHandle>,
     metrics: IngestionMetrics,
@@ -102,6 +187,8 @@ where
 {
     block_store: BlockStoreWriter,
     ingestion: Arc<I>,
+    finality_depth: Option<u64>,
+    max_concurrent_uploads: usize,
     metrics: IngestionMetrics,
 }
 
@@ -136,6 +223,16 @@ pub struct RecoverState {
     pub last_ingested: Cursor,
 }
 
+/// Which event woke up an ingestion tick.
+enum IngestTick {
+    Cancelled,
+    RefreshFinalized,
+    RefreshPending,
+    HeadChanged(Option<Result<(), IngestionError>>),
+    RefreshHead,
+    TaskFinished(Option<IngestionJobJoinResult>),
+}
+
 /// What action to take when starting ingestion.
 enum IngestionStartAction {
     /// Resume ingestion from the given cursor (cursor already ingested).
@@ -159,27 +256,40 @@ where
         metrics: IngestionMetrics,
     ) -> Self {
         let chain_store = ChainStore::new(object_store.clone(), file_cache);
+        let manifest_store = DatasetManifestStore::new(object_store.clone());
         let block_store = BlockStoreWriter::new(object_store);
         let state_client = IngestionStateClient::new(&etcd_client);
+        let ingestion = IngestionInner {
+            ingestion: ingestion.into(),
+            block_store,
+            finality_depth: options.finality_depth,
+            max_concurrent_uploads: options.max_concurrent_uploads.max(1),
+            metrics: metrics.clone(),
+        };
 
         Self {
             options,
-            ingestion: IngestionInner {
-                ingestion: ingestion.into(),
-                block_store,
-                metrics: metrics.clone(),
-            },
+            ingestion,
             state_client,
             chain_store,
+            manifest_store,
+            chain_id: None,
+            head_subscription: None,
+            last_head_change: Instant::now(),
             chain_builder: CanonicalChainBuilder::new(),
             task_queue: FuturesOrdered::new(),
             metrics,
         }
     }
 
+    /// Run ingestion until cancelled.
+    ///
+    /// While `paused` is `true`, ingestion stops scheduling new work and only keeps
+    /// the lock alive.
     pub async fn start(
         mut self,
         lock: &mut Lock,
+        mut paused: watch::Receiver<bool>,
         ct: CancellationToken,
     ) -> Result<(), IngestionError> {
         let mut state = self.initialize().await?;
@@ -189,6 +299,14 @@ where
                 return Ok(());
             }
 
+            if let Some(state) = state.as_ingest() {
+                if self.is_stop_block_reached(state) {
+                    self.mark_dataset_complete(state).await?;
+                    info!(cursor = %state.last_ingested, "ingestion reached stop block");
+                    return Ok(());
+                }
+            }
+
             let tick_span = tracing::info_span!(
                 "ingestion_tick",
                 state_name = state.state_name(),
@@ -202,6 +320,11 @@ where
                 .await
                 .change_context(IngestionError::LockKeepAlive)?;
 
+            if *paused.borrow_and_update() {
+                wait_until_resumed(lock, &mut paused, &ct).await?;
+                continue;
+            }
+
             state = async {
                 match state {
                     IngestionState::Ingest(inner_state) => {
@@ -228,6 +351,9 @@ where
     pub async fn initialize(&mut self) -> Result<IngestionState, IngestionError> {
         let head = self.ingestion.get_head_cursor().await?;
         let finalized = self.ingestion.get_finalized_cursor().await?;
+        self.chain_id = self.ingestion.get_chain_id().await?;
+
+        self.subscribe_heads().await;
 
         let current_span = tracing::Span::current();
 
@@ -270,6 +396,9 @@ where
 
                 info!(cursor = %starting_cursor, "uploaded genesis block");
 
+                self.update_dataset_manifest(&finalized, &starting_cursor)
+                    .await?;
+
                 Ok(IngestionState::Ingest(IngestState {
                     queued_block_number: starting_cursor.number,
                     finalized,
@@ -290,6 +419,9 @@ where
             IngestionStartAction::Resume(starting_cursor) => {
                 current_span.record("starting_block", starting_cursor.number);
 
+                self.update_dataset_manifest(&finalized, &starting_cursor)
+                    .await?;
+
                 Ok(IngestionState::Ingest(IngestState {
                     queued_block_number: starting_cursor.number,
                     finalized,
@@ -324,30 +456,86 @@ where
         current_span.record("finalized", state.finalized.number);
         current_span.record("task_queue_size", self.task_queue.len());
 
-        tokio::select! {
+        // Poll the subscription outside of the service so that the task queue can borrow it.
+        let mut head_subscription = self.head_subscription.take();
+
+        let tick = tokio::select! {
             biased;
 
-            _ = ct.cancelled() => Ok(IngestionState::Ingest(state)),
+            _ = ct.cancelled() => IngestTick::Cancelled,
+
+            _ = state.finalized_refresh_interval.tick() => IngestTick::RefreshFinalized,
+
+            _ = state.pending_refresh_interval.tick(), if state.head == state.last_ingested && self.ingestion.supports_pending() => {
+                IngestTick::RefreshPending
+            }
+
+            head_changed = next_head_change(&mut head_subscription), if head_subscription.is_some() => {
+                IngestTick::HeadChanged(head_changed)
+            }
+
+            // Keep polling while subscribed, in case the subscription stalls without closing.
+            _ = state.head_refresh_interval.tick() => IngestTick::RefreshHead,
+
+            join_result = self.task_queue_next(), if !self.task_queue_is_empty() => {
+                IngestTick::TaskFinished(join_result)
+            }
+        };
 
-            _ = state.finalized_refresh_interval.tick() => {
+        self.head_subscription = head_subscription;
+
+        match tick {
+            IngestTick::Cancelled => Ok(IngestionState::Ingest(state)),
+            IngestTick::RefreshFinalized => {
                 current_span.record("action", "refresh_finalized");
 
                 self.tick_refresh_finalized(state).await
             }
-
-            _ = state.pending_refresh_interval.tick(), if state.head == state.last_ingested && self.ingestion.supports_pending() => {
+            IngestTick::RefreshPending => {
                 current_span.record("action", "refresh_pending");
 
                 self.tick_refresh_pending(state).await
             }
+            IngestTick::HeadChanged(head_changed) => {
+                current_span.record("action", "head_changed");
 
-            _ = state.head_refresh_interval.tick() => {
-                current_span.record("action", "refresh_head");
+                match head_changed {
+                    Some(Ok(())) => {
+                        self.last_head_change = Instant::now();
+                    }
+                    Some(Err(err)) => {
+                        warn!(error = ?err, "head subscription error. polling for head changes");
+                        self.head_subscription = None;
+                    }
+                    None => {
+                        warn!("head subscription closed. polling for head changes");
+                        self.head_subscription = None;
+                    }
+                }
 
                 self.tick_refresh_head(state).await
             }
+            IngestTick::RefreshHead => {
+                if self.head_subscription.is_some() {
+                    let since_head_change = self.last_head_change.elapsed();
+                    if since_head_change < self.options.head_refresh_interval {
+                        return Ok(IngestionState::Ingest(state));
+                    }
 
-            join_result = self.task_queue_next(), if !self.task_queue_is_empty() => {
+                    if since_head_change > HEAD_SUBSCRIPTION_STALE_TIMEOUT {
+                        warn!(
+                            since_head_change = ?since_head_change,
+                            "head subscription is stale. resubscribing"
+                        );
+                        self.subscribe_heads().await;
+                    }
+                }
+
+                current_span.record("action", "refresh_head");
+
+                self.tick_refresh_head(state).await
+            }
+            IngestTick::TaskFinished(join_result) => {
                 current_span.record("action", "finish_ingestion");
 
                 self.tick_with_task_result(state, join_result).await
@@ -366,6 +554,11 @@ where
             .change_context(IngestionError::RpcRequest)
             .attach_printable("failed to refresh finalized cursor")?;
 
+        // The head can move backwards after a reorg, and the finalized block with it.
+        if self.options.finality_depth.is_some() && state.finalized.number > finalized.number {
+            return Ok(IngestionState::Ingest(state));
+        }
+
         if state.finalized.number > finalized.number {
             return Err(IngestionError::Model)
                 .attach_printable("the new finalized cursor is behind the old one")
@@ -383,6 +576,9 @@ where
             .await
             .change_context(IngestionError::StateClientRequest)?;
 
+        self.update_dataset_manifest(&finalized, &state.last_ingested)
+            .await?;
+
         Ok(IngestionState::Ingest(IngestState { finalized, ..state }))
     }
 
@@ -426,16 +622,8 @@ where
 
         info!(cursor = %head, "refreshed head cursor");
 
-        let mut block_number = state.queued_block_number;
-        while self.can_push_task() {
-            if block_number + 1 > head.number {
-                break;
-            }
-
-            block_number += 1;
-            trace!(block_number, "pushing finalized ingestion task");
-            self.push_ingest_block_by_number(block_number);
-        }
+        let block_number =
+            self.push_ingestion_tasks(state.queued_block_number, &head, &state.finalized);
 
         Ok(IngestionState::Ingest(IngestState {
             head,
@@ -495,8 +683,9 @@ where
                 }
             };
 
-            let block_info = match block_info {
-                IngestionTask::Main(block_info) => block_info,
+            let blocks = match block_info {
+                IngestionTask::Main(block_info) => vec![block_info],
+                IngestionTask::Backfill(blocks) => blocks,
                 IngestionTask::Pending(None) => {
                     let new_pending_block_state = PendingBlockState {
                         queued: false,
@@ -532,39 +721,51 @@ where
                 }
             };
 
-            info!(block = %block_info.cursor(), "ingested block");
+            let mut should_upload_recent_segment = false;
 
-            // Always upload recent segment if the block is non-finalized.
-            let mut should_upload_recent_segment = block_info.number >= state.finalized.number;
+            for block_info in blocks {
+                info!(block = %block_info.cursor(), "ingested block");
 
-            if !self.chain_builder.can_grow(&block_info) {
-                return Ok(IngestionState::Recover(RecoverState {
-                    finalized: state.finalized,
-                    existing_head: state.head,
-                    last_ingested: state.last_ingested,
-                }));
-            }
+                // Always upload recent segment if the block is non-finalized.
+                should_upload_recent_segment |= block_info.number >= state.finalized.number;
+                // Upload the last block before stopping.
+                should_upload_recent_segment |= self
+                    .options
+                    .stop_block
+                    .is_some_and(|stop_block| block_info.number >= stop_block);
 
-            last_ingested = block_info.cursor();
+                if !self.chain_builder.can_grow(&block_info) {
+                    return Ok(IngestionState::Recover(RecoverState {
+                        finalized: state.finalized,
+                        existing_head: state.head,
+                        last_ingested: state.last_ingested,
+                    }));
+                }
 
-            self.chain_builder
-                .grow(block_info)
-                .change_context(IngestionError::Model)?;
+                last_ingested = block_info.cursor();
 
-            if self.chain_builder.segment_size()
-                == self.options.chain_segment_size + self.options.chain_segment_upload_offset_size
-            {
-                let segment = self
-                    .chain_builder
-                    .take_segment(self.options.chain_segment_size)
+                self.chain_builder
+                    .grow(block_info)
                     .change_context(IngestionError::Model)?;
-                info!(first_block = %segment.info.first_block, "uploading chain segment");
-                self.chain_store
-                    .put(&segment)
-                    .await
-                    .change_context(IngestionError::CanonicalChainStoreRequest)?;
 
-                should_upload_recent_segment = true;
+                // After recovering from a deep reorg the builder contains more blocks
+                // than usual, so it may need to upload more than one segment.
+                while self.chain_builder.segment_size()
+                    >= self.options.chain_segment_size
+                        + self.options.chain_segment_upload_offset_size
+                {
+                    let segment = self
+                        .chain_builder
+                        .take_segment(self.options.chain_segment_size)
+                        .change_context(IngestionError::Model)?;
+                    info!(first_block = %segment.info.first_block, "uploading chain segment");
+                    self.chain_store
+                        .put(&segment)
+                        .await
+                        .change_context(IngestionError::CanonicalChainStoreRequest)?;
+
+                    should_upload_recent_segment = true;
+                }
             }
 
             if should_upload_recent_segment {
@@ -585,17 +786,8 @@ where
             }
         }
 
-        let mut block_number = state.queued_block_number;
-
-        while self.can_push_task() {
-            if block_number + 1 > state.head.number {
-                break;
-            }
-
-            block_number += 1;
-            trace!(block_number, "pushing finalized ingestion task");
-            self.push_ingest_block_by_number(block_number);
-        }
+        let block_number =
+            self.push_ingestion_tasks(state.queued_block_number, &state.head, &state.finalized);
 
         Ok(IngestionState::Ingest(IngestState {
             last_ingested,
@@ -615,7 +807,7 @@ where
 
         warn!(last_ingested = %state.last_ingested, "recovering from a chain reorganization");
 
-        let canonical_chain = self
+        let mut canonical_chain = self
             .chain_builder
             .current_segment()
             .change_context(IngestionError::Model)?;
@@ -668,6 +860,11 @@ where
                     .attach_printable("hint: are you connecting to the correct chain?");
             }
 
+            // The reorg is deeper than the current segment.
+            if new_head_candidate.number == canonical_chain.info.first_block.number {
+                canonical_chain = self.restore_previous_chain_segment().await?;
+            }
+
             new_head_candidate = canonical_chain
                 .canonical(new_head_candidate.number - 1)
                 .change_context(IngestionError::Model)
@@ -700,6 +897,41 @@ where
         }))
     }
 
+    /// Add the previous chain segment back to the canonical chain builder.
+    ///
+    /// Returns the new current segment.
+    async fn restore_previous_chain_segment(
+        &mut self,
+    ) -> Result<CanonicalChainSegment, IngestionError> {
+        let Some(previous_segment) = self.chain_builder.previous_segment().cloned() else {
+            return Err(IngestionError::Model)
+                .attach_printable("reorg is deeper than the first chain segment");
+        };
+
+        warn!(
+            first_block = %previous_segment.first_block,
+            last_block = %previous_segment.last_block,
+            "reorg crosses chain segment boundary. restoring previous segment"
+        );
+
+        let segment = self
+            .chain_store
+            .get(previous_segment.first_block.number)
+            .await
+            .change_context(IngestionError::CanonicalChainStoreRequest)?
+            .ok_or(IngestionError::CanonicalChainStoreRequest)
+            .attach_printable("previous chain segment not found")
+            .attach_printable_lazy(|| format!("first block: {}", previous_segment.first_block))?;
+
+        self.chain_builder
+            .prepend_segment(segment)
+            .change_context(IngestionError::Model)?;
+
+        self.chain_builder
+            .current_segment()
+            .change_context(IngestionError::Model)
+    }
+
     pub fn task_queue_clear(&mut self) {
         self.task_queue = FuturesOrdered::new();
     }
@@ -708,6 +940,27 @@ where
         self.task_queue.len()
     }
 
+    /// Subscribe to head changes, replacing the current subscription.
+    ///
+    /// Without a subscription the service polls for head changes.
+    async fn subscribe_heads(&mut self) {
+        self.head_subscription = match self.ingestion.subscribe_heads().await {
+            Ok(Some(subscription)) => {
+                info!("subscribed to head changes");
+                Some(subscription)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                warn!(
+                    error = ?err,
+                    "failed to subscribe to head changes. polling for head changes"
+                );
+                None
+            }
+        };
+        self.last_head_change = Instant::now();
+    }
+
     pub fn task_queue_is_empty(&self) -> bool {
         self.task_queue.is_empty()
     }
@@ -720,6 +973,61 @@ where
         self.task_queue.len() < self.options.max_concurrent_tasks
     }
 
+    /// Push ingestion tasks for the blocks after `block_number`, up to `head`.
+    ///
+    /// Finalized blocks are ingested in chunks if backfill is enabled.
+    /// Returns the last queued block number.
+    fn push_ingestion_tasks(
+        &mut self,
+        mut block_number: u64,
+        head: &Cursor,
+        finalized: &Cursor,
+    ) -> u64 {
+        let last_block = match self.options.stop_block {
+            Some(stop_block) => head.number.min(stop_block),
+            None => head.number,
+        };
+
+        while self.can_push_task() {
+            if block_number + 1 > last_block {
+                break;
+            }
+
+            if let Some(chunk_size) = self.options.backfill_chunk_size {
+                if chunk_size > 1 && block_number + 1 < finalized.number {
+                    let first_block = block_number + 1;
+                    let chunk_last_block = (block_number + chunk_size)
+                        .min(finalized.number)
+                        .min(last_block);
+                    trace!(
+                        first_block,
+                        last_block = chunk_last_block,
+                        "pushing backfill ingestion task"
+                    );
+                    self.push_ingest_block_range(first_block, chunk_last_block);
+                    block_number = chunk_last_block;
+                    continue;
+                }
+            }
+
+            block_number += 1;
+            trace!(block_number, "pushing finalized ingestion task");
+            self.push_ingest_block_by_number(block_number);
+        }
+
+        block_number
+    }
+
+    pub fn push_ingest_block_range(&mut self, first_block: u64, last_block: u64) {
+        let ingestion = self.ingestion.clone();
+        self.task_queue.push_back(tokio::spawn(async move {
+            let blocks = ingestion
+                .ingest_block_range(first_block, last_block)
+                .await?;
+            Ok(IngestionTask::Backfill(blocks))
+        }));
+    }
+
     pub fn push_ingest_block_by_number(&mut self, block_number: u64) {
         let ingestion = self.ingestion.clone();
         self.task_queue.push_back(tokio::spawn(async move {
@@ -738,6 +1046,56 @@ where
         }));
     }
 
+    /// Update the dataset manifest with the current ingestion state.
+    async fn update_dataset_manifest(
+        &mut self,
+        finalized: &Cursor,
+        last_ingested: &Cursor,
+    ) -> Result<(), IngestionError> {
+        let starting_block = self
+            .state_client
+            .get_starting_block()
+            .await
+            .change_context(IngestionError::StateClientRequest)?;
+
+        self.manifest_store
+            .update(|manifest| {
+                manifest.chain_id = self.chain_id.clone();
+                manifest.starting_block = starting_block;
+                manifest.ingested = Some(last_ingested.number);
+                manifest.finalized = Some(finalized.number);
+                manifest.complete = false;
+            })
+            .await
+            .change_context(IngestionError::DatasetManifest)?;
+
+        Ok(())
+    }
+
+    /// Returns true if the stop block has been ingested and finalized.
+    fn is_stop_block_reached(&self, state: &IngestState) -> bool {
+        let Some(stop_block) = self.options.stop_block else {
+            return false;
+        };
+
+        state.last_ingested.number >= stop_block && state.finalized.number >= stop_block
+    }
+
+    /// Mark the dataset as complete, since it won't grow past the stop block.
+    async fn mark_dataset_complete(&mut self, state: &IngestState) -> Result<(), IngestionError> {
+        self.manifest_store
+            .update(|manifest| {
+                manifest.chain_id = self.chain_id.clone();
+                manifest.ingested = Some(state.last_ingested.number);
+                manifest.finalized = Some(state.finalized.number);
+                manifest.complete = true;
+            })
+            .await
+            .change_context(IngestionError::DatasetManifest)?;
+
+        Ok(())
+    }
+
     pub fn current_chain_segment(&self) -> Option<CanonicalChainSegment> {
         self.chain_builder.current_segment().ok()
     }
@@ -791,6 +1149,46 @@ where
     }
 }
 
+async fn next_head_change(
+    subscription: &mut Option<HeadSubscription>,
+) -> Option<Result<(), IngestionError>> {
+    match subscription {
+        Some(subscription) => subscription.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait until ingestion is resumed or cancelled, keeping the lock alive.
+async fn wait_until_resumed(
+    lock: &mut Lock,
+    paused: &mut watch::Receiver<bool>,
+    ct: &CancellationToken,
+) -> Result<(), IngestionError> {
+    info!("ingestion paused");
+
+    let mut keep_alive = tokio::time::interval(PAUSED_KEEP_ALIVE_INTERVAL);
+
+    while *paused.borrow_and_update() {
+        tokio::select! {
+            _ = ct.cancelled() => return Ok(()),
+            changed = paused.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = keep_alive.tick() => {
+                lock.keep_alive()
+                    .await
+                    .change_context(IngestionError::LockKeepAlive)?;
+            }
+        }
+    }
+
+    info!("ingestion resumed");
+
+    Ok(())
+}
+
 impl<I> IngestionInner<I>
 where
     I: BlockIngestion + Send + Sync + 'static,
@@ -801,34 +1199,77 @@ where
 
     #[tracing::instrument("ingestion_ingest_block", skip(self), err(Debug))]
     async fn ingest_block_by_number(&self, block_number: u64) -> Result<BlockInfo, IngestionError> {
+        let (block_info, block) = self.fetch_block_with_parent(block_number, None).await?;
+        self.upload_block(&block_info, &block).await?;
+        Ok(block_info)
+    }
+
+    /// Ingest a block, checking that its parent is `expected_parent` if set.
+    ///
+    /// Blocks that fail validation are quarantined and ingested again.
+    async fn fetch_block_with_parent(
+        &self,
+        block_number: u64,
+        expected_parent: Option<&Hash>,
+    ) -> Result<(BlockInfo, Block), IngestionError> {
         let ingestion = self.ingestion.clone();
-        let store = self.block_store.clone();
-        let rpc_metrics = self.metrics.rpc.clone();
 
-        let (block_info, block) = ingestion
-            .ingest_block_by_number(block_number)
-            .record_request_with_attributes(
-                rpc_metrics,
-                &[KeyValue::new("method", "ingest_block_by_number")],
-            )
-            .await
-            .attach_printable_lazy(|| format!("block number: {}", block_number))?;
+        let mut attempt = 0;
+        let (block_info, block) = loop {
+            attempt += 1;
+
+            let result = ingestion
+                .ingest_block_by_number(block_number)
+                .record_request_with_attributes(
+                    self.metrics.rpc.clone(),
+                    &[KeyValue::new("method", "ingest_block_by_number")],
+                )
+                .await;
+
+            let err = match result {
+                Ok((block_info, block)) => {
+                    match validate_block(&block_info, &block, expected_parent) {
+                        Ok(_) => break (block_info, block),
+                        Err(err) => {
+                            self.quarantine_block(&block_info, &block).await;
+                            err
+                        }
+                    }
+                }
+                Err(err) if err.is_invalid_block() => err,
+                Err(err) => {
+                    return Err(err)
+                        .attach_printable_lazy(|| format!("block number: {}", block_number))
+                }
+            };
 
-        if block.index.len() != block.body.len() {
-            return Err(IngestionError::Model)
-                .attach_printable("block indexes and body fragments do not match")
-                .attach_printable_lazy(|| format!("block number: {}", block_number))
-                .attach_printable_lazy(|| format!("indexes len: {}", block.index.len()))
-                .attach_printable_lazy(|| format!("body len: {}", block.body.len()));
-        }
+            self.metrics.invalid_block.add(1, &[]);
+
+            if attempt > MAX_INVALID_BLOCK_RETRIES {
+                return Err(err)
+                    .attach_printable("block failed validation too many times")
+                    .attach_printable_lazy(|| format!("block number: {}", block_number));
+            }
+
+            warn!(block_number, attempt, error = ?err, "block failed validation. retrying");
+        };
 
+        Ok((block_info, block))
+    }
+
+    async fn upload_block(
+        &self,
+        block_info: &BlockInfo,
+        block: &Block,
+    ) -> Result<(), IngestionError> {
         let block_cursor = block_info.cursor();
         debug!(cursor = %block_cursor, "uploading block");
 
         let block_upload_metrics = self.metrics.block_upload.clone();
 
-        let (size, _etag) = store
-            .put_block(&block_cursor, &block)
+        let (size, _etag) = self
+            .block_store
+            .put_block(&block_cursor, block)
             .record_request_with_attributes(
                 block_upload_metrics,
                 &[KeyValue::new("method", "ingest_block_by_number")],
@@ -840,7 +1281,58 @@ where
             .block_size
             .record(size as u64, &[KeyValue::new("type", "produced")]);
 
-        Ok(block_info)
+        Ok(())
+    }
+
+    /// Ingest the blocks in the range, inclusive.
+    ///
+    /// Blocks are uploaded in the background while the next blocks are ingested, with at
+    /// most `max_concurrent_uploads` uploads in flight.
+    #[tracing::instrument("ingestion_ingest_block_range", skip(self), err(Debug))]
+    async fn ingest_block_range(
+        &self,
+        first_block: u64,
+        last_block: u64,
+    ) -> Result<Vec<BlockInfo>, IngestionError> {
+        let mut blocks = Vec::with_capacity((last_block + 1 - first_block) as usize);
+        let mut uploads = JoinSet::new();
+
+        let mut parent: Option<Hash> = None;
+        for block_number in first_block..=last_block {
+            let (block_info, block) = self
+                .fetch_block_with_parent(block_number, parent.as_ref())
+                .await?;
+            parent = Some(block_info.hash.clone());
+
+            if uploads.len() >= self.max_concurrent_uploads {
+                if let Some(result) = uploads.join_next().await {
+                    result.change_context(IngestionError::BlockStoreRequest)??;
+                }
+            }
+
+            uploads.spawn({
+                let inner = self.clone();
+                let block_info = block_info.clone();
+                async move { inner.upload_block(&block_info, &block).await }
+            });
+
+            blocks.push(block_info);
+        }
+
+        while let Some(result) = uploads.join_next().await {
+            result.change_context(IngestionError::BlockStoreRequest)??;
+        }
+
+        Ok(blocks)
+    }
+
+    async fn quarantine_block(&self, block_info: &BlockInfo, block: &Block) {
+        let cursor = block_info.cursor();
+        warn!(cursor = %cursor, "quarantining invalid block");
+
+        if let Err(err) = self.block_store.put_quarantined_block(&cursor, block).await {
+            warn!(error = ?err, "failed to upload quarantined block");
+        }
     }
 
     #[tracing::instrument("ingestion_ingest_pending_block", skip(self), err(Debug))]
@@ -866,13 +1358,9 @@ where
             return Ok(None);
         };
 
-        if block.index.len() != block.body.len() {
-            return Err(IngestionError::Model)
-                .attach_printable("block indexes and body fragments do not match (pending block)")
-                .attach_printable_lazy(|| format!("block number: {}", block_number))
-                .attach_printable_lazy(|| format!("indexes len: {}", block.index.len()))
-                .attach_printable_lazy(|| format!("body len: {}", block.body.len()));
-        }
+        validate_fragments(&block)
+            .attach_printable("invalid pending block")
+            .attach_printable_lazy(|| format!("block number: {}", block_number))?;
 
         debug!(cursor = ?block_info, "uploading pending block");
 
@@ -899,7 +1387,25 @@ where
     }
 
     async fn get_finalized_cursor(&self) -> Result<Cursor, IngestionError> {
-        self.ingestion.get_finalized_cursor().await
+        let Some(finality_depth) = self.finality_depth else {
+            return self.ingestion.get_finalized_cursor().await;
+        };
+
+        let head = self.get_head_cursor().await?;
+        let block_info = self
+            .get_block_info_by_number(head.number.saturating_sub(finality_depth))
+            .await
+            .attach_printable("failed to get finalized block from finality depth")?;
+
+        Ok(block_info.cursor())
+    }
+
+    async fn subscribe_heads(&self) -> Result<Option<HeadSubscription>, IngestionError> {
+        self.ingestion.subscribe_heads().await
+    }
+
+    async fn get_chain_id(&self) -> Result<Option<String>, IngestionError> {
+        self.ingestion.get_chain_id().await
     }
 
     async fn get_block_info_by_number(
@@ -920,6 +1426,14 @@ impl Default for IngestionServiceOptions {
             pending_refresh_interval: Duration::from_secs(3),
             head_refresh_interval: Duration::from_secs(3),
             finalized_refresh_interval: Duration::from_secs(30),
+            rpc_rate_limit: None,
+            rpc_concurrency: None,
+            backfill_chunk_size: None,
+            max_concurrent_uploads: 8,
+            lock_ttl: Duration::from_secs(60),
+            standby_warm_interval: Duration::from_secs(10),
+            finality_depth: None,
+            stop_block: None,
         }
     }
 }
@@ -984,3 +1498,154 @@ impl IngestionState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use error_stack::{Report, Result};
+    use futures::FutureExt;
+
+    use crate::{
+        block_store::BlockStoreWriter,
+        chain::BlockInfo,
+        fragment::{Block, HeaderFragment, IndexGroupFragment, JoinGroupFragment},
+        new_test_cursor,
+        object_store::{ObjectStore, ObjectStoreError},
+        Cursor,
+    };
+
+    use super::{BlockIngestion, IngestionError, IngestionInner, IngestionMetrics};
+
+    #[derive(Clone)]
+    struct TestBlockIngestion;
+
+    impl BlockIngestion for TestBlockIngestion {
+        async fn get_head_cursor(&self) -> Result<Cursor, IngestionError> {
+            Ok(new_test_cursor(100, 0))
+        }
+
+        async fn get_finalized_cursor(&self) -> Result<Cursor, IngestionError> {
+            Ok(new_test_cursor(100, 0))
+        }
+
+        async fn get_block_info_by_number(
+            &self,
+            block_number: u64,
+        ) -> Result<BlockInfo, IngestionError> {
+            let cursor = new_test_cursor(block_number, 0);
+            let parent = new_test_cursor(block_number.saturating_sub(1), 0);
+
+            Ok(BlockInfo {
+                number: block_number,
+                hash: cursor.hash,
+                parent: parent.hash,
+            })
+        }
+
+        async fn ingest_block_by_number(
+            &self,
+            block_number: u64,
+        ) -> Result<(BlockInfo, Block), IngestionError> {
+            let block_info = self.get_block_info_by_number(block_number).await?;
+            let block = Block {
+                header: HeaderFragment {
+                    data: vec![block_number as u8],
+                },
+                index: IndexGroupFragment {
+                    indexes: Vec::default(),
+                },
+                join: JoinGroupFragment {
+                    joins: Vec::default(),
+                },
+                body: Vec::default(),
+            };
+
+            Ok((block_info, block))
+        }
+    }
+
+    /// Track the uploads to the object store.
+    #[derive(Default)]
+    struct Uploads {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        completed: AtomicUsize,
+    }
+
+    /// Returns an ingestion with a slow object store, where uploads of the block
+    /// `failing_block` fail.
+    fn new_ingestion(
+        max_concurrent_uploads: usize,
+        failing_block: Option<u64>,
+    ) -> (IngestionInner<TestBlockIngestion>, Arc<Uploads>) {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let uploads = Arc::new(Uploads::default());
+
+        object_store.set_put_hook(Arc::new({
+            let uploads = uploads.clone();
+            move |key: &str| {
+                let uploads = uploads.clone();
+                let fails = failing_block
+                    .is_some_and(|block_number| key.contains(&format!("/{block_number:0>10}/")));
+                async move {
+                    let in_flight = uploads.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    uploads.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    uploads.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    if fails {
+                        return Err(Report::new(ObjectStoreError::Request));
+                    }
+
+                    uploads.completed.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+                .boxed()
+            }
+        }));
+
+        let ingestion = IngestionInner {
+            block_store: BlockStoreWriter::new(object_store),
+            ingestion: Arc::new(TestBlockIngestion),
+            finality_depth: None,
+            max_concurrent_uploads,
+            metrics: IngestionMetrics::default(),
+        };
+
+        (ingestion, uploads)
+    }
+
+    #[tokio::test]
+    async fn test_ingest_block_range_uploads_concurrently() {
+        let (ingestion, uploads) = new_ingestion(3, None);
+
+        let blocks = ingestion.ingest_block_range(1, 10).await.unwrap();
+        assert_eq!(blocks.len(), 10);
+
+        // The chunk completes only after all its uploads completed.
+        assert_eq!(uploads.completed.load(Ordering::SeqCst), 10);
+        assert_eq!(uploads.in_flight.load(Ordering::SeqCst), 0);
+
+        let max_in_flight = uploads.max_in_flight.load(Ordering::SeqCst);
+        assert!(max_in_flight > 1);
+        assert!(max_in_flight <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_block_range_fails_if_upload_fails() {
+        // The last upload fails after all the blocks are ingested.
+        let (ingestion, _uploads) = new_ingestion(3, Some(10));
+        assert!(ingestion.ingest_block_range(1, 10).await.is_err());
+
+        // An upload fails while the next blocks are ingested.
+        let (ingestion, _uploads) = new_ingestion(3, Some(2));
+        assert!(ingestion.ingest_block_range(1, 10).await.is_err());
+    }
+}
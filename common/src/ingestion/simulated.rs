@@ -0,0 +1,338 @@
+//! A simulated chain for end-to-end tests.
+//!
+//! The chain follows the model in `spec/reorg_detection/chain.qnt`: the head grows one
+//! block at a time, the finalized block only moves forward, and reorgs shrink the chain
+//! to a block after the finalized block. Block hashes are generated from a counter, so
+//! the same sequence of events always produces the same chain.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use error_stack::{Result, ResultExt};
+use futures::StreamExt;
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::{
+    chain::BlockInfo,
+    fragment::{Block, HeaderFragment, IndexGroupFragment, JoinGroupFragment},
+    Cursor, Hash,
+};
+
+use super::{BlockIngestion, HeadSubscription, IngestionError};
+
+/// A change to the simulated chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// Add the given number of blocks to the chain.
+    AdvanceHead(u64),
+    /// Move the finalized block forward, up to the head.
+    AdvanceFinalized(u64),
+    /// Remove all blocks after `new_head`.
+    ///
+    /// The new head must be after the finalized block. Blocks added after the reorg
+    /// have new hashes.
+    Reorg { new_head: u64 },
+    /// The next `times` requests for the block fail with `BlockNotFound`.
+    MissBlock { number: u64, times: usize },
+}
+
+/// A chain whose blocks are generated deterministically.
+///
+/// Clones share the same chain, so tests can keep a handle to change the chain while
+/// ingestion runs.
+#[derive(Clone)]
+pub struct SimulatedChain {
+    inner: Arc<Mutex<SimulatedChainInner>>,
+    head_tx: Arc<watch::Sender<u64>>,
+}
+
+struct SimulatedChainInner {
+    /// Used to generate unique block hashes.
+    block_hash_gen: u64,
+    finalized: u64,
+    /// The canonical chain, indexed by block number.
+    canonical: Vec<BlockInfo>,
+    /// How many more times each block is reported missing.
+    missing: HashMap<u64, usize>,
+    reorg_count: usize,
+}
+
+impl SimulatedChain {
+    /// Returns a chain with only the genesis block.
+    pub fn new() -> Self {
+        let genesis = BlockInfo {
+            number: 0,
+            hash: block_hash(0, 0),
+            parent: Hash(vec![0; 16]),
+        };
+
+        let inner = SimulatedChainInner {
+            block_hash_gen: 0,
+            finalized: 0,
+            canonical: vec![genesis],
+            missing: HashMap::default(),
+            reorg_count: 0,
+        };
+
+        let (head_tx, _) = watch::channel(0);
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            head_tx: Arc::new(head_tx),
+        }
+    }
+
+    /// Returns a chain after applying all `events` to a new chain.
+    pub fn from_events(events: impl IntoIterator<Item = ChainEvent>) -> Self {
+        let chain = Self::new();
+        chain.apply_all(events);
+        chain
+    }
+
+    /// Apply the event to the chain.
+    ///
+    /// Panics if the event is not valid for the current chain.
+    pub fn apply(&self, event: ChainEvent) {
+        let mut inner = self.inner.lock().expect("simulated chain lock");
+
+        match event {
+            ChainEvent::AdvanceHead(count) => {
+                for _ in 0..count {
+                    inner.advance_head();
+                }
+            }
+            ChainEvent::AdvanceFinalized(jump) => {
+                let head = inner.head().number;
+                inner.finalized = (inner.finalized + jump).min(head);
+            }
+            ChainEvent::Reorg { new_head } => {
+                let head = inner.head().number;
+                assert!(
+                    new_head > inner.finalized && new_head <= head,
+                    "reorg must be after the finalized block and not after the head"
+                );
+                inner.canonical.truncate(new_head as usize + 1);
+                inner.reorg_count += 1;
+            }
+            ChainEvent::MissBlock { number, times } => {
+                if times > 0 {
+                    inner.missing.insert(number, times);
+                }
+            }
+        }
+
+        let head = inner.head().number;
+        self.head_tx.send_modify(|value| *value = head);
+    }
+
+    /// Apply all events, in order.
+    pub fn apply_all(&self, events: impl IntoIterator<Item = ChainEvent>) {
+        for event in events {
+            self.apply(event);
+        }
+    }
+
+    pub fn head(&self) -> BlockInfo {
+        let inner = self.inner.lock().expect("simulated chain lock");
+        inner.head().clone()
+    }
+
+    pub fn finalized(&self) -> BlockInfo {
+        let inner = self.inner.lock().expect("simulated chain lock");
+        inner.canonical[inner.finalized as usize].clone()
+    }
+
+    /// Returns the canonical block with the given number, if any.
+    pub fn block(&self, number: u64) -> Option<BlockInfo> {
+        let inner = self.inner.lock().expect("simulated chain lock");
+        inner.canonical.get(number as usize).cloned()
+    }
+
+    pub fn reorg_count(&self) -> usize {
+        let inner = self.inner.lock().expect("simulated chain lock");
+        inner.reorg_count
+    }
+
+    fn get_block_info(&self, number: u64) -> Result<BlockInfo, IngestionError> {
+        let mut inner = self.inner.lock().expect("simulated chain lock");
+
+        if let Some(times) = inner.missing.get_mut(&number) {
+            *times -= 1;
+            if *times == 0 {
+                inner.missing.remove(&number);
+            }
+
+            return Err(IngestionError::BlockNotFound)
+                .attach_printable("simulated missed block")
+                .attach_printable_lazy(|| format!("block number: {number}"));
+        }
+
+        inner
+            .canonical
+            .get(number as usize)
+            .cloned()
+            .ok_or(IngestionError::BlockNotFound)
+            .attach_printable("block after the head")
+            .attach_printable_lazy(|| format!("block number: {number}"))
+    }
+}
+
+impl Default for SimulatedChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedChainInner {
+    fn head(&self) -> &BlockInfo {
+        self.canonical.last().expect("chain has genesis block")
+    }
+
+    fn advance_head(&mut self) {
+        self.block_hash_gen += 1;
+
+        let parent = self.head();
+        let number = parent.number + 1;
+        let block = BlockInfo {
+            number,
+            hash: block_hash(number, self.block_hash_gen),
+            parent: parent.hash.clone(),
+        };
+
+        self.canonical.push(block);
+    }
+}
+
+impl BlockIngestion for SimulatedChain {
+    async fn get_head_cursor(&self) -> Result<Cursor, IngestionError> {
+        let head = self.head();
+        Ok(Cursor::new(head.number, head.hash))
+    }
+
+    async fn get_finalized_cursor(&self) -> Result<Cursor, IngestionError> {
+        let finalized = self.finalized();
+        Ok(Cursor::new(finalized.number, finalized.hash))
+    }
+
+    async fn subscribe_heads(&self) -> Result<Option<HeadSubscription>, IngestionError> {
+        let stream = WatchStream::new(self.head_tx.subscribe()).map(|_| Ok(()));
+        Ok(Some(stream.boxed()))
+    }
+
+    async fn get_block_info_by_number(&self, number: u64) -> Result<BlockInfo, IngestionError> {
+        self.get_block_info(number)
+    }
+
+    async fn ingest_block_by_number(
+        &self,
+        number: u64,
+    ) -> Result<(BlockInfo, Block), IngestionError> {
+        let info = self.get_block_info(number)?;
+
+        let block = Block {
+            header: HeaderFragment {
+                data: Vec::default(),
+            },
+            index: IndexGroupFragment {
+                indexes: Vec::default(),
+            },
+            join: JoinGroupFragment {
+                joins: Vec::default(),
+            },
+            body: Vec::default(),
+        };
+
+        Ok((info, block))
+    }
+}
+
+/// The hash contains the block number followed by the hash counter.
+fn block_hash(number: u64, block_hash_gen: u64) -> Hash {
+    let mut hash = Vec::with_capacity(16);
+    hash.extend_from_slice(&number.to_be_bytes());
+    hash.extend_from_slice(&block_hash_gen.to_be_bytes());
+    Hash(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ingestion::{BlockIngestion, IngestionErrorExt};
+
+    use super::{ChainEvent, SimulatedChain};
+
+    #[tokio::test]
+    async fn test_simulated_chain() {
+        let chain = SimulatedChain::from_events([
+            ChainEvent::AdvanceHead(10),
+            ChainEvent::AdvanceFinalized(5),
+        ]);
+
+        assert_eq!(chain.head().number, 10);
+        assert_eq!(chain.finalized().number, 5);
+        for number in 1..=10 {
+            let block = chain.block(number).unwrap();
+            let parent = chain.block(number - 1).unwrap();
+            assert_eq!(block.parent, parent.hash);
+        }
+
+        let block_before_reorg = chain.block(8).unwrap();
+        chain.apply_all([
+            ChainEvent::Reorg { new_head: 7 },
+            ChainEvent::AdvanceHead(4),
+        ]);
+
+        assert_eq!(chain.head().number, 11);
+        assert_eq!(chain.reorg_count(), 1);
+        let block_after_reorg = chain.block(8).unwrap();
+        assert_ne!(block_after_reorg.hash, block_before_reorg.hash);
+        assert_eq!(block_after_reorg.parent, chain.block(7).unwrap().hash);
+
+        // The same events produce the same chain.
+        let other = SimulatedChain::from_events([
+            ChainEvent::AdvanceHead(10),
+            ChainEvent::AdvanceFinalized(5),
+            ChainEvent::Reorg { new_head: 7 },
+            ChainEvent::AdvanceHead(4),
+        ]);
+        assert_eq!(other.head(), chain.head());
+
+        // Finality never moves past the head.
+        chain.apply(ChainEvent::AdvanceFinalized(100));
+        assert_eq!(chain.finalized(), chain.head());
+    }
+
+    #[tokio::test]
+    async fn test_simulated_chain_missed_block() {
+        let chain = SimulatedChain::from_events([
+            ChainEvent::AdvanceHead(5),
+            ChainEvent::MissBlock {
+                number: 3,
+                times: 2,
+            },
+        ]);
+
+        for _ in 0..2 {
+            let err = chain.get_block_info_by_number(3).await.unwrap_err();
+            assert!(err.is_block_not_found());
+        }
+
+        let (info, _) = chain.ingest_block_by_number(3).await.unwrap();
+        assert_eq!(info, chain.block(3).unwrap());
+
+        let err = chain.get_block_info_by_number(6).await.unwrap_err();
+        assert!(err.is_block_not_found());
+    }
+
+    #[tokio::test]
+    #[should_panic]
+    async fn test_simulated_chain_reorg_finalized() {
+        let chain = SimulatedChain::from_events([
+            ChainEvent::AdvanceHead(5),
+            ChainEvent::AdvanceFinalized(3),
+        ]);
+
+        chain.apply(ChainEvent::Reorg { new_head: 3 });
+    }
+}
@@ -0,0 +1,54 @@
+//! Keep a standby ingestion instance warm while it waits for the ingestion lock.
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{block_store::BlockStoreReader, chain_store::ChainStore};
+
+/// How many of the most recent blocks to keep in the cache.
+const STANDBY_WARM_BLOCKS: u64 = 16;
+
+/// Periodically download the most recent blocks into the local cache.
+///
+/// This way a standby instance that takes over ingestion, and the DNA server
+/// running next to it, don't start with a cold cache.
+pub async fn standby_warm_cache_loop(
+    chain_store: ChainStore,
+    block_store: BlockStoreReader,
+    interval: Duration,
+    ct: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+
+        let segment = match chain_store.get_recent(None).await {
+            Ok(Some(segment)) => segment,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!(error = ?err, "standby failed to get recent chain segment");
+                continue;
+            }
+        };
+
+        let last_block = segment.info.last_block.number;
+        let first_block = last_block
+            .saturating_sub(STANDBY_WARM_BLOCKS - 1)
+            .max(segment.info.first_block.number);
+
+        debug!(first_block, last_block, "standby warming block cache");
+
+        for block_number in first_block..=last_block {
+            let Ok(cursor) = segment.canonical(block_number) else {
+                break;
+            };
+
+            block_store.prefetch_block(&cursor);
+        }
+    }
+}
@@ -12,14 +12,25 @@ pub static STARTING_BLOCK_KEY: &str = "ingestion/starting_block";
 pub static FINALIZED_KEY: &str = "ingestion/finalized";
 pub static SEGMENTED_KEY: &str = "ingestion/segmented";
 pub static GROUPED_KEY: &str = "ingestion/grouped";
+pub static PRUNED_KEY: &str = "ingestion/pruned";
 
 #[derive(Debug)]
 pub struct IngestionStateClientError;
 
 #[derive(Clone)]
 pub struct IngestionStateClient {
-    kv_client: KvClient,
-    watch_client: WatchClient,
+    backend: StateBackend,
+}
+
+/// Where the ingestion state is stored.
+#[derive(Clone)]
+enum StateBackend {
+    Etcd {
+        kv_client: KvClient,
+        watch_client: WatchClient,
+    },
+    #[cfg(any(test, feature = "testing"))]
+    Memory(testing::MemoryState),
 }
 
 #[derive(Clone, Debug)]
@@ -38,8 +49,20 @@ impl IngestionStateClient {
         let watch_client = client.watch_client();
 
         Self {
-            kv_client,
-            watch_client,
+            backend: StateBackend::Etcd {
+                kv_client,
+                watch_client,
+            },
+        }
+    }
+
+    /// Returns a client that keeps the state in memory, for tests.
+    ///
+    /// Clones of the client share the same state.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new_in_memory() -> Self {
+        Self {
+            backend: StateBackend::Memory(testing::MemoryState::default()),
         }
     }
 
@@ -50,8 +73,13 @@ impl IngestionStateClient {
         impl Stream<Item = Result<IngestionStateUpdate, IngestionStateClientError>>,
         IngestionStateClientError,
     > {
-        let (_watcher, stream) = self
-            .watch_client
+        let watch_client = match &mut self.backend {
+            StateBackend::Etcd { watch_client, .. } => watch_client,
+            #[cfg(any(test, feature = "testing"))]
+            StateBackend::Memory(state) => return Ok(state.watch_changes(ct).boxed()),
+        };
+
+        let (_watcher, stream) = watch_client
             .watch_prefix(INGESTION_PREFIX_KEY, ct)
             .await
             .change_context(IngestionStateClientError)
@@ -83,25 +111,18 @@ impl IngestionStateClient {
             futures::stream::iter(changes)
         });
 
-        Ok(changes)
+        Ok(changes.boxed())
     }
 
     pub async fn get_starting_block(&mut self) -> Result<Option<u64>, IngestionStateClientError> {
-        let response = self
-            .kv_client
-            .get(STARTING_BLOCK_KEY)
+        let Some(value) = self
+            .get_value(STARTING_BLOCK_KEY)
             .await
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to get starting block")?;
-
-        let Some(kv) = response.kvs().first() else {
+            .attach_printable("failed to get starting block")?
+        else {
             return Ok(None);
         };
 
-        let value = String::from_utf8(kv.value().to_vec())
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to decode starting block")?;
-
         let block = value
             .parse::<u64>()
             .change_context(IngestionStateClientError)
@@ -114,32 +135,22 @@ impl IngestionStateClient {
         &mut self,
         block: u64,
     ) -> Result<(), IngestionStateClientError> {
-        let value = block.to_string();
-        self.kv_client
-            .put(STARTING_BLOCK_KEY, value.as_bytes())
+        self.put_value(STARTING_BLOCK_KEY, block.to_string())
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put starting block")?;
 
         Ok(())
     }
 
     pub async fn get_finalized(&mut self) -> Result<Option<u64>, IngestionStateClientError> {
-        let response = self
-            .kv_client
-            .get(FINALIZED_KEY)
+        let Some(value) = self
+            .get_value(FINALIZED_KEY)
             .await
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to get finalized block")?;
-
-        let Some(kv) = response.kvs().first() else {
+            .attach_printable("failed to get finalized block")?
+        else {
             return Ok(None);
         };
 
-        let value = String::from_utf8(kv.value().to_vec())
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to decode finalized block")?;
-
         let block = value
             .parse::<u64>()
             .change_context(IngestionStateClientError)
@@ -149,76 +160,50 @@ impl IngestionStateClient {
     }
 
     pub async fn put_finalized(&mut self, block: u64) -> Result<(), IngestionStateClientError> {
-        let value = block.to_string();
-        self.kv_client
-            .put(FINALIZED_KEY, value.as_bytes())
+        self.put_value(FINALIZED_KEY, block.to_string())
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put finalized block")?;
 
         Ok(())
     }
 
     pub async fn get_ingested(&mut self) -> Result<Option<ObjectETag>, IngestionStateClientError> {
-        let response = self
-            .kv_client
-            .get(INGESTED_KEY)
+        let etag = self
+            .get_value(INGESTED_KEY)
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to get latest ingested block")?;
 
-        let Some(kv) = response.kvs().first() else {
-            return Ok(None);
-        };
-
-        let etag = String::from_utf8(kv.value().to_vec())
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to decode etag")?;
-
-        Ok(Some(ObjectETag(etag)))
+        Ok(etag.map(ObjectETag))
     }
 
     pub async fn put_ingested(
         &mut self,
         etag: ObjectETag,
     ) -> Result<(), IngestionStateClientError> {
-        let value = etag.0;
-        self.kv_client
-            .put_and_delete(INGESTED_KEY, value.as_bytes(), PENDING_KEY)
+        self.put_value_and_delete(INGESTED_KEY, etag.0, PENDING_KEY)
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put latest ingested block")?;
 
         Ok(())
     }
 
     pub async fn put_pending(&mut self, generation: u64) -> Result<(), IngestionStateClientError> {
-        let value = generation.to_string();
-        self.kv_client
-            .put(PENDING_KEY, value.as_bytes())
+        self.put_value(PENDING_KEY, generation.to_string())
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put pending block generation")?;
 
         Ok(())
     }
 
     pub async fn get_segmented(&mut self) -> Result<Option<u64>, IngestionStateClientError> {
-        let response = self
-            .kv_client
-            .get(SEGMENTED_KEY)
+        let Some(value) = self
+            .get_value(SEGMENTED_KEY)
             .await
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to get segmented block")?;
-
-        let Some(kv) = response.kvs().first() else {
+            .attach_printable("failed to get segmented block")?
+        else {
             return Ok(None);
         };
 
-        let value = String::from_utf8(kv.value().to_vec())
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to decode segmented block")?;
-
         let block = value
             .parse::<u64>()
             .change_context(IngestionStateClientError)
@@ -228,32 +213,22 @@ impl IngestionStateClient {
     }
 
     pub async fn put_segmented(&mut self, block: u64) -> Result<(), IngestionStateClientError> {
-        let value = block.to_string();
-        self.kv_client
-            .put(SEGMENTED_KEY, value.as_bytes())
+        self.put_value(SEGMENTED_KEY, block.to_string())
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put segmented block")?;
 
         Ok(())
     }
 
     pub async fn get_grouped(&mut self) -> Result<Option<u64>, IngestionStateClientError> {
-        let response = self
-            .kv_client
-            .get(GROUPED_KEY)
+        let Some(value) = self
+            .get_value(GROUPED_KEY)
             .await
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to get grouped block")?;
-
-        let Some(kv) = response.kvs().first() else {
+            .attach_printable("failed to get grouped block")?
+        else {
             return Ok(None);
         };
 
-        let value = String::from_utf8(kv.value().to_vec())
-            .change_context(IngestionStateClientError)
-            .attach_printable("failed to decode grouped block")?;
-
         let block = value
             .parse::<u64>()
             .change_context(IngestionStateClientError)
@@ -263,15 +238,135 @@ impl IngestionStateClient {
     }
 
     pub async fn put_grouped(&mut self, block: u64) -> Result<(), IngestionStateClientError> {
-        let value = block.to_string();
-        self.kv_client
-            .put(GROUPED_KEY, value.as_bytes())
+        self.put_value(GROUPED_KEY, block.to_string())
             .await
-            .change_context(IngestionStateClientError)
             .attach_printable("failed to put grouped block")?;
 
         Ok(())
     }
+
+    /// Returns the most recent block whose block objects have been deleted.
+    pub async fn get_pruned(&mut self) -> Result<Option<u64>, IngestionStateClientError> {
+        let Some(value) = self
+            .get_value(PRUNED_KEY)
+            .await
+            .attach_printable("failed to get pruned block")?
+        else {
+            return Ok(None);
+        };
+
+        let block = value
+            .parse::<u64>()
+            .change_context(IngestionStateClientError)
+            .attach_printable("failed to parse pruned block")?;
+
+        Ok(Some(block))
+    }
+
+    pub async fn put_pruned(&mut self, block: u64) -> Result<(), IngestionStateClientError> {
+        self.put_value(PRUNED_KEY, block.to_string())
+            .await
+            .attach_printable("failed to put pruned block")?;
+
+        Ok(())
+    }
+
+    /// Remove the grouped block, for example after the groups have been deleted.
+    pub async fn delete_grouped(&mut self) -> Result<(), IngestionStateClientError> {
+        self.delete_value(GROUPED_KEY)
+            .await
+            .attach_printable("failed to delete grouped block")?;
+
+        Ok(())
+    }
+
+    async fn get_value(&mut self, key: &str) -> Result<Option<String>, IngestionStateClientError> {
+        let kv_client = match &mut self.backend {
+            StateBackend::Etcd { kv_client, .. } => kv_client,
+            #[cfg(any(test, feature = "testing"))]
+            StateBackend::Memory(state) => return Ok(state.get(key)),
+        };
+
+        let response = kv_client
+            .get(key)
+            .await
+            .change_context(IngestionStateClientError)?;
+
+        let Some(kv) = response.kvs().first() else {
+            return Ok(None);
+        };
+
+        let value = String::from_utf8(kv.value().to_vec())
+            .change_context(IngestionStateClientError)
+            .attach_printable("failed to decode value")
+            .attach_printable_lazy(|| format!("key: {key}"))?;
+
+        Ok(Some(value))
+    }
+
+    async fn put_value(
+        &mut self,
+        key: &str,
+        value: String,
+    ) -> Result<(), IngestionStateClientError> {
+        let kv_client = match &mut self.backend {
+            StateBackend::Etcd { kv_client, .. } => kv_client,
+            #[cfg(any(test, feature = "testing"))]
+            StateBackend::Memory(state) => {
+                state.put(key, value);
+                return Ok(());
+            }
+        };
+
+        kv_client
+            .put(key, value.as_bytes())
+            .await
+            .change_context(IngestionStateClientError)?;
+
+        Ok(())
+    }
+
+    /// Put `key` and delete `delete_key` atomically.
+    async fn put_value_and_delete(
+        &mut self,
+        key: &str,
+        value: String,
+        delete_key: &str,
+    ) -> Result<(), IngestionStateClientError> {
+        let kv_client = match &mut self.backend {
+            StateBackend::Etcd { kv_client, .. } => kv_client,
+            #[cfg(any(test, feature = "testing"))]
+            StateBackend::Memory(state) => {
+                state.put_and_delete(key, value, delete_key);
+                return Ok(());
+            }
+        };
+
+        kv_client
+            .put_and_delete(key, value.as_bytes(), delete_key)
+            .await
+            .change_context(IngestionStateClientError)?;
+
+        Ok(())
+    }
+
+    async fn delete_value(&mut self, key: &str) -> Result<(), IngestionStateClientError> {
+        let kv_client = match &mut self.backend {
+            StateBackend::Etcd { kv_client, .. } => kv_client,
+            #[cfg(any(test, feature = "testing"))]
+            StateBackend::Memory(state) => {
+                state.delete(key);
+                return Ok(());
+            }
+        };
+
+        kv_client
+            .delete(key)
+            .await
+            .change_context(IngestionStateClientError)?;
+
+        Ok(())
+    }
 }
 
 impl error_stack::Context for IngestionStateClientError {}
@@ -296,6 +391,10 @@ impl IngestionStateUpdate {
             .change_context(IngestionStateClientError)
             .attach_printable("failed to decode value")?;
 
+        Self::from_key_value(&key, &value)
+    }
+
+    fn from_key_value(key: &str, value: &str) -> Result<Option<Self>, IngestionStateClientError> {
         if key.ends_with(STARTING_BLOCK_KEY) {
             let block = value
                 .parse::<u64>()
@@ -319,7 +418,7 @@ impl IngestionStateUpdate {
                 Ok(Some(IngestionStateUpdate::Pending(Some(generation))))
             }
         } else if key.ends_with(INGESTED_KEY) {
-            Ok(Some(IngestionStateUpdate::Ingested(value)))
+            Ok(Some(IngestionStateUpdate::Ingested(value.to_string())))
         } else if key.ends_with(SEGMENTED_KEY) {
             let block = value
                 .parse::<u64>()
@@ -341,6 +440,9 @@ impl IngestionStateUpdate {
 pub mod testing {
     use std::borrow::Cow;
 
+    #[cfg(any(test, feature = "testing"))]
+    pub(super) use self::memory::MemoryState;
+
     use apibara_etcd::EtcdClient;
     use futures::Future;
     use testcontainers::{
@@ -395,4 +497,147 @@ pub mod testing {
                 .expect("Etcd connection error")
         }
     }
+
+    #[cfg(any(test, feature = "testing"))]
+    mod memory {
+        use std::{
+            collections::BTreeMap,
+            sync::{Arc, Mutex},
+        };
+
+        use error_stack::Report;
+        use futures::{Stream, StreamExt};
+        use tokio::sync::broadcast;
+        use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+        use tokio_util::sync::CancellationToken;
+
+        use crate::ingestion::state_client::{IngestionStateClientError, IngestionStateUpdate};
+
+        /// Number of changes buffered for each watcher.
+        const CHANGES_CHANNEL_SIZE: usize = 1024;
+
+        /// The ingestion state of an in-memory client.
+        #[derive(Clone)]
+        pub struct MemoryState {
+            values: Arc<Mutex<BTreeMap<String, String>>>,
+            changes: broadcast::Sender<(String, String)>,
+        }
+
+        impl MemoryState {
+            pub fn get(&self, key: &str) -> Option<String> {
+                let values = self.values.lock().expect("memory state lock");
+                values.get(key).cloned()
+            }
+
+            pub fn put(&self, key: &str, value: String) {
+                let mut values = self.values.lock().expect("memory state lock");
+                values.insert(key.to_string(), value.clone());
+                // Notify while holding the lock so that watchers see changes in order.
+                let _ = self.changes.send((key.to_string(), value));
+            }
+
+            pub fn put_and_delete(&self, key: &str, value: String, delete_key: &str) {
+                let mut values = self.values.lock().expect("memory state lock");
+                values.insert(key.to_string(), value.clone());
+                let _ = self.changes.send((key.to_string(), value));
+
+                if values.remove(delete_key).is_some() {
+                    let _ = self.changes.send((delete_key.to_string(), String::new()));
+                }
+            }
+
+            pub fn delete(&self, key: &str) {
+                let mut values = self.values.lock().expect("memory state lock");
+                if values.remove(key).is_some() {
+                    // Like etcd, deleted keys are reported with an empty value.
+                    let _ = self.changes.send((key.to_string(), String::new()));
+                }
+            }
+
+            pub fn watch_changes(
+                &self,
+                ct: CancellationToken,
+            ) -> impl Stream<Item = Result<IngestionStateUpdate, Report<IngestionStateClientError>>>
+                   + Send
+                   + 'static {
+                BroadcastStream::new(self.changes.subscribe())
+                    .filter_map(|change| async move {
+                        match change {
+                            Ok((key, value)) => {
+                                IngestionStateUpdate::from_key_value(&key, &value).transpose()
+                            }
+                            Err(BroadcastStreamRecvError::Lagged(count)) => {
+                                Some(Err(Report::new(IngestionStateClientError)
+                                    .attach_printable("watcher lagged behind")
+                                    .attach_printable(format!("missed changes: {count}"))))
+                            }
+                        }
+                    })
+                    .take_until(async move { ct.cancelled().await })
+            }
+        }
+
+        impl Default for MemoryState {
+            fn default() -> Self {
+                let (changes, _) = broadcast::channel(CHANGES_CHANNEL_SIZE);
+
+                Self {
+                    values: Arc::default(),
+                    changes,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::object_store::ObjectETag;
+
+    use super::{IngestionStateClient, IngestionStateUpdate};
+
+    #[tokio::test]
+    async fn test_in_memory_state_client() {
+        let ct = CancellationToken::new();
+        let mut client = IngestionStateClient::new_in_memory();
+        let mut other = client.clone();
+
+        let changes = client.watch_changes(ct.clone()).await.unwrap();
+
+        assert!(client.get_finalized().await.unwrap().is_none());
+
+        client.put_finalized(100).await.unwrap();
+        client.put_pending(1).await.unwrap();
+        client
+            .put_ingested(ObjectETag("etag".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(other.get_finalized().await.unwrap(), Some(100));
+        assert_eq!(
+            other.get_ingested().await.unwrap(),
+            Some(ObjectETag("etag".to_string()))
+        );
+
+        let changes = changes.take(4).collect::<Vec<_>>().await;
+        assert!(matches!(
+            changes[0].as_ref().unwrap(),
+            IngestionStateUpdate::Finalized(100)
+        ));
+        assert!(matches!(
+            changes[1].as_ref().unwrap(),
+            IngestionStateUpdate::Pending(Some(1))
+        ));
+        assert!(matches!(
+            changes[2].as_ref().unwrap(),
+            IngestionStateUpdate::Ingested(etag) if etag == "etag"
+        ));
+        assert!(matches!(
+            changes[3].as_ref().unwrap(),
+            IngestionStateUpdate::Pending(None)
+        ));
+    }
 }
@@ -0,0 +1,149 @@
+//! Checks run on blocks before they're uploaded to the block store.
+use std::collections::HashMap;
+
+use error_stack::{Result, ResultExt};
+
+use crate::{chain::BlockInfo, fragment::Block, Hash};
+
+use super::IngestionError;
+
+/// Validate a block before uploading it.
+///
+/// If `expected_parent` is set, the block's parent hash must match it.
+pub fn validate_block(
+    block_info: &BlockInfo,
+    block: &Block,
+    expected_parent: Option<&Hash>,
+) -> Result<(), IngestionError> {
+    if let Some(expected_parent) = expected_parent {
+        if &block_info.parent != expected_parent {
+            return Err(IngestionError::InvalidBlock)
+                .attach_printable("block parent does not match the previous canonical block")
+                .attach_printable_lazy(|| format!("block number: {}", block_info.number))
+                .attach_printable_lazy(|| format!("parent: {}", block_info.parent))
+                .attach_printable_lazy(|| format!("expected parent: {}", expected_parent));
+        }
+    }
+
+    validate_fragments(block)
+        .attach_printable_lazy(|| format!("block number: {}", block_info.number))
+}
+
+/// Check that the block's indexes and joins are consistent with its body.
+pub fn validate_fragments(block: &Block) -> Result<(), IngestionError> {
+    if block.index.len() != block.body.len() {
+        return Err(IngestionError::InvalidBlock)
+            .attach_printable("block indexes and body fragments do not match")
+            .attach_printable_lazy(|| format!("indexes len: {}", block.index.len()))
+            .attach_printable_lazy(|| format!("body len: {}", block.body.len()));
+    }
+
+    let mut body_len = HashMap::new();
+    for fragment in block.body.iter() {
+        if body_len
+            .insert(fragment.fragment_id, fragment.data.len())
+            .is_some()
+        {
+            return Err(IngestionError::InvalidBlock)
+                .attach_printable("duplicate body fragment")
+                .attach_printable_lazy(|| format!("fragment id: {}", fragment.fragment_id));
+        }
+    }
+
+    for index in block.index.indexes.iter() {
+        let Some(len) = body_len.get(&index.fragment_id) else {
+            return Err(IngestionError::InvalidBlock)
+                .attach_printable("index references a missing body fragment")
+                .attach_printable_lazy(|| format!("fragment id: {}", index.fragment_id));
+        };
+
+        if (index.range_start + index.range_len) as usize != *len {
+            return Err(IngestionError::InvalidBlock)
+                .attach_printable("index range does not match body fragment length")
+                .attach_printable_lazy(|| format!("fragment id: {}", index.fragment_id))
+                .attach_printable_lazy(|| {
+                    format!("range: {}+{}", index.range_start, index.range_len)
+                })
+                .attach_printable_lazy(|| format!("body len: {}", len));
+        }
+    }
+
+    for join in block.join.joins.iter() {
+        let targets = join.joins.iter().map(|join| &join.to_fragment_id);
+        for fragment_id in std::iter::once(&join.fragment_id).chain(targets) {
+            if !body_len.contains_key(fragment_id) {
+                return Err(IngestionError::InvalidBlock)
+                    .attach_printable("join references a missing body fragment")
+                    .attach_printable_lazy(|| format!("fragment id: {}", fragment_id));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chain::BlockInfo,
+        fragment::{
+            Block, BodyFragment, HeaderFragment, IndexFragment, IndexGroupFragment,
+            JoinGroupFragment,
+        },
+        Hash,
+    };
+
+    use super::validate_block;
+
+    fn new_block(range_len: u32) -> Block {
+        Block {
+            header: HeaderFragment {
+                data: Vec::default(),
+            },
+            index: IndexGroupFragment {
+                indexes: vec![IndexFragment {
+                    fragment_id: 2,
+                    range_start: 0,
+                    range_len,
+                    indexes: Vec::default(),
+                }],
+            },
+            join: JoinGroupFragment {
+                joins: Vec::default(),
+            },
+            body: vec![BodyFragment {
+                fragment_id: 2,
+                name: "transaction".to_string(),
+                data: vec![Vec::default(), Vec::default()],
+            }],
+        }
+    }
+
+    fn new_block_info() -> BlockInfo {
+        BlockInfo {
+            number: 1,
+            hash: Hash([1; 32].to_vec()),
+            parent: Hash([0; 32].to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_validate_block() {
+        let block_info = new_block_info();
+        assert!(validate_block(&block_info, &new_block(2), None).is_ok());
+        assert!(validate_block(&block_info, &new_block(2), Some(&block_info.parent)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_block_parent_mismatch() {
+        let block_info = new_block_info();
+        let other = Hash([2; 32].to_vec());
+        assert!(validate_block(&block_info, &new_block(2), Some(&other)).is_err());
+    }
+
+    #[test]
+    fn test_validate_block_index_range_mismatch() {
+        let block_info = new_block_info();
+        assert!(validate_block(&block_info, &new_block(1), None).is_err());
+    }
+}
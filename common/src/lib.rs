@@ -4,25 +4,34 @@ pub mod chain_store;
 pub mod chain_view;
 pub mod cli;
 pub mod compaction;
+pub mod config_file;
 mod core;
 pub mod data_stream;
+pub mod dataset_manifest;
 pub mod dbg;
+pub mod export;
 pub mod file_cache;
 pub mod fragment;
 pub mod index;
 pub mod ingestion;
 pub mod join;
+pub mod migrate;
 pub mod object_store;
 pub mod options_store;
 pub mod query;
+pub mod reload;
+pub mod resegment;
 pub mod rkyv;
+pub mod rpc_pool;
 pub mod segment;
 pub mod server;
+pub mod shared_cache;
+pub mod snapshot;
 
 pub use apibara_etcd as etcd;
 use data_stream::BlockFilterFactory;
 use fragment::FragmentInfo;
-use ingestion::BlockIngestion;
+use ingestion::{BlockIngestion, RpcRateLimiter};
 
 pub use self::core::{testing::new_test_cursor, Cursor, GetCursor, Hash};
 
@@ -36,7 +45,9 @@ pub trait ChainSupport {
     fn fragment_info(&self) -> Vec<FragmentInfo>;
 
     /// Returns the block ingestion service.
-    fn block_ingestion(&self) -> Self::BlockIngestion;
+    ///
+    /// All requests to the chain's RPC must go through `rate_limiter`.
+    fn block_ingestion(&self, rate_limiter: RpcRateLimiter) -> Self::BlockIngestion;
 
     /// Returns the block filter factory.
     fn block_filter_factory(&self) -> Self::BlockFilterFactory;
@@ -45,16 +56,22 @@ pub trait ChainSupport {
 pub use self::server_impl::{run_server, ServerError};
 
 mod server_impl {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
     use crate::{
-        block_store::BlockStoreReader, chain_view::chain_view_sync_loop,
-        compaction::compaction_service_loop, fragment, ingestion::ingestion_service_loop,
-        server::server_loop, ChainSupport, StartArgs,
+        block_store::BlockStoreReader,
+        chain_view::chain_view_sync_loop,
+        compaction::compaction_service_loop,
+        dataset_manifest::DatasetManifestStore,
+        fragment,
+        ingestion::{ingestion_service_loop, validate_chain_identity, RpcRateLimiter},
+        reload::{reload_settings_loop, RuntimeSettings},
+        server::{server_loop, AdminControl},
+        ChainSupport, StartArgs,
     };
     use error_stack::ResultExt;
     use tokio_util::sync::CancellationToken;
-    use tracing::info;
+    use tracing::{info, warn};
 
     #[derive(Debug)]
     pub struct ServerError;
@@ -70,6 +87,16 @@ mod server_impl {
     {
         emit_dna_up_metric(version);
 
+        let settings = RuntimeSettings::new(&args.log, &args.server, &args.ingestion)
+            .change_context(ServerError)?;
+
+        if let Err(err) = apibara_observability::set_log_filter(&settings.log_filter) {
+            warn!(error = ?err, "failed to set log filter");
+        }
+
+        let (settings_tx, settings) = tokio::sync::watch::channel(settings);
+        let reload_handle = tokio::spawn(reload_settings_loop(settings_tx, ct.clone()));
+
         let object_store = args.object_store.into_object_store_client().await;
         let mut etcd_client = args
             .etcd
@@ -98,14 +125,35 @@ mod server_impl {
         let etcd_renew_handle =
             tokio::spawn(etcd_client.clone().start_renew_auth_token(ct.clone()));
 
+        let mut admin_control = AdminControl::default();
+
+        let manifest_store = DatasetManifestStore::new(object_store.clone());
+
         let ingestion_handle = if args.ingestion.ingestion_enabled {
-            let ingestion = chain_support.block_ingestion();
+            let rate_limiter = RpcRateLimiter::new(
+                ingestion_options.rpc_rate_limit,
+                ingestion_options.rpc_concurrency,
+            );
+            let ingestion = chain_support.block_ingestion(rate_limiter.clone());
+
+            // Don't ingest or serve data from a different chain.
+            validate_chain_identity(&ingestion, &manifest_store)
+                .await
+                .change_context(ServerError)
+                .attach_printable("failed to validate chain identity")?;
+
+            let (ingestion_paused_tx, ingestion_paused) = tokio::sync::watch::channel(false);
+            admin_control.ingestion_paused = Some(Arc::new(ingestion_paused_tx));
+
             tokio::spawn(ingestion_service_loop(
                 ingestion,
+                rate_limiter,
                 etcd_client.clone(),
                 object_store.clone(),
                 file_cache.clone(),
                 ingestion_options,
+                settings.clone(),
+                ingestion_paused,
                 ct.clone(),
             ))
         } else {
@@ -127,16 +175,24 @@ mod server_impl {
         .change_context(ServerError)
         .attach_printable("failed to start chain view sync service")?;
 
+        let chain_view_sync = match &args.server.server_chain_view_snapshot {
+            Some(path) => chain_view_sync.with_snapshot_path(PathBuf::from(path)),
+            None => chain_view_sync,
+        };
+
         let sync_handle = tokio::spawn(chain_view_sync.start(ct.clone()));
 
         let compaction_handle = if args.compaction.compaction_enabled {
             let options = args.compaction.to_compaction_options();
+            let trigger = Arc::new(tokio::sync::Notify::new());
+            admin_control.compaction_trigger = Some(trigger.clone());
 
             tokio::spawn(compaction_service_loop(
                 etcd_client.clone(),
                 object_store.clone(),
                 chain_view.clone(),
                 options,
+                trigger,
                 ct.clone(),
             ))
         } else {
@@ -185,7 +241,12 @@ mod server_impl {
             fragment_id_to_name
         };
 
+        // Streams read the segment generation from the chain view, which follows rewrites.
         let block_store = BlockStoreReader::new(object_store.clone(), file_cache.clone());
+        let block_store = match args.server.to_shared_cache().change_context(ServerError)? {
+            Some(shared_cache) => block_store.with_shared_cache(shared_cache),
+            None => block_store,
+        };
 
         let server_handle = if args.server.server_enabled {
             let options = args
@@ -198,7 +259,10 @@ mod server_impl {
                 chain_view,
                 fragment_id_to_name,
                 block_store,
+                manifest_store,
                 options,
+                settings,
+                admin_control,
                 ct,
             ))
         } else {
@@ -212,6 +276,10 @@ mod server_impl {
         };
 
         tokio::select! {
+            reload = reload_handle => {
+                info!("settings reload loop terminated");
+                reload.change_context(ServerError)?.change_context(ServerError)?;
+            }
             etcd_renew = etcd_renew_handle => {
                 info!("etcd auth token renewal loop terminated");
                 etcd_renew.change_context(ServerError)?.change_context(ServerError)?;
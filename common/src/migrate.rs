@@ -0,0 +1,270 @@
+//! Upgrade stored segments and groups to the current format version.
+use bytes::Bytes;
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tracing::{info, warn};
+
+use crate::{
+    cli::ObjectStoreArgs,
+    dataset_manifest::DatasetManifestStore,
+    object_store::{GetOptions, ObjectStore, ObjectStoreResultExt, PutMode, PutOptions},
+    segment::{decode_segment_header, encode_segment_header, SEGMENT_FORMAT_VERSION},
+};
+
+/// Object store prefixes that contain versioned objects.
+///
+/// Segments rewritten by resegmenting live under the `generation/` prefix.
+pub(crate) const VERSIONED_PREFIXES: &[&str] = &["segment/", "group/", "generation/"];
+
+#[derive(Debug)]
+pub struct MigrateError;
+
+#[derive(Args, Debug)]
+pub struct MigrateCommand {
+    #[clap(flatten)]
+    object_store: ObjectStoreArgs,
+    /// Only report which objects need to be migrated.
+    #[arg(long)]
+    dry_run: bool,
+    /// Number of objects migrated concurrently.
+    #[arg(long, default_value = "16")]
+    concurrency: usize,
+}
+
+impl MigrateCommand {
+    pub async fn run(self) -> Result<(), MigrateError> {
+        let object_store = self.object_store.into_object_store_client().await;
+        migrate_object_store(object_store, self.dry_run, self.concurrency).await
+    }
+}
+
+/// Rewrite all segments and groups that are not in the current format.
+///
+/// Objects are updated in place, conditional on their etag, so that objects
+/// rewritten concurrently by the compaction service are not overwritten.
+/// Once all objects are upgraded, the dataset manifest is updated with the current
+/// format version.
+pub async fn migrate_object_store(
+    object_store: ObjectStore,
+    dry_run: bool,
+    concurrency: usize,
+) -> Result<(), MigrateError> {
+    let mut keys = Vec::new();
+    for prefix in VERSIONED_PREFIXES {
+        let prefix_keys = object_store
+            .list(prefix)
+            .await
+            .change_context(MigrateError)
+            .attach_printable("failed to list objects")
+            .attach_printable_lazy(|| format!("prefix: {prefix}"))?;
+        keys.extend(prefix_keys);
+    }
+
+    info!(
+        objects = keys.len(),
+        version = SEGMENT_FORMAT_VERSION,
+        dry_run,
+        "migrating objects"
+    );
+
+    let migrated = futures::stream::iter(keys.iter())
+        .map(|key| migrate_object(&object_store, key, dry_run))
+        .buffer_unordered(concurrency.max(1))
+        .try_fold(
+            0,
+            |count, migrated| async move { Ok(count + migrated as usize) },
+        )
+        .await?;
+
+    if dry_run {
+        info!(migrated, "migration dry run completed");
+        return Ok(());
+    }
+
+    // All objects are in the current format now.
+    DatasetManifestStore::new(object_store)
+        .update(|manifest| manifest.format_version = SEGMENT_FORMAT_VERSION)
+        .await
+        .change_context(MigrateError)
+        .attach_printable("failed to update dataset manifest format version")?;
+
+    info!(migrated, "migration completed");
+
+    Ok(())
+}
+
+async fn migrate_object(
+    object_store: &ObjectStore,
+    key: &str,
+    dry_run: bool,
+) -> Result<bool, MigrateError> {
+    let response = object_store
+        .get(key, GetOptions::default())
+        .await
+        .change_context(MigrateError)
+        .attach_printable("failed to get object")
+        .attach_printable_lazy(|| format!("key: {key}"))?;
+
+    let (version, content) = decode_segment_header(response.body);
+
+    if version == SEGMENT_FORMAT_VERSION {
+        return Ok(false);
+    }
+
+    info!(key, version, "migrating object");
+
+    if dry_run {
+        return Ok(true);
+    }
+
+    let content =
+        upgrade_format(version, content).attach_printable_lazy(|| format!("key: {key}"))?;
+
+    let options = PutOptions {
+        mode: PutMode::Update(response.etag),
+        ..Default::default()
+    };
+
+    match object_store
+        .put(key, encode_segment_header(&content), options)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(err) if err.is_precondition() => {
+            warn!(key, "object changed during migration. skipping");
+            Ok(false)
+        }
+        Err(err) => Err(err)
+            .change_context(MigrateError)
+            .attach_printable("failed to put migrated object")
+            .attach_printable_lazy(|| format!("key: {key}")),
+    }
+}
+
+/// Convert the object content from `version` to the current format version.
+///
+/// Readers use it to read objects that were not migrated yet.
+pub(crate) fn upgrade_format(version: u32, content: Bytes) -> Result<Bytes, MigrateError> {
+    match version {
+        // Version 1 objects have no header, but their content didn't change.
+        1 => Ok(content),
+        _ => Err(MigrateError)
+            .attach_printable("no migration available for format version")
+            .attach_printable_lazy(|| format!("version: {version}"))
+            .attach_printable_lazy(|| format!("current version: {SEGMENT_FORMAT_VERSION}")),
+    }
+}
+
+impl error_stack::Context for MigrateError {}
+
+impl std::fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "migrate error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        block_store::UncachedBlockStoreReader,
+        object_store::{GetOptions, ObjectStore, PutOptions},
+        segment::{decode_segment_header, encode_segment_header, SEGMENT_FORMAT_VERSION},
+        Cursor,
+    };
+
+    use super::migrate_object_store;
+
+    #[tokio::test]
+    async fn test_migrate_objects_without_header() {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let legacy_key = "segment/0000001000/header";
+        let current_key = "group/0000001000/index";
+
+        object_store
+            .put(
+                legacy_key,
+                Bytes::from_static(b"legacy segment"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+        let current = object_store
+            .put(
+                current_key,
+                encode_segment_header(b"current group"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        // Objects in an older format are upgraded on read.
+        let reader = UncachedBlockStoreReader::new(object_store.clone());
+        let first_block = Cursor::new_finalized(1_000);
+        let segment = reader.get_segment(&first_block, "header").await.unwrap();
+        assert_eq!(segment.as_ref(), b"legacy segment");
+
+        // A dry run doesn't change anything.
+        let legacy = object_store
+            .get(legacy_key, GetOptions::default())
+            .await
+            .unwrap();
+        migrate_object_store(object_store.clone(), true, 4)
+            .await
+            .unwrap();
+        let stored = object_store
+            .get(legacy_key, GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(stored.etag, legacy.etag);
+
+        migrate_object_store(object_store.clone(), false, 4)
+            .await
+            .unwrap();
+
+        let segment = reader.get_segment(&first_block, "header").await.unwrap();
+        assert_eq!(segment.as_ref(), b"legacy segment");
+
+        let stored = object_store
+            .get(legacy_key, GetOptions::default())
+            .await
+            .unwrap();
+        let (version, _) = decode_segment_header(stored.body);
+        assert_eq!(version, SEGMENT_FORMAT_VERSION);
+
+        // Objects in the current format are not rewritten.
+        let group = object_store
+            .get(current_key, GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(group.etag, current.etag);
+        assert_eq!(
+            reader.get_group(&first_block).await.unwrap().as_ref(),
+            b"current group"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_newer_format_version() {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+
+        let mut newer = encode_segment_header(b"newer segment").to_vec();
+        newer[8..12].copy_from_slice(&(SEGMENT_FORMAT_VERSION + 1).to_le_bytes());
+        object_store
+            .put(
+                "segment/0000001000/header",
+                Bytes::from(newer),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        let reader = UncachedBlockStoreReader::new(object_store.clone());
+        assert!(reader
+            .get_segment(&Cursor::new_finalized(1_000), "header")
+            .await
+            .is_err());
+    }
+}
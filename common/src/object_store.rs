@@ -1,9 +1,27 @@
+use std::{collections::HashMap, io::Read};
+
 use apibara_etcd::normalize_prefix;
-use aws_sdk_s3::{config::http::HttpResponse, error::SdkError};
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::SdkError,
+    types::{CompletedMultipartUpload, CompletedPart},
+};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use error_stack::{Report, Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::debug;
 
+/// S3 requires all parts but the last one to be at least 5 MiB.
+const MIN_MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Object metadata key used to store the compression codec.
+///
+/// Objects without this key were written before the codec was configurable and
+/// are compressed with zstd.
+const COMPRESSION_METADATA_KEY: &str = "compression";
+
 #[derive(Debug)]
 pub enum ObjectStoreError {
     /// Precondition failed.
@@ -27,14 +45,53 @@ pub struct ObjectStoreOptions {
     pub bucket: String,
     /// Under which prefix to store the data.
     pub prefix: Option<String>,
+    /// How to compress objects before uploading them.
+    pub compression: CompressionOptions,
+}
+
+/// Compression codec used for stored objects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompressionCodec {
+    /// Store objects uncompressed.
+    None,
+    /// Compress objects with zstd.
+    Zstd,
+}
+
+/// Options for the objects compression.
+#[derive(Clone, Debug)]
+pub struct CompressionOptions {
+    /// The compression codec.
+    pub codec: CompressionCodec,
+    /// The compression level. Only used by zstd.
+    ///
+    /// Use `0` for zstd's default level.
+    pub level: i32,
 }
 
 /// This is an opinionated object store client.
 #[derive(Clone)]
 pub struct ObjectStore {
-    client: aws_sdk_s3::Client,
+    backend: Backend,
     prefix: String,
     bucket: String,
+    compression: CompressionOptions,
+}
+
+/// Where objects are stored.
+#[derive(Clone)]
+enum Backend {
+    S3(aws_sdk_s3::Client),
+    #[cfg(any(test, feature = "testing"))]
+    Memory(testing::MemoryBucket),
+}
+
+/// An object as stored, before decompression.
+#[derive(Clone)]
+struct StoredObject {
+    body: Bytes,
+    etag: ObjectETag,
+    metadata: HashMap<String, String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -60,6 +117,21 @@ pub enum PutMode {
 #[derive(Default, Clone, Debug)]
 pub struct PutOptions {
     pub mode: PutMode,
+    /// User-defined metadata stored with the object.
+    pub metadata: HashMap<String, String>,
+}
+
+/// Options for multipart uploads.
+#[derive(Clone, Debug)]
+pub struct PutMultipartOptions {
+    /// Size of each part, in bytes.
+    ///
+    /// Objects smaller than this are uploaded with a single request.
+    pub part_size: usize,
+    /// Maximum number of parts uploaded concurrently.
+    pub max_concurrent_parts: usize,
+    /// User-defined metadata stored with the object.
+    pub metadata: HashMap<String, String>,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -69,6 +141,10 @@ pub struct DeleteOptions {}
 pub struct GetResult {
     pub body: Bytes,
     pub etag: ObjectETag,
+    /// User-defined metadata stored with the object.
+    pub metadata: HashMap<String, String>,
+    /// Size of the object in the store, after compression.
+    pub stored_size: usize,
 }
 
 #[derive(Debug)]
@@ -79,6 +155,8 @@ pub struct PutResult {
 #[derive(Debug)]
 pub struct DeleteResult;
 
+// The in-memory backend only exists in tests, so most matches have a single arm.
+#[allow(clippy::infallible_destructuring_match)]
 impl ObjectStore {
     pub fn new(config: aws_config::SdkConfig, options: ObjectStoreOptions) -> Self {
         Self::new_from_config((&config).into(), options)
@@ -90,9 +168,35 @@ impl ObjectStore {
         let prefix = normalize_prefix(options.prefix);
 
         Self {
-            client,
+            backend: Backend::S3(client),
             bucket: options.bucket,
             prefix,
+            compression: options.compression,
+        }
+    }
+
+    /// Returns an object store that keeps objects in memory, for tests.
+    ///
+    /// Objects are encoded like in S3, so checksums and compression are exercised too.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new_in_memory(options: ObjectStoreOptions) -> Self {
+        let prefix = normalize_prefix(options.prefix);
+
+        Self {
+            backend: Backend::Memory(testing::MemoryBucket::default()),
+            bucket: options.bucket,
+            prefix,
+            compression: options.compression,
+        }
+    }
+
+    /// Run `hook` before each put to the in-memory object store.
+    ///
+    /// Used to slow down or fail uploads in tests.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn set_put_hook(&self, hook: testing::PutHook) {
+        if let Backend::Memory(bucket) = &self.backend {
+            bucket.set_put_hook(hook);
         }
     }
 
@@ -101,9 +205,27 @@ impl ObjectStore {
         Self::new(config, options)
     }
 
+    /// Returns a client that stores objects under `prefix`, relative to this client's prefix.
+    pub fn with_prefix(&self, prefix: &str) -> Self {
+        let prefix = normalize_prefix(Some(format!("{}{}", self.prefix, prefix)));
+
+        Self {
+            backend: self.backend.clone(),
+            bucket: self.bucket.clone(),
+            prefix,
+            compression: self.compression.clone(),
+        }
+    }
+
     /// Ensure the currently configured bucket exists.
     pub async fn ensure_bucket(&self) -> Result<(), ObjectStoreError> {
-        self.client
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(_) => return Ok(()),
+        };
+
+        client
             .create_bucket()
             .bucket(&self.bucket)
             .send()
@@ -121,11 +243,67 @@ impl ObjectStore {
         options: GetOptions,
     ) -> Result<GetResult, ObjectStoreError> {
         let key = self.full_key(path);
-        let response = self
-            .client
+        let StoredObject {
+            body,
+            etag,
+            metadata,
+        } = match &self.backend {
+            Backend::S3(client) => self.get_s3(client, &key, options).await?,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => bucket.get(&key, options.etag.as_ref())?,
+        };
+
+        let codec = match metadata.get(COMPRESSION_METADATA_KEY) {
+            None => CompressionCodec::Zstd,
+            Some(codec) => CompressionCodec::from_metadata(codec)
+                .ok_or(ObjectStoreError::Metadata)
+                .attach_printable("unknown compression codec")
+                .attach_printable_lazy(|| format!("codec: {codec}"))
+                .attach_printable_lazy(|| format!("key: {key}"))?,
+        };
+
+        let stored_size = body.len();
+
+        let decompressed = match codec {
+            CompressionCodec::None => body,
+            CompressionCodec::Zstd => {
+                let decompressed = BytesMut::with_capacity(stored_size);
+                let mut writer = decompressed.writer();
+                zstd::stream::copy_decode(&mut body.reader(), &mut writer)
+                    .change_context(ObjectStoreError::Request)?;
+                writer.into_inner().freeze()
+            }
+        };
+
+        let checksum = (&decompressed[decompressed.len() - 4..]).get_u32();
+        let data = decompressed[..decompressed.len() - 4].as_ref();
+
+        if crc32fast::hash(data) != checksum {
+            return Err(ObjectStoreError::ChecksumMismatch)
+                .attach_printable("checksum mismatch")
+                .attach_printable_lazy(|| format!("key: {key}"));
+        }
+
+        let body = Bytes::copy_from_slice(data);
+
+        Ok(GetResult {
+            body,
+            etag,
+            metadata,
+            stored_size,
+        })
+    }
+
+    async fn get_s3(
+        &self,
+        client: &aws_sdk_s3::Client,
+        key: &str,
+        options: GetOptions,
+    ) -> Result<StoredObject, ObjectStoreError> {
+        let response = client
             .get_object()
             .bucket(&self.bucket)
-            .key(&key)
+            .key(key)
             .customize()
             .mutate_request(move |request| {
                 if let Some(etag) = &options.etag {
@@ -138,6 +316,8 @@ impl ObjectStore {
             .attach_printable("failed to get object")
             .attach_printable_lazy(|| format!("key: {key}"))?;
 
+        let metadata = response.metadata.unwrap_or_default();
+
         let etag = response
             .e_tag
             .ok_or(ObjectStoreError::Metadata)
@@ -149,26 +329,14 @@ impl ObjectStore {
             .collect()
             .await
             .change_context(ObjectStoreError::Request)
-            .attach_printable("failed to read object body")?;
-
-        let decompressed = BytesMut::with_capacity(body.remaining());
-        let mut writer = decompressed.writer();
-        zstd::stream::copy_decode(&mut body.reader(), &mut writer)
-            .change_context(ObjectStoreError::Request)?;
-        let decompressed = writer.into_inner();
+            .attach_printable("failed to read object body")?
+            .into_bytes();
 
-        let checksum = (&decompressed[decompressed.len() - 4..]).get_u32();
-        let data = decompressed[..decompressed.len() - 4].as_ref();
-
-        if crc32fast::hash(data) != checksum {
-            return Err(ObjectStoreError::ChecksumMismatch)
-                .attach_printable("checksum mismatch")
-                .attach_printable_lazy(|| format!("key: {key}"));
-        }
-
-        let body = Bytes::copy_from_slice(data);
-
-        Ok(GetResult { body, etag })
+        Ok(StoredObject {
+            body,
+            etag,
+            metadata,
+        })
     }
 
     #[tracing::instrument(
@@ -182,36 +350,241 @@ impl ObjectStore {
         path: &str,
         body: Bytes,
         options: PutOptions,
+    ) -> Result<PutResult, ObjectStoreError> {
+        let key = self.full_key(path);
+        let compressed = self.compress_body(body, &key)?;
+
+        let metadata = self.object_metadata(&options.metadata);
+        self.put_body(&key, compressed, metadata, options.mode)
+            .await
+    }
+
+    /// Put an object as is, without the checksum and compression used for the DNA objects.
+    ///
+    /// Used for files read by other tools. Objects written this way can't be read with `get`.
+    pub async fn put_raw(
+        &self,
+        path: &str,
+        body: Bytes,
+        options: PutOptions,
+    ) -> Result<PutResult, ObjectStoreError> {
+        let key = self.full_key(path);
+        self.put_body(&key, body, options.metadata, options.mode)
+            .await
+    }
+
+    /// Put an object using a multipart upload.
+    ///
+    /// The object is compressed on the blocking thread pool and each part is uploaded as
+    /// soon as it's ready, with at most `max_concurrent_parts` parts in flight at the same
+    /// time. Only a few parts of the compressed object are held in memory.
+    /// Objects that fit in a single part are uploaded with a regular put request.
+    #[tracing::instrument(
+        name = "object_store_put_multipart",
+        skip_all,
+        fields(key, compression_ratio, parts),
+        level = "debug"
+    )]
+    pub async fn put_multipart(
+        &self,
+        path: &str,
+        body: Bytes,
+        options: PutMultipartOptions,
     ) -> Result<PutResult, ObjectStoreError> {
         let current_span = tracing::Span::current();
 
         let key = self.full_key(path);
         let size_before = body.len();
+        let part_size = options.part_size.max(MIN_MULTIPART_PART_SIZE);
+        let max_concurrent_parts = options.max_concurrent_parts.max(1);
 
-        let checksum = crc32fast::hash(&body);
-        let mut body = BytesMut::from(body);
-        body.put_u32(checksum);
+        let (parts_tx, mut parts_rx) = mpsc::channel(max_concurrent_parts);
+        let compression = self.compression.clone();
+        let compression_task = tokio::task::spawn_blocking(move || {
+            compress_into_parts(&compression, body, part_size, parts_tx)
+        });
 
-        let mut compressed = BytesMut::with_capacity(body.len()).writer();
-        zstd::stream::copy_encode(body.reader(), &mut compressed, 0)
-            .change_context(ObjectStoreError::Request)?;
-        let compressed = compressed.into_inner();
+        // The body always contains the checksum, so there's at least one part.
+        let first_part = parts_rx.recv().await;
+        let second_part = match first_part {
+            Some(_) => parts_rx.recv().await,
+            None => None,
+        };
 
-        let size_after = compressed.len();
-        let compression_ratio = size_before as f64 / size_after as f64;
+        let client = match &self.backend {
+            Backend::S3(client) if second_part.is_some() => client,
+            _ => {
+                let mut compressed = BytesMut::new();
+                for part in first_part.into_iter().chain(second_part) {
+                    compressed.put(part);
+                }
+                while let Some(part) = parts_rx.recv().await {
+                    compressed.put(part);
+                }
 
-        current_span.record("key", &key);
-        current_span.record("compression_ratio", compression_ratio);
-        debug!(compression_ratio, key, "compressed object");
+                join_compression_task(compression_task, &key, size_before).await?;
 
-        let response = self
-            .client
-            .put_object()
+                let metadata = self.object_metadata(&options.metadata);
+                return self
+                    .put_body(&key, compressed.freeze(), metadata, PutMode::default())
+                    .await;
+            }
+        };
+
+        let response = client
+            .create_multipart_upload()
             .bucket(&self.bucket)
             .key(&key)
-            .body(compressed.freeze().into())
+            .set_metadata(Some(self.object_metadata(&options.metadata)))
+            .send()
+            .await
+            .change_to_object_store_context()
+            .attach_printable("failed to create multipart upload")
+            .attach_printable_lazy(|| format!("key: {key}"))?;
+
+        let upload_id = response
+            .upload_id
+            .ok_or(ObjectStoreError::Metadata)
+            .attach_printable("missing multipart upload id")?;
+
+        let parts = futures::stream::iter(first_part.into_iter().chain(second_part))
+            .chain(ReceiverStream::new(parts_rx));
+
+        let completed_parts = parts
+            .enumerate()
+            .map(|(index, part)| self.upload_part(client, &key, &upload_id, index as i32 + 1, part))
+            .buffered(max_concurrent_parts)
+            .try_collect::<Vec<_>>()
+            .await;
+
+        // Compression stops early if uploading a part fails, so check it only after all
+        // parts are uploaded.
+        let completed_parts = match completed_parts {
+            Ok(completed_parts) => join_compression_task(compression_task, &key, size_before)
+                .await
+                .map(|_| completed_parts),
+            Err(err) => Err(err),
+        };
+
+        let completed_parts = match completed_parts {
+            Ok(completed_parts) => completed_parts,
+            Err(err) => {
+                self.abort_multipart_upload(client, &key, &upload_id).await;
+                return Err(err);
+            }
+        };
+
+        current_span.record("parts", completed_parts.len());
+
+        let multipart_upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(completed_parts))
+            .build();
+
+        let response = client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .upload_id(&upload_id)
+            .multipart_upload(multipart_upload)
+            .send()
+            .await
+            .change_to_object_store_context()
+            .attach_printable("failed to complete multipart upload")
+            .attach_printable_lazy(|| format!("key: {key}"));
+
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                self.abort_multipart_upload(client, &key, &upload_id).await;
+                return Err(err);
+            }
+        };
+
+        let etag = response
+            .e_tag
+            .ok_or(ObjectStoreError::Metadata)
+            .attach_printable("missing etag")?
+            .into();
+
+        Ok(PutResult { etag })
+    }
+
+    async fn upload_part(
+        &self,
+        client: &aws_sdk_s3::Client,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<CompletedPart, ObjectStoreError> {
+        let response = client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(body.into())
+            .send()
+            .await
+            .change_to_object_store_context()
+            .attach_printable("failed to upload part")
+            .attach_printable_lazy(|| format!("key: {key}"))
+            .attach_printable_lazy(|| format!("part number: {part_number}"))?;
+
+        let etag = response
+            .e_tag
+            .ok_or(ObjectStoreError::Metadata)
+            .attach_printable("missing part etag")?;
+
+        Ok(CompletedPart::builder()
+            .e_tag(etag)
+            .part_number(part_number)
+            .build())
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        client: &aws_sdk_s3::Client,
+        key: &str,
+        upload_id: &str,
+    ) {
+        // Best effort. The bucket lifecycle rules take care of uploads we fail to abort.
+        if let Err(err) = client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+        {
+            debug!(key, error = ?err, "failed to abort multipart upload");
+        }
+    }
+
+    async fn put_body(
+        &self,
+        key: &str,
+        body: Bytes,
+        metadata: HashMap<String, String>,
+        mode: PutMode,
+    ) -> Result<PutResult, ObjectStoreError> {
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => {
+                bucket.before_put(key).await?;
+                return bucket.put(key, body, metadata, mode);
+            }
+        };
+
+        let response = client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_metadata(Some(metadata))
+            .body(body.into())
             .customize()
-            .mutate_request(move |request| match &options.mode {
+            .mutate_request(move |request| match &mode {
                 PutMode::Overwrite => {}
                 PutMode::Create => {
                     // If-None-Match: "*" seems to be better supported than If-Match: "".
@@ -236,6 +609,110 @@ impl ObjectStore {
         Ok(PutResult { etag })
     }
 
+    /// List the keys of all objects under the given prefix.
+    ///
+    /// The returned keys are relative to the object store prefix.
+    #[tracing::instrument(name = "object_store_list", skip(self), level = "debug")]
+    pub async fn list(&self, path: &str) -> Result<Vec<String>, ObjectStoreError> {
+        let key = self.full_key(path);
+
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => return Ok(bucket.list(&key, &self.prefix)),
+        };
+
+        let mut pages = client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&key)
+            .into_paginator()
+            .send();
+
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page
+                .change_to_object_store_context()
+                .attach_printable("failed to list objects")
+                .attach_printable_lazy(|| format!("prefix: {key}"))?;
+
+            for object in page.contents() {
+                let Some(object_key) = object.key() else {
+                    continue;
+                };
+
+                if let Some(object_key) = object_key.strip_prefix(&self.prefix) {
+                    keys.push(object_key.to_string());
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Returns true if there's at least one object under the given prefix.
+    #[tracing::instrument(name = "object_store_has_objects", skip(self), level = "debug")]
+    pub async fn has_objects(&self, path: &str) -> Result<bool, ObjectStoreError> {
+        let key = self.full_key(path);
+
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => return Ok(!bucket.list(&key, &self.prefix).is_empty()),
+        };
+
+        let response = client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&key)
+            .max_keys(1)
+            .send()
+            .await
+            .change_to_object_store_context()
+            .attach_printable("failed to list objects")
+            .attach_printable_lazy(|| format!("prefix: {key}"))?;
+
+        Ok(!response.contents().is_empty())
+    }
+
+    /// Copy an object, together with its metadata, without downloading it.
+    #[tracing::instrument(name = "object_store_copy", skip(self), level = "debug")]
+    pub async fn copy(
+        &self,
+        from_path: &str,
+        to_path: &str,
+    ) -> Result<PutResult, ObjectStoreError> {
+        let from_key = self.full_key(from_path);
+        let to_key = self.full_key(to_path);
+
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => return bucket.copy(&from_key, &to_key),
+        };
+
+        let response = client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(format!("{}/{}", self.bucket, from_key))
+            .key(&to_key)
+            .send()
+            .await
+            .change_to_object_store_context()
+            .attach_printable("failed to copy object")
+            .attach_printable_lazy(|| format!("from: {from_key}"))
+            .attach_printable_lazy(|| format!("to: {to_key}"))?;
+
+        let etag = response
+            .copy_object_result
+            .and_then(|result| result.e_tag)
+            .ok_or(ObjectStoreError::Metadata)
+            .attach_printable("missing etag")?
+            .into();
+
+        Ok(PutResult { etag })
+    }
+
     #[tracing::instrument(name = "object_store_delete", skip(self, _options), level = "debug")]
     pub async fn delete(
         &self,
@@ -243,7 +720,17 @@ impl ObjectStore {
         _options: DeleteOptions,
     ) -> Result<DeleteResult, ObjectStoreError> {
         let key = self.full_key(path);
-        self.client
+
+        let client = match &self.backend {
+            Backend::S3(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(bucket) => {
+                bucket.delete(&key);
+                return Ok(DeleteResult);
+            }
+        };
+
+        client
             .delete_object()
             .bucket(&self.bucket)
             .key(&key)
@@ -259,6 +746,173 @@ impl ObjectStore {
     fn full_key(&self, path: &str) -> String {
         format!("{}{}", self.prefix, path)
     }
+
+    /// Returns the user metadata together with the metadata needed to read the object back.
+    fn object_metadata(&self, metadata: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut metadata = metadata.clone();
+        metadata.insert(
+            COMPRESSION_METADATA_KEY.to_string(),
+            self.compression.codec.as_metadata().to_string(),
+        );
+        metadata
+    }
+
+    /// Append the checksum to the body and compress it.
+    fn compress_body(&self, body: Bytes, key: &str) -> Result<Bytes, ObjectStoreError> {
+        let size_before = body.len();
+        let compressed = compress(&self.compression, body)?;
+        record_compression_ratio(key, size_before, compressed.len());
+        Ok(compressed)
+    }
+}
+
+fn compress(compression: &CompressionOptions, body: Bytes) -> Result<Bytes, ObjectStoreError> {
+    let checksum = crc32fast::hash(&body);
+    let mut body = BytesMut::from(body);
+    body.put_u32(checksum);
+
+    let compressed = match compression.codec {
+        CompressionCodec::None => body,
+        CompressionCodec::Zstd => {
+            let mut compressed = BytesMut::with_capacity(body.len()).writer();
+            zstd::stream::copy_encode(body.reader(), &mut compressed, compression.level)
+                .change_context(ObjectStoreError::Request)?;
+            compressed.into_inner()
+        }
+    };
+
+    Ok(compressed.freeze())
+}
+
+/// Append the checksum to the body, compress it and send it in parts of `part_size` bytes.
+///
+/// This function blocks. Returns the compressed size.
+fn compress_into_parts(
+    compression: &CompressionOptions,
+    body: Bytes,
+    part_size: usize,
+    parts_tx: mpsc::Sender<Bytes>,
+) -> Result<usize, ObjectStoreError> {
+    let checksum = crc32fast::hash(&body).to_be_bytes();
+    let mut reader = body.reader().chain(checksum.as_slice());
+
+    let mut writer = PartWriter {
+        part: BytesMut::with_capacity(part_size),
+        part_size,
+        size: 0,
+        parts_tx,
+    };
+
+    match compression.codec {
+        CompressionCodec::None => {
+            std::io::copy(&mut reader, &mut writer)
+                .change_context(ObjectStoreError::Request)
+                .attach_printable("failed to split object in parts")?;
+        }
+        CompressionCodec::Zstd => {
+            zstd::stream::copy_encode(reader, &mut writer, compression.level)
+                .change_context(ObjectStoreError::Request)
+                .attach_printable("failed to compress object")?;
+        }
+    }
+
+    writer
+        .finish()
+        .change_context(ObjectStoreError::Request)
+        .attach_printable("failed to send last part")
+}
+
+async fn join_compression_task(
+    task: tokio::task::JoinHandle<Result<usize, ObjectStoreError>>,
+    key: &str,
+    size_before: usize,
+) -> Result<(), ObjectStoreError> {
+    let size_after = task
+        .await
+        .change_context(ObjectStoreError::Request)
+        .attach_printable("compression task failed")??;
+    record_compression_ratio(key, size_before, size_after);
+    Ok(())
+}
+
+/// A writer that sends its data in chunks of `part_size` bytes.
+struct PartWriter {
+    part: BytesMut,
+    part_size: usize,
+    size: usize,
+    parts_tx: mpsc::Sender<Bytes>,
+}
+
+impl PartWriter {
+    fn send_part(&mut self, part: Bytes) -> std::io::Result<()> {
+        self.parts_tx
+            .blocking_send(part)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "upload stopped"))
+    }
+
+    /// Send the last part and return the total size.
+    fn finish(mut self) -> std::io::Result<usize> {
+        if !self.part.is_empty() {
+            let part = self.part.split().freeze();
+            self.send_part(part)?;
+        }
+        Ok(self.size)
+    }
+}
+
+impl std::io::Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.part_size - self.part.len());
+        self.part.put_slice(&buf[..len]);
+        self.size += len;
+
+        if self.part.len() == self.part_size {
+            let part = self.part.split().freeze();
+            self.part.reserve(self.part_size);
+            self.send_part(part)?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn record_compression_ratio(key: &str, size_before: usize, size_after: usize) {
+    let current_span = tracing::Span::current();
+    let compression_ratio = size_before as f64 / size_after as f64;
+
+    current_span.record("key", key);
+    current_span.record("compression_ratio", compression_ratio);
+    debug!(compression_ratio, key, "compressed object");
+}
+
+impl CompressionCodec {
+    fn as_metadata(&self) -> &'static str {
+        match self {
+            CompressionCodec::None => "none",
+            CompressionCodec::Zstd => "zstd",
+        }
+    }
+
+    fn from_metadata(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(CompressionCodec::None),
+            "zstd" => Some(CompressionCodec::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            codec: CompressionCodec::Zstd,
+            level: 0,
+        }
+    }
 }
 
 impl error_stack::Context for ObjectStoreError {}
@@ -308,6 +962,16 @@ impl Default for PutMode {
     }
 }
 
+impl Default for PutMultipartOptions {
+    fn default() -> Self {
+        Self {
+            part_size: 16 * 1024 * 1024,
+            max_concurrent_parts: 4,
+            metadata: HashMap::default(),
+        }
+    }
+}
+
 trait ToObjectStoreResult: Sized {
     type Ok;
 
@@ -339,6 +1003,11 @@ pub mod testing {
     use futures::Future;
     use testcontainers::{core::WaitFor, ContainerAsync, Image};
 
+    #[cfg(any(test, feature = "testing"))]
+    pub(super) use self::memory::MemoryBucket;
+    #[cfg(any(test, feature = "testing"))]
+    pub use self::memory::PutHook;
+
     pub struct MinIO;
 
     pub trait MinIOExt {
@@ -392,4 +1061,274 @@ pub mod testing {
         let config: aws_sdk_s3::Config = (&config).into();
         config.to_builder().force_path_style(true).build()
     }
+
+    #[cfg(any(test, feature = "testing"))]
+    mod memory {
+        use std::{
+            collections::{BTreeMap, HashMap},
+            sync::{Arc, Mutex},
+        };
+
+        use bytes::Bytes;
+        use error_stack::{Result, ResultExt};
+        use futures::future::BoxFuture;
+
+        use crate::object_store::{ObjectETag, ObjectStoreError, PutMode, PutResult, StoredObject};
+
+        /// Runs before each put with the object's key. The put fails if the hook fails.
+        pub type PutHook =
+            Arc<dyn Fn(&str) -> BoxFuture<'static, Result<(), ObjectStoreError>> + Send + Sync>;
+
+        /// The objects of an in-memory object store.
+        ///
+        /// Clones share the same objects.
+        #[derive(Clone, Default)]
+        pub struct MemoryBucket {
+            inner: Arc<Mutex<MemoryBucketInner>>,
+        }
+
+        #[derive(Default)]
+        struct MemoryBucketInner {
+            next_etag: u64,
+            objects: BTreeMap<String, StoredObject>,
+            put_hook: Option<PutHook>,
+        }
+
+        impl MemoryBucket {
+            pub fn set_put_hook(&self, hook: PutHook) {
+                let mut inner = self.inner.lock().expect("memory bucket lock");
+                inner.put_hook = Some(hook);
+            }
+
+            pub async fn before_put(&self, key: &str) -> Result<(), ObjectStoreError> {
+                let hook = {
+                    let inner = self.inner.lock().expect("memory bucket lock");
+                    inner.put_hook.clone()
+                };
+
+                match hook {
+                    Some(hook) => hook(key).await,
+                    None => Ok(()),
+                }
+            }
+
+            pub fn get(
+                &self,
+                key: &str,
+                etag: Option<&ObjectETag>,
+            ) -> Result<StoredObject, ObjectStoreError> {
+                let inner = self.inner.lock().expect("memory bucket lock");
+
+                let object = inner
+                    .objects
+                    .get(key)
+                    .ok_or(ObjectStoreError::NotFound)
+                    .attach_printable("failed to get object")
+                    .attach_printable_lazy(|| format!("key: {key}"))?;
+
+                if let Some(etag) = etag {
+                    if object.etag != *etag {
+                        return Err(ObjectStoreError::Precondition)
+                            .attach_printable("failed to get object")
+                            .attach_printable_lazy(|| format!("key: {key}"));
+                    }
+                }
+
+                Ok(object.clone())
+            }
+
+            pub fn put(
+                &self,
+                key: &str,
+                body: Bytes,
+                metadata: HashMap<String, String>,
+                mode: PutMode,
+            ) -> Result<PutResult, ObjectStoreError> {
+                let mut inner = self.inner.lock().expect("memory bucket lock");
+
+                match (&mode, inner.objects.get(key)) {
+                    (PutMode::Overwrite, _) | (PutMode::Create, None) => {}
+                    (PutMode::Create, Some(_)) => {
+                        return Err(ObjectStoreError::Precondition)
+                            .attach_printable("failed to put object")
+                            .attach_printable_lazy(|| format!("key: {key}"));
+                    }
+                    (PutMode::Update(_), None) => {
+                        return Err(ObjectStoreError::NotFound)
+                            .attach_printable("failed to put object")
+                            .attach_printable_lazy(|| format!("key: {key}"));
+                    }
+                    (PutMode::Update(etag), Some(existing)) => {
+                        if existing.etag != *etag {
+                            return Err(ObjectStoreError::Precondition)
+                                .attach_printable("failed to put object")
+                                .attach_printable_lazy(|| format!("key: {key}"));
+                        }
+                    }
+                }
+
+                let etag = inner.next_etag();
+                inner.objects.insert(
+                    key.to_string(),
+                    StoredObject {
+                        body,
+                        etag: etag.clone(),
+                        metadata,
+                    },
+                );
+
+                Ok(PutResult { etag })
+            }
+
+            /// Returns the keys under `key`, relative to `prefix`.
+            pub fn list(&self, key: &str, prefix: &str) -> Vec<String> {
+                let inner = self.inner.lock().expect("memory bucket lock");
+
+                inner
+                    .objects
+                    .keys()
+                    .filter(|object_key| object_key.starts_with(key))
+                    .filter_map(|object_key| object_key.strip_prefix(prefix))
+                    .map(|object_key| object_key.to_string())
+                    .collect()
+            }
+
+            pub fn copy(
+                &self,
+                from_key: &str,
+                to_key: &str,
+            ) -> Result<PutResult, ObjectStoreError> {
+                let mut inner = self.inner.lock().expect("memory bucket lock");
+
+                let mut object = inner
+                    .objects
+                    .get(from_key)
+                    .cloned()
+                    .ok_or(ObjectStoreError::NotFound)
+                    .attach_printable("failed to copy object")
+                    .attach_printable_lazy(|| format!("from: {from_key}"))
+                    .attach_printable_lazy(|| format!("to: {to_key}"))?;
+
+                let etag = inner.next_etag();
+                object.etag = etag.clone();
+                inner.objects.insert(to_key.to_string(), object);
+
+                Ok(PutResult { etag })
+            }
+
+            pub fn delete(&self, key: &str) {
+                let mut inner = self.inner.lock().expect("memory bucket lock");
+                inner.objects.remove(key);
+            }
+        }
+
+        impl MemoryBucketInner {
+            fn next_etag(&mut self) -> ObjectETag {
+                self.next_etag += 1;
+                ObjectETag(format!("\"{:x}\"", self.next_etag))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Buf, BufMut, Bytes, BytesMut};
+    use tokio::sync::mpsc;
+
+    use super::{
+        compress_into_parts, CompressionCodec, CompressionOptions, GetOptions, ObjectStore,
+        ObjectStoreOptions, ObjectStoreResultExt, PutMode, PutOptions,
+    };
+
+    fn compressed_parts(codec: CompressionCodec, body: &Bytes, part_size: usize) -> Vec<Bytes> {
+        let compression = CompressionOptions { codec, level: 0 };
+        let (tx, mut rx) = mpsc::channel(1024);
+        let size = compress_into_parts(&compression, body.clone(), part_size, tx).unwrap();
+
+        let mut parts = Vec::new();
+        while let Ok(part) = rx.try_recv() {
+            parts.push(part);
+        }
+        assert_eq!(parts.iter().map(Bytes::len).sum::<usize>(), size);
+        parts
+    }
+
+    #[test]
+    fn test_compress_into_parts() {
+        let body = Bytes::from((0..1000u32).map(|i| (i % 7) as u8).collect::<Vec<_>>());
+
+        let mut expected = BytesMut::from(body.clone());
+        expected.put_u32(crc32fast::hash(&body));
+
+        let parts = compressed_parts(CompressionCodec::None, &body, 256);
+        assert_eq!(
+            parts.iter().map(Bytes::len).collect::<Vec<_>>(),
+            vec![256, 256, 256, 236]
+        );
+        assert_eq!(parts.concat(), expected.to_vec());
+
+        let parts = compressed_parts(CompressionCodec::Zstd, &body, 16);
+        assert!(parts.len() > 1);
+        assert!(parts[..parts.len() - 1].iter().all(|part| part.len() == 16));
+
+        let compressed = Bytes::from(parts.concat());
+        let mut decompressed = Vec::new();
+        zstd::stream::copy_decode(compressed.reader(), &mut decompressed).unwrap();
+        assert_eq!(decompressed, expected.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_object_store() {
+        let store = ObjectStore::new_in_memory(ObjectStoreOptions {
+            prefix: Some("test".to_string()),
+            ..Default::default()
+        });
+
+        let body = Bytes::from_static(b"hello world");
+        let created = store
+            .put(
+                "blocks/1",
+                body.clone(),
+                PutOptions {
+                    mode: PutMode::Create,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let object = store.get("blocks/1", GetOptions::default()).await.unwrap();
+        assert_eq!(object.body, body);
+        assert_eq!(object.etag, created.etag);
+
+        let err = store
+            .put(
+                "blocks/1",
+                body.clone(),
+                PutOptions {
+                    mode: PutMode::Create,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(err.is_precondition());
+
+        store.copy("blocks/1", "blocks/2").await.unwrap();
+        assert_eq!(
+            store.list("blocks/").await.unwrap(),
+            vec!["blocks/1".to_string(), "blocks/2".to_string()]
+        );
+
+        let other = store.with_prefix("other");
+        assert!(other.list("").await.unwrap().is_empty());
+
+        store.delete("blocks/1", Default::default()).await.unwrap();
+        let err = store
+            .get("blocks/1", GetOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
 }
@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+#[cfg(any(test, feature = "testing"))]
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
 use apibara_etcd::{EtcdClient, KvClient};
 use error_stack::{Result, ResultExt};
 
@@ -5,19 +12,44 @@ pub static OPTIONS_PREFIX_KEY: &str = "options/";
 pub static CHAIN_SEGMENT_SIZE_KEY: &str = "options/chain_segment_size";
 pub static SEGMENT_SIZE_KEY: &str = "options/segment_size";
 pub static GROUP_SIZE_KEY: &str = "options/group_size";
+pub static FRAGMENT_SEGMENT_SIZE_PREFIX_KEY: &str = "options/fragment_segment_size/";
+pub static SEGMENT_GENERATION_KEY: &str = "options/segment_generation";
 
 #[derive(Debug)]
 pub struct OptionsStoreError;
 
 /// A client to get and set DNA options.
+#[derive(Clone)]
 pub struct OptionsStore {
-    client: KvClient,
+    backend: OptionsBackend,
+}
+
+/// Where the options are stored.
+#[derive(Clone)]
+enum OptionsBackend {
+    Etcd(KvClient),
+    #[cfg(any(test, feature = "testing"))]
+    Memory(Arc<Mutex<BTreeMap<String, String>>>),
 }
 
+// The in-memory backend only exists in tests, so most matches have a single arm.
+#[allow(clippy::infallible_destructuring_match)]
 impl OptionsStore {
     pub fn new(client: &EtcdClient) -> Self {
         let client = client.kv_client();
-        Self { client }
+        Self {
+            backend: OptionsBackend::Etcd(client),
+        }
+    }
+
+    /// Returns a store that keeps the options in memory, for tests.
+    ///
+    /// Clones of the store share the same options.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new_in_memory() -> Self {
+        Self {
+            backend: OptionsBackend::Memory(Arc::default()),
+        }
     }
 
     pub async fn set_chain_segment_size(&mut self, size: usize) -> Result<(), OptionsStoreError> {
@@ -56,9 +88,152 @@ impl OptionsStore {
             .attach_printable("failed to get group size")
     }
 
+    /// Set the segment size of the fragment with the given name.
+    pub async fn set_fragment_segment_size(
+        &mut self,
+        name: &str,
+        size: usize,
+    ) -> Result<(), OptionsStoreError> {
+        let key = format!("{FRAGMENT_SEGMENT_SIZE_PREFIX_KEY}{name}");
+        self.set_usize(&key, size)
+            .await
+            .attach_printable("failed to set fragment segment size")
+            .attach_printable_lazy(|| format!("fragment: {name}"))
+    }
+
+    /// Remove the segment size of the fragment, so that it uses the default segment size.
+    pub async fn delete_fragment_segment_size(
+        &mut self,
+        name: &str,
+    ) -> Result<(), OptionsStoreError> {
+        let key = format!("{FRAGMENT_SEGMENT_SIZE_PREFIX_KEY}{name}");
+        let client = match &mut self.backend {
+            OptionsBackend::Etcd(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            OptionsBackend::Memory(options) => {
+                options.lock().expect("memory options lock").remove(&key);
+                return Ok(());
+            }
+        };
+
+        client
+            .delete(&key)
+            .await
+            .change_context(OptionsStoreError)
+            .attach_printable("failed to delete fragment segment size")
+            .attach_printable_lazy(|| format!("fragment: {name}"))?;
+
+        Ok(())
+    }
+
+    /// Returns the segment size of the fragments that don't use the default segment size.
+    pub async fn get_fragment_segment_sizes(
+        &mut self,
+    ) -> Result<HashMap<String, usize>, OptionsStoreError> {
+        let mut sizes = HashMap::new();
+
+        for (key, value) in self.get_prefix(FRAGMENT_SEGMENT_SIZE_PREFIX_KEY).await? {
+            let Some((_, name)) = key.split_once(FRAGMENT_SEGMENT_SIZE_PREFIX_KEY) else {
+                return Err(OptionsStoreError)
+                    .attach_printable("invalid fragment segment size key")
+                    .attach_printable_lazy(|| format!("key: {key}"));
+            };
+
+            let size = parse_usize(&value)?;
+            sizes.insert(name.to_string(), size);
+        }
+
+        Ok(sizes)
+    }
+
+    /// Returns the generation of the segments and groups.
+    ///
+    /// The generation changes each time the segments are rewritten with a new layout.
+    pub async fn get_segment_generation(&mut self) -> Result<u64, OptionsStoreError> {
+        let generation = self
+            .get_usize(SEGMENT_GENERATION_KEY)
+            .await
+            .attach_printable("failed to get segment generation")?;
+        Ok(generation.unwrap_or_default() as u64)
+    }
+
+    pub async fn set_segment_generation(
+        &mut self,
+        generation: u64,
+    ) -> Result<(), OptionsStoreError> {
+        self.set_usize(SEGMENT_GENERATION_KEY, generation as usize)
+            .await
+            .attach_printable("failed to set segment generation")
+    }
+
+    /// Switch to a new generation of segments with the given layout.
+    ///
+    /// All options are updated in a single transaction, so readers see either the
+    /// old or the new layout.
+    pub async fn swap_segment_layout(
+        &mut self,
+        generation: u64,
+        segment_size: usize,
+        group_size: usize,
+        fragment_segment_size: &HashMap<String, usize>,
+    ) -> Result<(), OptionsStoreError> {
+        let deletes = self
+            .get_fragment_segment_sizes()
+            .await?
+            .into_keys()
+            .filter(|name| !fragment_segment_size.contains_key(name))
+            .map(|name| format!("{FRAGMENT_SEGMENT_SIZE_PREFIX_KEY}{name}"))
+            .collect::<Vec<_>>();
+
+        let mut puts = vec![
+            (SEGMENT_SIZE_KEY.to_string(), segment_size.to_string()),
+            (GROUP_SIZE_KEY.to_string(), group_size.to_string()),
+            (SEGMENT_GENERATION_KEY.to_string(), generation.to_string()),
+        ];
+        puts.extend(fragment_segment_size.iter().map(|(name, size)| {
+            (
+                format!("{FRAGMENT_SEGMENT_SIZE_PREFIX_KEY}{name}"),
+                size.to_string(),
+            )
+        }));
+
+        let client = match &mut self.backend {
+            OptionsBackend::Etcd(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            OptionsBackend::Memory(options) => {
+                let mut options = options.lock().expect("memory options lock");
+                for key in deletes.iter() {
+                    options.remove(key);
+                }
+                options.extend(puts);
+                return Ok(());
+            }
+        };
+
+        client
+            .put_and_delete_many(&puts, &deletes)
+            .await
+            .change_context(OptionsStoreError)
+            .attach_printable("failed to swap segment layout")
+            .attach_printable_lazy(|| format!("generation: {generation}"))?;
+
+        Ok(())
+    }
+
     async fn set_usize(&mut self, key: &str, size: usize) -> Result<(), OptionsStoreError> {
         let size = size.to_string();
-        self.client
+
+        let client = match &mut self.backend {
+            OptionsBackend::Etcd(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            OptionsBackend::Memory(options) => {
+                let mut options = options.lock().expect("memory options lock");
+                options.insert(key.to_string(), size);
+                return Ok(());
+            }
+        };
+
+        client
             .put(key, size.as_bytes())
             .await
             .change_context(OptionsStoreError)
@@ -68,8 +243,19 @@ impl OptionsStore {
     }
 
     async fn get_usize(&mut self, key: &str) -> Result<Option<usize>, OptionsStoreError> {
-        let response = self
-            .client
+        let client = match &mut self.backend {
+            OptionsBackend::Etcd(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            OptionsBackend::Memory(options) => {
+                let options = options.lock().expect("memory options lock");
+                return options
+                    .get(key)
+                    .map(|value| parse_usize(value.as_bytes()))
+                    .transpose();
+            }
+        };
+
+        let response = client
             .get(key)
             .await
             .change_context(OptionsStoreError)
@@ -79,20 +265,60 @@ impl OptionsStore {
             return Ok(None);
         };
 
-        let size = String::from_utf8(kv.value().to_vec())
-            .change_context(OptionsStoreError)
-            .attach_printable("failed to decode size")?;
+        let size = parse_usize(kv.value())?;
 
-        let size = size
-            .parse::<usize>()
+        Ok(size.into())
+    }
+
+    /// Returns the keys and values under the given prefix.
+    async fn get_prefix(
+        &mut self,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, OptionsStoreError> {
+        let client = match &mut self.backend {
+            OptionsBackend::Etcd(client) => client,
+            #[cfg(any(test, feature = "testing"))]
+            OptionsBackend::Memory(options) => {
+                let options = options.lock().expect("memory options lock");
+                return Ok(options
+                    .range(prefix.to_string()..)
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .map(|(key, value)| (key.clone(), value.as_bytes().to_vec()))
+                    .collect());
+            }
+        };
+
+        let response = client
+            .get_prefix(prefix)
+            .await
             .change_context(OptionsStoreError)
-            .attach_printable("failed to parse size")
-            .attach_printable_lazy(|| format!("size: {}", size))?;
+            .attach_printable("failed to get options")
+            .attach_printable_lazy(|| format!("prefix: {prefix}"))?;
 
-        Ok(size.into())
+        response
+            .kvs()
+            .iter()
+            .map(|kv| {
+                let key = String::from_utf8(kv.key().to_vec())
+                    .change_context(OptionsStoreError)
+                    .attach_printable("failed to decode option key")?;
+                Ok((key, kv.value().to_vec()))
+            })
+            .collect()
     }
 }
 
+fn parse_usize(value: &[u8]) -> Result<usize, OptionsStoreError> {
+    let size = String::from_utf8(value.to_vec())
+        .change_context(OptionsStoreError)
+        .attach_printable("failed to decode size")?;
+
+    size.parse::<usize>()
+        .change_context(OptionsStoreError)
+        .attach_printable("failed to parse size")
+        .attach_printable_lazy(|| format!("size: {}", size))
+}
+
 impl error_stack::Context for OptionsStoreError {}
 
 impl std::fmt::Display for OptionsStoreError {
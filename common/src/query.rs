@@ -1,6 +1,9 @@
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+};
 
-use error_stack::Result;
+use error_stack::{Result, ResultExt};
 use roaring::RoaringBitmap;
 use tracing::trace;
 
@@ -24,7 +27,21 @@ pub struct Condition {
     /// The index to filter on.
     pub index_id: IndexId,
     /// The value to filter on.
-    pub key: ScalarValue,
+    pub key: ConditionKey,
+}
+
+/// The values matched by a condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionKey {
+    /// Match items with exactly this value.
+    Eq(ScalarValue),
+    /// Match items with any of these values.
+    AnyOf(Vec<ScalarValue>),
+    /// Match items with a value between `start` and `end`, inclusive.
+    Range {
+        start: ScalarValue,
+        end: ScalarValue,
+    },
 }
 
 /// A single filter.
@@ -33,6 +50,9 @@ pub struct Filter {
     /// The filter id.
     pub filter_id: FilterId,
     /// The fragment to filter.
+    ///
+    /// Filters on the header fragment select blocks: the header of the blocks they
+    /// match is sent, like with [HeaderFilter::OnData].
     pub fragment_id: FragmentId,
     /// The conditions to filter on.
     ///
@@ -40,13 +60,27 @@ pub struct Filter {
     pub conditions: Vec<Condition>,
     /// Join results from this filter with the given fragments.
     pub joins: Vec<FragmentId>,
+    /// Only send these fields of the matched messages. Sends all fields if `None`.
+    ///
+    /// Messages included by joins are always sent with all fields.
+    pub fields: Option<FieldProjection>,
 }
 
+/// The top-level fields of a message sent to the client, by field number.
+///
+/// Used to omit the fields that the client doesn't need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldProjection(BTreeSet<u32>);
+
 /// A collection of filters.
 #[derive(Debug, Clone, Default)]
 pub struct BlockFilter {
     pub header_filter: HeaderFilter,
+    /// Only send these header fields. Sends all fields if `None`.
+    pub header_fields: Option<FieldProjection>,
     filters: BTreeMap<FragmentId, Vec<Filter>>,
+    /// The fields of each fragment sent for each filter id. `None` to send all fields.
+    field_projections: HashMap<(FragmentId, FilterId), Option<FieldProjection>>,
 }
 
 impl BlockFilter {
@@ -62,8 +96,28 @@ impl BlockFilter {
         self.header_filter = value;
     }
 
+    pub fn set_header_fields(&mut self, value: FieldProjection) {
+        self.header_fields = Some(value);
+    }
+
     /// Add a filter to the block filter.
     pub fn add_filter(&mut self, filter: Filter) {
+        let key = (filter.fragment_id, filter.filter_id);
+        let fields = match (self.field_projections.remove(&key), &filter.fields) {
+            (None, fields) => fields.clone(),
+            (Some(Some(mut existing)), Some(fields)) => {
+                existing.0.extend(fields.0.iter());
+                Some(existing)
+            }
+            _ => None,
+        };
+        self.field_projections.insert(key, fields);
+
+        for fragment_id in filter.joins.iter() {
+            self.field_projections
+                .insert((*fragment_id, filter.filter_id), None);
+        }
+
         self.filters
             .entry(filter.fragment_id)
             .or_default()
@@ -83,6 +137,60 @@ impl BlockFilter {
         self.filters.len()
     }
 
+    /// Returns the number of conditions evaluated by the filters.
+    ///
+    /// A condition matching any of a set of values counts once for each value.
+    /// Filters without conditions, and block filters without filters (for example
+    /// header-only filters), count as one condition.
+    pub fn condition_count(&self) -> usize {
+        self.filters
+            .values()
+            .flatten()
+            .map(|filter| {
+                filter
+                    .conditions
+                    .iter()
+                    .map(|condition| match &condition.key {
+                        ConditionKey::AnyOf(keys) => keys.len().max(1),
+                        ConditionKey::Eq(_) | ConditionKey::Range { .. } => 1,
+                    })
+                    .sum::<usize>()
+                    .max(1)
+            })
+            .sum::<usize>()
+            .max(1)
+    }
+
+    /// Returns the fields to send for a message of the fragment matched by the given filters.
+    ///
+    /// A message matched by more than one filter is sent with the union of their fields.
+    /// Returns `None` to send all fields, that is if any of the filters has no field
+    /// projection or joins its data with the fragment.
+    pub fn field_projection(
+        &self,
+        fragment_id: FragmentId,
+        filter_ids: &[FilterId],
+    ) -> Option<Cow<'_, FieldProjection>> {
+        let mut projection: Option<Cow<'_, FieldProjection>> = None;
+
+        for filter_id in filter_ids {
+            let Some(fields) = self.field_projections.get(&(fragment_id, *filter_id)) else {
+                continue;
+            };
+            let fields = fields.as_ref()?;
+
+            projection = Some(match projection {
+                None => Cow::Borrowed(fields),
+                Some(mut projection) => {
+                    projection.to_mut().0.extend(fields.0.iter());
+                    projection
+                }
+            });
+        }
+
+        projection
+    }
+
     /// Returns all fragment id needed by this filter.
     pub fn all_fragment_ids(&self) -> HashSet<FragmentId> {
         let mut out = HashSet::default();
@@ -105,19 +213,32 @@ impl Filter {
     pub fn filter(&self, indexes: &ArchivedIndexFragment) -> Result<RoaringBitmap, FilterError> {
         let range_start = indexes.range_start.to_native();
         let range_len = indexes.range_len.to_native();
-        let mut result = RoaringBitmap::from_iter(range_start..(range_start + range_len));
+        let mut result = RoaringBitmap::new();
+        result.insert_range(range_start..(range_start + range_len));
         trace!(starting = ?result, "starting bitmap");
 
         for cond in self.conditions.iter() {
-            let cond_index = indexes
-                .indexes
-                .get(cond.index_id as usize)
-                .ok_or(FilterError)?;
+            // Data ingested before the index was introduced doesn't have it. Matching
+            // nothing would silently skip data, so the blocks must be ingested again.
+            let Some(cond_index) = indexes.indexes.get(cond.index_id as usize) else {
+                return Err(FilterError)
+                    .attach_printable(
+                        "index missing from stored data. re-ingest the blocks to filter by it",
+                    )
+                    .attach_printable_lazy(|| format!("fragment id: {}", self.fragment_id))
+                    .attach_printable_lazy(|| format!("index id: {}", cond.index_id));
+            };
 
             match &cond_index.index {
                 index::ArchivedIndex::Empty => {}
                 index::ArchivedIndex::Bitmap(bitmap) => {
-                    if let Some(bitmap) = bitmap.get(&cond.key) {
+                    let matches = match &cond.key {
+                        ConditionKey::Eq(key) => bitmap.get(key),
+                        ConditionKey::AnyOf(keys) => bitmap.get_any(keys),
+                        ConditionKey::Range { start, end } => bitmap.get_range(start, end),
+                    };
+
+                    if let Some(bitmap) = matches {
                         result &= bitmap;
                         trace!(result = ?result, "bitmap match");
                     } else {
@@ -133,6 +254,46 @@ impl Filter {
     }
 }
 
+impl FieldProjection {
+    pub fn new(fields: impl IntoIterator<Item = u32>) -> Self {
+        Self(fields.into_iter().collect())
+    }
+
+    /// Returns the encoded message with only the projected fields.
+    ///
+    /// Returns the message unchanged if it cannot be decoded.
+    pub fn project(&self, message: &[u8]) -> Vec<u8> {
+        self.try_project(message)
+            .unwrap_or_else(|_| message.to_vec())
+    }
+
+    fn try_project(&self, message: &[u8]) -> std::result::Result<Vec<u8>, prost::DecodeError> {
+        use prost::encoding::{decode_key, skip_field, DecodeContext};
+
+        let mut output = Vec::with_capacity(message.len());
+        let mut buf = message;
+
+        while !buf.is_empty() {
+            let start = message.len() - buf.len();
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            skip_field(wire_type, tag, &mut buf, DecodeContext::default())?;
+            let end = message.len() - buf.len();
+
+            if self.0.contains(&tag) {
+                output.extend_from_slice(&message[start..end]);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl From<ScalarValue> for ConditionKey {
+    fn from(value: ScalarValue) -> Self {
+        ConditionKey::Eq(value)
+    }
+}
+
 impl error_stack::Context for FilterError {}
 
 impl std::fmt::Display for FilterError {
@@ -146,3 +307,256 @@ impl Default for HeaderFilter {
         Self::OnDataOrOnNewBlock
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use crate::{
+        fragment::{ArchivedIndexFragment, FragmentId, Index, IndexFragment},
+        index::{BitmapIndexBuilder, ScalarValue},
+    };
+
+    use super::{BlockFilter, Condition, ConditionKey, FieldProjection, Filter, FilterId};
+
+    fn projection(
+        block_filter: &BlockFilter,
+        fragment_id: FragmentId,
+        filter_ids: &[FilterId],
+    ) -> Option<FieldProjection> {
+        block_filter
+            .field_projection(fragment_id, filter_ids)
+            .map(Cow::into_owned)
+    }
+
+    fn filter_rows(indexes: &ArchivedIndexFragment, key: ConditionKey) -> Vec<u32> {
+        let filter = Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![Condition { index_id: 0, key }],
+            joins: Vec::default(),
+            fields: None,
+        };
+
+        filter.filter(indexes).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_filter_condition_keys() {
+        let mut builder = BitmapIndexBuilder::default();
+        for (row, value) in [10, 11, 12, 20, 11].into_iter().enumerate() {
+            builder.insert(ScalarValue::Uint32(value), row as u32);
+        }
+
+        let fragment = IndexFragment {
+            fragment_id: 2,
+            range_start: 0,
+            range_len: 5,
+            indexes: vec![Index {
+                index_id: 0,
+                index: builder.build().unwrap().into(),
+            }],
+        };
+
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&fragment).unwrap();
+        let indexes = rkyv::access::<ArchivedIndexFragment, rkyv::rancor::Error>(&bytes).unwrap();
+
+        assert_eq!(
+            filter_rows(indexes, ScalarValue::Uint32(11).into()),
+            vec![1, 4]
+        );
+        assert!(filter_rows(indexes, ScalarValue::Uint32(13).into()).is_empty());
+
+        assert_eq!(
+            filter_rows(
+                indexes,
+                ConditionKey::AnyOf(vec![
+                    ScalarValue::Uint32(10),
+                    ScalarValue::Uint32(13),
+                    ScalarValue::Uint32(20),
+                ])
+            ),
+            vec![0, 3]
+        );
+
+        // More keys than the index has.
+        assert_eq!(
+            filter_rows(
+                indexes,
+                ConditionKey::AnyOf((0..100).step_by(10).map(ScalarValue::Uint32).collect())
+            ),
+            vec![0, 3]
+        );
+
+        assert_eq!(
+            filter_rows(
+                indexes,
+                ConditionKey::Range {
+                    start: ScalarValue::Uint32(11),
+                    end: ScalarValue::Uint32(20),
+                }
+            ),
+            vec![1, 2, 3, 4]
+        );
+        assert!(filter_rows(
+            indexes,
+            ConditionKey::Range {
+                start: ScalarValue::Uint32(13),
+                end: ScalarValue::Uint32(19),
+            }
+        )
+        .is_empty());
+
+        // Conditions on indexes missing from the fragment fail.
+        let missing_index = Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![Condition {
+                index_id: 1,
+                key: ScalarValue::Uint32(11).into(),
+            }],
+            joins: Vec::default(),
+            fields: None,
+        };
+        assert!(missing_index.filter(indexes).is_err());
+    }
+
+    #[test]
+    fn test_block_filter_condition_count() {
+        let mut block_filter = BlockFilter::default();
+        assert_eq!(block_filter.condition_count(), 1);
+
+        block_filter.add_filter(Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: Vec::default(),
+            joins: Vec::default(),
+            fields: None,
+        });
+        block_filter.add_filter(Filter {
+            filter_id: 1,
+            fragment_id: 3,
+            conditions: vec![
+                Condition {
+                    index_id: 0,
+                    key: ScalarValue::Uint32(1).into(),
+                },
+                Condition {
+                    index_id: 1,
+                    key: ConditionKey::AnyOf(vec![ScalarValue::Uint32(1), ScalarValue::Uint32(2)]),
+                },
+            ],
+            joins: Vec::default(),
+            fields: None,
+        });
+
+        assert_eq!(block_filter.condition_count(), 4);
+
+        let mut block_filter = BlockFilter::default();
+        block_filter.add_filter(Filter {
+            filter_id: 0,
+            fragment_id: 2,
+            conditions: vec![Condition {
+                index_id: 0,
+                key: ConditionKey::AnyOf((0..100_000).map(ScalarValue::Uint32).collect()),
+            }],
+            joins: Vec::default(),
+            fields: None,
+        });
+
+        assert_eq!(block_filter.condition_count(), 100_000);
+    }
+
+    #[test]
+    fn test_field_projection() {
+        use prost::encoding::{bytes, string, uint64};
+
+        let mut message = Vec::new();
+        uint64::encode(1, &100, &mut message);
+        bytes::encode(2, &vec![1u8; 32], &mut message);
+        string::encode(14, &"extra".to_string(), &mut message);
+
+        let mut expected = Vec::new();
+        uint64::encode(1, &100, &mut expected);
+        string::encode(14, &"extra".to_string(), &mut expected);
+
+        let projection = FieldProjection::new([1, 14, 20]);
+        assert_eq!(projection.project(&message), expected);
+
+        assert!(FieldProjection::new([3]).project(&message).is_empty());
+
+        // Messages that can't be decoded are sent as they are.
+        let invalid = vec![0xff];
+        assert_eq!(projection.project(&invalid), invalid);
+    }
+
+    #[test]
+    fn test_block_filter_field_projection() {
+        let mut block_filter = BlockFilter::default();
+        block_filter.add_filter(Filter {
+            filter_id: 1,
+            fragment_id: 2,
+            conditions: Vec::default(),
+            joins: vec![3],
+            fields: Some(FieldProjection::new([1, 2])),
+        });
+        block_filter.add_filter(Filter {
+            filter_id: 2,
+            fragment_id: 2,
+            conditions: Vec::default(),
+            joins: Vec::default(),
+            fields: Some(FieldProjection::new([2, 3])),
+        });
+        block_filter.add_filter(Filter {
+            filter_id: 3,
+            fragment_id: 3,
+            conditions: Vec::default(),
+            joins: Vec::default(),
+            fields: None,
+        });
+
+        assert_eq!(
+            projection(&block_filter, 2, &[1]),
+            Some(FieldProjection::new([1, 2]))
+        );
+        assert!(matches!(
+            block_filter.field_projection(2, &[1]),
+            Some(Cow::Borrowed(_))
+        ));
+        assert_eq!(
+            projection(&block_filter, 2, &[1, 2]),
+            Some(FieldProjection::new([1, 2, 3]))
+        );
+        // Filters without projection and joins send all fields.
+        assert_eq!(projection(&block_filter, 3, &[3]), None);
+        assert_eq!(projection(&block_filter, 3, &[1]), None);
+        assert_eq!(projection(&block_filter, 3, &[1, 3]), None);
+        assert_eq!(projection(&block_filter, 4, &[1]), None);
+
+        // Filters with the same id are merged.
+        block_filter.add_filter(Filter {
+            filter_id: 2,
+            fragment_id: 2,
+            conditions: Vec::default(),
+            joins: Vec::default(),
+            fields: Some(FieldProjection::new([4])),
+        });
+        assert_eq!(
+            projection(&block_filter, 2, &[2]),
+            Some(FieldProjection::new([2, 3, 4]))
+        );
+
+        block_filter.add_filter(Filter {
+            filter_id: 2,
+            fragment_id: 4,
+            conditions: Vec::default(),
+            joins: vec![2],
+            fields: Some(FieldProjection::new([1])),
+        });
+        assert_eq!(projection(&block_filter, 2, &[2]), None);
+        assert_eq!(
+            projection(&block_filter, 4, &[2]),
+            Some(FieldProjection::new([1]))
+        );
+    }
+}
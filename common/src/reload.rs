@@ -0,0 +1,119 @@
+//! Reload settings on SIGHUP, without restarting the server.
+use error_stack::{Result, ResultExt};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    cli::LogArgs,
+    config_file::{reparse_args, ConfigFile},
+    ingestion::IngestionArgs,
+    server::ServerArgs,
+};
+
+/// Settings that can change while the server is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeSettings {
+    /// Filter for the logs printed to stdout.
+    pub log_filter: String,
+    /// Maximum number of concurrent streams.
+    pub max_concurrent_streams: usize,
+    /// Maximum size of the messages buffered by each new stream, in bytes.
+    pub stream_memory_budget: usize,
+    /// Maximum number of RPC requests per second.
+    pub rpc_rate_limit: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct ReloadError;
+
+impl RuntimeSettings {
+    pub fn new(
+        log: &LogArgs,
+        server: &ServerArgs,
+        ingestion: &IngestionArgs,
+    ) -> Result<Self, ReloadError> {
+        let server_options = server
+            .to_server_options()
+            .change_context(ReloadError)
+            .attach_printable("invalid server options")?;
+
+        Ok(Self {
+            log_filter: log.log_filter.clone(),
+            max_concurrent_streams: server_options.stream_service_options.max_concurrent_streams,
+            stream_memory_budget: server_options.stream_service_options.stream_memory_budget,
+            rpc_rate_limit: ingestion.rpc_rate_limit,
+        })
+    }
+
+    /// Read the settings again from the configuration file, the environment, and
+    /// the command line.
+    pub fn reload() -> Result<Self, ReloadError> {
+        let config_file = ConfigFile::load()
+            .change_context(ReloadError)
+            .attach_printable("failed to reload configuration file")?;
+        let config_file = config_file.as_ref();
+
+        let log = reparse_args::<LogArgs>(config_file).change_context(ReloadError)?;
+        let server = reparse_args::<ServerArgs>(config_file).change_context(ReloadError)?;
+        let ingestion = reparse_args::<IngestionArgs>(config_file).change_context(ReloadError)?;
+
+        Self::new(&log, &server, &ingestion)
+    }
+}
+
+/// Reload the runtime settings every time the process receives SIGHUP.
+///
+/// Invalid settings are logged and ignored, the server keeps the previous ones.
+pub async fn reload_settings_loop(
+    settings: watch::Sender<RuntimeSettings>,
+    ct: CancellationToken,
+) -> Result<(), ReloadError> {
+    let mut hangup = signal(SignalKind::hangup())
+        .change_context(ReloadError)
+        .attach_printable("failed to register SIGHUP handler")?;
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => break,
+            signal = hangup.recv() => {
+                if signal.is_none() {
+                    break;
+                }
+            }
+        }
+
+        info!("SIGHUP received, reloading settings");
+
+        let new_settings = match RuntimeSettings::reload() {
+            Ok(new_settings) => new_settings,
+            Err(err) => {
+                warn!(error = ?err, "failed to reload settings");
+                continue;
+            }
+        };
+
+        if new_settings.log_filter != settings.borrow().log_filter {
+            if let Err(err) = apibara_observability::set_log_filter(&new_settings.log_filter) {
+                warn!(error = ?err, "failed to change log filter");
+            }
+        }
+
+        info!(settings = ?new_settings, "settings reloaded");
+
+        settings.send_replace(new_settings);
+    }
+
+    Ok(())
+}
+
+impl error_stack::Context for ReloadError {}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to reload settings")
+    }
+}
@@ -0,0 +1,867 @@
+//! Rewrite the stored segments with a new segment size or compression.
+//!
+//! The new segments and groups are written as a new generation, next to the
+//! current ones. Once they are all written, the segment options are switched to
+//! the new generation and layout in a single transaction. If the command fails
+//! before the switch, the deployment keeps using the current generation.
+//!
+//! The command holds the compaction lock while it runs. DNA servers reload the
+//! segment options when the grouped block is updated after the switch, and restart
+//! the streams reading the previous generation. The next run deletes the previous
+//! generation, together with the objects written by runs that failed.
+use std::collections::HashMap;
+
+use apibara_etcd::{EtcdClient, Lock, LockOptions};
+use clap::Args;
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    block_store::{
+        segment_generation_prefix, BlockStoreWriter, UncachedBlockStoreReader, GENERATION_PREFIX,
+    },
+    cli::{EtcdArgs, ObjectStoreArgs},
+    compaction::{
+        parse_fragment_segment_size, validate_fragment_segment_size, CompactionServiceOptions,
+        SegmentBuilder, SegmentGroupBuilder,
+    },
+    dataset_manifest::DatasetManifestStore,
+    fragment::{
+        Block, BodyFragment, HeaderFragment, IndexGroupFragment, JoinGroupFragment,
+        HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_NAME, JOIN_FRAGMENT_NAME,
+    },
+    ingestion::IngestionStateClient,
+    object_store::{DeleteOptions, ObjectStore},
+    options_store::OptionsStore,
+    segment::{FragmentData, Segment},
+    Cursor,
+};
+
+/// Object store prefixes of the segments and groups, relative to the generation prefix.
+const SEGMENT_PREFIXES: &[&str] = &["segment/", "group/"];
+
+#[derive(Debug)]
+pub struct ResegmentError;
+
+#[derive(Args, Debug)]
+pub struct ResegmentCommand {
+    #[clap(flatten)]
+    object_store: ObjectStoreArgs,
+    #[clap(flatten)]
+    etcd: EtcdArgs,
+    /// The new segment size. Defaults to the current segment size.
+    #[arg(long)]
+    segment_size: Option<usize>,
+    /// The new group size. Defaults to the current group size.
+    #[arg(long)]
+    group_size: Option<usize>,
+    /// The new segment size of some fragments, as `name=size` pairs.
+    ///
+    /// Fragments not listed use the segment size.
+    #[arg(long, value_delimiter = ',', value_parser = parse_fragment_segment_size)]
+    fragment_segment_size: Vec<(String, usize)>,
+    /// Number of objects written or deleted concurrently.
+    #[arg(long, default_value = "16")]
+    concurrency: usize,
+}
+
+/// How segments and groups are laid out in the object store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentLayout {
+    pub segment_size: usize,
+    pub group_size: usize,
+    pub fragment_segment_size: HashMap<String, usize>,
+}
+
+impl ResegmentCommand {
+    pub async fn run(self) -> Result<(), ResegmentError> {
+        let object_store = self.object_store.into_object_store_client().await;
+        let etcd_client = self
+            .etcd
+            .into_etcd_client()
+            .await
+            .change_context(ResegmentError)?;
+
+        resegment(
+            object_store,
+            etcd_client,
+            self.segment_size,
+            self.group_size,
+            self.fragment_segment_size.into_iter().collect(),
+            self.concurrency,
+        )
+        .await
+    }
+}
+
+/// Rewrite all segments and groups with the new layout.
+///
+/// The segment and group sizes default to the current ones. The objects are
+/// compressed with the object store's compression options.
+pub async fn resegment(
+    object_store: ObjectStore,
+    etcd_client: EtcdClient,
+    segment_size: Option<usize>,
+    group_size: Option<usize>,
+    fragment_segment_size: HashMap<String, usize>,
+    concurrency: usize,
+) -> Result<(), ResegmentError> {
+    let mut lock_client = etcd_client.lock_client(LockOptions::default());
+
+    info!("acquiring compaction lock");
+
+    let Some(mut lock) = lock_client
+        .lock("compaction/lock", CancellationToken::new())
+        .await
+        .change_context(ResegmentError)
+        .attach_printable("failed to acquire compaction lock")?
+    else {
+        return Err(ResegmentError).attach_printable("failed to acquire compaction lock");
+    };
+
+    let result = resegment_with_lock(
+        object_store,
+        OptionsStore::new(&etcd_client),
+        IngestionStateClient::new(&etcd_client),
+        segment_size,
+        group_size,
+        fragment_segment_size,
+        concurrency,
+        Some(&mut lock),
+    )
+    .await;
+
+    lock_client
+        .unlock(lock)
+        .await
+        .change_context(ResegmentError)
+        .attach_printable("failed to release compaction lock")?;
+
+    result
+}
+
+/// Rewrite the segments, keeping the lock alive if there's one.
+#[allow(clippy::too_many_arguments)]
+async fn resegment_with_lock(
+    object_store: ObjectStore,
+    mut options_store: OptionsStore,
+    mut state_client: IngestionStateClient,
+    segment_size: Option<usize>,
+    group_size: Option<usize>,
+    fragment_segment_size: HashMap<String, usize>,
+    concurrency: usize,
+    lock: Option<&mut Lock>,
+) -> Result<(), ResegmentError> {
+    let current = get_current_layout(&mut options_store).await?;
+    let current_generation = options_store
+        .get_segment_generation()
+        .await
+        .change_context(ResegmentError)?;
+
+    let layout = SegmentLayout {
+        segment_size: segment_size.unwrap_or(current.segment_size),
+        group_size: group_size.unwrap_or(current.group_size),
+        fragment_segment_size,
+    };
+
+    validate_fragment_segment_size(&CompactionServiceOptions {
+        segment_size: layout.segment_size,
+        group_size: layout.group_size,
+        fragment_segment_size: layout.fragment_segment_size.clone(),
+        ..Default::default()
+    })
+    .change_context(ResegmentError)
+    .attach_printable("invalid segment layout")?;
+
+    if layout.segment_size == 0 || layout.group_size == 0 {
+        return Err(ResegmentError)
+            .attach_printable("segment and group size must be positive")
+            .attach_printable_lazy(|| format!("layout: {layout:?}"));
+    }
+
+    let starting_block = state_client
+        .get_starting_block()
+        .await
+        .change_context(ResegmentError)?
+        .ok_or(ResegmentError)
+        .attach_printable("starting block not found")?;
+
+    let Some(segmented) = state_client
+        .get_segmented()
+        .await
+        .change_context(ResegmentError)?
+    else {
+        return Err(ResegmentError).attach_printable("no segments to rewrite");
+    };
+
+    let segmented_blocks = segmented + 1 - starting_block;
+    if segmented_blocks % layout.segment_size as u64 != 0 {
+        return Err(ResegmentError)
+            .attach_printable("segmented blocks must be a multiple of the new segment size")
+            .attach_printable_lazy(|| format!("segmented blocks: {segmented_blocks}"))
+            .attach_printable_lazy(|| format!("new segment size: {}", layout.segment_size));
+    }
+
+    let generation = current_generation + 1;
+
+    info!(
+        starting_block,
+        segmented,
+        current = ?current,
+        new = ?layout,
+        generation,
+        "rewriting segments"
+    );
+
+    delete_stale_generations(&object_store, current_generation, concurrency).await?;
+
+    let grouped = write_segments(
+        &object_store,
+        current_generation,
+        generation,
+        &current,
+        &layout,
+        starting_block,
+        segmented,
+        concurrency,
+        lock,
+    )
+    .await?;
+
+    // Servers read the grouped block from the state, so it must only reference groups
+    // that exist in both generations while switching.
+    let current_grouped = state_client
+        .get_grouped()
+        .await
+        .change_context(ResegmentError)?;
+    if grouped < current_grouped {
+        put_grouped(&mut state_client, grouped).await?;
+    }
+
+    options_store
+        .swap_segment_layout(
+            generation,
+            layout.segment_size,
+            layout.group_size,
+            &layout.fragment_segment_size,
+        )
+        .await
+        .change_context(ResegmentError)
+        .attach_printable("failed to switch to the new segment generation")?;
+
+    put_grouped(&mut state_client, grouped).await?;
+
+    DatasetManifestStore::new(object_store.clone())
+        .update(|manifest| manifest.grouped = grouped)
+        .await
+        .change_context(ResegmentError)?;
+
+    info!(
+        generation,
+        grouped = ?grouped,
+        "segments rewritten. DNA servers switch to the new layout"
+    );
+
+    Ok(())
+}
+
+/// Write the segments and groups of the current generation with the new layout.
+///
+/// Returns the last block in a group.
+#[allow(clippy::too_many_arguments)]
+async fn write_segments(
+    object_store: &ObjectStore,
+    current_generation: u64,
+    generation: u64,
+    current: &SegmentLayout,
+    layout: &SegmentLayout,
+    starting_block: u64,
+    segmented: u64,
+    concurrency: usize,
+    mut lock: Option<&mut Lock>,
+) -> Result<Option<u64>, ResegmentError> {
+    let reader = UncachedBlockStoreReader::new(object_store.clone())
+        .with_segment_generation(current_generation);
+    let writer = BlockStoreWriter::new(object_store.clone()).with_segment_generation(generation);
+
+    let body_names = reader
+        .list_segment_names(&Cursor::new_finalized(starting_block))
+        .await
+        .change_context(ResegmentError)?
+        .into_iter()
+        .filter(|name| {
+            name != INDEX_FRAGMENT_NAME
+                && name != JOIN_FRAGMENT_NAME
+                && name != HEADER_FRAGMENT_NAME
+        })
+        .collect::<Vec<_>>();
+
+    let mut indexes = FragmentReader::new(
+        INDEX_FRAGMENT_NAME,
+        current,
+        starting_block,
+        rkyv::from_bytes::<Segment<IndexGroupFragment>, rkyv::rancor::Error>,
+    );
+    let mut joins = FragmentReader::new(
+        JOIN_FRAGMENT_NAME,
+        current,
+        starting_block,
+        rkyv::from_bytes::<Segment<JoinGroupFragment>, rkyv::rancor::Error>,
+    );
+    let mut headers = FragmentReader::new(
+        HEADER_FRAGMENT_NAME,
+        current,
+        starting_block,
+        rkyv::from_bytes::<Segment<HeaderFragment>, rkyv::rancor::Error>,
+    );
+    let mut bodies = body_names
+        .iter()
+        .map(|name| {
+            FragmentReader::new(
+                name,
+                current,
+                starting_block,
+                rkyv::from_bytes::<Segment<BodyFragment>, rkyv::rancor::Error>,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let segment_size = layout.segment_size as u64;
+    let mut group_builder = SegmentGroupBuilder::new(layout.segment_size);
+    let mut grouped = None;
+
+    for segment_start in (starting_block..=segmented).step_by(layout.segment_size) {
+        if let Some(lock) = lock.as_deref_mut() {
+            lock.keep_alive().await.change_context(ResegmentError)?;
+        }
+
+        let mut builder = SegmentBuilder::default();
+
+        for block_number in segment_start..segment_start + segment_size {
+            let index = indexes.next(&reader, block_number).await?;
+            let join = joins.next(&reader, block_number).await?;
+            let header = headers.next(&reader, block_number).await?;
+
+            let mut body = Vec::with_capacity(bodies.len());
+            for fragment in bodies.iter_mut() {
+                body.push(fragment.next(&reader, block_number).await?.data);
+            }
+
+            if block_number == segment_start {
+                builder
+                    .start_new_segment(index.cursor.clone())
+                    .change_context(ResegmentError)?;
+            }
+
+            let block = Block {
+                header: header.data,
+                index: index.data,
+                join: join.data,
+                body,
+            };
+
+            builder
+                .add_decoded_block(&index.cursor, block)
+                .change_context(ResegmentError)
+                .attach_printable_lazy(|| format!("block number: {block_number}"))?;
+        }
+
+        let fragment_segment_size = layout.fragment_segment_size.clone();
+        let segment_data =
+            tokio::task::spawn_blocking(move || builder.segment_data(&fragment_segment_size))
+                .await
+                .change_context(ResegmentError)
+                .attach_printable("segment builder task failed")?
+                .change_context(ResegmentError)?;
+
+        for segment in segment_data.iter() {
+            if segment.name != INDEX_FRAGMENT_NAME {
+                continue;
+            }
+
+            let index_segment =
+                rkyv::from_bytes::<Segment<IndexGroupFragment>, rkyv::rancor::Error>(&segment.data)
+                    .change_context(ResegmentError)?;
+
+            group_builder
+                .add_segment(&index_segment)
+                .change_context(ResegmentError)?;
+        }
+
+        futures::stream::iter(segment_data)
+            .map(|segment| writer.put_segment(segment))
+            .buffer_unordered(concurrency.max(1))
+            .try_collect::<Vec<_>>()
+            .await
+            .change_context(ResegmentError)
+            .attach_printable("failed to put segment")?;
+
+        if group_builder.segment_count == layout.group_size {
+            let builder = std::mem::replace(
+                &mut group_builder,
+                SegmentGroupBuilder::new(layout.segment_size),
+            );
+            let group = builder.build().change_context(ResegmentError)?;
+
+            writer
+                .put_group(&group.first_block, &group)
+                .await
+                .change_context(ResegmentError)
+                .attach_printable("failed to put group")?;
+
+            grouped = Some(segment_start + segment_size - 1);
+        }
+
+        info!(
+            first_block = segment_start,
+            last_block = segment_start + segment_size - 1,
+            "rewrote segment"
+        );
+    }
+
+    Ok(grouped)
+}
+
+async fn put_grouped(
+    state_client: &mut IngestionStateClient,
+    grouped: Option<u64>,
+) -> Result<(), ResegmentError> {
+    match grouped {
+        Some(grouped) => state_client.put_grouped(grouped).await,
+        None => state_client.delete_grouped().await,
+    }
+    .change_context(ResegmentError)
+}
+
+/// Delete the segments and groups that don't belong to the current generation.
+///
+/// These are the generation replaced by the previous run and the objects written
+/// by runs that failed before switching generation.
+async fn delete_stale_generations(
+    object_store: &ObjectStore,
+    current_generation: u64,
+    concurrency: usize,
+) -> Result<(), ResegmentError> {
+    let current_prefix = segment_generation_prefix(current_generation);
+    let generations_prefix = format!("{GENERATION_PREFIX}/");
+
+    let mut stale = object_store
+        .list(&generations_prefix)
+        .await
+        .change_context(ResegmentError)
+        .attach_printable("failed to list segment generations")?
+        .into_iter()
+        .filter(|key| current_generation == 0 || !key.starts_with(&current_prefix))
+        .collect::<Vec<_>>();
+
+    if current_generation != 0 {
+        stale.extend(list_objects(object_store, "").await?);
+    }
+
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    info!(objects = stale.len(), "deleting stale segment generations");
+
+    delete_objects(object_store, &stale, concurrency).await
+}
+
+/// Reads a fragment block by block from the existing segments.
+struct FragmentReader<T> {
+    name: String,
+    starting_block: u64,
+    segment_size: u64,
+    decode: fn(&[u8]) -> std::result::Result<Segment<T>, rkyv::rancor::Error>,
+    current: std::vec::IntoIter<FragmentData<T>>,
+}
+
+impl<T> FragmentReader<T> {
+    fn new(
+        name: &str,
+        layout: &SegmentLayout,
+        starting_block: u64,
+        decode: fn(&[u8]) -> std::result::Result<Segment<T>, rkyv::rancor::Error>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            starting_block,
+            segment_size: layout.fragment_segment_size(name) as u64,
+            decode,
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns the fragment data of the given block.
+    ///
+    /// Blocks must be read in order.
+    async fn next(
+        &mut self,
+        reader: &UncachedBlockStoreReader,
+        block_number: u64,
+    ) -> Result<FragmentData<T>, ResegmentError> {
+        if self.current.len() == 0 {
+            if (block_number - self.starting_block) % self.segment_size != 0 {
+                return Err(ResegmentError)
+                    .attach_printable("segment ended before the expected block")
+                    .attach_printable_lazy(|| format!("fragment: {}", self.name))
+                    .attach_printable_lazy(|| format!("block number: {block_number}"));
+            }
+
+            let bytes = reader
+                .get_segment(&Cursor::new_finalized(block_number), &self.name)
+                .await
+                .change_context(ResegmentError)?;
+
+            let segment = (self.decode)(&bytes)
+                .change_context(ResegmentError)
+                .attach_printable("failed to deserialize segment")
+                .attach_printable_lazy(|| format!("fragment: {}", self.name))
+                .attach_printable_lazy(|| format!("block number: {block_number}"))?;
+
+            self.current = segment.data.into_iter();
+        }
+
+        let data = self
+            .current
+            .next()
+            .ok_or(ResegmentError)
+            .attach_printable("segment is empty")
+            .attach_printable_lazy(|| format!("fragment: {}", self.name))?;
+
+        if data.cursor.number != block_number {
+            return Err(ResegmentError)
+                .attach_printable("segment block does not match the expected block")
+                .attach_printable_lazy(|| format!("fragment: {}", self.name))
+                .attach_printable_lazy(|| format!("expected: {block_number}"))
+                .attach_printable_lazy(|| format!("actual: {}", data.cursor));
+        }
+
+        Ok(data)
+    }
+}
+
+impl SegmentLayout {
+    /// Returns the number of blocks in the fragment's segments.
+    pub fn fragment_segment_size(&self, name: &str) -> usize {
+        self.fragment_segment_size
+            .get(name)
+            .copied()
+            .unwrap_or(self.segment_size)
+    }
+}
+
+async fn get_current_layout(
+    options_store: &mut OptionsStore,
+) -> Result<SegmentLayout, ResegmentError> {
+    let segment_size = options_store
+        .get_segment_size()
+        .await
+        .change_context(ResegmentError)?
+        .ok_or(ResegmentError)
+        .attach_printable("segment size option not found")?;
+    let group_size = options_store
+        .get_group_size()
+        .await
+        .change_context(ResegmentError)?
+        .ok_or(ResegmentError)
+        .attach_printable("group size option not found")?;
+    let fragment_segment_size = options_store
+        .get_fragment_segment_sizes()
+        .await
+        .change_context(ResegmentError)?;
+
+    Ok(SegmentLayout {
+        segment_size,
+        group_size,
+        fragment_segment_size,
+    })
+}
+
+/// List the segments and groups under the given generation prefix.
+async fn list_objects(
+    object_store: &ObjectStore,
+    generation_prefix: &str,
+) -> Result<Vec<String>, ResegmentError> {
+    let mut keys = Vec::new();
+    for prefix in SEGMENT_PREFIXES {
+        let prefix = format!("{generation_prefix}{prefix}");
+        let prefix_keys = object_store
+            .list(&prefix)
+            .await
+            .change_context(ResegmentError)
+            .attach_printable("failed to list objects")
+            .attach_printable_lazy(|| format!("prefix: {prefix}"))?;
+        keys.extend(prefix_keys);
+    }
+
+    Ok(keys)
+}
+
+async fn delete_objects(
+    object_store: &ObjectStore,
+    keys: &[String],
+    concurrency: usize,
+) -> Result<(), ResegmentError> {
+    futures::stream::iter(keys.iter())
+        .map(|key| async move {
+            object_store
+                .delete(key, DeleteOptions::default())
+                .await
+                .change_context(ResegmentError)
+                .attach_printable("failed to delete object")
+                .attach_printable_lazy(|| format!("key: {key}"))
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(())
+}
+
+impl error_stack::Context for ResegmentError {}
+
+impl std::fmt::Display for ResegmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "resegment error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+
+    use crate::{
+        block_store::{segment_generation_prefix, BlockStoreWriter, UncachedBlockStoreReader},
+        compaction::{SegmentBuilder, SegmentGroupBuilder},
+        fragment::{
+            Block, BodyFragment, HeaderFragment, IndexGroupFragment, JoinGroupFragment,
+            HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_NAME,
+        },
+        ingestion::IngestionStateClient,
+        object_store::{GetOptions, ObjectStore, ObjectStoreResultExt, PutOptions},
+        options_store::OptionsStore,
+        segment::Segment,
+        Cursor,
+    };
+
+    use super::resegment_with_lock;
+
+    fn new_block(number: u64) -> Block {
+        Block {
+            header: HeaderFragment {
+                data: vec![number as u8],
+            },
+            index: IndexGroupFragment {
+                indexes: Vec::default(),
+            },
+            join: JoinGroupFragment {
+                joins: Vec::default(),
+            },
+            body: vec![BodyFragment {
+                fragment_id: 2,
+                name: "transaction".to_string(),
+                data: vec![vec![number as u8]],
+            }],
+        }
+    }
+
+    /// A deployment with blocks 0 to 7, in segments of 2 blocks and groups of 2 segments.
+    async fn init_deployment() -> (ObjectStore, OptionsStore, IngestionStateClient) {
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let mut options_store = OptionsStore::new_in_memory();
+        let mut state_client = IngestionStateClient::new_in_memory();
+        let writer = BlockStoreWriter::new(object_store.clone());
+
+        let mut group_builder = SegmentGroupBuilder::new(2);
+        for segment_start in (0..8).step_by(2) {
+            let mut builder = SegmentBuilder::default();
+            builder
+                .start_new_segment(Cursor::new_finalized(segment_start))
+                .unwrap();
+            for block_number in segment_start..segment_start + 2 {
+                builder
+                    .add_decoded_block(
+                        &Cursor::new_finalized(block_number),
+                        new_block(block_number),
+                    )
+                    .unwrap();
+            }
+
+            for segment in builder.segment_data(&HashMap::default()).unwrap() {
+                if segment.name == INDEX_FRAGMENT_NAME {
+                    let index =
+                        rkyv::from_bytes::<Segment<IndexGroupFragment>, rkyv::rancor::Error>(
+                            &segment.data,
+                        )
+                        .unwrap();
+                    group_builder.add_segment(&index).unwrap();
+                }
+                writer.put_segment(segment).await.unwrap();
+            }
+
+            if group_builder.segment_count == 2 {
+                let builder = std::mem::replace(&mut group_builder, SegmentGroupBuilder::new(2));
+                let group = builder.build().unwrap();
+                writer.put_group(&group.first_block, &group).await.unwrap();
+            }
+        }
+
+        options_store.set_segment_size(2).await.unwrap();
+        options_store.set_group_size(2).await.unwrap();
+        state_client.put_starting_block(0).await.unwrap();
+        state_client.put_segmented(7).await.unwrap();
+        state_client.put_grouped(7).await.unwrap();
+
+        (object_store, options_store, state_client)
+    }
+
+    async fn exists(object_store: &ObjectStore, key: &str) -> bool {
+        match object_store.get(key, GetOptions::default()).await {
+            Ok(_) => true,
+            Err(err) if err.is_not_found() => false,
+            Err(err) => panic!("failed to get object: {err:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resegment_switches_generation() {
+        let (object_store, mut options_store, mut state_client) = init_deployment().await;
+
+        resegment_with_lock(
+            object_store.clone(),
+            options_store.clone(),
+            state_client.clone(),
+            Some(4),
+            None,
+            HashMap::default(),
+            4,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(options_store.get_segment_generation().await.unwrap(), 1);
+        assert_eq!(options_store.get_segment_size().await.unwrap(), Some(4));
+        assert_eq!(options_store.get_group_size().await.unwrap(), Some(2));
+        assert_eq!(state_client.get_grouped().await.unwrap(), Some(7));
+
+        let reader = UncachedBlockStoreReader::new(object_store.clone()).with_segment_generation(1);
+        let headers = reader
+            .get_segment(&Cursor::new_finalized(4), HEADER_FRAGMENT_NAME)
+            .await
+            .unwrap();
+        let headers =
+            rkyv::from_bytes::<Segment<HeaderFragment>, rkyv::rancor::Error>(&headers).unwrap();
+        let blocks = headers
+            .data
+            .iter()
+            .map(|header| header.data.data[0])
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec![4, 5, 6, 7]);
+
+        reader
+            .get_segment(&Cursor::new_finalized(0), "transaction")
+            .await
+            .unwrap();
+        reader.get_group(&Cursor::new_finalized(0)).await.unwrap();
+
+        // Streams that started before the switch keep reading the previous generation.
+        let previous = UncachedBlockStoreReader::new(object_store.clone());
+        previous
+            .get_segment(&Cursor::new_finalized(2), HEADER_FRAGMENT_NAME)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resegment_deletes_stale_generations() {
+        let (object_store, mut options_store, state_client) = init_deployment().await;
+
+        // Left by a run that failed before switching generation.
+        let failed_run_key = format!("{}segment/0000000100/header", segment_generation_prefix(1));
+        object_store
+            .put(
+                &failed_run_key,
+                Bytes::from_static(b"partial"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(options_store.get_segment_generation().await.unwrap(), 0);
+
+        resegment_with_lock(
+            object_store.clone(),
+            options_store.clone(),
+            state_client.clone(),
+            Some(4),
+            Some(1),
+            HashMap::default(),
+            4,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(!exists(&object_store, &failed_run_key).await);
+        assert!(exists(&object_store, "segment/0000000000/header").await);
+
+        resegment_with_lock(
+            object_store.clone(),
+            options_store.clone(),
+            state_client.clone(),
+            Some(8),
+            Some(1),
+            HashMap::default(),
+            4,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(options_store.get_segment_generation().await.unwrap(), 2);
+        assert!(!exists(&object_store, "segment/0000000000/header").await);
+        assert!(!exists(&object_store, "group/0000000000/index").await);
+        assert!(
+            exists(
+                &object_store,
+                &format!("{}segment/0000000004/header", segment_generation_prefix(1))
+            )
+            .await
+        );
+        assert!(
+            exists(
+                &object_store,
+                &format!("{}segment/0000000000/header", segment_generation_prefix(2))
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resegment_lowers_grouped_block() {
+        let (object_store, mut options_store, mut state_client) = init_deployment().await;
+
+        // Groups of 3 segments of 2 blocks only cover blocks 0 to 5.
+        resegment_with_lock(
+            object_store.clone(),
+            options_store.clone(),
+            state_client.clone(),
+            None,
+            Some(3),
+            HashMap::default(),
+            4,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(options_store.get_segment_generation().await.unwrap(), 1);
+        assert_eq!(options_store.get_group_size().await.unwrap(), Some(3));
+        assert_eq!(state_client.get_grouped().await.unwrap(), Some(5));
+    }
+}
@@ -0,0 +1,677 @@
+//! Failover and load balancing across multiple RPC endpoints.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use apibara_observability::{Counter, KeyValue, RecordRequest, RequestMetrics};
+use error_stack::{Report, Result, ResultExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::ingestion::RpcRateLimiter;
+
+#[derive(Debug)]
+pub struct RpcPoolError;
+
+#[derive(Debug, Clone)]
+pub struct RpcPoolOptions {
+    /// Distribute requests across healthy endpoints based on their weight.
+    ///
+    /// If false, requests are sent to the first healthy endpoint.
+    pub load_balance: bool,
+    /// How long an endpoint is considered unhealthy after a failed request.
+    pub unhealthy_cooldown: Duration,
+    /// How often to probe the endpoints' health in the background.
+    ///
+    /// If `None`, endpoints are only marked unhealthy when a request fails.
+    pub health_check_interval: Option<Duration>,
+}
+
+/// An RPC endpoint in the pool.
+#[derive(Debug, Clone)]
+pub struct RpcEndpoint<P> {
+    /// Name used in logs and metrics, usually the endpoint's host.
+    pub name: String,
+    pub client: P,
+    /// Relative weight used when load balancing.
+    pub weight: u32,
+}
+
+/// What to do after a request fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failover {
+    /// Mark the endpoint unhealthy and try the next one.
+    MarkUnhealthy,
+    /// Try the next endpoint, without marking this one unhealthy.
+    ///
+    /// Used for errors like missing blocks, where the endpoint may only be lagging.
+    TryNext,
+    /// Return the error.
+    Return,
+}
+
+/// A pool of RPC clients that fails over to the next endpoint on error.
+///
+/// Endpoints that fail a request are marked unhealthy and only used as a last
+/// resort until their cooldown expires.
+#[derive(Clone)]
+pub struct RpcPool<P> {
+    endpoints: Arc<Vec<RpcEndpoint<P>>>,
+    state: Arc<Mutex<Vec<EndpointState>>>,
+    options: RpcPoolOptions,
+    metrics: RpcPoolMetrics,
+    rate_limiter: RpcRateLimiter,
+    /// The endpoint tried first by a pinned pool.
+    pinned: Option<Arc<AtomicUsize>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct EndpointState {
+    /// Used by the smooth weighted round-robin.
+    current_weight: i64,
+    unhealthy_until: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct RpcPoolMetrics {
+    failover: Counter<u64>,
+    request: RequestMetrics,
+}
+
+impl<P> RpcPool<P>
+where
+    P: Clone,
+{
+    pub fn new(
+        endpoints: Vec<RpcEndpoint<P>>,
+        options: RpcPoolOptions,
+    ) -> Result<Self, RpcPoolError> {
+        if endpoints.is_empty() {
+            return Err(RpcPoolError).attach_printable("no RPC endpoints configured");
+        }
+
+        if let Some(endpoint) = endpoints.iter().find(|endpoint| endpoint.weight == 0) {
+            return Err(RpcPoolError)
+                .attach_printable("RPC endpoint weight must be positive")
+                .attach_printable_lazy(|| format!("endpoint: {}", endpoint.name));
+        }
+
+        let state = vec![EndpointState::default(); endpoints.len()];
+
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            state: Arc::new(Mutex::new(state)),
+            options,
+            metrics: RpcPoolMetrics::default(),
+            rate_limiter: RpcRateLimiter::default(),
+            pinned: None,
+        })
+    }
+
+    /// Limit the requests sent to the endpoints.
+    ///
+    /// Every request counts against the limit, including retries on other endpoints.
+    pub fn with_rate_limiter(mut self, rate_limiter: RpcRateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Returns a pool that sends all its requests to the same endpoint.
+    ///
+    /// Use it for requests that must be served by the same node, for example the
+    /// different parts of a block. If the endpoint fails, the pool fails over as usual
+    /// and sends the following requests to the endpoint that succeeded.
+    pub fn pinned(&self) -> Self {
+        let endpoint = self.candidates()[0];
+
+        Self {
+            pinned: Some(Arc::new(AtomicUsize::new(endpoint))),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the number of endpoints in the pool.
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// Call `f` with the endpoints' clients until one of them succeeds.
+    ///
+    /// Errors for which `should_failover` returns false (for example, "not found"
+    /// errors) are returned immediately.
+    /// Requests are recorded in the metrics with the `method` and endpoint name.
+    pub async fn call<T, E, F, Fut>(
+        &self,
+        method: &'static str,
+        f: F,
+        should_failover: impl Fn(&Report<E>) -> bool,
+    ) -> Result<T, E>
+    where
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.call_with_failover(method, f, |err| {
+            if should_failover(err) {
+                Failover::MarkUnhealthy
+            } else {
+                Failover::Return
+            }
+        })
+        .await
+    }
+
+    /// Call `f` with the endpoints' clients until one of them succeeds.
+    ///
+    /// `failover` decides what to do after each failed request.
+    pub async fn call_with_failover<T, E, F, Fut>(
+        &self,
+        method: &'static str,
+        f: F,
+        failover: impl Fn(&Report<E>) -> Failover,
+    ) -> Result<T, E>
+    where
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let candidates = self.candidates();
+        let mut last_error = None;
+
+        for index in candidates {
+            let endpoint = &self.endpoints[index];
+
+            let attributes = [
+                KeyValue::new("method", method),
+                KeyValue::new("endpoint", endpoint.name.clone()),
+            ];
+
+            let permit = self.rate_limiter.acquire(method).await;
+            let result = f(endpoint.client.clone())
+                .record_request_with_attributes(self.metrics.request.clone(), &attributes)
+                .await;
+            drop(permit);
+
+            let err = match result {
+                Ok(value) => {
+                    self.mark_healthy(index);
+                    if let Some(pinned) = self.pinned.as_ref() {
+                        pinned.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(value);
+                }
+                Err(err) => err,
+            };
+
+            match failover(&err) {
+                Failover::MarkUnhealthy => {
+                    warn!(
+                        endpoint = %endpoint.name,
+                        method,
+                        error = ?err,
+                        "RPC request failed. trying next endpoint"
+                    );
+                    self.mark_unhealthy(index);
+                    self.metrics.failover.add(1, &attributes);
+                }
+                Failover::TryNext => {
+                    debug!(
+                        endpoint = %endpoint.name,
+                        method,
+                        error = ?err,
+                        "RPC request failed. trying next endpoint"
+                    );
+                    self.metrics.failover.add(1, &attributes);
+                }
+                Failover::Return => return Err(err),
+            }
+
+            last_error = Some(err);
+        }
+
+        Err(last_error.expect("rpc pool has at least one endpoint"))
+    }
+
+    /// Call `f` with the clients of all endpoints concurrently.
+    ///
+    /// Returns each endpoint's name with its result, in the order of the endpoints.
+    /// Used to compare the responses of different endpoints.
+    pub async fn call_all<T, E, F, Fut>(
+        &self,
+        method: &'static str,
+        f: F,
+    ) -> Vec<(String, Result<T, E>)>
+    where
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let f = &f;
+        let requests = self.endpoints.iter().map(|endpoint| async move {
+            let attributes = [
+                KeyValue::new("method", method),
+                KeyValue::new("endpoint", endpoint.name.clone()),
+            ];
+
+            let _permit = self.rate_limiter.acquire(method).await;
+            let result = f(endpoint.client.clone())
+                .record_request_with_attributes(self.metrics.request.clone(), &attributes)
+                .await;
+
+            (endpoint.name.clone(), result)
+        });
+
+        futures::future::join_all(requests).await
+    }
+
+    /// Probe the endpoints with `probe` every `health_check_interval`, until cancelled.
+    ///
+    /// Endpoints that fail the probe are marked unhealthy before a request fails on
+    /// them. Unhealthy endpoints that pass it are used again without waiting for
+    /// their cooldown to expire.
+    pub async fn health_check_loop<T, E, F, Fut>(self, probe: F, ct: CancellationToken)
+    where
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let Some(interval) = self.options.health_check_interval else {
+            return;
+        };
+
+        loop {
+            tokio::select! {
+                _ = ct.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            self.check_health(&probe).await;
+        }
+    }
+
+    /// Probe all endpoints once and update their health.
+    async fn check_health<T, E, F, Fut>(&self, probe: &F)
+    where
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let results = self.call_all("health_check", probe).await;
+
+        for (index, (name, result)) in results.into_iter().enumerate() {
+            let was_healthy = self.is_healthy(index);
+            match result {
+                Ok(_) => {
+                    self.mark_healthy(index);
+                    if !was_healthy {
+                        info!(endpoint = %name, "RPC endpoint passed health check");
+                    }
+                }
+                Err(err) => {
+                    self.mark_unhealthy(index);
+                    if was_healthy {
+                        warn!(endpoint = %name, error = ?err, "RPC endpoint failed health check");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the endpoints' indices in the order they should be tried.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("rpc pool state lock");
+
+        let (mut healthy, unhealthy): (Vec<usize>, Vec<usize>) = (0..self.endpoints.len())
+            .partition(|index| {
+                state[*index]
+                    .unhealthy_until
+                    .map(|until| until <= now)
+                    .unwrap_or(true)
+            });
+
+        if self.options.load_balance && self.pinned.is_none() && healthy.len() > 1 {
+            // Smooth weighted round-robin, as implemented by nginx.
+            let total_weight = healthy
+                .iter()
+                .map(|index| self.endpoints[*index].weight as i64)
+                .sum::<i64>();
+
+            let mut selected = healthy[0];
+            for index in healthy.iter() {
+                state[*index].current_weight += self.endpoints[*index].weight as i64;
+                if state[*index].current_weight > state[selected].current_weight {
+                    selected = *index;
+                }
+            }
+
+            state[selected].current_weight -= total_weight;
+
+            healthy.retain(|index| *index != selected);
+            healthy.insert(0, selected);
+        }
+
+        healthy.extend(unhealthy);
+
+        if let Some(pinned) = self.pinned.as_ref() {
+            let pinned = pinned.load(Ordering::Relaxed);
+            healthy.retain(|index| *index != pinned);
+            healthy.insert(0, pinned);
+        }
+
+        healthy
+    }
+
+    fn is_healthy(&self, index: usize) -> bool {
+        let state = self.state.lock().expect("rpc pool state lock");
+        state[index]
+            .unhealthy_until
+            .map(|until| until <= Instant::now())
+            .unwrap_or(true)
+    }
+
+    fn mark_healthy(&self, index: usize) {
+        let mut state = self.state.lock().expect("rpc pool state lock");
+        state[index].unhealthy_until = None;
+    }
+
+    fn mark_unhealthy(&self, index: usize) {
+        let mut state = self.state.lock().expect("rpc pool state lock");
+        state[index].unhealthy_until = Some(Instant::now() + self.options.unhealthy_cooldown);
+    }
+}
+
+/// Pair each URL with its weight.
+///
+/// URLs without a weight default to a weight of 1.
+pub fn weighted_urls<'a>(
+    urls: &'a [String],
+    weights: &[u32],
+) -> Result<Vec<(&'a str, u32)>, RpcPoolError> {
+    if weights.len() > urls.len() {
+        return Err(RpcPoolError)
+            .attach_printable("more RPC weights than URLs")
+            .attach_printable_lazy(|| format!("urls: {}", urls.len()))
+            .attach_printable_lazy(|| format!("weights: {}", weights.len()));
+    }
+
+    let weighted = urls
+        .iter()
+        .enumerate()
+        .map(|(index, url)| (url.as_str(), weights.get(index).copied().unwrap_or(1)))
+        .collect();
+
+    Ok(weighted)
+}
+
+impl Default for RpcPoolOptions {
+    fn default() -> Self {
+        Self {
+            load_balance: false,
+            unhealthy_cooldown: Duration::from_secs(30),
+            health_check_interval: None,
+        }
+    }
+}
+
+impl Default for RpcPoolMetrics {
+    fn default() -> Self {
+        let meter = apibara_observability::meter("dna_rpc_pool");
+
+        Self {
+            failover: meter
+                .u64_counter("dna.rpc_pool.failover")
+                .with_description("requests that failed over to another endpoint")
+                .build(),
+            request: RequestMetrics::new("dna_rpc_pool", "dna.rpc_pool.request"),
+        }
+    }
+}
+
+impl error_stack::Context for RpcPoolError {}
+
+impl std::fmt::Display for RpcPoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rpc pool error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use error_stack::Report;
+
+    use crate::ingestion::RpcRateLimiter;
+
+    use super::{weighted_urls, Failover, RpcEndpoint, RpcPool, RpcPoolError, RpcPoolOptions};
+
+    fn new_pool(weights: &[u32], load_balance: bool) -> RpcPool<usize> {
+        let endpoints = weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| RpcEndpoint {
+                name: format!("endpoint-{index}"),
+                client: index,
+                weight: *weight,
+            })
+            .collect();
+
+        let options = RpcPoolOptions {
+            load_balance,
+            ..Default::default()
+        };
+
+        RpcPool::new(endpoints, options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_endpoint() {
+        let pool = new_pool(&[1, 1], false);
+
+        let result = pool
+            .call(
+                "test",
+                |client| async move {
+                    if client == 0 {
+                        Err(Report::new(RpcPoolError))
+                    } else {
+                        Ok(client)
+                    }
+                },
+                |_| true,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, 1);
+
+        // The first endpoint is now unhealthy and tried last.
+        assert_eq!(pool.candidates(), vec![1, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_returns_error() {
+        let pool = new_pool(&[1, 1], false);
+
+        let result = pool
+            .call(
+                "test",
+                |client| async move {
+                    if client == 0 {
+                        Err(Report::new(RpcPoolError))
+                    } else {
+                        Ok(client)
+                    }
+                },
+                |_| false,
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(pool.candidates(), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_try_next_keeps_endpoint_healthy() {
+        let pool = new_pool(&[1, 1], false);
+
+        let result = pool
+            .call_with_failover(
+                "test",
+                |client| async move {
+                    if client == 0 {
+                        Err(Report::new(RpcPoolError))
+                    } else {
+                        Ok(client)
+                    }
+                },
+                |_| Failover::TryNext,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, 1);
+        assert_eq!(pool.candidates(), vec![0, 1]);
+
+        // All endpoints fail.
+        let result = pool
+            .call_with_failover(
+                "test",
+                |_| async move { Err::<usize, _>(Report::new(RpcPoolError)) },
+                |_| Failover::TryNext,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_pool() {
+        let pool = new_pool(&[1, 1, 1], true);
+
+        let pinned = pool.pinned();
+        let first = pinned.candidates()[0];
+        for _ in 0..4 {
+            assert_eq!(pinned.candidates()[0], first);
+        }
+
+        // After a failover, the pinned pool sticks to the endpoint that succeeded.
+        let result = pinned
+            .call(
+                "test",
+                |client| async move {
+                    if client == first {
+                        Err(Report::new(RpcPoolError))
+                    } else {
+                        Ok(client)
+                    }
+                },
+                |_| true,
+            )
+            .await
+            .unwrap();
+        assert_ne!(result, first);
+        assert_eq!(pinned.candidates()[0], result);
+    }
+
+    #[tokio::test]
+    async fn test_requests_share_concurrency_limit() {
+        let pool =
+            new_pool(&[1, 1, 1], false).with_rate_limiter(RpcRateLimiter::new(None, Some(1)));
+
+        // Requests to all endpoints run concurrently, but only one at a time holds
+        // the permit.
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let results = pool
+            .call_all("test", |client| {
+                let in_flight = in_flight.clone();
+                async move {
+                    assert_eq!(in_flight.fetch_add(1, Ordering::SeqCst), 0);
+                    tokio::task::yield_now().await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, Report<RpcPoolError>>(client)
+                }
+            })
+            .await;
+
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_call_all_endpoints() {
+        let pool = new_pool(&[1, 1, 1], false);
+
+        let results = pool
+            .call_all("test", |client| async move {
+                if client == 1 {
+                    Err(Report::new(RpcPoolError))
+                } else {
+                    Ok(client * 10)
+                }
+            })
+            .await;
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, "endpoint-0");
+        assert_eq!(results[0].1.as_ref().ok(), Some(&0));
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2].1.as_ref().ok(), Some(&20));
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let pool = new_pool(&[1, 1], false);
+        let failing = Arc::new(AtomicUsize::new(0));
+
+        let probe = |client| {
+            let failing = failing.clone();
+            async move {
+                if client == failing.load(Ordering::SeqCst) {
+                    Err(Report::new(RpcPoolError))
+                } else {
+                    Ok(client)
+                }
+            }
+        };
+
+        // The failing endpoint is marked unhealthy without any request failing.
+        pool.check_health(&probe).await;
+        assert_eq!(pool.candidates(), vec![1, 0]);
+
+        // It's used again as soon as it passes the health check.
+        failing.store(usize::MAX, Ordering::SeqCst);
+        pool.check_health(&probe).await;
+        assert_eq!(pool.candidates(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_weighted_load_balance() {
+        let pool = new_pool(&[3, 1], true);
+
+        let mut counts = [0; 2];
+        for _ in 0..8 {
+            counts[pool.candidates()[0]] += 1;
+        }
+
+        assert_eq!(counts, [6, 2]);
+    }
+
+    #[test]
+    fn test_reject_empty_pool() {
+        assert!(RpcPool::<usize>::new(Vec::default(), RpcPoolOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_weighted_urls() {
+        let urls = vec!["http://a".to_string(), "http://b".to_string()];
+
+        let weighted = weighted_urls(&urls, &[3]).unwrap();
+        assert_eq!(weighted, vec![("http://a", 3), ("http://b", 1)]);
+
+        assert!(weighted_urls(&urls, &[1, 2, 3]).is_err());
+    }
+}
@@ -1,10 +1,24 @@
 //! A segment is a collection of fragments from different blocks.
 
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::{fragment::IndexGroupFragment, Cursor};
 
+/// Version of the segment and group format.
+///
+/// Bump this value on breaking changes to the layout of segments and groups, and
+/// add the corresponding migration to the `migrate` command.
+/// Objects written before the version was tracked have no header and use version 1.
+pub const SEGMENT_FORMAT_VERSION: u32 = 2;
+
+/// Size of the header stored before the content of segments and groups.
+///
+/// The size is a multiple of 16 so that the content keeps the buffer's alignment.
+pub const SEGMENT_HEADER_SIZE: usize = 16;
+
+static SEGMENT_HEADER_MAGIC: &[u8; 8] = b"DNASEGMT";
+
 #[derive(Archive, Serialize, Deserialize, Debug)]
 pub struct FragmentData<T> {
     pub cursor: Cursor,
@@ -31,5 +45,61 @@ pub struct SegmentGroup {
 /// A segment ready to be written to the storage.
 pub struct SerializedSegment {
     pub name: String,
+    /// The first block in the segment.
+    pub first_block: Cursor,
     pub data: Bytes,
 }
+
+/// Prefix the serialized segment or group with the header for the current format version.
+pub fn encode_segment_header(data: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(SEGMENT_HEADER_SIZE + data.len());
+    out.put_slice(SEGMENT_HEADER_MAGIC);
+    out.put_u32_le(SEGMENT_FORMAT_VERSION);
+    out.put_bytes(0, SEGMENT_HEADER_SIZE - SEGMENT_HEADER_MAGIC.len() - 4);
+    out.put_slice(data);
+    out.freeze()
+}
+
+/// Split a stored segment or group into its format version and content.
+///
+/// The content shares the buffer with `data`.
+pub fn decode_segment_header(data: Bytes) -> (u32, Bytes) {
+    if data.len() < SEGMENT_HEADER_SIZE || !data.starts_with(SEGMENT_HEADER_MAGIC) {
+        return (1, data);
+    }
+
+    let mut version = [0; 4];
+    version.copy_from_slice(&data[SEGMENT_HEADER_MAGIC.len()..SEGMENT_HEADER_MAGIC.len() + 4]);
+
+    (
+        u32::from_le_bytes(version),
+        data.slice(SEGMENT_HEADER_SIZE..),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{
+        decode_segment_header, encode_segment_header, SEGMENT_FORMAT_VERSION, SEGMENT_HEADER_SIZE,
+    };
+
+    #[test]
+    fn test_segment_header_roundtrip() {
+        let encoded = encode_segment_header(b"segment content");
+        assert_eq!(encoded.len(), SEGMENT_HEADER_SIZE + 15);
+
+        let (version, content) = decode_segment_header(encoded);
+        assert_eq!(version, SEGMENT_FORMAT_VERSION);
+        assert_eq!(content.as_ref(), b"segment content");
+    }
+
+    #[test]
+    fn test_segment_without_header() {
+        let data = Bytes::from_static(b"written before the segment header");
+        let (version, content) = decode_segment_header(data.clone());
+        assert_eq!(version, 1);
+        assert_eq!(content, data);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use apibara_dna_protocol::dna::stream::{Cursor as ProtoCursor, DataFinality};
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+/// The streams served by the server.
+#[derive(Clone, Default)]
+pub struct ActiveStreams {
+    inner: Arc<Mutex<ActiveStreamsInner>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveStreamInfo {
+    pub stream_id: u64,
+    /// Fingerprint of the bearer token sent by the client.
+    ///
+    /// The token itself is never stored, since it's shown to operators.
+    pub api_key_fingerprint: Option<String>,
+    /// The cursor of the last message sent to the client.
+    pub cursor: Option<ProtoCursor>,
+    pub finality: DataFinality,
+    pub started_at: SystemTime,
+}
+
+/// Keeps a stream in the list of active streams until dropped.
+pub struct ActiveStreamGuard {
+    stream_id: u64,
+    streams: ActiveStreams,
+    ct: CancellationToken,
+}
+
+#[derive(Default)]
+struct ActiveStreamsInner {
+    next_stream_id: u64,
+    streams: BTreeMap<u64, ActiveStream>,
+}
+
+struct ActiveStream {
+    info: ActiveStreamInfo,
+    ct: CancellationToken,
+}
+
+impl ActiveStreams {
+    /// Add a new stream.
+    ///
+    /// The stream is cancelled when `ct` is cancelled or by [ActiveStreams::cancel].
+    pub fn register(
+        &self,
+        api_key: Option<&str>,
+        cursor: Option<ProtoCursor>,
+        finality: DataFinality,
+        ct: &CancellationToken,
+    ) -> ActiveStreamGuard {
+        let ct = ct.child_token();
+        let mut inner = self.inner.lock().expect("active streams lock");
+
+        let stream_id = inner.next_stream_id;
+        inner.next_stream_id += 1;
+
+        let info = ActiveStreamInfo {
+            stream_id,
+            api_key_fingerprint: api_key.map(api_key_fingerprint),
+            cursor,
+            finality,
+            started_at: SystemTime::now(),
+        };
+
+        inner.streams.insert(
+            stream_id,
+            ActiveStream {
+                info,
+                ct: ct.clone(),
+            },
+        );
+
+        ActiveStreamGuard {
+            stream_id,
+            streams: self.clone(),
+            ct,
+        }
+    }
+
+    /// Returns the streams, ordered by when they started.
+    pub fn list(&self) -> Vec<ActiveStreamInfo> {
+        let inner = self.inner.lock().expect("active streams lock");
+        inner
+            .streams
+            .values()
+            .map(|stream| stream.info.clone())
+            .collect()
+    }
+
+    /// Cancel a stream, returning `false` if the stream doesn't exist.
+    pub fn cancel(&self, stream_id: u64) -> bool {
+        let inner = self.inner.lock().expect("active streams lock");
+        let Some(stream) = inner.streams.get(&stream_id) else {
+            return false;
+        };
+
+        stream.ct.cancel();
+        true
+    }
+
+    fn set_cursor(&self, stream_id: u64, cursor: ProtoCursor) {
+        let mut inner = self.inner.lock().expect("active streams lock");
+        if let Some(stream) = inner.streams.get_mut(&stream_id) {
+            stream.info.cursor = Some(cursor);
+        }
+    }
+
+    fn remove(&self, stream_id: u64) {
+        let mut inner = self.inner.lock().expect("active streams lock");
+        inner.streams.remove(&stream_id);
+    }
+}
+
+/// Returns the first 8 bytes of the key's SHA-256 hash, hex encoded.
+fn api_key_fingerprint(api_key: &str) -> String {
+    let hash = Sha256::digest(api_key.as_bytes());
+    hex::encode(&hash[..8])
+}
+
+impl ActiveStreamGuard {
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Returns the token cancelled when the stream is cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.ct.clone()
+    }
+
+    pub fn set_cursor(&self, cursor: ProtoCursor) {
+        self.streams.set_cursor(self.stream_id, cursor);
+    }
+}
+
+impl Drop for ActiveStreamGuard {
+    fn drop(&mut self) {
+        self.streams.remove(self.stream_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_dna_protocol::dna::stream::{Cursor as ProtoCursor, DataFinality};
+    use tokio_util::sync::CancellationToken;
+
+    use super::ActiveStreams;
+
+    #[test]
+    fn test_active_streams() {
+        let ct = CancellationToken::new();
+        let streams = ActiveStreams::default();
+
+        let first = streams.register(Some("my-key"), None, DataFinality::Accepted, &ct);
+        let second = streams.register(None, None, DataFinality::Finalized, &ct);
+
+        first.set_cursor(ProtoCursor::new_finalized(100));
+
+        let list = streams.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].stream_id, first.stream_id());
+        let fingerprint = list[0].api_key_fingerprint.as_deref().unwrap();
+        assert_eq!(fingerprint.len(), 16);
+        assert!(!fingerprint.contains("my-key"));
+        assert_eq!(list[0].cursor, Some(ProtoCursor::new_finalized(100)));
+        assert_eq!(list[1].stream_id, second.stream_id());
+        assert!(list[1].cursor.is_none());
+
+        assert!(streams.cancel(second.stream_id()));
+        assert!(second.cancellation_token().is_cancelled());
+        assert!(!first.cancellation_token().is_cancelled());
+
+        drop(second);
+        assert_eq!(streams.list().len(), 1);
+        assert!(!streams.cancel(u64::MAX));
+
+        ct.cancel();
+        assert!(first.cancellation_token().is_cancelled());
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use apibara_dna_protocol::dna::admin::{
+    dna_admin_server::{self, DnaAdmin},
+    CancelStreamRequest, CancelStreamResponse, ListStreamsRequest, ListStreamsResponse,
+    PauseIngestionRequest, PauseIngestionResponse, ResumeIngestionRequest, ResumeIngestionResponse,
+    StreamInfo, TriggerCompactionRequest, TriggerCompactionResponse,
+};
+use tokio::sync::{watch, Notify};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tracing::info;
+
+use super::{active_streams::ActiveStreams, service::bearer_token};
+
+/// Handles to the services controlled by the admin service.
+///
+/// Services that don't run on this server are `None`.
+#[derive(Clone, Default)]
+pub struct AdminControl {
+    /// Ingestion stops ingesting new blocks while the value is `true`.
+    pub ingestion_paused: Option<Arc<watch::Sender<bool>>>,
+    /// Wakes up compaction to check for new segments.
+    pub compaction_trigger: Option<Arc<Notify>>,
+}
+
+/// Lets operators inspect and control the server.
+pub struct AdminService {
+    active_streams: ActiveStreams,
+    control: AdminControl,
+}
+
+/// Rejects requests without the admin token.
+#[derive(Clone)]
+pub struct AdminAuth {
+    token: Arc<str>,
+}
+
+impl AdminService {
+    pub fn new(active_streams: ActiveStreams, control: AdminControl) -> Self {
+        Self {
+            active_streams,
+            control,
+        }
+    }
+
+    pub fn into_service(
+        self,
+        token: String,
+    ) -> InterceptedService<dna_admin_server::DnaAdminServer<Self>, AdminAuth> {
+        let auth = AdminAuth {
+            token: token.into(),
+        };
+        dna_admin_server::DnaAdminServer::with_interceptor(self, auth)
+    }
+
+    fn ingestion_paused(&self) -> tonic::Result<&watch::Sender<bool>, tonic::Status> {
+        self.control
+            .ingestion_paused
+            .as_deref()
+            .ok_or_else(|| tonic::Status::failed_precondition("ingestion is not enabled"))
+    }
+}
+
+#[tonic::async_trait]
+impl DnaAdmin for AdminService {
+    #[tracing::instrument(name = "admin::list_streams", skip_all)]
+    async fn list_streams(
+        &self,
+        _request: tonic::Request<ListStreamsRequest>,
+    ) -> tonic::Result<tonic::Response<ListStreamsResponse>, tonic::Status> {
+        let streams = self
+            .active_streams
+            .list()
+            .into_iter()
+            .map(|stream| StreamInfo {
+                stream_id: stream.stream_id,
+                api_key_fingerprint: stream.api_key_fingerprint,
+                cursor: stream.cursor,
+                finality: stream.finality as i32,
+                started_at: Some(stream.started_at.into()),
+            })
+            .collect();
+
+        Ok(tonic::Response::new(ListStreamsResponse { streams }))
+    }
+
+    #[tracing::instrument(name = "admin::cancel_stream", skip_all)]
+    async fn cancel_stream(
+        &self,
+        request: tonic::Request<CancelStreamRequest>,
+    ) -> tonic::Result<tonic::Response<CancelStreamResponse>, tonic::Status> {
+        let stream_id = request.into_inner().stream_id;
+
+        if !self.active_streams.cancel(stream_id) {
+            return Err(tonic::Status::not_found(format!(
+                "stream {stream_id} not found"
+            )));
+        }
+
+        info!(stream_id, "stream cancelled by admin");
+
+        Ok(tonic::Response::new(CancelStreamResponse {}))
+    }
+
+    #[tracing::instrument(name = "admin::pause_ingestion", skip_all)]
+    async fn pause_ingestion(
+        &self,
+        _request: tonic::Request<PauseIngestionRequest>,
+    ) -> tonic::Result<tonic::Response<PauseIngestionResponse>, tonic::Status> {
+        let was_paused = self.ingestion_paused()?.send_replace(true);
+
+        info!(was_paused, "ingestion paused by admin");
+
+        Ok(tonic::Response::new(PauseIngestionResponse { was_paused }))
+    }
+
+    #[tracing::instrument(name = "admin::resume_ingestion", skip_all)]
+    async fn resume_ingestion(
+        &self,
+        _request: tonic::Request<ResumeIngestionRequest>,
+    ) -> tonic::Result<tonic::Response<ResumeIngestionResponse>, tonic::Status> {
+        let was_paused = self.ingestion_paused()?.send_replace(false);
+
+        info!(was_paused, "ingestion resumed by admin");
+
+        Ok(tonic::Response::new(ResumeIngestionResponse { was_paused }))
+    }
+
+    #[tracing::instrument(name = "admin::trigger_compaction", skip_all)]
+    async fn trigger_compaction(
+        &self,
+        _request: tonic::Request<TriggerCompactionRequest>,
+    ) -> tonic::Result<tonic::Response<TriggerCompactionResponse>, tonic::Status> {
+        let Some(trigger) = &self.control.compaction_trigger else {
+            return Err(tonic::Status::failed_precondition(
+                "compaction is not enabled",
+            ));
+        };
+
+        trigger.notify_one();
+
+        info!("compaction triggered by admin");
+
+        Ok(tonic::Response::new(TriggerCompactionResponse {}))
+    }
+}
+
+impl Interceptor for AdminAuth {
+    fn call(
+        &mut self,
+        request: tonic::Request<()>,
+    ) -> tonic::Result<tonic::Request<()>, tonic::Status> {
+        let Some(token) = bearer_token(request.metadata()) else {
+            return Err(tonic::Status::unauthenticated("missing admin token"));
+        };
+
+        // Compare in constant time to not leak the token.
+        let expected = self.token.as_bytes();
+        let is_valid = token.len() == expected.len()
+            && token
+                .as_bytes()
+                .iter()
+                .zip(expected)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+
+        if !is_valid {
+            return Err(tonic::Status::unauthenticated("invalid admin token"));
+        }
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::service::Interceptor;
+
+    use super::AdminAuth;
+
+    fn request_with_token(token: Option<&str>) -> tonic::Request<()> {
+        let mut request = tonic::Request::new(());
+        if let Some(token) = token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_admin_auth() {
+        let mut auth = AdminAuth {
+            token: "secret".into(),
+        };
+
+        assert!(auth.call(request_with_token(Some("Bearer secret"))).is_ok());
+        assert!(auth.call(request_with_token(None)).is_err());
+        assert!(auth.call(request_with_token(Some("secret"))).is_err());
+        assert!(auth
+            .call(request_with_token(Some("Bearer secret2")))
+            .is_err());
+        assert!(auth.call(request_with_token(Some("Bearer other"))).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use apibara_dna_protocol::dna::{
+    self,
+    stream::{
+        dna_chain_view_server::{self, DnaChainView},
+        get_canonical_cursor_response, get_next_cursor_response, AtHead, GetCanonicalCursorRequest,
+        GetCanonicalCursorResponse, GetNextCursorRequest, GetNextCursorResponse,
+        GetPreviousCursorRequest, GetPreviousCursorResponse, ValidateCursorRequest,
+        ValidateCursorResponse,
+    },
+};
+use tracing::error;
+
+use crate::{
+    chain_view::{CanonicalCursor, ChainView, ChainViewError, NextCursor, ValidatedCursor},
+    Cursor,
+};
+
+use super::service::ChainViewExt;
+
+/// Exposes the chain view to clients that need to validate cursors.
+pub struct ChainViewService {
+    chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
+}
+
+impl ChainViewService {
+    pub fn new(chain_view: tokio::sync::watch::Receiver<Option<ChainView>>) -> Self {
+        Self { chain_view }
+    }
+
+    pub fn into_service(self) -> dna_chain_view_server::DnaChainViewServer<Self> {
+        dna_chain_view_server::DnaChainViewServer::new(self)
+    }
+
+    fn current_chain_view(&self) -> tonic::Result<ChainView, tonic::Status> {
+        self.chain_view
+            .borrow()
+            .clone()
+            .ok_or_else(|| tonic::Status::unavailable("chain view not initialized yet"))
+    }
+}
+
+#[tonic::async_trait]
+impl DnaChainView for ChainViewService {
+    #[tracing::instrument(name = "chain_view::get_canonical_cursor", skip_all)]
+    async fn get_canonical_cursor(
+        &self,
+        request: tonic::Request<GetCanonicalCursorRequest>,
+    ) -> tonic::Result<tonic::Response<GetCanonicalCursorResponse>, tonic::Status> {
+        let chain_view = self.current_chain_view()?;
+        let request = request.into_inner();
+
+        let result = match chain_view
+            .get_canonical(request.block_number)
+            .await
+            .map_err(internal_error)?
+        {
+            CanonicalCursor::Canonical(cursor) => {
+                get_canonical_cursor_response::Result::Canonical(cursor.into())
+            }
+            CanonicalCursor::BeforeAvailable(first) => {
+                get_canonical_cursor_response::Result::BeforeAvailable(first.into())
+            }
+            CanonicalCursor::AfterAvailable(last) => {
+                get_canonical_cursor_response::Result::AfterAvailable(last.into())
+            }
+        };
+
+        Ok(tonic::Response::new(GetCanonicalCursorResponse {
+            result: Some(result),
+        }))
+    }
+
+    #[tracing::instrument(name = "chain_view::validate_cursor", skip_all)]
+    async fn validate_cursor(
+        &self,
+        request: tonic::Request<ValidateCursorRequest>,
+    ) -> tonic::Result<tonic::Response<ValidateCursorResponse>, tonic::Status> {
+        let chain_view = self.current_chain_view()?;
+        let cursor = request_cursor(request.into_inner().cursor)?;
+
+        chain_view.ensure_cursor_in_range(&cursor).await?;
+
+        let response = match chain_view
+            .validate_cursor(&cursor)
+            .await
+            .map_err(internal_error)?
+        {
+            ValidatedCursor::Valid(canonical) => ValidateCursorResponse {
+                valid: true,
+                canonical: Some(canonical.into()),
+                reorged: Vec::default(),
+            },
+            ValidatedCursor::Invalid(canonical, reorged) => ValidateCursorResponse {
+                valid: false,
+                canonical: Some(canonical.into()),
+                reorged: reorged.into_iter().map(Into::into).collect(),
+            },
+        };
+
+        Ok(tonic::Response::new(response))
+    }
+
+    #[tracing::instrument(name = "chain_view::get_next_cursor", skip_all)]
+    async fn get_next_cursor(
+        &self,
+        request: tonic::Request<GetNextCursorRequest>,
+    ) -> tonic::Result<tonic::Response<GetNextCursorResponse>, tonic::Status> {
+        let chain_view = self.current_chain_view()?;
+        let cursor = request.into_inner().cursor.map(Cursor::from);
+
+        if let Some(cursor) = &cursor {
+            chain_view.ensure_cursor_in_range(cursor).await?;
+        }
+
+        let result = match chain_view
+            .get_next_cursor(&cursor)
+            .await
+            .map_err(internal_error)?
+        {
+            NextCursor::Continue { cursor, is_head } => {
+                get_next_cursor_response::Result::Next(dna::stream::NextCursor {
+                    cursor: Some(cursor.into()),
+                    is_head,
+                })
+            }
+            NextCursor::Invalidate(cursor) => {
+                get_next_cursor_response::Result::Invalidate(cursor.into())
+            }
+            NextCursor::AtHead => get_next_cursor_response::Result::AtHead(AtHead {}),
+        };
+
+        Ok(tonic::Response::new(GetNextCursorResponse {
+            result: Some(result),
+        }))
+    }
+
+    #[tracing::instrument(name = "chain_view::get_previous_cursor", skip_all)]
+    async fn get_previous_cursor(
+        &self,
+        request: tonic::Request<GetPreviousCursorRequest>,
+    ) -> tonic::Result<tonic::Response<GetPreviousCursorResponse>, tonic::Status> {
+        let chain_view = self.current_chain_view()?;
+        let cursor = request_cursor(request.into_inner().cursor)?;
+
+        chain_view.ensure_cursor_in_range(&cursor).await?;
+
+        if let ValidatedCursor::Invalid(canonical, _) = chain_view
+            .validate_cursor(&cursor)
+            .await
+            .map_err(internal_error)?
+        {
+            return Err(tonic::Status::invalid_argument(format!(
+                "cursor {cursor} is not canonical. canonical: {}",
+                canonical.hash_as_hex()
+            )));
+        }
+
+        let Some(previous_number) = cursor.number.checked_sub(1) else {
+            return Ok(tonic::Response::new(GetPreviousCursorResponse {
+                cursor: None,
+            }));
+        };
+
+        let previous = match chain_view
+            .get_canonical(previous_number)
+            .await
+            .map_err(internal_error)?
+        {
+            CanonicalCursor::Canonical(previous) => Some(previous.into()),
+            CanonicalCursor::BeforeAvailable(_) => None,
+            CanonicalCursor::AfterAvailable(_) => {
+                return Err(tonic::Status::internal("internal server error"));
+            }
+        };
+
+        Ok(tonic::Response::new(GetPreviousCursorResponse {
+            cursor: previous,
+        }))
+    }
+}
+
+fn request_cursor(cursor: Option<dna::stream::Cursor>) -> tonic::Result<Cursor, tonic::Status> {
+    cursor
+        .map(Cursor::from)
+        .ok_or_else(|| tonic::Status::invalid_argument("missing cursor"))
+}
+
+fn internal_error(err: error_stack::Report<ChainViewError>) -> tonic::Status {
+    error!(error = ?err, "DnaChainView error");
+    tonic::Status::internal("internal server error")
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_dna_protocol::dna::stream::{
+        dna_chain_view_server::DnaChainView, get_canonical_cursor_response,
+        get_next_cursor_response, GetCanonicalCursorRequest, GetNextCursorRequest,
+        GetPreviousCursorRequest, ValidateCursorRequest,
+    };
+
+    use crate::{
+        new_test_cursor,
+        server::testing::{TestChain, TEST_CHAIN_HEAD},
+        Cursor,
+    };
+
+    use super::ChainViewService;
+
+    async fn get_canonical(
+        service: &ChainViewService,
+        block_number: u64,
+    ) -> get_canonical_cursor_response::Result {
+        service
+            .get_canonical_cursor(tonic::Request::new(GetCanonicalCursorRequest {
+                block_number,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .result
+            .unwrap()
+    }
+
+    async fn get_next(
+        service: &ChainViewService,
+        cursor: Option<Cursor>,
+    ) -> get_next_cursor_response::Result {
+        service
+            .get_next_cursor(tonic::Request::new(GetNextCursorRequest {
+                cursor: cursor.map(Into::into),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .result
+            .unwrap()
+    }
+
+    async fn get_previous(
+        service: &ChainViewService,
+        cursor: Cursor,
+    ) -> tonic::Result<Option<Cursor>, tonic::Status> {
+        let response = service
+            .get_previous_cursor(tonic::Request::new(GetPreviousCursorRequest {
+                cursor: Some(cursor.into()),
+            }))
+            .await?;
+        Ok(response.into_inner().cursor.map(Cursor::from))
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_before_chain_view() {
+        let (_tx, rx) = tokio::sync::watch::channel(None);
+        let service = ChainViewService::new(rx);
+
+        let status = service
+            .get_canonical_cursor(tonic::Request::new(GetCanonicalCursorRequest {
+                block_number: 0,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn test_get_canonical_cursor() {
+        let chain = TestChain::new().await;
+        let service = ChainViewService::new(chain.chain_view_rx());
+
+        let get_canonical_cursor_response::Result::Canonical(cursor) =
+            get_canonical(&service, 3).await
+        else {
+            panic!("expected canonical cursor");
+        };
+        assert_eq!(Cursor::from(cursor), new_test_cursor(3, 0));
+
+        let get_canonical_cursor_response::Result::AfterAvailable(last) =
+            get_canonical(&service, TEST_CHAIN_HEAD + 1).await
+        else {
+            panic!("expected cursor after available");
+        };
+        assert_eq!(Cursor::from(last), new_test_cursor(TEST_CHAIN_HEAD, 0));
+    }
+
+    #[tokio::test]
+    async fn test_validate_cursor() {
+        let chain = TestChain::new().await;
+        let service = ChainViewService::new(chain.chain_view_rx());
+
+        let response = service
+            .validate_cursor(tonic::Request::new(ValidateCursorRequest {
+                cursor: Some(new_test_cursor(8, 0).into()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.valid);
+        assert_eq!(
+            response.canonical.map(Cursor::from),
+            Some(new_test_cursor(8, 0))
+        );
+
+        let response = service
+            .validate_cursor(tonic::Request::new(ValidateCursorRequest {
+                cursor: Some(new_test_cursor(8, 1).into()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.valid);
+        assert_eq!(
+            response.canonical.map(Cursor::from),
+            Some(new_test_cursor(8, 0))
+        );
+
+        let status = service
+            .validate_cursor(tonic::Request::new(ValidateCursorRequest { cursor: None }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_next_cursor() {
+        let chain = TestChain::new().await;
+        let service = ChainViewService::new(chain.chain_view_rx());
+
+        let get_next_cursor_response::Result::Next(next) = get_next(&service, None).await else {
+            panic!("expected next cursor");
+        };
+        assert_eq!(next.cursor.map(Cursor::from), Some(new_test_cursor(0, 0)));
+        assert!(!next.is_head);
+
+        let get_next_cursor_response::Result::Next(next) =
+            get_next(&service, Some(new_test_cursor(TEST_CHAIN_HEAD - 1, 0))).await
+        else {
+            panic!("expected next cursor");
+        };
+        assert_eq!(
+            next.cursor.map(Cursor::from),
+            Some(new_test_cursor(TEST_CHAIN_HEAD, 0))
+        );
+        assert!(next.is_head);
+
+        assert!(matches!(
+            get_next(&service, Some(new_test_cursor(TEST_CHAIN_HEAD, 0))).await,
+            get_next_cursor_response::Result::AtHead(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_previous_cursor() {
+        let chain = TestChain::new().await;
+        let service = ChainViewService::new(chain.chain_view_rx());
+
+        assert_eq!(
+            get_previous(&service, new_test_cursor(5, 0)).await.unwrap(),
+            Some(new_test_cursor(4, 0))
+        );
+        assert_eq!(
+            get_previous(&service, new_test_cursor(0, 0)).await.unwrap(),
+            None
+        );
+
+        let status = get_previous(&service, new_test_cursor(5, 1))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
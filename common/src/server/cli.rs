@@ -1,9 +1,9 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, str::FromStr};
 
 use clap::Args;
 use error_stack::{Result, ResultExt};
 
-use crate::server::ServerOptions;
+use crate::{data_stream::SlowConsumerPolicy, server::ServerOptions, shared_cache::SharedCache};
 
 use super::{error::ServerError, StreamServiceOptions};
 
@@ -20,6 +20,8 @@ pub struct ServerArgs {
     )]
     pub server_address: String,
     /// Maximum number of concurrent streams served.
+    ///
+    /// Reloaded on SIGHUP.
     #[clap(
         long = "server.max-concurrent-streams",
         env = "DNA_SERVER_MAX_CONCURRENT_STREAMS",
@@ -33,9 +35,139 @@ pub struct ServerArgs {
         default_value = "128"
     )]
     pub server_prefetch_segment_count: usize,
+    /// Number of blocks to prefetch when streaming blocks that are not in a segment yet.
+    #[clap(
+        long = "server.prefetch-block-count",
+        env = "DNA_SERVER_PREFETCH_BLOCK_COUNT",
+        default_value = "32"
+    )]
+    pub server_prefetch_block_count: usize,
+    /// Maximum size of the filtered segments shared between streams with the same filter.
+    #[clap(
+        long = "server.shared-filter-cache-size",
+        env = "DNA_SERVER_SHARED_FILTER_CACHE_SIZE",
+        default_value = "256Mi"
+    )]
+    pub server_shared_filter_cache_size: String,
+    /// Maximum size of the rows matched by filters in each segment, cached to speed up
+    /// backfilling popular filters. Set to 0 to disable the cache.
+    #[clap(
+        long = "server.filter-result-cache-size",
+        env = "DNA_SERVER_FILTER_RESULT_CACHE_SIZE",
+        default_value = "0"
+    )]
+    pub server_filter_result_cache_size: String,
+    /// Maximum size of the messages buffered by each stream for its client.
+    ///
+    /// Streams stop producing data until the client reads the buffered messages.
+    /// Reloaded on SIGHUP, for new streams.
+    #[clap(
+        long = "server.stream-memory-budget",
+        env = "DNA_SERVER_STREAM_MEMORY_BUDGET",
+        default_value = "64Mi"
+    )]
+    pub server_stream_memory_budget: String,
+    /// Enable the admin service, authenticating requests with this bearer token.
+    ///
+    /// The admin service lists and cancels streams, pauses ingestion, and triggers compaction.
+    #[clap(long = "server.admin-token", env = "DNA_SERVER_ADMIN_TOKEN")]
+    pub server_admin_token: Option<String>,
+    /// The admin service address.
+    ///
+    /// The admin service is never served on the public server address.
+    #[clap(
+        long = "server.admin-address",
+        env = "DNA_SERVER_ADMIN_ADDRESS",
+        default_value = "127.0.0.1:7008"
+    )]
+    pub server_admin_address: String,
+    /// Maximum number of filter conditions in a stream request, across all filters.
+    ///
+    /// Each condition on an index counts as one, or as the number of values for conditions
+    /// matching a set of values. Filters without conditions count as one.
+    #[clap(
+        long = "server.max-filter-conditions",
+        env = "DNA_SERVER_MAX_FILTER_CONDITIONS",
+        default_value = "10000"
+    )]
+    pub server_max_filter_conditions: usize,
+    /// Maximum size of the stream sessions kept to resume streams. Set to 0 to disable
+    /// sessions.
+    ///
+    /// Sessions are estimated by the size of their filters.
+    #[clap(
+        long = "server.session-cache-size",
+        env = "DNA_SERVER_SESSION_CACHE_SIZE",
+        default_value = "64Mi"
+    )]
+    pub server_session_cache_size: String,
+    /// Report streams whose client doesn't read data for this long, for example "30s".
+    #[clap(
+        long = "server.slow-consumer-threshold",
+        env = "DNA_SERVER_SLOW_CONSUMER_THRESHOLD",
+        default_value = "30s"
+    )]
+    pub server_slow_consumer_threshold: String,
+    /// Disconnect streams whose client doesn't read data for this long, for example "5m".
+    ///
+    /// Streams are never disconnected if not set.
+    #[clap(
+        long = "server.slow-consumer-timeout",
+        env = "DNA_SERVER_SLOW_CONSUMER_TIMEOUT"
+    )]
+    pub server_slow_consumer_timeout: Option<String>,
+    /// Identifies this server in the data sent to clients.
+    ///
+    /// Useful to find which server produced corrupted data when running multiple servers.
+    #[clap(long = "server.id", env = "DNA_SERVER_ID")]
+    pub server_id: Option<String>,
+    /// Save the chain view to this file and restore it on startup.
+    ///
+    /// Restarted servers start streaming without waiting to download the recent
+    /// canonical chain, then catch up with ingestion.
+    #[clap(
+        long = "server.chain-view-snapshot",
+        env = "DNA_SERVER_CHAIN_VIEW_SNAPSHOT"
+    )]
+    pub server_chain_view_snapshot: Option<String>,
+    /// URL of an HTTP cache shared by all replicas, for example "http://dna-cache:8080/mainnet".
+    ///
+    /// Replicas read blocks and segments from the shared cache before downloading them
+    /// from the object store, and store the objects they download in it.
+    #[clap(long = "server.shared-cache-url", env = "DNA_SERVER_SHARED_CACHE_URL")]
+    pub server_shared_cache_url: Option<String>,
+    /// Timeout for requests to the shared cache, for example "2s".
+    #[clap(
+        long = "server.shared-cache-timeout",
+        env = "DNA_SERVER_SHARED_CACHE_TIMEOUT",
+        default_value = "2s"
+    )]
+    pub server_shared_cache_timeout: String,
+    /// Report the server as ready only after streaming a block with an internal stream.
+    ///
+    /// The gRPC health service reports `NOT_SERVING` until the finalized block is streamed
+    /// successfully, so that load balancers don't route clients to a broken server.
+    #[clap(long = "server.self-test", env = "DNA_SERVER_SELF_TEST")]
+    pub server_self_test: bool,
 }
 
 impl ServerArgs {
+    /// Returns the shared cache, if configured.
+    pub fn to_shared_cache(&self) -> Result<Option<SharedCache>, ServerError> {
+        let Some(url) = &self.server_shared_cache_url else {
+            return Ok(None);
+        };
+
+        let timeout =
+            duration_str::parse_std(&self.server_shared_cache_timeout).or_else(|err| {
+                Err(ServerError)
+                    .attach_printable("failed to parse shared cache timeout")
+                    .attach_printable(format!("error: {}", err))
+            })?;
+
+        Ok(Some(SharedCache::new(url, timeout)))
+    }
+
     pub fn to_server_options(&self) -> Result<ServerOptions, ServerError> {
         let address = self
             .server_address
@@ -44,14 +176,108 @@ impl ServerArgs {
             .attach_printable("failed to parse server address")
             .attach_printable_lazy(|| format!("address: {}", self.server_address))?;
 
+        let admin_address = self
+            .server_admin_address
+            .parse::<SocketAddr>()
+            .change_context(ServerError)
+            .attach_printable("failed to parse admin server address")
+            .attach_printable_lazy(|| format!("address: {}", self.server_admin_address))?;
+
+        if self.server_admin_token.is_some() && admin_address == address {
+            return Err(ServerError)
+                .attach_printable("the admin server address must differ from the server address")
+                .attach_printable_lazy(|| format!("address: {address}"));
+        }
+
+        let shared_filter_cache_size =
+            byte_unit::Byte::from_str(&self.server_shared_filter_cache_size)
+                .change_context(ServerError)
+                .attach_printable("failed to parse shared filter cache size")
+                .attach_printable_lazy(|| {
+                    format!(
+                        "shared filter cache size: {}",
+                        self.server_shared_filter_cache_size
+                    )
+                })?
+                .as_u64() as usize;
+
+        let filter_result_cache_size =
+            byte_unit::Byte::from_str(&self.server_filter_result_cache_size)
+                .change_context(ServerError)
+                .attach_printable("failed to parse filter result cache size")
+                .attach_printable_lazy(|| {
+                    format!(
+                        "filter result cache size: {}",
+                        self.server_filter_result_cache_size
+                    )
+                })?
+                .as_u64() as usize;
+
+        let stream_memory_budget = byte_unit::Byte::from_str(&self.server_stream_memory_budget)
+            .change_context(ServerError)
+            .attach_printable("failed to parse stream memory budget")
+            .attach_printable_lazy(|| {
+                format!("stream memory budget: {}", self.server_stream_memory_budget)
+            })?
+            .as_u64() as usize;
+
+        let session_cache_size = byte_unit::Byte::from_str(&self.server_session_cache_size)
+            .change_context(ServerError)
+            .attach_printable("failed to parse session cache size")
+            .attach_printable_lazy(|| {
+                format!("session cache size: {}", self.server_session_cache_size)
+            })?
+            .as_u64() as usize;
+
+        let report_after =
+            duration_str::parse_std(&self.server_slow_consumer_threshold).or_else(|err| {
+                Err(ServerError)
+                    .attach_printable("failed to parse slow consumer threshold")
+                    .attach_printable(format!("error: {}", err))
+            })?;
+
+        let disconnect_after = self
+            .server_slow_consumer_timeout
+            .as_ref()
+            .map(|timeout| {
+                duration_str::parse_std(timeout).or_else(|err| {
+                    Err(ServerError)
+                        .attach_printable("failed to parse slow consumer timeout")
+                        .attach_printable(format!("error: {}", err))
+                })
+            })
+            .transpose()?;
+
+        if let Some(disconnect_after) = disconnect_after {
+            if disconnect_after < report_after {
+                return Err(ServerError).attach_printable(
+                    "slow consumer timeout must not be shorter than the slow consumer threshold",
+                );
+            }
+        }
+
         let stream_service_options = StreamServiceOptions {
             max_concurrent_streams: self.server_max_concurrent_streams,
             prefetch_segment_count: self.server_prefetch_segment_count,
+            prefetch_block_count: self.server_prefetch_block_count,
+            shared_filter_cache_size,
+            filter_result_cache_size,
+            stream_memory_budget,
+            server_id: self.server_id.clone(),
+            max_filter_conditions: self.server_max_filter_conditions,
+            session_cache_size,
+            slow_consumer: SlowConsumerPolicy {
+                report_after,
+                disconnect_after,
+            },
         };
 
         Ok(ServerOptions {
             address,
             stream_service_options,
+            admin_token: self.server_admin_token.clone(),
+            admin_address,
+            self_test: self.server_self_test,
         })
     }
 }
@@ -1,15 +1,26 @@
+mod active_streams;
+mod admin_service;
+mod chain_view_service;
 mod cli;
 mod error;
+mod self_test;
 mod service;
+mod sessions;
 mod stream_with_heartbeat;
+#[cfg(test)]
+mod testing;
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
+use active_streams::ActiveStreams;
+use admin_service::AdminService;
 use apibara_dna_protocol::dna::stream::dna_stream_file_descriptor_set;
 use apibara_observability::Gauge;
+use chain_view_service::ChainViewService;
 use error::ServerError;
 use error_stack::{Result, ResultExt};
+use futures::FutureExt;
 use service::StreamService;
 use tokio_util::sync::CancellationToken;
 use tonic::transport::Server as TonicServer;
@@ -17,9 +28,10 @@ use tracing::info;
 
 use crate::{
     block_store::BlockStoreReader, chain_view::ChainView, data_stream::BlockFilterFactory,
-    fragment::FragmentId,
+    dataset_manifest::DatasetManifestStore, fragment::FragmentId, reload::RuntimeSettings,
 };
 
+pub use self::admin_service::AdminControl;
 pub use self::cli::ServerArgs;
 pub use self::service::StreamServiceOptions;
 
@@ -29,18 +41,28 @@ pub struct ServerOptions {
     pub address: SocketAddr,
     /// Stream service options.
     pub stream_service_options: StreamServiceOptions,
+    /// Token required by the admin service. The service is disabled if `None`.
+    pub admin_token: Option<String>,
+    /// The admin service address, separate from the public server address.
+    pub admin_address: SocketAddr,
+    /// Report the server as ready only after streaming data with an internal stream.
+    pub self_test: bool,
 }
 
 pub struct ServerMetrics {
     pub up: Gauge<u64>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn server_loop<BFF>(
     filter_factory: BFF,
     chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
     fragment_id_to_name: HashMap<FragmentId, String>,
     block_store: BlockStoreReader,
+    manifest_store: DatasetManifestStore,
     options: ServerOptions,
+    settings: tokio::sync::watch::Receiver<RuntimeSettings>,
+    admin_control: AdminControl,
     ct: CancellationToken,
 ) -> Result<(), ServerError>
 where
@@ -48,7 +70,7 @@ where
 {
     let metrics = ServerMetrics::default();
 
-    let (_health_reporter, health_service) = tonic_health::server::health_reporter();
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
 
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(dna_stream_file_descriptor_set())
@@ -57,29 +79,77 @@ where
         .change_context(ServerError)
         .attach_printable("failed to create gRPC reflection service")?;
 
+    let chain_view_service = ChainViewService::new(chain_view.clone());
+
+    let active_streams = ActiveStreams::default();
+
+    let admin_service = options.admin_token.map(|token| {
+        info!("admin service enabled");
+        AdminService::new(active_streams.clone(), admin_control).into_service(token)
+    });
+
     let stream_service = StreamService::new(
         filter_factory,
         chain_view,
         fragment_id_to_name,
         block_store,
+        manifest_store,
         options.stream_service_options,
+        settings,
+        active_streams,
         ct.clone(),
     );
 
+    tokio::spawn(stream_service.stream_limit_loop(ct.clone()));
+
+    if options.self_test {
+        info!("self-test enabled, server is not ready until it passes");
+        tokio::spawn(
+            stream_service
+                .self_test()
+                .run_until_ready(health_reporter, ct.clone()),
+        );
+    }
+
     info!(address = %options.address, "starting DNA server");
 
     metrics.up.record(1, &[]);
 
-    TonicServer::builder()
+    let server = TonicServer::builder()
         .add_service(health_service)
         .add_service(reflection_service)
         .add_service(stream_service.into_service())
+        .add_service(chain_view_service.into_service())
         .serve_with_shutdown(options.address, {
             let ct = ct.clone();
             async move { ct.cancelled().await }
         })
-        .await
-        .change_context(ServerError)
+        .map(|result| result.change_context(ServerError));
+
+    // The admin service runs on its own address, so that it's never exposed
+    // together with the public stream service.
+    let admin_address = options.admin_address;
+    let admin_server = {
+        let ct = ct.clone();
+        async move {
+            let Some(admin_service) = admin_service else {
+                return Ok(());
+            };
+
+            info!(address = %admin_address, "starting admin server");
+
+            TonicServer::builder()
+                .add_service(admin_service)
+                .serve_with_shutdown(admin_address, async move { ct.cancelled().await })
+                .await
+                .change_context(ServerError)
+                .attach_printable("admin server failed")
+        }
+    };
+
+    tokio::try_join!(server, admin_server)?;
+
+    Ok(())
 }
 
 impl Default for ServerMetrics {
@@ -0,0 +1,275 @@
+//! Check that data flows end-to-end before reporting the server as ready.
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use apibara_dna_protocol::dna::stream::{
+    data_checksum, stream_data_response::Message, DataFinality,
+};
+use error_stack::{Result, ResultExt};
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::{info, warn};
+
+use crate::{
+    block_store::BlockStoreReader,
+    chain_view::{CanonicalCursor, ChainView},
+    data_stream::{
+        DataStream, DataStreamMetrics, FilterKey, SharedFilterCache, StreamMemoryBudget,
+    },
+    fragment::FragmentId,
+    query::{BlockFilter, HeaderFilter},
+    reload::RuntimeSettings,
+    Cursor,
+};
+
+use super::{error::ServerError, StreamServiceOptions};
+
+/// Filter key of the internal stream.
+const SELF_TEST_FILTER_KEY: FilterKey = [0xff; 32];
+
+const SELF_TEST_CHANNEL_SIZE: usize = 16;
+
+static SELF_TEST_TIMEOUT: Duration = Duration::from_secs(60);
+static SELF_TEST_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Streams the finalized block with an internal stream.
+///
+/// The internal stream goes through the same path as client streams (chain view,
+/// block store, and filters), so the server is only reported as ready once data
+/// actually flows from ingestion to clients.
+pub struct SelfTest {
+    chain_view: watch::Receiver<Option<ChainView>>,
+    fragment_id_to_name: HashMap<FragmentId, String>,
+    block_store: BlockStoreReader,
+    shared_filter: SharedFilterCache,
+    options: StreamServiceOptions,
+    settings: watch::Receiver<RuntimeSettings>,
+    metrics: DataStreamMetrics,
+}
+
+impl SelfTest {
+    pub fn new(
+        chain_view: watch::Receiver<Option<ChainView>>,
+        fragment_id_to_name: HashMap<FragmentId, String>,
+        block_store: BlockStoreReader,
+        shared_filter: SharedFilterCache,
+        options: StreamServiceOptions,
+        settings: watch::Receiver<RuntimeSettings>,
+        metrics: DataStreamMetrics,
+    ) -> Self {
+        Self {
+            chain_view,
+            fragment_id_to_name,
+            block_store,
+            shared_filter,
+            options,
+            settings,
+            metrics,
+        }
+    }
+
+    /// Report the server as not serving until the self-test passes.
+    pub async fn run_until_ready(self, mut health_reporter: HealthReporter, ct: CancellationToken) {
+        health_reporter
+            .set_service_status("", ServingStatus::NotServing)
+            .await;
+
+        loop {
+            match tokio::time::timeout(SELF_TEST_TIMEOUT, self.run(ct.clone())).await {
+                Ok(Ok(cursor)) => {
+                    info!(cursor = %cursor, "self-test passed");
+                    health_reporter
+                        .set_service_status("", ServingStatus::Serving)
+                        .await;
+                    return;
+                }
+                Ok(Err(err)) => {
+                    warn!(error = ?err, "self-test failed");
+                }
+                Err(_) => {
+                    warn!("self-test timed out");
+                }
+            }
+
+            tokio::select! {
+                _ = ct.cancelled() => return,
+                _ = tokio::time::sleep(SELF_TEST_RETRY_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Stream the finalized block, returning its cursor.
+    pub async fn run(&self, ct: CancellationToken) -> Result<Cursor, ServerError> {
+        let Some(chain_view) = self
+            .chain_view
+            .clone()
+            .wait_for(Option::is_some)
+            .await
+            .change_context(ServerError)
+            .attach_printable("chain view channel closed")?
+            .clone()
+        else {
+            return Err(ServerError).attach_printable("chain view not initialized");
+        };
+
+        let finalized = chain_view
+            .get_finalized_cursor()
+            .await
+            .change_context(ServerError)
+            .attach_printable("failed to get finalized cursor")?;
+
+        // The stream starts after the starting cursor, so start from the parent of the
+        // finalized block.
+        let starting = match finalized.number.checked_sub(1) {
+            None => None,
+            Some(parent) => match chain_view
+                .get_canonical(parent)
+                .await
+                .change_context(ServerError)
+                .attach_printable("failed to get finalized block parent")?
+            {
+                CanonicalCursor::Canonical(cursor) => Some(cursor),
+                _ => None,
+            },
+        };
+
+        let mut block_filter = BlockFilter::default();
+        block_filter.set_header_filter(HeaderFilter::Always);
+
+        let permit = Arc::new(Semaphore::new(1))
+            .acquire_owned()
+            .await
+            .change_context(ServerError)?;
+        let stream_memory_budget = self.settings.borrow().stream_memory_budget;
+        let memory_budget =
+            StreamMemoryBudget::new(stream_memory_budget, self.metrics.buffered_bytes.clone());
+
+        let ds = DataStream::new(
+            vec![block_filter],
+            SELF_TEST_FILTER_KEY,
+            self.shared_filter.clone(),
+            memory_budget,
+            starting,
+            Some(finalized.clone()),
+            finalized.clone(),
+            DataFinality::Finalized,
+            chain_view,
+            self.fragment_id_to_name.clone(),
+            self.block_store.clone(),
+            self.options.prefetch_segment_count,
+            self.options.prefetch_block_count,
+            self.options.server_id.clone(),
+            self.options.slow_consumer.clone(),
+            CancellationToken::new(),
+            permit,
+            self.metrics.clone(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(SELF_TEST_CHANNEL_SIZE);
+        let stream_ct = ct.child_token();
+        let stream = tokio::spawn(ds.start(tx, stream_ct.clone()));
+        // Stop the internal stream if the self-test returns early.
+        let _stream_guard = stream_ct.drop_guard();
+
+        let mut streamed = None;
+        while let Some(message) = rx.recv().await {
+            let response = match message.into_response() {
+                Ok(response) => response,
+                Err(status) => {
+                    return Err(ServerError)
+                        .attach_printable("self-test stream returned an error")
+                        .attach_printable(format!("status: {status:?}"));
+                }
+            };
+
+            match response.message {
+                Some(Message::Data(data)) => {
+                    if data.data.first().is_none_or(|header| header.is_empty()) {
+                        return Err(ServerError)
+                            .attach_printable("self-test stream returned a block without header");
+                    }
+
+                    if let Some(checksum) = data.checksum {
+                        if checksum != data_checksum(&data.data) {
+                            return Err(ServerError)
+                                .attach_printable("self-test stream returned corrupted data");
+                        }
+                    }
+
+                    streamed = data.end_cursor.map(Cursor::from);
+                }
+                Some(Message::RangeComplete(_)) => break,
+                _ => {}
+            }
+        }
+
+        stream
+            .await
+            .change_context(ServerError)
+            .attach_printable("self-test stream panicked")?
+            .change_context(ServerError)
+            .attach_printable("self-test stream failed")?;
+
+        match streamed {
+            Some(cursor) if cursor == finalized => Ok(cursor),
+            Some(cursor) => Err(ServerError)
+                .attach_printable("self-test stream ended at the wrong block")
+                .attach_printable(format!("expected: {finalized}, got: {cursor}")),
+            None => Err(ServerError).attach_printable("self-test stream returned no data"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        block_store::BlockStoreReader,
+        data_stream::{DataStreamMetrics, FilterResultCache, SharedFilterCache},
+        new_test_cursor,
+        object_store::ObjectStore,
+        server::testing::{
+            fragment_id_to_name, runtime_settings, stream_service_options, TestChain,
+            TEST_CHAIN_HEAD,
+        },
+    };
+
+    use super::SelfTest;
+
+    fn new_self_test(chain: &TestChain, block_store: BlockStoreReader) -> SelfTest {
+        let metrics = DataStreamMetrics::default();
+        let shared_filter =
+            SharedFilterCache::new(1024 * 1024, FilterResultCache::new(0), metrics.clone());
+        SelfTest::new(
+            chain.chain_view_rx(),
+            fragment_id_to_name(),
+            block_store,
+            shared_filter,
+            stream_service_options(),
+            runtime_settings(),
+            metrics,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_self_test_streams_finalized_block() {
+        let chain = TestChain::new().await;
+        let self_test = new_self_test(&chain, chain.block_store());
+
+        let cursor = self_test.run(CancellationToken::new()).await.unwrap();
+        assert_eq!(cursor, new_test_cursor(TEST_CHAIN_HEAD, 0));
+    }
+
+    #[tokio::test]
+    async fn test_self_test_fails_without_block_data() {
+        let chain = TestChain::new().await;
+        let empty_store = ObjectStore::new_in_memory(Default::default());
+        let self_test = new_self_test(
+            &chain,
+            BlockStoreReader::new(empty_store, chain.file_cache.clone()),
+        );
+
+        assert!(self_test.run(CancellationToken::new()).await.is_err());
+    }
+}
@@ -1,4 +1,11 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use apibara_dna_protocol::dna::stream::{
     dna_stream_server::{self, DnaStream},
@@ -6,29 +13,63 @@ use apibara_dna_protocol::dna::stream::{
 };
 use error_stack::Result;
 use futures::{Future, TryFutureExt};
-use tokio::sync::{mpsc, Semaphore};
+use tokio::sync::{mpsc, watch, Semaphore};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, info_span, Instrument};
 
 use crate::{
     block_store::BlockStoreReader,
     chain_view::{CanonicalCursor, ChainView, ChainViewError, ValidatedCursor},
-    data_stream::{BlockFilterFactory, DataStream, DataStreamMetrics},
+    data_stream::{
+        filter_key, BlockFilterFactory, DataStream, DataStreamMetrics, FilterResultCache,
+        SharedFilterCache, SlowConsumerPolicy, StreamMemoryBudget,
+    },
+    dataset_manifest::{DatasetManifest, DatasetManifestStore},
     fragment::FragmentId,
-    server::stream_with_heartbeat::ResponseStreamWithHeartbeat,
+    index::ScalarValue,
+    query::BlockFilter,
+    reload::RuntimeSettings,
+    server::{
+        active_streams::ActiveStreams,
+        self_test::SelfTest,
+        sessions::{SessionToken, StreamSessions},
+        stream_with_heartbeat::{ResponseStreamWithHeartbeat, SharedReceiver},
+    },
     Cursor,
 };
 
 const CHANNEL_SIZE: usize = 1024;
 
+/// Response header with the token used to resume the stream session.
+const SESSION_TOKEN_HEADER: &str = "x-dna-session-token";
+
 static STREAM_SEMAPHORE_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// Maximum number of filters in a stream request, regardless of their conditions.
+const MAX_BLOCK_FILTERS: usize = 256;
+
 #[derive(Debug, Clone)]
 pub struct StreamServiceOptions {
     /// Maximum number of concurrent streams.
     pub max_concurrent_streams: usize,
     /// Number of segments to prefetch.
     pub prefetch_segment_count: usize,
+    /// Number of blocks to prefetch.
+    pub prefetch_block_count: usize,
+    /// Maximum size of the filtered segments shared between streams, in bytes.
+    pub shared_filter_cache_size: usize,
+    /// Maximum size of the cached filter results, in bytes. Zero disables the cache.
+    pub filter_result_cache_size: usize,
+    /// Maximum size of the messages buffered by each stream, in bytes.
+    pub stream_memory_budget: usize,
+    /// Identifies the server in the data sent to clients.
+    pub server_id: Option<String>,
+    /// Maximum number of filter conditions in a stream request.
+    pub max_filter_conditions: usize,
+    /// Maximum size of the stream sessions, in bytes. Zero disables sessions.
+    pub session_cache_size: usize,
+    /// How to handle clients that stop reading their stream.
+    pub slow_consumer: SlowConsumerPolicy,
 }
 
 pub struct StreamService<BFF>
@@ -37,11 +78,19 @@ where
 {
     filter_factory: BFF,
     stream_semaphore: Arc<Semaphore>,
+    max_concurrent_streams: Arc<AtomicUsize>,
+    /// Permits to remove from the stream semaphore once active streams release them.
+    excess_stream_permits: Arc<AtomicUsize>,
+    settings: watch::Receiver<RuntimeSettings>,
     chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
     fragment_id_to_name: HashMap<FragmentId, String>,
     block_store: BlockStoreReader,
+    manifest_store: DatasetManifestStore,
     options: StreamServiceOptions,
+    shared_filter: SharedFilterCache,
     metrics: DataStreamMetrics,
+    active_streams: ActiveStreams,
+    sessions: StreamSessions,
     ct: CancellationToken,
 }
 
@@ -49,23 +98,42 @@ impl<BFF> StreamService<BFF>
 where
     BFF: BlockFilterFactory,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         filter_factory: BFF,
         chain_view: tokio::sync::watch::Receiver<Option<ChainView>>,
         fragment_id_to_name: HashMap<FragmentId, String>,
         block_store: BlockStoreReader,
+        manifest_store: DatasetManifestStore,
         options: StreamServiceOptions,
+        settings: watch::Receiver<RuntimeSettings>,
+        active_streams: ActiveStreams,
         ct: CancellationToken,
     ) -> Self {
-        let stream_semaphore = Arc::new(Semaphore::new(options.max_concurrent_streams));
+        let max_concurrent_streams = settings.borrow().max_concurrent_streams;
+        let stream_semaphore = Arc::new(Semaphore::new(max_concurrent_streams));
+        let metrics = DataStreamMetrics::default();
+        let shared_filter = SharedFilterCache::new(
+            options.shared_filter_cache_size,
+            FilterResultCache::new(options.filter_result_cache_size),
+            metrics.clone(),
+        );
+        let sessions = StreamSessions::new(options.session_cache_size);
         Self {
             filter_factory,
             stream_semaphore,
+            max_concurrent_streams: Arc::new(AtomicUsize::new(max_concurrent_streams)),
+            excess_stream_permits: Arc::default(),
+            settings,
             chain_view,
             fragment_id_to_name,
             block_store,
+            manifest_store,
             options,
-            metrics: Default::default(),
+            shared_filter,
+            metrics,
+            active_streams,
+            sessions,
             ct,
         }
     }
@@ -75,12 +143,89 @@ where
     }
 
     pub fn current_stream_count(&self) -> usize {
-        self.options.max_concurrent_streams - self.stream_semaphore.available_permits()
+        let total_permits = self.max_concurrent_streams.load(Ordering::Relaxed)
+            + self.excess_stream_permits.load(Ordering::Relaxed);
+        total_permits.saturating_sub(self.stream_semaphore.available_permits())
     }
 
     pub fn current_stream_available(&self) -> usize {
         self.stream_semaphore.available_permits()
     }
+
+    /// Returns the self-test, which streams data using the same components as this service.
+    pub fn self_test(&self) -> SelfTest {
+        SelfTest::new(
+            self.chain_view.clone(),
+            self.fragment_id_to_name.clone(),
+            self.block_store.clone(),
+            self.shared_filter.clone(),
+            self.options.clone(),
+            self.settings.clone(),
+            self.metrics.clone(),
+        )
+    }
+
+    /// Returns a future that resizes the stream semaphore when the maximum number
+    /// of concurrent streams changes.
+    ///
+    /// Active streams are never closed. When the limit decreases, new streams are
+    /// rejected until enough streams finish. The permits of the finished streams
+    /// are removed as they're released, without blocking further limit changes.
+    pub fn stream_limit_loop(&self, ct: CancellationToken) -> impl Future<Output = ()> {
+        let semaphore = self.stream_semaphore.clone();
+        let max_concurrent_streams = self.max_concurrent_streams.clone();
+        let excess_permits = self.excess_stream_permits.clone();
+        let mut settings = self.settings.clone();
+
+        async move {
+            loop {
+                let has_excess = excess_permits.load(Ordering::Relaxed) > 0;
+
+                tokio::select! {
+                    _ = ct.cancelled() => break,
+                    changed = settings.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    permit = semaphore.acquire(), if has_excess => {
+                        if let Ok(permit) = permit {
+                            permit.forget();
+                            excess_permits.fetch_sub(1, Ordering::Relaxed);
+                        }
+                        continue;
+                    }
+                }
+
+                let new_max = settings.borrow_and_update().max_concurrent_streams;
+                let current_max = max_concurrent_streams.swap(new_max, Ordering::Relaxed);
+
+                if new_max == current_max {
+                    continue;
+                }
+
+                info!(
+                    max_concurrent_streams = new_max,
+                    "updating maximum concurrent streams"
+                );
+
+                if new_max > current_max {
+                    // Cancel the permits that are still waiting to be removed first.
+                    let mut added = new_max - current_max;
+                    let excess = excess_permits.load(Ordering::Relaxed);
+                    let cancelled = added.min(excess);
+                    excess_permits.fetch_sub(cancelled, Ordering::Relaxed);
+                    added -= cancelled;
+                    semaphore.add_permits(added);
+                    continue;
+                }
+
+                let mut missing = current_max - new_max;
+                missing -= semaphore.forget_permits(missing);
+                excess_permits.fetch_add(missing, Ordering::Relaxed);
+            }
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -99,11 +244,21 @@ where
             return Err(tonic::Status::unavailable("chain view not initialized yet"));
         };
 
-        let response = chain_view.get_status().await.map_err(|err| {
+        let mut response = chain_view.get_status().await.map_err(|err| {
             error!(error = ?err, "DnaStream::status error");
             tonic::Status::internal("internal server error")
         })?;
 
+        response.manifest = self
+            .manifest_store
+            .get_cached()
+            .await
+            .map_err(|err| {
+                error!(error = ?err, "DnaStream::status error");
+                tonic::Status::internal("internal server error")
+            })?
+            .map(DatasetManifest::into);
+
         Ok(tonic::Response::new(response))
     }
 
@@ -118,6 +273,7 @@ where
     ) -> tonic::Result<tonic::Response<Self::StreamDataStream>, tonic::Status> {
         let current_span = tracing::Span::current();
 
+        let api_key = bearer_token(request.metadata());
         let request = request.into_inner();
         info!(request = ?request, "stream data request");
 
@@ -125,6 +281,33 @@ where
             return Err(tonic::Status::unavailable("chain view not initialized yet"));
         };
 
+        let session_token = request
+            .session_token
+            .as_deref()
+            .map(|token| {
+                SessionToken::parse(token)
+                    .ok_or_else(|| tonic::Status::invalid_argument("invalid session token"))
+            })
+            .transpose()?;
+
+        // Sessions are kept by each server, clients that reconnect to another server
+        // or after the session expired send their filters again.
+        let session = match &session_token {
+            None => None,
+            Some(token) => match self.sessions.get(token, api_key.as_deref()) {
+                Some(session) => {
+                    debug!("resuming stream session");
+                    Some(session)
+                }
+                None if request.filter.is_empty() => {
+                    return Err(tonic::Status::not_found(
+                        "stream session not found or expired",
+                    ));
+                }
+                None => None,
+            },
+        };
+
         let permit = match tokio::time::timeout(
             STREAM_SEMAPHORE_ACQUIRE_TIMEOUT,
             self.stream_semaphore.clone().acquire_owned(),
@@ -141,9 +324,33 @@ where
         current_span.record("stream_count", self.current_stream_count());
         current_span.record("stream_available", self.current_stream_available());
 
+        let start_at_head = request.start_at_head.unwrap_or(false);
+        if start_at_head && request.starting_cursor.is_some() {
+            return Err(tonic::Status::invalid_argument(
+                "starting cursor cannot be used together with start at head",
+            ));
+        }
+
+        // Resumed sessions restart from the session's starting cursor, unless the
+        // client sends the cursor of the last message it received.
+        let requested_starting_cursor = match &session_token {
+            Some(token) if !start_at_head => request
+                .starting_cursor
+                .clone()
+                .or_else(|| token.cursor.clone()),
+            _ => request.starting_cursor.clone(),
+        };
+
         // Validate starting cursor by checking it's in range.
         // The block could be reorged but that's handled by the `DataStream`.
-        let starting_cursor = if let Some(cursor) = request.starting_cursor {
+        let starting_cursor = if start_at_head {
+            let head = chain_view
+                .get_head()
+                .await
+                .map_err(|_| tonic::Status::internal("internal server error"))?;
+            debug!(head = %head, "starting stream at head");
+            Some(head)
+        } else if let Some(cursor) = requested_starting_cursor {
             let cursor = Cursor::from(cursor);
             debug!(cursor = %cursor, "starting cursor before validation");
             chain_view.ensure_cursor_in_range(&cursor).await?;
@@ -172,6 +379,18 @@ where
             None
         };
 
+        // The stream starts after the starting cursor, so the range must contain at least
+        // one block.
+        let ending_cursor = request.ending_cursor.map(Cursor::from);
+        if let (Some(starting), Some(ending)) = (&starting_cursor, &ending_cursor) {
+            if ending.number <= starting.number {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "ending cursor {} must be after the starting cursor {}",
+                    ending.number, starting.number
+                )));
+            }
+        }
+
         let finalized = chain_view
             .get_finalized_cursor()
             .await
@@ -183,6 +402,7 @@ where
             .map(TryFrom::try_from)
             .transpose()
             .map_err(|_| tonic::Status::invalid_argument("invalid finality"))?
+            .or(session.as_ref().map(|session| session.session().finality))
             .unwrap_or(DataFinality::Accepted);
 
         let heartbeat_interval = request
@@ -192,34 +412,139 @@ where
             .map_err(|_| tonic::Status::invalid_argument("invalid heartbeat interval"))
             .and_then(validate_heartbeat_interval)?;
 
-        // Parse and validate filter.
-        let filter = self.filter_factory.create_block_filter(&request.filter)?;
+        let (filter, filter_key, session) = match session {
+            Some(session) => (
+                session.session().filter.clone(),
+                session.session().filter_key,
+                Some(session),
+            ),
+            None => {
+                if request.filter.len() > MAX_BLOCK_FILTERS {
+                    return Err(tonic::Status::invalid_argument(format!(
+                        "too many filters ({} > {})",
+                        request.filter.len(),
+                        MAX_BLOCK_FILTERS
+                    )));
+                }
+
+                // Parse and validate filter.
+                let filter = self.filter_factory.create_block_filter(&request.filter)?;
+                let condition_count = filter
+                    .iter()
+                    .map(BlockFilter::condition_count)
+                    .sum::<usize>();
+                if condition_count > self.options.max_filter_conditions {
+                    return Err(tonic::Status::invalid_argument(format!(
+                        "too many filter conditions ({} > {})",
+                        condition_count, self.options.max_filter_conditions
+                    )));
+                }
+
+                let filter_key = filter_key(&request.filter);
+
+                // Sessions are opt-in, clients resuming a session keep using one.
+                let session = if request.create_session.unwrap_or(false) || session_token.is_some()
+                {
+                    let size_bytes = request.filter.iter().map(Vec::len).sum::<usize>()
+                        + condition_count * std::mem::size_of::<ScalarValue>();
+                    self.sessions.create(
+                        api_key.clone(),
+                        filter.clone(),
+                        filter_key,
+                        finality,
+                        size_bytes,
+                    )
+                } else {
+                    None
+                };
+
+                (filter, filter_key, session)
+            }
+        };
+
+        let session_token = session
+            .as_ref()
+            .map(|session| {
+                let token = session
+                    .session()
+                    .token(starting_cursor.clone().map(Into::into));
+                tonic::metadata::AsciiMetadataValue::try_from(token.encode())
+                    .map_err(|_| tonic::Status::internal("internal server error"))
+            })
+            .transpose()?;
+
+        let stream_memory_budget = self.settings.borrow().stream_memory_budget;
+        let memory_budget =
+            StreamMemoryBudget::new(stream_memory_budget, self.metrics.buffered_bytes.clone());
+
+        let active_stream = self.active_streams.register(
+            api_key.as_deref(),
+            starting_cursor.clone().map(Into::into),
+            finality,
+            &self.ct,
+        );
+        debug!(stream_id = active_stream.stream_id(), "stream registered");
+
+        let disconnected = CancellationToken::new();
 
         let ds = DataStream::new(
             filter,
+            filter_key,
+            self.shared_filter.clone(),
+            memory_budget,
             starting_cursor,
+            ending_cursor,
             finalized,
             finality,
             chain_view,
             self.fragment_id_to_name.clone(),
             self.block_store.clone(),
             self.options.prefetch_segment_count,
+            self.options.prefetch_block_count,
+            self.options.server_id.clone(),
+            self.options.slow_consumer.clone(),
+            disconnected.clone(),
             permit,
             self.metrics.clone(),
         );
         let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let rx = SharedReceiver::new(rx);
+
+        tokio::spawn(
+            rx.clone()
+                .run_producer(
+                    disconnected.clone(),
+                    ds.start(tx, active_stream.cancellation_token()),
+                )
+                .inspect_err(|err| {
+                    error!(error = ?err, "data stream error");
+                })
+                .instrument(info_span!(
+                    "data_stream",
+                    stream_id = active_stream.stream_id()
+                )),
+        );
 
-        tokio::spawn(ds.start(tx, self.ct.clone()).inspect_err(|err| {
-            error!(error = ?err, "data stream error");
-        }));
+        let stream = ResponseStreamWithHeartbeat::new(
+            rx,
+            heartbeat_interval,
+            active_stream,
+            session,
+            disconnected,
+        );
 
-        let stream = ResponseStreamWithHeartbeat::new(rx, heartbeat_interval);
+        let mut response = tonic::Response::new(stream);
+        if let Some(session_token) = session_token {
+            response
+                .metadata_mut()
+                .insert(SESSION_TOKEN_HEADER, session_token);
+        }
 
-        Ok(tonic::Response::new(stream))
+        Ok(response)
     }
 }
 
-trait ChainViewExt {
+pub(super) trait ChainViewExt {
     fn get_status(&self) -> impl Future<Output = Result<StatusResponse, ChainViewError>> + Send;
     fn ensure_cursor_in_range(
         &self,
@@ -238,6 +563,7 @@ impl ChainViewExt for ChainView {
             last_ingested: Some(head.into()),
             finalized: Some(finalized.into()),
             starting: Some(starting.into()),
+            manifest: None,
         })
     }
 
@@ -264,6 +590,15 @@ impl ChainViewExt for ChainView {
     }
 }
 
+/// Returns the bearer token in the `authorization` header, if any.
+pub(super) fn bearer_token(metadata: &tonic::metadata::MetadataMap) -> Option<String> {
+    let value = metadata.get("authorization")?.to_str().ok()?;
+    value
+        .strip_prefix("Bearer ")
+        .or_else(|| value.strip_prefix("bearer "))
+        .map(str::to_string)
+}
+
 fn validate_heartbeat_interval(
     heartbeat_interval: Option<Duration>,
 ) -> tonic::Result<Duration, tonic::Status> {
@@ -285,3 +620,184 @@ fn validate_heartbeat_interval(
         Ok(heartbeat_interval)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use apibara_dna_protocol::dna::stream::{
+        dna_stream_server::DnaStream, stream_data_response::Message, DataFinality,
+        StreamDataRequest, StreamDataResponse,
+    };
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        dataset_manifest::DatasetManifestStore,
+        new_test_cursor,
+        server::{
+            active_streams::ActiveStreams,
+            testing::{
+                fragment_id_to_name, runtime_settings, stream_service_options, HeaderFilterFactory,
+                TestChain, TEST_CHAIN_HEAD,
+            },
+        },
+        Cursor,
+    };
+
+    use super::StreamService;
+
+    fn new_stream_service(chain: &TestChain) -> StreamService<HeaderFilterFactory> {
+        StreamService::new(
+            HeaderFilterFactory,
+            chain.chain_view_rx(),
+            fragment_id_to_name(),
+            chain.block_store(),
+            DatasetManifestStore::new(chain.object_store.clone()),
+            stream_service_options(),
+            runtime_settings(),
+            ActiveStreams::default(),
+            CancellationToken::new(),
+        )
+    }
+
+    fn stream_request(starting_block: u64, ending_block: u64) -> StreamDataRequest {
+        StreamDataRequest {
+            starting_cursor: Some(new_test_cursor(starting_block, 0).into()),
+            ending_cursor: Some(new_test_cursor(ending_block, 0).into()),
+            finality: Some(DataFinality::Finalized as i32),
+            filter: vec![Vec::default()],
+            ..Default::default()
+        }
+    }
+
+    /// Returns all messages sent before the stream closes.
+    async fn collect_messages(
+        service: &StreamService<HeaderFilterFactory>,
+        request: StreamDataRequest,
+    ) -> Vec<Message> {
+        let stream = service
+            .stream_data(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+
+        stream
+            .map(|response| response.unwrap())
+            .filter_map(|response: StreamDataResponse| async move { response.message })
+            .filter(|message| std::future::ready(!matches!(message, Message::Heartbeat(_))))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_ending_cursor_inside_segment() {
+        let chain = TestChain::new().await;
+        let service = new_stream_service(&chain);
+
+        // Blocks 4 to 7 are in the same segment.
+        let messages = collect_messages(&service, stream_request(1, 5)).await;
+
+        let (last, data) = messages.split_last().unwrap();
+        let streamed = data
+            .iter()
+            .map(|message| match message {
+                Message::Data(data) => Cursor::from(data.end_cursor.clone().unwrap()).number,
+                message => panic!("unexpected message: {message:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(streamed, vec![2, 3, 4, 5]);
+
+        let Message::RangeComplete(range_complete) = last else {
+            panic!("expected range complete, got: {last:?}");
+        };
+        assert_eq!(range_complete.cursor.as_ref().unwrap().order_key, 5);
+    }
+
+    #[tokio::test]
+    async fn test_ending_cursor_after_segments() {
+        let chain = TestChain::new().await;
+        let service = new_stream_service(&chain);
+
+        // Blocks 8 and 9 are not in a segment yet.
+        let messages = collect_messages(&service, stream_request(6, 9)).await;
+
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(messages.last(), Some(Message::RangeComplete(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reject_ending_cursor_not_after_starting_cursor() {
+        let chain = TestChain::new().await;
+        let service = new_stream_service(&chain);
+
+        for ending_block in [2, 3] {
+            let Err(status) = service
+                .stream_data(tonic::Request::new(stream_request(3, ending_block)))
+                .await
+            else {
+                panic!("expected ending cursor {ending_block} to be rejected");
+            };
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_start_at_head() {
+        let chain = TestChain::new().await;
+        let service = new_stream_service(&chain);
+
+        let request = StreamDataRequest {
+            start_at_head: Some(true),
+            finality: Some(DataFinality::Finalized as i32),
+            filter: vec![Vec::default()],
+            ..Default::default()
+        };
+
+        // The stream starts after the head, so none of the existing blocks are sent.
+        let stream = service
+            .stream_data(tonic::Request::new(request.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut data = stream.filter(|response| {
+            std::future::ready(!matches!(
+                response.as_ref().unwrap().message,
+                Some(Message::Heartbeat(_))
+            ))
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), data.next())
+                .await
+                .is_err()
+        );
+
+        // The head is the starting cursor, so the stream cannot end there.
+        let Err(status) = service
+            .stream_data(tonic::Request::new(StreamDataRequest {
+                ending_cursor: Some(new_test_cursor(TEST_CHAIN_HEAD, 0).into()),
+                ..request
+            }))
+            .await
+        else {
+            panic!("expected ending cursor at head to be rejected");
+        };
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_reject_start_at_head_with_starting_cursor() {
+        let chain = TestChain::new().await;
+        let service = new_stream_service(&chain);
+
+        let request = StreamDataRequest {
+            start_at_head: Some(true),
+            ..stream_request(3, 6)
+        };
+
+        let Err(status) = service.stream_data(tonic::Request::new(request)).await else {
+            panic!("expected start at head with a starting cursor to be rejected");
+        };
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use apibara_dna_protocol::dna::stream::{Cursor as ProtoCursor, DataFinality};
+
+use crate::{data_stream::FilterKey, query::BlockFilter};
+
+/// Sessions not used for this long are removed.
+const SESSION_TTL: Duration = Duration::from_secs(600);
+
+/// Stream sessions, used to resume streams without sending and compiling the filters again.
+///
+/// Sessions are kept in memory by each server, so clients must be ready to send
+/// their filters again when they reconnect to another server. The sessions' total
+/// size is bounded, the least recently used sessions are evicted first.
+///
+/// Sessions are disabled if the maximum size is zero.
+#[derive(Clone)]
+pub struct StreamSessions {
+    inner: Arc<Mutex<StreamSessionsInner>>,
+}
+
+/// The state of a stream needed to resume it.
+pub struct StreamSession {
+    session_id: u64,
+    /// The bearer token of the client that created the session.
+    pub api_key: Option<String>,
+    pub filter: Vec<BlockFilter>,
+    pub filter_key: FilterKey,
+    pub finality: DataFinality,
+    /// Estimate of the memory used by the session's filters.
+    size_bytes: usize,
+}
+
+/// A session in use by a stream.
+///
+/// The session's time to live starts again when the stream ends.
+pub struct StreamSessionLease {
+    sessions: StreamSessions,
+    session: Arc<StreamSession>,
+}
+
+/// The token used by clients to resume a session.
+///
+/// The token contains the session id, the filter hash and the cursor the stream
+/// started from. The cursor is the fallback starting cursor of the resumed stream,
+/// so that resuming never skips data the client didn't receive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionToken {
+    pub session_id: u64,
+    pub filter_key: FilterKey,
+    pub cursor: Option<ProtoCursor>,
+}
+
+struct StreamSessionsInner {
+    max_size_bytes: usize,
+    size_bytes: usize,
+    next_session_id: u64,
+    /// Incremented on each access, used to evict the least recently used sessions.
+    clock: u64,
+    sessions: HashMap<u64, SessionEntry>,
+    /// Session ids ordered by last use, oldest first.
+    by_last_used: BTreeMap<u64, u64>,
+}
+
+struct SessionEntry {
+    session: Arc<StreamSession>,
+    last_used: u64,
+    last_used_at: Instant,
+}
+
+impl StreamSessions {
+    pub fn new(max_size_bytes: usize) -> Self {
+        let inner = StreamSessionsInner {
+            max_size_bytes,
+            size_bytes: 0,
+            next_session_id: 0,
+            clock: 0,
+            sessions: HashMap::default(),
+            by_last_used: BTreeMap::default(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Create a new session.
+    ///
+    /// Removes the least recently used sessions to make space for the new one.
+    /// Returns `None` if sessions are disabled or the session is too large.
+    pub fn create(
+        &self,
+        api_key: Option<String>,
+        filter: Vec<BlockFilter>,
+        filter_key: FilterKey,
+        finality: DataFinality,
+        size_bytes: usize,
+    ) -> Option<StreamSessionLease> {
+        let mut inner = self.inner.lock().expect("stream sessions lock");
+
+        let size_bytes = std::mem::size_of::<StreamSession>() + size_bytes;
+        if size_bytes > inner.max_size_bytes {
+            return None;
+        }
+
+        inner.remove_expired();
+
+        while inner.size_bytes + size_bytes > inner.max_size_bytes {
+            let Some((&last_used, &oldest)) = inner.by_last_used.first_key_value() else {
+                break;
+            };
+            inner.by_last_used.remove(&last_used);
+            inner.remove(oldest);
+        }
+
+        let session_id = inner.next_session_id;
+        inner.next_session_id += 1;
+
+        let session = Arc::new(StreamSession {
+            session_id,
+            api_key,
+            filter,
+            filter_key,
+            finality,
+            size_bytes,
+        });
+
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        inner.size_bytes += size_bytes;
+        inner.by_last_used.insert(clock, session_id);
+        inner.sessions.insert(
+            session_id,
+            SessionEntry {
+                session: session.clone(),
+                last_used: clock,
+                last_used_at: Instant::now(),
+            },
+        );
+
+        Some(StreamSessionLease {
+            sessions: self.clone(),
+            session,
+        })
+    }
+
+    /// Returns the session with the given token, if it exists and belongs to the client.
+    pub fn get(&self, token: &SessionToken, api_key: Option<&str>) -> Option<StreamSessionLease> {
+        let mut inner = self.inner.lock().expect("stream sessions lock");
+        inner.remove_expired();
+
+        let session = inner.sessions.get(&token.session_id)?.session.clone();
+
+        if session.filter_key != token.filter_key || session.api_key.as_deref() != api_key {
+            return None;
+        }
+
+        inner.touch(token.session_id);
+
+        Some(StreamSessionLease {
+            sessions: self.clone(),
+            session,
+        })
+    }
+
+    fn touch(&self, session_id: u64) {
+        let mut inner = self.inner.lock().expect("stream sessions lock");
+        inner.touch(session_id);
+    }
+}
+
+impl StreamSessionsInner {
+    fn touch(&mut self, session_id: u64) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        let Some(entry) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        entry.last_used_at = Instant::now();
+
+        self.by_last_used.remove(&previous);
+        self.by_last_used.insert(clock, session_id);
+    }
+
+    fn remove(&mut self, session_id: u64) {
+        if let Some(entry) = self.sessions.remove(&session_id) {
+            self.by_last_used.remove(&entry.last_used);
+            self.size_bytes -= entry.session.size_bytes;
+        }
+    }
+
+    /// Remove the sessions not used for longer than the time to live.
+    fn remove_expired(&mut self) {
+        while let Some((&last_used, &oldest)) = self.by_last_used.first_key_value() {
+            let state = self.sessions.get(&oldest).map(|entry| {
+                (
+                    Arc::strong_count(&entry.session) > 1,
+                    entry.last_used_at.elapsed() > SESSION_TTL,
+                )
+            });
+
+            match state {
+                Some((_, false)) => break,
+                // Sessions used by a stream don't expire.
+                Some((true, true)) => self.touch(oldest),
+                Some((false, true)) | None => {
+                    self.by_last_used.remove(&last_used);
+                    self.remove(oldest);
+                }
+            }
+        }
+    }
+}
+
+impl StreamSessionLease {
+    pub fn session(&self) -> &StreamSession {
+        &self.session
+    }
+}
+
+impl Drop for StreamSessionLease {
+    fn drop(&mut self) {
+        self.sessions.touch(self.session.session_id);
+    }
+}
+
+impl StreamSession {
+    /// The token used by the client to resume the session from the given cursor.
+    pub fn token(&self, cursor: Option<ProtoCursor>) -> SessionToken {
+        SessionToken {
+            session_id: self.session_id,
+            filter_key: self.filter_key,
+            cursor,
+        }
+    }
+}
+
+impl SessionToken {
+    /// Encode the token as `<session id>.<filter hash>.<order key>-<unique key>`.
+    ///
+    /// The cursor part is empty if the token has no cursor.
+    pub fn encode(&self) -> String {
+        let cursor = match &self.cursor {
+            None => String::new(),
+            Some(cursor) => format!("{:x}-{}", cursor.order_key, hex::encode(&cursor.unique_key)),
+        };
+
+        format!(
+            "{:x}.{}.{}",
+            self.session_id,
+            hex::encode(self.filter_key),
+            cursor
+        )
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+        let (Some(session_id), Some(filter_key), Some(cursor), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+
+        let session_id = u64::from_str_radix(session_id, 16).ok()?;
+
+        let mut key = FilterKey::default();
+        hex::decode_to_slice(filter_key, &mut key).ok()?;
+
+        let cursor = if cursor.is_empty() {
+            None
+        } else {
+            let (order_key, unique_key) = cursor.split_once('-')?;
+            Some(ProtoCursor {
+                order_key: u64::from_str_radix(order_key, 16).ok()?,
+                unique_key: hex::decode(unique_key).ok()?,
+            })
+        };
+
+        Some(Self {
+            session_id,
+            filter_key: key,
+            cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_dna_protocol::dna::stream::{Cursor as ProtoCursor, DataFinality};
+
+    use super::{SessionToken, StreamSessions};
+
+    #[test]
+    fn test_session_token() {
+        let token = SessionToken {
+            session_id: 42,
+            filter_key: [1; 32],
+            cursor: Some(ProtoCursor {
+                order_key: 100,
+                unique_key: vec![0xab; 32],
+            }),
+        };
+        assert_eq!(SessionToken::parse(&token.encode()), Some(token));
+
+        let token = SessionToken {
+            session_id: 0,
+            filter_key: [2; 32],
+            cursor: None,
+        };
+        assert_eq!(SessionToken::parse(&token.encode()), Some(token));
+
+        assert!(SessionToken::parse("not-a-token").is_none());
+        assert!(SessionToken::parse("").is_none());
+        assert!(SessionToken::parse(&format!("0.{}", hex::encode([1; 32]))).is_none());
+        assert!(SessionToken::parse(&format!("0.{}.zz-00", hex::encode([1; 32]))).is_none());
+    }
+
+    #[test]
+    fn test_stream_sessions() {
+        let sessions = StreamSessions::new(1024);
+
+        let session = sessions
+            .create(
+                Some("my-key".to_string()),
+                Vec::default(),
+                [1; 32],
+                DataFinality::Accepted,
+                0,
+            )
+            .unwrap();
+
+        let token = session
+            .session()
+            .token(Some(ProtoCursor::new_finalized(100)));
+        let token = SessionToken::parse(&token.encode()).unwrap();
+        assert_eq!(token.cursor, Some(ProtoCursor::new_finalized(100)));
+
+        let resumed = sessions.get(&token, Some("my-key")).unwrap();
+        assert_eq!(resumed.session().filter_key, [1; 32]);
+
+        // Sessions belong to the client that created them.
+        assert!(sessions.get(&token, None).is_none());
+        assert!(sessions.get(&token, Some("other-key")).is_none());
+
+        // The filter hash must match.
+        let other_filter = SessionToken {
+            filter_key: [2; 32],
+            ..token
+        };
+        assert!(sessions.get(&other_filter, Some("my-key")).is_none());
+    }
+
+    #[test]
+    fn test_stream_sessions_size() {
+        let sessions = StreamSessions::new(1024);
+
+        let create = |filter_key: u8, size_bytes: usize| {
+            sessions
+                .create(
+                    None,
+                    Vec::default(),
+                    [filter_key; 32],
+                    DataFinality::Accepted,
+                    size_bytes,
+                )
+                .map(|lease| lease.session().token(None))
+        };
+
+        // Sessions larger than the maximum size are not stored.
+        assert!(create(0, 2048).is_none());
+        assert_eq!(sessions.inner.lock().unwrap().size_bytes, 0);
+
+        let first = create(1, 300).unwrap();
+        let second = create(2, 300).unwrap();
+        assert!(sessions.get(&first, None).is_some());
+
+        // The least recently used session is evicted to make space.
+        let third = create(3, 300).unwrap();
+        assert!(sessions.get(&first, None).is_some());
+        assert!(sessions.get(&second, None).is_none());
+        assert!(sessions.get(&third, None).is_some());
+        assert!(sessions.inner.lock().unwrap().size_bytes <= 1024);
+
+        let disabled = StreamSessions::new(0);
+        assert!(disabled
+            .create(None, Vec::default(), [1; 32], DataFinality::Accepted, 0)
+            .is_none());
+    }
+}
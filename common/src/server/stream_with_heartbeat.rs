@@ -1,5 +1,7 @@
 use std::{
+    future::Future,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
     time::Duration,
 };
@@ -7,22 +9,67 @@ use std::{
 use apibara_dna_protocol::dna::stream::{stream_data_response, StreamDataResponse};
 use futures::Stream;
 use tokio::{sync::mpsc, time::Interval};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    data_stream::DataStreamMessage,
+    server::{active_streams::ActiveStreamGuard, sessions::StreamSessionLease},
+};
+
+/// The receiving end of a stream's messages, shared with the task producing them.
+///
+/// The gRPC server doesn't poll the response of a client without flow-control
+/// window, so the response can't drop the messages buffered for a client that
+/// stopped reading. The producer closes and drains the channel instead.
+#[derive(Clone)]
+pub struct SharedReceiver(Arc<Mutex<mpsc::Receiver<DataStreamMessage>>>);
 
 pub struct ResponseStreamWithHeartbeat {
-    rx: mpsc::Receiver<Result<StreamDataResponse, tonic::Status>>,
+    rx: SharedReceiver,
     interval: Interval,
+    active_stream: ActiveStreamGuard,
+    /// The session's time to live restarts when the stream ends.
+    _session: Option<StreamSessionLease>,
+    /// Cancelled when the client is disconnected for reading too slowly.
+    disconnected: CancellationToken,
+    is_done: bool,
 }
 
 impl ResponseStreamWithHeartbeat {
     pub fn new(
-        rx: mpsc::Receiver<Result<StreamDataResponse, tonic::Status>>,
+        rx: SharedReceiver,
         heartbeat_interval: Duration,
+        active_stream: ActiveStreamGuard,
+        session: Option<StreamSessionLease>,
+        disconnected: CancellationToken,
     ) -> Self {
         let mut interval = tokio::time::interval(heartbeat_interval);
         interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         interval.reset();
 
-        Self { rx, interval }
+        Self {
+            rx,
+            interval,
+            active_stream,
+            _session: session,
+            disconnected,
+            is_done: false,
+        }
+    }
+
+    /// Track the cursor of the messages sent to the client.
+    fn update_cursor(&self, response: &StreamDataResponse) {
+        use stream_data_response::Message;
+
+        let cursor = match &response.message {
+            Some(Message::Data(data)) => data.end_cursor.clone(),
+            Some(Message::Invalidate(invalidate)) => invalidate.cursor.clone(),
+            _ => None,
+        };
+
+        if let Some(cursor) = cursor {
+            self.active_stream.set_cursor(cursor);
+        }
     }
 }
 
@@ -30,9 +77,24 @@ impl Stream for ResponseStreamWithHeartbeat {
     type Item = Result<StreamDataResponse, tonic::Status>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.is_done {
+            return Poll::Ready(None);
+        }
+
+        // The producer already dropped the buffered messages, the client is too far
+        // behind to catch up.
+        if self.disconnected.is_cancelled() {
+            self.is_done = true;
+            return Poll::Ready(Some(Err(disconnected_status())));
+        }
+
         if let Poll::Ready(data) = self.rx.poll_recv(cx) {
             self.interval.reset();
-            return Poll::Ready(data);
+            let response = data.map(DataStreamMessage::into_response);
+            if let Some(Ok(response)) = &response {
+                self.update_cursor(response);
+            }
+            return Poll::Ready(response);
         }
 
         if self.interval.poll_tick(cx).is_ready() {
@@ -46,3 +108,120 @@ impl Stream for ResponseStreamWithHeartbeat {
         Poll::Pending
     }
 }
+
+impl SharedReceiver {
+    pub fn new(rx: mpsc::Receiver<DataStreamMessage>) -> Self {
+        Self(Arc::new(Mutex::new(rx)))
+    }
+
+    /// Run the task producing the stream's messages.
+    ///
+    /// If the producer disconnects the client, close the channel and drop the
+    /// messages buffered for it.
+    pub async fn run_producer<T>(
+        self,
+        disconnected: CancellationToken,
+        producer: impl Future<Output = T>,
+    ) -> T {
+        let result = producer.await;
+
+        if disconnected.is_cancelled() {
+            let mut rx = self.0.lock().expect("stream receiver lock");
+            rx.close();
+            while rx.try_recv().is_ok() {}
+        }
+
+        result
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<DataStreamMessage>> {
+        let mut rx = self.0.lock().expect("stream receiver lock");
+        rx.poll_recv(cx)
+    }
+}
+
+fn disconnected_status() -> tonic::Status {
+    tonic::Status::resource_exhausted("stream disconnected because the client is not reading data")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use apibara_dna_protocol::dna::stream::{
+        stream_data_response::Message, DataFinality, Heartbeat, StreamDataResponse,
+    };
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        data_stream::{DataStreamMessage, StreamMemoryBudget},
+        server::active_streams::ActiveStreams,
+    };
+
+    use super::{ResponseStreamWithHeartbeat, SharedReceiver};
+
+    #[tokio::test]
+    async fn test_disconnect_stalled_client() {
+        let buffered_bytes = apibara_observability::meter("test")
+            .i64_up_down_counter("test.buffered_bytes")
+            .build();
+        let budget = StreamMemoryBudget::new(100, buffered_bytes);
+
+        let (tx, rx) = mpsc::channel(4);
+        let rx = SharedReceiver::new(rx);
+        let disconnected = CancellationToken::new();
+
+        let active_streams = ActiveStreams::default();
+        let active_stream = active_streams.register(
+            None,
+            None,
+            DataFinality::Accepted,
+            &CancellationToken::new(),
+        );
+
+        // The client never reads the stream.
+        let mut stream = ResponseStreamWithHeartbeat::new(
+            rx.clone(),
+            Duration::from_secs(30),
+            active_stream,
+            None,
+            disconnected.clone(),
+        );
+
+        // Send messages until the channel is full, then disconnect the client.
+        let producer = {
+            let budget = budget.clone();
+            let disconnected = disconnected.clone();
+            async move {
+                loop {
+                    let reservation = budget.reserve(10).await;
+                    let message = StreamDataResponse {
+                        message: Some(Message::Heartbeat(Heartbeat::default())),
+                    };
+                    let message = DataStreamMessage::new(Ok(message), Some(reservation));
+
+                    let sent =
+                        tokio::time::timeout(Duration::from_millis(50), tx.send(message)).await;
+                    if sent.is_err() {
+                        disconnected.cancel();
+                        break;
+                    }
+                }
+            }
+        };
+
+        tokio::spawn(rx.run_producer(disconnected, producer))
+            .await
+            .unwrap();
+
+        // The buffered messages are dropped without polling the stream.
+        let reserved = tokio::time::timeout(Duration::from_millis(50), budget.reserve(100)).await;
+        assert!(reserved.is_ok());
+
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(stream.next().await.is_none());
+    }
+}
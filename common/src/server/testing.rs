@@ -0,0 +1,177 @@
+//! A small chain and the services built on it, used to test the server.
+use std::{collections::HashMap, time::Duration};
+
+use tempfile::TempDir;
+use tokio::sync::watch;
+
+use crate::{
+    block_store::{BlockStoreReader, BlockStoreWriter},
+    chain_store::ChainStore,
+    chain_view::{ChainView, SegmentLayout},
+    compaction::SegmentBuilder,
+    data_stream::{BlockFilterFactory, SlowConsumerPolicy},
+    file_cache::{testing::temp_file_cache, FileCache},
+    fragment::{
+        Block, FragmentId, HeaderFragment, IndexGroupFragment, JoinGroupFragment,
+        HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME, INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME,
+        JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME,
+    },
+    new_test_cursor,
+    object_store::ObjectStore,
+    query::{BlockFilter, HeaderFilter},
+    reload::RuntimeSettings,
+};
+
+use super::StreamServiceOptions;
+
+/// The last block of the test chain. All blocks are finalized.
+pub const TEST_CHAIN_HEAD: u64 = 9;
+
+/// Blocks 0 to 7 are stored in segments of this size.
+pub const TEST_SEGMENT_SIZE: u64 = 4;
+
+/// The blocks of a test chain, stored in an in-memory object store.
+pub struct TestChain {
+    _cache_dir: TempDir,
+    pub object_store: ObjectStore,
+    pub file_cache: FileCache,
+    pub chain_view: ChainView,
+}
+
+/// Sends the header of all blocks, whatever filter the client sends.
+pub struct HeaderFilterFactory;
+
+impl TestChain {
+    /// Returns a chain of blocks 0 to 9, where blocks 0 to 7 are stored in segments.
+    pub async fn new() -> Self {
+        let (cache_dir, file_cache) = temp_file_cache().await.unwrap();
+        let object_store = ObjectStore::new_in_memory(Default::default());
+        let writer = BlockStoreWriter::new(object_store.clone());
+
+        for block_number in 0..=TEST_CHAIN_HEAD {
+            writer
+                .put_block(&new_test_cursor(block_number, 0), &new_block(block_number))
+                .await
+                .unwrap();
+        }
+
+        for segment_start in (0..8).step_by(TEST_SEGMENT_SIZE as usize) {
+            let mut builder = SegmentBuilder::default();
+            builder
+                .start_new_segment(new_test_cursor(segment_start, 0))
+                .unwrap();
+            for block_number in segment_start..segment_start + TEST_SEGMENT_SIZE {
+                builder
+                    .add_decoded_block(&new_test_cursor(block_number, 0), new_block(block_number))
+                    .unwrap();
+            }
+
+            for segment in builder.segment_data(&HashMap::default()).unwrap() {
+                writer.put_segment(segment).await.unwrap();
+            }
+        }
+
+        let chain_view = ChainView::new_for_testing(
+            ChainStore::new(object_store.clone(), file_cache.clone()),
+            0,
+            TEST_CHAIN_HEAD,
+            Some(7),
+            None,
+            SegmentLayout {
+                generation: 0,
+                segment_size: TEST_SEGMENT_SIZE,
+                group_size: 2,
+                fragment_segment_size: HashMap::default(),
+            },
+        );
+
+        Self {
+            _cache_dir: cache_dir,
+            object_store,
+            file_cache,
+            chain_view,
+        }
+    }
+
+    pub fn block_store(&self) -> BlockStoreReader {
+        BlockStoreReader::new(self.object_store.clone(), self.file_cache.clone())
+    }
+
+    pub fn chain_view_rx(&self) -> watch::Receiver<Option<ChainView>> {
+        let (_tx, rx) = watch::channel(Some(self.chain_view.clone()));
+        rx
+    }
+}
+
+pub fn new_block(number: u64) -> Block {
+    Block {
+        header: HeaderFragment {
+            data: vec![number as u8],
+        },
+        index: IndexGroupFragment {
+            indexes: Vec::default(),
+        },
+        join: JoinGroupFragment {
+            joins: Vec::default(),
+        },
+        body: Vec::default(),
+    }
+}
+
+pub fn fragment_id_to_name() -> HashMap<FragmentId, String> {
+    HashMap::from([
+        (INDEX_FRAGMENT_ID, INDEX_FRAGMENT_NAME.to_string()),
+        (JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME.to_string()),
+        (HEADER_FRAGMENT_ID, HEADER_FRAGMENT_NAME.to_string()),
+    ])
+}
+
+pub fn stream_service_options() -> StreamServiceOptions {
+    StreamServiceOptions {
+        max_concurrent_streams: 4,
+        prefetch_segment_count: 2,
+        prefetch_block_count: 2,
+        shared_filter_cache_size: 1024 * 1024,
+        filter_result_cache_size: 0,
+        stream_memory_budget: 0,
+        server_id: None,
+        max_filter_conditions: 16,
+        session_cache_size: 0,
+        slow_consumer: SlowConsumerPolicy {
+            report_after: Duration::from_secs(10),
+            disconnect_after: None,
+        },
+    }
+}
+
+pub fn runtime_settings() -> watch::Receiver<RuntimeSettings> {
+    let (_tx, rx) = watch::channel(RuntimeSettings {
+        log_filter: "info".to_string(),
+        max_concurrent_streams: 4,
+        stream_memory_budget: 0,
+        rpc_rate_limit: None,
+    });
+    rx
+}
+
+impl BlockFilterFactory for HeaderFilterFactory {
+    fn create_block_filter(
+        &self,
+        filters: &[Vec<u8>],
+    ) -> tonic::Result<Vec<BlockFilter>, tonic::Status> {
+        let block_filter = filters
+            .iter()
+            .map(|_| {
+                let mut block_filter = BlockFilter::default();
+                block_filter.set_header_filter(HeaderFilter::Always);
+                block_filter
+            })
+            .collect();
+
+        Ok(block_filter)
+    }
+
+    fn filter_descriptor(&self) -> (&'static [u8], &'static str) {
+        (&[], "")
+    }
+}
@@ -0,0 +1,191 @@
+//! A cache tier shared by multiple stream server replicas.
+use std::time::Duration;
+
+use apibara_observability::{Counter, KeyValue};
+use bytes::{BufMut, Bytes, BytesMut};
+use reqwest::StatusCode;
+use tracing::warn;
+
+use crate::segment::SEGMENT_FORMAT_VERSION;
+
+const CHECKSUM_SIZE: usize = 4;
+
+/// An HTTP cache shared by the stream server replicas.
+///
+/// Objects are read with `GET {url}/{key}` and stored with `PUT {url}/{key}`, so any
+/// HTTP server that stores the body of `PUT` requests works, for example nginx with
+/// WebDAV or a dedicated cache service in front of Redis. Replicas check the shared
+/// cache before downloading immutable objects from the object store, so scaling out
+/// doesn't multiply the object store egress for the same hot segments.
+///
+/// The cache is best effort: errors are logged and treated as misses.
+/// Objects are stored with their checksum, and objects that don't match it are
+/// treated as misses too. Replicas of different deployments must use a different URL.
+#[derive(Clone)]
+pub struct SharedCache {
+    client: reqwest::Client,
+    base_url: String,
+    timeout: Duration,
+    metrics: SharedCacheMetrics,
+}
+
+#[derive(Clone)]
+struct SharedCacheMetrics {
+    request: Counter<u64>,
+}
+
+impl SharedCache {
+    pub fn new(base_url: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            timeout,
+            metrics: SharedCacheMetrics::default(),
+        }
+    }
+
+    /// Returns the object with the given key, if it's in the cache.
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        let response = self
+            .client
+            .get(self.url(key))
+            .timeout(self.timeout)
+            .send()
+            .await;
+
+        let response = match response {
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+                self.record("get", "miss");
+                return None;
+            }
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                warn!(key, status = %response.status(), "shared cache get failed");
+                self.record("get", "error");
+                return None;
+            }
+            Err(err) => {
+                warn!(key, error = ?err, "shared cache get failed");
+                self.record("get", "error");
+                return None;
+            }
+        };
+
+        match response.bytes().await {
+            Ok(body) => match decode_entry(body) {
+                Some(body) => {
+                    self.record("get", "hit");
+                    Some(body)
+                }
+                None => {
+                    warn!(key, "shared cache object checksum mismatch");
+                    self.record("get", "corrupted");
+                    None
+                }
+            },
+            Err(err) => {
+                warn!(key, error = ?err, "failed to read shared cache response");
+                self.record("get", "error");
+                None
+            }
+        }
+    }
+
+    /// Store the object in the cache.
+    pub async fn put(&self, key: &str, body: Bytes) {
+        let response = self
+            .client
+            .put(self.url(key))
+            .timeout(self.timeout)
+            .body(encode_entry(&body))
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => self.record("put", "ok"),
+            Ok(response) => {
+                warn!(key, status = %response.status(), "shared cache put failed");
+                self.record("put", "error");
+            }
+            Err(err) => {
+                warn!(key, error = ?err, "shared cache put failed");
+                self.record("put", "error");
+            }
+        }
+    }
+
+    /// Objects are stored under the format version, so that replicas running
+    /// different versions never read each other's objects.
+    fn url(&self, key: &str) -> String {
+        format!("{}/v{}/{}", self.base_url, SEGMENT_FORMAT_VERSION, key)
+    }
+
+    fn record(&self, operation: &'static str, result: &'static str) {
+        self.metrics.request.add(
+            1,
+            &[
+                KeyValue::new("operation", operation),
+                KeyValue::new("result", result),
+            ],
+        );
+    }
+}
+
+/// Append the checksum to the object.
+fn encode_entry(body: &[u8]) -> Bytes {
+    let mut entry = BytesMut::with_capacity(body.len() + CHECKSUM_SIZE);
+    entry.put_slice(body);
+    entry.put_u32(crc32fast::hash(body));
+    entry.freeze()
+}
+
+/// Returns the object without its checksum, or `None` if it doesn't match the checksum.
+fn decode_entry(mut entry: Bytes) -> Option<Bytes> {
+    if entry.len() < CHECKSUM_SIZE {
+        return None;
+    }
+
+    let checksum = entry.split_off(entry.len() - CHECKSUM_SIZE);
+    let checksum = u32::from_be_bytes(checksum.as_ref().try_into().ok()?);
+
+    (crc32fast::hash(&entry) == checksum).then_some(entry)
+}
+
+impl Default for SharedCacheMetrics {
+    fn default() -> Self {
+        let meter = apibara_observability::meter("dna_shared_cache");
+
+        Self {
+            request: meter
+                .u64_counter("dna.shared_cache.request")
+                .with_description("shared cache requests by operation and result")
+                .build(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{decode_entry, encode_entry};
+
+    #[test]
+    fn test_entry_checksum() {
+        let body = Bytes::from_static(b"segment data");
+
+        let entry = encode_entry(&body);
+        assert_eq!(entry.len(), body.len() + 4);
+        assert_eq!(decode_entry(entry.clone()), Some(body));
+
+        let mut corrupted = entry.to_vec();
+        corrupted[0] ^= 1;
+        assert_eq!(decode_entry(corrupted.into()), None);
+
+        // Truncated objects.
+        assert_eq!(decode_entry(entry.slice(..entry.len() - 1)), None);
+        assert_eq!(decode_entry(Bytes::from_static(b"abc")), None);
+
+        assert_eq!(decode_entry(encode_entry(b"")), Some(Bytes::new()));
+    }
+}
@@ -0,0 +1,10 @@
+#[derive(Debug)]
+pub struct SnapshotError;
+
+impl error_stack::Context for SnapshotError {}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "snapshot error")
+    }
+}
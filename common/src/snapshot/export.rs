@@ -0,0 +1,635 @@
+use std::{collections::HashMap, path::Path};
+
+use bytes::Bytes;
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tracing::{info, warn};
+
+use crate::{
+    block_store::segment_generation_prefix,
+    chain::CanonicalChainSegment,
+    dataset_manifest::DatasetManifestStore,
+    ingestion::IngestionStateClient,
+    object_store::{GetOptions, ObjectStore, ObjectStoreResultExt},
+    options_store::OptionsStore,
+};
+
+use super::{
+    manifest::{object_path, RECENT_CHAIN_SEGMENT_KEY, SNAPSHOT_PREFIXES, SNAPSHOT_VERSION},
+    SnapshotError, SnapshotManifest,
+};
+
+/// Maximum number of times the export starts over because the deployment changed.
+const MAX_EXPORT_ATTEMPTS: usize = 5;
+
+/// The blocks to include in a snapshot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnapshotRange {
+    /// The first block. Defaults to the deployment's starting block.
+    ///
+    /// It must be the first block of a chain segment, segment and group.
+    pub from_block: Option<u64>,
+    /// The last block. Defaults to the deployment's head.
+    ///
+    /// It must be finalized. If it's already segmented, it must be the last block of
+    /// a segment.
+    pub to_block: Option<u64>,
+}
+
+/// The deployment's state when the export starts.
+#[derive(Debug, Clone)]
+struct DeploymentState {
+    starting_block: u64,
+    finalized: u64,
+    segmented: Option<u64>,
+    grouped: Option<u64>,
+    head: u64,
+    chain_segment_size: Option<usize>,
+    segment_size: Option<usize>,
+    group_size: Option<usize>,
+    fragment_segment_size: HashMap<String, usize>,
+}
+
+/// The state of the deployment restored from the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnapshotPlan {
+    starting_block: u64,
+    last_block: u64,
+    finalized: u64,
+    segmented: Option<u64>,
+    grouped: Option<u64>,
+}
+
+/// Export the blocks in `range` to the `output` directory.
+///
+/// The export reads the ingestion state first and copies only the objects it
+/// references, so the snapshot is consistent even if ingestion and compaction keep
+/// running. If they delete or replace an object before it's copied, the export
+/// starts over.
+///
+/// The manifest is written last, so an interrupted export is never mistaken for
+/// a valid snapshot.
+pub async fn export_snapshot(
+    object_store: ObjectStore,
+    state_client: IngestionStateClient,
+    options_store: OptionsStore,
+    output: &Path,
+    range: SnapshotRange,
+    concurrency: usize,
+) -> Result<SnapshotManifest, SnapshotError> {
+    for attempt in 1..=MAX_EXPORT_ATTEMPTS {
+        if let Some(manifest) = export_snapshot_once(
+            &object_store,
+            state_client.clone(),
+            options_store.clone(),
+            output,
+            range,
+            concurrency,
+        )
+        .await?
+        {
+            info!("snapshot exported");
+            return Ok(manifest);
+        }
+
+        warn!(attempt, "deployment changed during export. starting over");
+    }
+
+    Err(SnapshotError)
+        .attach_printable("deployment kept changing during export")
+        .attach_printable_lazy(|| format!("attempts: {MAX_EXPORT_ATTEMPTS}"))
+}
+
+/// Export the snapshot, returning `None` if the deployment changed in the meantime.
+async fn export_snapshot_once(
+    object_store: &ObjectStore,
+    mut state_client: IngestionStateClient,
+    mut options_store: OptionsStore,
+    output: &Path,
+    range: SnapshotRange,
+    concurrency: usize,
+) -> Result<Option<SnapshotManifest>, SnapshotError> {
+    let starting_block = state_client
+        .get_starting_block()
+        .await
+        .change_context(SnapshotError)?
+        .ok_or(SnapshotError)
+        .attach_printable("starting block not found. is the deployment empty?")?;
+    let ingested = state_client
+        .get_ingested()
+        .await
+        .change_context(SnapshotError)?
+        .ok_or(SnapshotError)
+        .attach_printable("ingested block not found. is the deployment empty?")?;
+    let finalized = state_client
+        .get_finalized()
+        .await
+        .change_context(SnapshotError)?
+        .ok_or(SnapshotError)
+        .attach_printable("finalized block not found")?;
+    let segmented = state_client
+        .get_segmented()
+        .await
+        .change_context(SnapshotError)?;
+    let grouped = state_client
+        .get_grouped()
+        .await
+        .change_context(SnapshotError)?;
+
+    let chain_segment_size = options_store
+        .get_chain_segment_size()
+        .await
+        .change_context(SnapshotError)?;
+    let segment_size = options_store
+        .get_segment_size()
+        .await
+        .change_context(SnapshotError)?;
+    let group_size = options_store
+        .get_group_size()
+        .await
+        .change_context(SnapshotError)?;
+    let fragment_segment_size = options_store
+        .get_fragment_segment_sizes()
+        .await
+        .change_context(SnapshotError)?;
+    let segment_generation = options_store
+        .get_segment_generation()
+        .await
+        .change_context(SnapshotError)?;
+
+    // The recent chain segment must be the one referenced by the state we just read.
+    let recent = match object_store
+        .get(
+            RECENT_CHAIN_SEGMENT_KEY,
+            GetOptions {
+                etag: Some(ingested),
+            },
+        )
+        .await
+    {
+        Ok(response) => decode_chain_segment(&response.body)?,
+        Err(err) if err.is_precondition() || err.is_not_found() => return Ok(None),
+        Err(err) => {
+            return Err(err)
+                .change_context(SnapshotError)
+                .attach_printable("failed to get recent canonical chain segment")
+        }
+    };
+
+    let state = DeploymentState {
+        starting_block,
+        finalized,
+        segmented,
+        grouped,
+        head: recent.info.last_block.number,
+        chain_segment_size,
+        segment_size,
+        group_size,
+        fragment_segment_size: fragment_segment_size.clone(),
+    };
+
+    let plan = plan_snapshot(&state, &range)?;
+
+    // The chain segment that contains the last block becomes the recent segment.
+    let mut recent = if plan.last_block >= recent.info.first_block.number {
+        recent
+    } else {
+        let chain_segment_size = chain_segment_size
+            .ok_or(SnapshotError)
+            .attach_printable("chain segment size not found")?
+            as u64;
+        let first_block = (plan.last_block - starting_block) / chain_segment_size
+            * chain_segment_size
+            + starting_block;
+        let key = chain_segment_key(first_block);
+
+        match object_store.get(&key, GetOptions::default()).await {
+            Ok(response) => decode_chain_segment(&response.body)?,
+            Err(err) if err.is_not_found() => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .change_context(SnapshotError)
+                    .attach_printable("failed to get canonical chain segment")
+                    .attach_printable_lazy(|| format!("key: {key}"))
+            }
+        }
+    };
+
+    recent
+        .truncate(plan.last_block)
+        .change_context(SnapshotError)
+        .attach_printable("failed to truncate canonical chain segment")?;
+    let recent_first_block = recent.info.first_block.number;
+
+    // Segments and groups are stored without their generation prefix, so that the
+    // snapshot is imported as generation 0.
+    let generation_prefix = segment_generation_prefix(segment_generation);
+
+    let mut sources = Vec::new();
+    for prefix in SNAPSHOT_PREFIXES {
+        let source_prefix = match *prefix {
+            "segment/" | "group/" => format!("{generation_prefix}{prefix}"),
+            _ => prefix.to_string(),
+        };
+        let keys = object_store
+            .list(&source_prefix)
+            .await
+            .change_context(SnapshotError)
+            .attach_printable("failed to list objects")
+            .attach_printable_lazy(|| format!("prefix: {source_prefix}"))?;
+        sources.extend(keys.into_iter().filter_map(|source| {
+            let key = source
+                .strip_prefix(&source_prefix)
+                .map(|rest| format!("{prefix}{rest}"))?;
+            includes_object(&key, &plan, recent_first_block).then_some((source, key))
+        }));
+    }
+
+    info!(
+        starting_block = plan.starting_block,
+        last_block = plan.last_block,
+        finalized = plan.finalized,
+        segmented = plan.segmented,
+        grouped = plan.grouped,
+        objects = sources.len(),
+        output = %output.display(),
+        "exporting snapshot"
+    );
+
+    let copied = futures::stream::iter(sources.iter())
+        .map(|(source, key)| async move {
+            let response = match object_store.get(source, GetOptions::default()).await {
+                Ok(response) => response,
+                // Compaction deletes blocks after they're segmented.
+                Err(err) if err.is_not_found() => return Ok(false),
+                Err(err) => {
+                    return Err(err)
+                        .change_context(SnapshotError)
+                        .attach_printable("failed to download object")
+                        .attach_printable_lazy(|| format!("key: {source}"))
+                }
+            };
+
+            write_object(output, key, response.body).await?;
+
+            Ok(true)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    if copied.iter().any(|copied| !copied) {
+        return Ok(None);
+    }
+
+    let mut objects = sources.into_iter().map(|(_, key)| key).collect::<Vec<_>>();
+
+    let recent = rkyv::to_bytes::<rkyv::rancor::Error>(&recent)
+        .change_context(SnapshotError)
+        .attach_printable("failed to serialize recent canonical chain segment")?;
+    write_object(
+        output,
+        RECENT_CHAIN_SEGMENT_KEY,
+        Bytes::copy_from_slice(recent.as_slice()),
+    )
+    .await?;
+    objects.push(RECENT_CHAIN_SEGMENT_KEY.to_string());
+
+    let dataset = DatasetManifestStore::new(object_store.clone())
+        .get()
+        .await
+        .change_context(SnapshotError)?;
+
+    let manifest = SnapshotManifest {
+        version: SNAPSHOT_VERSION,
+        starting_block: plan.starting_block,
+        last_block: Some(plan.last_block),
+        finalized: plan.finalized,
+        segmented: plan.segmented,
+        grouped: plan.grouped,
+        chain_segment_size,
+        segment_size,
+        group_size,
+        fragment_segment_size,
+        chain_id: dataset
+            .as_ref()
+            .and_then(|dataset| dataset.chain_id.clone()),
+        genesis_hash: dataset.and_then(|dataset| dataset.genesis_hash),
+        objects,
+    };
+
+    manifest.write(output).await?;
+
+    Ok(Some(manifest))
+}
+
+fn plan_snapshot(
+    state: &DeploymentState,
+    range: &SnapshotRange,
+) -> Result<SnapshotPlan, SnapshotError> {
+    let starting_block = range.from_block.unwrap_or(state.starting_block);
+    if starting_block < state.starting_block {
+        return Err(SnapshotError)
+            .attach_printable("from block is before the deployment's starting block")
+            .attach_printable_lazy(|| format!("from block: {starting_block}"))
+            .attach_printable_lazy(|| format!("starting block: {}", state.starting_block));
+    }
+
+    if starting_block > state.starting_block {
+        // Chain segments, segments and groups are aligned to the starting block.
+        let blocks_in_group = state
+            .segment_size
+            .zip(state.group_size)
+            .map(|(segment_size, group_size)| segment_size * group_size);
+        let sizes = [
+            state.chain_segment_size,
+            state.segment_size,
+            blocks_in_group,
+        ]
+        .into_iter()
+        .chain(state.fragment_segment_size.values().copied().map(Some));
+
+        for size in sizes {
+            let Some(size) = size else {
+                return Err(SnapshotError)
+                    .attach_printable("cannot export from a block without the deployment options");
+            };
+
+            if (starting_block - state.starting_block) % size as u64 != 0 {
+                return Err(SnapshotError)
+                    .attach_printable("from block is not the first block of a segment or group")
+                    .attach_printable_lazy(|| format!("from block: {starting_block}"))
+                    .attach_printable_lazy(|| format!("size: {size}"));
+            }
+        }
+    }
+
+    let last_block = match range.to_block {
+        None => state.head,
+        Some(to_block) if to_block > state.finalized => {
+            return Err(SnapshotError)
+                .attach_printable("to block is not finalized")
+                .attach_printable_lazy(|| format!("to block: {to_block}"))
+                .attach_printable_lazy(|| format!("finalized: {}", state.finalized));
+        }
+        Some(to_block) => to_block,
+    };
+
+    if last_block < starting_block {
+        return Err(SnapshotError)
+            .attach_printable("the block range is empty")
+            .attach_printable_lazy(|| format!("from block: {starting_block}"))
+            .attach_printable_lazy(|| format!("to block: {last_block}"));
+    }
+
+    // Blocks are deleted once segmented, so the last block must end a segment.
+    let segmented = match state.segmented {
+        Some(segmented) if segmented <= last_block => Some(segmented),
+        Some(_) => {
+            let segment_size = state
+                .segment_size
+                .ok_or(SnapshotError)
+                .attach_printable("segment size not found")? as u64;
+
+            if (last_block + 1 - state.starting_block) % segment_size != 0 {
+                return Err(SnapshotError)
+                    .attach_printable("to block is not the last block of a segment")
+                    .attach_printable_lazy(|| format!("to block: {last_block}"))
+                    .attach_printable_lazy(|| format!("segment size: {segment_size}"));
+            }
+
+            Some(last_block)
+        }
+        None => None,
+    };
+
+    let grouped = match state.grouped {
+        Some(grouped) if grouped <= last_block => Some(grouped),
+        Some(_) => {
+            let blocks_in_group = state
+                .segment_size
+                .zip(state.group_size)
+                .map(|(segment_size, group_size)| (segment_size * group_size) as u64)
+                .ok_or(SnapshotError)
+                .attach_printable("segment or group size not found")?;
+
+            let groups = (last_block + 1 - state.starting_block) / blocks_in_group;
+            (groups > 0).then(|| state.starting_block + groups * blocks_in_group - 1)
+        }
+        None => None,
+    };
+
+    Ok(SnapshotPlan {
+        starting_block,
+        last_block,
+        finalized: state.finalized.min(last_block),
+        segmented: segmented.filter(|segmented| *segmented >= starting_block),
+        grouped: grouped.filter(|grouped| *grouped >= starting_block),
+    })
+}
+
+/// Returns whether the object with the given key is part of the snapshot.
+///
+/// The recent chain segment is not included, since it's truncated to the last block.
+fn includes_object(key: &str, plan: &SnapshotPlan, recent_first_block: u64) -> bool {
+    let Some((prefix, rest)) = key.split_once('/') else {
+        return false;
+    };
+
+    let block_number = match prefix {
+        "canon" => rest.strip_prefix("z-").and_then(|n| n.parse::<u64>().ok()),
+        _ => rest
+            .split_once('/')
+            .and_then(|(number, _)| number.parse::<u64>().ok()),
+    };
+
+    let Some(block_number) = block_number else {
+        return false;
+    };
+
+    if block_number < plan.starting_block {
+        return false;
+    }
+
+    match prefix {
+        "canon" => block_number < recent_first_block,
+        // Pending blocks are short-lived and not referenced by the ingestion state.
+        "block" => {
+            !key.contains("/pending-")
+                && block_number <= plan.last_block
+                && plan
+                    .segmented
+                    .map(|segmented| block_number > segmented)
+                    .unwrap_or(true)
+        }
+        "segment" => plan
+            .segmented
+            .map(|segmented| block_number <= segmented)
+            .unwrap_or(false),
+        "group" => plan
+            .grouped
+            .map(|grouped| block_number <= grouped)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn chain_segment_key(first_block: u64) -> String {
+    format!("canon/z-{:0>10}", first_block)
+}
+
+fn decode_chain_segment(bytes: &[u8]) -> Result<CanonicalChainSegment, SnapshotError> {
+    rkyv::from_bytes::<_, rkyv::rancor::Error>(bytes)
+        .change_context(SnapshotError)
+        .attach_printable("failed to deserialize canonical chain segment")
+}
+
+async fn write_object(output: &Path, key: &str, body: Bytes) -> Result<(), SnapshotError> {
+    let path = object_path(output, key);
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .change_context(SnapshotError)
+            .attach_printable("failed to create snapshot directory")?;
+    }
+
+    tokio::fs::write(&path, body)
+        .await
+        .change_context(SnapshotError)
+        .attach_printable("failed to write object")
+        .attach_printable_lazy(|| format!("path: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{includes_object, plan_snapshot, DeploymentState, SnapshotPlan, SnapshotRange};
+
+    fn state() -> DeploymentState {
+        DeploymentState {
+            starting_block: 1_000,
+            finalized: 1_450,
+            segmented: Some(1_399),
+            grouped: Some(1_199),
+            head: 1_500,
+            chain_segment_size: Some(100),
+            segment_size: Some(50),
+            group_size: Some(2),
+            fragment_segment_size: HashMap::default(),
+        }
+    }
+
+    #[test]
+    fn test_plan_whole_deployment() {
+        let plan = plan_snapshot(&state(), &SnapshotRange::default()).unwrap();
+        assert_eq!(
+            plan,
+            SnapshotPlan {
+                starting_block: 1_000,
+                last_block: 1_500,
+                finalized: 1_450,
+                segmented: Some(1_399),
+                grouped: Some(1_199),
+            }
+        );
+    }
+
+    #[test]
+    fn test_plan_block_range() {
+        let range = SnapshotRange {
+            from_block: Some(1_100),
+            to_block: Some(1_349),
+        };
+        let plan = plan_snapshot(&state(), &range).unwrap();
+        assert_eq!(
+            plan,
+            SnapshotPlan {
+                starting_block: 1_100,
+                last_block: 1_349,
+                finalized: 1_349,
+                segmented: Some(1_349),
+                grouped: Some(1_199),
+            }
+        );
+
+        // Unsegmented and finalized.
+        let range = SnapshotRange {
+            from_block: None,
+            to_block: Some(1_420),
+        };
+        let plan = plan_snapshot(&state(), &range).unwrap();
+        assert_eq!(plan.segmented, Some(1_399));
+        assert_eq!(plan.finalized, 1_420);
+    }
+
+    #[test]
+    fn test_plan_invalid_range() {
+        let invalid = [
+            // Before the starting block.
+            (Some(900), None),
+            // Not the first block of a group.
+            (Some(1_050), None),
+            // Not finalized.
+            (None, Some(1_451)),
+            // Segmented, but not the last block of a segment.
+            (None, Some(1_300)),
+            // Empty.
+            (Some(1_200), Some(1_149)),
+        ];
+
+        for (from_block, to_block) in invalid {
+            let range = SnapshotRange {
+                from_block,
+                to_block,
+            };
+            assert!(
+                plan_snapshot(&state(), &range).is_err(),
+                "{from_block:?} - {to_block:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_includes_object() {
+        let plan = SnapshotPlan {
+            starting_block: 1_100,
+            last_block: 1_349,
+            finalized: 1_349,
+            segmented: Some(1_299),
+            grouped: Some(1_199),
+        };
+        let recent_first_block = 1_300;
+
+        let included = [
+            "canon/z-0000001100",
+            "canon/z-0000001200",
+            "block/0000001300/0xabcd",
+            "block/0000001349/0xabcd",
+            "segment/0000001100/header",
+            "segment/0000001250/log",
+            "group/0000001100/index",
+        ];
+        for key in included {
+            assert!(includes_object(key, &plan, recent_first_block), "{key}");
+        }
+
+        let excluded = [
+            "canon/recent",
+            "canon/z-0000001000",
+            "canon/z-0000001300",
+            "block/0000001299/0xabcd",
+            "block/0000001350/0xabcd",
+            "block/0000001300/pending-0001",
+            "segment/0000001000/header",
+            "segment/0000001300/header",
+            "group/0000001000/index",
+            "group/0000001200/index",
+            "quarantine/0000001300/0xabcd",
+        ];
+        for key in excluded {
+            assert!(!includes_object(key, &plan, recent_first_block), "{key}");
+        }
+    }
+}
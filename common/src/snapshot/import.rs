@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
+use tracing::info;
+
+use crate::{
+    dataset_manifest::DatasetManifestStore,
+    ingestion::IngestionStateClient,
+    object_store::{ObjectETag, ObjectStore, PutOptions},
+    options_store::OptionsStore,
+};
+
+use super::{
+    manifest::{object_path, RECENT_CHAIN_SEGMENT_KEY},
+    SnapshotError, SnapshotManifest,
+};
+
+/// Import the snapshot in the `input` directory.
+///
+/// The target deployment must be empty. The ingestion state is written only after
+/// all objects are uploaded, so the deployment never references missing data.
+pub async fn import_snapshot(
+    object_store: ObjectStore,
+    mut state_client: IngestionStateClient,
+    mut options_store: OptionsStore,
+    input: &Path,
+    concurrency: usize,
+) -> Result<(), SnapshotError> {
+    let manifest = SnapshotManifest::read(input).await?;
+
+    if state_client
+        .get_starting_block()
+        .await
+        .change_context(SnapshotError)?
+        .is_some()
+    {
+        return Err(SnapshotError)
+            .attach_printable("cannot import snapshot into a non-empty deployment");
+    }
+
+    info!(
+        starting_block = manifest.starting_block,
+        finalized = manifest.finalized,
+        segmented = manifest.segmented,
+        grouped = manifest.grouped,
+        objects = manifest.objects.len(),
+        "importing snapshot"
+    );
+
+    futures::stream::iter(
+        manifest
+            .objects
+            .iter()
+            .filter(|key| *key != RECENT_CHAIN_SEGMENT_KEY),
+    )
+    .map(|key| upload_object(&object_store, input, key))
+    .buffer_unordered(concurrency.max(1))
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let recent_etag = upload_object(&object_store, input, RECENT_CHAIN_SEGMENT_KEY).await?;
+
+    if let Some(chain_segment_size) = manifest.chain_segment_size {
+        options_store
+            .set_chain_segment_size(chain_segment_size)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    if let Some(segment_size) = manifest.segment_size {
+        options_store
+            .set_segment_size(segment_size)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    if let Some(group_size) = manifest.group_size {
+        options_store
+            .set_group_size(group_size)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    for (name, size) in manifest.fragment_segment_size.iter() {
+        options_store
+            .set_fragment_segment_size(name, *size)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    // Snapshots store segments and groups without their generation prefix.
+    options_store
+        .set_segment_generation(0)
+        .await
+        .change_context(SnapshotError)?;
+
+    state_client
+        .put_starting_block(manifest.starting_block)
+        .await
+        .change_context(SnapshotError)?;
+    state_client
+        .put_finalized(manifest.finalized)
+        .await
+        .change_context(SnapshotError)?;
+
+    if let Some(segmented) = manifest.segmented {
+        state_client
+            .put_segmented(segmented)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    if let Some(grouped) = manifest.grouped {
+        state_client
+            .put_grouped(grouped)
+            .await
+            .change_context(SnapshotError)?;
+    }
+
+    state_client
+        .put_ingested(recent_etag)
+        .await
+        .change_context(SnapshotError)?;
+
+    DatasetManifestStore::new(object_store)
+        .update(|dataset| {
+            dataset.chain_id = manifest.chain_id.clone();
+            dataset.genesis_hash = manifest.genesis_hash.clone();
+            dataset.starting_block = Some(manifest.starting_block);
+            dataset.ingested = manifest.last_block;
+            dataset.finalized = Some(manifest.finalized);
+            dataset.segmented = manifest.segmented;
+            dataset.grouped = manifest.grouped;
+        })
+        .await
+        .change_context(SnapshotError)?;
+
+    info!("snapshot imported");
+
+    Ok(())
+}
+
+async fn upload_object(
+    object_store: &ObjectStore,
+    input: &Path,
+    key: &str,
+) -> Result<ObjectETag, SnapshotError> {
+    let path = object_path(input, key);
+    let content = tokio::fs::read(&path)
+        .await
+        .change_context(SnapshotError)
+        .attach_printable("failed to read object")
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+    let response = object_store
+        .put(key, Bytes::from(content), PutOptions::default())
+        .await
+        .change_context(SnapshotError)
+        .attach_printable("failed to upload object")
+        .attach_printable_lazy(|| format!("key: {key}"))?;
+
+    Ok(response.etag)
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+
+use super::SnapshotError;
+
+/// Current version of the snapshot format.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Object store prefixes included in the snapshot.
+pub(crate) const SNAPSHOT_PREFIXES: &[&str] = &["canon/", "block/", "segment/", "group/"];
+
+/// The recent canonical chain segment, it's uploaded last when importing.
+pub(crate) const RECENT_CHAIN_SEGMENT_KEY: &str = "canon/recent";
+
+static MANIFEST_FILENAME: &str = "manifest.json";
+static OBJECTS_DIR: &str = "objects";
+
+/// Describes the content of a snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub starting_block: u64,
+    /// The last block in the snapshot.
+    #[serde(default)]
+    pub last_block: Option<u64>,
+    pub finalized: u64,
+    pub segmented: Option<u64>,
+    pub grouped: Option<u64>,
+    pub chain_segment_size: Option<usize>,
+    pub segment_size: Option<usize>,
+    pub group_size: Option<usize>,
+    /// Segment size of the fragments that don't use `segment_size`.
+    #[serde(default)]
+    pub fragment_segment_size: HashMap<String, usize>,
+    /// The chain identifier from the dataset manifest.
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    /// The genesis hash from the dataset manifest.
+    #[serde(default)]
+    pub genesis_hash: Option<String>,
+    /// The object store keys included in the snapshot.
+    pub objects: Vec<String>,
+}
+
+impl SnapshotManifest {
+    pub async fn read(dir: &Path) -> Result<Self, SnapshotError> {
+        let path = dir.join(MANIFEST_FILENAME);
+        let content = tokio::fs::read(&path)
+            .await
+            .change_context(SnapshotError)
+            .attach_printable("failed to read snapshot manifest")
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+
+        let manifest: Self = serde_json::from_slice(&content)
+            .change_context(SnapshotError)
+            .attach_printable("failed to parse snapshot manifest")?;
+
+        if manifest.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError)
+                .attach_printable("unsupported snapshot version")
+                .attach_printable_lazy(|| format!("version: {}", manifest.version));
+        }
+
+        Ok(manifest)
+    }
+
+    pub async fn write(&self, dir: &Path) -> Result<(), SnapshotError> {
+        let path = dir.join(MANIFEST_FILENAME);
+        let content = serde_json::to_vec_pretty(self)
+            .change_context(SnapshotError)
+            .attach_printable("failed to serialize snapshot manifest")?;
+
+        tokio::fs::write(&path, content)
+            .await
+            .change_context(SnapshotError)
+            .attach_printable("failed to write snapshot manifest")
+            .attach_printable_lazy(|| format!("path: {}", path.display()))
+    }
+}
+
+pub(crate) fn object_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(OBJECTS_DIR).join(key)
+}
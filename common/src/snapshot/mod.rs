@@ -0,0 +1,256 @@
+//! Export and import the content of a DNA deployment.
+//!
+//! A snapshot is a directory with a manifest and a copy of the objects (chain
+//! segments, blocks, segments and groups) for a range of blocks. Importing a snapshot
+//! uploads the objects to the new object store and restores the ingestion state in
+//! etcd, so that a new deployment can start without re-ingesting from the RPC.
+mod error;
+mod export;
+mod import;
+mod manifest;
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use error_stack::{Result, ResultExt};
+
+use crate::{
+    cli::{EtcdArgs, ObjectStoreArgs},
+    ingestion::IngestionStateClient,
+    options_store::OptionsStore,
+};
+
+pub use self::error::SnapshotError;
+pub use self::export::{export_snapshot, SnapshotRange};
+pub use self::import::import_snapshot;
+pub use self::manifest::SnapshotManifest;
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCommand {
+    /// Export the deployment's data to a local directory.
+    Export {
+        #[clap(flatten)]
+        object_store: ObjectStoreArgs,
+        #[clap(flatten)]
+        etcd: EtcdArgs,
+        /// Where to write the snapshot.
+        #[arg(long)]
+        output: PathBuf,
+        /// The first block in the snapshot, defaults to the deployment's starting block.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// The last block in the snapshot, defaults to the deployment's head.
+        #[arg(long)]
+        to_block: Option<u64>,
+        /// Number of objects downloaded concurrently.
+        #[arg(long, default_value = "16")]
+        concurrency: usize,
+    },
+    /// Import a snapshot into an empty deployment.
+    Import {
+        #[clap(flatten)]
+        object_store: ObjectStoreArgs,
+        #[clap(flatten)]
+        etcd: EtcdArgs,
+        /// The snapshot directory.
+        #[arg(long)]
+        input: PathBuf,
+        /// Number of objects uploaded concurrently.
+        #[arg(long, default_value = "16")]
+        concurrency: usize,
+    },
+}
+
+impl SnapshotCommand {
+    pub async fn run(self) -> Result<(), SnapshotError> {
+        match self {
+            SnapshotCommand::Export {
+                object_store,
+                etcd,
+                output,
+                from_block,
+                to_block,
+                concurrency,
+            } => {
+                let object_store = object_store.into_object_store_client().await;
+                let etcd_client = etcd
+                    .into_etcd_client()
+                    .await
+                    .change_context(SnapshotError)?;
+                let range = SnapshotRange {
+                    from_block,
+                    to_block,
+                };
+                export_snapshot(
+                    object_store,
+                    IngestionStateClient::new(&etcd_client),
+                    OptionsStore::new(&etcd_client),
+                    &output,
+                    range,
+                    concurrency,
+                )
+                .await?;
+                Ok(())
+            }
+            SnapshotCommand::Import {
+                object_store,
+                etcd,
+                input,
+                concurrency,
+            } => {
+                let object_store = object_store.into_object_store_client().await;
+                let etcd_client = etcd
+                    .into_etcd_client()
+                    .await
+                    .change_context(SnapshotError)?;
+                import_snapshot(
+                    object_store,
+                    IngestionStateClient::new(&etcd_client),
+                    OptionsStore::new(&etcd_client),
+                    &input,
+                    concurrency,
+                )
+                .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        chain::{BlockInfo, CanonicalChainBuilder, CanonicalChainSegment},
+        dataset_manifest::DatasetManifestStore,
+        ingestion::IngestionStateClient,
+        new_test_cursor,
+        object_store::{GetOptions, ObjectStore, PutOptions},
+        options_store::OptionsStore,
+        Hash,
+    };
+
+    use super::{export_snapshot, import_snapshot, SnapshotRange};
+
+    #[tokio::test]
+    async fn test_export_import_block_range() {
+        let source = ObjectStore::new_in_memory(Default::default());
+        let mut source_state = IngestionStateClient::new_in_memory();
+
+        let mut builder = CanonicalChainBuilder::new();
+        let mut parent = Hash::default();
+        for number in 1_000..1_010 {
+            let cursor = new_test_cursor(number, 0);
+            builder
+                .grow(BlockInfo {
+                    number,
+                    hash: cursor.hash.clone(),
+                    parent,
+                })
+                .unwrap();
+            parent = cursor.hash.clone();
+
+            let key = format!("block/{:0>10}/{}", number, cursor.hash);
+            source
+                .put(&key, Bytes::from(vec![number as u8]), PutOptions::default())
+                .await
+                .unwrap();
+        }
+
+        // Pending blocks are never included.
+        source
+            .put(
+                "block/0000001010/pending-0001",
+                Bytes::from_static(b"pending"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        let recent = builder.current_segment().unwrap();
+        let recent = rkyv::to_bytes::<rkyv::rancor::Error>(&recent).unwrap();
+        let recent = source
+            .put(
+                "canon/recent",
+                Bytes::copy_from_slice(recent.as_slice()),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        source_state.put_starting_block(1_000).await.unwrap();
+        source_state.put_finalized(1_007).await.unwrap();
+        source_state.put_ingested(recent.etag).await.unwrap();
+
+        DatasetManifestStore::new(source.clone())
+            .update(|dataset| {
+                dataset.chain_id = Some("test".to_string());
+            })
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let range = SnapshotRange {
+            from_block: None,
+            to_block: Some(1_005),
+        };
+
+        let manifest = export_snapshot(
+            source,
+            source_state,
+            OptionsStore::new_in_memory(),
+            dir.path(),
+            range,
+            4,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(manifest.starting_block, 1_000);
+        assert_eq!(manifest.last_block, Some(1_005));
+        assert_eq!(manifest.finalized, 1_005);
+        assert_eq!(manifest.objects.len(), 7);
+        assert_eq!(manifest.chain_id.as_deref(), Some("test"));
+
+        let target = ObjectStore::new_in_memory(Default::default());
+        let mut target_state = IngestionStateClient::new_in_memory();
+
+        import_snapshot(
+            target.clone(),
+            target_state.clone(),
+            OptionsStore::new_in_memory(),
+            dir.path(),
+            4,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            target_state.get_starting_block().await.unwrap(),
+            Some(1_000)
+        );
+        assert_eq!(target_state.get_finalized().await.unwrap(), Some(1_005));
+
+        let mut blocks = target.list("block/").await.unwrap();
+        blocks.sort();
+        assert_eq!(blocks.len(), 6);
+        assert!(blocks[5].starts_with("block/0000001005/"));
+
+        let ingested = target_state.get_ingested().await.unwrap();
+        let recent = target
+            .get("canon/recent", GetOptions { etag: ingested })
+            .await
+            .unwrap();
+        let recent: CanonicalChainSegment =
+            rkyv::from_bytes::<_, rkyv::rancor::Error>(&recent.body).unwrap();
+        assert_eq!(recent.info.last_block, new_test_cursor(1_005, 0));
+
+        let dataset = DatasetManifestStore::new(target)
+            .get()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dataset.chain_id.as_deref(), Some("test"));
+        assert_eq!(dataset.finalized, Some(1_005));
+    }
+}
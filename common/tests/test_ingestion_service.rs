@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use alloy_rpc_types::BlockNumberOrTag;
 use apibara_etcd::EtcdClient;
@@ -8,22 +8,27 @@ use testcontainers::{runners::AsyncRunner, ContainerAsync};
 
 use apibara_dna_common::{
     chain::BlockInfo,
+    chain_store::ChainStore,
+    dataset_manifest::{DatasetManifest, DatasetManifestStore},
     file_cache::FileCache,
     fragment,
     ingestion::{
+        ingestion_service_loop,
         state_client::testing::{etcd_server_container, EtcdServer, EtcdServerExt},
         BlockIngestion, IngestionError, IngestionMetrics, IngestionService,
-        IngestionServiceOptions, IngestionStateClient,
+        IngestionServiceOptions, IngestionStateClient, RpcRateLimiter,
     },
     object_store::{
         testing::{minio_container, MinIO, MinIOExt},
         ObjectStore, ObjectStoreOptions,
     },
+    reload::RuntimeSettings,
     Cursor, Hash,
 };
 use testing::{
     anvil_server_container, AnvilProvider, AnvilProviderExt, AnvilServer, AnvilServerExt,
 };
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 
 async fn init_minio() -> (ContainerAsync<MinIO>, ObjectStore) {
@@ -164,6 +169,146 @@ async fn test_ingestion_initialize_with_starting_block() {
     assert!(ingested.is_none());
 }
 
+#[tokio::test]
+async fn test_ingestion_initialize_with_finality_depth() {
+    let (_minio, object_store) = init_minio().await;
+    let (_etcd_server, etcd_client) = init_etcd_server().await;
+    let (_anvil_server, anvil_provider) = init_anvil().await;
+
+    let mut state_client = IngestionStateClient::new(&etcd_client);
+    let file_cache = init_file_cache().await;
+
+    let block_ingestion = TestBlockIngestion {
+        provider: anvil_provider.clone(),
+    };
+
+    let options = IngestionServiceOptions {
+        finality_depth: Some(10),
+        ..Default::default()
+    };
+
+    let mut service = IngestionService::new(
+        block_ingestion,
+        etcd_client,
+        object_store,
+        file_cache,
+        options,
+        IngestionMetrics::default(),
+    );
+
+    anvil_provider.anvil_mine(50, 3).await;
+
+    let starting_state = service.initialize().await.unwrap();
+    let ingest_state = starting_state.as_ingest().unwrap();
+
+    assert_eq!(ingest_state.head.number, 50);
+    assert_eq!(ingest_state.finalized.number, 40);
+
+    let finalized = state_client.get_finalized().await.unwrap();
+    assert_eq!(finalized, Some(40));
+}
+
+#[tokio::test]
+async fn test_ingestion_stops_at_stop_block() {
+    let (_minio, object_store) = init_minio().await;
+    let (_etcd_server, etcd_client) = init_etcd_server().await;
+    let (_anvil_server, anvil_provider) = init_anvil().await;
+
+    let manifest_store = DatasetManifestStore::new(object_store.clone());
+
+    let settings = RuntimeSettings {
+        log_filter: "info".to_string(),
+        max_concurrent_streams: 1,
+        stream_memory_budget: 0,
+        rpc_rate_limit: None,
+    };
+    let (_settings_tx, settings_rx) = watch::channel(settings);
+    let (_paused_tx, paused_rx) = watch::channel(false);
+
+    let start_service = |stop_block: Option<u64>, ct: CancellationToken| {
+        let block_ingestion = TestBlockIngestion {
+            provider: anvil_provider.clone(),
+        };
+        let etcd_client = etcd_client.clone();
+        let object_store = object_store.clone();
+        let options = IngestionServiceOptions {
+            chain_segment_size: 10,
+            chain_segment_upload_offset_size: 1,
+            max_concurrent_tasks: 5,
+            lock_ttl: Duration::from_secs(5),
+            finality_depth: Some(10),
+            stop_block,
+            ..Default::default()
+        };
+        let settings_rx = settings_rx.clone();
+        let paused_rx = paused_rx.clone();
+        async move {
+            let file_cache = init_file_cache().await;
+            ingestion_service_loop(
+                block_ingestion,
+                RpcRateLimiter::default(),
+                etcd_client,
+                object_store,
+                file_cache,
+                options,
+                settings_rx,
+                paused_rx,
+                ct,
+            )
+            .await
+        }
+    };
+
+    let wait_for_manifest = |predicate: fn(&DatasetManifest) -> bool| {
+        let manifest_store = manifest_store.clone();
+        async move {
+            tokio::time::timeout(Duration::from_secs(60), async {
+                loop {
+                    if let Some(manifest) = manifest_store.get().await.unwrap() {
+                        if predicate(&manifest) {
+                            break manifest;
+                        }
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            })
+            .await
+            .expect("manifest not updated")
+        }
+    };
+
+    anvil_provider.anvil_mine(100, 3).await;
+
+    let ct = CancellationToken::new();
+    let handle = tokio::spawn(start_service(Some(50), ct.clone()));
+
+    let manifest = wait_for_manifest(|manifest| manifest.complete).await;
+    assert_eq!(manifest.ingested, Some(50));
+
+    // The recent chain segment ends at the stop block, even though the chain is longer.
+    let chain_store = ChainStore::new(object_store.clone(), init_file_cache().await);
+    let recent = chain_store.get_recent(None).await.unwrap().unwrap();
+    assert_eq!(recent.info.last_block.number, 50);
+
+    // The service keeps running after ingestion stops.
+    assert!(!handle.is_finished());
+    ct.cancel();
+    handle.await.unwrap().unwrap();
+
+    // Without a stop block, ingestion resumes and the dataset is no longer complete.
+    let ct = CancellationToken::new();
+    let handle = tokio::spawn(start_service(None, ct.clone()));
+
+    let manifest = wait_for_manifest(|manifest| {
+        !manifest.complete && manifest.ingested.is_some_and(|ingested| ingested > 50)
+    })
+    .await;
+    assert!(manifest.ingested.unwrap() > 50);
+
+    ct.cancel();
+    handle.await.unwrap().unwrap();
+}
+
 #[tokio::test]
 async fn test_ingestion_advances_as_head_changes() {
     let (_minio, object_store) = init_minio().await;
@@ -261,6 +406,62 @@ async fn test_ingestion_advances_as_head_changes() {
     assert!(ingested.is_some());
 }
 
+#[tokio::test]
+async fn test_ingestion_backfill_in_chunks() {
+    let (_minio, object_store) = init_minio().await;
+    let (_etcd_server, etcd_client) = init_etcd_server().await;
+    let (_anvil_server, anvil_provider) = init_anvil().await;
+
+    let file_cache = init_file_cache().await;
+
+    let block_ingestion = TestBlockIngestion {
+        provider: anvil_provider.clone(),
+    };
+
+    let options = IngestionServiceOptions {
+        chain_segment_size: 10,
+        chain_segment_upload_offset_size: 1,
+        max_concurrent_tasks: 5,
+        backfill_chunk_size: Some(10),
+        ..Default::default()
+    };
+
+    let mut service = IngestionService::new(
+        block_ingestion,
+        etcd_client,
+        object_store,
+        file_cache,
+        options,
+        IngestionMetrics::default(),
+    );
+
+    anvil_provider.anvil_mine(200, 3).await;
+
+    let starting_state = service.initialize().await.unwrap();
+    assert_eq!(service.task_queue_len(), 0);
+
+    anvil_provider.anvil_mine(1, 3).await;
+
+    let state = starting_state.take_ingest().unwrap();
+    assert!(state.finalized.number > 50);
+
+    // Each task ingests a chunk of finalized blocks.
+    let state = service.tick_refresh_head(state).await.unwrap();
+    let state = state.take_ingest().unwrap();
+    assert_eq!(service.task_queue_len(), 5);
+    assert_eq!(state.queued_block_number, 50);
+
+    let join_result = service.task_queue_next().await;
+    let state = service
+        .tick_with_task_result(state, join_result)
+        .await
+        .unwrap();
+    let state = state.take_ingest().unwrap();
+    assert_eq!(state.last_ingested.number, 10);
+    assert_eq!(state.queued_block_number, 60);
+    assert_eq!(service.task_queue_len(), 5);
+}
+
 #[tokio::test]
 async fn test_ingestion_not_affected_by_reorg_after_ingested_block() {
     let (_minio, object_store) = init_minio().await;
@@ -777,6 +978,102 @@ async fn test_ingestion_detect_reorg_on_block_ingestion() {
     assert_eq!(reconnect_cursor.number, 90);
 }
 
+#[tokio::test]
+async fn test_standby_takes_over_ingestion() {
+    let (_minio, object_store) = init_minio().await;
+    let (_etcd_server, etcd_client) = init_etcd_server().await;
+    let (_anvil_server, anvil_provider) = init_anvil().await;
+
+    let mut state_client = IngestionStateClient::new(&etcd_client);
+
+    let options = IngestionServiceOptions {
+        chain_segment_size: 10,
+        chain_segment_upload_offset_size: 1,
+        max_concurrent_tasks: 5,
+        lock_ttl: Duration::from_secs(5),
+        standby_warm_interval: Duration::from_secs(1),
+        ..Default::default()
+    };
+
+    let settings = RuntimeSettings {
+        log_filter: "info".to_string(),
+        max_concurrent_streams: 1,
+        stream_memory_budget: 0,
+        rpc_rate_limit: None,
+    };
+    let (_settings_tx, settings_rx) = watch::channel(settings);
+    let (_paused_tx, paused_rx) = watch::channel(false);
+
+    let start_service = |ct: CancellationToken| {
+        let block_ingestion = TestBlockIngestion {
+            provider: anvil_provider.clone(),
+        };
+        let etcd_client = etcd_client.clone();
+        let object_store = object_store.clone();
+        let options = options.clone();
+        let settings_rx = settings_rx.clone();
+        let paused_rx = paused_rx.clone();
+        async move {
+            let file_cache = init_file_cache().await;
+            ingestion_service_loop(
+                block_ingestion,
+                RpcRateLimiter::default(),
+                etcd_client,
+                object_store,
+                file_cache,
+                options,
+                settings_rx,
+                paused_rx,
+                ct,
+            )
+            .await
+        }
+    };
+
+    anvil_provider.anvil_mine(200, 3).await;
+
+    let active_ct = CancellationToken::new();
+    let active_handle = tokio::spawn(start_service(active_ct.clone()));
+
+    let ingested = tokio::time::timeout(Duration::from_secs(60), async {
+        loop {
+            if let Some(ingested) = state_client.get_ingested().await.unwrap() {
+                break ingested;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("active service did not ingest");
+
+    // The standby waits for the lock held by the active service.
+    let standby_ct = CancellationToken::new();
+    let standby_handle = tokio::spawn(start_service(standby_ct.clone()));
+
+    // The active service releases the lock when it stops.
+    active_ct.cancel();
+    active_handle.await.unwrap().unwrap();
+
+    anvil_provider.anvil_mine(100, 3).await;
+
+    tokio::time::timeout(Duration::from_secs(60), async {
+        loop {
+            let current = state_client.get_ingested().await.unwrap();
+            if current.as_ref() != Some(&ingested) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("standby service did not take over ingestion");
+
+    assert!(!standby_handle.is_finished());
+
+    standby_ct.cancel();
+    standby_handle.await.unwrap().unwrap();
+}
+
 #[derive(Clone)]
 struct TestBlockIngestion {
     provider: Arc<AnvilProvider>,
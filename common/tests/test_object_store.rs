@@ -2,8 +2,8 @@ use testcontainers::runners::AsyncRunner;
 
 use apibara_dna_common::object_store::{
     testing::{minio_container, MinIOExt},
-    DeleteOptions, GetOptions, ObjectETag, ObjectStore, ObjectStoreOptions, ObjectStoreResultExt,
-    PutMode, PutOptions,
+    CompressionCodec, CompressionOptions, DeleteOptions, GetOptions, ObjectETag, ObjectStore,
+    ObjectStoreOptions, ObjectStoreResultExt, PutMode, PutMultipartOptions, PutOptions,
 };
 
 #[tokio::test]
@@ -49,6 +49,7 @@ async fn test_put_and_get_with_prefix_no_precondition() {
         ObjectStoreOptions {
             bucket: "test".to_string(),
             prefix: Some("my-prefix".to_string()),
+            ..Default::default()
         },
     );
 
@@ -65,6 +66,7 @@ async fn test_put_and_get_with_prefix_no_precondition() {
             ObjectStoreOptions {
                 bucket: "test".to_string(),
                 prefix: None,
+                ..Default::default()
             },
         );
         client
@@ -148,6 +150,7 @@ async fn test_put_with_overwrite() {
             "Something else".into(),
             PutOptions {
                 mode: PutMode::Overwrite,
+                ..Default::default()
             },
         )
         .await
@@ -177,6 +180,7 @@ async fn test_put_with_create() {
             "Hello, World".into(),
             PutOptions {
                 mode: PutMode::Create,
+                ..Default::default()
             },
         )
         .await
@@ -188,6 +192,7 @@ async fn test_put_with_create() {
             "Something else".into(),
             PutOptions {
                 mode: PutMode::Create,
+                ..Default::default()
             },
         )
         .await;
@@ -217,6 +222,7 @@ async fn test_put_with_update() {
             "Hello, World".into(),
             PutOptions {
                 mode: PutMode::Create,
+                ..Default::default()
             },
         )
         .await
@@ -230,6 +236,7 @@ async fn test_put_with_update() {
             "Something else".into(),
             PutOptions {
                 mode: PutMode::Update("bad etag".to_string().into()),
+                ..Default::default()
             },
         )
         .await;
@@ -242,6 +249,7 @@ async fn test_put_with_update() {
             "Something else".into(),
             PutOptions {
                 mode: PutMode::Update(original_etag.clone()),
+                ..Default::default()
             },
         )
         .await
@@ -279,3 +287,126 @@ async fn test_delete() {
     assert!(response.is_err());
     assert!(response.unwrap_err().is_not_found());
 }
+
+#[tokio::test]
+async fn test_put_multipart() {
+    let minio = minio_container().start().await.unwrap();
+    let config = minio.s3_config().await;
+
+    let client = ObjectStore::new_from_config(
+        config.clone(),
+        ObjectStoreOptions {
+            bucket: "test".to_string(),
+            ..Default::default()
+        },
+    );
+
+    client.ensure_bucket().await.unwrap();
+
+    // Random data doesn't compress, so this is split into three parts.
+    let body: Vec<u8> = (0..12 * 1024 * 1024)
+        .map(|_| rand::random::<u8>())
+        .collect();
+
+    client
+        .put_multipart(
+            "test",
+            body.clone().into(),
+            PutMultipartOptions {
+                part_size: 5 * 1024 * 1024,
+                max_concurrent_parts: 2,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let response = client.get("test", GetOptions::default()).await.unwrap();
+    assert_eq!(response.body, body);
+}
+
+#[tokio::test]
+async fn test_put_and_get_with_different_compression() {
+    let minio = minio_container().start().await.unwrap();
+    let config = minio.s3_config().await;
+
+    let zstd_client = ObjectStore::new_from_config(
+        config.clone(),
+        ObjectStoreOptions {
+            bucket: "test".to_string(),
+            compression: CompressionOptions {
+                codec: CompressionCodec::Zstd,
+                level: 19,
+            },
+            ..Default::default()
+        },
+    );
+
+    let uncompressed_client = ObjectStore::new_from_config(
+        config,
+        ObjectStoreOptions {
+            bucket: "test".to_string(),
+            compression: CompressionOptions {
+                codec: CompressionCodec::None,
+                level: 0,
+            },
+            ..Default::default()
+        },
+    );
+
+    zstd_client.ensure_bucket().await.unwrap();
+
+    zstd_client
+        .put("zstd", "Hello, Zstd".into(), PutOptions::default())
+        .await
+        .unwrap();
+    uncompressed_client
+        .put("none", "Hello, None".into(), PutOptions::default())
+        .await
+        .unwrap();
+
+    // Clients can read objects regardless of their compression settings.
+    let get_res = uncompressed_client
+        .get("zstd", GetOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(get_res.body, "Hello, Zstd".as_bytes());
+
+    let get_res = zstd_client
+        .get("none", GetOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(get_res.body, "Hello, None".as_bytes());
+}
+
+#[tokio::test]
+async fn test_copy_from_prefixed_client() {
+    let minio = minio_container().start().await.unwrap();
+    let config = minio.s3_config().await;
+
+    let client = ObjectStore::new_from_config(
+        config,
+        ObjectStoreOptions {
+            bucket: "test".to_string(),
+            prefix: Some("my-prefix".to_string()),
+            ..Default::default()
+        },
+    );
+
+    client.ensure_bucket().await.unwrap();
+
+    let staging = client.with_prefix("staging");
+
+    staging
+        .put("test", "Staged".into(), PutOptions::default())
+        .await
+        .unwrap();
+
+    client.copy("staging/test", "test").await.unwrap();
+
+    let get_res = client.get("test", GetOptions::default()).await.unwrap();
+    assert_eq!(get_res.body, "Staged".as_bytes());
+
+    let keys = staging.list("").await.unwrap();
+    assert_eq!(keys, vec!["test".to_string()]);
+}
@@ -48,7 +48,10 @@ async fn main() -> Result<(), CliError> {
             prefix,
         } => {
             let ct = CancellationToken::new();
-            let options = EtcdClientOptions { prefix, auth: None };
+            let options = EtcdClientOptions {
+                prefix,
+                ..Default::default()
+            };
             let client = EtcdClient::connect(endpoints, options)
                 .await
                 .change_context(CliError)
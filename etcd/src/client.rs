@@ -20,10 +20,24 @@ pub struct AuthOptions {
     pub token_ttl: Duration,
 }
 
+/// TLS options, with certificates in PEM format.
+///
+/// The etcd endpoints must use the `https://` scheme.
+#[derive(Debug, Default, Clone)]
+pub struct TlsOptions {
+    /// CA certificate used to verify the server.
+    pub ca_certificate: Option<Vec<u8>>,
+    /// Client certificate and key, for mutual TLS.
+    pub identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// Domain name used to verify the server certificate.
+    pub domain_name: Option<String>,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct EtcdClientOptions {
     pub prefix: Option<String>,
     pub auth: Option<AuthOptions>,
+    pub tls: Option<TlsOptions>,
 }
 
 #[derive(Clone)]
@@ -38,10 +52,18 @@ impl EtcdClient {
         endpoints: S,
         options: EtcdClientOptions,
     ) -> Result<Self, EtcdClientError> {
-        let connect_options = if let Some(auth) = options.auth.clone() {
-            etcd_client::ConnectOptions::new()
-                .with_user(auth.user, auth.password)
-                .into()
+        let connect_options = if options.auth.is_some() || options.tls.is_some() {
+            let mut connect_options = etcd_client::ConnectOptions::new();
+
+            if let Some(auth) = options.auth.clone() {
+                connect_options = connect_options.with_user(auth.user, auth.password);
+            }
+
+            if let Some(tls) = options.tls.clone() {
+                connect_options = connect_options.with_tls(tls.into_etcd_tls_options());
+            }
+
+            Some(connect_options)
         } else {
             None
         };
@@ -120,6 +142,26 @@ impl EtcdClient {
     }
 }
 
+impl TlsOptions {
+    fn into_etcd_tls_options(self) -> etcd_client::TlsOptions {
+        let mut tls = etcd_client::TlsOptions::new();
+
+        if let Some(ca_certificate) = self.ca_certificate {
+            tls = tls.ca_certificate(etcd_client::Certificate::from_pem(ca_certificate));
+        }
+
+        if let Some((cert, key)) = self.identity {
+            tls = tls.identity(etcd_client::Identity::from_pem(cert, key));
+        }
+
+        if let Some(domain_name) = self.domain_name {
+            tls = tls.domain_name(domain_name);
+        }
+
+        tls
+    }
+}
+
 impl error_stack::Context for EtcdClientError {}
 
 impl std::fmt::Display for EtcdClientError {
@@ -1,7 +1,7 @@
 use error_stack::{Result, ResultExt};
 
+pub use etcd_client::{DeleteResponse, GetResponse, PutResponse};
 use etcd_client::{GetOptions, TxnResponse};
-pub use etcd_client::{GetResponse, PutResponse};
 
 use crate::client::EtcdClientError;
 
@@ -54,6 +54,20 @@ impl KvClient {
             .attach_printable_lazy(|| format!("key: {}", key))
     }
 
+    #[tracing::instrument(level = "debug", skip_all, fields(key = key.as_ref()))]
+    pub async fn delete(
+        &mut self,
+        key: impl AsRef<str>,
+    ) -> Result<DeleteResponse, EtcdClientError> {
+        let key = key.as_ref();
+        self.client
+            .delete(self.format_key(key), None)
+            .await
+            .change_context(EtcdClientError)
+            .attach_printable("failed to delete key from etcd")
+            .attach_printable_lazy(|| format!("key: {}", key))
+    }
+
     #[tracing::instrument(level = "debug", skip_all, fields(put_key = put_key.as_ref(), del_key = del_key.as_ref()))]
     pub async fn put_and_delete(
         &mut self,
@@ -79,6 +93,36 @@ impl KvClient {
             .attach_printable_lazy(|| format!("del_key: {}", del_key))
     }
 
+    /// Put and delete the keys in a single transaction.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn put_and_delete_many(
+        &mut self,
+        puts: &[(String, String)],
+        deletes: &[String],
+    ) -> Result<TxnResponse, EtcdClientError> {
+        let ops = puts
+            .iter()
+            .map(|(key, value)| {
+                etcd_client::TxnOp::put(self.format_key(key), value.as_bytes(), None)
+            })
+            .chain(
+                deletes
+                    .iter()
+                    .map(|key| etcd_client::TxnOp::delete(self.format_key(key), None)),
+            )
+            .collect::<Vec<_>>();
+
+        let txn = etcd_client::Txn::new().and_then(ops);
+
+        self.client
+            .txn(txn)
+            .await
+            .change_context(EtcdClientError)
+            .attach_printable("failed to put and delete keys to etcd")
+            .attach_printable_lazy(|| format!("puts: {}", puts.len()))
+            .attach_printable_lazy(|| format!("deletes: {}", deletes.len()))
+    }
+
     fn format_key(&self, key: &str) -> Vec<u8> {
         format!("{}{}", self.prefix, key).into_bytes()
     }
@@ -6,9 +6,9 @@ mod utils;
 mod watch;
 
 pub use self::client::{
-    AuthOptions, EtcdClient, EtcdClientError, EtcdClientOptions, StatusResponse,
+    AuthOptions, EtcdClient, EtcdClientError, EtcdClientOptions, StatusResponse, TlsOptions,
 };
-pub use self::kv::{GetResponse, KvClient, PutResponse};
+pub use self::kv::{DeleteResponse, GetResponse, KvClient, PutResponse};
 pub use self::lock::{Lock, LockClient, LockOptions};
 pub use self::utils::normalize_prefix;
 pub use self::watch::WatchClient;
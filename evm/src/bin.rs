@@ -1,6 +1,6 @@
+use apibara_dna_common::config_file::parse_with_config_file;
 use apibara_dna_evm::{cli::Cli, error::EvmError};
 use apibara_observability::init_opentelemetry;
-use clap::Parser;
 use error_stack::{Result, ResultExt};
 use mimalloc::MiMalloc;
 use tokio_util::sync::CancellationToken;
@@ -11,7 +11,7 @@ static GLOBAL: MiMalloc = MiMalloc;
 
 #[tokio::main]
 async fn main() -> Result<(), EvmError> {
-    let args = Cli::parse();
+    let args = parse_with_config_file::<Cli>().change_context(EvmError)?;
     run_with_args(args).await
 }
 
@@ -0,0 +1,79 @@
+use apibara_dna_common::{
+    cli::ObjectStoreArgs,
+    dbg::{run_debug_store_diff, DebugInspectSegmentCommand, DebugLintFilterCommand},
+    fragment::{FragmentId, HEADER_FRAGMENT_ID},
+};
+use apibara_dna_protocol::evm;
+use clap::Subcommand;
+use error_stack::{Result, ResultExt};
+use prost::Message;
+
+use crate::{
+    cli::rpc::RpcArgs,
+    error::EvmError,
+    filter::EvmFilterFactory,
+    fragment::{
+        LOG_FRAGMENT_ID, RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID, WITHDRAWAL_FRAGMENT_ID,
+    },
+    EvmBlockIngestionOptions, EvmChainSupport,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum DebugStoreCommand {
+    /// Compare a stored block with the same block fetched from the RPC.
+    Diff {
+        #[clap(flatten)]
+        rpc: RpcArgs,
+        #[clap(flatten)]
+        object_store: ObjectStoreArgs,
+        /// The block number.
+        #[arg(long)]
+        block: u64,
+    },
+    /// Print a summary of the segment starting at the given block.
+    InspectSegment(DebugInspectSegmentCommand),
+    /// Validate filters and estimate their selectivity on a stored segment.
+    LintFilter(DebugLintFilterCommand),
+}
+
+impl DebugStoreCommand {
+    pub async fn run(self) -> Result<(), EvmError> {
+        match self {
+            DebugStoreCommand::Diff {
+                rpc,
+                object_store,
+                block,
+            } => {
+                let provider = rpc.to_json_rpc_provider()?;
+                let options = EvmBlockIngestionOptions {
+                    ingest_pending: false,
+                    index_proxy_implementation: false,
+                };
+                let evm_chain = EvmChainSupport::new(provider, options);
+
+                run_debug_store_diff(evm_chain, format_fragment, block, object_store)
+                    .await
+                    .change_context(EvmError)
+            }
+            DebugStoreCommand::InspectSegment(command) => {
+                command.run().await.change_context(EvmError)
+            }
+            DebugStoreCommand::LintFilter(command) => {
+                command.run(EvmFilterFactory).await.change_context(EvmError)
+            }
+        }
+    }
+}
+
+fn format_fragment(fragment_id: FragmentId, data: &[u8]) -> String {
+    let formatted = match fragment_id {
+        HEADER_FRAGMENT_ID => evm::BlockHeader::decode(data).map(|m| format!("{m:#?}")),
+        WITHDRAWAL_FRAGMENT_ID => evm::Withdrawal::decode(data).map(|m| format!("{m:#?}")),
+        TRANSACTION_FRAGMENT_ID => evm::Transaction::decode(data).map(|m| format!("{m:#?}")),
+        RECEIPT_FRAGMENT_ID => evm::TransactionReceipt::decode(data).map(|m| format!("{m:#?}")),
+        LOG_FRAGMENT_ID => evm::Log::decode(data).map(|m| format!("{m:#?}")),
+        _ => return hex::encode(data),
+    };
+
+    formatted.unwrap_or_else(|err| format!("invalid message: {err}"))
+}
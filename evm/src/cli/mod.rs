@@ -2,18 +2,31 @@ mod dbg;
 mod rpc;
 mod start;
 
-use apibara_dna_common::dbg::DebugIndexCommand;
+use std::path::PathBuf;
+
+use apibara_dna_common::{
+    dbg::DebugIndexCommand, export::ExportCommand, migrate::MigrateCommand,
+    resegment::ResegmentCommand, snapshot::SnapshotCommand,
+};
 use clap::{Parser, Subcommand};
 use error_stack::{Result, ResultExt};
 use tokio_util::sync::CancellationToken;
 
-use crate::error::EvmError;
+use crate::{error::EvmError, filter::EvmFilterFactory, fragment};
 
-use self::{dbg::DebugRpcCommand, start::StartCommand};
+use self::{
+    dbg::{DebugRpcCommand, DebugStoreCommand},
+    start::StartCommand,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Path to a TOML or YAML configuration file.
+    ///
+    /// Options set on the command line or in the environment take precedence.
+    #[arg(long = "config", env = "DNA_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
@@ -28,12 +41,29 @@ pub enum Command {
         #[clap(subcommand)]
         command: DebugRpcCommand,
     },
+    /// Debug the blocks in the object store.
+    #[command(name = "dbg-store")]
+    DebugStore {
+        #[clap(subcommand)]
+        command: DebugStoreCommand,
+    },
     /// Debug the index file.
     #[command(name = "dbg-index")]
     DebugIndex {
         #[clap(subcommand)]
         command: DebugIndexCommand,
     },
+    /// Upgrade stored segments to the current format.
+    Migrate(Box<MigrateCommand>),
+    /// Rewrite stored segments with a new segment size or compression.
+    Resegment(Box<ResegmentCommand>),
+    /// Export the data matching a filter to Parquet files.
+    Export(Box<ExportCommand>),
+    /// Export or import a snapshot of the data.
+    Snapshot {
+        #[clap(subcommand)]
+        command: Box<SnapshotCommand>,
+    },
 }
 
 impl Cli {
@@ -41,7 +71,15 @@ impl Cli {
         match self.command {
             Command::Start(command) => command.run(ct).await,
             Command::DebugRpc { command } => command.run().await,
+            Command::DebugStore { command } => command.run().await,
             Command::DebugIndex { command } => command.run().await.change_context(EvmError),
+            Command::Migrate(command) => command.run().await.change_context(EvmError),
+            Command::Resegment(command) => command.run().await.change_context(EvmError),
+            Command::Export(command) => command
+                .run(EvmFilterFactory, fragment::fragment_info(), ct)
+                .await
+                .change_context(EvmError),
+            Command::Snapshot { command } => command.run().await.change_context(EvmError),
         }
     }
 }
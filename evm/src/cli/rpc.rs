@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use apibara_dna_common::rpc_pool::{weighted_urls, RpcEndpoint, RpcPoolOptions};
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
@@ -13,12 +14,61 @@ use crate::{
 #[derive(Args, Debug)]
 pub struct RpcArgs {
     /// Evm RPC URL.
+    ///
+    /// Pass multiple URLs, separated by commas, to fail over between them.
     #[arg(
         long = "rpc.url",
         env = "EVM_RPC_URL",
+        value_delimiter = ',',
         default_value = "http://localhost:9545"
     )]
-    pub rpc_url: String,
+    pub rpc_url: Vec<String>,
+
+    /// Weight of each RPC URL when load balancing, in the same order as the URLs.
+    ///
+    /// URLs without a weight default to 1.
+    #[arg(long = "rpc.weights", env = "EVM_RPC_WEIGHTS", value_delimiter = ',')]
+    pub rpc_weights: Vec<u32>,
+
+    /// Distribute requests across all healthy RPC URLs.
+    #[arg(
+        long = "rpc.load-balance",
+        env = "EVM_RPC_LOAD_BALANCE",
+        default_value = "false"
+    )]
+    pub rpc_load_balance: bool,
+
+    /// How long to skip an RPC URL after a failed request.
+    #[arg(
+        long = "rpc.failover-cooldown-sec",
+        env = "EVM_RPC_FAILOVER_COOLDOWN_SEC",
+        default_value = "30"
+    )]
+    pub rpc_failover_cooldown_sec: u64,
+
+    /// How often to check the RPC URLs' health in the background, in seconds.
+    ///
+    /// Set to 0 to disable the health checks.
+    #[arg(
+        long = "rpc.health-check-interval-sec",
+        env = "EVM_RPC_HEALTH_CHECK_INTERVAL_SEC",
+        default_value = "10"
+    )]
+    pub rpc_health_check_interval_sec: u64,
+
+    /// WebSocket URL used to subscribe to new heads instead of polling.
+    #[arg(long = "rpc.ws-url", env = "EVM_RPC_WS_URL")]
+    pub rpc_ws_url: Option<String>,
+
+    /// Check that the block receipts hash to the block's receipts root.
+    ///
+    /// Only enable this on chains that use Ethereum's receipt types.
+    #[arg(
+        long = "rpc.validate-receipts-root",
+        env = "EVM_RPC_VALIDATE_RECEIPTS_ROOT",
+        default_value = "false"
+    )]
+    pub rpc_validate_receipts_root: bool,
 
     /// Request timeout.
     #[arg(
@@ -35,12 +85,34 @@ pub struct RpcArgs {
 
 impl RpcArgs {
     pub fn to_json_rpc_provider(&self) -> Result<JsonRpcProvider, EvmError> {
-        let url = self
-            .rpc_url
-            .parse::<Url>()
+        let urls = weighted_urls(&self.rpc_url, &self.rpc_weights)
             .change_context(EvmError)
-            .attach_printable("failed to parse RPC URL")
-            .attach_printable_lazy(|| format!("url: {}", self.rpc_url))?;
+            .attach_printable("invalid RPC weights")?
+            .into_iter()
+            .map(|(url, weight)| -> Result<RpcEndpoint<Url>, EvmError> {
+                let url = url
+                    .parse::<Url>()
+                    .change_context(EvmError)
+                    .attach_printable("failed to parse RPC URL")
+                    .attach_printable_lazy(|| format!("url: {}", url))?;
+                Ok(RpcEndpoint {
+                    name: url.host_str().unwrap_or_default().to_string(),
+                    client: url,
+                    weight,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let ws_url = self
+            .rpc_ws_url
+            .as_ref()
+            .map(|url| {
+                url.parse::<Url>()
+                    .change_context(EvmError)
+                    .attach_printable("failed to parse WebSocket URL")
+                    .attach_printable_lazy(|| format!("url: {}", url))
+            })
+            .transpose()?;
 
         let headers = {
             let mut headers = HeaderMap::default();
@@ -71,8 +143,16 @@ impl RpcArgs {
         let options = JsonRpcProviderOptions {
             timeout: Duration::from_secs(self.rpc_timeout_sec),
             headers,
+            pool: RpcPoolOptions {
+                load_balance: self.rpc_load_balance,
+                unhealthy_cooldown: Duration::from_secs(self.rpc_failover_cooldown_sec),
+                health_check_interval: (self.rpc_health_check_interval_sec > 0)
+                    .then(|| Duration::from_secs(self.rpc_health_check_interval_sec)),
+            },
+            ws_url,
+            validate_receipts_root: self.rpc_validate_receipts_root,
         };
 
-        JsonRpcProvider::new(url, options).change_context(EvmError)
+        JsonRpcProvider::new(urls, options).change_context(EvmError)
     }
 }
@@ -22,14 +22,27 @@ pub struct StartCommand {
         default_value = "false"
     )]
     no_ingest_pending: bool,
+
+    /// Index logs by the implementation of the EIP-1967 proxy emitting them.
+    ///
+    /// The implementation of contracts not upgraded since ingestion started is read
+    /// from the node, which sends more requests to the node.
+    #[arg(
+        long = "evm.index-proxy-implementation",
+        env = "EVM_INDEX_PROXY_IMPLEMENTATION",
+        default_value = "false"
+    )]
+    index_proxy_implementation: bool,
 }
 
 impl StartCommand {
     pub async fn run(self, ct: CancellationToken) -> Result<(), EvmError> {
         info!("Starting EVM DNA server");
         let provider = self.rpc.to_json_rpc_provider()?;
+        tokio::spawn(provider.clone().health_check_loop(ct.clone()));
         let evm_ingestion_options = EvmBlockIngestionOptions {
             ingest_pending: !self.no_ingest_pending,
+            index_proxy_implementation: self.index_proxy_implementation,
         };
         let evm_chain = EvmChainSupport::new(provider, evm_ingestion_options);
 
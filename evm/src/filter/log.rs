@@ -1,32 +1,81 @@
 use apibara_dna_common::{
     index::ScalarValue,
-    query::{Condition, Filter},
+    query::{Condition, ConditionKey, Filter},
 };
-use apibara_dna_protocol::evm;
+use apibara_dna_protocol::{error_details::FieldViolation, evm};
 
 use crate::fragment::{
-    INDEX_LOG_BY_ADDRESS, INDEX_LOG_BY_TOPIC0, INDEX_LOG_BY_TOPIC1, INDEX_LOG_BY_TOPIC2,
-    INDEX_LOG_BY_TOPIC3, INDEX_LOG_BY_TOPIC_LENGTH, INDEX_LOG_BY_TRANSACTION_STATUS,
-    LOG_FRAGMENT_ID, RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
+    INDEX_LOG_BY_ADDRESS, INDEX_LOG_BY_PROXY_IMPLEMENTATION, INDEX_LOG_BY_TOPIC0,
+    INDEX_LOG_BY_TOPIC1, INDEX_LOG_BY_TOPIC2, INDEX_LOG_BY_TOPIC3, INDEX_LOG_BY_TOPIC_LENGTH,
+    INDEX_LOG_BY_TRANSACTION_STATUS, LOG_FRAGMENT_ID, RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+/// Maximum number of addresses in a log filter's address set.
+const MAX_ADDRESS_SET_SIZE: usize = 100_000;
 
 impl FragmentFilterExt for evm::LogFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(address) = self.address {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_ADDRESS,
-                key: ScalarValue::B160(address.to_bytes()),
+                key: ScalarValue::B160(address.to_bytes()).into(),
+            });
+        }
+
+        if let Some(address_set) = &self.address_set {
+            if address_set.len() % 20 != 0 {
+                return Err(FieldViolation::new(
+                    "address_set",
+                    format!(
+                        "invalid address set in log filter with id {}: length must be a multiple of 20",
+                        self.id
+                    ),
+                ));
+            }
+
+            if address_set.len() / 20 > MAX_ADDRESS_SET_SIZE {
+                return Err(FieldViolation::new(
+                    "address_set",
+                    format!(
+                        "address set in log filter with id {} has more than {} addresses",
+                        self.id, MAX_ADDRESS_SET_SIZE
+                    ),
+                ));
+            }
+
+            // Sorted keys are evaluated faster against the index.
+            let mut addresses = address_set
+                .chunks_exact(20)
+                .map(|address| {
+                    let mut bytes = [0; 20];
+                    bytes.copy_from_slice(address);
+                    ScalarValue::B160(bytes)
+                })
+                .collect::<Vec<_>>();
+            addresses.sort();
+            addresses.dedup();
+
+            conditions.push(Condition {
+                index_id: INDEX_LOG_BY_ADDRESS,
+                key: ConditionKey::AnyOf(addresses),
+            });
+        }
+
+        if let Some(implementation) = self.implementation {
+            conditions.push(Condition {
+                index_id: INDEX_LOG_BY_PROXY_IMPLEMENTATION,
+                key: ScalarValue::B160(implementation.to_bytes()).into(),
             });
         }
 
         if let Some(true) = self.strict {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_TOPIC_LENGTH,
-                key: ScalarValue::Uint32(self.topics.len() as u32),
+                key: ScalarValue::Uint32(self.topics.len() as u32).into(),
             });
         }
 
@@ -35,34 +84,37 @@ impl FragmentFilterExt for evm::LogFilter {
         if let Some(topic) = topics.next().and_then(|t| t.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_TOPIC0,
-                key: ScalarValue::B256(topic.to_bytes()),
+                key: ScalarValue::B256(topic.to_bytes()).into(),
             });
         }
         if let Some(topic) = topics.next().and_then(|t| t.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_TOPIC1,
-                key: ScalarValue::B256(topic.to_bytes()),
+                key: ScalarValue::B256(topic.to_bytes()).into(),
             });
         }
         if let Some(topic) = topics.next().and_then(|t| t.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_TOPIC2,
-                key: ScalarValue::B256(topic.to_bytes()),
+                key: ScalarValue::B256(topic.to_bytes()).into(),
             });
         }
         if let Some(topic) = topics.next().and_then(|t| t.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_LOG_BY_TOPIC3,
-                key: ScalarValue::B256(topic.to_bytes()),
+                key: ScalarValue::B256(topic.to_bytes()).into(),
             });
         }
 
         let transaction_status = if let Some(transaction_status) = self.transaction_status {
             evm::TransactionStatusFilter::try_from(transaction_status).map_err(|_| {
-                tonic::Status::invalid_argument(format!(
-                    "invalid transaction status in log filter with id {}",
-                    self.id
-                ))
+                FieldViolation::new(
+                    "transaction_status",
+                    format!(
+                        "invalid transaction status in log filter with id {}",
+                        self.id
+                    ),
+                )
             })?
         } else {
             evm::TransactionStatusFilter::Succeeded
@@ -74,13 +126,13 @@ impl FragmentFilterExt for evm::LogFilter {
             evm::TransactionStatusFilter::Succeeded => {
                 conditions.push(Condition {
                     index_id: INDEX_LOG_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(evm::TransactionStatus::Succeeded as i32),
+                    key: ScalarValue::Int32(evm::TransactionStatus::Succeeded as i32).into(),
                 });
             }
             evm::TransactionStatusFilter::Reverted => {
                 conditions.push(Condition {
                     index_id: INDEX_LOG_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(evm::TransactionStatus::Reverted as i32),
+                    key: ScalarValue::Int32(evm::TransactionStatus::Reverted as i32).into(),
                 });
             }
         };
@@ -104,6 +156,7 @@ impl FragmentFilterExt for evm::LogFilter {
             fragment_id: LOG_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("evm.v2.Log", self.fields.as_ref())?,
         })
     }
 }
@@ -5,12 +5,16 @@ mod withdrawal;
 
 use apibara_dna_common::{
     data_stream::BlockFilterFactory,
-    query::{BlockFilter, HeaderFilter},
+    query::{BlockFilter, FieldProjection, HeaderFilter},
+};
+use apibara_dna_protocol::{
+    error_details::{BadRequest, FieldViolation},
+    evm,
+    field_mask::resolve_field_mask,
 };
-use apibara_dna_protocol::evm;
 use prost::Message;
 
-use self::helpers::{BlockFilterExt, FragmentFilterExt};
+use self::helpers::{compile_fragment_filters, BlockFilterExt};
 
 pub struct EvmFilterFactory;
 
@@ -19,40 +23,50 @@ impl BlockFilterFactory for EvmFilterFactory {
         &self,
         filters: &[Vec<u8>],
     ) -> tonic::Result<Vec<BlockFilter>, tonic::Status> {
-        let proto_filters = filters
-            .iter()
-            .map(|bytes| evm::Filter::decode(bytes.as_slice()))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| tonic::Status::invalid_argument("failed to decode filter"))?;
-
-        if proto_filters.is_empty() {
-            return Err(tonic::Status::invalid_argument("no filters provided"));
+        let mut block_filters = Vec::with_capacity(filters.len());
+        let mut violations = Vec::new();
+
+        for (index, bytes) in filters.iter().enumerate() {
+            let parent = format!("filter[{index}]");
+
+            let Ok(filter) = evm::Filter::decode(bytes.as_slice()) else {
+                violations
+                    .push(FieldViolation::new("", "failed to decode filter").with_parent(parent));
+                continue;
+            };
+
+            match filter.compile_to_block_filter() {
+                Ok(block_filter) => block_filters.push(block_filter),
+                Err(errors) => violations.extend(
+                    errors
+                        .into_iter()
+                        .map(|violation| violation.with_parent(&parent)),
+                ),
+            }
         }
 
-        if proto_filters.len() > 5 {
-            return Err(tonic::Status::invalid_argument(format!(
-                "too many filters ({} > 5)",
-                proto_filters.len(),
-            )));
+        if !violations.is_empty() {
+            return Err(BadRequest::new(violations).into_status());
         }
 
-        let filters = proto_filters
-            .iter()
-            .map(BlockFilterExt::compile_to_block_filter)
-            .collect::<tonic::Result<Vec<_>>>()?;
+        if block_filters.is_empty() {
+            return Err(filter_violation("no filters provided"));
+        }
 
-        if filters.iter().any(|f| f.can_produce_data()) {
-            Ok(filters)
+        if block_filters.iter().any(|f| f.can_produce_data()) {
+            Ok(block_filters)
         } else {
-            Err(tonic::Status::invalid_argument(
-                "at least one filter must be non-empty",
-            ))
+            Err(filter_violation("at least one filter must be non-empty"))
         }
     }
+
+    fn filter_descriptor(&self) -> (&'static [u8], &'static str) {
+        (evm::EVM_DESCRIPTOR_SET, "evm.v2.Filter")
+    }
 }
 
 impl BlockFilterExt for evm::Filter {
-    fn compile_to_block_filter(&self) -> tonic::Result<BlockFilter, tonic::Status> {
+    fn compile_to_block_filter(&self) -> Result<BlockFilter, Vec<FieldViolation>> {
         let mut block_filter = BlockFilter::default();
 
         let header_filter = match evm::HeaderFilter::try_from(self.header) {
@@ -65,21 +79,37 @@ impl BlockFilterExt for evm::Filter {
 
         block_filter.set_header_filter(header_filter);
 
-        for filter in self.withdrawals.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+        let mut violations = Vec::new();
+        if let Some(header_fields) = self.header_fields.as_ref() {
+            match resolve_field_mask(evm::EVM_DESCRIPTOR_SET, "evm.v2.BlockHeader", header_fields) {
+                Ok(fields) => block_filter.set_header_fields(FieldProjection::new(fields)),
+                Err(violation) => violations.push(violation.with_parent("header_fields")),
+            }
         }
 
-        for filter in self.transactions.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
+        compile_fragment_filters(
+            &mut block_filter,
+            "withdrawals",
+            &self.withdrawals,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "transactions",
+            &self.transactions,
+            &mut violations,
+        );
+        compile_fragment_filters(&mut block_filter, "logs", &self.logs, &mut violations);
 
-        for filter in self.logs.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+        if violations.is_empty() {
+            Ok(block_filter)
+        } else {
+            Err(violations)
         }
-
-        Ok(block_filter)
     }
 }
+
+/// Returns an error about the list of filters as a whole.
+fn filter_violation(description: &str) -> tonic::Status {
+    BadRequest::new(vec![FieldViolation::new("filter", description)]).into_status()
+}
@@ -1,47 +1,95 @@
 use apibara_dna_common::{
     index::ScalarValue,
-    query::{Condition, Filter},
+    query::{Condition, ConditionKey, Filter},
 };
-use apibara_dna_protocol::evm;
+use apibara_dna_protocol::{error_details::FieldViolation, evm};
 
 use crate::fragment::{
-    INDEX_TRANSACTION_BY_CREATE, INDEX_TRANSACTION_BY_FROM_ADDRESS, INDEX_TRANSACTION_BY_STATUS,
-    INDEX_TRANSACTION_BY_TO_ADDRESS, LOG_FRAGMENT_ID, RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
+    u128_index_key, INDEX_TRANSACTION_BY_CREATE, INDEX_TRANSACTION_BY_FROM_ADDRESS,
+    INDEX_TRANSACTION_BY_GAS_PRICE, INDEX_TRANSACTION_BY_STATUS, INDEX_TRANSACTION_BY_TO_ADDRESS,
+    INDEX_TRANSACTION_BY_VALUE, LOG_FRAGMENT_ID, RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for evm::TransactionFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(from) = self.from {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_FROM_ADDRESS,
-                key: ScalarValue::B160(from.to_bytes()),
+                key: ScalarValue::B160(from.to_bytes()).into(),
             });
         }
 
         if let Some(to) = self.to {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_TO_ADDRESS,
-                key: ScalarValue::B160(to.to_bytes()),
+                key: ScalarValue::B160(to.to_bytes()).into(),
             });
         }
 
         if let Some(true) = self.create {
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_CREATE,
-                key: ScalarValue::Bool(true),
+                key: ScalarValue::Bool(true).into(),
+            });
+        }
+
+        if let Some(range) = &self.value {
+            let start = ScalarValue::B256(range.start.map(|v| v.to_bytes()).unwrap_or([0; 32]));
+            let end = ScalarValue::B256(range.end.map(|v| v.to_bytes()).unwrap_or([0xff; 32]));
+
+            if start > end {
+                return Err(FieldViolation::new(
+                    "value",
+                    format!(
+                        "invalid value range in transaction filter with id {}",
+                        self.id
+                    ),
+                ));
+            }
+
+            conditions.push(Condition {
+                index_id: INDEX_TRANSACTION_BY_VALUE,
+                key: ConditionKey::Range { start, end },
+            });
+        }
+
+        if let Some(range) = &self.gas_price {
+            let max = evm::U128 {
+                x0: u64::MAX,
+                x1: u64::MAX,
+            };
+            let start = u128_index_key(&range.start.unwrap_or_default());
+            let end = u128_index_key(&range.end.unwrap_or(max));
+
+            if start > end {
+                return Err(FieldViolation::new(
+                    "gas_price",
+                    format!(
+                        "invalid gas price range in transaction filter with id {}",
+                        self.id
+                    ),
+                ));
+            }
+
+            conditions.push(Condition {
+                index_id: INDEX_TRANSACTION_BY_GAS_PRICE,
+                key: ConditionKey::Range { start, end },
             });
         }
 
         let transaction_status = if let Some(transaction_status) = self.transaction_status {
             evm::TransactionStatusFilter::try_from(transaction_status).map_err(|_| {
-                tonic::Status::invalid_argument(format!(
-                    "invalid transaction status in transaction filter with id {}",
-                    self.id
-                ))
+                FieldViolation::new(
+                    "transaction_status",
+                    format!(
+                        "invalid transaction status in transaction filter with id {}",
+                        self.id
+                    ),
+                )
             })?
         } else {
             evm::TransactionStatusFilter::Succeeded
@@ -53,13 +101,13 @@ impl FragmentFilterExt for evm::TransactionFilter {
             evm::TransactionStatusFilter::Succeeded => {
                 conditions.push(Condition {
                     index_id: INDEX_TRANSACTION_BY_STATUS,
-                    key: ScalarValue::Int32(evm::TransactionStatus::Succeeded as i32),
+                    key: ScalarValue::Int32(evm::TransactionStatus::Succeeded as i32).into(),
                 });
             }
             evm::TransactionStatusFilter::Reverted => {
                 conditions.push(Condition {
                     index_id: INDEX_TRANSACTION_BY_STATUS,
-                    key: ScalarValue::Int32(evm::TransactionStatus::Reverted as i32),
+                    key: ScalarValue::Int32(evm::TransactionStatus::Reverted as i32).into(),
                 });
             }
         };
@@ -79,6 +127,7 @@ impl FragmentFilterExt for evm::TransactionFilter {
             fragment_id: TRANSACTION_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("evm.v2.Transaction", self.fields.as_ref())?,
         })
     }
 }
@@ -1,30 +1,62 @@
 use apibara_dna_common::{
     index::ScalarValue,
-    query::{Condition, Filter},
+    query::{Condition, ConditionKey, Filter},
 };
-use apibara_dna_protocol::evm;
+use apibara_dna_protocol::{error_details::FieldViolation, evm};
 
 use crate::fragment::{
     INDEX_WITHDRAWAL_BY_ADDRESS, INDEX_WITHDRAWAL_BY_VALIDATOR_INDEX, WITHDRAWAL_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for evm::WithdrawalFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(validator_index) = self.validator_index {
             conditions.push(Condition {
                 index_id: INDEX_WITHDRAWAL_BY_VALIDATOR_INDEX,
-                key: ScalarValue::Uint32(validator_index),
+                key: ScalarValue::Uint32(validator_index).into(),
+            });
+        }
+
+        if let Some(range) = &self.validator_index_range {
+            if range.start > range.end {
+                return Err(FieldViolation::new(
+                    "validator_index_range",
+                    format!(
+                        "invalid validator index range in withdrawal filter with id {}",
+                        self.id
+                    ),
+                ));
+            }
+
+            conditions.push(Condition {
+                index_id: INDEX_WITHDRAWAL_BY_VALIDATOR_INDEX,
+                key: ConditionKey::Range {
+                    start: ScalarValue::Uint32(range.start),
+                    end: ScalarValue::Uint32(range.end),
+                },
             });
         }
 
         if let Some(address) = self.address {
             conditions.push(Condition {
                 index_id: INDEX_WITHDRAWAL_BY_ADDRESS,
-                key: ScalarValue::B160(address.to_bytes()),
+                key: ScalarValue::B160(address.to_bytes()).into(),
+            });
+        }
+
+        if !self.addresses.is_empty() {
+            conditions.push(Condition {
+                index_id: INDEX_WITHDRAWAL_BY_ADDRESS,
+                key: ConditionKey::AnyOf(
+                    self.addresses
+                        .iter()
+                        .map(|address| ScalarValue::B160(address.to_bytes()))
+                        .collect(),
+                ),
             });
         }
 
@@ -33,6 +65,7 @@ impl FragmentFilterExt for evm::WithdrawalFilter {
             fragment_id: WITHDRAWAL_FRAGMENT_ID,
             conditions,
             joins: Vec::default(),
+            fields: compile_field_mask("evm.v2.Withdrawal", self.fields.as_ref())?,
         })
     }
 }
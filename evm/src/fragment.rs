@@ -1,5 +1,8 @@
 //! Fragment constants.
 
+use apibara_dna_common::{fragment::FragmentInfo, index::ScalarValue};
+use apibara_dna_protocol::evm;
+
 // Make sure the fragment IDs match the field tags in the protobuf Block message.
 
 pub const WITHDRAWAL_FRAGMENT_ID: u8 = 2;
@@ -21,6 +24,8 @@ pub const INDEX_TRANSACTION_BY_FROM_ADDRESS: u8 = 0;
 pub const INDEX_TRANSACTION_BY_TO_ADDRESS: u8 = 1;
 pub const INDEX_TRANSACTION_BY_CREATE: u8 = 2;
 pub const INDEX_TRANSACTION_BY_STATUS: u8 = 3;
+pub const INDEX_TRANSACTION_BY_VALUE: u8 = 4;
+pub const INDEX_TRANSACTION_BY_GAS_PRICE: u8 = 5;
 
 // No receipts index.
 
@@ -31,3 +36,35 @@ pub const INDEX_LOG_BY_TOPIC2: u8 = 3;
 pub const INDEX_LOG_BY_TOPIC3: u8 = 4;
 pub const INDEX_LOG_BY_TOPIC_LENGTH: u8 = 5;
 pub const INDEX_LOG_BY_TRANSACTION_STATUS: u8 = 6;
+pub const INDEX_LOG_BY_PROXY_IMPLEMENTATION: u8 = 7;
+
+/// Returns the fragments generated by the chain.
+pub fn fragment_info() -> Vec<FragmentInfo> {
+    vec![
+        FragmentInfo {
+            fragment_id: WITHDRAWAL_FRAGMENT_ID,
+            name: WITHDRAWAL_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: TRANSACTION_FRAGMENT_ID,
+            name: TRANSACTION_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: RECEIPT_FRAGMENT_ID,
+            name: RECEIPT_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: LOG_FRAGMENT_ID,
+            name: LOG_FRAGMENT_NAME.to_string(),
+        },
+    ]
+}
+
+/// Returns the index key of a 128 bits value.
+///
+/// Values are zero-extended to 256 bits so that keys sort like the numbers they encode.
+pub fn u128_index_key(value: &evm::U128) -> ScalarValue {
+    let mut bytes = [0; 32];
+    bytes[16..].copy_from_slice(&value.to_bytes());
+    ScalarValue::B256(bytes)
+}
@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use alloy_rpc_types::BlockId;
 use apibara_dna_common::{
     chain::{BlockInfo, PendingBlockInfo},
@@ -6,42 +8,142 @@ use apibara_dna_common::{
         JoinFragment, JoinGroupFragment,
     },
     index::{BitmapIndexBuilder, ScalarValue},
-    ingestion::{BlockIngestion, IngestionError},
+    ingestion::{BlockIngestion, HeadSubscription, IngestionError},
     join::{JoinToManyIndexBuilder, JoinToOneIndexBuilder},
     Cursor, Hash,
 };
 use apibara_dna_protocol::evm;
 use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
 use prost::Message;
 
 use crate::{
     fragment::{
-        INDEX_LOG_BY_ADDRESS, INDEX_LOG_BY_TOPIC0, INDEX_LOG_BY_TOPIC1, INDEX_LOG_BY_TOPIC2,
-        INDEX_LOG_BY_TOPIC3, INDEX_LOG_BY_TOPIC_LENGTH, INDEX_LOG_BY_TRANSACTION_STATUS,
-        INDEX_TRANSACTION_BY_CREATE, INDEX_TRANSACTION_BY_FROM_ADDRESS,
-        INDEX_TRANSACTION_BY_STATUS, INDEX_TRANSACTION_BY_TO_ADDRESS, INDEX_WITHDRAWAL_BY_ADDRESS,
-        INDEX_WITHDRAWAL_BY_VALIDATOR_INDEX, LOG_FRAGMENT_ID, LOG_FRAGMENT_NAME,
-        RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID,
+        u128_index_key, INDEX_LOG_BY_ADDRESS, INDEX_LOG_BY_PROXY_IMPLEMENTATION,
+        INDEX_LOG_BY_TOPIC0, INDEX_LOG_BY_TOPIC1, INDEX_LOG_BY_TOPIC2, INDEX_LOG_BY_TOPIC3,
+        INDEX_LOG_BY_TOPIC_LENGTH, INDEX_LOG_BY_TRANSACTION_STATUS, INDEX_TRANSACTION_BY_CREATE,
+        INDEX_TRANSACTION_BY_FROM_ADDRESS, INDEX_TRANSACTION_BY_GAS_PRICE,
+        INDEX_TRANSACTION_BY_STATUS, INDEX_TRANSACTION_BY_TO_ADDRESS, INDEX_TRANSACTION_BY_VALUE,
+        INDEX_WITHDRAWAL_BY_ADDRESS, INDEX_WITHDRAWAL_BY_VALIDATOR_INDEX, LOG_FRAGMENT_ID,
+        LOG_FRAGMENT_NAME, RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID,
         TRANSACTION_FRAGMENT_NAME, WITHDRAWAL_FRAGMENT_ID, WITHDRAWAL_FRAGMENT_NAME,
     },
     proto::{convert_block_header, ModelExt},
     provider::{models, BlockExt, JsonRpcProvider, JsonRpcProviderErrorExt},
+    proxy::{
+        implementation_from_slot, upgraded_implementation, ProxyImplementationMap,
+        IMPLEMENTATION_SLOT,
+    },
 };
 
+/// Maximum number of concurrent proxy implementation lookups for each block.
+const MAX_CONCURRENT_IMPLEMENTATION_LOOKUPS: usize = 16;
+
 #[derive(Clone, Debug)]
 pub struct EvmBlockIngestionOptions {
     pub ingest_pending: bool,
+    /// Index logs by the implementation of the EIP-1967 proxy emitting them.
+    pub index_proxy_implementation: bool,
 }
 
 #[derive(Clone)]
 pub struct EvmBlockIngestion {
     provider: JsonRpcProvider,
+    proxy_implementations: ProxyImplementationMap,
     options: EvmBlockIngestionOptions,
 }
 
 impl EvmBlockIngestion {
     pub fn new(provider: JsonRpcProvider, options: EvmBlockIngestionOptions) -> Self {
-        Self { provider, options }
+        Self {
+            provider,
+            proxy_implementations: ProxyImplementationMap::default(),
+            options,
+        }
+    }
+
+    /// Returns the implementation of the proxy emitting each log in the block, in the
+    /// same order as the block's logs.
+    ///
+    /// The list is empty if indexing logs by proxy implementation is disabled.
+    async fn collect_log_implementations(
+        &self,
+        block_number: u64,
+        parent_hash: models::B256,
+        receipts: &[models::TransactionReceipt],
+        is_pending: bool,
+    ) -> Result<Vec<Option<models::Address>>, IngestionError> {
+        if !self.options.index_proxy_implementation {
+            return Ok(Vec::new());
+        }
+
+        let logs = receipts
+            .iter()
+            .flat_map(|receipt| receipt.inner.logs().iter())
+            .collect::<Vec<_>>();
+
+        // Implementations at the start of the block, that is at the end of the parent block.
+        let mut implementations = HashMap::new();
+        let mut missing = HashSet::new();
+        if let Some(parent_number) = block_number.checked_sub(1) {
+            for log in logs.iter() {
+                let address = log.address();
+                if implementations.contains_key(&address) || missing.contains(&address) {
+                    continue;
+                }
+
+                match self.proxy_implementations.get(&address, parent_number) {
+                    Some(implementation) => {
+                        implementations.insert(address, implementation);
+                    }
+                    None => {
+                        missing.insert(address);
+                    }
+                }
+            }
+
+            let slots = futures::stream::iter(missing)
+                .map(|address| async move {
+                    self.provider
+                        .get_storage_at(address, IMPLEMENTATION_SLOT, BlockId::hash(parent_hash))
+                        .await
+                        .map(|slot| (address, slot))
+                })
+                .buffer_unordered(MAX_CONCURRENT_IMPLEMENTATION_LOOKUPS)
+                .try_collect::<Vec<_>>()
+                .await
+                .change_context(IngestionError::RpcRequest)
+                .attach_printable("failed to get proxy implementation")?;
+
+            for (address, slot) in slots {
+                let implementation = implementation_from_slot(slot);
+                self.proxy_implementations
+                    .insert(address, implementation, parent_number);
+                implementations.insert(address, implementation);
+            }
+        }
+
+        // Proxies can be upgraded in the middle of the block, so follow the upgrades
+        // in the order they happened.
+        let mut upgrades = HashMap::new();
+        let mut log_implementations = Vec::with_capacity(logs.len());
+        for log in logs.iter() {
+            let address = log.address();
+            if let Some(implementation) = upgraded_implementation(log) {
+                implementations.insert(address, Some(implementation));
+                upgrades.insert(address, implementation);
+            }
+
+            log_implementations.push(implementations.get(&address).copied().flatten());
+        }
+
+        // Pending blocks can still change, so they don't update the map.
+        if !is_pending {
+            self.proxy_implementations
+                .apply_upgrades(&upgrades, block_number);
+        }
+
+        Ok(log_implementations)
     }
 }
 
@@ -61,6 +163,31 @@ impl BlockIngestion for EvmBlockIngestion {
         Ok(block.cursor())
     }
 
+    async fn subscribe_heads(&self) -> Result<Option<HeadSubscription>, IngestionError> {
+        let Some(stream) = self
+            .provider
+            .subscribe_heads()
+            .await
+            .change_context(IngestionError::RpcRequest)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(stream.map(Ok).boxed()))
+    }
+
+    #[tracing::instrument("evm_get_chain_id", skip_all, err(Debug), level = "debug")]
+    async fn get_chain_id(&self) -> Result<Option<String>, IngestionError> {
+        let chain_id = self
+            .provider
+            .get_chain_id()
+            .await
+            .change_context(IngestionError::RpcRequest)
+            .attach_printable("failed to get chain id")?;
+
+        Ok(Some(chain_id.to_string()))
+    }
+
     #[tracing::instrument("evm_get_finalized_cursor", skip_all, err(Debug), level = "debug")]
     async fn get_finalized_cursor(&self) -> Result<Cursor, IngestionError> {
         let block = self
@@ -100,35 +227,25 @@ impl BlockIngestion for EvmBlockIngestion {
         &self,
         block_number: u64,
     ) -> Result<(BlockInfo, Block), IngestionError> {
-        let mut block_with_transactions = match self
-            .provider
-            .get_block_with_transactions(BlockId::number(block_number))
-            .await
-        {
-            Ok(block_with_transactions) => block_with_transactions,
-            Err(err) if err.is_not_found() => {
-                return Err(err).change_context(IngestionError::BlockNotFound)
-            }
-            Err(err) => {
-                return Err(err)
-                    .change_context(IngestionError::RpcRequest)
-                    .attach_printable("failed to get block with transactions")
-                    .attach_printable_lazy(|| format!("block number: {}", block_number))
-            }
-        };
-
-        let block_id = BlockId::hash(block_with_transactions.header.hash);
-
-        let block_receipts = self
-            .provider
-            .get_block_receipts(block_id)
-            .await
-            .change_context(IngestionError::RpcRequest)
-            .attach_printable("failed to get block receipts")
-            .attach_printable_lazy(|| format!("block number: {}", block_number))
-            .attach_printable_lazy(|| {
-                format!("block hash: {}", block_with_transactions.header.hash)
-            })?;
+        let (mut block_with_transactions, block_receipts) =
+            match self.provider.get_block_with_receipts(block_number).await {
+                Ok(block_with_receipts) => block_with_receipts,
+                Err(err) if err.is_not_found() => {
+                    return Err(err).change_context(IngestionError::BlockNotFound)
+                }
+                Err(err) if err.is_invalid_data() => {
+                    return Err(err)
+                        .change_context(IngestionError::InvalidBlock)
+                        .attach_printable("block receipts failed validation")
+                        .attach_printable_lazy(|| format!("block number: {}", block_number))
+                }
+                Err(err) => {
+                    return Err(err)
+                        .change_context(IngestionError::RpcRequest)
+                        .attach_printable("failed to get block with receipts")
+                        .attach_printable_lazy(|| format!("block number: {}", block_number))
+                }
+            };
 
         let block_transactions = std::mem::take(&mut block_with_transactions.transactions);
         let Some(block_transactions) = block_transactions.as_transactions() else {
@@ -141,6 +258,15 @@ impl BlockIngestion for EvmBlockIngestion {
 
         let block_info = block_with_transactions.block_info();
 
+        let log_implementations = self
+            .collect_log_implementations(
+                block_info.number,
+                block_with_transactions.header.parent_hash,
+                &block_receipts,
+                false,
+            )
+            .await?;
+
         let header_fragment = {
             let header = convert_block_header(block_with_transactions.header);
             HeaderFragment {
@@ -148,8 +274,12 @@ impl BlockIngestion for EvmBlockIngestion {
             }
         };
 
-        let (body, index, join) =
-            collect_block_body_and_index(block_transactions, &block_withdrawals, &block_receipts)?;
+        let (body, index, join) = collect_block_body_and_index(
+            block_transactions,
+            &block_withdrawals,
+            &block_receipts,
+            &log_implementations,
+        )?;
 
         let block = Block {
             header: header_fragment,
@@ -210,6 +340,15 @@ impl BlockIngestion for EvmBlockIngestion {
         let block_withdrawals =
             std::mem::take(&mut block_with_transactions.withdrawals).unwrap_or_default();
 
+        let log_implementations = self
+            .collect_log_implementations(
+                parent.number + 1,
+                block_with_transactions.header.parent_hash,
+                &block_receipts,
+                true,
+            )
+            .await?;
+
         let header_fragment = {
             let header = convert_block_header(block_with_transactions.header);
             HeaderFragment {
@@ -217,8 +356,12 @@ impl BlockIngestion for EvmBlockIngestion {
             }
         };
 
-        let (body, index, join) =
-            collect_block_body_and_index(block_transactions, &block_withdrawals, &block_receipts)?;
+        let (body, index, join) = collect_block_body_and_index(
+            block_transactions,
+            &block_withdrawals,
+            &block_receipts,
+            &log_implementations,
+        )?;
 
         let pending_block_info = PendingBlockInfo {
             number: parent.number + 1,
@@ -241,6 +384,7 @@ fn collect_block_body_and_index(
     transactions: &[models::Transaction],
     withdrawals: &[models::Withdrawal],
     receipts: &[models::TransactionReceipt],
+    log_implementations: &[Option<models::Address>],
 ) -> Result<(Vec<BodyFragment>, IndexGroupFragment, JoinGroupFragment), IngestionError> {
     let mut block_withdrawals = Vec::new();
     let mut block_transactions = Vec::new();
@@ -254,6 +398,8 @@ fn collect_block_body_and_index(
     let mut index_transaction_by_to_address = BitmapIndexBuilder::default();
     let mut index_transaction_by_create = BitmapIndexBuilder::default();
     let mut index_transaction_by_status = BitmapIndexBuilder::default();
+    let mut index_transaction_by_value = BitmapIndexBuilder::default();
+    let mut index_transaction_by_gas_price = BitmapIndexBuilder::default();
     let mut join_transaction_to_receipt = JoinToOneIndexBuilder::default();
     let mut join_transaction_to_logs = JoinToManyIndexBuilder::default();
 
@@ -264,6 +410,7 @@ fn collect_block_body_and_index(
     let mut index_log_by_topic3 = BitmapIndexBuilder::default();
     let mut index_log_by_topic_length = BitmapIndexBuilder::default();
     let mut index_log_by_transaction_status = BitmapIndexBuilder::default();
+    let mut index_log_by_proxy_implementation = BitmapIndexBuilder::default();
     let mut join_log_to_transaction = JoinToOneIndexBuilder::default();
     let mut join_log_to_receipt = JoinToOneIndexBuilder::default();
     let mut join_log_to_siblings = JoinToManyIndexBuilder::default();
@@ -294,6 +441,8 @@ fn collect_block_body_and_index(
         let transaction_index = transaction_index as u32;
         let transaction_hash = receipt.transaction_hash.to_proto();
 
+        // Receipts from before Byzantium have a state root instead of a status,
+        // `status` reports them as succeeded.
         let transaction_status = if receipt.status() {
             evm::TransactionStatus::Succeeded as i32
         } else {
@@ -349,6 +498,15 @@ fn collect_block_body_and_index(
         index_transaction_by_status
             .insert(ScalarValue::Int32(transaction_status), transaction_index);
 
+        if let Some(value) = transaction.value {
+            index_transaction_by_value
+                .insert(ScalarValue::B256(value.to_bytes()), transaction_index);
+        }
+
+        if let Some(gas_price) = transaction.max_fee_per_gas.or(transaction.gas_price) {
+            index_transaction_by_gas_price.insert(u128_index_key(&gas_price), transaction_index);
+        }
+
         block_transactions.push(transaction);
 
         let mut transaction_logs_id = Vec::new();
@@ -417,6 +575,13 @@ fn collect_block_body_and_index(
             index_log_by_transaction_status
                 .insert(ScalarValue::Int32(transaction_status), log_index);
 
+            if let Some(Some(implementation)) = log_implementations.get(log_index as usize) {
+                index_log_by_proxy_implementation.insert(
+                    ScalarValue::B160(implementation.to_proto().to_bytes()),
+                    log_index,
+                );
+            }
+
             block_logs.push(log);
         }
 
@@ -512,6 +677,22 @@ fn collect_block_body_and_index(
                 .into(),
         };
 
+        let index_transaction_by_value = Index {
+            index_id: INDEX_TRANSACTION_BY_VALUE,
+            index: index_transaction_by_value
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
+
+        let index_transaction_by_gas_price = Index {
+            index_id: INDEX_TRANSACTION_BY_GAS_PRICE,
+            index: index_transaction_by_gas_price
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
+
         IndexFragment {
             fragment_id: TRANSACTION_FRAGMENT_ID,
             range_start: 0,
@@ -521,6 +702,8 @@ fn collect_block_body_and_index(
                 index_transaction_by_to_address,
                 index_transaction_by_create,
                 index_transaction_by_status,
+                index_transaction_by_value,
+                index_transaction_by_gas_price,
             ],
         }
     };
@@ -630,6 +813,14 @@ fn collect_block_body_and_index(
                 .into(),
         };
 
+        let index_log_by_proxy_implementation = Index {
+            index_id: INDEX_LOG_BY_PROXY_IMPLEMENTATION,
+            index: index_log_by_proxy_implementation
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
+
         IndexFragment {
             fragment_id: LOG_FRAGMENT_ID,
             range_start: 0,
@@ -642,6 +833,7 @@ fn collect_block_body_and_index(
                 index_log_by_topic3,
                 index_log_by_topic_length,
                 index_log_by_transaction_status,
+                index_log_by_proxy_implementation,
             ],
         }
     };
@@ -5,19 +5,11 @@ pub mod fragment;
 pub mod ingestion;
 pub mod proto;
 pub mod provider;
+pub mod proxy;
 
-use apibara_dna_common::{fragment::FragmentInfo, ChainSupport};
+use apibara_dna_common::{fragment::FragmentInfo, ingestion::RpcRateLimiter, ChainSupport};
 
-use crate::{
-    filter::EvmFilterFactory,
-    fragment::{
-        LOG_FRAGMENT_ID, LOG_FRAGMENT_NAME, RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME,
-        TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME, WITHDRAWAL_FRAGMENT_ID,
-        WITHDRAWAL_FRAGMENT_NAME,
-    },
-    ingestion::EvmBlockIngestion,
-    provider::JsonRpcProvider,
-};
+use crate::{filter::EvmFilterFactory, ingestion::EvmBlockIngestion, provider::JsonRpcProvider};
 
 pub use ingestion::EvmBlockIngestionOptions;
 
@@ -37,31 +29,15 @@ impl ChainSupport for EvmChainSupport {
     type BlockFilterFactory = EvmFilterFactory;
 
     fn fragment_info(&self) -> Vec<FragmentInfo> {
-        vec![
-            FragmentInfo {
-                fragment_id: WITHDRAWAL_FRAGMENT_ID,
-                name: WITHDRAWAL_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: TRANSACTION_FRAGMENT_ID,
-                name: TRANSACTION_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: RECEIPT_FRAGMENT_ID,
-                name: RECEIPT_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: LOG_FRAGMENT_ID,
-                name: LOG_FRAGMENT_NAME.to_string(),
-            },
-        ]
+        fragment::fragment_info()
     }
 
     fn block_filter_factory(&self) -> Self::BlockFilterFactory {
         EvmFilterFactory
     }
 
-    fn block_ingestion(&self) -> Self::BlockIngestion {
-        EvmBlockIngestion::new(self.provider.clone(), self.options.clone())
+    fn block_ingestion(&self, rate_limiter: RpcRateLimiter) -> Self::BlockIngestion {
+        let provider = self.provider.clone().with_rate_limiter(rate_limiter);
+        EvmBlockIngestion::new(provider, self.options.clone())
     }
 }
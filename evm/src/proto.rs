@@ -1,3 +1,4 @@
+use alloy_consensus::{Eip658Value, TxReceipt};
 use apibara_dna_protocol::evm;
 
 use crate::provider::models;
@@ -119,6 +120,10 @@ impl ModelExt for models::TransactionReceipt {
             } else {
                 evm::TransactionStatus::Reverted as i32
             },
+            state_root: match self.inner.status_or_post_state() {
+                Eip658Value::PostState(root) => root.to_proto().into(),
+                Eip658Value::Eip658(_) => None,
+            },
         }
     }
 }
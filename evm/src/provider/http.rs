@@ -1,16 +1,27 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use alloy_primitives::BlockHash;
-use alloy_provider::{network::Ethereum, Provider, ProviderBuilder};
+use alloy_provider::{network::Ethereum, Provider, ProviderBuilder, WsConnect};
 use alloy_rpc_client::ClientBuilder;
 use alloy_transport::BoxTransport;
+use apibara_dna_common::{
+    ingestion::RpcRateLimiter,
+    rpc_pool::{Failover, RpcEndpoint, RpcPool, RpcPoolOptions},
+};
 use error_stack::{Report, Result, ResultExt};
+use futures::{stream::BoxStream, Stream, StreamExt};
 use reqwest::header::{HeaderMap, HeaderValue};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
 pub use alloy_rpc_types::BlockId;
 
-use super::models;
+use super::{models, validate::validate_block_receipts};
 
 #[derive(Debug)]
 pub enum JsonRpcProviderError {
@@ -18,6 +29,15 @@ pub enum JsonRpcProviderError {
     Timeout,
     NotFound,
     Configuration,
+    InvalidData,
+}
+
+/// New heads stream that owns the WebSocket provider it is subscribed on.
+///
+/// Dropping the provider closes the connection, so it must live as long as the stream.
+struct HeadStream<P> {
+    stream: BoxStream<'static, ()>,
+    _provider: P,
 }
 
 #[derive(Debug, Clone)]
@@ -26,104 +46,327 @@ pub struct JsonRpcProviderOptions {
     pub timeout: Duration,
     /// Request headers.
     pub headers: HeaderMap<HeaderValue>,
+    /// Failover and load balancing options.
+    pub pool: RpcPoolOptions,
+    /// WebSocket URL used to subscribe to new heads.
+    pub ws_url: Option<Url>,
+    /// Check that the block receipts hash to the block's receipts root.
+    pub validate_receipts_root: bool,
 }
 
+type BoxedProvider = Arc<dyn Provider<BoxTransport, Ethereum>>;
+
 #[derive(Clone)]
 pub struct JsonRpcProvider {
-    provider: Arc<dyn Provider<BoxTransport, Ethereum>>,
+    pool: RpcPool<BoxedProvider>,
     options: JsonRpcProviderOptions,
 }
 
 pub trait JsonRpcProviderErrorExt {
     fn is_not_found(&self) -> bool;
+    fn is_invalid_data(&self) -> bool;
 }
 
 impl JsonRpcProvider {
-    pub fn new(url: Url, options: JsonRpcProviderOptions) -> Result<Self, JsonRpcProviderError> {
+    pub fn new(
+        urls: Vec<RpcEndpoint<Url>>,
+        options: JsonRpcProviderOptions,
+    ) -> Result<Self, JsonRpcProviderError> {
         if !options.headers.is_empty() {
             return Err(JsonRpcProviderError::Configuration)
                 .attach_printable("custom headers are not supported");
         }
 
-        let client = ClientBuilder::default().http(url);
-        let provider = ProviderBuilder::default().on_client(client).boxed();
+        let endpoints = urls
+            .into_iter()
+            .map(|endpoint| {
+                let client = ClientBuilder::default().http(endpoint.client);
+                let provider: BoxedProvider =
+                    Arc::new(ProviderBuilder::default().on_client(client).boxed());
+                RpcEndpoint {
+                    name: endpoint.name,
+                    client: provider,
+                    weight: endpoint.weight,
+                }
+            })
+            .collect();
+
+        let pool = RpcPool::new(endpoints, options.pool.clone())
+            .change_context(JsonRpcProviderError::Configuration)?;
+
+        Ok(Self { pool, options })
+    }
+
+    /// Limit the requests sent to the RPC endpoints.
+    pub fn with_rate_limiter(self, rate_limiter: RpcRateLimiter) -> Self {
+        Self {
+            pool: self.pool.with_rate_limiter(rate_limiter),
+            ..self
+        }
+    }
+
+    /// Check the endpoints' health in the background, until cancelled.
+    pub async fn health_check_loop(self, ct: CancellationToken) {
+        let timeout = self.options.timeout;
+        self.pool
+            .health_check_loop(
+                |provider| async move {
+                    let request = provider.get_block_number();
+
+                    let Ok(response) = tokio::time::timeout(timeout, request).await else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block number");
+                    };
 
-        Ok(Self {
-            provider: Arc::new(provider),
-            options,
-        })
+                    response.change_context(JsonRpcProviderError::Request)
+                },
+                ct,
+            )
+            .await
+    }
+
+    /// Subscribe to new heads with `eth_subscribe("newHeads")`.
+    ///
+    /// Returns `None` if no WebSocket URL is configured.
+    pub async fn subscribe_heads(
+        &self,
+    ) -> Result<Option<BoxStream<'static, ()>>, JsonRpcProviderError> {
+        let Some(ws_url) = self.options.ws_url.clone() else {
+            return Ok(None);
+        };
+
+        let provider = ProviderBuilder::new()
+            .on_ws(WsConnect::new(ws_url))
+            .await
+            .change_context(JsonRpcProviderError::Request)
+            .attach_printable("failed to connect to websocket")?;
+
+        let subscription = provider
+            .subscribe_blocks()
+            .await
+            .change_context(JsonRpcProviderError::Request)
+            .attach_printable("failed to subscribe to new heads")?;
+
+        let stream = HeadStream {
+            stream: subscription.into_stream().map(|_| ()).boxed(),
+            _provider: provider,
+        };
+
+        Ok(Some(stream.boxed()))
+    }
+
+    pub async fn get_chain_id(&self) -> Result<u64, JsonRpcProviderError> {
+        self.pool
+            .call_with_failover(
+                "get_chain_id",
+                |provider| async move {
+                    let request = provider.get_chain_id();
+
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get chain id");
+                    };
+
+                    response.change_context(JsonRpcProviderError::Request)
+                },
+                failover,
+            )
+            .await
     }
 
     pub async fn get_block_header(
         &self,
         block_id: BlockId,
     ) -> Result<models::BlockWithTxHashes, JsonRpcProviderError> {
-        let request = match block_id {
-            BlockId::Number(number) => self
-                .provider
-                .client()
-                .request::<_, Option<models::BlockWithTxHashes>>(
-                    "eth_getBlockByNumber",
-                    (number, false),
-                )
-                .boxed(),
-            BlockId::Hash(hash) => {
-                let hash = BlockHash::from(hash);
-                self.provider
-                    .client()
-                    .request::<_, Option<models::BlockWithTxHashes>>(
-                        "eth_getBlockByHash",
-                        (hash, false),
-                    )
-                    .boxed()
-            }
-        };
+        self.pool
+            .call_with_failover(
+                "get_block_header",
+                |provider| async move {
+                    let request = match block_id {
+                        BlockId::Number(number) => provider
+                            .client()
+                            .request::<_, Option<models::BlockWithTxHashes>>(
+                                "eth_getBlockByNumber",
+                                (number, false),
+                            )
+                            .boxed(),
+                        BlockId::Hash(hash) => {
+                            let hash = BlockHash::from(hash);
+                            provider
+                                .client()
+                                .request::<_, Option<models::BlockWithTxHashes>>(
+                                    "eth_getBlockByHash",
+                                    (hash, false),
+                                )
+                                .boxed()
+                        }
+                    };
 
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(JsonRpcProviderError::Timeout)
-                .attach_printable("failed to get block header")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
-        };
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block header")
+                            .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                    };
 
-        response
-            .change_context(JsonRpcProviderError::Request)?
-            .ok_or(JsonRpcProviderError::NotFound.into())
+                    response
+                        .change_context(JsonRpcProviderError::Request)?
+                        .ok_or(JsonRpcProviderError::NotFound.into())
+                },
+                failover,
+            )
+            .await
     }
 
     pub async fn get_block_with_transactions(
         &self,
         block_id: BlockId,
     ) -> Result<models::Block, JsonRpcProviderError> {
-        let request = self
-            .provider
-            .get_block(block_id, alloy_rpc_types::BlockTransactionsKind::Full);
-
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(JsonRpcProviderError::Timeout)
-                .attach_printable("failed to get block with transactions")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
-        };
+        self.pool
+            .call_with_failover(
+                "get_block_with_transactions",
+                |provider| async move {
+                    let request =
+                        provider.get_block(block_id, alloy_rpc_types::BlockTransactionsKind::Full);
 
-        response
-            .change_context(JsonRpcProviderError::Request)?
-            .ok_or(JsonRpcProviderError::NotFound.into())
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block with transactions")
+                            .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                    };
+
+                    response
+                        .change_context(JsonRpcProviderError::Request)?
+                        .ok_or(JsonRpcProviderError::NotFound.into())
+                },
+                failover,
+            )
+            .await
+    }
+
+    /// Get a block with its transactions and receipts from the same endpoint.
+    ///
+    /// The receipts are checked against the block. If the check fails, the request
+    /// is sent to the next endpoint.
+    pub async fn get_block_with_receipts(
+        &self,
+        block_number: u64,
+    ) -> Result<(models::Block, Vec<models::TransactionReceipt>), JsonRpcProviderError> {
+        self.pool
+            .call_with_failover(
+                "get_block_with_receipts",
+                |provider| async move {
+                    let request = provider.get_block(
+                        BlockId::number(block_number),
+                        alloy_rpc_types::BlockTransactionsKind::Full,
+                    );
+
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block with transactions")
+                            .attach_printable_lazy(|| format!("block number: {block_number}"));
+                    };
+
+                    let block = response
+                        .change_context(JsonRpcProviderError::Request)?
+                        .ok_or(JsonRpcProviderError::NotFound)?;
+
+                    let request = provider.get_block_receipts(BlockId::hash(block.header.hash));
+
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block receipts")
+                            .attach_printable_lazy(|| format!("block number: {block_number}"));
+                    };
+
+                    let receipts = response
+                        .change_context(JsonRpcProviderError::Request)?
+                        .ok_or(JsonRpcProviderError::NotFound)?;
+
+                    validate_block_receipts(&block, &receipts, self.options.validate_receipts_root)
+                        .attach_printable_lazy(|| format!("block number: {block_number}"))?;
+
+                    Ok((block, receipts))
+                },
+                failover,
+            )
+            .await
+    }
+
+    /// Returns the value of the contract's storage slot at the end of the block.
+    pub async fn get_storage_at(
+        &self,
+        address: models::Address,
+        slot: models::B256,
+        block_id: BlockId,
+    ) -> Result<models::U256, JsonRpcProviderError> {
+        self.pool
+            .call_with_failover(
+                "get_storage_at",
+                |provider| async move {
+                    let request = provider
+                        .get_storage_at(address, models::U256::from_be_bytes(slot.0))
+                        .block_id(block_id);
+
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get storage at")
+                            .attach_printable_lazy(|| format!("address: {address}"))
+                            .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                    };
+
+                    response
+                        .change_context(JsonRpcProviderError::Request)
+                        .attach_printable("failed to get storage at")
+                        .attach_printable_lazy(|| format!("address: {address}"))
+                        .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                },
+                failover,
+            )
+            .await
     }
 
     pub async fn get_block_receipts(
         &self,
         block_id: BlockId,
     ) -> Result<Vec<models::TransactionReceipt>, JsonRpcProviderError> {
-        let request = self.provider.get_block_receipts(block_id);
+        self.pool
+            .call_with_failover(
+                "get_block_receipts",
+                |provider| async move {
+                    let request = provider.get_block_receipts(block_id);
 
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(JsonRpcProviderError::Timeout)
-                .attach_printable("failed to get block with receipts")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
-        };
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(JsonRpcProviderError::Timeout)
+                            .attach_printable("failed to get block with receipts")
+                            .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                    };
+
+                    response
+                        .change_context(JsonRpcProviderError::Request)?
+                        .ok_or(JsonRpcProviderError::NotFound.into())
+                },
+                failover,
+            )
+            .await
+    }
+}
 
-        response
-            .change_context(JsonRpcProviderError::Request)?
-            .ok_or(JsonRpcProviderError::NotFound.into())
+/// Decide what to do after a request fails.
+///
+/// Nodes that don't have the block may be lagging behind, so the other nodes are
+/// tried before reporting the block as missing.
+fn failover(err: &Report<JsonRpcProviderError>) -> Failover {
+    if err.is_not_found() {
+        Failover::TryNext
+    } else {
+        Failover::MarkUnhealthy
     }
 }
 
@@ -136,6 +379,7 @@ impl std::fmt::Display for JsonRpcProviderError {
             JsonRpcProviderError::Timeout => write!(f, "request timed out"),
             JsonRpcProviderError::NotFound => write!(f, "not found"),
             JsonRpcProviderError::Configuration => write!(f, "configuration error"),
+            JsonRpcProviderError::InvalidData => write!(f, "invalid data"),
         }
     }
 }
@@ -144,4 +388,16 @@ impl JsonRpcProviderErrorExt for Report<JsonRpcProviderError> {
     fn is_not_found(&self) -> bool {
         matches!(self.current_context(), JsonRpcProviderError::NotFound)
     }
+
+    fn is_invalid_data(&self) -> bool {
+        matches!(self.current_context(), JsonRpcProviderError::InvalidData)
+    }
+}
+
+impl<P: Unpin> Stream for HeadStream<P> {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
 }
@@ -1,5 +1,6 @@
 mod http;
 pub mod models;
+mod validate;
 
 pub use self::http::{
     BlockId, JsonRpcProvider, JsonRpcProviderError, JsonRpcProviderErrorExt, JsonRpcProviderOptions,
@@ -0,0 +1,202 @@
+//! Consistency checks on the data returned by the JSON-RPC provider.
+use alloy_consensus::{Receipt, ReceiptEnvelope, ReceiptWithBloom, TxReceipt, TxType};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_trie::{HashBuilder, Nibbles, EMPTY_ROOT_HASH};
+use error_stack::{Result, ResultExt};
+
+use super::{models, JsonRpcProviderError};
+
+/// Check that the receipts belong to the block.
+///
+/// If `check_receipts_root` is set, also check that the receipts hash to the block's
+/// receipts root. This only works on chains that use Ethereum's receipt types.
+pub fn validate_block_receipts(
+    block: &models::Block,
+    receipts: &[models::TransactionReceipt],
+    check_receipts_root: bool,
+) -> Result<(), JsonRpcProviderError> {
+    let Some(transactions) = block.transactions.as_transactions() else {
+        return Err(JsonRpcProviderError::InvalidData)
+            .attach_printable("unexpected transactions as hashes");
+    };
+
+    if transactions.len() != receipts.len() {
+        return Err(JsonRpcProviderError::InvalidData)
+            .attach_printable("block transactions and receipts do not match")
+            .attach_printable_lazy(|| format!("transactions len: {}", transactions.len()))
+            .attach_printable_lazy(|| format!("receipts len: {}", receipts.len()));
+    }
+
+    for (transaction, receipt) in transactions.iter().zip(receipts.iter()) {
+        if receipt.block_hash != Some(block.header.hash) {
+            return Err(JsonRpcProviderError::InvalidData)
+                .attach_printable("receipt belongs to a different block")
+                .attach_printable_lazy(|| format!("transaction hash: {}", transaction.hash))
+                .attach_printable_lazy(|| format!("receipt block hash: {:?}", receipt.block_hash));
+        }
+
+        if receipt.transaction_hash != transaction.hash {
+            return Err(JsonRpcProviderError::InvalidData)
+                .attach_printable("receipt belongs to a different transaction")
+                .attach_printable_lazy(|| format!("transaction hash: {}", transaction.hash))
+                .attach_printable_lazy(|| {
+                    format!("receipt transaction hash: {}", receipt.transaction_hash)
+                });
+        }
+    }
+
+    if !check_receipts_root {
+        return Ok(());
+    }
+
+    let envelopes = receipts
+        .iter()
+        .map(to_consensus_receipt)
+        .collect::<Vec<_>>();
+    let receipts_root =
+        ordered_trie_root_with_encoder(&envelopes, |envelope, buf| envelope.encode_2718(buf));
+
+    if receipts_root != block.header.receipts_root {
+        return Err(JsonRpcProviderError::InvalidData)
+            .attach_printable("receipts root mismatch")
+            .attach_printable_lazy(|| format!("expected: {}", block.header.receipts_root))
+            .attach_printable_lazy(|| format!("actual: {}", receipts_root));
+    }
+
+    Ok(())
+}
+
+fn to_consensus_receipt(receipt: &models::TransactionReceipt) -> ReceiptEnvelope {
+    let inner = &receipt.inner;
+
+    let receipt = ReceiptWithBloom {
+        receipt: Receipt {
+            status: *inner.status_or_post_state(),
+            cumulative_gas_used: inner.cumulative_gas_used(),
+            logs: inner.logs().iter().map(|log| log.inner.clone()).collect(),
+        },
+        logs_bloom: *inner.logs_bloom(),
+    };
+
+    match inner.tx_type() {
+        TxType::Legacy => ReceiptEnvelope::Legacy(receipt),
+        TxType::Eip2930 => ReceiptEnvelope::Eip2930(receipt),
+        TxType::Eip1559 => ReceiptEnvelope::Eip1559(receipt),
+        TxType::Eip4844 => ReceiptEnvelope::Eip4844(receipt),
+        TxType::Eip7702 => ReceiptEnvelope::Eip7702(receipt),
+    }
+}
+
+/// Returns the root of the trie of the items, keyed by their RLP-encoded index.
+///
+/// This is how the transactions and receipts roots are computed.
+fn ordered_trie_root_with_encoder<T, F>(items: &[T], mut encode: F) -> models::B256
+where
+    F: FnMut(&T, &mut Vec<u8>),
+{
+    if items.is_empty() {
+        return EMPTY_ROOT_HASH;
+    }
+
+    // Leaves must be added in key order. Index 0 is encoded as 0x80, so it sorts
+    // after the indexes encoded as a single byte (1 to 127).
+    let mut indexes = (0..items.len()).collect::<Vec<_>>();
+    indexes.sort_by_cached_key(|index| rlp_encode_index(*index));
+
+    let mut hash_builder = HashBuilder::default();
+    let mut value = Vec::new();
+    for index in indexes {
+        value.clear();
+        encode(&items[index], &mut value);
+        hash_builder.add_leaf(Nibbles::unpack(rlp_encode_index(index)), &value);
+    }
+
+    hash_builder.root()
+}
+
+fn rlp_encode_index(index: usize) -> Vec<u8> {
+    match index {
+        0 => vec![0x80],
+        1..=0x7f => vec![index as u8],
+        _ => {
+            let bytes = index.to_be_bytes();
+            let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+            let mut encoded = vec![0x80 + (bytes.len() - leading_zeros) as u8];
+            encoded.extend_from_slice(&bytes[leading_zeros..]);
+            encoded
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_consensus::{Receipt, ReceiptEnvelope, ReceiptWithBloom};
+    use alloy_eips::eip2718::Encodable2718;
+    use alloy_primitives::{b256, logs_bloom, Address, Bytes, Log, B256};
+    use alloy_trie::EMPTY_ROOT_HASH;
+
+    use super::ordered_trie_root_with_encoder;
+
+    fn receipts_root(receipts: &[ReceiptEnvelope]) -> B256 {
+        ordered_trie_root_with_encoder(receipts, |receipt, buf| receipt.encode_2718(buf))
+    }
+
+    /// Receipts of a block with 130 transactions, so that the indexes take one and two bytes.
+    ///
+    /// The expected roots in the tests were computed with an independent trie implementation.
+    fn block_receipts() -> Vec<ReceiptEnvelope> {
+        (0..130u8)
+            .map(|i| {
+                let logs = if i % 5 == 0 {
+                    vec![Log::new_unchecked(
+                        Address::repeat_byte(i),
+                        vec![B256::repeat_byte(0xaa), B256::with_last_byte(i)],
+                        Bytes::from(vec![i; 3]),
+                    )]
+                } else {
+                    Vec::new()
+                };
+
+                let receipt = ReceiptWithBloom {
+                    logs_bloom: logs_bloom(logs.iter()),
+                    receipt: Receipt {
+                        status: (i % 7 != 0).into(),
+                        cumulative_gas_used: 21_000 * (i as u128 + 1),
+                        logs,
+                    },
+                };
+
+                match i % 3 {
+                    0 => ReceiptEnvelope::Legacy(receipt),
+                    1 => ReceiptEnvelope::Eip2930(receipt),
+                    _ => ReceiptEnvelope::Eip1559(receipt),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_receipts_root_of_empty_block() {
+        assert_eq!(receipts_root(&[]), EMPTY_ROOT_HASH);
+    }
+
+    #[test]
+    fn test_receipts_root_of_single_receipt() {
+        let receipts = block_receipts();
+        assert_eq!(
+            receipts_root(&receipts[..1]),
+            b256!("bcf7014baa44bf48e85fdbabdedd9f69a0c86fa4a86943a837e5b195493977ff")
+        );
+    }
+
+    #[test]
+    fn test_receipts_root_of_block() {
+        let mut receipts = block_receipts();
+        let expected = b256!("3995e71a2f321ed5e76c550e583d93d12a38bf940fd50bfcdeefcee4a884c677");
+        assert_eq!(receipts_root(&receipts), expected);
+
+        // Swapping two receipts changes the root.
+        receipts.swap(0, 128);
+        assert_ne!(receipts_root(&receipts), expected);
+    }
+}
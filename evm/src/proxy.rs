@@ -0,0 +1,181 @@
+//! Track the implementation of EIP-1967 proxies, to index logs by the proxy's implementation.
+use std::collections::HashMap;
+
+use alloy_primitives::b256;
+use apibara_dna_common::ingestion::BlockStateCache;
+
+use crate::provider::models;
+
+/// Storage slot of the implementation address, `keccak256("eip1967.proxy.implementation") - 1`.
+pub const IMPLEMENTATION_SLOT: models::B256 =
+    b256!("360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc");
+
+/// Topic of the `Upgraded(address indexed implementation)` event emitted by proxies.
+pub const UPGRADED_TOPIC: models::B256 =
+    b256!("bc7cd75a20ee27fd9adebab32041f755214dbc6bffa90cc0225b39da2e5c2d3b");
+
+/// Maximum number of contracts tracked, older entries are looked up again when needed.
+const MAX_CONTRACT_COUNT: usize = 1_000_000;
+
+/// Maps contract addresses to the implementation of the proxy at that address.
+///
+/// Contracts that are not proxies are tracked too, so that they're looked up only once.
+/// The map is updated with the `Upgraded` events emitted in each block, other contracts
+/// are looked up from the node and added to the map.
+///
+/// Blocks are ingested concurrently, so the implementation of a proxy is only used for
+/// another block once the upgrades of all blocks in between are known.
+#[derive(Clone)]
+pub struct ProxyImplementationMap {
+    implementations: BlockStateCache<models::Address, Option<models::Address>>,
+}
+
+impl ProxyImplementationMap {
+    /// Returns the implementation of the contract at the end of the given block, if known.
+    ///
+    /// The implementation is `None` if the contract is not a proxy.
+    pub fn get(
+        &self,
+        address: &models::Address,
+        block_number: u64,
+    ) -> Option<Option<models::Address>> {
+        self.implementations.get(address, block_number)
+    }
+
+    /// Store the implementation of the contract at the end of the given block, as returned
+    /// by the node.
+    pub fn insert(
+        &self,
+        address: models::Address,
+        implementation: Option<models::Address>,
+        block_number: u64,
+    ) {
+        self.implementations
+            .insert(address, implementation, block_number);
+    }
+
+    /// Update the map with the implementation of the proxies upgraded in the block.
+    ///
+    /// Must be called for every block ingested, including blocks without upgrades.
+    pub fn apply_upgrades(
+        &self,
+        upgrades: &HashMap<models::Address, models::Address>,
+        block_number: u64,
+    ) {
+        self.implementations.apply_block(
+            block_number,
+            upgrades
+                .iter()
+                .map(|(proxy, implementation)| (*proxy, Some(*implementation))),
+        );
+    }
+}
+
+impl Default for ProxyImplementationMap {
+    fn default() -> Self {
+        Self {
+            implementations: BlockStateCache::new(MAX_CONTRACT_COUNT),
+        }
+    }
+}
+
+/// Returns the new implementation if the log is an `Upgraded` event.
+pub fn upgraded_implementation(log: &models::Log) -> Option<models::Address> {
+    match log.topics() {
+        [topic, implementation] if *topic == UPGRADED_TOPIC => {
+            Some(models::Address::from_word(*implementation))
+        }
+        _ => None,
+    }
+}
+
+/// Converts the value of the implementation slot to an address.
+pub fn implementation_from_slot(value: models::U256) -> Option<models::Address> {
+    if value.is_zero() {
+        return None;
+    }
+
+    Some(models::Address::from_word(models::B256::from(
+        value.to_be_bytes::<32>(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::{address, LogData};
+
+    use crate::provider::models;
+
+    use super::{
+        implementation_from_slot, upgraded_implementation, ProxyImplementationMap, UPGRADED_TOPIC,
+    };
+
+    #[test]
+    fn test_proxy_implementation_map() {
+        let map = ProxyImplementationMap::default();
+        let proxy = address!("0000000000000000000000000000000000000001");
+        let first = address!("00000000000000000000000000000000000000aa");
+        let second = address!("00000000000000000000000000000000000000bb");
+
+        map.insert(proxy, Some(first), 100);
+        assert_eq!(map.get(&proxy, 100), Some(Some(first)));
+        assert_eq!(map.get(&proxy, 101), None);
+
+        // The proxy is upgraded in a block ingested out of order.
+        map.apply_upgrades(&HashMap::default(), 101);
+        map.apply_upgrades(&HashMap::default(), 103);
+        assert_eq!(map.get(&proxy, 101), Some(Some(first)));
+        assert_eq!(map.get(&proxy, 103), None);
+
+        map.apply_upgrades(&HashMap::from([(proxy, second)]), 102);
+        assert_eq!(map.get(&proxy, 101), None);
+        assert_eq!(map.get(&proxy, 102), Some(Some(second)));
+        assert_eq!(map.get(&proxy, 103), Some(Some(second)));
+
+        // Implementations looked up at an earlier block don't replace later upgrades.
+        map.insert(proxy, Some(first), 100);
+        assert_eq!(map.get(&proxy, 103), Some(Some(second)));
+
+        // Contracts that are not proxies are tracked too.
+        let contract = address!("0000000000000000000000000000000000000002");
+        map.insert(contract, None, 103);
+        assert_eq!(map.get(&contract, 101), Some(None));
+    }
+
+    fn log_with_topics(topics: Vec<models::B256>) -> models::Log {
+        models::Log {
+            inner: alloy_primitives::Log {
+                address: address!("0000000000000000000000000000000000000001"),
+                data: LogData::new_unchecked(topics, Default::default()),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_upgraded_implementation() {
+        let implementation = address!("00000000000000000000000000000000000000aa");
+
+        let upgraded = log_with_topics(vec![UPGRADED_TOPIC, implementation.into_word()]);
+        assert_eq!(upgraded_implementation(&upgraded), Some(implementation));
+
+        let other = log_with_topics(vec![models::B256::ZERO, implementation.into_word()]);
+        assert_eq!(upgraded_implementation(&other), None);
+
+        let missing_topic = log_with_topics(vec![UPGRADED_TOPIC]);
+        assert_eq!(upgraded_implementation(&missing_topic), None);
+    }
+
+    #[test]
+    fn test_implementation_from_slot() {
+        let implementation = address!("00000000000000000000000000000000000000aa");
+
+        assert_eq!(implementation_from_slot(models::U256::ZERO), None);
+        assert_eq!(
+            implementation_from_slot(models::U256::from_be_bytes(implementation.into_word().0)),
+            Some(implementation)
+        );
+    }
+}
@@ -4,6 +4,7 @@ mod dna_fmt;
 mod request;
 
 use std::borrow::Cow;
+use std::sync::OnceLock;
 use std::time::Duration;
 
 use error_stack::{Result, ResultExt};
@@ -21,7 +22,7 @@ pub use opentelemetry::trace::{SpanContext, TraceContextExt};
 pub use opentelemetry::{Context, Key, KeyValue};
 use tracing_opentelemetry::MetricsLayer;
 pub use tracing_opentelemetry::OpenTelemetrySpanExt;
-use tracing_subscriber::{prelude::*, registry::LookupSpan, EnvFilter, Layer};
+use tracing_subscriber::{prelude::*, registry::LookupSpan, reload, EnvFilter, Layer};
 
 pub use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter, UpDownCounter};
 
@@ -31,6 +32,12 @@ const OTEL_SDK_DISABLED: &str = "OTEL_SDK_DISABLED";
 
 pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
 
+type ReloadLogFilter =
+    Box<dyn Fn(EnvFilter) -> std::result::Result<(), reload::Error> + Send + Sync>;
+
+/// Replaces the filter of the stdout logs, set by `init_opentelemetry`.
+static RELOAD_LOG_FILTER: OnceLock<ReloadLogFilter> = OnceLock::new();
+
 #[derive(Debug)]
 pub struct OpenTelemetryInitError;
 impl error_stack::Context for OpenTelemetryInitError {}
@@ -41,6 +48,16 @@ impl std::fmt::Display for OpenTelemetryInitError {
     }
 }
 
+#[derive(Debug)]
+pub struct LogFilterError;
+impl error_stack::Context for LogFilterError {}
+
+impl std::fmt::Display for LogFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("failed to change log filter")
+    }
+}
+
 pub fn meter(name: &'static str) -> Meter {
     global::meter(name)
 }
@@ -152,14 +169,34 @@ where
     Ok(otel_layer)
 }
 
+/// Change the filter of the logs printed to stdout.
+///
+/// The directives use the same syntax as `RUST_LOG`.
+pub fn set_log_filter(directives: &str) -> Result<(), LogFilterError> {
+    let filter = EnvFilter::try_new(directives)
+        .change_context(LogFilterError)
+        .attach_printable("invalid log filter")
+        .attach_printable_lazy(|| format!("filter: {directives}"))?;
+
+    let reload = RELOAD_LOG_FILTER
+        .get()
+        .ok_or(LogFilterError)
+        .attach_printable("logging is not initialized")?;
+
+    reload(filter).change_context(LogFilterError)
+}
+
 fn stdout<S>() -> BoxedLayer<S>
 where
-    S: Subscriber,
+    S: Subscriber + 'static,
     for<'a> S: LookupSpan<'a>,
 {
     let log_env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("INFO"));
 
+    let (log_env_filter, handle) = reload::Layer::new(log_env_filter);
+    let _ = RELOAD_LOG_FILTER.set(Box::new(move |filter| handle.reload(filter)));
+
     let json_fmt = std::env::var("RUST_LOG_FORMAT")
         .map(|val| val == "json")
         .unwrap_or(false);
@@ -3,6 +3,7 @@ use std::{env, io::Result, path::PathBuf, println};
 static DNA_STREAM_DESCRIPTOR_FILE: &str = "dna_stream_v2_descriptor.bin";
 static EVM_DESCRIPTOR_FILE: &str = "evm_descriptor.bin";
 static STARKNET_DESCRIPTOR_FILE: &str = "starknet_descriptor.bin";
+static BEACONCHAIN_DESCRIPTOR_FILE: &str = "beaconchain_descriptor.bin";
 
 fn main() -> Result<()> {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
@@ -14,7 +15,10 @@ fn main() -> Result<()> {
         .skip_debug("StreamDataRequest")
         .bytes([".dna.v2.stream.Data.data"])
         .file_descriptor_set_path(out_dir.join(DNA_STREAM_DESCRIPTOR_FILE))
-        .compile_protos(&["proto/dna/v2/stream.proto"], &["proto/dna/"])?;
+        .compile_protos(
+            &["proto/dna/v2/stream.proto", "proto/dna/v2/admin.proto"],
+            &["proto/dna/"],
+        )?;
 
     /*
      * EVM
@@ -49,7 +53,7 @@ fn main() -> Result<()> {
     tonic_build::configure()
         .build_client(true)
         .build_server(true)
-        .file_descriptor_set_path(out_dir.join(STARKNET_DESCRIPTOR_FILE))
+        .file_descriptor_set_path(out_dir.join(BEACONCHAIN_DESCRIPTOR_FILE))
         .compile_protos(
             &[
                 "proto/beaconchain/v2/data.proto",
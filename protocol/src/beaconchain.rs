@@ -6,6 +6,9 @@ use crate::helpers::{
 
 tonic::include_proto!("beaconchain.v2");
 
+pub const BEACONCHAIN_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("beaconchain_descriptor");
+
 impl_scalar_traits!(Address);
 impl_from_to_bytes!(Address, 20);
 impl_scalar_helpers!(Address, 20);
@@ -4,9 +4,12 @@ use pin_project::pin_project;
 use tokio_stream::{Stream, StreamExt, Timeout};
 use tonic::{service::interceptor::InterceptedService, transport::Channel, IntoRequest, Streaming};
 
-use crate::dna::stream::{
-    dna_stream_client::DnaStreamClient, stream_data_response, StatusRequest, StatusResponse,
-    StreamDataRequest, StreamDataResponse,
+use crate::{
+    dna::stream::{
+        dna_stream_client::DnaStreamClient, stream_data_response, StatusRequest, StatusResponse,
+        StreamDataRequest, StreamDataResponse,
+    },
+    error_details::BadRequest,
 };
 
 use super::MetadataInterceptor;
@@ -89,6 +92,16 @@ impl Stream for DataStream {
     }
 }
 
+impl DataStreamError {
+    /// Returns the fields of the request rejected by the server, if any.
+    pub fn bad_request(&self) -> Option<BadRequest> {
+        match self {
+            DataStreamError::Timeout => None,
+            DataStreamError::Tonic(status) => BadRequest::from_status(status),
+        }
+    }
+}
+
 impl error_stack::Context for DataStreamError {}
 
 impl fmt::Display for DataStreamError {
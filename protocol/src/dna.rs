@@ -15,6 +15,29 @@ pub mod stream {
         DNA_STREAM_DESCRIPTOR_SET
     }
 
+    impl Data {
+        /// Returns true if the data matches its checksum.
+        ///
+        /// Data without a checksum is always valid.
+        pub fn verify_checksum(&self) -> bool {
+            match self.checksum {
+                Some(checksum) => checksum == data_checksum(&self.data),
+                None => true,
+            }
+        }
+    }
+
+    /// Returns the checksum of the block data, as sent in [Data::checksum].
+    pub fn data_checksum<T: AsRef<[u8]>>(data: &[T]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for item in data {
+            let item = item.as_ref();
+            hasher.update(&(item.len() as u64).to_le_bytes());
+            hasher.update(item);
+        }
+        hasher.finalize()
+    }
+
     impl DataFinality {
         pub fn is_pending(&self) -> bool {
             matches!(self, DataFinality::Pending)
@@ -186,12 +209,17 @@ pub mod stream {
     impl Debug for StreamDataRequest {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             let cursor = PrettyCursor(&self.starting_cursor);
+            let ending_cursor = PrettyCursor(&self.ending_cursor);
             let filter = self.filter.iter().map(PrettyFilter).collect::<Vec<_>>();
             f.debug_struct("StreamDataRequest")
                 .field("starting_cursor", &cursor)
                 .field("finality", &self.finality)
                 .field("filter", &filter)
                 .field("heartbeat_interval", &self.heartbeat_interval)
+                .field("start_at_head", &self.start_at_head)
+                .field("ending_cursor", &ending_cursor)
+                .field("session_token", &self.session_token)
+                .field("create_session", &self.create_session)
                 .finish()
         }
     }
@@ -212,9 +240,15 @@ pub mod stream {
     }
 }
 
+pub mod admin {
+    tonic::include_proto!("dna.v2.admin");
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::dna::stream::{Cursor, DataFinality};
+    use prost::bytes::Bytes;
+
+    use crate::dna::stream::{data_checksum, Cursor, Data, DataFinality};
 
     #[test]
     fn test_cursor_serialization() {
@@ -258,4 +292,24 @@ mod tests {
         let back: DataFinality = serde_json::from_str(r#""FINALIZED""#).unwrap();
         assert_eq!(back, DataFinality::Finalized);
     }
+
+    #[test]
+    fn test_data_checksum() {
+        let data = vec![Bytes::from_static(b"ab"), Bytes::from_static(b"c")];
+        let other = vec![Bytes::from_static(b"a"), Bytes::from_static(b"bc")];
+        assert_ne!(data_checksum(&data), data_checksum(&other));
+
+        let mut message = Data {
+            checksum: Some(data_checksum(&data)),
+            data,
+            ..Default::default()
+        };
+        assert!(message.verify_checksum());
+
+        message.data[1] = Bytes::from_static(b"d");
+        assert!(!message.verify_checksum());
+
+        message.checksum = None;
+        assert!(message.verify_checksum());
+    }
 }
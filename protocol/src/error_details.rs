@@ -0,0 +1,174 @@
+//! Structured error details sent with `InvalidArgument` errors.
+//!
+//! The messages follow `google.rpc.BadRequest`, so that clients in any language
+//! can decode them with the standard gRPC error details libraries.
+use std::fmt;
+
+use prost::{bytes::Bytes, Message};
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// Maximum number of violations sent to the client.
+///
+/// The status message and details are sent in the response trailers, which
+/// clients and proxies limit in size.
+const MAX_FIELD_VIOLATIONS: usize = 16;
+
+/// A field of the request that is not valid.
+#[derive(Clone, PartialEq, Message)]
+pub struct FieldViolation {
+    /// Path to the field, for example `filter[0].logs[1].address_set`.
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// Describes all the fields of the request that are not valid.
+#[derive(Clone, PartialEq, Message)]
+pub struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub field_violations: Vec<FieldViolation>,
+}
+
+/// The `google.rpc.Status` message, encoded in the `grpc-status-details-bin` trailer.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+impl FieldViolation {
+    pub fn new(field: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            description: description.into(),
+        }
+    }
+
+    /// Prepend the path of the parent message to the field's path.
+    pub fn with_parent(mut self, parent: impl fmt::Display) -> Self {
+        self.field = if self.field.is_empty() {
+            parent.to_string()
+        } else {
+            format!("{parent}.{}", self.field)
+        };
+        self
+    }
+}
+
+impl BadRequest {
+    pub fn new(field_violations: Vec<FieldViolation>) -> Self {
+        Self { field_violations }
+    }
+
+    /// Returns an `InvalidArgument` status with the violations in its details.
+    ///
+    /// The status message lists the violations, for clients that don't decode the details.
+    /// Only the first violations are included if there are too many.
+    pub fn into_status(mut self) -> tonic::Status {
+        if self.field_violations.len() > MAX_FIELD_VIOLATIONS {
+            let omitted = self.field_violations.len() - MAX_FIELD_VIOLATIONS;
+            self.field_violations.truncate(MAX_FIELD_VIOLATIONS);
+            self.field_violations.push(FieldViolation::new(
+                "",
+                format!("and {omitted} more violations"),
+            ));
+        }
+
+        let code = tonic::Code::InvalidArgument;
+        let message = self
+            .field_violations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let status = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: self.encode_to_vec(),
+            }],
+        };
+
+        tonic::Status::with_details(code, message, Bytes::from(status.encode_to_vec()))
+    }
+
+    /// Returns the violations in the status details, if any.
+    pub fn from_status(status: &tonic::Status) -> Option<Self> {
+        if status.details().is_empty() {
+            return None;
+        }
+
+        let status = RpcStatus::decode(status.details()).ok()?;
+        status
+            .details
+            .iter()
+            .find(|detail| detail.type_url == BAD_REQUEST_TYPE_URL)
+            .and_then(|detail| BadRequest::decode(detail.value.as_slice()).ok())
+    }
+}
+
+impl fmt::Display for FieldViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}", self.description)
+        } else {
+            write!(f, "{}: {}", self.field, self.description)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BadRequest, FieldViolation, MAX_FIELD_VIOLATIONS};
+
+    #[test]
+    fn test_bad_request_status() {
+        let bad_request = BadRequest::new(vec![
+            FieldViolation::new("address_set", "length must be a multiple of 20")
+                .with_parent("logs[1]")
+                .with_parent("filter[0]"),
+            FieldViolation::new("", "no filters provided").with_parent("filter"),
+        ]);
+
+        let status = bad_request.clone().into_status();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "filter[0].logs[1].address_set: length must be a multiple of 20; filter: no filters provided"
+        );
+        assert_eq!(BadRequest::from_status(&status), Some(bad_request));
+
+        let status = tonic::Status::invalid_argument("no details");
+        assert_eq!(BadRequest::from_status(&status), None);
+    }
+
+    #[test]
+    fn test_bad_request_status_too_many_violations() {
+        let bad_request = BadRequest::new(
+            (0..1000)
+                .map(|i| {
+                    FieldViolation::new("", "invalid filter").with_parent(format!("filter[{i}]"))
+                })
+                .collect(),
+        );
+
+        let status = bad_request.into_status();
+        assert!(status.message().starts_with("filter[0]: invalid filter; "));
+        assert!(status.message().ends_with("; and 984 more violations"));
+
+        let details = BadRequest::from_status(&status).unwrap();
+        assert_eq!(details.field_violations.len(), MAX_FIELD_VIOLATIONS + 1);
+        assert_eq!(
+            details.field_violations[MAX_FIELD_VIOLATIONS - 1].field,
+            "filter[15]"
+        );
+    }
+}
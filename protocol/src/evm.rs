@@ -6,6 +6,8 @@ use crate::helpers::{
 
 tonic::include_proto!("evm.v2");
 
+pub const EVM_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("evm_descriptor");
+
 impl_scalar_traits!(Address);
 impl_from_to_bytes!(Address, 20);
 impl_scalar_helpers!(Address, 20);
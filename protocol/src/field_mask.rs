@@ -0,0 +1,99 @@
+//! Resolve field masks to the field numbers of a message.
+use prost::Message;
+use prost_types::{FieldMask, FileDescriptorSet};
+
+use crate::error_details::FieldViolation;
+
+/// Returns the field numbers of the message's fields included in the mask.
+///
+/// The message is looked up by its fully qualified name (e.g. `evm.v2.BlockHeader`)
+/// in the descriptor set. Only top-level fields are supported.
+pub fn resolve_field_mask(
+    descriptor_set: &[u8],
+    message_name: &str,
+    mask: &FieldMask,
+) -> Result<Vec<u32>, FieldViolation> {
+    let descriptor_set = FileDescriptorSet::decode(descriptor_set)
+        .map_err(|_| FieldViolation::new("", "failed to decode message descriptors"))?;
+
+    let message = descriptor_set
+        .file
+        .iter()
+        .flat_map(|file| {
+            file.message_type
+                .iter()
+                .map(move |message| (file.package(), message))
+        })
+        .find(|(package, message)| format!("{package}.{}", message.name()) == message_name)
+        .map(|(_, message)| message)
+        .ok_or_else(|| FieldViolation::new("", format!("unknown message {message_name}")))?;
+
+    let mut fields = Vec::with_capacity(mask.paths.len());
+    for (index, path) in mask.paths.iter().enumerate() {
+        if path.contains('.') {
+            return Err(FieldViolation::new(
+                format!("paths[{index}]"),
+                "nested fields are not supported",
+            ));
+        }
+
+        let field = message
+            .field
+            .iter()
+            .find(|field| field.name() == path)
+            .ok_or_else(|| {
+                FieldViolation::new(
+                    format!("paths[{index}]"),
+                    format!("unknown field {path} in {message_name}"),
+                )
+            })?;
+
+        fields.push(field.number() as u32);
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::FieldMask;
+
+    use crate::evm::EVM_DESCRIPTOR_SET;
+
+    use super::resolve_field_mask;
+
+    fn mask(paths: &[&str]) -> FieldMask {
+        FieldMask {
+            paths: paths.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_field_mask() {
+        let fields = resolve_field_mask(
+            EVM_DESCRIPTOR_SET,
+            "evm.v2.BlockHeader",
+            &mask(&["block_number", "parent_block_hash"]),
+        )
+        .unwrap();
+        assert_eq!(fields, vec![1, 3]);
+
+        let violation = resolve_field_mask(
+            EVM_DESCRIPTOR_SET,
+            "evm.v2.BlockHeader",
+            &mask(&["block_number", "not_a_field"]),
+        )
+        .unwrap_err();
+        assert_eq!(violation.field, "paths[1]");
+
+        let violation = resolve_field_mask(
+            EVM_DESCRIPTOR_SET,
+            "evm.v2.BlockHeader",
+            &mask(&["block_hash.value"]),
+        )
+        .unwrap_err();
+        assert_eq!(violation.field, "paths[0]");
+
+        assert!(resolve_field_mask(EVM_DESCRIPTOR_SET, "evm.v2.NotAMessage", &mask(&[])).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Convert between JSON and protobuf messages using their descriptors.
+//!
+//! The mapping follows the protobuf JSON mapping, with a few changes to make it
+//! easier to read and write by hand:
+//!
+//!  - Fixed-size scalars (addresses, hashes, field elements) are hex strings.
+//!  - Bytes are hex strings.
+//!  - 64 bits integers are numbers.
+use error_stack::{Result, ResultExt};
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FieldDescriptor, Kind, MessageDescriptor, ReflectMessage, Value,
+};
+use serde_json::{Map, Number, Value as JsonValue};
+
+#[derive(Debug)]
+pub struct JsonError;
+
+/// Returns the descriptor of the message with the given fully qualified name
+/// (e.g. `evm.v2.Filter`) in the descriptor set.
+pub fn message_descriptor(
+    descriptor_set: &[u8],
+    message_name: &str,
+) -> Result<MessageDescriptor, JsonError> {
+    let pool = DescriptorPool::decode(descriptor_set)
+        .change_context(JsonError)
+        .attach_printable("failed to decode descriptor set")?;
+
+    pool.get_message_by_name(message_name)
+        .ok_or(JsonError)
+        .attach_printable("message not found in descriptor set")
+        .attach_printable_lazy(|| format!("message: {message_name}"))
+}
+
+/// Parse a JSON value into a message of the given type.
+pub fn message_from_json(
+    descriptor: &MessageDescriptor,
+    json: &JsonValue,
+) -> Result<DynamicMessage, JsonError> {
+    if is_scalar(descriptor) {
+        let JsonValue::String(hex) = json else {
+            return Err(JsonError)
+                .attach_printable("expected hex string")
+                .attach_printable_lazy(|| format!("type: {}", descriptor.full_name()));
+        };
+
+        return scalar_from_hex(descriptor, hex);
+    }
+
+    let JsonValue::Object(object) = json else {
+        return Err(JsonError)
+            .attach_printable("expected object")
+            .attach_printable_lazy(|| format!("type: {}", descriptor.full_name()));
+    };
+
+    let mut message = DynamicMessage::new(descriptor.clone());
+
+    for (key, value) in object {
+        let field = descriptor
+            .get_field_by_json_name(key)
+            .or_else(|| descriptor.get_field_by_name(key))
+            .ok_or(JsonError)
+            .attach_printable("unknown field")
+            .attach_printable_lazy(|| format!("field: {key}"))
+            .attach_printable_lazy(|| format!("type: {}", descriptor.full_name()))?;
+
+        if value.is_null() {
+            continue;
+        }
+
+        let value = if field.is_map() {
+            return Err(JsonError)
+                .attach_printable("map fields are not supported")
+                .attach_printable_lazy(|| format!("field: {}", field.full_name()));
+        } else if field.is_list() {
+            let JsonValue::Array(items) = value else {
+                return Err(JsonError)
+                    .attach_printable("expected array")
+                    .attach_printable_lazy(|| format!("field: {}", field.full_name()));
+            };
+
+            let items = items
+                .iter()
+                .map(|item| value_from_json(&field, item))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Value::List(items)
+        } else {
+            value_from_json(&field, value)?
+        };
+
+        message.set_field(&field, value);
+    }
+
+    Ok(message)
+}
+
+/// Convert a message to JSON.
+pub fn message_to_json(message: &DynamicMessage) -> JsonValue {
+    let descriptor = message.descriptor();
+
+    if is_scalar(&descriptor) {
+        return JsonValue::String(scalar_to_hex(message));
+    }
+
+    let mut object = Map::new();
+
+    for (field, value) in message.fields() {
+        object.insert(field.json_name().to_string(), value_to_json(&field, value));
+    }
+
+    JsonValue::Object(object)
+}
+
+fn value_from_json(field: &FieldDescriptor, json: &JsonValue) -> Result<Value, JsonError> {
+    let invalid = || {
+        error_stack::report!(JsonError)
+            .attach_printable("invalid field value")
+            .attach_printable(format!("field: {}", field.full_name()))
+            .attach_printable(format!("value: {json}"))
+    };
+
+    let value = match field.kind() {
+        Kind::Bool => Value::Bool(json.as_bool().ok_or_else(invalid)?),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => Value::I32(
+            json_integer::<i64>(json)
+                .and_then(|v| i32::try_from(v).ok())
+                .ok_or_else(invalid)?,
+        ),
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            Value::I64(json_integer::<i64>(json).ok_or_else(invalid)?)
+        }
+        Kind::Uint32 | Kind::Fixed32 => Value::U32(
+            json_integer::<u64>(json)
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(invalid)?,
+        ),
+        Kind::Uint64 | Kind::Fixed64 => Value::U64(json_integer::<u64>(json).ok_or_else(invalid)?),
+        Kind::Float => Value::F32(json.as_f64().ok_or_else(invalid)? as f32),
+        Kind::Double => Value::F64(json.as_f64().ok_or_else(invalid)?),
+        Kind::String => Value::String(json.as_str().ok_or_else(invalid)?.to_string()),
+        Kind::Bytes => {
+            let hex = json.as_str().ok_or_else(invalid)?;
+            let bytes = hex::decode(hex.trim_start_matches("0x")).map_err(|_| invalid())?;
+            Value::Bytes(bytes.into())
+        }
+        Kind::Enum(descriptor) => {
+            let number = match json {
+                JsonValue::String(name) => descriptor
+                    .get_value_by_name(name)
+                    .map(|value| value.number())
+                    .ok_or_else(invalid)?,
+                json => json_integer::<i64>(json)
+                    .and_then(|v| i32::try_from(v).ok())
+                    .ok_or_else(invalid)?,
+            };
+            Value::EnumNumber(number)
+        }
+        Kind::Message(descriptor) => Value::Message(message_from_json(&descriptor, json)?),
+    };
+
+    Ok(value)
+}
+
+fn value_to_json(field: &FieldDescriptor, value: &Value) -> JsonValue {
+    match value {
+        Value::Bool(value) => JsonValue::Bool(*value),
+        Value::I32(value) => JsonValue::from(*value),
+        Value::I64(value) => JsonValue::from(*value),
+        Value::U32(value) => JsonValue::from(*value),
+        Value::U64(value) => JsonValue::from(*value),
+        Value::F32(value) => Number::from_f64(*value as f64)
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        Value::F64(value) => Number::from_f64(*value)
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        Value::String(value) => JsonValue::String(value.clone()),
+        Value::Bytes(value) => JsonValue::String(format!("0x{}", hex::encode(value))),
+        Value::EnumNumber(number) => {
+            let name = match field.kind() {
+                Kind::Enum(descriptor) => descriptor
+                    .get_value(*number)
+                    .map(|value| value.name().to_string()),
+                _ => None,
+            };
+            name.map(JsonValue::String)
+                .unwrap_or_else(|| JsonValue::from(*number))
+        }
+        Value::Message(message) => message_to_json(message),
+        Value::List(items) => JsonValue::Array(
+            items
+                .iter()
+                .map(|item| value_to_json(field, item))
+                .collect(),
+        ),
+        Value::Map(entries) => JsonValue::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (map_key_to_string(key), value_to_json(field, value)))
+                .collect(),
+        ),
+    }
+}
+
+fn map_key_to_string(key: &prost_reflect::MapKey) -> String {
+    use prost_reflect::MapKey;
+
+    match key {
+        MapKey::Bool(key) => key.to_string(),
+        MapKey::I32(key) => key.to_string(),
+        MapKey::I64(key) => key.to_string(),
+        MapKey::U32(key) => key.to_string(),
+        MapKey::U64(key) => key.to_string(),
+        MapKey::String(key) => key.clone(),
+    }
+}
+
+/// Integers can be numbers or strings, like in the protobuf JSON mapping.
+fn json_integer<T>(json: &JsonValue) -> Option<T>
+where
+    T: std::str::FromStr + TryFrom<u64> + TryFrom<i64>,
+{
+    match json {
+        JsonValue::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                T::try_from(value).ok()
+            } else {
+                number.as_i64().and_then(|value| T::try_from(value).ok())
+            }
+        }
+        JsonValue::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Fixed-size scalars are messages with `x0`, `x1`, ... fixed integer fields,
+/// from the most significant to the least significant.
+fn is_scalar(descriptor: &MessageDescriptor) -> bool {
+    let mut fields = descriptor.fields().peekable();
+    if fields.peek().is_none() {
+        return false;
+    }
+
+    fields.enumerate().all(|(index, field)| {
+        field.name() == format!("x{index}")
+            && matches!(field.kind(), Kind::Fixed64 | Kind::Fixed32)
+            && !field.is_list()
+    })
+}
+
+fn scalar_to_hex(message: &DynamicMessage) -> String {
+    let mut bytes = Vec::new();
+
+    for field in message.descriptor().fields() {
+        match message.get_field(&field).as_ref() {
+            Value::U64(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+            Value::U32(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+            _ => {}
+        }
+    }
+
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn scalar_from_hex(descriptor: &MessageDescriptor, hex: &str) -> Result<DynamicMessage, JsonError> {
+    let size = descriptor
+        .fields()
+        .map(|field| match field.kind() {
+            Kind::Fixed32 => 4,
+            _ => 8,
+        })
+        .sum::<usize>();
+
+    let hex = hex.trim_start_matches("0x");
+    if hex.len() > size * 2 {
+        return Err(JsonError)
+            .attach_printable("hex value is too long")
+            .attach_printable_lazy(|| format!("type: {}", descriptor.full_name()))
+            .attach_printable_lazy(|| format!("value: 0x{hex}"));
+    }
+
+    let bytes = hex::decode(format!("{hex:0>width$}", width = size * 2))
+        .change_context(JsonError)
+        .attach_printable("invalid hex value")
+        .attach_printable_lazy(|| format!("value: 0x{hex}"))?;
+
+    let mut message = DynamicMessage::new(descriptor.clone());
+    let mut offset = 0;
+
+    for field in descriptor.fields() {
+        let value = match field.kind() {
+            Kind::Fixed32 => {
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(&bytes[offset..offset + 4]);
+                offset += 4;
+                Value::U32(u32::from_be_bytes(buf))
+            }
+            _ => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(&bytes[offset..offset + 8]);
+                offset += 8;
+                Value::U64(u64::from_be_bytes(buf))
+            }
+        };
+
+        message.set_field(&field, value);
+    }
+
+    Ok(message)
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to convert between JSON and protobuf")
+    }
+}
+
+impl error_stack::Context for JsonError {}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_reflect::DynamicMessage;
+    use serde_json::json;
+
+    use crate::evm;
+
+    use super::{message_descriptor, message_from_json, message_to_json};
+
+    #[test]
+    fn test_evm_filter_from_json() {
+        let descriptor = message_descriptor(evm::EVM_DESCRIPTOR_SET, "evm.v2.Filter").unwrap();
+
+        let json = json!({
+            "logs": [{
+                "address": "0x27504265a9bc4330e3fe82061a60cd8b6369b4dc",
+                "strict": true,
+            }],
+        });
+
+        let message = message_from_json(&descriptor, &json).unwrap();
+        let filter = evm::Filter::decode(message.encode_to_vec().as_slice()).unwrap();
+
+        let address = filter.logs[0].address.as_ref().unwrap();
+        assert_eq!(
+            address.to_hex(),
+            "0x27504265a9bc4330e3fe82061a60cd8b6369b4dc"
+        );
+
+        let back = DynamicMessage::decode(descriptor, filter.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(message_to_json(&back), json);
+    }
+}
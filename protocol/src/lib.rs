@@ -2,6 +2,9 @@ pub mod beaconchain;
 pub mod client;
 pub mod dna;
 pub mod error;
+pub mod error_details;
 pub mod evm;
+pub mod field_mask;
 mod helpers;
+pub mod json;
 pub mod starknet;
@@ -6,6 +6,9 @@ use crate::helpers::{
 
 tonic::include_proto!("starknet.v2");
 
+pub const STARKNET_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("starknet_descriptor");
+
 impl_scalar_traits!(FieldElement);
 impl_from_to_bytes!(FieldElement, 32);
 impl_scalar_helpers!(FieldElement, 32);
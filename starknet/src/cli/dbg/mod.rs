@@ -1,5 +1,7 @@
 mod prefetch;
 mod rpc;
+mod store;
 
 pub use self::prefetch::DebugPrefetchCommand;
 pub use self::rpc::DebugRpcCommand;
+pub use self::store::DebugStoreCommand;
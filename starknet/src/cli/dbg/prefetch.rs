@@ -12,7 +12,7 @@ use apibara_dna_common::{
         JOIN_FRAGMENT_ID, JOIN_FRAGMENT_NAME,
     },
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::BadRequest, starknet};
 use clap::Args;
 use tokio_util::sync::CancellationToken;
 
@@ -20,11 +20,11 @@ use crate::{
     error::StarknetError,
     filter::BlockFilterExt,
     fragment::{
-        CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CHANGE_FRAGMENT_NAME, EVENT_FRAGMENT_ID,
-        EVENT_FRAGMENT_NAME, MESSAGE_FRAGMENT_ID, MESSAGE_FRAGMENT_NAME, NONCE_UPDATE_FRAGMENT_ID,
-        NONCE_UPDATE_FRAGMENT_NAME, RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME,
-        STORAGE_DIFF_FRAGMENT_ID, STORAGE_DIFF_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID,
-        TRANSACTION_FRAGMENT_NAME,
+        CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CHANGE_FRAGMENT_NAME, CONTRACT_CLASS_FRAGMENT_ID,
+        CONTRACT_CLASS_FRAGMENT_NAME, EVENT_FRAGMENT_ID, EVENT_FRAGMENT_NAME, MESSAGE_FRAGMENT_ID,
+        MESSAGE_FRAGMENT_NAME, NONCE_UPDATE_FRAGMENT_ID, NONCE_UPDATE_FRAGMENT_NAME,
+        RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME, STORAGE_DIFF_FRAGMENT_ID,
+        STORAGE_DIFF_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME,
     },
 };
 
@@ -56,6 +56,7 @@ impl DebugPrefetchCommand {
 
         let filter = filter
             .compile_to_block_filter()
+            .map_err(|violations| BadRequest::new(violations).into_status())
             .change_context(StarknetError)?;
 
         let fragment_id_to_name = HashMap::from([
@@ -85,6 +86,10 @@ impl DebugPrefetchCommand {
                 NONCE_UPDATE_FRAGMENT_ID,
                 NONCE_UPDATE_FRAGMENT_NAME.to_string(),
             ),
+            (
+                CONTRACT_CLASS_FRAGMENT_ID,
+                CONTRACT_CLASS_FRAGMENT_NAME.to_string(),
+            ),
         ]);
 
         run_debug_prefetch_stream(
@@ -0,0 +1,96 @@
+use apibara_dna_common::{
+    cli::ObjectStoreArgs,
+    dbg::{run_debug_store_diff, DebugInspectSegmentCommand, DebugLintFilterCommand},
+    fragment::{FragmentId, HEADER_FRAGMENT_ID},
+};
+use apibara_dna_protocol::starknet;
+use clap::Subcommand;
+use error_stack::{Result, ResultExt};
+use prost::Message;
+
+use crate::{
+    cli::rpc::RpcArgs,
+    error::StarknetError,
+    filter::StarknetFilterFactory,
+    fragment::{
+        CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CLASS_FRAGMENT_ID, EVENT_FRAGMENT_ID,
+        MESSAGE_FRAGMENT_ID, NONCE_UPDATE_FRAGMENT_ID, RECEIPT_FRAGMENT_ID,
+        STORAGE_DIFF_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
+    },
+    FeeTokens, FinalityMode, StarknetBlockIngestionOptions, StarknetChainSupport,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum DebugStoreCommand {
+    /// Compare a stored block with the same block fetched from the RPC.
+    Diff {
+        #[clap(flatten)]
+        rpc: RpcArgs,
+        #[clap(flatten)]
+        object_store: ObjectStoreArgs,
+        /// The block number.
+        #[arg(long)]
+        block: u64,
+    },
+    /// Print a summary of the segment starting at the given block.
+    InspectSegment(DebugInspectSegmentCommand),
+    /// Validate filters and estimate their selectivity on a stored segment.
+    LintFilter(DebugLintFilterCommand),
+}
+
+impl DebugStoreCommand {
+    pub async fn run(self) -> Result<(), StarknetError> {
+        match self {
+            DebugStoreCommand::Diff {
+                rpc,
+                object_store,
+                block,
+            } => {
+                let provider = rpc.to_starknet_provider()?;
+                let options = StarknetBlockIngestionOptions {
+                    ingest_pending: false,
+                    ingest_classes: false,
+                    ingest_state_updates: true,
+                    index_event_class_hash: false,
+                    finality_mode: FinalityMode::default(),
+                    fee_tokens: FeeTokens::default(),
+                };
+                let starknet_chain = StarknetChainSupport::new(provider, options);
+
+                run_debug_store_diff(starknet_chain, format_fragment, block, object_store)
+                    .await
+                    .change_context(StarknetError)
+            }
+            DebugStoreCommand::InspectSegment(command) => {
+                command.run().await.change_context(StarknetError)
+            }
+            DebugStoreCommand::LintFilter(command) => command
+                .run(StarknetFilterFactory::default())
+                .await
+                .change_context(StarknetError),
+        }
+    }
+}
+
+fn format_fragment(fragment_id: FragmentId, data: &[u8]) -> String {
+    let formatted = match fragment_id {
+        HEADER_FRAGMENT_ID => starknet::BlockHeader::decode(data).map(|m| format!("{m:#?}")),
+        TRANSACTION_FRAGMENT_ID => starknet::Transaction::decode(data).map(|m| format!("{m:#?}")),
+        RECEIPT_FRAGMENT_ID => {
+            starknet::TransactionReceipt::decode(data).map(|m| format!("{m:#?}"))
+        }
+        EVENT_FRAGMENT_ID => starknet::Event::decode(data).map(|m| format!("{m:#?}")),
+        MESSAGE_FRAGMENT_ID => starknet::MessageToL1::decode(data).map(|m| format!("{m:#?}")),
+        STORAGE_DIFF_FRAGMENT_ID => starknet::StorageDiff::decode(data).map(|m| format!("{m:#?}")),
+        CONTRACT_CHANGE_FRAGMENT_ID => {
+            starknet::ContractChange::decode(data).map(|m| format!("{m:#?}"))
+        }
+        NONCE_UPDATE_FRAGMENT_ID => starknet::NonceUpdate::decode(data).map(|m| format!("{m:#?}")),
+        CONTRACT_CLASS_FRAGMENT_ID => {
+            starknet::ContractClass::decode(data).map(|m| format!("{m:#?}"))
+        }
+        _ => return hex::encode(data),
+    };
+
+    formatted.unwrap_or_else(|err| format!("invalid message: {err}"))
+}
@@ -2,18 +2,32 @@ mod dbg;
 mod rpc;
 mod start;
 
+use std::path::PathBuf;
+
+use apibara_dna_common::{
+    export::ExportCommand, migrate::MigrateCommand, resegment::ResegmentCommand,
+    snapshot::SnapshotCommand,
+};
 use clap::{Parser, Subcommand};
 use dbg::DebugPrefetchCommand;
-use error_stack::Result;
+use error_stack::{Result, ResultExt};
 use tokio_util::sync::CancellationToken;
 
-use crate::error::StarknetError;
+use crate::{error::StarknetError, filter::StarknetFilterFactory, fragment};
 
-use self::{dbg::DebugRpcCommand, start::StartCommand};
+use self::{
+    dbg::{DebugRpcCommand, DebugStoreCommand},
+    start::StartCommand,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Path to a TOML or YAML configuration file.
+    ///
+    /// Options set on the command line or in the environment take precedence.
+    #[arg(long = "config", env = "DNA_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
@@ -28,9 +42,26 @@ pub enum Command {
         #[clap(subcommand)]
         command: DebugRpcCommand,
     },
+    /// Debug the blocks in the object store.
+    #[command(name = "dbg-store")]
+    DebugStore {
+        #[clap(subcommand)]
+        command: DebugStoreCommand,
+    },
     #[command(name = "dbg-prefetch")]
     /// Debug the prefetch module.
     DebugPrefetch(Box<DebugPrefetchCommand>),
+    /// Upgrade stored segments to the current format.
+    Migrate(Box<MigrateCommand>),
+    /// Rewrite stored segments with a new segment size or compression.
+    Resegment(Box<ResegmentCommand>),
+    /// Export the data matching a filter to Parquet files.
+    Export(Box<ExportCommand>),
+    /// Export or import a snapshot of the data.
+    Snapshot {
+        #[clap(subcommand)]
+        command: Box<SnapshotCommand>,
+    },
 }
 
 impl Cli {
@@ -38,7 +69,19 @@ impl Cli {
         match self.command {
             Command::Start(command) => command.run(ct).await,
             Command::DebugRpc { command } => command.run().await,
+            Command::DebugStore { command } => command.run().await,
             Command::DebugPrefetch(command) => command.run(ct).await,
+            Command::Migrate(command) => command.run().await.change_context(StarknetError),
+            Command::Resegment(command) => command.run().await.change_context(StarknetError),
+            Command::Export(command) => command
+                .run(
+                    StarknetFilterFactory::default(),
+                    fragment::fragment_info(),
+                    ct,
+                )
+                .await
+                .change_context(StarknetError),
+            Command::Snapshot { command } => command.run().await.change_context(StarknetError),
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use apibara_dna_common::rpc_pool::{weighted_urls, RpcEndpoint, RpcPoolOptions};
 use clap::Args;
 use error_stack::{Result, ResultExt};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
@@ -7,18 +8,57 @@ use url::Url;
 
 use crate::{
     error::StarknetError,
-    provider::{StarknetProvider, StarknetProviderOptions},
+    provider::{FallbackRequest, StarknetProvider, StarknetProviderOptions},
 };
 
 #[derive(Args, Debug)]
 pub struct RpcArgs {
     /// Starknet RPC URL.
+    ///
+    /// Pass multiple URLs, separated by commas, to fail over between them.
     #[arg(
         long = "rpc.url",
         env = "STARKNET_RPC_URL",
+        value_delimiter = ',',
         default_value = "http://localhost:8545"
     )]
-    pub rpc_url: String,
+    pub rpc_url: Vec<String>,
+
+    /// Weight of each RPC URL when load balancing, in the same order as the URLs.
+    ///
+    /// URLs without a weight default to 1.
+    #[arg(
+        long = "rpc.weights",
+        env = "STARKNET_RPC_WEIGHTS",
+        value_delimiter = ','
+    )]
+    pub rpc_weights: Vec<u32>,
+
+    /// Distribute requests across all healthy RPC URLs.
+    #[arg(
+        long = "rpc.load-balance",
+        env = "STARKNET_RPC_LOAD_BALANCE",
+        default_value = "false"
+    )]
+    pub rpc_load_balance: bool,
+
+    /// How long to skip an RPC URL after a failed request.
+    #[arg(
+        long = "rpc.failover-cooldown-sec",
+        env = "STARKNET_RPC_FAILOVER_COOLDOWN_SEC",
+        default_value = "30"
+    )]
+    pub rpc_failover_cooldown_sec: u64,
+
+    /// How often to check the RPC URLs' health in the background, in seconds.
+    ///
+    /// Set to 0 to disable the health checks.
+    #[arg(
+        long = "rpc.health-check-interval-sec",
+        env = "STARKNET_RPC_HEALTH_CHECK_INTERVAL_SEC",
+        default_value = "10"
+    )]
+    pub rpc_health_check_interval_sec: u64,
 
     /// Request timeout.
     #[arg(
@@ -31,16 +71,28 @@ pub struct RpcArgs {
     /// Headers to send with the requests.
     #[arg(long = "rpc.headers", env = "STARKNET_RPC_HEADERS")]
     pub rpc_headers: Vec<String>,
+
+    /// RPC URLs used when the main RPC URLs fail, separated by commas.
+    #[arg(
+        long = "rpc.fallback-url",
+        env = "STARKNET_RPC_FALLBACK_URL",
+        value_delimiter = ','
+    )]
+    pub rpc_fallback_url: Vec<String>,
+
+    /// Requests sent to the fallback RPC URLs when the main RPC URLs fail.
+    #[arg(
+        long = "rpc.fallback-requests",
+        env = "STARKNET_RPC_FALLBACK_REQUESTS",
+        value_delimiter = ',',
+        default_value = "pending"
+    )]
+    pub rpc_fallback_requests: Vec<FallbackRequest>,
 }
 
 impl RpcArgs {
     pub fn to_starknet_provider(&self) -> Result<StarknetProvider, StarknetError> {
-        let url = self
-            .rpc_url
-            .parse::<Url>()
-            .change_context(StarknetError)
-            .attach_printable("failed to parse RPC URL")
-            .attach_printable_lazy(|| format!("url: {}", self.rpc_url))?;
+        let urls = parse_endpoints(&self.rpc_url, &self.rpc_weights)?;
 
         let headers = {
             let mut headers = HeaderMap::default();
@@ -71,8 +123,47 @@ impl RpcArgs {
         let options = StarknetProviderOptions {
             timeout: Duration::from_secs(self.rpc_timeout_sec),
             headers,
+            pool: RpcPoolOptions {
+                load_balance: self.rpc_load_balance,
+                unhealthy_cooldown: Duration::from_secs(self.rpc_failover_cooldown_sec),
+                health_check_interval: (self.rpc_health_check_interval_sec > 0)
+                    .then(|| Duration::from_secs(self.rpc_health_check_interval_sec)),
+            },
+            fallback_requests: self.rpc_fallback_requests.clone(),
         };
 
-        StarknetProvider::new(url, options).change_context(StarknetError)
+        let provider = StarknetProvider::new(urls, options).change_context(StarknetError)?;
+
+        if self.rpc_fallback_url.is_empty() {
+            return Ok(provider);
+        }
+
+        let fallback_urls = parse_endpoints(&self.rpc_fallback_url, &[])?;
+        provider
+            .with_fallback(fallback_urls)
+            .change_context(StarknetError)
     }
 }
+
+fn parse_endpoints(
+    urls: &[String],
+    weights: &[u32],
+) -> Result<Vec<RpcEndpoint<Url>>, StarknetError> {
+    weighted_urls(urls, weights)
+        .change_context(StarknetError)
+        .attach_printable("invalid RPC weights")?
+        .into_iter()
+        .map(|(url, weight)| -> Result<RpcEndpoint<Url>, StarknetError> {
+            let url = url
+                .parse::<Url>()
+                .change_context(StarknetError)
+                .attach_printable("failed to parse RPC URL")
+                .attach_printable_lazy(|| format!("url: {}", url))?;
+            Ok(RpcEndpoint {
+                name: url.host_str().unwrap_or_default().to_string(),
+                client: url,
+                weight,
+            })
+        })
+        .collect()
+}
@@ -4,7 +4,12 @@ use error_stack::{Result, ResultExt};
 use tokio_util::sync::CancellationToken;
 use tracing::info;
 
-use crate::{error::StarknetError, StarknetBlockIngestionOptions, StarknetChainSupport};
+use crate::{
+    error::StarknetError,
+    ingestion::{ETH_FEE_TOKEN_ADDRESS, STRK_FEE_TOKEN_ADDRESS},
+    provider::models,
+    FeeTokens, FinalityMode, StarknetBlockIngestionOptions, StarknetChainSupport,
+};
 
 use super::rpc::RpcArgs;
 
@@ -22,14 +27,83 @@ pub struct StartCommand {
         default_value = "false"
     )]
     no_ingest_pending: bool,
+
+    /// Fetch and store the definition of the classes declared in each block.
+    #[arg(
+        long = "starknet.ingest-classes",
+        env = "STARKNET_INGEST_CLASSES",
+        default_value = "false"
+    )]
+    ingest_classes: bool,
+
+    /// Do NOT fetch state updates, for sequencers that don't implement them.
+    ///
+    /// Blocks are stored with empty state diffs.
+    #[arg(
+        long = "starknet.no-ingest-state-updates",
+        env = "STARKNET_NO_INGEST_STATE_UPDATES",
+        default_value = "false"
+    )]
+    no_ingest_state_updates: bool,
+
+    /// Index events by the class hash of the contract emitting them.
+    ///
+    /// The class of contracts not deployed or replaced since ingestion started is
+    /// fetched from the node, which sends more requests to the node.
+    #[arg(
+        long = "starknet.index-event-class-hash",
+        env = "STARKNET_INDEX_EVENT_CLASS_HASH",
+        default_value = "false"
+    )]
+    index_event_class_hash: bool,
+
+    /// How to decide which blocks are finalized.
+    ///
+    /// Use `instant` for appchains that don't settle on L1.
+    #[arg(
+        long = "starknet.finality-mode",
+        env = "STARKNET_FINALITY_MODE",
+        default_value = "l1"
+    )]
+    finality_mode: FinalityMode,
+
+    /// Address of the token used to pay fees in WEI.
+    ///
+    /// Change it for appchains with a custom fee token.
+    #[arg(
+        long = "starknet.fee-token-wei",
+        env = "STARKNET_FEE_TOKEN_WEI",
+        default_value = ETH_FEE_TOKEN_ADDRESS
+    )]
+    fee_token_wei: String,
+
+    /// Address of the token used to pay fees in FRI.
+    ///
+    /// Change it for appchains with a custom fee token.
+    #[arg(
+        long = "starknet.fee-token-fri",
+        env = "STARKNET_FEE_TOKEN_FRI",
+        default_value = STRK_FEE_TOKEN_ADDRESS
+    )]
+    fee_token_fri: String,
 }
 
 impl StartCommand {
     pub async fn run(self, ct: CancellationToken) -> Result<(), StarknetError> {
         info!("Starting Starknet DNA server");
         let provider = self.rpc.to_starknet_provider()?;
+        tokio::spawn(provider.clone().health_check_loop(ct.clone()));
+        let fee_tokens = FeeTokens {
+            wei: parse_fee_token(&self.fee_token_wei)?,
+            fri: parse_fee_token(&self.fee_token_fri)?,
+        };
         let starknet_ingestion_options = StarknetBlockIngestionOptions {
             ingest_pending: !self.no_ingest_pending,
+            ingest_classes: self.ingest_classes,
+            ingest_state_updates: !self.no_ingest_state_updates,
+            index_event_class_hash: self.index_event_class_hash,
+            finality_mode: self.finality_mode,
+            fee_tokens,
         };
         let starknet_chain = StarknetChainSupport::new(provider, starknet_ingestion_options);
 
@@ -38,3 +112,10 @@ impl StartCommand {
             .change_context(StarknetError)
     }
 }
+
+fn parse_fee_token(address: &str) -> Result<models::FieldElement, StarknetError> {
+    models::FieldElement::from_hex(address)
+        .map_err(|_| StarknetError)
+        .attach_printable("invalid fee token address")
+        .attach_printable_lazy(|| format!("address: {address}"))
+}
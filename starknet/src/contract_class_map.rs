@@ -0,0 +1,125 @@
+//! Track the class of contracts, to index events by the class of the contract emitting them.
+use apibara_dna_common::ingestion::BlockStateCache;
+
+use crate::provider::models;
+
+/// Maximum number of contracts tracked, older entries are looked up again when needed.
+const MAX_CONTRACT_COUNT: usize = 1_000_000;
+
+/// Maps contract addresses to their class hash.
+///
+/// The map is updated with the contracts deployed and the classes replaced in each
+/// block. Contracts deployed before ingestion started are looked up from the node
+/// and added to the map.
+///
+/// Blocks are ingested concurrently, so the class of a contract is only used for
+/// another block once the state diffs of all blocks in between are known.
+#[derive(Clone)]
+pub struct ContractClassMap {
+    classes: BlockStateCache<models::FieldElement, models::FieldElement>,
+}
+
+impl ContractClassMap {
+    /// Returns the class of the contract at the end of the given block, if known.
+    pub fn get(
+        &self,
+        contract_address: &models::FieldElement,
+        block_number: u64,
+    ) -> Option<models::FieldElement> {
+        self.classes.get(contract_address, block_number)
+    }
+
+    /// Store the class of the contract at the end of the given block, as returned by the node.
+    pub fn insert(
+        &self,
+        contract_address: models::FieldElement,
+        class_hash: models::FieldElement,
+        block_number: u64,
+    ) {
+        self.classes
+            .insert(contract_address, class_hash, block_number);
+    }
+
+    /// Update the map with the contracts deployed and replaced in the block.
+    ///
+    /// Must be called for every block ingested with its state diff.
+    pub fn apply_state_diff(&self, state_diff: &models::StateDiff, block_number: u64) {
+        self.classes.apply_block(
+            block_number,
+            state_diff
+                .replaced_classes
+                .iter()
+                .map(|replaced| (replaced.contract_address, replaced.class_hash)),
+        );
+
+        // Contracts have no class before they're deployed, so deployments don't
+        // change the class of existing contracts.
+        for deployed in state_diff.deployed_contracts.iter() {
+            self.classes
+                .insert(deployed.address, deployed.class_hash, block_number);
+        }
+    }
+}
+
+impl Default for ContractClassMap {
+    fn default() -> Self {
+        Self {
+            classes: BlockStateCache::new(MAX_CONTRACT_COUNT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::provider::models;
+
+    use super::ContractClassMap;
+
+    fn state_diff(deployed: &[(u64, u64)], replaced: &[(u64, u64)]) -> models::StateDiff {
+        models::StateDiff {
+            storage_diffs: Vec::default(),
+            deprecated_declared_classes: Vec::default(),
+            declared_classes: Vec::default(),
+            deployed_contracts: deployed
+                .iter()
+                .map(|(address, class_hash)| models::DeployedContractItem {
+                    address: (*address).into(),
+                    class_hash: (*class_hash).into(),
+                })
+                .collect(),
+            replaced_classes: replaced
+                .iter()
+                .map(|(address, class_hash)| models::ReplacedClassItem {
+                    contract_address: (*address).into(),
+                    class_hash: (*class_hash).into(),
+                })
+                .collect(),
+            nonces: Vec::default(),
+        }
+    }
+
+    #[test]
+    fn test_contract_class_map() {
+        let map = ContractClassMap::default();
+        let contract = models::FieldElement::from(1u64);
+
+        map.apply_state_diff(&state_diff(&[(1, 10)], &[]), 100);
+        map.apply_state_diff(&state_diff(&[], &[]), 101);
+        assert_eq!(map.get(&contract, 101), Some(10u64.into()));
+
+        // The class is replaced in a block ingested out of order.
+        map.apply_state_diff(&state_diff(&[], &[]), 103);
+        assert_eq!(map.get(&contract, 103), None);
+
+        map.apply_state_diff(&state_diff(&[], &[(1, 20)]), 102);
+        assert_eq!(map.get(&contract, 101), None);
+        assert_eq!(map.get(&contract, 102), Some(20u64.into()));
+        assert_eq!(map.get(&contract, 103), Some(20u64.into()));
+
+        // Contracts looked up from the node are used for the blocks with a known state diff.
+        let other = models::FieldElement::from(2u64);
+        map.insert(other, 30u64.into(), 103);
+        assert_eq!(map.get(&other, 100), Some(30u64.into()));
+        assert_eq!(map.get(&other, 104), None);
+    }
+}
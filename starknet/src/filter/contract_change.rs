@@ -2,11 +2,11 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
 
 use crate::fragment::{CONTRACT_CHANGE_FRAGMENT_ID, INDEX_CONTRACT_CHANGE_BY_TYPE};
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ContractChangeType {
@@ -16,7 +16,7 @@ pub enum ContractChangeType {
 }
 
 impl FragmentFilterExt for starknet::ContractChangeFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(change) = self.change.as_ref() {
@@ -30,7 +30,7 @@ impl FragmentFilterExt for starknet::ContractChangeFilter {
 
             conditions.push(Condition {
                 index_id: INDEX_CONTRACT_CHANGE_BY_TYPE,
-                key: key.to_scalar_value(),
+                key: key.to_scalar_value().into(),
             });
         }
 
@@ -39,6 +39,7 @@ impl FragmentFilterExt for starknet::ContractChangeFilter {
             fragment_id: CONTRACT_CHANGE_FRAGMENT_ID,
             conditions,
             joins: Vec::default(),
+            fields: compile_field_mask("starknet.v2.ContractChange", self.fields.as_ref())?,
         })
     }
 }
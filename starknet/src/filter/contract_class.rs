@@ -0,0 +1,30 @@
+use apibara_dna_common::{
+    index::ScalarValue,
+    query::{Condition, Filter},
+};
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
+
+use crate::fragment::{CONTRACT_CLASS_FRAGMENT_ID, INDEX_CONTRACT_CLASS_BY_CLASS_HASH};
+
+use super::helpers::{compile_field_mask, FragmentFilterExt};
+
+impl FragmentFilterExt for starknet::ContractClassFilter {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
+        let mut conditions = Vec::new();
+
+        if let Some(class_hash) = self.class_hash.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_CONTRACT_CLASS_BY_CLASS_HASH,
+                key: ScalarValue::B256(class_hash.to_bytes()).into(),
+            })
+        }
+
+        Ok(Filter {
+            filter_id: self.id,
+            fragment_id: CONTRACT_CLASS_FRAGMENT_ID,
+            conditions,
+            joins: Vec::default(),
+            fields: compile_field_mask("starknet.v2.ContractClass", self.fields.as_ref())?,
+        })
+    }
+}
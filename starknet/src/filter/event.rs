@@ -2,32 +2,39 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
 
 use crate::fragment::{
-    EVENT_FRAGMENT_ID, INDEX_EVENT_BY_ADDRESS, INDEX_EVENT_BY_KEY0, INDEX_EVENT_BY_KEY1,
-    INDEX_EVENT_BY_KEY2, INDEX_EVENT_BY_KEY3, INDEX_EVENT_BY_KEY_LENGTH,
-    INDEX_EVENT_BY_TRANSACTION_STATUS, MESSAGE_FRAGMENT_ID, RECEIPT_FRAGMENT_ID,
-    TRANSACTION_FRAGMENT_ID,
+    EVENT_FRAGMENT_ID, INDEX_EVENT_BY_ADDRESS, INDEX_EVENT_BY_CONTRACT_CLASS_HASH,
+    INDEX_EVENT_BY_KEY0, INDEX_EVENT_BY_KEY1, INDEX_EVENT_BY_KEY2, INDEX_EVENT_BY_KEY3,
+    INDEX_EVENT_BY_KEY_LENGTH, INDEX_EVENT_BY_TRANSACTION_STATUS, MESSAGE_FRAGMENT_ID,
+    RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for starknet::EventFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(address) = self.address.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_ADDRESS,
-                key: ScalarValue::B256(address.to_bytes()),
+                key: ScalarValue::B256(address.to_bytes()).into(),
+            });
+        }
+
+        if let Some(class_hash) = self.class_hash.as_ref() {
+            conditions.push(Condition {
+                index_id: INDEX_EVENT_BY_CONTRACT_CLASS_HASH,
+                key: ScalarValue::B256(class_hash.to_bytes()).into(),
             });
         }
 
         if let Some(true) = self.strict.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_KEY_LENGTH,
-                key: ScalarValue::Uint32(self.keys.len() as u32),
+                key: ScalarValue::Uint32(self.keys.len() as u32).into(),
             });
         }
 
@@ -36,34 +43,37 @@ impl FragmentFilterExt for starknet::EventFilter {
         if let Some(key) = keys.next().and_then(|key| key.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_KEY0,
-                key: ScalarValue::B256(key.to_bytes()),
+                key: ScalarValue::B256(key.to_bytes()).into(),
             });
         }
         if let Some(key) = keys.next().and_then(|key| key.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_KEY1,
-                key: ScalarValue::B256(key.to_bytes()),
+                key: ScalarValue::B256(key.to_bytes()).into(),
             });
         }
         if let Some(key) = keys.next().and_then(|key| key.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_KEY2,
-                key: ScalarValue::B256(key.to_bytes()),
+                key: ScalarValue::B256(key.to_bytes()).into(),
             });
         }
         if let Some(key) = keys.next().and_then(|key| key.value.as_ref()) {
             conditions.push(Condition {
                 index_id: INDEX_EVENT_BY_KEY3,
-                key: ScalarValue::B256(key.to_bytes()),
+                key: ScalarValue::B256(key.to_bytes()).into(),
             });
         }
 
         let transaction_status = if let Some(transaction_status) = self.transaction_status {
             starknet::TransactionStatusFilter::try_from(transaction_status).map_err(|_| {
-                tonic::Status::invalid_argument(format!(
-                    "invalid transaction status in event filter with id {}",
-                    self.id
-                ))
+                FieldViolation::new(
+                    "transaction_status",
+                    format!(
+                        "invalid transaction status in event filter with id {}",
+                        self.id
+                    ),
+                )
             })?
         } else {
             starknet::TransactionStatusFilter::Succeeded
@@ -75,13 +85,13 @@ impl FragmentFilterExt for starknet::EventFilter {
             starknet::TransactionStatusFilter::Succeeded => {
                 conditions.push(Condition {
                     index_id: INDEX_EVENT_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32).into(),
                 });
             }
             starknet::TransactionStatusFilter::Reverted => {
                 conditions.push(Condition {
                     index_id: INDEX_EVENT_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32).into(),
                 });
             }
         };
@@ -109,6 +119,7 @@ impl FragmentFilterExt for starknet::EventFilter {
             fragment_id: EVENT_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("starknet.v2.Event", self.fields.as_ref())?,
         })
     }
 }
@@ -1,9 +1,45 @@
-use apibara_dna_common::query::{BlockFilter, Filter};
+use apibara_dna_common::query::{BlockFilter, FieldProjection, Filter};
+use apibara_dna_protocol::{
+    error_details::FieldViolation, field_mask::resolve_field_mask,
+    starknet::STARKNET_DESCRIPTOR_SET,
+};
+use prost_types::FieldMask;
 
 pub trait BlockFilterExt {
-    fn compile_to_block_filter(&self) -> tonic::Result<BlockFilter, tonic::Status>;
+    fn compile_to_block_filter(&self) -> Result<BlockFilter, Vec<FieldViolation>>;
 }
 
 pub trait FragmentFilterExt {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status>;
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation>;
+}
+
+/// Compile the fragment filters in the `field` list and add them to the block filter.
+///
+/// Violations are collected instead of returned so that clients see all of them at once.
+pub fn compile_fragment_filters<F: FragmentFilterExt>(
+    block_filter: &mut BlockFilter,
+    field: &str,
+    filters: &[F],
+    violations: &mut Vec<FieldViolation>,
+) {
+    for (index, filter) in filters.iter().enumerate() {
+        match filter.compile_to_filter() {
+            Ok(filter) => block_filter.add_filter(filter),
+            Err(violation) => violations.push(violation.with_parent(format!("{field}[{index}]"))),
+        }
+    }
+}
+
+/// Resolve the `fields` mask of a fragment filter against the fragment's message.
+pub fn compile_field_mask(
+    message_name: &str,
+    mask: Option<&FieldMask>,
+) -> Result<Option<FieldProjection>, FieldViolation> {
+    let Some(mask) = mask else {
+        return Ok(None);
+    };
+
+    resolve_field_mask(STARKNET_DESCRIPTOR_SET, message_name, mask)
+        .map(|fields| Some(FieldProjection::new(fields)))
+        .map_err(|violation| violation.with_parent("fields"))
 }
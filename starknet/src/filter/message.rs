@@ -2,7 +2,7 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
 
 use crate::fragment::{
     EVENT_FRAGMENT_ID, INDEX_MESSAGE_BY_FROM_ADDRESS, INDEX_MESSAGE_BY_TO_ADDRESS,
@@ -10,32 +10,35 @@ use crate::fragment::{
     TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for starknet::MessageToL1Filter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(address) = self.from_address.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_MESSAGE_BY_FROM_ADDRESS,
-                key: ScalarValue::B256(address.to_bytes()),
+                key: ScalarValue::B256(address.to_bytes()).into(),
             })
         }
 
         if let Some(address) = self.to_address.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_MESSAGE_BY_TO_ADDRESS,
-                key: ScalarValue::B256(address.to_bytes()),
+                key: ScalarValue::B256(address.to_bytes()).into(),
             })
         }
 
         let transaction_status = if let Some(transaction_status) = self.transaction_status {
             starknet::TransactionStatusFilter::try_from(transaction_status).map_err(|_| {
-                tonic::Status::invalid_argument(format!(
-                    "invalid transaction status in message filter with id {}",
-                    self.id
-                ))
+                FieldViolation::new(
+                    "transaction_status",
+                    format!(
+                        "invalid transaction status in message filter with id {}",
+                        self.id
+                    ),
+                )
             })?
         } else {
             starknet::TransactionStatusFilter::Succeeded
@@ -47,13 +50,13 @@ impl FragmentFilterExt for starknet::MessageToL1Filter {
             starknet::TransactionStatusFilter::Succeeded => {
                 conditions.push(Condition {
                     index_id: INDEX_MESSAGE_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32).into(),
                 });
             }
             starknet::TransactionStatusFilter::Reverted => {
                 conditions.push(Condition {
                     index_id: INDEX_MESSAGE_BY_TRANSACTION_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32).into(),
                 });
             }
         };
@@ -81,6 +84,7 @@ impl FragmentFilterExt for starknet::MessageToL1Filter {
             fragment_id: MESSAGE_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("starknet.v2.MessageToL1", self.fields.as_ref())?,
         })
     }
 }
@@ -1,4 +1,5 @@
 mod contract_change;
+mod contract_class;
 mod event;
 mod helpers;
 mod message;
@@ -8,11 +9,17 @@ mod transaction;
 
 use apibara_dna_common::{
     data_stream::BlockFilterFactory,
-    query::{BlockFilter, HeaderFilter},
+    query::{BlockFilter, FieldProjection, HeaderFilter},
+};
+use apibara_dna_protocol::{
+    error_details::{BadRequest, FieldViolation},
+    field_mask::resolve_field_mask,
+    starknet,
 };
-use apibara_dna_protocol::starknet;
 use prost::Message;
 
+use self::helpers::compile_fragment_filters;
+
 pub use self::{
     contract_change::ContractChangeType,
     helpers::{BlockFilterExt, FragmentFilterExt},
@@ -20,47 +27,87 @@ pub use self::{
 };
 
 #[derive(Debug, Clone)]
-pub struct StarknetFilterFactory;
+pub struct StarknetFilterFactory {
+    /// Whether the server ingests state updates.
+    ///
+    /// Filters on state updates are rejected if not, since they would never match.
+    state_updates: bool,
+}
+
+impl StarknetFilterFactory {
+    /// Returns a factory that rejects filters on state updates.
+    pub fn without_state_updates() -> Self {
+        Self {
+            state_updates: false,
+        }
+    }
+}
+
+impl Default for StarknetFilterFactory {
+    fn default() -> Self {
+        Self {
+            state_updates: true,
+        }
+    }
+}
 
 impl BlockFilterFactory for StarknetFilterFactory {
     fn create_block_filter(
         &self,
         filters: &[Vec<u8>],
     ) -> tonic::Result<Vec<BlockFilter>, tonic::Status> {
-        let proto_filters = filters
-            .iter()
-            .map(|bytes| starknet::Filter::decode(bytes.as_slice()))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| tonic::Status::invalid_argument("failed to decode filter"))?;
-
-        if proto_filters.is_empty() {
-            return Err(tonic::Status::invalid_argument("no filters provided"));
+        let mut block_filters = Vec::with_capacity(filters.len());
+        let mut violations = Vec::new();
+
+        for (index, bytes) in filters.iter().enumerate() {
+            let parent = format!("filter[{index}]");
+
+            let Ok(filter) = starknet::Filter::decode(bytes.as_slice()) else {
+                violations
+                    .push(FieldViolation::new("", "failed to decode filter").with_parent(parent));
+                continue;
+            };
+
+            if !self.state_updates {
+                violations.extend(
+                    state_update_violations(&filter)
+                        .into_iter()
+                        .map(|violation| violation.with_parent(&parent)),
+                );
+            }
+
+            match filter.compile_to_block_filter() {
+                Ok(block_filter) => block_filters.push(block_filter),
+                Err(errors) => violations.extend(
+                    errors
+                        .into_iter()
+                        .map(|violation| violation.with_parent(&parent)),
+                ),
+            }
         }
 
-        if proto_filters.len() > 5 {
-            return Err(tonic::Status::invalid_argument(format!(
-                "too many filters ({} > 5)",
-                proto_filters.len(),
-            )));
+        if !violations.is_empty() {
+            return Err(BadRequest::new(violations).into_status());
         }
 
-        let filters = proto_filters
-            .iter()
-            .map(BlockFilterExt::compile_to_block_filter)
-            .collect::<tonic::Result<Vec<_>>>()?;
+        if block_filters.is_empty() {
+            return Err(filter_violation("no filters provided"));
+        }
 
-        if filters.iter().any(|f| f.can_produce_data()) {
-            Ok(filters)
+        if block_filters.iter().any(|f| f.can_produce_data()) {
+            Ok(block_filters)
         } else {
-            Err(tonic::Status::invalid_argument(
-                "at least one filter must be non-empty",
-            ))
+            Err(filter_violation("at least one filter must be non-empty"))
         }
     }
+
+    fn filter_descriptor(&self) -> (&'static [u8], &'static str) {
+        (starknet::STARKNET_DESCRIPTOR_SET, "starknet.v2.Filter")
+    }
 }
 
 impl BlockFilterExt for starknet::Filter {
-    fn compile_to_block_filter(&self) -> tonic::Result<BlockFilter, tonic::Status> {
+    fn compile_to_block_filter(&self) -> Result<BlockFilter, Vec<FieldViolation>> {
         let mut block_filter = BlockFilter::default();
 
         let header_filter = match starknet::HeaderFilter::try_from(self.header) {
@@ -75,36 +122,78 @@ impl BlockFilterExt for starknet::Filter {
 
         block_filter.set_header_filter(header_filter);
 
-        for filter in self.transactions.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
-
-        for filter in self.events.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
-
-        for filter in self.messages.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
-
-        for filter in self.storage_diffs.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+        let mut violations = Vec::new();
+        if let Some(header_fields) = self.header_fields.as_ref() {
+            match resolve_field_mask(
+                starknet::STARKNET_DESCRIPTOR_SET,
+                "starknet.v2.BlockHeader",
+                header_fields,
+            ) {
+                Ok(fields) => block_filter.set_header_fields(FieldProjection::new(fields)),
+                Err(violation) => violations.push(violation.with_parent("header_fields")),
+            }
         }
 
-        for filter in self.contract_changes.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
+        compile_fragment_filters(
+            &mut block_filter,
+            "transactions",
+            &self.transactions,
+            &mut violations,
+        );
+        compile_fragment_filters(&mut block_filter, "events", &self.events, &mut violations);
+        compile_fragment_filters(
+            &mut block_filter,
+            "messages",
+            &self.messages,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "storage_diffs",
+            &self.storage_diffs,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "contract_changes",
+            &self.contract_changes,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "nonce_updates",
+            &self.nonce_updates,
+            &mut violations,
+        );
+        compile_fragment_filters(
+            &mut block_filter,
+            "contract_classes",
+            &self.contract_classes,
+            &mut violations,
+        );
+
+        if violations.is_empty() {
+            Ok(block_filter)
+        } else {
+            Err(violations)
         }
+    }
+}
 
-        for filter in self.nonce_updates.iter() {
-            let filter = filter.compile_to_filter()?;
-            block_filter.add_filter(filter);
-        }
+/// Returns a violation for each state update filter, for servers that don't ingest them.
+fn state_update_violations(filter: &starknet::Filter) -> Vec<FieldViolation> {
+    [
+        ("storage_diffs", filter.storage_diffs.is_empty()),
+        ("contract_changes", filter.contract_changes.is_empty()),
+        ("nonce_updates", filter.nonce_updates.is_empty()),
+    ]
+    .into_iter()
+    .filter(|(_, is_empty)| !is_empty)
+    .map(|(field, _)| FieldViolation::new(field, "state updates are not ingested by this server"))
+    .collect()
+}
 
-        Ok(block_filter)
-    }
+/// Returns an error about the list of filters as a whole.
+fn filter_violation(description: &str) -> tonic::Status {
+    BadRequest::new(vec![FieldViolation::new("filter", description)]).into_status()
 }
@@ -2,20 +2,20 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
 
 use crate::fragment::{INDEX_NONCE_UPDATE_BY_CONTRACT_ADDRESS, NONCE_UPDATE_FRAGMENT_ID};
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 impl FragmentFilterExt for starknet::NonceUpdateFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         if let Some(address) = self.contract_address.as_ref() {
             conditions.push(Condition {
                 index_id: INDEX_NONCE_UPDATE_BY_CONTRACT_ADDRESS,
-                key: ScalarValue::B256(address.to_bytes()),
+                key: ScalarValue::B256(address.to_bytes()).into(),
             })
         }
 
@@ -24,6 +24,7 @@ impl FragmentFilterExt for starknet::NonceUpdateFilter {
             fragment_id: NONCE_UPDATE_FRAGMENT_ID,
             conditions,
             joins: Vec::default(),
+            fields: compile_field_mask("starknet.v2.NonceUpdate", self.fields.as_ref())?,
         })
     }
 }
@@ -2,14 +2,14 @@ use apibara_dna_common::{
     index::ScalarValue,
     query::{Condition, Filter},
 };
-use apibara_dna_protocol::starknet;
+use apibara_dna_protocol::{error_details::FieldViolation, starknet};
 
 use crate::fragment::{
     EVENT_FRAGMENT_ID, INDEX_TRANSACTION_BY_STATUS, INDEX_TRANSACTION_BY_TYPE, MESSAGE_FRAGMENT_ID,
     RECEIPT_FRAGMENT_ID, TRANSACTION_FRAGMENT_ID,
 };
 
-use super::helpers::FragmentFilterExt;
+use super::helpers::{compile_field_mask, FragmentFilterExt};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionType {
@@ -31,15 +31,18 @@ pub enum TransactionType {
 }
 
 impl FragmentFilterExt for starknet::TransactionFilter {
-    fn compile_to_filter(&self) -> tonic::Result<Filter, tonic::Status> {
+    fn compile_to_filter(&self) -> Result<Filter, FieldViolation> {
         let mut conditions = Vec::new();
 
         let transaction_status = if let Some(transaction_status) = self.transaction_status {
             starknet::TransactionStatusFilter::try_from(transaction_status).map_err(|_| {
-                tonic::Status::invalid_argument(format!(
-                    "invalid transaction status in transaction filter with id {}",
-                    self.id
-                ))
+                FieldViolation::new(
+                    "transaction_status",
+                    format!(
+                        "invalid transaction status in transaction filter with id {}",
+                        self.id
+                    ),
+                )
             })?
         } else {
             starknet::TransactionStatusFilter::Succeeded
@@ -51,13 +54,13 @@ impl FragmentFilterExt for starknet::TransactionFilter {
             starknet::TransactionStatusFilter::Succeeded => {
                 conditions.push(Condition {
                     index_id: INDEX_TRANSACTION_BY_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Succeeded as i32).into(),
                 });
             }
             starknet::TransactionStatusFilter::Reverted => {
                 conditions.push(Condition {
                     index_id: INDEX_TRANSACTION_BY_STATUS,
-                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32),
+                    key: ScalarValue::Int32(starknet::TransactionStatus::Reverted as i32).into(),
                 });
             }
         };
@@ -80,7 +83,7 @@ impl FragmentFilterExt for starknet::TransactionFilter {
 
             conditions.push(Condition {
                 index_id: INDEX_TRANSACTION_BY_TYPE,
-                key: key.to_scalar_value(),
+                key: key.to_scalar_value().into(),
             });
         }
 
@@ -103,6 +106,7 @@ impl FragmentFilterExt for starknet::TransactionFilter {
             fragment_id: TRANSACTION_FRAGMENT_ID,
             conditions,
             joins,
+            fields: compile_field_mask("starknet.v2.Transaction", self.fields.as_ref())?,
         })
     }
 }
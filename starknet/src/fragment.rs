@@ -1,5 +1,7 @@
 //! Fragment constants.
 
+use apibara_dna_common::fragment::FragmentInfo;
+
 // Make sure the fragment IDs match the field tags in the protobuf Block message.
 
 pub const TRANSACTION_FRAGMENT_ID: u8 = 2;
@@ -23,6 +25,9 @@ pub const CONTRACT_CHANGE_FRAGMENT_NAME: &str = "contract_change";
 pub const NONCE_UPDATE_FRAGMENT_ID: u8 = 8;
 pub const NONCE_UPDATE_FRAGMENT_NAME: &str = "nonce_update";
 
+pub const CONTRACT_CLASS_FRAGMENT_ID: u8 = 9;
+pub const CONTRACT_CLASS_FRAGMENT_NAME: &str = "contract_class";
+
 pub const INDEX_TRANSACTION_BY_STATUS: u8 = 0;
 pub const INDEX_TRANSACTION_BY_TYPE: u8 = 1;
 
@@ -35,6 +40,7 @@ pub const INDEX_EVENT_BY_KEY2: u8 = 3;
 pub const INDEX_EVENT_BY_KEY3: u8 = 4;
 pub const INDEX_EVENT_BY_KEY_LENGTH: u8 = 5;
 pub const INDEX_EVENT_BY_TRANSACTION_STATUS: u8 = 6;
+pub const INDEX_EVENT_BY_CONTRACT_CLASS_HASH: u8 = 7;
 
 pub const INDEX_MESSAGE_BY_FROM_ADDRESS: u8 = 0;
 pub const INDEX_MESSAGE_BY_TO_ADDRESS: u8 = 1;
@@ -45,3 +51,43 @@ pub const INDEX_STORAGE_DIFF_BY_CONTRACT_ADDRESS: u8 = 0;
 pub const INDEX_CONTRACT_CHANGE_BY_TYPE: u8 = 0;
 
 pub const INDEX_NONCE_UPDATE_BY_CONTRACT_ADDRESS: u8 = 0;
+
+pub const INDEX_CONTRACT_CLASS_BY_CLASS_HASH: u8 = 0;
+
+/// Returns the fragments generated by the chain.
+pub fn fragment_info() -> Vec<FragmentInfo> {
+    vec![
+        FragmentInfo {
+            fragment_id: TRANSACTION_FRAGMENT_ID,
+            name: TRANSACTION_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: RECEIPT_FRAGMENT_ID,
+            name: RECEIPT_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: EVENT_FRAGMENT_ID,
+            name: EVENT_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: MESSAGE_FRAGMENT_ID,
+            name: MESSAGE_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: STORAGE_DIFF_FRAGMENT_ID,
+            name: STORAGE_DIFF_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: CONTRACT_CHANGE_FRAGMENT_ID,
+            name: CONTRACT_CHANGE_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: NONCE_UPDATE_FRAGMENT_ID,
+            name: NONCE_UPDATE_FRAGMENT_NAME.to_string(),
+        },
+        FragmentInfo {
+            fragment_id: CONTRACT_CLASS_FRAGMENT_ID,
+            name: CONTRACT_CLASS_FRAGMENT_NAME.to_string(),
+        },
+    ]
+}
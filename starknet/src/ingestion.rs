@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use apibara_dna_common::{
     chain::{BlockInfo, PendingBlockInfo},
     fragment::{
@@ -11,37 +13,94 @@ use apibara_dna_common::{
 };
 use apibara_dna_protocol::starknet;
 use error_stack::{Result, ResultExt};
+use futures::{StreamExt, TryStreamExt};
 use prost::Message;
 use tokio::sync::Mutex;
 use tracing::trace;
 
 use crate::{
+    contract_class_map::ContractClassMap,
     filter::{ContractChangeType, TransactionType},
     fragment::{
-        CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CHANGE_FRAGMENT_NAME, EVENT_FRAGMENT_ID,
-        EVENT_FRAGMENT_NAME, INDEX_CONTRACT_CHANGE_BY_TYPE, INDEX_EVENT_BY_ADDRESS,
-        INDEX_EVENT_BY_KEY0, INDEX_EVENT_BY_KEY1, INDEX_EVENT_BY_KEY2, INDEX_EVENT_BY_KEY3,
-        INDEX_EVENT_BY_KEY_LENGTH, INDEX_EVENT_BY_TRANSACTION_STATUS,
-        INDEX_MESSAGE_BY_FROM_ADDRESS, INDEX_MESSAGE_BY_TO_ADDRESS,
-        INDEX_MESSAGE_BY_TRANSACTION_STATUS, INDEX_NONCE_UPDATE_BY_CONTRACT_ADDRESS,
-        INDEX_STORAGE_DIFF_BY_CONTRACT_ADDRESS, INDEX_TRANSACTION_BY_STATUS,
-        INDEX_TRANSACTION_BY_TYPE, MESSAGE_FRAGMENT_ID, MESSAGE_FRAGMENT_NAME,
-        NONCE_UPDATE_FRAGMENT_ID, NONCE_UPDATE_FRAGMENT_NAME, RECEIPT_FRAGMENT_ID,
-        RECEIPT_FRAGMENT_NAME, STORAGE_DIFF_FRAGMENT_ID, STORAGE_DIFF_FRAGMENT_NAME,
-        TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME,
+        CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CHANGE_FRAGMENT_NAME, CONTRACT_CLASS_FRAGMENT_ID,
+        CONTRACT_CLASS_FRAGMENT_NAME, EVENT_FRAGMENT_ID, EVENT_FRAGMENT_NAME,
+        INDEX_CONTRACT_CHANGE_BY_TYPE, INDEX_CONTRACT_CLASS_BY_CLASS_HASH, INDEX_EVENT_BY_ADDRESS,
+        INDEX_EVENT_BY_CONTRACT_CLASS_HASH, INDEX_EVENT_BY_KEY0, INDEX_EVENT_BY_KEY1,
+        INDEX_EVENT_BY_KEY2, INDEX_EVENT_BY_KEY3, INDEX_EVENT_BY_KEY_LENGTH,
+        INDEX_EVENT_BY_TRANSACTION_STATUS, INDEX_MESSAGE_BY_FROM_ADDRESS,
+        INDEX_MESSAGE_BY_TO_ADDRESS, INDEX_MESSAGE_BY_TRANSACTION_STATUS,
+        INDEX_NONCE_UPDATE_BY_CONTRACT_ADDRESS, INDEX_STORAGE_DIFF_BY_CONTRACT_ADDRESS,
+        INDEX_TRANSACTION_BY_STATUS, INDEX_TRANSACTION_BY_TYPE, MESSAGE_FRAGMENT_ID,
+        MESSAGE_FRAGMENT_NAME, NONCE_UPDATE_FRAGMENT_ID, NONCE_UPDATE_FRAGMENT_NAME,
+        RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME, STORAGE_DIFF_FRAGMENT_ID,
+        STORAGE_DIFF_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID, TRANSACTION_FRAGMENT_NAME,
     },
     proto::{convert_block_header, convert_pending_block_header, ModelExt},
     provider::{models, BlockExt, BlockId, StarknetProvider, StarknetProviderErrorExt},
 };
 
+/// Maximum number of concurrent class hash lookups for each block.
+const MAX_CONCURRENT_CLASS_LOOKUPS: usize = 16;
+
 #[derive(Clone, Debug)]
 pub struct StarknetBlockIngestionOptions {
     pub ingest_pending: bool,
+    /// Fetch the definition of the classes declared in each block.
+    pub ingest_classes: bool,
+    /// Fetch the state update of each block.
+    ///
+    /// Some appchain sequencers don't implement `starknet_getStateUpdate`. Blocks
+    /// ingested without it have empty state diffs.
+    pub ingest_state_updates: bool,
+    /// Index events by the class hash of the contract emitting them.
+    pub index_event_class_hash: bool,
+    pub finality_mode: FinalityMode,
+    pub fee_tokens: FeeTokens,
+}
+
+/// Address of the ETH token on Starknet, used to pay fees in WEI.
+pub const ETH_FEE_TOKEN_ADDRESS: &str =
+    "0x049d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7";
+
+/// Address of the STRK token on Starknet, used to pay fees in FRI.
+pub const STRK_FEE_TOKEN_ADDRESS: &str =
+    "0x04718f5a0fc34cc1af16a1cdee98ffb20c31f5cd61d6ab07201858f4287c938d";
+
+/// The tokens used to pay fees, by price unit.
+///
+/// Appchains can use their own fee tokens instead of ETH and STRK.
+#[derive(Clone, Debug)]
+pub struct FeeTokens {
+    pub wei: models::FieldElement,
+    pub fri: models::FieldElement,
+}
+
+impl Default for FeeTokens {
+    fn default() -> Self {
+        Self {
+            wei: models::FieldElement::from_hex(ETH_FEE_TOKEN_ADDRESS)
+                .expect("valid ETH token address"),
+            fri: models::FieldElement::from_hex(STRK_FEE_TOKEN_ADDRESS)
+                .expect("valid STRK token address"),
+        }
+    }
+}
+
+/// How the ingestion decides which blocks are finalized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FinalityMode {
+    /// Blocks are finalized once accepted on L1.
+    #[default]
+    L1,
+    /// Blocks are finalized as soon as they're accepted, like on single-sequencer
+    /// appchains that never settle on L1.
+    Instant,
 }
 
 pub struct StarknetBlockIngestion {
     provider: StarknetProvider,
     finalized_hint: Mutex<Option<u64>>,
+    contract_classes: ContractClassMap,
     options: StarknetBlockIngestionOptions,
 }
 
@@ -51,9 +110,198 @@ impl StarknetBlockIngestion {
         Self {
             provider,
             finalized_hint,
+            contract_classes: ContractClassMap::default(),
             options,
         }
     }
+
+    /// Returns the class hash of the contracts emitting events in the block.
+    ///
+    /// The map is empty if indexing events by class hash is disabled.
+    async fn collect_event_contract_classes(
+        &self,
+        block_id: &BlockId,
+        block_number: u64,
+        transactions: &[models::TransactionWithReceipt],
+        state_diff: &models::StateDiff,
+    ) -> Result<HashMap<models::FieldElement, models::FieldElement>, IngestionError> {
+        let mut contract_classes = HashMap::new();
+
+        if !self.options.index_event_class_hash {
+            return Ok(contract_classes);
+        }
+
+        // The state diff has the class of the contracts at the end of the block.
+        let mut block_classes = HashMap::new();
+        for deployed in state_diff.deployed_contracts.iter() {
+            block_classes.insert(deployed.address, deployed.class_hash);
+        }
+        for replaced in state_diff.replaced_classes.iter() {
+            block_classes.insert(replaced.contract_address, replaced.class_hash);
+        }
+
+        let mut missing = HashSet::new();
+        for transaction in transactions.iter() {
+            for event in receipt_events(&transaction.receipt) {
+                let address = event.from_address;
+                if contract_classes.contains_key(&address) || missing.contains(&address) {
+                    continue;
+                }
+
+                let class_hash = block_classes
+                    .get(&address)
+                    .copied()
+                    .or_else(|| self.contract_classes.get(&address, block_number));
+
+                match class_hash {
+                    Some(class_hash) => {
+                        contract_classes.insert(address, class_hash);
+                    }
+                    None => {
+                        missing.insert(address);
+                    }
+                }
+            }
+        }
+
+        let class_hashes = futures::stream::iter(missing)
+            .map(|address| async move {
+                self.provider
+                    .get_class_hash_at(block_id, &address)
+                    .await
+                    .change_context(IngestionError::RpcRequest)
+                    .map(|class_hash| (address, class_hash))
+            })
+            .buffer_unordered(MAX_CONCURRENT_CLASS_LOOKUPS)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let is_pending = matches!(block_id, BlockId::Pending);
+        for (address, class_hash) in class_hashes {
+            // Pending blocks can still change, so they don't update the map.
+            if !is_pending {
+                self.contract_classes
+                    .insert(address, class_hash, block_number);
+            }
+            contract_classes.insert(address, class_hash);
+        }
+
+        // Blocks ingested without state updates have empty state diffs, which must
+        // not be taken as blocks without class changes.
+        if !is_pending && self.options.ingest_state_updates {
+            self.contract_classes
+                .apply_state_diff(state_diff, block_number);
+        }
+
+        Ok(contract_classes)
+    }
+
+    /// Fetch the classes declared in the state diff.
+    ///
+    /// The fragment is empty if class ingestion is disabled.
+    async fn collect_contract_classes(
+        &self,
+        block_id: &BlockId,
+        state_diff: &models::StateDiff,
+    ) -> Result<BlockIngestionResult, IngestionError> {
+        let mut declared_classes = Vec::new();
+
+        if self.options.ingest_classes {
+            declared_classes.extend(
+                state_diff
+                    .deprecated_declared_classes
+                    .iter()
+                    .map(|class_hash| (*class_hash, None)),
+            );
+            declared_classes.extend(
+                state_diff
+                    .declared_classes
+                    .iter()
+                    .map(|class| (class.class_hash, Some(class.compiled_class_hash))),
+            );
+        }
+
+        let classes = futures::future::try_join_all(declared_classes.iter().map(
+            |(class_hash, _)| async move {
+                self.provider
+                    .get_class(block_id, class_hash)
+                    .await
+                    .change_context(IngestionError::RpcRequest)
+            },
+        ))
+        .await?;
+
+        let mut block_contract_classes = Vec::with_capacity(classes.len());
+        let mut index_contract_class_by_class_hash = BitmapIndexBuilder::default();
+
+        for ((class_hash, compiled_class_hash), class) in declared_classes.iter().zip(classes) {
+            let index = block_contract_classes.len() as u32;
+
+            let definition = serde_json::to_string(&class)
+                .change_context(IngestionError::Model)
+                .attach_printable("failed to serialize class definition")
+                .attach_printable_lazy(|| format!("class hash: {class_hash:#x}"))?;
+
+            let class_hash = class_hash.to_proto();
+            index_contract_class_by_class_hash
+                .insert(ScalarValue::B256(class_hash.to_bytes()), index);
+
+            block_contract_classes.push(starknet::ContractClass {
+                filter_ids: Vec::default(),
+                class_hash: class_hash.into(),
+                compiled_class_hash: compiled_class_hash.as_ref().map(ModelExt::to_proto),
+                definition,
+            });
+        }
+
+        let contract_class_fragment = BodyFragment {
+            fragment_id: CONTRACT_CLASS_FRAGMENT_ID,
+            name: CONTRACT_CLASS_FRAGMENT_NAME.to_string(),
+            data: block_contract_classes
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        };
+
+        let contract_class_index = {
+            let index_contract_class_by_class_hash = Index {
+                index_id: INDEX_CONTRACT_CLASS_BY_CLASS_HASH,
+                index: index_contract_class_by_class_hash
+                    .build()
+                    .change_context(IngestionError::Indexing)?
+                    .into(),
+            };
+
+            IndexFragment {
+                fragment_id: CONTRACT_CLASS_FRAGMENT_ID,
+                range_start: 0,
+                range_len: block_contract_classes.len() as u32,
+                indexes: vec![index_contract_class_by_class_hash],
+            }
+        };
+
+        let contract_class_join = JoinFragment {
+            fragment_id: CONTRACT_CLASS_FRAGMENT_ID,
+            joins: Vec::default(),
+        };
+
+        Ok(BlockIngestionResult {
+            body: vec![contract_class_fragment],
+            index: vec![contract_class_index],
+            join: vec![contract_class_join],
+        })
+    }
+}
+
+fn empty_state_diff() -> models::StateDiff {
+    models::StateDiff {
+        storage_diffs: Vec::default(),
+        deprecated_declared_classes: Vec::default(),
+        declared_classes: Vec::default(),
+        deployed_contracts: Vec::default(),
+        replaced_classes: Vec::default(),
+        nonces: Vec::default(),
+    }
 }
 
 struct BlockIngestionResult {
@@ -82,8 +330,23 @@ impl BlockIngestion for StarknetBlockIngestion {
         Ok(cursor)
     }
 
+    #[tracing::instrument("starknet_get_chain_id", skip_all, err(Debug), level = "debug")]
+    async fn get_chain_id(&self) -> Result<Option<String>, IngestionError> {
+        let chain_id = self
+            .provider
+            .get_chain_id()
+            .await
+            .change_context(IngestionError::RpcRequest)?;
+
+        Ok(Some(format!("{chain_id:#x}")))
+    }
+
     #[tracing::instrument("starknet_get_finalized_cursor", skip_all, err(Debug), level = "debug")]
     async fn get_finalized_cursor(&self) -> Result<Cursor, IngestionError> {
+        if self.options.finality_mode == FinalityMode::Instant {
+            return self.get_head_cursor().await;
+        }
+
         let mut finalized_hint_guard = self.finalized_hint.lock().await;
 
         let head = self.get_head_cursor().await?;
@@ -91,7 +354,7 @@ impl BlockIngestion for StarknetBlockIngestion {
         let finalized_hint = if let Some(finalized_hint) = finalized_hint_guard.as_ref() {
             Cursor::new_finalized(*finalized_hint)
         } else {
-            let mut number = head.number - 50;
+            let mut number = head.number.saturating_sub(50);
             loop {
                 let block = self
                     .provider
@@ -111,7 +374,10 @@ impl BlockIngestion for StarknetBlockIngestion {
                 let step_size = 200;
                 if number == 0 {
                     return Err(IngestionError::RpcRequest)
-                        .attach_printable("failed to find finalized block");
+                        .attach_printable("failed to find finalized block")
+                        .attach_printable(
+                            "hint: use --starknet.finality-mode=instant for appchains",
+                        );
                 } else if number < step_size {
                     number = 0;
                 } else {
@@ -210,15 +476,21 @@ impl BlockIngestion for StarknetBlockIngestion {
             return Ok(None);
         }
 
-        let state_update = self
-            .provider
-            .get_state_update(&block_id)
-            .await
-            .change_context(IngestionError::RpcRequest)?;
+        let state_diff = if self.options.ingest_state_updates {
+            let state_update = self
+                .provider
+                .get_state_update(&block_id)
+                .await
+                .change_context(IngestionError::RpcRequest)?;
 
-        let models::MaybePendingStateUpdate::PendingUpdate(state_update) = state_update else {
-            return Err(IngestionError::RpcRequest)
-                .attach_printable("unexpected non-pending state update");
+            let models::MaybePendingStateUpdate::PendingUpdate(state_update) = state_update else {
+                return Err(IngestionError::RpcRequest)
+                    .attach_printable("unexpected non-pending state update");
+            };
+
+            state_update.state_diff
+        } else {
+            empty_state_diff()
         };
 
         let pending_block_info = PendingBlockInfo {
@@ -234,10 +506,26 @@ impl BlockIngestion for StarknetBlockIngestion {
             }
         };
 
-        let body_ingestion_result = collect_block_body_and_index(&block.transactions)?;
+        let event_contract_classes = self
+            .collect_event_contract_classes(
+                &block_id,
+                pending_block_info.number,
+                &block.transactions,
+                &state_diff,
+            )
+            .await?;
+
+        let body_ingestion_result = collect_block_body_and_index(
+            &block.transactions,
+            &event_contract_classes,
+            &self.options.fee_tokens,
+        )?;
+
+        let state_update_ingestion_result = collect_state_update_body_and_index(&state_diff)?;
 
-        let state_update_ingestion_result =
-            collect_state_update_body_and_index(&state_update.state_diff)?;
+        let contract_class_ingestion_result = self
+            .collect_contract_classes(&block_id, &state_diff)
+            .await?;
 
         let mut body_fragments = body_ingestion_result.body;
         let mut index_fragments = body_ingestion_result.index;
@@ -247,6 +535,10 @@ impl BlockIngestion for StarknetBlockIngestion {
         index_fragments.extend(state_update_ingestion_result.index);
         join_fragments.extend(state_update_ingestion_result.join);
 
+        body_fragments.extend(contract_class_ingestion_result.body);
+        index_fragments.extend(contract_class_ingestion_result.index);
+        join_fragments.extend(contract_class_ingestion_result.join);
+
         let index_group = IndexGroupFragment {
             indexes: index_fragments,
         };
@@ -301,16 +593,22 @@ impl BlockIngestion for StarknetBlockIngestion {
         // Use the block hash to avoid issues with reorgs.
         let block_id = BlockId::Hash(block.block_hash);
 
-        let state_update = self
-            .provider
-            .get_state_update(&block_id)
-            .await
-            .change_context(IngestionError::RpcRequest)?;
+        let state_diff = if self.options.ingest_state_updates {
+            let state_update = self
+                .provider
+                .get_state_update(&block_id)
+                .await
+                .change_context(IngestionError::RpcRequest)?;
 
-        let models::MaybePendingStateUpdate::Update(state_update) = state_update else {
-            return Err(IngestionError::RpcRequest)
-                .attach_printable("unexpected pending state update")
-                .attach_printable_lazy(|| format!("block number: {}", block_number));
+            let models::MaybePendingStateUpdate::Update(state_update) = state_update else {
+                return Err(IngestionError::RpcRequest)
+                    .attach_printable("unexpected pending state update")
+                    .attach_printable_lazy(|| format!("block number: {}", block_number));
+            };
+
+            state_update.state_diff
+        } else {
+            empty_state_diff()
         };
 
         let hash = block.block_hash.to_bytes_be().to_vec();
@@ -330,10 +628,26 @@ impl BlockIngestion for StarknetBlockIngestion {
             }
         };
 
-        let body_ingestion_result = collect_block_body_and_index(&block.transactions)?;
+        let event_contract_classes = self
+            .collect_event_contract_classes(
+                &block_id,
+                block_info.number,
+                &block.transactions,
+                &state_diff,
+            )
+            .await?;
+
+        let body_ingestion_result = collect_block_body_and_index(
+            &block.transactions,
+            &event_contract_classes,
+            &self.options.fee_tokens,
+        )?;
+
+        let state_update_ingestion_result = collect_state_update_body_and_index(&state_diff)?;
 
-        let state_update_ingestion_result =
-            collect_state_update_body_and_index(&state_update.state_diff)?;
+        let contract_class_ingestion_result = self
+            .collect_contract_classes(&block_id, &state_diff)
+            .await?;
 
         let mut body_fragments = body_ingestion_result.body;
         let mut index_fragments = body_ingestion_result.index;
@@ -343,6 +657,10 @@ impl BlockIngestion for StarknetBlockIngestion {
         index_fragments.extend(state_update_ingestion_result.index);
         join_fragments.extend(state_update_ingestion_result.join);
 
+        body_fragments.extend(contract_class_ingestion_result.body);
+        index_fragments.extend(contract_class_ingestion_result.index);
+        join_fragments.extend(contract_class_ingestion_result.join);
+
         let index_group = IndexGroupFragment {
             indexes: index_fragments,
         };
@@ -367,13 +685,26 @@ impl Clone for StarknetBlockIngestion {
         Self {
             provider: self.provider.clone(),
             finalized_hint: Mutex::new(None),
+            contract_classes: self.contract_classes.clone(),
             options: self.options.clone(),
         }
     }
 }
 
+fn receipt_events(receipt: &models::TransactionReceipt) -> &[models::Event] {
+    match receipt {
+        models::TransactionReceipt::Invoke(rx) => &rx.events,
+        models::TransactionReceipt::L1Handler(rx) => &rx.events,
+        models::TransactionReceipt::Declare(rx) => &rx.events,
+        models::TransactionReceipt::Deploy(rx) => &rx.events,
+        models::TransactionReceipt::DeployAccount(rx) => &rx.events,
+    }
+}
+
 fn collect_block_body_and_index(
     transactions: &[models::TransactionWithReceipt],
+    event_contract_classes: &HashMap<models::FieldElement, models::FieldElement>,
+    fee_tokens: &FeeTokens,
 ) -> Result<BlockIngestionResult, IngestionError> {
     let mut block_transactions = Vec::new();
     let mut block_receipts = Vec::new();
@@ -393,6 +724,7 @@ fn collect_block_body_and_index(
     let mut index_event_by_key3 = BitmapIndexBuilder::default();
     let mut index_event_by_key_length = BitmapIndexBuilder::default();
     let mut index_event_by_transaction_status = BitmapIndexBuilder::default();
+    let mut index_event_by_contract_class_hash = BitmapIndexBuilder::default();
     let mut join_event_to_transaction = JoinToOneIndexBuilder::default();
     let mut join_event_to_receipt = JoinToOneIndexBuilder::default();
     let mut join_event_to_siblings = JoinToManyIndexBuilder::default();
@@ -418,18 +750,19 @@ fn collect_block_body_and_index(
             models::ExecutionResult::Reverted { .. } => starknet::TransactionStatus::Reverted,
         };
 
-        let events = match &transaction_with_receipt.receipt {
-            models::TransactionReceipt::Invoke(rx) => &rx.events,
-            models::TransactionReceipt::L1Handler(rx) => &rx.events,
-            models::TransactionReceipt::Declare(rx) => &rx.events,
-            models::TransactionReceipt::Deploy(rx) => &rx.events,
-            models::TransactionReceipt::DeployAccount(rx) => &rx.events,
-        };
+        let events = receipt_events(&transaction_with_receipt.receipt);
 
         let mut transaction_events_id = Vec::new();
         let mut transaction_messages_id = Vec::new();
 
         for (event_index_in_transaction, event) in events.iter().enumerate() {
+            if let Some(class_hash) = event_contract_classes.get(&event.from_address) {
+                index_event_by_contract_class_hash.insert(
+                    ScalarValue::B256(class_hash.to_proto().to_bytes()),
+                    block_events.len() as u32,
+                );
+            }
+
             let mut event = event.to_proto();
 
             event.event_index = block_events.len() as u32;
@@ -571,6 +904,7 @@ fn collect_block_body_and_index(
 
         let mut receipt = transaction_with_receipt.receipt.to_proto();
         set_receipt_transaction_index(&mut receipt, transaction_index);
+        set_receipt_fee_token(&mut receipt, fee_tokens);
 
         join_transaction_to_receipt.insert(transaction_index, transaction_index);
 
@@ -728,6 +1062,13 @@ fn collect_block_body_and_index(
                 .change_context(IngestionError::Indexing)?
                 .into(),
         };
+        let index_event_by_contract_class_hash = Index {
+            index_id: INDEX_EVENT_BY_CONTRACT_CLASS_HASH,
+            index: index_event_by_contract_class_hash
+                .build()
+                .change_context(IngestionError::Indexing)?
+                .into(),
+        };
 
         IndexFragment {
             fragment_id: EVENT_FRAGMENT_ID,
@@ -741,6 +1082,7 @@ fn collect_block_body_and_index(
                 index_event_by_key3,
                 index_event_by_key_length,
                 index_event_by_transaction_status,
+                index_event_by_contract_class_hash,
             ],
         }
     };
@@ -1108,6 +1450,24 @@ fn set_receipt_transaction_index(receipt: &mut starknet::TransactionReceipt, ind
     meta.transaction_index = index;
 }
 
+fn set_receipt_fee_token(receipt: &mut starknet::TransactionReceipt, fee_tokens: &FeeTokens) {
+    let Some(fee) = receipt
+        .meta
+        .as_mut()
+        .and_then(|meta| meta.actual_fee.as_mut())
+    else {
+        return;
+    };
+
+    let token = match starknet::PriceUnit::try_from(fee.unit) {
+        Ok(starknet::PriceUnit::Wei) => &fee_tokens.wei,
+        Ok(starknet::PriceUnit::Fri) => &fee_tokens.fri,
+        _ => return,
+    };
+
+    fee.token_address = token.to_proto().into();
+}
+
 async fn binary_search_finalized_block(
     provider: &StarknetProvider,
     existing_finalized: Cursor,
@@ -1,18 +1,12 @@
-use apibara_dna_common::{fragment::FragmentInfo, ChainSupport};
+use apibara_dna_common::{fragment::FragmentInfo, ingestion::RpcRateLimiter, ChainSupport};
 use filter::StarknetFilterFactory;
-use fragment::{
-    CONTRACT_CHANGE_FRAGMENT_ID, CONTRACT_CHANGE_FRAGMENT_NAME, EVENT_FRAGMENT_ID,
-    EVENT_FRAGMENT_NAME, MESSAGE_FRAGMENT_ID, MESSAGE_FRAGMENT_NAME, NONCE_UPDATE_FRAGMENT_ID,
-    NONCE_UPDATE_FRAGMENT_NAME, RECEIPT_FRAGMENT_ID, RECEIPT_FRAGMENT_NAME,
-    STORAGE_DIFF_FRAGMENT_ID, STORAGE_DIFF_FRAGMENT_NAME, TRANSACTION_FRAGMENT_ID,
-    TRANSACTION_FRAGMENT_NAME,
-};
 use ingestion::StarknetBlockIngestion;
 use provider::StarknetProvider;
 
-pub use ingestion::StarknetBlockIngestionOptions;
+pub use ingestion::{FeeTokens, FinalityMode, StarknetBlockIngestionOptions};
 
 pub mod cli;
+pub mod contract_class_map;
 pub mod error;
 pub mod filter;
 pub mod fragment;
@@ -36,43 +30,19 @@ impl ChainSupport for StarknetChainSupport {
     type BlockFilterFactory = StarknetFilterFactory;
 
     fn fragment_info(&self) -> Vec<FragmentInfo> {
-        vec![
-            FragmentInfo {
-                fragment_id: TRANSACTION_FRAGMENT_ID,
-                name: TRANSACTION_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: RECEIPT_FRAGMENT_ID,
-                name: RECEIPT_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: EVENT_FRAGMENT_ID,
-                name: EVENT_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: MESSAGE_FRAGMENT_ID,
-                name: MESSAGE_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: STORAGE_DIFF_FRAGMENT_ID,
-                name: STORAGE_DIFF_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: CONTRACT_CHANGE_FRAGMENT_ID,
-                name: CONTRACT_CHANGE_FRAGMENT_NAME.to_string(),
-            },
-            FragmentInfo {
-                fragment_id: NONCE_UPDATE_FRAGMENT_ID,
-                name: NONCE_UPDATE_FRAGMENT_NAME.to_string(),
-            },
-        ]
+        fragment::fragment_info()
     }
 
     fn block_filter_factory(&self) -> Self::BlockFilterFactory {
-        StarknetFilterFactory
+        if self.options.ingest_state_updates {
+            StarknetFilterFactory::default()
+        } else {
+            StarknetFilterFactory::without_state_updates()
+        }
     }
 
-    fn block_ingestion(&self) -> Self::BlockIngestion {
-        StarknetBlockIngestion::new(self.provider.clone(), self.options.clone())
+    fn block_ingestion(&self, rate_limiter: RpcRateLimiter) -> Self::BlockIngestion {
+        let provider = self.provider.clone().with_rate_limiter(rate_limiter);
+        StarknetBlockIngestion::new(provider, self.options.clone())
     }
 }
@@ -718,6 +718,8 @@ impl ModelExt for models::FeePayment {
         starknet::FeePayment {
             amount: self.amount.to_proto().into(),
             unit: self.unit.to_proto().into(),
+            // Set by the ingestion, which knows the chain's fee tokens.
+            token_address: None,
         }
     }
 }
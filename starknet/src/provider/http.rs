@@ -1,8 +1,14 @@
-use std::{sync::Arc, time::Duration};
+use std::{future::Future, sync::Arc, time::Duration};
 
+use apibara_dna_common::{
+    ingestion::RpcRateLimiter,
+    rpc_pool::{RpcEndpoint, RpcPool, RpcPoolOptions},
+};
 use error_stack::{Report, Result, ResultExt};
 use reqwest::header::{HeaderMap, HeaderValue};
 use starknet::providers::{jsonrpc::HttpTransport, JsonRpcClient, Provider};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 use url::Url;
 
 use super::models;
@@ -33,11 +39,32 @@ pub struct StarknetProviderOptions {
     pub timeout: Duration,
     /// Request headers.
     pub headers: HeaderMap<HeaderValue>,
+    /// Failover and load balancing options.
+    pub pool: RpcPoolOptions,
+    /// Requests sent to the fallback endpoints when the main endpoints fail.
+    pub fallback_requests: Vec<FallbackRequest>,
 }
 
+/// Kind of request that can be sent to the fallback endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FallbackRequest {
+    /// Pending blocks and state updates.
+    Pending,
+    /// Accepted blocks, with transactions or receipts.
+    Block,
+    /// Accepted state updates.
+    StateUpdate,
+    /// Class definitions.
+    Class,
+}
+
+type Pool = RpcPool<Arc<JsonRpcClient<HttpTransport>>>;
+
 #[derive(Clone)]
 pub struct StarknetProvider {
-    client: Arc<JsonRpcClient<HttpTransport>>,
+    pool: Pool,
+    /// Endpoints used when the main endpoints fail, for some requests.
+    fallback: Option<Pool>,
     options: StarknetProviderOptions,
 }
 
@@ -46,21 +73,86 @@ pub trait StarknetProviderErrorExt {
 }
 
 impl StarknetProvider {
-    pub fn new(url: Url, options: StarknetProviderOptions) -> Result<Self, StarknetProviderError> {
-        let mut transport = HttpTransport::new(url);
-        for (key, value) in options.headers.iter() {
-            let key = key.to_string();
-            let value = value
-                .to_str()
-                .change_context(StarknetProviderError::Configuration)
-                .attach_printable("failed to convert header value to string")?
-                .to_string();
-            transport.add_header(key, value);
+    pub fn new(
+        urls: Vec<RpcEndpoint<Url>>,
+        options: StarknetProviderOptions,
+    ) -> Result<Self, StarknetProviderError> {
+        let pool = new_pool(urls, &options)?;
+
+        Ok(Self {
+            pool,
+            fallback: None,
+            options,
+        })
+    }
+
+    /// Send the requests in `options.fallback_requests` to these endpoints when the
+    /// main endpoints fail.
+    ///
+    /// Useful because pending data is often missing or inconsistent on a single provider.
+    pub fn with_fallback(self, urls: Vec<RpcEndpoint<Url>>) -> Result<Self, StarknetProviderError> {
+        let fallback = new_pool(urls, &self.options)?;
+
+        Ok(Self {
+            fallback: Some(fallback),
+            ..self
+        })
+    }
+
+    /// Limit the requests sent to the RPC endpoints, including the fallback endpoints.
+    pub fn with_rate_limiter(self, rate_limiter: RpcRateLimiter) -> Self {
+        Self {
+            pool: self.pool.with_rate_limiter(rate_limiter.clone()),
+            fallback: self
+                .fallback
+                .map(|fallback| fallback.with_rate_limiter(rate_limiter)),
+            ..self
         }
+    }
 
-        let client = JsonRpcClient::new(transport).into();
+    /// Check the endpoints' health in the background, until cancelled.
+    ///
+    /// The fallback endpoints are only used after the main endpoints fail, so they
+    /// aren't checked.
+    pub async fn health_check_loop(self, ct: CancellationToken) {
+        let timeout = self.options.timeout;
+        self.pool
+            .health_check_loop(
+                |client| async move {
+                    let request = client.block_number();
+                    let Ok(response) = tokio::time::timeout(timeout, request).await else {
+                        return Err(StarknetProviderError::Timeout)
+                            .attach_printable("failed to get block number");
+                    };
 
-        Ok(Self { client, options })
+                    response
+                        .or_else(convert_error)
+                        .attach_printable("failed to get block number")
+                },
+                ct,
+            )
+            .await
+    }
+
+    pub async fn get_chain_id(&self) -> Result<models::FieldElement, StarknetProviderError> {
+        self.pool
+            .call(
+                "get_chain_id",
+                |client| async move {
+                    let request = client.chain_id();
+                    let Ok(response) = tokio::time::timeout(self.options.timeout, request).await
+                    else {
+                        return Err(StarknetProviderError::Timeout)
+                            .attach_printable("failed to get chain id");
+                    };
+
+                    response
+                        .or_else(convert_error)
+                        .attach_printable("failed to get chain id")
+                },
+                should_failover,
+            )
+            .await
     }
 
     pub async fn get_block_with_tx_hashes(
@@ -69,17 +161,24 @@ impl StarknetProvider {
     ) -> Result<models::MaybePendingBlockWithTxHashes, StarknetProviderError> {
         let starknet_block_id: starknet::core::types::BlockId = block_id.into();
 
-        let request = self.client.get_block_with_tx_hashes(starknet_block_id);
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(StarknetProviderError::Timeout)
-                .attach_printable("failed to get block with transaction hashes")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
-        };
+        self.call(
+            "get_block_with_tx_hashes",
+            FallbackRequest::block(block_id),
+            |client| async move {
+                let request = client.get_block_with_tx_hashes(starknet_block_id);
+                let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
+                    return Err(StarknetProviderError::Timeout)
+                        .attach_printable("failed to get block with transaction hashes")
+                        .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                };
 
-        response
-            .or_else(convert_error)
-            .attach_printable("failed to get block with transaction hashes")
-            .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                response
+                    .or_else(convert_error)
+                    .attach_printable("failed to get block with transaction hashes")
+                    .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+            },
+        )
+        .await
     }
 
     pub async fn get_block_with_receipts(
@@ -88,17 +187,24 @@ impl StarknetProvider {
     ) -> Result<models::MaybePendingBlockWithReceipts, StarknetProviderError> {
         let starknet_block_id: starknet::core::types::BlockId = block_id.into();
 
-        let request = self.client.get_block_with_receipts(starknet_block_id);
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(StarknetProviderError::Timeout)
-                .attach_printable("failed to get block with receipts")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
-        };
+        self.call(
+            "get_block_with_receipts",
+            FallbackRequest::block(block_id),
+            |client| async move {
+                let request = client.get_block_with_receipts(starknet_block_id);
+                let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
+                    return Err(StarknetProviderError::Timeout)
+                        .attach_printable("failed to get block with receipts")
+                        .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                };
 
-        response
-            .or_else(convert_error)
-            .attach_printable("failed to get block with receipts")
-            .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                response
+                    .or_else(convert_error)
+                    .attach_printable("failed to get block with receipts")
+                    .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+            },
+        )
+        .await
     }
 
     pub async fn get_state_update(
@@ -107,18 +213,158 @@ impl StarknetProvider {
     ) -> Result<models::MaybePendingStateUpdate, StarknetProviderError> {
         let starknet_block_id: starknet::core::types::BlockId = block_id.into();
 
-        let request = self.client.get_state_update(starknet_block_id);
-        let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
-            return Err(StarknetProviderError::Timeout)
-                .attach_printable("failed to get block state update")
-                .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+        self.call(
+            "get_state_update",
+            FallbackRequest::state_update(block_id),
+            |client| async move {
+                let request = client.get_state_update(starknet_block_id);
+                let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
+                    return Err(StarknetProviderError::Timeout)
+                        .attach_printable("failed to get block state update")
+                        .attach_printable_lazy(|| format!("block id: {block_id:?}"));
+                };
+
+                response
+                    .or_else(convert_error)
+                    .attach_printable("failed to get block state update")
+                    .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+            },
+        )
+        .await
+    }
+
+    pub async fn get_class(
+        &self,
+        block_id: &BlockId,
+        class_hash: &models::FieldElement,
+    ) -> Result<models::ContractClass, StarknetProviderError> {
+        let starknet_block_id: starknet::core::types::BlockId = block_id.into();
+
+        self.call("get_class", FallbackRequest::Class, |client| async move {
+            let request = client.get_class(starknet_block_id, class_hash);
+            let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
+                return Err(StarknetProviderError::Timeout)
+                    .attach_printable("failed to get class")
+                    .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                    .attach_printable_lazy(|| format!("class hash: {class_hash:#x}"));
+            };
+
+            response
+                .or_else(convert_error)
+                .attach_printable("failed to get class")
+                .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                .attach_printable_lazy(|| format!("class hash: {class_hash:#x}"))
+        })
+        .await
+    }
+
+    pub async fn get_class_hash_at(
+        &self,
+        block_id: &BlockId,
+        contract_address: &models::FieldElement,
+    ) -> Result<models::FieldElement, StarknetProviderError> {
+        let starknet_block_id: starknet::core::types::BlockId = block_id.into();
+
+        self.call(
+            "get_class_hash_at",
+            FallbackRequest::state_update(block_id),
+            |client| async move {
+                let request = client.get_class_hash_at(starknet_block_id, contract_address);
+                let Ok(response) = tokio::time::timeout(self.options.timeout, request).await else {
+                    return Err(StarknetProviderError::Timeout)
+                        .attach_printable("failed to get class hash at")
+                        .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                        .attach_printable_lazy(|| {
+                            format!("contract address: {contract_address:#x}")
+                        });
+                };
+
+                response
+                    .or_else(convert_error)
+                    .attach_printable("failed to get class hash at")
+                    .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+                    .attach_printable_lazy(|| format!("contract address: {contract_address:#x}"))
+            },
+        )
+        .await
+    }
+
+    /// Send the request to the main endpoints and, for the requests configured to do so,
+    /// to the fallback endpoints if the main endpoints fail.
+    async fn call<T, F, Fut>(
+        &self,
+        method: &'static str,
+        request: FallbackRequest,
+        f: F,
+    ) -> Result<T, StarknetProviderError>
+    where
+        F: Fn(Arc<JsonRpcClient<HttpTransport>>) -> Fut,
+        Fut: Future<Output = Result<T, StarknetProviderError>>,
+    {
+        let result = self.pool.call(method, &f, should_failover).await;
+
+        let Some(fallback) = self.fallback.as_ref() else {
+            return result;
         };
 
-        response
-            .or_else(convert_error)
-            .attach_printable("failed to get block state update")
-            .attach_printable_lazy(|| format!("block id: {block_id:?}"))
+        match result {
+            Err(err) if self.options.fallback_requests.contains(&request) => {
+                warn!(method, error = ?err, "request failed. retrying with fallback endpoints");
+                fallback.call(method, &f, should_failover).await
+            }
+            result => result,
+        }
+    }
+}
+
+impl FallbackRequest {
+    fn block(block_id: &BlockId) -> Self {
+        match block_id {
+            BlockId::Pending => FallbackRequest::Pending,
+            _ => FallbackRequest::Block,
+        }
+    }
+
+    fn state_update(block_id: &BlockId) -> Self {
+        match block_id {
+            BlockId::Pending => FallbackRequest::Pending,
+            _ => FallbackRequest::StateUpdate,
+        }
+    }
+}
+
+fn new_pool(
+    urls: Vec<RpcEndpoint<Url>>,
+    options: &StarknetProviderOptions,
+) -> Result<Pool, StarknetProviderError> {
+    let mut endpoints = Vec::with_capacity(urls.len());
+
+    for endpoint in urls {
+        let mut transport = HttpTransport::new(endpoint.client);
+        for (key, value) in options.headers.iter() {
+            let key = key.to_string();
+            let value = value
+                .to_str()
+                .change_context(StarknetProviderError::Configuration)
+                .attach_printable("failed to convert header value to string")?
+                .to_string();
+            transport.add_header(key, value);
+        }
+
+        endpoints.push(RpcEndpoint {
+            name: endpoint.name,
+            client: Arc::new(JsonRpcClient::new(transport)),
+            weight: endpoint.weight,
+        });
     }
+
+    RpcPool::new(endpoints, options.pool.clone())
+        .change_context(StarknetProviderError::Configuration)
+}
+
+/// Blocks not found are not an endpoint failure, so they don't trigger failover.
+fn should_failover(err: &Report<StarknetProviderError>) -> bool {
+    !err.is_not_found()
 }
 
 impl error_stack::Context for StarknetProviderError {}
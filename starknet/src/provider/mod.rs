@@ -2,7 +2,7 @@ mod http;
 pub mod models;
 
 pub use self::http::{
-    BlockId, StarknetProvider, StarknetProviderError, StarknetProviderErrorExt,
+    BlockId, FallbackRequest, StarknetProvider, StarknetProviderError, StarknetProviderErrorExt,
     StarknetProviderOptions,
 };
 pub use self::models::BlockExt;